@@ -0,0 +1,43 @@
+#!/usr/bin/env sh
+# Pin the checksums of a Kubo release for managed installs.
+#
+#   scripts/pin-kubo.sh v0.29.0
+#
+# Appends to src-tauri/checksums/kubo.sha512 the SHA-512 of every archive
+# Control can install for that version. Each checksum must be the same on
+# dist.ipfs.tech and on the GitHub release, so one compromised origin
+# can't pin a bad build.
+set -eu
+
+[ $# -eq 1 ] || { sed -n '2,9p' "$0" | sed 's/^# \{0,1\}//'; exit 1; }
+version=$1
+list=$(dirname "$0")/../src-tauri/checksums/kubo.sha512
+
+# Targets dist_target() in src-tauri/src/kubo.rs can produce
+targets="darwin-amd64 darwin-arm64 linux-386 linux-amd64 linux-arm linux-arm64
+freebsd-amd64 freebsd-arm64 openbsd-amd64 openbsd-arm64
+windows-386 windows-amd64 windows-arm64"
+
+for target in $targets; do
+    case $target in
+        windows-*) file=kubo_${version}_${target}.zip ;;
+        *) file=kubo_${version}_${target}.tar.gz ;;
+    esac
+    if grep -q "  $file\$" "$list"; then
+        echo "$file already pinned"
+        continue
+    fi
+
+    dist=$(curl -fsSL "https://dist.ipfs.tech/kubo/$version/$file.sha512" 2>/dev/null | cut -d' ' -f1) || dist=
+    github=$(curl -fsSL "https://github.com/ipfs/kubo/releases/download/$version/$file.sha512" 2>/dev/null | cut -d' ' -f1) || github=
+    if [ -z "$dist" ] && [ -z "$github" ]; then
+        echo "$file is not published, skipped"
+        continue
+    fi
+    if [ "$dist" != "$github" ] || [ ${#dist} -ne 128 ]; then
+        echo "Checksums for $file differ between dist.ipfs.tech and GitHub" >&2
+        exit 1
+    fi
+    echo "$dist  $file" >>"$list"
+    echo "Pinned $file"
+done
@@ -0,0 +1,56 @@
+#!/usr/bin/env sh
+# Release signing for the in-app updater.
+#
+#   scripts/sign-update.sh keygen <key.pem>
+#       Create the Ed25519 release key and print its public half as hex.
+#       Build releases with CONTROL_UPDATE_PUBLIC_KEY set to that value.
+#
+#   scripts/sign-update.sh sign <key.pem> <manifest.json> [update.json]
+#       Sign a release manifest and write the update.json the updater
+#       downloads (default: update.json next to the manifest).
+#
+#   scripts/sign-update.sh pubkey <key.pem>
+#       Print the public key of an existing release key.
+#
+# Needs OpenSSL 3 and jq. Keep key.pem offline; anyone holding it can ship
+# builds to every Control install.
+set -eu
+
+usage() {
+    sed -n '2,17p' "$0" | sed 's/^# \{0,1\}//'
+    exit 1
+}
+
+pubkey() {
+    # The last 32 bytes of the DER public key are the raw Ed25519 key
+    openssl pkey -in "$1" -pubout -outform DER | tail -c 32 | od -An -tx1 | tr -d ' \n'
+    echo
+}
+
+[ $# -ge 2 ] || usage
+
+case "$1" in
+    keygen)
+        [ ! -e "$2" ] || { echo "$2 already exists" >&2; exit 1; }
+        (umask 077 && openssl genpkey -algorithm ed25519 -out "$2")
+        pubkey "$2"
+        ;;
+    pubkey)
+        pubkey "$2"
+        ;;
+    sign)
+        [ $# -ge 3 ] || usage
+        manifest=$3
+        out=${4:-$(dirname "$manifest")/update.json}
+        # The manifest is signed byte for byte and shipped as a string, so
+        # the updater checks exactly what was signed
+        jq -e 'has("version") and has("published_at") and has("artifacts")' "$manifest" >/dev/null
+        signature=$(openssl pkeyutl -sign -rawin -inkey "$2" -in "$manifest" | od -An -tx1 | tr -d ' \n')
+        jq -n --rawfile manifest "$manifest" --arg signature "$signature" \
+            '{manifest: $manifest, signature: $signature}' >"$out"
+        echo "Wrote $out"
+        ;;
+    *)
+        usage
+        ;;
+esac
@@ -0,0 +1,31 @@
+#![no_main]
+
+use control::crypto::{encrypt_message, Identity};
+use control::p2p::handle_incoming_for_fuzzing;
+use libfuzzer_sys::fuzz_target;
+
+// Fixed keys keep runs reproducible; the seeds are sealed between them
+const RECIPIENT_SECRET: [u8; 32] = [1; 32];
+const SENDER_SECRET: [u8; 32] = [2; 32];
+
+// The first byte picks the mode. Even: the rest is raw wire bytes, as any
+// peer on the topic can send. Odd: the rest is sealed from a fixed sender
+// first, so the fuzzer reaches the JSON parsing and message handling.
+fuzz_target!(|data: &[u8]| {
+    let Some((&mode, rest)) = data.split_first() else {
+        return;
+    };
+    let recipient = Identity::from_secret_bytes(RECIPIENT_SECRET);
+    let wire = if mode % 2 == 0 {
+        rest.to_vec()
+    } else {
+        let sender = Identity::from_secret_bytes(SENDER_SECRET);
+        let shared_secret = sender.shared_secret(&recipient.public_key);
+        let mut wire = sender.public_key.as_bytes().to_vec();
+        wire.extend_from_slice(&encrypt_message(&shared_secret, rest).unwrap());
+        wire
+    };
+
+    let data_dir = tempfile::tempdir().unwrap();
+    let _ = handle_incoming_for_fuzzing(&wire, &recipient, data_dir.path());
+});
@@ -0,0 +1,20 @@
+#![no_main]
+
+use control::crypto::Identity;
+use control::shards::{open_sealed_shards, recover_session_key, ShardEnvelope};
+use libfuzzer_sys::fuzz_target;
+
+// One shard per line, as pasted into the retrieve form
+fuzz_target!(|data: &[u8]| {
+    let text = String::from_utf8_lossy(data);
+    let shards: Vec<String> = text.lines().map(str::to_string).collect();
+    for shard in &shards {
+        let _ = ShardEnvelope::parse(shard);
+    }
+
+    let identity = Identity::from_secret_bytes([1; 32]);
+    if let Ok(opened) = open_sealed_shards(shards.clone(), Some(&identity)) {
+        let _ = recover_session_key(&opened);
+    }
+    let _ = recover_session_key(&shards);
+});
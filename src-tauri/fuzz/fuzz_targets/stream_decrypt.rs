@@ -0,0 +1,24 @@
+#![no_main]
+
+use control::crypto::SessionKey;
+use control::dead_drop::{stream_decrypt_file, DropOptions};
+use control::mapped_io::decrypt_mapped;
+use libfuzzer_sys::fuzz_target;
+
+// The seeds are encrypted under this key
+const KEY: [u8; 32] = [7; 32];
+
+// Downloaded ciphertext, through both the buffered and the mapped reader
+fuzz_target!(|data: &[u8]| {
+    let key = SessionKey::from_bytes(&KEY).unwrap();
+    let options = DropOptions::default();
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    std::fs::write(&input, data).unwrap();
+    let output = output.to_str().unwrap();
+
+    let streamed = stream_decrypt_file(&input, output, &key, &options).ok();
+    let mapped = decrypt_mapped(&input, output, &key, &options).ok();
+    assert_eq!(streamed, mapped, "buffered and mapped readers disagree");
+});
@@ -0,0 +1,210 @@
+//! UniFFI bindings over the `control` library for the iOS and Android
+//! clients, so they share the desktop app's protocol implementation.
+//! Calls block on a shared Tokio runtime; make them off the UI thread.
+
+use control::crypto::Identity;
+use control::dead_drop::DeadDropCreated;
+use control::p2p::P2PEvents;
+use control::{settings, DropManager, IdentityStore, NewDrop, Node};
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+use tokio::runtime::Runtime;
+
+uniffi::setup_scaffolding!();
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().expect("Failed to start the Tokio runtime"))
+}
+
+#[derive(Debug, uniffi::Error)]
+pub enum MobileError {
+    Failed { message: String },
+}
+
+impl fmt::Display for MobileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failed { message } => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for MobileError {}
+
+impl From<anyhow::Error> for MobileError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Failed {
+            message: format!("{:#}", e),
+        }
+    }
+}
+
+/// Load the settings kept in `data_dir`; call once before anything else
+#[uniffi::export]
+pub fn init(data_dir: String) -> Result<(), MobileError> {
+    Ok(settings::init(data_dir.as_ref())?)
+}
+
+#[uniffi::export]
+pub fn identity_exists(data_dir: String) -> bool {
+    IdentityStore::new(data_dir).exists()
+}
+
+#[derive(uniffi::Object)]
+pub struct MobileIdentity {
+    identity: Identity,
+}
+
+#[uniffi::export]
+impl MobileIdentity {
+    /// Unlock the identity in `data_dir`, generating one on first use
+    #[uniffi::constructor]
+    pub fn open(data_dir: String, password: String) -> Result<Arc<Self>, MobileError> {
+        let identity = IdentityStore::new(data_dir).open(&password)?;
+        Ok(Arc::new(Self { identity }))
+    }
+
+    /// Unlock an existing identity only
+    #[uniffi::constructor]
+    pub fn unlock(data_dir: String, password: String) -> Result<Arc<Self>, MobileError> {
+        let identity = IdentityStore::new(data_dir).unlock(&password)?;
+        Ok(Arc::new(Self { identity }))
+    }
+
+    pub fn public_id(&self) -> String {
+        self.identity.public_id()
+    }
+}
+
+/// Receives node events with the same names and JSON payloads the desktop
+/// frontend gets, e.g. `ghost_msg` and `msg_delivered`
+#[uniffi::export(callback_interface)]
+pub trait EventListener: Send + Sync {
+    fn on_event(&self, name: String, payload_json: String);
+}
+
+struct ListenerEvents(Box<dyn EventListener>);
+
+impl P2PEvents for ListenerEvents {
+    fn emit_event(&self, event: &str, payload: serde_json::Value) -> anyhow::Result<()> {
+        self.0.on_event(event.to_string(), payload.to_string());
+        Ok(())
+    }
+}
+
+#[derive(uniffi::Object)]
+pub struct MobileNode {
+    node: Node,
+}
+
+#[uniffi::export]
+impl MobileNode {
+    /// Start Ghost Mode for `identity`
+    #[uniffi::constructor]
+    pub fn start(
+        identity: Arc<MobileIdentity>,
+        data_dir: String,
+        listener: Box<dyn EventListener>,
+    ) -> Result<Arc<Self>, MobileError> {
+        let _runtime = runtime().enter();
+        let node = Node::builder(identity.identity.clone(), data_dir)
+            .events(Arc::new(ListenerEvents(listener)))
+            .start()?;
+        Ok(Arc::new(Self { node }))
+    }
+
+    /// Returns the message ID that `msg_delivered` or `msg_failed` refers to
+    pub fn send_message(
+        &self,
+        target_public_key: String,
+        content: String,
+    ) -> Result<String, MobileError> {
+        Ok(runtime().block_on(self.node.send_message(&target_public_key, &content))?)
+    }
+
+    pub fn send_contact_request(&self, public_id: String, note: String) -> Result<(), MobileError> {
+        runtime().block_on(self.node.send_contact_request(&public_id, &note))?;
+        Ok(())
+    }
+
+    pub fn respond_contact_request(
+        &self,
+        public_id: String,
+        accept: bool,
+    ) -> Result<(), MobileError> {
+        runtime().block_on(self.node.respond_contact_request(&public_id, accept))?;
+        Ok(())
+    }
+
+    pub fn shutdown(&self) {
+        runtime().block_on(self.node.clone().shutdown());
+    }
+}
+
+#[derive(uniffi::Record)]
+pub struct CreatedDrop {
+    pub cid: String,
+    pub shards: Vec<String>,
+    /// The upload is still queued
+    pub pending: bool,
+}
+
+impl From<DeadDropCreated> for CreatedDrop {
+    fn from(created: DeadDropCreated) -> Self {
+        Self {
+            cid: created.cid,
+            shards: created.shards,
+            pending: created.pending,
+        }
+    }
+}
+
+#[derive(uniffi::Object)]
+pub struct MobileDrops {
+    drops: DropManager,
+}
+
+#[uniffi::export]
+impl MobileDrops {
+    /// `identity` is needed only to open shards sealed to it
+    #[uniffi::constructor]
+    pub fn new(data_dir: String, identity: Option<Arc<MobileIdentity>>) -> Arc<Self> {
+        let mut builder = DropManager::builder(data_dir);
+        if let Some(identity) = identity {
+            builder = builder.identity(identity.identity.clone());
+        }
+        Arc::new(Self {
+            drops: builder.build(),
+        })
+    }
+
+    /// Encrypt and upload a file. With recipients, shard N is sealed to
+    /// the Nth public ID.
+    pub fn create_drop(
+        &self,
+        file_path: String,
+        threshold: u8,
+        total_shards: u8,
+        recipients: Vec<String>,
+    ) -> Result<CreatedDrop, MobileError> {
+        let request = NewDrop {
+            name: None,
+            threshold,
+            total_shards,
+            recipients,
+            expires_in_secs: None,
+        };
+        let created = runtime().block_on(self.drops.create_file(&file_path, request))?;
+        Ok(created.into())
+    }
+
+    pub fn retrieve_drop(
+        &self,
+        cid: String,
+        shards: Vec<String>,
+        output_path: String,
+    ) -> Result<(), MobileError> {
+        Ok(runtime().block_on(self.drops.retrieve(&cid, shards, &output_path))?)
+    }
+}
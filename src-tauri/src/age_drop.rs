@@ -0,0 +1,241 @@
+use crate::crypto::{decode_public_key, Identity};
+use crate::dead_drop::{
+    download_file_from_ipfs, is_upload_pending, upload_ciphertext, DeadDropCreated, DropOptions,
+};
+use crate::ipfs;
+use age::secrecy::SecretString;
+use anyhow::{Context, Result};
+use bech32::{ToBase32, Variant};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::info;
+use zeroize::Zeroize;
+
+const RECIPIENT_HRP: &str = "age";
+const IDENTITY_HRP: &str = "age-secret-key-";
+const BINARY_HEADER: &[u8] = b"age-encryption.org/v1\n";
+const ARMORED_HEADER: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+const SNIFF_TIMEOUT_SECS: u64 = 30;
+
+/// Recipients of an age-format drop. The payload is a standard age file, so
+/// `ipfs cat <cid> | age -d` opens it outside Control.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AgeDropConfig {
+    /// `age1…` recipients or Control public IDs
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// Encrypt to a passphrase instead (age scrypt recipient)
+    pub passphrase: Option<String>,
+}
+
+impl AgeDropConfig {
+    pub fn validate(&self) -> Result<()> {
+        match (self.recipients.is_empty(), &self.passphrase) {
+            (true, None) => anyhow::bail!("An age drop needs recipients or a passphrase"),
+            // age allows a passphrase only as the sole recipient
+            (false, Some(_)) => {
+                anyhow::bail!("An age drop can't have both recipients and a passphrase")
+            }
+            (true, Some(passphrase)) if passphrase.is_empty() => {
+                anyhow::bail!("Passphrase must not be empty")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The age recipient (`age1…`) for a Control public ID; both are the same
+/// X25519 key
+pub fn age_recipient(public_id: &str) -> Result<String> {
+    let key = decode_public_key(public_id)?;
+    Ok(bech32::encode(
+        RECIPIENT_HRP,
+        key.as_bytes().to_base32(),
+        Variant::Bech32,
+    )?)
+}
+
+/// The identity as an age secret key (`AGE-SECRET-KEY-1…`), for decrypting
+/// drops with `age -d -i`
+pub fn age_identity(identity: &Identity) -> Result<SecretString> {
+    let mut secret = identity.secret_bytes();
+    let encoded = bech32::encode(IDENTITY_HRP, secret.to_base32(), Variant::Bech32);
+    secret.zeroize();
+    Ok(SecretString::new(encoded?.to_uppercase()))
+}
+
+fn parse_recipient(recipient: &str) -> Result<age::x25519::Recipient> {
+    let recipient = recipient.trim();
+    if recipient.starts_with("age1") {
+        return age::x25519::Recipient::from_str(recipient)
+            .map_err(|e| anyhow::anyhow!("Invalid age recipient {}: {}", recipient, e));
+    }
+    let age_key =
+        age_recipient(recipient).with_context(|| format!("Invalid recipient key {}", recipient))?;
+    age::x25519::Recipient::from_str(&age_key).map_err(|e| anyhow::anyhow!(e))
+}
+
+fn parse_identity(identity: &Identity) -> Result<age::x25519::Identity> {
+    use age::secrecy::ExposeSecret;
+    let key = age_identity(identity)?;
+    age::x25519::Identity::from_str(key.expose_secret()).map_err(|e| anyhow::anyhow!(e))
+}
+
+/// Encrypt a file in the age format and upload it. The drop reference is the
+/// plain CID; no shards are produced.
+pub async fn create_age_drop(
+    file_path: &str,
+    config: &AgeDropConfig,
+    options: &DropOptions,
+) -> Result<DeadDropCreated> {
+    config.validate()?;
+    let encryptor = match &config.passphrase {
+        Some(passphrase) => {
+            age::Encryptor::with_user_passphrase(SecretString::new(passphrase.clone()))
+        }
+        None => {
+            let mut recipients: Vec<Box<dyn age::Recipient + Send>> = Vec::new();
+            for recipient in &config.recipients {
+                recipients.push(Box::new(parse_recipient(recipient)?));
+            }
+            age::Encryptor::with_recipients(recipients).context("No age recipients")?
+        }
+    };
+
+    let temp_file = options.staging_file()?;
+    let source = PathBuf::from(file_path);
+    let target = temp_file.path().to_path_buf();
+    // scrypt and the stream cipher are blocking work
+    let encrypted_size = tokio::task::spawn_blocking(move || -> Result<u64> {
+        let mut input = BufReader::new(File::open(&source).context("Failed to open file")?);
+        let output = BufWriter::new(File::create(&target)?);
+        let mut writer = encryptor.wrap_output(output)?;
+        io::copy(&mut input, &mut writer).context("Failed to encrypt file")?;
+        writer.finish()?.flush()?;
+        Ok(std::fs::metadata(&target)?.len())
+    })
+    .await??;
+    info!("Encrypted file as age: {} bytes", encrypted_size);
+
+    let cid = upload_ciphertext(temp_file, options).await?;
+    Ok(DeadDropCreated {
+        pending: is_upload_pending(options, &cid),
+        cid,
+        shards: Vec::new(),
+        drop_id: None,
+        piece_cids: Vec::new(),
+        shred: None,
+        cipher: None,
+    })
+}
+
+/// Whether a CID holds an age file, binary or armored, judged by its header
+pub async fn is_age_encrypted(cid: &str, options: &DropOptions) -> Result<bool> {
+    let client = ipfs::client()?;
+    let length = ARMORED_HEADER.len().to_string();
+    let mut request = client
+        .post(ipfs::api("cat"))
+        .query(&[("arg", cid), ("length", length.as_str())])
+        .timeout(Duration::from_secs(SNIFF_TIMEOUT_SECS));
+    if options.offline {
+        request = request.query(&[("offline", "true")]);
+    }
+    let response = request.send().await.context("Failed to read from IPFS")?;
+    if !response.status().is_success() {
+        anyhow::bail!("IPFS read failed: {}", response.status());
+    }
+    let head = response.bytes().await?;
+    Ok(head.starts_with(BINARY_HEADER) || head.starts_with(ARMORED_HEADER))
+}
+
+/// Retrieve an age-encrypted CID, whether created here or by `age`/`rage`.
+/// X25519 files open with our identity, passphrase files with `passphrase`.
+pub async fn retrieve_age_drop(
+    cid: &str,
+    identity: Option<&Identity>,
+    passphrase: Option<&str>,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<u64> {
+    let temp_file = options.staging_file()?;
+    download_file_from_ipfs(cid, temp_file.path(), options).await?;
+
+    let identity = identity.map(parse_identity).transpose()?;
+    let passphrase = passphrase.map(|p| SecretString::new(p.to_string()));
+    let source = temp_file.path().to_path_buf();
+    let target = PathBuf::from(output_path);
+    let decrypted_size = tokio::task::spawn_blocking(move || {
+        decrypt_file(&source, &target, identity.as_ref(), passphrase.as_ref())
+    })
+    .await??;
+
+    info!("Decrypted age file {} to {}", cid, output_path);
+    drop(temp_file);
+    Ok(decrypted_size)
+}
+
+fn decrypt_file(
+    source: &Path,
+    target: &Path,
+    identity: Option<&age::x25519::Identity>,
+    passphrase: Option<&SecretString>,
+) -> Result<u64> {
+    let input = age::armor::ArmoredReader::new(BufReader::new(File::open(source)?));
+    let mut reader: Box<dyn Read> =
+        match age::Decryptor::new(input).context("Not an age-encrypted file")? {
+            age::Decryptor::Recipients(decryptor) => {
+                let identity = identity.context("Unlock your identity to open this age file")?;
+                Box::new(
+                    decryptor
+                        .decrypt(std::iter::once(identity as &dyn age::Identity))
+                        .context("This age file was not encrypted to this identity")?,
+                )
+            }
+            age::Decryptor::Passphrase(decryptor) => {
+                let passphrase = passphrase.context("This age file needs a passphrase")?;
+                Box::new(
+                    decryptor
+                        .decrypt(passphrase, None)
+                        .context("Wrong passphrase for this age file")?,
+                )
+            }
+        };
+
+    let mut output = BufWriter::new(File::create(target).context("Failed to create output file")?);
+    let size = io::copy(&mut reader, &mut output).context("Failed to decrypt age file")?;
+    output.flush()?;
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_keys_map_to_age_keys() {
+        let identity = Identity::generate();
+        let recipient = age_recipient(&identity.public_id()).unwrap();
+        let age_identity = parse_identity(&identity).unwrap();
+        assert_eq!(age_identity.to_public().to_string(), recipient);
+
+        // A file age encrypts to the recipient opens with the identity
+        let dir = tempfile::tempdir().unwrap();
+        let encrypted = dir.path().join("secret.age");
+        let decrypted = dir.path().join("secret.txt");
+        let encryptor =
+            age::Encryptor::with_recipients(vec![Box::new(parse_recipient(&recipient).unwrap())])
+                .unwrap();
+        let mut writer = encryptor
+            .wrap_output(File::create(&encrypted).unwrap())
+            .unwrap();
+        writer.write_all(b"dead drop").unwrap();
+        writer.finish().unwrap();
+
+        decrypt_file(&encrypted, &decrypted, Some(&age_identity), None).unwrap();
+        assert_eq!(std::fs::read(&decrypted).unwrap(), b"dead drop");
+    }
+}
@@ -0,0 +1,499 @@
+use crate::catalog::now_secs;
+use crate::contacts::ContactBook;
+use crate::crypto::{decode_public_key, Identity, SessionKey};
+use crate::events;
+use crate::no_traces;
+use crate::search::{SearchHit, SearchIndex};
+use crate::session::AppState;
+use crate::settings;
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::Manager;
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+const ARCHIVES_FILE: &str = "archives.enc";
+const ARCHIVE_DIR: &str = "archives";
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+const DAY_SECS: u64 = 24 * 3600;
+/// A century; longer than any history worth keeping
+const MAX_AGE_DAYS: u64 = 36_500;
+
+/// Limits on one conversation's history; 0 means no limit
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /// Archive messages older than this many days
+    pub max_age_days: u64,
+    /// Archive the oldest messages once the conversation holds more bytes
+    pub max_bytes: u64,
+}
+
+/// When old messages leave the search index for archive files
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RetentionSettings {
+    pub enabled: bool,
+    /// For conversations without a policy of their own
+    pub default: RetentionPolicy,
+    /// Policies by contact public ID
+    pub conversations: BTreeMap<String, RetentionPolicy>,
+    /// Archive the oldest messages of any conversation once the whole
+    /// history holds more bytes; 0 for no limit
+    pub max_total_bytes: u64,
+}
+
+impl RetentionSettings {
+    pub fn validate(&self) -> Result<()> {
+        for contact in self.conversations.keys() {
+            decode_public_key(contact)
+                .with_context(|| format!("Invalid public ID in retention policy: {}", contact))?;
+        }
+        let mut policies = std::iter::once(&self.default).chain(self.conversations.values());
+        if policies.any(|policy| policy.max_age_days > MAX_AGE_DAYS) {
+            anyhow::bail!("Messages can be kept for at most {} days", MAX_AGE_DAYS);
+        }
+        Ok(())
+    }
+
+    pub fn policy(&self, contact: &str) -> RetentionPolicy {
+        self.conversations
+            .get(contact)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// An archive file, described without opening it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchiveInfo {
+    pub id: String,
+    pub contact: String,
+    pub created_at: u64,
+    pub messages: usize,
+    /// Timestamps of the oldest and newest archived message
+    pub oldest: u64,
+    pub newest: u64,
+    /// Size of the file, compressed and encrypted
+    pub bytes: u64,
+}
+
+/// History held for one conversation, in the index and in archives
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ConversationUsage {
+    pub contact: String,
+    /// From the address book
+    pub nickname: Option<String>,
+    pub messages: usize,
+    /// Bytes of message content in the index
+    pub bytes: u64,
+    pub oldest: Option<u64>,
+    pub archives: usize,
+    pub archived_messages: usize,
+    pub archive_bytes: u64,
+}
+
+/// Contents of an archive file, before compression and encryption: the
+/// messages with their numbers in the index, so a restore puts them back in
+/// place
+type ArchivedMessages = Vec<(u64, SearchHit)>;
+
+/// The list of archives, encrypted with the local identity; the archives
+/// themselves are files under `archives/` with the same key
+pub struct ArchiveStore {
+    path: PathBuf,
+    dir: PathBuf,
+    storage_key: SessionKey,
+    archives: Vec<ArchiveInfo>,
+}
+
+impl ArchiveStore {
+    /// Load the store, starting empty if it does not exist yet
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(ARCHIVES_FILE);
+        let storage_key = identity.storage_key(b"archives");
+
+        let archives = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read archives")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt archives")?;
+            let archives = serde_json::from_slice(&json);
+            json.zeroize();
+            archives.context("Failed to parse archives")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            dir: data_dir.join(ARCHIVE_DIR),
+            storage_key,
+            archives,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut json = serde_json::to_vec(&self.archives)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write archives")?;
+        Ok(())
+    }
+
+    /// Newest first
+    pub fn list(&self) -> Vec<ArchiveInfo> {
+        let mut archives = self.archives.clone();
+        archives.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        archives
+    }
+
+    /// Compress, encrypt and write messages of one conversation
+    fn write(&mut self, contact: &str, messages: ArchivedMessages) -> Result<ArchiveInfo> {
+        let timestamps = || messages.iter().map(|(_, hit)| hit.timestamp);
+        let mut info = ArchiveInfo {
+            id: uuid::Uuid::new_v4().to_string(),
+            contact: contact.to_string(),
+            created_at: now_secs(),
+            messages: messages.len(),
+            oldest: timestamps().min().unwrap_or_default(),
+            newest: timestamps().max().unwrap_or_default(),
+            bytes: 0,
+        };
+
+        let mut json = serde_json::to_vec(&messages)?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        let written = encoder.write_all(&json);
+        json.zeroize();
+        written?;
+        let mut compressed = encoder.finish()?;
+        let encrypted = self.storage_key.encrypt_file(&compressed);
+        compressed.zeroize();
+        let encrypted = encrypted?;
+
+        info.bytes = encrypted.len() as u64;
+        if !no_traces::active() {
+            fs::create_dir_all(&self.dir).context("Failed to create archive directory")?;
+        }
+        no_traces::write_file(&self.file(&info.id), encrypted)
+            .context("Failed to write archive")?;
+        self.archives.push(info.clone());
+        Ok(info)
+    }
+
+    fn read(&self, id: &str) -> Result<ArchivedMessages> {
+        let encrypted = no_traces::read_file(&self.file(id))
+            .context("Failed to read archive")?
+            .context("Archive file is missing")?;
+        let mut compressed = self
+            .storage_key
+            .decrypt_file(&encrypted)
+            .context("Failed to decrypt archive")?;
+        let mut json = Vec::new();
+        let decoded = GzDecoder::new(compressed.as_slice()).read_to_end(&mut json);
+        compressed.zeroize();
+        decoded.context("Failed to decompress archive")?;
+        let archived = serde_json::from_slice(&json);
+        json.zeroize();
+        archived.context("Failed to parse archive")
+    }
+
+    /// Forget an archive and delete its file
+    fn remove(&mut self, id: &str) -> Result<ArchiveInfo> {
+        let index = self
+            .archives
+            .iter()
+            .position(|a| a.id == id)
+            .context("Unknown archive")?;
+        no_traces::remove_file(&self.file(id)).context("Failed to delete archive")?;
+        Ok(self.archives.remove(index))
+    }
+
+    fn file(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.enc", id))
+    }
+}
+
+/// Numbers of the messages the settings send to archives. `messages` is
+/// oldest first; restored messages are never picked.
+pub fn select(
+    messages: &[(u64, SearchHit)],
+    settings: &RetentionSettings,
+    now: u64,
+) -> BTreeSet<u64> {
+    let mut selected = BTreeSet::new();
+    let mut kept_bytes: BTreeMap<&str, u64> = BTreeMap::new();
+    for (doc, hit) in messages.iter().rev().filter(|(_, hit)| !hit.restored) {
+        let policy = settings.policy(&hit.contact);
+        let bytes = kept_bytes.entry(&hit.contact).or_default();
+        *bytes += hit.content.len() as u64;
+        let too_old = policy.max_age_days > 0
+            && hit
+                .timestamp
+                .saturating_add(policy.max_age_days.saturating_mul(DAY_SECS))
+                < now;
+        let too_big = policy.max_bytes > 0 && *bytes > policy.max_bytes;
+        if too_old || too_big {
+            selected.insert(*doc);
+        }
+    }
+
+    if settings.max_total_bytes > 0 {
+        let mut total = 0u64;
+        for (doc, hit) in messages.iter().rev() {
+            if hit.restored || selected.contains(doc) {
+                continue;
+            }
+            total += hit.content.len() as u64;
+            if total > settings.max_total_bytes {
+                selected.insert(*doc);
+            }
+        }
+    }
+    selected
+}
+
+/// Move messages past the retention limits into one new archive per
+/// conversation
+pub fn apply_retention(
+    data_dir: &Path,
+    identity: &Identity,
+    settings: &RetentionSettings,
+) -> Result<Vec<ArchiveInfo>> {
+    let index = SearchIndex::open(data_dir, identity);
+    let selected = select(&index.messages()?, settings, now_secs());
+    if selected.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut store = ArchiveStore::load(data_dir, identity)?;
+    let mut by_contact: BTreeMap<String, Vec<(u64, SearchHit)>> = BTreeMap::new();
+    for (doc, hit) in index.remove(&selected)? {
+        by_contact
+            .entry(hit.contact.clone())
+            .or_default()
+            .push((doc, hit));
+    }
+    let mut archives = Vec::new();
+    for (contact, messages) in by_contact {
+        archives.push(store.write(&contact, messages)?);
+    }
+    store.save()?;
+    Ok(archives)
+}
+
+/// Put an archive's messages back into the index and delete the archive
+pub fn restore_archive(data_dir: &Path, identity: &Identity, id: &str) -> Result<ArchiveInfo> {
+    let mut store = ArchiveStore::load(data_dir, identity)?;
+    let archived = store.read(id)?;
+    SearchIndex::open(data_dir, identity).restore(archived)?;
+    let info = store.remove(id)?;
+    store.save()?;
+    Ok(info)
+}
+
+pub fn delete_archive(data_dir: &Path, identity: &Identity, id: &str) -> Result<ArchiveInfo> {
+    let mut store = ArchiveStore::load(data_dir, identity)?;
+    let info = store.remove(id)?;
+    store.save()?;
+    Ok(info)
+}
+
+/// History held per conversation, largest first
+pub fn storage_usage(data_dir: &Path, identity: &Identity) -> Result<Vec<ConversationUsage>> {
+    let mut usage: BTreeMap<String, ConversationUsage> = BTreeMap::new();
+    for (_, hit) in SearchIndex::open(data_dir, identity).messages()? {
+        let conversation = usage_of(&mut usage, &hit.contact);
+        conversation.messages += 1;
+        conversation.bytes += hit.content.len() as u64;
+        conversation.oldest = Some(
+            conversation
+                .oldest
+                .map_or(hit.timestamp, |t| t.min(hit.timestamp)),
+        );
+    }
+    for archive in ArchiveStore::load(data_dir, identity)?.list() {
+        let conversation = usage_of(&mut usage, &archive.contact);
+        conversation.archives += 1;
+        conversation.archived_messages += archive.messages;
+        conversation.archive_bytes += archive.bytes;
+    }
+
+    let contacts = ContactBook::load(data_dir, identity)?;
+    let mut usage: Vec<_> = usage
+        .into_values()
+        .map(|mut conversation| {
+            conversation.nickname = contacts
+                .get(&conversation.contact)
+                .map(|c| c.nickname.clone())
+                .filter(|n| !n.is_empty());
+            conversation
+        })
+        .collect();
+    usage.sort_by(|a, b| (b.bytes + b.archive_bytes).cmp(&(a.bytes + a.archive_bytes)));
+    Ok(usage)
+}
+
+fn usage_of<'a>(
+    usage: &'a mut BTreeMap<String, ConversationUsage>,
+    contact: &str,
+) -> &'a mut ConversationUsage {
+    usage
+        .entry(contact.to_string())
+        .or_insert_with(|| ConversationUsage {
+            contact: contact.to_string(),
+            ..Default::default()
+        })
+}
+
+/// Apply the retention settings every hour while the session is unlocked
+/// and emit `history_archived` with the archives made
+pub fn start_retention_worker(app: tauri::AppHandle, data_dir: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+
+            let retention = settings::retention_settings();
+            if !retention.enabled {
+                continue;
+            }
+            let Some(identity) = app.state::<AppState>().peek_identity() else {
+                continue;
+            };
+
+            match apply_retention(&data_dir, &identity, &retention) {
+                Ok(archives) if archives.is_empty() => {}
+                Ok(archives) => {
+                    info!("Archived history of {} conversations", archives.len());
+                    events::emit(&app, "history_archived", archives);
+                }
+                Err(e) => warn!("Failed to apply retention: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::GhostMessage;
+
+    fn hit(contact: &str, content: &str, timestamp: u64) -> SearchHit {
+        SearchHit {
+            message_id: uuid::Uuid::new_v4().to_string(),
+            contact: contact.to_string(),
+            outgoing: false,
+            timestamp,
+            content: content.to_string(),
+            restored: false,
+        }
+    }
+
+    #[test]
+    fn selects_by_age_and_size() {
+        let now = 100 * DAY_SECS;
+        let messages = vec![
+            (0, hit("ana", "aaaa", now - 40 * DAY_SECS)),
+            (1, hit("bo", "bbbb", now - 40 * DAY_SECS)),
+            (2, hit("ana", "aaaa", now - 2 * DAY_SECS)),
+            (3, hit("bo", "bbbbbbbb", now)),
+        ];
+        let mut settings = RetentionSettings {
+            enabled: true,
+            default: RetentionPolicy {
+                max_age_days: 30,
+                max_bytes: 0,
+            },
+            ..Default::default()
+        };
+        settings.conversations.insert(
+            "bo".to_string(),
+            RetentionPolicy {
+                max_age_days: 0,
+                max_bytes: 10,
+            },
+        );
+        // ana's old message by age; bo's old one because bo keeps 10 bytes
+        assert_eq!(select(&messages, &settings, now), BTreeSet::from([0, 1]));
+
+        settings.conversations.clear();
+        settings.default.max_age_days = 0;
+        settings.max_total_bytes = 12;
+        assert_eq!(select(&messages, &settings, now), BTreeSet::from([0, 1]));
+    }
+
+    #[test]
+    fn huge_max_age_is_refused_but_never_overflows() {
+        let mut settings = RetentionSettings::default();
+        settings.default.max_age_days = u64::MAX;
+        assert!(settings.validate().is_err());
+        settings.default.max_age_days = MAX_AGE_DAYS;
+        settings.validate().unwrap();
+
+        // Settings saved before the bound keep nothing from being archived
+        settings.default.max_age_days = u64::MAX;
+        let messages = vec![(0, hit("ana", "aaaa", 0))];
+        assert!(select(&messages, &settings, now_secs()).is_empty());
+    }
+
+    #[test]
+    fn archives_and_restores_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = Identity::generate();
+        let index = SearchIndex::open(dir.path(), &identity);
+        for (id, content, timestamp) in [("1", "old harbor", 10), ("2", "new harbor", now_secs())] {
+            let message = GhostMessage {
+                id: id.to_string(),
+                from: String::new(),
+                content: content.to_string(),
+                timestamp,
+                view_once: false,
+                location: None,
+            };
+            index.add("ana", false, &message).unwrap();
+        }
+
+        let settings = RetentionSettings {
+            enabled: true,
+            default: RetentionPolicy {
+                max_age_days: 1,
+                max_bytes: 0,
+            },
+            ..Default::default()
+        };
+        let archives = apply_retention(dir.path(), &identity, &settings).unwrap();
+        assert_eq!(archives.len(), 1);
+        assert_eq!(archives[0].messages, 1);
+        let found = index.search("harbor", None, 10).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].message_id, "2");
+
+        let usage = storage_usage(dir.path(), &identity).unwrap();
+        assert_eq!((usage[0].messages, usage[0].archived_messages), (1, 1));
+
+        restore_archive(dir.path(), &identity, &archives[0].id).unwrap();
+        let found = index.search("old", None, 10).unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].restored);
+        assert!(ArchiveStore::load(dir.path(), &identity)
+            .unwrap()
+            .list()
+            .is_empty());
+        // Restored messages stay put on the next run
+        assert!(apply_retention(dir.path(), &identity, &settings)
+            .unwrap()
+            .is_empty());
+    }
+}
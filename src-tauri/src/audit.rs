@@ -0,0 +1,155 @@
+use crate::catalog::now_secs;
+use crate::hash_chain::{append_record, read_records, record_hash};
+use crate::no_traces;
+use crate::settings;
+use crate::webhooks;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::warn;
+
+const AUDIT_FILE: &str = "drop_audit.enc";
+/// Name of the log in errors
+const AUDIT_LOG: &str = "drop audit log";
+
+/// What was done to a drop
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Retrieve,
+    Verify,
+    Unpin,
+    Import,
+}
+
+impl AuditAction {
+    /// Webhook event fired when the action is recorded
+    fn event(self) -> &'static str {
+        match self {
+            AuditAction::Create => "drop_created",
+            AuditAction::Update => "drop_updated",
+            AuditAction::Retrieve => "drop_retrieved",
+            AuditAction::Verify => "drop_verified",
+            AuditAction::Unpin => "drop_unpinned",
+            AuditAction::Import => "drop_imported",
+        }
+    }
+}
+
+/// One action on a drop. Parameters describe the operation only, never the
+/// file contents or key material.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub at: u64,
+    pub action: AuditAction,
+    /// CID or versioned drop ID the action was asked for
+    pub drop: String,
+    pub params: serde_json::Value,
+    pub error: Option<String>,
+    /// SHA-256 of the previous record as stored, hex
+    pub prev: String,
+}
+
+/// Entries read back, and whether the chain between them is unbroken
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditLog {
+    pub entries: Vec<AuditEntry>,
+    /// False if a record was removed, reordered or altered
+    pub intact: bool,
+}
+
+/// Appends are serialized so `seq` and `prev` follow the file order
+static APPEND: Mutex<()> = Mutex::new(());
+
+/// Append an entry to the audit log (held in memory in no-traces mode).
+/// Failures are logged, not returned, so the audited action itself is
+/// never blocked by the log.
+pub fn record(
+    data_dir: &Path,
+    action: AuditAction,
+    drop: &str,
+    params: serde_json::Value,
+    error: Option<String>,
+) {
+    // Parameters can name local paths, so webhooks only get the outcome
+    let outcome = serde_json::json!({ "drop": drop, "error": error });
+    if let Err(e) = append(data_dir, action, drop, params, error) {
+        warn!("Failed to write drop audit log: {}", e);
+    }
+    webhooks::fire(action.event(), &outcome);
+}
+
+fn append(
+    data_dir: &Path,
+    action: AuditAction,
+    drop: &str,
+    params: serde_json::Value,
+    error: Option<String>,
+) -> Result<()> {
+    let _guard = APPEND.lock().unwrap();
+    let key = settings::storage_key().context("Settings key not available")?;
+    let path = data_dir.join(AUDIT_FILE);
+    let bytes = no_traces::read_file(&path)
+        .context("Failed to read drop audit log")?
+        .unwrap_or_default();
+    let records = read_records(&bytes, AUDIT_LOG)?;
+
+    let entry = AuditEntry {
+        seq: records.len() as u64,
+        at: now_secs(),
+        action,
+        drop: drop.to_string(),
+        params,
+        error,
+        prev: records.last().map(|r| record_hash(r)).unwrap_or_default(),
+    };
+    let encrypted = key.encrypt_file(&serde_json::to_vec(&entry)?)?;
+    append_record(&path, &encrypted, AUDIT_LOG)
+}
+
+/// Read the audit log, optionally only the entries for one drop. The chain
+/// is checked over the whole log either way.
+pub fn read_log(data_dir: &Path, drop: Option<&str>) -> Result<AuditLog> {
+    let path = data_dir.join(AUDIT_FILE);
+    let Some(bytes) = no_traces::read_file(&path).context("Failed to read drop audit log")? else {
+        return Ok(AuditLog {
+            entries: Vec::new(),
+            intact: true,
+        });
+    };
+    let key = settings::storage_key().context("Settings key not available")?;
+    let records = read_records(&bytes, AUDIT_LOG)?;
+
+    let mut entries = Vec::with_capacity(records.len());
+    let mut intact = true;
+    let mut prev = String::new();
+    for (seq, record) in records.iter().enumerate() {
+        let json = key
+            .decrypt_file(&record[4..])
+            .context("Failed to decrypt drop audit log")?;
+        let entry: AuditEntry =
+            serde_json::from_slice(&json).context("Failed to parse drop audit log")?;
+        intact &= entry.seq == seq as u64 && entry.prev == prev;
+        prev = record_hash(record);
+        entries.push(entry);
+    }
+    if !intact {
+        warn!("Drop audit log chain is broken");
+    }
+
+    entries.retain(|e| drop.map_or(true, |d| e.drop == d));
+    Ok(AuditLog { entries, intact })
+}
+
+/// Write the audit log as plain JSON for someone who has to review it.
+/// Returns the number of entries written.
+pub fn export_log(data_dir: &Path, drop: Option<&str>, path: &Path) -> Result<usize> {
+    let log = read_log(data_dir, drop)?;
+    fs::write(path, serde_json::to_vec_pretty(&log)?).context("Failed to write audit export")?;
+    Ok(log.entries.len())
+}
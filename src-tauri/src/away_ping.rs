@@ -0,0 +1,196 @@
+use crate::i18n::t;
+use crate::ipfs;
+use crate::settings;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const PING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pings sent elsewhere when a message arrives while nobody is looking at
+/// Control. They say only "check Control": no sender, no content, no count.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AwayPingSettings {
+    pub enabled: bool,
+    pub matrix: Option<MatrixTarget>,
+    /// At most one ping per this many minutes, however many messages arrive
+    pub min_interval_mins: u32,
+    /// Route pings through the Tor proxy from the network settings
+    pub use_tor: bool,
+}
+
+impl Default for AwayPingSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            matrix: None,
+            min_interval_mins: 30,
+            use_tor: false,
+        }
+    }
+}
+
+impl AwayPingSettings {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(matrix) = &self.matrix {
+            matrix.validate().context("Invalid Matrix target")?;
+        }
+        if self.enabled && self.matrix.is_none() {
+            anyhow::bail!("Configure a Matrix account to send away pings to");
+        }
+        if !(1..=24 * 60).contains(&self.min_interval_mins) {
+            anyhow::bail!("Away ping interval must be between 1 minute and 24 hours");
+        }
+        Ok(())
+    }
+}
+
+/// Matrix room that receives the pings, posted to as the account whose
+/// access token this is
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatrixTarget {
+    /// e.g. `https://matrix.example.org`
+    pub homeserver: String,
+    pub access_token: String,
+    /// `!room:server`; the account must already be in it
+    pub room_id: String,
+}
+
+impl MatrixTarget {
+    fn validate(&self) -> Result<()> {
+        let url = reqwest::Url::parse(&self.homeserver).context("Invalid homeserver URL")?;
+        let onion = url.host_str().is_some_and(|h| h.ends_with(".onion"));
+        if url.scheme() != "https" && !(url.scheme() == "http" && onion) {
+            anyhow::bail!("Homeserver must be an https:// URL");
+        }
+        if self.access_token.is_empty() {
+            anyhow::bail!("Missing access token");
+        }
+        if !self.room_id.starts_with('!') || !self.room_id.contains(':') {
+            anyhow::bail!("Room ID must look like !room:server");
+        }
+        Ok(())
+    }
+}
+
+/// Somewhere a "check Control" ping can go
+#[async_trait]
+pub trait AwayNotifier: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn ping(&self, client: &reqwest::Client, text: &str) -> Result<()>;
+}
+
+pub struct MatrixNotifier(pub MatrixTarget);
+
+#[async_trait]
+impl AwayNotifier for MatrixNotifier {
+    fn name(&self) -> &'static str {
+        "matrix"
+    }
+
+    async fn ping(&self, client: &reqwest::Client, text: &str) -> Result<()> {
+        let url = reqwest::Url::parse(&self.0.homeserver)?.join(&format!(
+            "_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            url_escape(&self.0.room_id),
+            uuid::Uuid::new_v4().simple(),
+        ))?;
+        let response = client
+            .put(url)
+            .bearer_auth(&self.0.access_token)
+            .json(&serde_json::json!({
+                "msgtype": "m.notice",
+                "body": text,
+            }))
+            .timeout(PING_TIMEOUT)
+            .send()
+            .await
+            .context("Homeserver unreachable")?;
+        if !response.status().is_success() {
+            anyhow::bail!("Homeserver answered {}", response.status());
+        }
+        Ok(())
+    }
+}
+
+/// Backends configured in `settings`
+pub fn notifiers(settings: &AwayPingSettings) -> Vec<Box<dyn AwayNotifier>> {
+    let mut notifiers: Vec<Box<dyn AwayNotifier>> = Vec::new();
+    if let Some(matrix) = &settings.matrix {
+        notifiers.push(Box::new(MatrixNotifier(matrix.clone())));
+    }
+    notifiers
+}
+
+/// When the last ping went out
+static LAST_PING: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// A message arrived while nobody was looking; ping every backend unless
+/// one went out within the interval
+pub fn message_waiting() {
+    let settings = settings::away_ping_settings();
+    if !settings.enabled {
+        return;
+    }
+    {
+        let mut last = LAST_PING.lock().unwrap();
+        let interval = Duration::from_secs(settings.min_interval_mins as u64 * 60);
+        if last.is_some_and(|at| at.elapsed() < interval) {
+            return;
+        }
+        *last = Some(Instant::now());
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = send_pings(&settings).await {
+            warn!("Away ping failed: {}", e);
+        }
+    });
+}
+
+/// Ping every configured backend now, regardless of the interval
+pub async fn send_pings(settings: &AwayPingSettings) -> Result<()> {
+    let client = client(settings)?;
+    let text = t("away_ping.body");
+    let mut failed = Vec::new();
+    for notifier in notifiers(settings) {
+        match notifier.ping(&client, &text).await {
+            Ok(()) => info!("Away ping sent via {}", notifier.name()),
+            Err(e) => failed.push(format!("{}: {}", notifier.name(), e)),
+        }
+    }
+    if !failed.is_empty() {
+        anyhow::bail!("{}", failed.join("; "));
+    }
+    Ok(())
+}
+
+fn client(settings: &AwayPingSettings) -> Result<reqwest::Client> {
+    crate::settings::check_loaded()?;
+    if settings.use_tor {
+        if ipfs::network().tor_proxy.is_none() {
+            anyhow::bail!("Away pings over Tor need a Tor proxy in the network settings");
+        }
+        return ipfs::client();
+    }
+    reqwest::Client::builder()
+        .build()
+        .context("Failed to build away ping HTTP client")
+}
+
+/// Percent-encode a path segment such as a room ID
+fn url_escape(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
@@ -0,0 +1,400 @@
+use crate::audit::{self, AuditAction};
+use crate::catalog::{now_secs, DropCatalog};
+use crate::crypto::{Identity, SessionKey};
+use crate::dead_drop::{drop_cache_dir, new_drop_cache, unpin_dead_drop, DropOptions};
+use crate::dedup::create_dedup_drop;
+use crate::events;
+use crate::groups::{load_recipient_group, record_group_drop};
+use crate::mfs;
+use crate::no_traces;
+use crate::session::AppState;
+use crate::settings;
+use anyhow::{Context, Result};
+use flate2::{Compression, GzBuilder};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tauri::Manager;
+use tracing::{error, info, warn};
+use zeroize::Zeroize;
+
+const BACKUP_STATE_FILE: &str = "backups.enc";
+const MIN_INTERVAL_SECS: u64 = 3600;
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Wait after a failed run before the scheduler tries again
+const RETRY_AFTER_SECS: u64 = 15 * 60;
+
+/// Only one backup runs at a time, whether scheduled or started by hand
+static BACKUP_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Folders backed up on a schedule as deduplicated group drops. Every member
+/// of the group gets one shard.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupSettings {
+    pub enabled: bool,
+    pub folders: Vec<PathBuf>,
+    pub group_id: Option<String>,
+    pub threshold: u8,
+    pub interval_secs: u64,
+    /// Older backup drops are unpinned once this many newer ones exist
+    pub keep_last: usize,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            folders: Vec::new(),
+            group_id: None,
+            threshold: 2,
+            interval_secs: 24 * 3600,
+            keep_last: 7,
+        }
+    }
+}
+
+impl BackupSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.threshold < 2 {
+            anyhow::bail!("Threshold must be at least 2");
+        }
+        if self.keep_last == 0 {
+            anyhow::bail!("At least one backup must be kept");
+        }
+        if self.folders.iter().any(|f| !f.is_absolute()) {
+            anyhow::bail!("Backup folders must be absolute paths");
+        }
+        if self.enabled && (self.folders.is_empty() || self.group_id.is_none()) {
+            anyhow::bail!("Backups need folders and a recipient group");
+        }
+        Ok(())
+    }
+
+    fn interval_secs(&self) -> u64 {
+        self.interval_secs.max(MIN_INTERVAL_SECS)
+    }
+}
+
+/// One backup drop
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupRecord {
+    pub cid: String,
+    pub created_at: u64,
+    pub files: usize,
+    /// Plaintext bytes before compression
+    pub bytes: u64,
+}
+
+/// Backups still kept, newest last, encrypted with the local identity
+#[derive(Serialize, Deserialize, Default)]
+struct BackupState {
+    last_run_at: Option<u64>,
+    backups: Vec<BackupRecord>,
+}
+
+impl BackupState {
+    fn load(data_dir: &Path, key: &SessionKey) -> Result<Self> {
+        let path = data_dir.join(BACKUP_STATE_FILE);
+        let Some(encrypted) = no_traces::read_file(&path).context("Failed to read backup state")?
+        else {
+            return Ok(Self::default());
+        };
+        let mut json = key
+            .decrypt_file(&encrypted)
+            .context("Failed to decrypt backup state")?;
+        let state = serde_json::from_slice(&json);
+        json.zeroize();
+        state.context("Failed to parse backup state")
+    }
+
+    fn save(&self, data_dir: &Path, key: &SessionKey) -> Result<()> {
+        let mut json = serde_json::to_vec(self)?;
+        let encrypted = key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&data_dir.join(BACKUP_STATE_FILE), encrypted?)
+            .context("Failed to write backup state")?;
+        Ok(())
+    }
+}
+
+/// Backups currently kept, oldest first
+pub fn list_backups(data_dir: &Path, identity: &Identity) -> Result<Vec<BackupRecord>> {
+    Ok(BackupState::load(data_dir, &identity.storage_key(b"backups"))?.backups)
+}
+
+/// Run backups on schedule for the lifetime of the app, while unlocked.
+/// Emits `backup_completed` with the new backup or `backup_failed`.
+pub fn start_backup_scheduler(app: tauri::AppHandle, data_dir: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        let mut retry_at = 0;
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+
+            let backup = settings::backup_settings();
+            let now = now_secs();
+            if !backup.enabled || now < retry_at {
+                continue;
+            }
+            let Some(identity) = app.state::<AppState>().peek_identity() else {
+                continue;
+            };
+            let key = identity.storage_key(b"backups");
+            let last_run_at = match BackupState::load(&data_dir, &key) {
+                Ok(state) => state.last_run_at.unwrap_or(0),
+                Err(e) => {
+                    warn!("Backups paused: {}", e);
+                    continue;
+                }
+            };
+            if now.saturating_sub(last_run_at) < backup.interval_secs() {
+                continue;
+            }
+
+            match run_backup(&app, &data_dir, &identity).await {
+                Ok(record) => events::emit(&app, "backup_completed", record),
+                Err(e) => {
+                    error!("Scheduled backup failed: {:#}", e);
+                    retry_at = now + RETRY_AFTER_SECS;
+                    events::emit(&app, "backup_failed", format!("{:#}", e));
+                }
+            }
+        }
+    });
+}
+
+/// Snapshot the configured folders into one deduplicated drop for the backup
+/// group, then unpin backups past the retention count
+pub async fn run_backup(
+    app: &tauri::AppHandle,
+    data_dir: &Path,
+    identity: &Identity,
+) -> Result<BackupRecord> {
+    let _guard = BACKUP_LOCK.lock().await;
+    let backup = settings::backup_settings();
+    let group_id = backup
+        .group_id
+        .as_deref()
+        .context("Backups need a recipient group")?;
+    if backup.folders.is_empty() {
+        anyhow::bail!("No folders to back up");
+    }
+    let group = load_recipient_group(data_dir, identity, group_id, backup.threshold)?;
+    let total_shards = u8::try_from(group.members.len()).context("Group is too large")?;
+    let options = DropOptions {
+        cache_dir: new_drop_cache(data_dir),
+        ..settings::drop_settings().drop_options(data_dir)
+    };
+
+    let archive = options.staging_file()?;
+    let (archive, files, bytes) = {
+        let folders = backup.folders.clone();
+        let staging = options.staging_dir.clone();
+        tokio::task::spawn_blocking(move || -> Result<_> {
+            let mut archive = archive;
+            let (files, bytes) = pack_folders(&folders, archive.as_file_mut(), staging)?;
+            Ok((archive, files, bytes))
+        })
+        .await??
+    };
+    let archive_path = archive.path().to_str().context("Invalid staging path")?;
+    let created = create_dedup_drop(
+        archive_path,
+        backup.threshold,
+        total_shards,
+        identity,
+        &options,
+    )
+    .await?;
+    drop(archive);
+
+    let created_at = now_secs();
+    let file_name = format!("backup-{}.tar", chrono::Utc::now().format("%Y%m%dT%H%M%SZ"));
+    let record = record_group_drop(
+        app,
+        data_dir,
+        identity,
+        &group,
+        backup.threshold,
+        file_name,
+        created,
+    )
+    .await?;
+    let backup_record = BackupRecord {
+        cid: record.cid,
+        created_at,
+        files,
+        bytes,
+    };
+
+    let key = identity.storage_key(b"backups");
+    let mut state = BackupState::load(data_dir, &key)?;
+    state.last_run_at = Some(created_at);
+    state.backups.push(backup_record.clone());
+    let expired = state.backups.len().saturating_sub(backup.keep_last);
+    let expired: Vec<BackupRecord> = state.backups.drain(..expired).collect();
+    state.save(data_dir, &key)?;
+
+    rotate(data_dir, &expired).await;
+    info!(
+        "Backed up {} files ({} bytes) as {}",
+        files, bytes, backup_record.cid
+    );
+    Ok(backup_record)
+}
+
+/// Unpin backups that fell out of the retention window. Chunks still used by
+/// a newer backup stay pinned.
+async fn rotate(data_dir: &Path, expired: &[BackupRecord]) {
+    if expired.is_empty() {
+        return;
+    }
+    let mut catalog = match DropCatalog::load(data_dir) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            warn!("Old backups not rotated: {}", e);
+            return;
+        }
+    };
+    let cache_dir = drop_cache_dir(data_dir);
+    for backup in expired {
+        let piece_cids = catalog.unshared_pieces(&backup.cid);
+        let result = unpin_dead_drop(&backup.cid, &piece_cids, Some(&cache_dir)).await;
+        audit::record(
+            data_dir,
+            AuditAction::Unpin,
+            &backup.cid,
+            serde_json::json!({ "pieces": piece_cids.len(), "backup_rotation": true }),
+            result.as_ref().err().map(|e| format!("{:#}", e)),
+        );
+        if let Err(e) = result {
+            warn!("Failed to unpin old backup {}: {}", backup.cid, e);
+            continue;
+        }
+        if let Some(record) = catalog.get_mut(&backup.cid) {
+            record.pinned = false;
+            if let Err(e) = mfs::unlink_drop(record).await {
+                warn!("Failed to unlink {} from MFS: {}", backup.cid, e);
+            }
+        }
+    }
+    if let Err(e) = catalog.save() {
+        warn!("Failed to update drop catalog after rotation: {}", e);
+    }
+}
+
+/// Write the folders as a tar archive of individually gzipped files, named
+/// `<n>-<folder>/<path>.gz`. Compressing file by file keeps unchanged files
+/// byte-identical between snapshots, so their chunks deduplicate.
+fn pack_folders(
+    folders: &[PathBuf],
+    out: &mut File,
+    staging: Option<PathBuf>,
+) -> Result<(usize, u64)> {
+    let mut builder = tar::Builder::new(out);
+    let mut files = 0;
+    let mut bytes = 0;
+    for (i, folder) in folders.iter().enumerate() {
+        let name = folder
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "root".to_string());
+        let prefix = PathBuf::from(format!("{}-{}", i, name));
+        let mut stack = vec![folder.clone()];
+        while let Some(dir) = stack.pop() {
+            let mut entries = fs::read_dir(&dir)
+                .with_context(|| format!("Failed to read {}", dir.display()))?
+                .collect::<std::io::Result<Vec<_>>>()?;
+            entries.sort_by_key(|e| e.file_name());
+            for entry in entries {
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    stack.push(entry.path());
+                } else if file_type.is_file() {
+                    let relative = entry.path().strip_prefix(folder)?.to_path_buf();
+                    let mut archived = prefix.join(relative).into_os_string();
+                    archived.push(".gz");
+                    bytes += append_compressed(
+                        &mut builder,
+                        &entry.path(),
+                        Path::new(&archived),
+                        staging.as_deref(),
+                    )?;
+                    files += 1;
+                }
+            }
+        }
+    }
+    builder
+        .finish()
+        .context("Failed to finish backup archive")?;
+    Ok((files, bytes))
+}
+
+/// Gzip one file into the archive. Returns its uncompressed size.
+fn append_compressed(
+    builder: &mut tar::Builder<&mut File>,
+    source: &Path,
+    archived: &Path,
+    staging: Option<&Path>,
+) -> Result<u64> {
+    let file =
+        File::open(source).with_context(|| format!("Failed to open {}", source.display()))?;
+    let metadata = file.metadata()?;
+
+    // No name or timestamp in the gzip header, so equal files compress equally
+    let mut compressed = crate::staging::staging_file(staging)?;
+    let mut encoder = GzBuilder::new()
+        .mtime(0)
+        .write(compressed.as_file_mut(), Compression::default());
+    let size = std::io::copy(&mut BufReader::new(file), &mut encoder)?;
+    encoder.finish()?;
+
+    let compressed = compressed.as_file_mut();
+    let compressed_len = compressed.seek(SeekFrom::End(0))?;
+    compressed.seek(SeekFrom::Start(0))?;
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(compressed_len);
+    header.set_mode(0o644);
+    header.set_mtime(
+        metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs()),
+    );
+    header.set_cksum();
+    builder
+        .append_data(&mut header, archived, compressed)
+        .with_context(|| format!("Failed to archive {}", source.display()))?;
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unchanged_folders_pack_identically() {
+        let folder = tempfile::tempdir().unwrap();
+        fs::create_dir(folder.path().join("docs")).unwrap();
+        fs::write(folder.path().join("docs/notes.txt"), b"backup me").unwrap();
+        fs::write(folder.path().join("todo.txt"), b"and me").unwrap();
+        let folders = vec![folder.path().to_path_buf()];
+
+        let mut first = tempfile::tempfile().unwrap();
+        let mut second = tempfile::tempfile().unwrap();
+        assert_eq!(pack_folders(&folders, &mut first, None).unwrap(), (2, 15));
+        pack_folders(&folders, &mut second, None).unwrap();
+
+        let read = |file: &mut File| {
+            let mut bytes = Vec::new();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            std::io::Read::read_to_end(file, &mut bytes).unwrap();
+            bytes
+        };
+        assert_eq!(read(&mut first), read(&mut second));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::crypto::{FileCipher, SessionKey};
+use crate::dead_drop::{
+    unpin_dead_drop, upload_bytes_to_ipfs, DropOptions, MAX_CHUNK_SIZE, MAX_IN_FLIGHT_BUFFERS,
+    MIN_CHUNK_SIZE,
+};
+use crate::password_drop::{DEFAULT_M_COST, DEFAULT_P_COST, DEFAULT_T_COST, MAX_T_COST};
+use crate::settings::DropSettings;
+use anyhow::{Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// Plaintext pushed through each cipher
+const CIPHER_SAMPLE_BYTES: usize = 64 * 1024 * 1024;
+/// Written to and read back from the staging directory
+const DISK_SAMPLE_BYTES: usize = 64 * 1024 * 1024;
+/// Random bytes uploaded to the IPFS node, then unpinned
+const IPFS_SAMPLE_BYTES: usize = 8 * 1024 * 1024;
+/// A passphrase should take about this long to stretch
+const ARGON2_TARGET_MILLIS: u64 = 1000;
+
+/// Measured speeds of this machine and the settings they suggest
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BenchmarkReport {
+    pub argon2: Argon2Timing,
+    pub ciphers: Vec<CipherThroughput>,
+    /// What new drops use on this CPU
+    pub preferred_cipher: FileCipher,
+    pub disk_write_bytes_per_sec: u64,
+    /// Read right after writing, so it may come from the page cache
+    pub disk_read_bytes_per_sec: u64,
+    /// `None` if the IPFS node was skipped or unreachable
+    pub ipfs_upload_bytes_per_sec: Option<u64>,
+    pub cpu_threads: usize,
+    pub recommended: Tuning,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Argon2Timing {
+    pub m_cost: u32, // KiB
+    pub t_cost: u32,
+    pub p_cost: u32,
+    pub millis: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CipherThroughput {
+    pub cipher: FileCipher,
+    pub bytes_per_sec: u64,
+}
+
+/// Settings derived from a benchmark
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tuning {
+    pub chunk_size: usize,
+    pub in_flight_buffers: usize,
+    pub max_concurrent_operations: usize,
+    /// Argon2id passes for password drops at the default memory cost
+    pub argon2_t_cost: u32,
+}
+
+impl Tuning {
+    /// Apply the drop tunables to existing settings, keeping everything else
+    pub fn apply(&self, settings: DropSettings) -> DropSettings {
+        DropSettings {
+            chunk_size: self.chunk_size,
+            in_flight_buffers: self.in_flight_buffers,
+            max_concurrent_operations: self.max_concurrent_operations,
+            ..settings
+        }
+        .clamped()
+    }
+}
+
+/// Benchmark Argon2, both file ciphers, the staging disk and optionally the
+/// IPFS node. Takes a few seconds; the CPU and disk parts run off the runtime.
+pub async fn run_benchmark(options: &DropOptions, include_ipfs: bool) -> Result<BenchmarkReport> {
+    let chunk_size = options.chunk_size;
+    let argon2 = tokio::task::spawn_blocking(time_argon2).await??;
+    let ciphers = tokio::task::spawn_blocking(move || cipher_throughput(chunk_size)).await??;
+
+    let staging = options.staging_file()?;
+    let (disk_write_bytes_per_sec, disk_read_bytes_per_sec) =
+        tokio::task::spawn_blocking(move || disk_throughput(staging)).await??;
+
+    let ipfs_upload_bytes_per_sec = if include_ipfs {
+        ipfs_throughput()
+            .await
+            .map_err(|e| warn!("IPFS upload benchmark failed: {:#}", e))
+            .ok()
+    } else {
+        None
+    };
+
+    let cpu_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let preferred_cipher = FileCipher::preferred();
+    let cipher_bytes_per_sec = ciphers
+        .iter()
+        .find(|c| c.cipher == preferred_cipher)
+        .map_or(0, |c| c.bytes_per_sec);
+    let recommended = recommend(
+        &argon2,
+        cipher_bytes_per_sec,
+        disk_write_bytes_per_sec.min(disk_read_bytes_per_sec),
+        cpu_threads,
+    );
+
+    info!(
+        "Benchmark: Argon2 {} ms, cipher {} MB/s, disk {}/{} MB/s",
+        argon2.millis,
+        cipher_bytes_per_sec / 1_000_000,
+        disk_write_bytes_per_sec / 1_000_000,
+        disk_read_bytes_per_sec / 1_000_000
+    );
+    Ok(BenchmarkReport {
+        argon2,
+        ciphers,
+        preferred_cipher,
+        disk_write_bytes_per_sec,
+        disk_read_bytes_per_sec,
+        ipfs_upload_bytes_per_sec,
+        cpu_threads,
+        recommended,
+    })
+}
+
+fn time_argon2() -> Result<Argon2Timing> {
+    let params = Params::new(DEFAULT_M_COST, DEFAULT_T_COST, DEFAULT_P_COST, Some(32))
+        .map_err(|e| anyhow::anyhow!("Invalid Argon2 params: {:?}", e))?;
+    let mut key = [0u8; 32];
+    let started = Instant::now();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(b"benchmark passphrase", b"benchmark salt", &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive key: {:?}", e))?;
+    Ok(Argon2Timing {
+        m_cost: DEFAULT_M_COST,
+        t_cost: DEFAULT_T_COST,
+        p_cost: DEFAULT_P_COST,
+        millis: started.elapsed().as_millis() as u64,
+    })
+}
+
+fn cipher_throughput(chunk_size: usize) -> Result<Vec<CipherThroughput>> {
+    let key = SessionKey::generate();
+    let mut chunk = vec![0u8; chunk_size];
+    rand::thread_rng().fill_bytes(&mut chunk);
+    let rounds = CIPHER_SAMPLE_BYTES.div_ceil(chunk_size);
+
+    let mut results = Vec::new();
+    for cipher in [FileCipher::ChaCha20Poly1305, FileCipher::Aes256Gcm] {
+        let started = Instant::now();
+        for _ in 0..rounds {
+            key.encrypt_chunk(cipher, &chunk)?;
+        }
+        results.push(CipherThroughput {
+            cipher,
+            bytes_per_sec: rate((rounds * chunk_size) as u64, started),
+        });
+    }
+    Ok(results)
+}
+
+/// Write and read back a sample in the staging directory, where drops are
+/// actually staged; the temp file is removed when dropped
+fn disk_throughput(staging: tempfile::NamedTempFile) -> Result<(u64, u64)> {
+    let mut block = vec![0u8; MIN_CHUNK_SIZE];
+    rand::thread_rng().fill_bytes(&mut block);
+
+    let started = Instant::now();
+    let mut file = File::create(staging.path())?;
+    for _ in 0..DISK_SAMPLE_BYTES / block.len() {
+        file.write_all(&block)?;
+    }
+    file.sync_all().context("Failed to sync benchmark file")?;
+    let write = rate(DISK_SAMPLE_BYTES as u64, started);
+
+    let started = Instant::now();
+    let mut file = File::open(staging.path())?;
+    while file.read(&mut block)? > 0 {}
+    let read = rate(DISK_SAMPLE_BYTES as u64, started);
+    Ok((write, read))
+}
+
+async fn ipfs_throughput() -> Result<u64> {
+    let mut sample = vec![0u8; IPFS_SAMPLE_BYTES];
+    rand::thread_rng().fill_bytes(&mut sample);
+    let started = Instant::now();
+    let cid = upload_bytes_to_ipfs(sample).await?;
+    let upload = rate(IPFS_SAMPLE_BYTES as u64, started);
+    unpin_dead_drop(&cid, &[], None).await?;
+    Ok(upload)
+}
+
+fn rate(bytes: u64, started: Instant) -> u64 {
+    let micros = started.elapsed().as_micros().max(1) as u64;
+    bytes.saturating_mul(1_000_000) / micros
+}
+
+/// Bigger chunks where cipher and disk are fast (fewer frames and syscalls),
+/// deeper read-ahead where the disk lags the cipher, and one operation per
+/// two cores
+fn recommend(
+    argon2: &Argon2Timing,
+    cipher_bytes_per_sec: u64,
+    disk_bytes_per_sec: u64,
+    cpu_threads: usize,
+) -> Tuning {
+    const MB: u64 = 1_000_000;
+    let pipeline = cipher_bytes_per_sec.min(disk_bytes_per_sec);
+    let chunk_size = match pipeline {
+        rate if rate >= 1000 * MB => MAX_CHUNK_SIZE,
+        rate if rate >= 300 * MB => 8 * 1024 * 1024,
+        _ => 4 * 1024 * 1024,
+    };
+    let in_flight_buffers = if disk_bytes_per_sec < cipher_bytes_per_sec {
+        MAX_IN_FLIGHT_BUFFERS.min(4)
+    } else {
+        2
+    };
+
+    // Passes scale linearly, so aim the default memory cost at the target time
+    let per_pass = (argon2.millis / argon2.t_cost as u64).max(1);
+    let argon2_t_cost =
+        ((ARGON2_TARGET_MILLIS / per_pass) as u32).clamp(DEFAULT_T_COST, MAX_T_COST);
+
+    Tuning {
+        chunk_size,
+        in_flight_buffers,
+        max_concurrent_operations: (cpu_threads / 2).clamp(1, 8),
+        argon2_t_cost,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tuning_follows_the_bottleneck() {
+        let argon2 = Argon2Timing {
+            m_cost: DEFAULT_M_COST,
+            t_cost: 3,
+            p_cost: 1,
+            millis: 150,
+        };
+        let fast = recommend(&argon2, 2_000_000_000, 3_000_000_000, 16);
+        assert_eq!(fast.chunk_size, MAX_CHUNK_SIZE);
+        assert_eq!(fast.in_flight_buffers, 2);
+        assert_eq!(fast.max_concurrent_operations, 8);
+        assert_eq!(fast.argon2_t_cost, 16);
+
+        let slow_disk = recommend(&argon2, 2_000_000_000, 100_000_000, 2);
+        assert_eq!(slow_disk.chunk_size, 4 * 1024 * 1024);
+        assert_eq!(slow_disk.in_flight_buffers, 4);
+        assert_eq!(slow_disk.max_concurrent_operations, 1);
+    }
+}
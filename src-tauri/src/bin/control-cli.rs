@@ -0,0 +1,255 @@
+//! Headless Control: the same identity, Ghost Mode and dead drops as the app,
+//! for servers and scripts. Results go to stdout as JSON, logs to stderr.
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use control::crypto::Identity;
+use control::shards::SealedShard;
+use control::{conformance, daemon, logging, settings, storage};
+use control::{ChannelEvents, DropManager, IdentityStore, NewDrop, Node, NodeEvent};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Must match `tauri.bundle.identifier` so the CLI shares the app's data directory
+const APP_IDENTIFIER: &str = "com.control.app";
+const PASSWORD_ENV: &str = "CONTROL_PASSWORD";
+
+#[derive(Parser)]
+#[command(name = "control-cli", about = "Control without the UI", version)]
+struct Cli {
+    /// Use this data directory instead of the app's
+    #[arg(long, global = true)]
+    data_dir: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create or unlock the identity and print its public ID
+    Init,
+    /// Send a Ghost Mode message and wait for its delivery receipt
+    Send {
+        target_public_key: String,
+        message: String,
+        /// Seconds to wait for peer discovery before sending
+        #[arg(long, default_value_t = 5)]
+        warmup: u64,
+        /// Seconds to wait for the delivery receipt
+        #[arg(long, default_value_t = 300)]
+        timeout: u64,
+    },
+    /// Print incoming Ghost Mode events as JSON lines until interrupted
+    Listen,
+    /// Keep Ghost Mode online in the background; the app, `send` and `listen`
+    /// connect to it instead of starting their own P2P node
+    Daemon {
+        /// Also serve the HTTP/WebSocket API on 127.0.0.1:PORT, authenticated
+        /// with the token in `daemon.token`
+        #[arg(long)]
+        api_port: Option<u16>,
+    },
+    /// Dead drops
+    #[command(subcommand)]
+    Drop(DropCommand),
+    /// Check this build against the published protocol test vectors and
+    /// print the results as JSON
+    Conformance,
+}
+
+#[derive(Subcommand)]
+enum DropCommand {
+    /// Encrypt and upload a file, printing its CID and shards. Pass `-` to
+    /// read the plaintext from stdin without writing it to disk.
+    Create {
+        file: String,
+        /// Catalog name for a drop read from stdin
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        threshold: u8,
+        #[arg(long)]
+        shards: u8,
+        /// Seal shard N to the Nth recipient public ID (repeat once per shard)
+        #[arg(long = "recipient")]
+        recipients: Vec<String>,
+        #[arg(long)]
+        expires_in_secs: Option<u64>,
+    },
+    /// Download and decrypt a drop
+    Retrieve {
+        cid: String,
+        #[arg(long = "shard", required = true)]
+        shards: Vec<String>,
+        #[arg(long)]
+        output: String,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    logging::init();
+    let cli = Cli::parse();
+
+    let data_dir = match cli.data_dir {
+        Some(dir) => dir,
+        None => {
+            let mut config = tauri::Config::default();
+            config.tauri.bundle.identifier = APP_IDENTIFIER.to_string();
+            storage::init(&config)?
+        }
+    };
+    settings::init(&data_dir)?;
+    logging::start_file_logging(&data_dir);
+
+    match cli.command {
+        Command::Init => {
+            let identity = load_identity(&data_dir, true)?;
+            println!("{}", identity.public_id());
+        }
+        Command::Send {
+            target_public_key,
+            message,
+            warmup,
+            timeout,
+        } => send(&data_dir, target_public_key, message, warmup, timeout).await?,
+        Command::Listen => listen(&data_dir).await?,
+        Command::Daemon { api_port } => {
+            let identity = load_identity(&data_dir, false)?;
+            daemon::run_daemon(identity, &data_dir, api_port).await?;
+        }
+        Command::Drop(DropCommand::Create {
+            file,
+            name,
+            threshold,
+            shards,
+            recipients,
+            expires_in_secs,
+        }) => {
+            create_drop(&data_dir, &file, name, threshold, shards, recipients, expires_in_secs)
+                .await?
+        }
+        Command::Drop(DropCommand::Retrieve { cid, shards, output }) => {
+            retrieve_drop(&data_dir, &cid, shards, &output).await?
+        }
+        Command::Conformance => {
+            let report = conformance::verify_conformance()?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.passed() {
+                anyhow::bail!("Some test vectors failed");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Load the identity with the password from `CONTROL_PASSWORD` or a prompt
+fn load_identity(data_dir: &Path, create: bool) -> Result<Identity> {
+    let store = IdentityStore::new(data_dir);
+    if !create && !store.exists() {
+        anyhow::bail!("No identity yet, run `control-cli init` first");
+    }
+
+    let password = match std::env::var(PASSWORD_ENV) {
+        Ok(password) => password,
+        Err(_) => rpassword::prompt_password("Password: ").context("Failed to read password")?,
+    };
+    store.open(&password).context("Failed to unlock identity")
+}
+
+/// Use the running daemon if there is one (no password needed), else start P2P here
+async fn start_ghost_mode(data_dir: &Path) -> Result<(Node, mpsc::UnboundedReceiver<NodeEvent>)> {
+    let (events, events_rx) = ChannelEvents::new();
+    if let Some(node) = Node::attach(data_dir, events.clone()).await? {
+        return Ok((node, events_rx));
+    }
+
+    let identity = load_identity(data_dir, false)?;
+    let node = Node::builder(identity, data_dir).events(events).start()?;
+    Ok((node, events_rx))
+}
+
+async fn send(data_dir: &Path, target: String, content: String, warmup: u64, timeout: u64) -> Result<()> {
+    let (node, mut events) = start_ghost_mode(data_dir).await?;
+    tokio::time::sleep(Duration::from_secs(warmup)).await;
+
+    let message_id = node.send_message(&target, &content).await?;
+
+    let outcome = tokio::time::timeout(Duration::from_secs(timeout), async {
+        while let Some(NodeEvent { name, payload }) = events.recv().await {
+            let for_us = payload["message_id"].as_str() == Some(message_id.as_str());
+            match name.as_str() {
+                "msg_delivered" if for_us => return Ok(()),
+                "msg_failed" if for_us => {
+                    anyhow::bail!("Not delivered: {}", payload["reason"].as_str().unwrap_or("unknown"))
+                }
+                "ghost_error" => {
+                    anyhow::bail!("{}", payload["message"].as_str().unwrap_or("Send failed"))
+                }
+                _ => {}
+            }
+        }
+        anyhow::bail!("Ghost Mode stopped")
+    })
+    .await
+    .context("Timed out waiting for the delivery receipt")?;
+
+    node.shutdown().await;
+    outcome?;
+    println!("{}", message_id);
+    Ok(())
+}
+
+async fn listen(data_dir: &Path) -> Result<()> {
+    let (node, mut events) = start_ghost_mode(data_dir).await?;
+
+    loop {
+        tokio::select! {
+            Some(NodeEvent { name, payload }) = events.recv() => {
+                println!("{}", serde_json::json!({ "event": name, "payload": payload }));
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    node.shutdown().await;
+    Ok(())
+}
+
+async fn create_drop(
+    data_dir: &Path,
+    file: &str,
+    name: Option<String>,
+    threshold: u8,
+    total_shards: u8,
+    recipients: Vec<String>,
+    expires_in_secs: Option<u64>,
+) -> Result<()> {
+    let drops = DropManager::builder(data_dir).build();
+    let request = NewDrop {
+        name,
+        threshold,
+        total_shards,
+        recipients,
+        expires_in_secs,
+    };
+    let created = if file == "-" {
+        drops.create_from_reader(std::io::stdin(), request).await?
+    } else {
+        drops.create_file(file, request).await?
+    };
+
+    println!("{}", serde_json::to_string_pretty(&created)?);
+    Ok(())
+}
+
+async fn retrieve_drop(data_dir: &Path, cid: &str, shards: Vec<String>, output: &str) -> Result<()> {
+    let mut drops = DropManager::builder(data_dir);
+    if shards.iter().any(|s| SealedShard::parse(s).is_some()) {
+        drops = drops.identity(load_identity(data_dir, false)?);
+    }
+    drops.build().retrieve(cid, shards, output).await
+}
@@ -0,0 +1,197 @@
+use crate::catalog::now_secs;
+use crate::crypto::{Identity, SessionKey};
+use crate::no_traces;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
+
+const BURN_REGISTRY_FILE: &str = "burn_keys.enc";
+const BURN_SHARD_PREFIX: &str = "burn";
+
+/// Unwrap key for a burn-after-read drop, held only by the creator's node
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BurnEntry {
+    pub drop_id: String,
+    pub cid: String,
+    pub unwrap_key: String, // hex
+    pub created_at: u64,
+    pub burned_at: Option<u64>,
+}
+
+/// Emitted to the frontend when a burn key is consumed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DropBurned {
+    pub drop_id: String,
+    pub cid: String,
+    pub burned_at: u64,
+    pub consumed_by: String,
+}
+
+/// A shard wrapped for burn-after-read: `burn:<drop_id>:<creator_id>:<hex>`
+pub struct WrappedShard {
+    pub drop_id: String,
+    pub creator_id: String,
+    pub ciphertext: Vec<u8>,
+}
+
+impl WrappedShard {
+    pub fn parse(shard: &str) -> Option<Result<Self>> {
+        let rest = shard.strip_prefix(BURN_SHARD_PREFIX)?.strip_prefix(':')?;
+        Some(Self::parse_body(rest))
+    }
+
+    fn parse_body(rest: &str) -> Result<Self> {
+        let mut parts = rest.splitn(3, ':');
+        let drop_id = parts.next().context("Missing drop ID in shard")?;
+        let creator_id = parts.next().context("Missing creator ID in shard")?;
+        let payload = parts.next().context("Missing payload in shard")?;
+
+        Ok(Self {
+            drop_id: drop_id.to_string(),
+            creator_id: creator_id.to_string(),
+            ciphertext: hex::decode(payload).context("Invalid hex in wrapped shard")?,
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            BURN_SHARD_PREFIX,
+            self.drop_id,
+            self.creator_id,
+            hex::encode(&self.ciphertext)
+        )
+    }
+}
+
+/// Wrap plain hex shards with a fresh one-time unwrap key
+pub fn wrap_shards(
+    shards: &[String],
+    drop_id: &str,
+    creator_id: &str,
+) -> Result<(Vec<String>, SessionKey)> {
+    let wrap_key = SessionKey::generate();
+
+    let wrapped = shards
+        .iter()
+        .map(|shard| {
+            let ciphertext = wrap_key.encrypt_file(shard.as_bytes())?;
+            Ok(WrappedShard {
+                drop_id: drop_id.to_string(),
+                creator_id: creator_id.to_string(),
+                ciphertext,
+            }
+            .encode())
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((wrapped, wrap_key))
+}
+
+/// Unwrap burn shards back into plain hex shards
+pub fn unwrap_shards(shards: &[WrappedShard], unwrap_key: &SessionKey) -> Result<Vec<String>> {
+    shards
+        .iter()
+        .map(|shard| {
+            let plain = unwrap_key
+                .decrypt_file(&shard.ciphertext)
+                .context("Failed to unwrap shard - wrong drop?")?;
+            String::from_utf8(plain).context("Unwrapped shard is not valid text")
+        })
+        .collect()
+}
+
+/// Encrypted registry of burn keys, bound to the local identity
+pub struct BurnRegistry {
+    path: PathBuf,
+    storage_key: SessionKey,
+    entries: Vec<BurnEntry>,
+}
+
+impl BurnRegistry {
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(BURN_REGISTRY_FILE);
+        let storage_key = identity.storage_key(b"burn-registry");
+
+        let entries = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read burn registry")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt burn registry")?;
+            let entries = serde_json::from_slice(&json);
+            json.zeroize();
+            entries.context("Failed to parse burn registry")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            entries,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut json = serde_json::to_vec(&self.entries)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write burn registry")?;
+        Ok(())
+    }
+
+    pub fn register(&mut self, drop_id: &str, cid: &str, unwrap_key: &SessionKey) {
+        let mut key_bytes = unwrap_key.as_bytes();
+        self.entries.push(BurnEntry {
+            drop_id: drop_id.to_string(),
+            cid: cid.to_string(),
+            unwrap_key: hex::encode(key_bytes),
+            created_at: now_secs(),
+            burned_at: None,
+        });
+        key_bytes.zeroize();
+    }
+
+    /// Release the unwrap key exactly once. Returns `Err` with the burn time
+    /// if the key has already been consumed.
+    pub fn consume(&mut self, drop_id: &str) -> Result<(SessionKey, DropBurnedInfo)> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|e| e.drop_id == drop_id)
+            .context("Unknown burn-after-read drop")?;
+
+        if let Some(burned_at) = entry.burned_at {
+            anyhow::bail!("Drop already burned at {}", burned_at);
+        }
+
+        let mut key_bytes = hex::decode(&entry.unwrap_key).context("Corrupt burn key")?;
+        let key = SessionKey::from_bytes(&key_bytes);
+        key_bytes.zeroize();
+
+        let burned_at = now_secs();
+        entry.burned_at = Some(burned_at);
+        // The key is never needed again
+        entry.unwrap_key.zeroize();
+
+        Ok((
+            key?,
+            DropBurnedInfo {
+                cid: entry.cid.clone(),
+                burned_at,
+            },
+        ))
+    }
+}
+
+/// Details recorded when a burn key is released
+pub struct DropBurnedInfo {
+    pub cid: String,
+    pub burned_at: u64,
+}
@@ -0,0 +1,372 @@
+use crate::burn::WrappedShard;
+use crate::catalog::now_secs;
+use crate::crypto::{decode_public_key, Identity, SessionKey};
+use crate::no_traces;
+use crate::p2p::{P2PEvents, P2PMessage};
+use crate::polls::PollMessage;
+use anyhow::{Context, Result};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error, warn};
+use zeroize::Zeroize;
+
+const CANARY_FILE: &str = "canaries.enc";
+/// Oldest alerts are dropped past this many
+const MAX_ALERTS: usize = 200;
+const MAX_LABEL_LEN: usize = 64;
+/// Size of the random payload in a canary shard, like a wrapped real shard
+const CANARY_SHARD_BYTES: usize = 96;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CanarySettings {
+    /// Lock the session as soon as a canary is tripped
+    pub auto_lock: bool,
+}
+
+/// A public ID that should never get in touch: a decoy planted in an address
+/// book or shared nowhere
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CanaryContact {
+    pub public_id: String,
+    pub label: String,
+    pub created_at: u64,
+}
+
+/// Shards of a drop that doesn't exist. They look like burn-after-read
+/// shards naming us as creator, so whoever tries them asks us for the key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CanaryDrop {
+    pub drop_id: String,
+    pub label: String,
+    pub shards: Vec<String>,
+    pub created_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CanaryTrigger {
+    /// A canary contact sent us something
+    Contact,
+    /// A message or introduction named a canary
+    Mention,
+    /// Someone asked us for the key of a canary drop
+    BurnKeyRequest,
+    /// Canary shards were used to retrieve on this device
+    Retrieval,
+}
+
+/// Emitted as `canary_alert`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CanaryAlert {
+    pub at: u64,
+    pub trigger: CanaryTrigger,
+    /// Public ID or drop ID of the canary
+    pub canary: String,
+    pub label: String,
+    /// Who set it off, when known
+    pub source: Option<String>,
+}
+
+/// Everything planted, for the settings screen
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Canaries {
+    pub contacts: Vec<CanaryContact>,
+    pub drops: Vec<CanaryDrop>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CanaryState {
+    contacts: Vec<CanaryContact>,
+    drops: Vec<CanaryDrop>,
+    alerts: Vec<CanaryAlert>,
+}
+
+/// Canaries and their alerts, encrypted with the local identity
+pub struct CanaryStore {
+    path: PathBuf,
+    storage_key: SessionKey,
+    state: CanaryState,
+}
+
+impl CanaryStore {
+    /// Load the store, starting empty if it does not exist yet
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(CANARY_FILE);
+        let storage_key = identity.storage_key(b"canaries");
+
+        let state = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read canaries")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt canaries")?;
+            let state = serde_json::from_slice(&json);
+            json.zeroize();
+            state.context("Failed to parse canaries")?
+        } else {
+            CanaryState::default()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            state,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut json = serde_json::to_vec(&self.state)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write canaries")?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Canaries {
+        Canaries {
+            contacts: self.state.contacts.clone(),
+            drops: self.state.drops.clone(),
+        }
+    }
+
+    /// Most recent first
+    pub fn alerts(&self) -> Vec<CanaryAlert> {
+        self.state.alerts.iter().rev().cloned().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.contacts.is_empty() && self.state.drops.is_empty()
+    }
+
+    pub fn add_contact(&mut self, public_id: &str, label: &str) -> Result<CanaryContact> {
+        decode_public_key(public_id).context("Invalid public ID")?;
+        let label = check_label(label)?;
+        self.state.contacts.retain(|c| c.public_id != public_id);
+        let contact = CanaryContact {
+            public_id: public_id.to_string(),
+            label,
+            created_at: now_secs(),
+        };
+        self.state.contacts.push(contact.clone());
+        Ok(contact)
+    }
+
+    /// Make shards to plant somewhere; any use of them raises an alert
+    pub fn create_drop(
+        &mut self,
+        identity: &Identity,
+        label: &str,
+        shards: u8,
+    ) -> Result<CanaryDrop> {
+        let label = check_label(label)?;
+        if shards < 2 {
+            anyhow::bail!("A canary drop needs at least 2 shards");
+        }
+        let drop_id = uuid::Uuid::new_v4().to_string();
+        let shards = (0..shards)
+            .map(|_| {
+                let mut ciphertext = vec![0u8; CANARY_SHARD_BYTES];
+                rand::thread_rng().fill_bytes(&mut ciphertext);
+                WrappedShard {
+                    drop_id: drop_id.clone(),
+                    creator_id: identity.public_id(),
+                    ciphertext,
+                }
+                .encode()
+            })
+            .collect();
+        let drop = CanaryDrop {
+            drop_id,
+            label,
+            shards,
+            created_at: now_secs(),
+        };
+        self.state.drops.push(drop.clone());
+        Ok(drop)
+    }
+
+    /// Remove a canary contact or drop; true if there was one
+    pub fn remove(&mut self, canary: &str) -> bool {
+        let before = self.state.contacts.len() + self.state.drops.len();
+        self.state.contacts.retain(|c| c.public_id != canary);
+        self.state.drops.retain(|d| d.drop_id != canary);
+        self.state.contacts.len() + self.state.drops.len() != before
+    }
+
+    pub fn contact_label(&self, public_id: &str) -> Option<&str> {
+        self.state
+            .contacts
+            .iter()
+            .find(|c| c.public_id == public_id)
+            .map(|c| c.label.as_str())
+    }
+
+    pub fn drop_label(&self, drop_id: &str) -> Option<&str> {
+        self.state
+            .drops
+            .iter()
+            .find(|d| d.drop_id == drop_id)
+            .map(|d| d.label.as_str())
+    }
+
+    /// The first canary named anywhere in `text`, as (canary, label)
+    pub fn mentioned_in(&self, text: &str) -> Option<(String, String)> {
+        let contacts = self.state.contacts.iter().map(|c| (&c.public_id, &c.label));
+        let drops = self.state.drops.iter().map(|d| (&d.drop_id, &d.label));
+        contacts
+            .chain(drops)
+            .find(|(canary, _)| text.contains(canary.as_str()))
+            .map(|(canary, label)| (canary.clone(), label.clone()))
+    }
+
+    pub fn trip(
+        &mut self,
+        trigger: CanaryTrigger,
+        canary: &str,
+        label: &str,
+        source: Option<&str>,
+    ) -> CanaryAlert {
+        let alert = CanaryAlert {
+            at: now_secs(),
+            trigger,
+            canary: canary.to_string(),
+            label: label.to_string(),
+            source: source.map(str::to_string),
+        };
+        self.state.alerts.push(alert.clone());
+        if self.state.alerts.len() > MAX_ALERTS {
+            let excess = self.state.alerts.len() - MAX_ALERTS;
+            self.state.alerts.drain(..excess);
+        }
+        alert
+    }
+}
+
+fn check_label(label: &str) -> Result<String> {
+    let label = label.trim();
+    if label.chars().count() > MAX_LABEL_LEN {
+        anyhow::bail!("Label is longer than {} characters", MAX_LABEL_LEN);
+    }
+    Ok(label.to_string())
+}
+
+/// Emit `canary_alert` and let the frontend act on it (notify, lock)
+pub fn raise(events: &dyn P2PEvents, alert: &CanaryAlert) {
+    error!(
+        "Canary {} ({}) tripped: {:?} from {}",
+        alert.label,
+        alert.canary,
+        alert.trigger,
+        alert.source.as_deref().unwrap_or("this device")
+    );
+    let _ = events.emit("canary_alert", alert);
+    events.canary_tripped(alert);
+}
+
+/// Check an incoming envelope against the canaries
+pub fn inspect(
+    data_dir: &Path,
+    identity: &Identity,
+    events: &dyn P2PEvents,
+    sender_id: &str,
+    message: &P2PMessage,
+) {
+    let result = CanaryStore::load(data_dir, identity).and_then(|mut store| {
+        if store.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut alerts = Vec::new();
+        if let Some(label) = store.contact_label(sender_id).map(str::to_string) {
+            alerts.push(store.trip(CanaryTrigger::Contact, sender_id, &label, Some(sender_id)));
+        }
+        let mentioned = match message {
+            P2PMessage::Message(message) => store.mentioned_in(&message.content),
+            P2PMessage::ContactRequest(request) => store.mentioned_in(&request.note),
+            P2PMessage::Introduction(signed) => store.mentioned_in(&signed.statement),
+            P2PMessage::EscrowRequest(signed) => store.mentioned_in(&signed.statement),
+            P2PMessage::Poll(PollMessage::Open { poll }) => store.mentioned_in(&poll.statement),
+            _ => None,
+        };
+        if let Some((canary, label)) = mentioned.filter(|(canary, _)| canary != sender_id) {
+            alerts.push(store.trip(CanaryTrigger::Mention, &canary, &label, Some(sender_id)));
+        }
+        if let P2PMessage::BurnKeyRequest(request) = message {
+            if let Some(label) = store.drop_label(&request.drop_id).map(str::to_string) {
+                alerts.push(store.trip(
+                    CanaryTrigger::BurnKeyRequest,
+                    &request.drop_id,
+                    &label,
+                    Some(sender_id),
+                ));
+            }
+        }
+        if !alerts.is_empty() {
+            store.save()?;
+        }
+        Ok(alerts)
+    });
+
+    match result {
+        Ok(alerts) => alerts.iter().for_each(|alert| raise(events, alert)),
+        Err(e) => warn!("Failed to check canaries: {}", e),
+    }
+}
+
+/// Alert if a burn drop about to be retrieved here is a canary
+pub fn check_retrieval(
+    data_dir: &Path,
+    identity: &Identity,
+    events: &dyn P2PEvents,
+    drop_id: &str,
+) -> Result<()> {
+    let mut store = CanaryStore::load(data_dir, identity)?;
+    if let Some(label) = store.drop_label(drop_id).map(str::to_string) {
+        let alert = store.trip(CanaryTrigger::Retrieval, drop_id, &label, None);
+        store.save()?;
+        raise(events, &alert);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canary_shards_point_back_to_us() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = Identity::generate();
+        let mut store = CanaryStore::load(dir.path(), &identity).unwrap();
+
+        let drop = store.create_drop(&identity, "backup folder", 3).unwrap();
+        assert_eq!(drop.shards.len(), 3);
+        let shard = WrappedShard::parse(&drop.shards[0]).unwrap().unwrap();
+        assert_eq!(shard.drop_id, drop.drop_id);
+        assert_eq!(shard.creator_id, identity.public_id());
+        assert_eq!(store.drop_label(&drop.drop_id), Some("backup folder"));
+    }
+
+    #[test]
+    fn finds_mentions() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = Identity::generate();
+        let decoy = Identity::generate().public_id();
+        let mut store = CanaryStore::load(dir.path(), &identity).unwrap();
+        store.add_contact(&decoy, "decoy").unwrap();
+
+        let text = format!("can you introduce me to {}?", decoy);
+        assert_eq!(
+            store.mentioned_in(&text),
+            Some((decoy.clone(), "decoy".to_string()))
+        );
+        assert_eq!(store.mentioned_in("hello"), None);
+        assert!(store.remove(&decoy));
+        assert!(store.is_empty());
+    }
+}
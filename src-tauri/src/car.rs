@@ -0,0 +1,144 @@
+use crate::dead_drop::{drop_manifest_cid, DropOptions};
+use crate::ipfs;
+use crate::stego::stego_cid;
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use reqwest::multipart;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+use tracing::info;
+
+/// Result of importing a CAR archive into the local node
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CarImportResult {
+    pub roots: Vec<String>,
+    pub bytes: u64,
+}
+
+/// Export a drop's encrypted blocks into a CAR archive for offline transport.
+/// Returns the archive size.
+pub async fn export_drop_car(cid: &str, output_path: &Path, options: &DropOptions) -> Result<u64> {
+    // A hidden drop is the single carrier image
+    let cid = stego_cid(cid).unwrap_or(cid);
+    if drop_manifest_cid(cid).is_some() {
+        anyhow::bail!(
+            "Drops made of a manifest and pieces span several CIDs and can't be exported as one CAR"
+        );
+    }
+
+    let client = ipfs::client()?;
+    let response = client
+        .post(ipfs::api("dag/export"))
+        .query(&[("arg", cid)])
+        .send()
+        .await
+        .context("Failed to export CAR from IPFS")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("CAR export failed: {}", response.status());
+    }
+
+    let mut file = tokio::fs::File::create(output_path)
+        .await
+        .context("Failed to create CAR file")?;
+
+    let content_length = response.content_length().unwrap_or(0);
+    let mut stream = response.bytes_stream();
+    let mut written = 0u64;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.context("Failed to read CAR from IPFS")?;
+        tokio::io::AsyncWriteExt::write_all(&mut file, &chunk)
+            .await
+            .context("Failed to write CAR file")?;
+
+        written += chunk.len() as u64;
+        options.report("exporting", written, content_length);
+    }
+
+    tokio::io::AsyncWriteExt::flush(&mut file)
+        .await
+        .context("Failed to flush CAR file")?;
+
+    info!("Exported {} ({} bytes) to {}", cid, written, output_path.display());
+
+    Ok(written)
+}
+
+/// Import a CAR archive into the local node and pin its roots, so the drop can
+/// be retrieved offline
+pub async fn import_drop_car(car_path: &Path, options: &DropOptions) -> Result<CarImportResult> {
+    let client = ipfs::client()?;
+
+    let file = tokio::fs::File::open(car_path)
+        .await
+        .context("Failed to open CAR file")?;
+    let file_size = file
+        .metadata()
+        .await
+        .context("Failed to get CAR file metadata")?
+        .len();
+
+    let reader = tokio::io::BufReader::new(file);
+    let upload_options = options.clone();
+    let stream = futures::stream::try_unfold((reader, 0u64), move |(mut reader, uploaded)| {
+        let options = upload_options.clone();
+        async move {
+            let mut chunk = vec![0u8; options.chunk_size];
+            let bytes_read = reader.read(&mut chunk).await?;
+            if bytes_read == 0 {
+                return Ok::<_, std::io::Error>(None); // EOF
+            }
+            chunk.truncate(bytes_read);
+            let uploaded = uploaded + bytes_read as u64;
+            options.report("importing", uploaded, file_size);
+            Ok(Some((bytes::Bytes::from(chunk), (reader, uploaded))))
+        }
+    });
+
+    let part = multipart::Part::stream_with_length(reqwest::Body::wrap_stream(stream), file_size)
+        .file_name("drop.car")
+        .mime_str("application/vnd.ipld.car")?;
+    let form = multipart::Form::new().part("file", part);
+
+    let response = client
+        .post(ipfs::api("dag/import"))
+        .query(&[("pin-roots", "true")])
+        .multipart(form)
+        .send()
+        .await
+        .context("Failed to import CAR into IPFS")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("CAR import failed: {}", response.status());
+    }
+
+    // Response is newline-delimited JSON, one object per root
+    let body = response.text().await?;
+    let mut roots = Vec::new();
+    for line in body.lines().filter(|l| !l.trim().is_empty()) {
+        let json: serde_json::Value = serde_json::from_str(line).context("Invalid CAR import response")?;
+        let Some(root) = json.get("Root") else {
+            continue;
+        };
+        let cid = root["Cid"]["/"]
+            .as_str()
+            .context("No root CID in CAR import response")?;
+        if let Some(error) = root["PinErrorMsg"].as_str().filter(|e| !e.is_empty()) {
+            anyhow::bail!("Failed to pin CAR root {}: {}", cid, error);
+        }
+        roots.push(cid.to_string());
+    }
+
+    if roots.is_empty() {
+        anyhow::bail!("CAR archive has no roots");
+    }
+
+    info!("Imported CAR {} with roots {:?}", car_path.display(), roots);
+
+    Ok(CarImportResult {
+        roots,
+        bytes: file_size,
+    })
+}
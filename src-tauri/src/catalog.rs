@@ -0,0 +1,126 @@
+use crate::crypto::FileCipher;
+use crate::groups::GroupDrop;
+use crate::no_traces;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CATALOG_FILE: &str = "drops.json";
+
+/// A dead drop created on this device
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DropRecord {
+    pub cid: String,
+    pub file_name: String,
+    pub threshold: u8,
+    pub total_shards: u8,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub pinned: bool,
+    #[serde(default)]
+    pub drop_id: Option<String>, // Stable ID for versioned drops
+    #[serde(default)]
+    pub piece_cids: Vec<String>, // Erasure pieces, indexed ciphertext or dedup chunks
+    #[serde(default)]
+    pub reshared_at: Option<u64>, // Last time a fresh shard set was dealt
+    #[serde(default)]
+    pub group: Option<GroupDrop>, // Members holding shards and their receipts
+    #[serde(default)]
+    pub imported: bool, // Adopted from the node; no shards were dealt here
+    #[serde(default)]
+    pub cipher: Option<FileCipher>, // Chunk cipher, if known; older drops used ChaCha20-Poly1305
+}
+
+impl DropRecord {
+    /// Drop has passed its expiry time
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.map(|t| now >= t).unwrap_or(false)
+    }
+}
+
+/// Local catalog of created drops, persisted as JSON in the app data directory
+pub struct DropCatalog {
+    path: PathBuf,
+    records: Vec<DropRecord>,
+}
+
+impl DropCatalog {
+    /// Load the catalog, starting empty if it does not exist yet
+    pub fn load(data_dir: &Path) -> Result<Self> {
+        let path = data_dir.join(CATALOG_FILE);
+
+        let records = if let Some(json) =
+            no_traces::read_file(&path).context("Failed to read drop catalog")?
+        {
+            serde_json::from_slice(&json).context("Failed to parse drop catalog")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self { path, records })
+    }
+
+    /// Write the catalog back to disk
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let json = serde_json::to_string_pretty(&self.records)?;
+        no_traces::write_file(&self.path, json).context("Failed to write drop catalog")?;
+        Ok(())
+    }
+
+    pub fn records(&self) -> &[DropRecord] {
+        &self.records
+    }
+
+    pub fn get_mut(&mut self, cid: &str) -> Option<&mut DropRecord> {
+        self.records.iter_mut().find(|r| r.cid == cid)
+    }
+
+    /// Find a record by CID or, for versioned drops, by drop ID
+    pub fn find_mut(&mut self, reference: &str) -> Option<&mut DropRecord> {
+        self.records
+            .iter_mut()
+            .find(|r| r.cid == reference || r.drop_id.as_deref() == Some(reference))
+    }
+
+    /// Add a record, replacing any previous record for the same CID
+    pub fn insert(&mut self, record: DropRecord) {
+        self.records.retain(|r| r.cid != record.cid);
+        self.records.push(record);
+    }
+
+    /// Pieces of a drop that no other pinned drop references, i.e. the ones
+    /// safe to unpin with it. Deduplicated drops share chunks.
+    pub fn unshared_pieces(&self, cid: &str) -> Vec<String> {
+        let Some(record) = self.records.iter().find(|r| r.cid == cid) else {
+            return Vec::new();
+        };
+        record
+            .piece_cids
+            .iter()
+            .filter(|piece| {
+                !self
+                    .records
+                    .iter()
+                    .any(|r| r.cid != cid && r.pinned && r.piece_cids.contains(piece))
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn remove(&mut self, cid: &str) -> Option<DropRecord> {
+        let index = self.records.iter().position(|r| r.cid == cid)?;
+        Some(self.records.remove(index))
+    }
+}
+
+/// Current UNIX time in seconds
+pub fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -0,0 +1,483 @@
+use crate::crypto::SessionKey;
+use crate::dead_drop::{
+    build_drop_stat, cat_bytes_from_ipfs, object_size, parse_chunk_prefix, probe_download_speed,
+    stream_encrypt_file, upload_bytes_to_ipfs, upload_file_to_ipfs, DeadDropCreated, DropOptions,
+    DropStat, CHUNK_OVERHEAD, MAX_CHUNK_SIZE,
+};
+use crate::ipfs;
+use crate::shards::{recover_session_key, split_session_key};
+use crate::unixfs;
+use crate::wire;
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use tracing::info;
+use zeroize::Zeroize;
+
+/// Drop references pointing at a chunk index carry this prefix
+pub const INDEX_CID_PREFIX: &str = "mt:";
+const LEAF_TAG: u8 = 0;
+const NODE_TAG: u8 = 1;
+
+/// Layout of an indexed drop, stored on IPFS encrypted with the session key.
+/// Frame `i` of the ciphertext starts at `i * frame_len` and holds plaintext
+/// bytes `i * chunk_size ..`; only the last frame may be shorter.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChunkIndex {
+    pub ciphertext_cid: String,
+    pub ciphertext_len: u64,
+    pub plaintext_len: u64,
+    pub chunk_size: usize,
+    /// Hash of each encrypted frame, length prefix included (hex)
+    pub leaves: Vec<String>,
+    /// Merkle root over `leaves` (hex)
+    pub root: String,
+}
+
+impl ChunkIndex {
+    fn frame_len(&self) -> u64 {
+        self.chunk_size as u64 + CHUNK_OVERHEAD
+    }
+
+    /// Check the tree and the layout agree before trusting any of it
+    fn validate(&self) -> Result<()> {
+        let leaves = self
+            .leaves
+            .iter()
+            .map(|leaf| {
+                hex::decode(leaf)
+                    .ok()
+                    .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                    .context("Invalid leaf in chunk index")
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if hex::encode(merkle_root(&leaves)) != self.root {
+            anyhow::bail!("Chunk index does not match its Merkle root");
+        }
+
+        if self.chunk_size == 0 || self.chunk_size > MAX_CHUNK_SIZE {
+            anyhow::bail!("Invalid chunk size in index: {}", self.chunk_size);
+        }
+        let frames = self.leaves.len() as u64;
+        if self.ciphertext_len != self.plaintext_len + frames * CHUNK_OVERHEAD
+            || self.plaintext_len.div_ceil(self.chunk_size as u64) != frames
+        {
+            anyhow::bail!("Chunk index layout is inconsistent");
+        }
+        Ok(())
+    }
+
+    /// Authenticate frame `i` against its leaf and decrypt it
+    fn open_frame(&self, i: usize, frame: &[u8], session_key: &SessionKey) -> Result<Vec<u8>> {
+        let leaf = self
+            .leaves
+            .get(i)
+            .context("More frames than the index lists")?;
+        if hex::encode(leaf_hash(frame)) != *leaf {
+            anyhow::bail!("Chunk {} does not match the index", i);
+        }
+        let (cipher, _) = parse_chunk_prefix(frame[..4].try_into()?)?;
+        session_key
+            .decrypt_chunk(cipher, &frame[4..])
+            .with_context(|| format!("Failed to decrypt chunk {}", i))
+    }
+}
+
+/// Strip the index prefix from a drop reference, if present
+pub fn index_manifest_cid(cid: &str) -> Option<&str> {
+    cid.strip_prefix(INDEX_CID_PREFIX)
+}
+
+fn leaf_hash(frame: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_TAG]);
+    hasher.update(frame);
+    hasher.finalize().into()
+}
+
+/// Root of a binary SHA-256 tree; an unpaired node moves up unchanged
+pub fn merkle_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return Sha256::digest([]).into();
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = Sha256::new();
+                    hasher.update([NODE_TAG]);
+                    hasher.update(left);
+                    hasher.update(right);
+                    hasher.finalize().into()
+                }
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// Hash every frame of a ciphertext file written by `stream_encrypt_file`
+fn hash_frames(ciphertext_path: &Path) -> Result<Vec<[u8; 32]>> {
+    let mut reader =
+        BufReader::new(File::open(ciphertext_path).context("Failed to open ciphertext")?);
+    let mut leaves = Vec::new();
+    let mut size_buffer = [0u8; 4];
+    loop {
+        match reader.read_exact(&mut size_buffer) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e).context("Failed to read chunk size"),
+        }
+        let mut frame = size_buffer.to_vec();
+        let (_, chunk_size) = parse_chunk_prefix(size_buffer)?;
+        frame.resize(4 + chunk_size, 0);
+        reader
+            .read_exact(&mut frame[4..])
+            .context("Failed to read encrypted chunk")?;
+        leaves.push(leaf_hash(&frame));
+    }
+    Ok(leaves)
+}
+
+/// Create a drop with a chunk index: the ciphertext is uploaded as usual and
+/// an encrypted index of its frames lets readers verify chunks as they
+/// arrive and fetch any byte range on its own
+pub async fn create_indexed_drop(
+    file_path: &str,
+    threshold: u8,
+    total_shards: u8,
+    options: &DropOptions,
+) -> Result<DeadDropCreated> {
+    if threshold > total_shards {
+        anyhow::bail!("Threshold cannot exceed total shards");
+    }
+    if threshold < 2 {
+        anyhow::bail!("Threshold must be at least 2");
+    }
+
+    let session_key = SessionKey::generate();
+
+    let ciphertext_file = options.staging_file()?;
+    let ciphertext_len =
+        stream_encrypt_file(file_path, ciphertext_file.path(), &session_key, options)
+            .context("Failed to encrypt file")?;
+    let leaves = hash_frames(ciphertext_file.path())?;
+    let ciphertext_cid = upload_file_to_ipfs(ciphertext_file.path(), options).await?;
+    drop(ciphertext_file);
+
+    let index = ChunkIndex {
+        ciphertext_cid: ciphertext_cid.clone(),
+        ciphertext_len,
+        plaintext_len: ciphertext_len - leaves.len() as u64 * CHUNK_OVERHEAD,
+        chunk_size: options.chunk_size,
+        root: hex::encode(merkle_root(&leaves)),
+        leaves: leaves.iter().map(hex::encode).collect(),
+    };
+    index
+        .validate()
+        .context("Ciphertext frames are not uniform")?;
+    let mut index_bytes = wire::encode(&index)?;
+    let encrypted_index = session_key.encrypt_file(&index_bytes);
+    index_bytes.zeroize();
+    let index_cid = upload_bytes_to_ipfs(encrypted_index?).await?;
+
+    let shards = split_session_key(&session_key, threshold, total_shards);
+    drop(session_key);
+
+    info!(
+        "Created indexed drop {} ({} chunks)",
+        index_cid,
+        index.leaves.len()
+    );
+
+    Ok(DeadDropCreated {
+        cid: format!("{}{}", INDEX_CID_PREFIX, index_cid),
+        shards,
+        drop_id: None,
+        piece_cids: vec![ciphertext_cid],
+        shred: None,
+        cipher: Some(options.cipher),
+        pending: false,
+    })
+}
+
+/// Decrypt and check the index of an indexed drop
+pub async fn fetch_chunk_index(index_cid: &str, session_key: &SessionKey) -> Result<ChunkIndex> {
+    let encrypted = cat_bytes_from_ipfs(index_cid).await?;
+    let mut index_bytes = session_key
+        .decrypt_file(&encrypted)
+        .context("Failed to decrypt chunk index - wrong shards?")?;
+    let index = wire::decode::<ChunkIndex>(&index_bytes);
+    index_bytes.zeroize();
+    let index = index.context("Invalid chunk index")?;
+    index.validate()?;
+    Ok(index)
+}
+
+/// Retrieve an indexed drop, checking every chunk against the index before
+/// it is decrypted. The output is removed if any check fails.
+pub async fn retrieve_indexed_drop(
+    index_cid: &str,
+    shard_strings: Vec<String>,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<()> {
+    let session_key = recover_session_key(&shard_strings)?;
+    let index = fetch_chunk_index(index_cid, &session_key).await?;
+
+    let result = download_verified(&index, &session_key, output_path, options).await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(output_path).await;
+    }
+    let decrypted_size = result?;
+
+    info!(
+        "Decrypted {} bytes to {} (indexed)",
+        decrypted_size, output_path
+    );
+    Ok(())
+}
+
+async fn download_verified(
+    index: &ChunkIndex,
+    session_key: &SessionKey,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<u64> {
+    let cid = index.ciphertext_cid.as_str();
+    let client = ipfs::client()?;
+    let mut request = client.post(ipfs::api("cat")).query(&[("arg", cid)]);
+    if options.offline {
+        request = request.query(&[("offline", "true")]);
+    }
+    let response = request
+        .send()
+        .await
+        .context("Failed to download from IPFS")?;
+    if !response.status().is_success() {
+        anyhow::bail!("IPFS download failed: {}", response.status());
+    }
+
+    let output_file = tokio::fs::File::create(output_path)
+        .await
+        .context("Failed to create output file")?;
+    let mut writer = tokio::io::BufWriter::new(output_file);
+
+    let mut cid_builder = cid.starts_with('b').then(unixfs::CidBuilder::default);
+    let mut stream = response.bytes_stream();
+    let mut pending: Vec<u8> = Vec::new();
+    let mut frame_index = 0;
+    let mut total_downloaded = 0u64;
+    let mut total_decrypted = 0u64;
+
+    while let Some(bytes) = stream.next().await {
+        let bytes = bytes.context("Failed to read chunk from IPFS")?;
+        if let Some(builder) = &mut cid_builder {
+            builder.update(&bytes);
+        }
+        total_downloaded += bytes.len() as u64;
+        pending.extend_from_slice(&bytes);
+
+        let mut offset = 0;
+        while pending.len() - offset >= 4 {
+            let size_bytes: [u8; 4] = pending[offset..offset + 4].try_into()?;
+            let (_, chunk_size) = parse_chunk_prefix(size_bytes)?;
+            let frame_end = offset + 4 + chunk_size;
+            if frame_end - offset > index.frame_len() as usize {
+                anyhow::bail!(
+                    "Encrypted chunk {} is larger than the index allows",
+                    frame_index
+                );
+            }
+            if pending.len() < frame_end {
+                break; // Rest of the frame is still in flight
+            }
+
+            let mut plaintext =
+                index.open_frame(frame_index, &pending[offset..frame_end], session_key)?;
+            tokio::io::AsyncWriteExt::write_all(&mut writer, &plaintext)
+                .await
+                .context("Failed to write decrypted chunk")?;
+            total_decrypted += plaintext.len() as u64;
+            plaintext.zeroize();
+
+            frame_index += 1;
+            offset = frame_end;
+        }
+        pending.drain(..offset);
+
+        options.report("downloading", total_downloaded, index.ciphertext_len);
+    }
+
+    if !pending.is_empty() || frame_index != index.leaves.len() {
+        anyhow::bail!("Download ended early - drop is truncated");
+    }
+    tokio::io::AsyncWriteExt::flush(&mut writer)
+        .await
+        .context("Failed to flush output")?;
+
+    if let Some(builder) = cid_builder {
+        if builder.finish() != cid {
+            anyhow::bail!("Downloaded content does not match CID {}", cid);
+        }
+    }
+    Ok(total_decrypted)
+}
+
+/// Fetch and decrypt plaintext bytes `offset..offset + len` of an indexed
+/// drop, downloading only the frames that cover them
+pub async fn read_range(
+    index: &ChunkIndex,
+    session_key: &SessionKey,
+    offset: u64,
+    len: u64,
+    options: &DropOptions,
+) -> Result<Vec<u8>> {
+    if offset >= index.plaintext_len || len == 0 {
+        return Ok(Vec::new());
+    }
+    let end = (offset + len).min(index.plaintext_len);
+    let chunk_size = index.chunk_size as u64;
+    let first = offset / chunk_size;
+    let last = (end - 1) / chunk_size;
+
+    let frame_len = index.frame_len();
+    let cipher_start = first * frame_len;
+    let cipher_end = ((last + 1) * frame_len).min(index.ciphertext_len);
+    let ciphertext = cat_range(
+        &index.ciphertext_cid,
+        cipher_start,
+        cipher_end - cipher_start,
+        options,
+    )
+    .await?;
+
+    let mut plaintext = Vec::with_capacity((end - offset) as usize);
+    let mut frames = ciphertext.chunks(frame_len as usize);
+    for i in first..=last {
+        let frame = frames.next().context("Range download is truncated")?;
+        let mut chunk = index.open_frame(i as usize, frame, session_key)?;
+        let chunk_start = i * chunk_size;
+        let from = offset.saturating_sub(chunk_start) as usize;
+        let to = ((end - chunk_start) as usize).min(chunk.len());
+        plaintext.extend_from_slice(&chunk[from..to]);
+        chunk.zeroize();
+    }
+    Ok(plaintext)
+}
+
+/// Read `length` bytes of a CID starting at `offset`
+async fn cat_range(cid: &str, offset: u64, length: u64, options: &DropOptions) -> Result<Vec<u8>> {
+    let client = ipfs::client()?;
+    let offset = offset.to_string();
+    let length_param = length.to_string();
+    let mut request = client.post(ipfs::api("cat")).query(&[
+        ("arg", cid),
+        ("offset", offset.as_str()),
+        ("length", length_param.as_str()),
+    ]);
+    if options.offline {
+        request = request.query(&[("offline", "true")]);
+    }
+    let response = request
+        .send()
+        .await
+        .context("Failed to download from IPFS")?;
+    if !response.status().is_success() {
+        anyhow::bail!("IPFS download failed: {}", response.status());
+    }
+
+    let bytes = response.bytes().await?;
+    if bytes.len() as u64 != length {
+        anyhow::bail!("IPFS returned {} of {} bytes", bytes.len(), length);
+    }
+    Ok(bytes.to_vec())
+}
+
+/// Preflight estimate for an indexed drop; the ciphertext size is checked
+/// against the index
+pub async fn stat_indexed_drop(
+    index_cid: &str,
+    shard_strings: Vec<String>,
+    output_path: &Path,
+    options: &DropOptions,
+) -> Result<DropStat> {
+    let session_key = recover_session_key(&shard_strings)?;
+    let index = fetch_chunk_index(index_cid, &session_key).await?;
+    drop(session_key);
+
+    let client = ipfs::client()?;
+    let (size, size_source) = object_size(&client, &index.ciphertext_cid).await?;
+    let bytes_per_sec = probe_download_speed(&client, &index.ciphertext_cid).await;
+
+    // Chunks are decrypted as they arrive, so only the plaintext needs room
+    let mut stat = build_drop_stat(
+        &format!("{}{}", INDEX_CID_PREFIX, index_cid),
+        size,
+        index.ciphertext_len,
+        size_source,
+        Some(size == index.ciphertext_len),
+        bytes_per_sec,
+        output_path,
+        options,
+    );
+    stat.estimated_plaintext_size = index.plaintext_len;
+    stat.required_disk_space = index.plaintext_len;
+    stat.enough_disk_space = stat
+        .available_disk_space
+        .map(|free| free >= stat.required_disk_space);
+    Ok(stat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dead_drop::{stream_decrypt_file, MIN_CHUNK_SIZE};
+
+    #[test]
+    fn verifies_frames_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("plain");
+        let encrypted = dir.path().join("cipher");
+        std::fs::write(&input, vec![7u8; MIN_CHUNK_SIZE * 2 + 100]).unwrap();
+
+        let key = SessionKey::generate();
+        let options = DropOptions {
+            chunk_size: MIN_CHUNK_SIZE,
+            ..DropOptions::default()
+        };
+        let ciphertext_len =
+            stream_encrypt_file(input.to_str().unwrap(), &encrypted, &key, &options).unwrap();
+        let leaves = hash_frames(&encrypted).unwrap();
+        let index = ChunkIndex {
+            ciphertext_cid: String::new(),
+            ciphertext_len,
+            plaintext_len: (MIN_CHUNK_SIZE * 2 + 100) as u64,
+            chunk_size: MIN_CHUNK_SIZE,
+            root: hex::encode(merkle_root(&leaves)),
+            leaves: leaves.iter().map(hex::encode).collect(),
+        };
+        index.validate().unwrap();
+
+        let ciphertext = std::fs::read(&encrypted).unwrap();
+        let frames: Vec<&[u8]> = ciphertext.chunks(index.frame_len() as usize).collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(index.open_frame(2, frames[2], &key).unwrap().len(), 100);
+        assert!(index.open_frame(0, frames[1], &key).is_err());
+
+        let output = dir.path().join("out");
+        stream_decrypt_file(&encrypted, output.to_str().unwrap(), &key, &options).unwrap();
+        assert_eq!(
+            std::fs::read(output).unwrap(),
+            std::fs::read(input).unwrap()
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use crate::logging;
+use crate::no_traces;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, ClipboardManager};
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+const DEFAULT_TTL_SECS: u64 = 30;
+const MIN_TTL_SECS: u64 = 5;
+const MAX_TTL_SECS: u64 = 300;
+
+/// Sensitive value currently on the clipboard, and which copy put it there
+struct Pending {
+    value: Zeroizing<String>,
+    generation: u64,
+}
+
+static PENDING: Mutex<Option<Pending>> = Mutex::new(None);
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Put a shard or key on the clipboard and clear it again after `ttl_secs`
+/// (default 30, clamped to 5..=300, shorter in no-traces mode) or when the
+/// session locks. The value is redacted from logs from now on.
+pub fn copy_sensitive(
+    app: &AppHandle,
+    value: String,
+    ttl_secs: Option<u64>,
+) -> anyhow::Result<u64> {
+    let ttl = no_traces::clipboard_ttl(ttl_secs)
+        .unwrap_or(DEFAULT_TTL_SECS)
+        .clamp(MIN_TTL_SECS, MAX_TTL_SECS);
+    let value = Zeroizing::new(value);
+
+    logging::register_sensitive(&value);
+    app.clipboard_manager()
+        .write_text(value.as_str())
+        .map_err(|e| anyhow::anyhow!("Failed to write clipboard: {}", e))?;
+
+    let generation = GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+    *PENDING.lock().unwrap() = Some(Pending { value, generation });
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(ttl)).await;
+        clear(&app, Some(generation));
+    });
+
+    Ok(ttl)
+}
+
+/// Clear a sensitive value still on the clipboard right away, e.g. on lock
+pub fn clear_sensitive(app: &AppHandle) {
+    clear(app, None);
+}
+
+/// Clear the clipboard if it still holds what we put there; anything the user
+/// copied since is left alone
+fn clear(app: &AppHandle, generation: Option<u64>) {
+    let mut pending = PENDING.lock().unwrap();
+    let Some(current) = pending.as_ref() else {
+        return;
+    };
+    if generation.is_some_and(|g| g != current.generation) {
+        return;
+    }
+
+    let mut clipboard = app.clipboard_manager();
+    match clipboard.read_text().map(|text| text.map(Zeroizing::new)) {
+        Ok(Some(text)) if *text == *current.value => {
+            if let Err(e) = clipboard.write_text(String::new()) {
+                warn!("Failed to clear clipboard: {}", e);
+                return;
+            }
+            info!("Cleared sensitive value from the clipboard");
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Failed to read clipboard: {}", e),
+    }
+    *pending = None;
+}
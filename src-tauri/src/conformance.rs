@@ -0,0 +1,288 @@
+use crate::crypto::{Identity, SessionKey};
+use crate::dead_drop::{chunk_prefix, parse_chunk_prefix, stream_decrypt_to, DropOptions};
+use crate::p2p::open_p2p_message;
+use crate::password_drop::{unlock, PasswordManifest};
+use crate::shards::{recover_session_key, split_session_key, ShardEnvelope};
+use crate::wire;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use tracing::{info, warn};
+
+/// Published wire-format vectors. They were computed independently of this
+/// crate, so passing them shows the format, not just self-consistency. v1
+/// holds JSON envelopes and manifests, still read through the fallback.
+const VECTORS: [&str; 2] = [
+    include_str!("../test-vectors/v1.json"),
+    include_str!("../test-vectors/v2.json"),
+];
+
+#[derive(Deserialize)]
+struct TestVectors {
+    version: u32,
+    message_envelope: Vec<EnvelopeVector>,
+    chunk_format: Vec<ChunkVector>,
+    shard_format: Vec<ShardVector>,
+    password_manifest: Vec<ManifestVector>,
+}
+
+/// A P2P message sealed from `sender_secret` to `recipient_secret`
+#[derive(Deserialize)]
+struct EnvelopeVector {
+    name: String,
+    recipient_secret: String, // hex
+    sender_secret: String,    // hex
+    sender_id: String,
+    nonce: String, // hex
+    /// JSON in v1, hex of the CBOR encoding from v2, exactly as it is sealed
+    plaintext: String,
+    wire: String, // hex
+}
+
+/// A ciphertext file as written by `stream_encrypt_file`
+#[derive(Deserialize)]
+struct ChunkVector {
+    name: String,
+    key: String,        // hex
+    plaintext: String,  // hex
+    ciphertext: String, // hex
+}
+
+/// Shards of `key`; any `threshold` of them recover it
+#[derive(Deserialize)]
+struct ShardVector {
+    name: String,
+    key: String, // hex
+    threshold: u8,
+    key_check: String,
+    shards: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ManifestVector {
+    name: String,
+    passphrase: String,
+    /// JSON in v1, hex of the CBOR encoding from v2
+    manifest: String,
+    session_key: String, // hex
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConformanceReport {
+    /// The newest vectors checked
+    pub vectors_version: u32,
+    pub results: Vec<VectorResult>,
+}
+
+impl ConformanceReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.error.is_none())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorResult {
+    /// e.g. `v2/chunk_format/aes-256-gcm`
+    pub name: String,
+    /// Why the vector failed, `None` if it passed
+    pub error: Option<String>,
+}
+
+/// Check this build against every published test vector. Failing vectors
+/// are reported, not returned as errors.
+pub fn verify_conformance() -> Result<ConformanceReport> {
+    let mut vectors_version = 0;
+    let mut results = Vec::new();
+    for file in VECTORS {
+        let vectors: TestVectors = serde_json::from_str(file).context("Invalid test vectors")?;
+        let version = vectors.version;
+        vectors_version = vectors_version.max(version);
+        let mut record = |group: &str, name: &str, outcome: Result<()>| {
+            let name = format!("v{}/{}/{}", version, group, name);
+            let error = outcome.err().map(|e| format!("{:#}", e));
+            if let Some(error) = &error {
+                warn!("Conformance vector {} failed: {}", name, error);
+            }
+            results.push(VectorResult { name, error });
+        };
+
+        for vector in &vectors.message_envelope {
+            record(
+                "message_envelope",
+                &vector.name,
+                check_envelope(version, vector),
+            );
+        }
+        for vector in &vectors.chunk_format {
+            record("chunk_format", &vector.name, check_chunks(vector));
+        }
+        for vector in &vectors.shard_format {
+            record("shard_format", &vector.name, check_shards(vector));
+        }
+        for vector in &vectors.password_manifest {
+            record(
+                "password_manifest",
+                &vector.name,
+                check_manifest(version, vector),
+            );
+        }
+    }
+
+    let report = ConformanceReport {
+        vectors_version,
+        results,
+    };
+    info!(
+        "Conformance: {} of {} vectors passed",
+        report.results.iter().filter(|r| r.error.is_none()).count(),
+        report.results.len()
+    );
+    Ok(report)
+}
+
+fn check_envelope(version: u32, vector: &EnvelopeVector) -> Result<()> {
+    let recipient = Identity::from_secret_bytes(decode_key(&vector.recipient_secret)?);
+    let sender = Identity::from_secret_bytes(decode_key(&vector.sender_secret)?);
+    expect_eq("sender ID", sender.public_id(), vector.sender_id.clone())?;
+
+    let wire = hex::decode(&vector.wire)?;
+    let nonce = hex::decode(&vector.nonce)?;
+    expect_eq(
+        "nonce",
+        wire.get(32..32 + nonce.len()),
+        Some(nonce.as_slice()),
+    )?;
+
+    let (sender_key, message) = open_p2p_message(&recipient, &wire)?;
+    expect_eq("sender key", sender_key, sender.public_key)?;
+    expect_eq(
+        "plaintext",
+        encode_as(version, &message)?,
+        vector.plaintext.clone(),
+    )
+}
+
+fn check_chunks(vector: &ChunkVector) -> Result<()> {
+    let key = SessionKey::from_bytes(&hex::decode(&vector.key)?)?;
+    let ciphertext = hex::decode(&vector.ciphertext)?;
+
+    // Prefixes must re-encode byte for byte
+    let mut offset = 0;
+    while offset < ciphertext.len() {
+        let prefix: [u8; 4] = ciphertext
+            .get(offset..offset + 4)
+            .context("Truncated chunk prefix")?
+            .try_into()?;
+        let (cipher, len) = parse_chunk_prefix(prefix)?;
+        expect_eq("chunk prefix", chunk_prefix(cipher, len), prefix)?;
+        offset += 4 + len;
+    }
+
+    let staged = tempfile::NamedTempFile::new()?;
+    std::fs::write(staged.path(), &ciphertext)?;
+    let mut plaintext = Vec::new();
+    stream_decrypt_to(staged.path(), &mut plaintext, &key, &DropOptions::default())?;
+    expect_eq(
+        "plaintext",
+        hex::encode(plaintext),
+        vector.plaintext.clone(),
+    )
+}
+
+fn check_shards(vector: &ShardVector) -> Result<()> {
+    let key = SessionKey::from_bytes(&hex::decode(&vector.key)?)?;
+    for shard in &vector.shards {
+        let envelope = ShardEnvelope::parse(shard)?;
+        expect_eq("threshold", envelope.threshold, vector.threshold)?;
+        expect_eq("key check", envelope.key_check, vector.key_check.clone())?;
+    }
+
+    // Legacy shards carry no threshold and need every share
+    let needed = match vector.threshold {
+        0 => vector.shards.len(),
+        threshold => threshold as usize,
+    };
+    for window in vector.shards.windows(needed) {
+        let recovered = recover_session_key(window)?;
+        expect_eq("recovered key", recovered.as_bytes(), key.as_bytes())?;
+    }
+
+    // Fresh shards of the same key must carry the same key check
+    if !vector.key_check.is_empty() {
+        let fresh = split_session_key(&key, vector.threshold, vector.threshold);
+        let envelope = ShardEnvelope::parse(&fresh[0])?;
+        expect_eq(
+            "fresh key check",
+            envelope.key_check,
+            vector.key_check.clone(),
+        )?;
+    }
+    Ok(())
+}
+
+fn check_manifest(version: u32, vector: &ManifestVector) -> Result<()> {
+    let manifest: PasswordManifest = match version {
+        1 => serde_json::from_str(&vector.manifest)?,
+        _ => wire::decode(&hex::decode(&vector.manifest)?)?,
+    };
+    expect_eq(
+        "manifest",
+        encode_as(version, &manifest)?,
+        vector.manifest.clone(),
+    )?;
+
+    let session_key = unlock(&manifest, &vector.passphrase)?;
+    expect_eq(
+        "session key",
+        hex::encode(session_key.as_bytes()),
+        vector.session_key.clone(),
+    )?;
+    if unlock(&manifest, &format!("{}!", vector.passphrase)).is_ok() {
+        anyhow::bail!("A wrong passphrase unlocked the manifest");
+    }
+    Ok(())
+}
+
+/// Re-encode a value the way vectors of `version` store it
+fn encode_as<T: Serialize>(version: u32, value: &T) -> Result<String> {
+    Ok(match version {
+        1 => serde_json::to_string(value)?,
+        _ => hex::encode(wire::encode_cbor(value)?),
+    })
+}
+
+fn decode_key(hex_key: &str) -> Result<[u8; 32]> {
+    hex::decode(hex_key)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Key must be 32 bytes"))
+}
+
+fn expect_eq<T: PartialEq + Debug>(what: &str, actual: T, expected: T) -> Result<()> {
+    if actual != expected {
+        anyhow::bail!(
+            "{} mismatch: got {:?}, expected {:?}",
+            what,
+            actual,
+            expected
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_published_vectors_pass() {
+        let report = verify_conformance().unwrap();
+        let failures: Vec<_> = report
+            .results
+            .iter()
+            .filter(|r| r.error.is_some())
+            .collect();
+        assert!(failures.is_empty(), "{:#?}", failures);
+        assert_eq!(report.vectors_version, 2);
+        assert_eq!(report.results.len(), 22);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::catalog::now_secs;
+use crate::contacts::{Contact, ContactBook};
+use crate::crypto::{decode_public_key, Identity};
+use crate::key_log::{self, KeyChange, KeyKind, KeySource};
+use crate::p2p::{relay_peer_id, PROTOCOL_VERSION};
+use crate::proofs::{Claim, ProofCheck};
+use crate::settings;
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+pub const CARD_VERSION: u32 = 1;
+/// Cards are a few hundred bytes; refuse to parse anything much bigger
+const MAX_CARD_SIZE: u64 = 64 * 1024;
+const MAX_ALIAS_LEN: usize = 64;
+/// A card can't make us dial an unbounded list of addresses
+const MAX_RELAY_HINTS: usize = 8;
+const MAX_PROOFS: usize = 16;
+
+/// Public identity for handing out of band instead of a base58 string
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContactCard {
+    pub version: u32,
+    pub public_id: String,
+    pub alias: String,
+    /// SHA-256 (hex) of an avatar image exchanged separately
+    #[serde(default)]
+    pub avatar_hash: Option<String>,
+    /// Relays the owner can be reached through, dialed first when sending to them
+    #[serde(default)]
+    pub relays: Vec<String>,
+    /// Places outside Control where the owner published this public ID
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proofs: Vec<Claim>,
+    pub protocol_version: String,
+    /// The owner's ed25519 key, hex; signs this card and their introductions
+    pub signing_key: String,
+    pub created_at: u64,
+}
+
+/// The card file: the card JSON and a signature over it byte for byte
+#[derive(Serialize, Deserialize)]
+struct SignedCard {
+    card: String,
+    signature: String, // hex
+}
+
+/// Result of importing a card
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContactCardImport {
+    pub contact: Contact,
+    pub card: ContactCard,
+    /// Set if the key log holds another signing key for this contact
+    pub key_change: Option<KeyChange>,
+}
+
+/// Write a signed card for this identity to `path`
+pub fn export_contact_card(
+    identity: &Identity,
+    alias: &str,
+    avatar_hash: Option<&str>,
+    proofs: &[Claim],
+    path: &Path,
+) -> Result<ContactCard> {
+    let alias = alias.trim();
+    if alias.chars().count() > MAX_ALIAS_LEN {
+        anyhow::bail!("Alias is longer than {} characters", MAX_ALIAS_LEN);
+    }
+    if let Some(hash) = avatar_hash {
+        check_avatar_hash(hash)?;
+    }
+
+    let signing_key = identity.signing_key();
+    let card = ContactCard {
+        version: CARD_VERSION,
+        public_id: identity.public_id(),
+        alias: alias.to_string(),
+        avatar_hash: avatar_hash.map(str::to_lowercase),
+        relays: settings::p2p_settings().relays,
+        proofs: proofs.iter().take(MAX_PROOFS).cloned().collect(),
+        protocol_version: PROTOCOL_VERSION.to_string(),
+        signing_key: hex::encode(signing_key.verifying_key().to_bytes()),
+        created_at: now_secs(),
+    };
+    let card_json = serde_json::to_string(&card)?;
+    let signature = signing_key.sign(card_json.as_bytes());
+    let signed = SignedCard {
+        card: card_json,
+        signature: hex::encode(signature.to_bytes()),
+    };
+
+    fs::write(path, serde_json::to_vec_pretty(&signed)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!("Exported contact card to {}", path.display());
+    Ok(card)
+}
+
+/// Read a card file and check its signature
+pub fn read_contact_card(path: &Path) -> Result<ContactCard> {
+    let size = fs::metadata(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .len();
+    if size > MAX_CARD_SIZE {
+        anyhow::bail!("{} is too large to be a contact card", path.display());
+    }
+    let signed: SignedCard =
+        serde_json::from_slice(&fs::read(path)?).context("Not a contact card")?;
+    verify_card(&signed)
+}
+
+/// Verify a card and add its owner to the address book. `nickname` overrides
+/// the card's alias.
+pub fn import_contact_card(
+    data_dir: &Path,
+    identity: &Identity,
+    path: &Path,
+    nickname: Option<&str>,
+) -> Result<ContactCardImport> {
+    let card = read_contact_card(path)?;
+    if card.public_id == identity.public_id() {
+        anyhow::bail!("This is your own contact card");
+    }
+
+    let mut book = ContactBook::load(data_dir, identity)?;
+    let existing = book.get(&card.public_id).cloned();
+    if let Some(known) = existing.as_ref().and_then(|c| c.signing_key.as_ref()) {
+        if *known != card.signing_key {
+            anyhow::bail!(
+                "Card is signed with a different key than the one on file for this contact"
+            );
+        }
+    }
+
+    let nickname = nickname
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .unwrap_or(&card.alias);
+    let contact = Contact {
+        public_id: card.public_id.clone(),
+        nickname: nickname.to_string(),
+        verified: existing.as_ref().is_some_and(|c| c.verified),
+        updated_at: now_secs(),
+        introduced_by: existing.as_ref().and_then(|c| c.introduced_by.clone()),
+        signing_key: Some(card.signing_key.clone()),
+        relay_hints: relay_hints(&card.relays),
+        proofs: proof_checks(&card.proofs, existing.as_ref()),
+        appearance: existing
+            .as_ref()
+            .map(|c| c.appearance.clone())
+            .unwrap_or_default(),
+        pgp_key: existing.and_then(|c| c.pgp_key),
+    };
+    book.insert(contact.clone());
+    book.save()?;
+    let key_change = key_log::observe(
+        data_dir,
+        identity,
+        &card.public_id,
+        KeyKind::Signing,
+        &card.signing_key,
+        KeySource::ContactCard,
+    );
+
+    info!("Imported contact card of {}", card.public_id);
+    Ok(ContactCardImport {
+        contact,
+        card,
+        key_change,
+    })
+}
+
+fn verify_card(signed: &SignedCard) -> Result<ContactCard> {
+    let card: ContactCard = serde_json::from_str(&signed.card).context("Invalid contact card")?;
+    if card.version > CARD_VERSION {
+        anyhow::bail!("Contact card version {} is not supported", card.version);
+    }
+    decode_public_key(&card.public_id).context("Invalid public ID in contact card")?;
+
+    let key_bytes: [u8; 32] = hex::decode(&card.signing_key)
+        .context("Invalid signing key in contact card")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Signing key has the wrong length"))?;
+    let key =
+        VerifyingKey::from_bytes(&key_bytes).context("Invalid signing key in contact card")?;
+    let signature = hex::decode(&signed.signature).context("Invalid card signature")?;
+    let signature = Signature::from_slice(&signature).context("Invalid card signature")?;
+    key.verify(signed.card.as_bytes(), &signature)
+        .context("Contact card signature does not match")?;
+
+    if let Some(hash) = &card.avatar_hash {
+        check_avatar_hash(hash)?;
+    }
+    Ok(card)
+}
+
+/// Relay addresses from a card that can actually be dialed
+fn relay_hints(relays: &[String]) -> Vec<String> {
+    relays
+        .iter()
+        .filter(|addr| relay_peer_id(addr).is_some())
+        .take(MAX_RELAY_HINTS)
+        .cloned()
+        .collect()
+}
+
+/// The card's claims, keeping earlier results for claims already checked
+fn proof_checks(claims: &[Claim], existing: Option<&Contact>) -> Vec<ProofCheck> {
+    claims
+        .iter()
+        .take(MAX_PROOFS)
+        .filter(|claim| claim.validate().is_ok())
+        .map(|claim| {
+            existing
+                .and_then(|c| c.proofs.iter().find(|p| p.claim == *claim))
+                .cloned()
+                .unwrap_or_else(|| ProofCheck::unchecked(claim.clone()))
+        })
+        .collect()
+}
+
+fn check_avatar_hash(hash: &str) -> Result<()> {
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("Avatar hash must be a hex SHA-256");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_rejects_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("card.json");
+        let identity = Identity::generate();
+
+        export_contact_card(&identity, "Alice", None, &[], &path).unwrap();
+        let card = read_contact_card(&path).unwrap();
+        assert_eq!(card.public_id, identity.public_id());
+        assert_eq!(card.alias, "Alice");
+
+        let other = Identity::generate().public_id();
+        let edited = fs::read_to_string(&path)
+            .unwrap()
+            .replace(&identity.public_id(), &other);
+        fs::write(&path, edited).unwrap();
+        assert!(read_contact_card(&path).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+use crate::contacts::ContactBook;
+use crate::crypto::{decode_public_key, Identity};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Exports larger than this are refused rather than parsed
+const MAX_IMPORT_BYTES: u64 = 4 * 1024 * 1024;
+const MAX_NICKNAME_LEN: usize = 64;
+/// Prefixes people paste in front of a public ID
+const ID_PREFIXES: [&str; 2] = ["control://", "control:"];
+/// Column names holding the public ID, lowercase
+const ID_COLUMNS: [&str; 6] = [
+    "public_id",
+    "public id",
+    "publicid",
+    "public_key",
+    "public key",
+    "control id",
+];
+/// Column names holding the name, lowercase, in order of preference
+const NAME_COLUMNS: [&str; 5] = ["nickname", "alias", "display name", "name", "full name"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    /// Header row naming an ID column and a name column
+    Csv,
+    /// `signal-cli listContacts --output=json`; IDs are found in names and notes
+    Signal,
+    /// `[{"public_id": ..., "nickname": ...}]`, optionally under `contacts`
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportAction {
+    /// New contact
+    Add,
+    /// Known contact without a nickname, which the import fills in
+    Rename,
+    /// Already in the address book; left as it is
+    Existing,
+    /// Same public ID as an earlier row
+    Duplicate,
+    /// Our own public ID
+    Own,
+    /// No usable public ID
+    Invalid,
+}
+
+/// One record of the export and what importing it does
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImportRow {
+    /// Record number in the export, from 1
+    pub record: usize,
+    pub public_id: Option<String>,
+    pub nickname: String,
+    pub action: ImportAction,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImportReport {
+    pub format: ImportFormat,
+    pub rows: Vec<ImportRow>,
+    pub added: usize,
+    pub renamed: usize,
+    pub skipped: usize,
+    /// False for a dry run: nothing was written
+    pub committed: bool,
+}
+
+/// A record as read from the export, before checking it
+struct Candidate {
+    public_id: Option<String>,
+    nickname: String,
+}
+
+/// Read an export and plan the import against the address book. With
+/// `dry_run` the report is all that is produced; otherwise new contacts are
+/// added unverified, since a file can't confirm anyone's identity.
+pub fn import_contacts(
+    data_dir: &Path,
+    identity: &Identity,
+    path: &Path,
+    format: Option<ImportFormat>,
+    dry_run: bool,
+) -> Result<ImportReport> {
+    let size = fs::metadata(path)
+        .context("Failed to read contact export")?
+        .len();
+    if size > MAX_IMPORT_BYTES {
+        anyhow::bail!("Contact export is larger than {} bytes", MAX_IMPORT_BYTES);
+    }
+    let data = fs::read_to_string(path).context("Failed to read contact export")?;
+    let format = match format {
+        Some(format) => format,
+        None => detect_format(path, &data),
+    };
+    let candidates = parse(format, &data)?;
+
+    let mut book = ContactBook::load(data_dir, identity)?;
+    let rows = plan(&book, &identity.public_id(), candidates);
+    let count = |action| rows.iter().filter(|r| r.action == action).count();
+    let added = count(ImportAction::Add);
+    let renamed = count(ImportAction::Rename);
+    let mut report = ImportReport {
+        format,
+        skipped: rows.len() - added - renamed,
+        added,
+        renamed,
+        rows,
+        committed: false,
+    };
+    if dry_run || added + renamed == 0 {
+        return Ok(report);
+    }
+
+    for row in &report.rows {
+        if let (ImportAction::Add | ImportAction::Rename, Some(public_id)) =
+            (row.action, &row.public_id)
+        {
+            let verified = book.get(public_id).is_some_and(|c| c.verified);
+            book.upsert(public_id, &row.nickname, verified)?;
+        }
+    }
+    book.save()?;
+    report.committed = true;
+    Ok(report)
+}
+
+/// JSON exports are told apart by their fields; anything else is CSV
+pub fn detect_format(path: &Path, data: &str) -> ImportFormat {
+    let json = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+        || data.trim_start().starts_with(['[', '{']);
+    if !json {
+        return ImportFormat::Csv;
+    }
+    let signal = serde_json::from_str::<Value>(data).is_ok_and(|value| {
+        records(&value).iter().any(|record| {
+            ["number", "uuid", "profile", "givenName"]
+                .iter()
+                .any(|field| record.get(field).is_some())
+        })
+    });
+    if signal {
+        ImportFormat::Signal
+    } else {
+        ImportFormat::Json
+    }
+}
+
+fn parse(format: ImportFormat, data: &str) -> Result<Vec<Candidate>> {
+    match format {
+        ImportFormat::Csv => parse_csv(data),
+        ImportFormat::Json => {
+            let value: Value = serde_json::from_str(data).context("Invalid JSON export")?;
+            Ok(records(&value).iter().map(json_candidate).collect())
+        }
+        ImportFormat::Signal => {
+            let value: Value = serde_json::from_str(data).context("Invalid Signal export")?;
+            Ok(records(&value).iter().map(signal_candidate).collect())
+        }
+    }
+}
+
+fn parse_csv(data: &str) -> Result<Vec<Candidate>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes());
+    let headers: Vec<String> = reader
+        .headers()
+        .context("Invalid CSV export")?
+        .iter()
+        .map(|h| h.trim_start_matches('\u{feff}').to_lowercase())
+        .collect();
+    let column = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| headers.iter().position(|h| h == name))
+    };
+    let id_column = column(&ID_COLUMNS);
+    let name_column = column(&NAME_COLUMNS);
+
+    let mut candidates = Vec::new();
+    for record in reader.records() {
+        let record = record.context("Invalid CSV export")?;
+        // Without an ID column the key may sit in any field, e.g. a note
+        let public_id = match id_column {
+            Some(i) => record.get(i).map(str::to_string),
+            None => record.iter().find_map(find_public_id),
+        };
+        let nickname = name_column
+            .and_then(|i| record.get(i))
+            .unwrap_or_default()
+            .to_string();
+        candidates.push(Candidate {
+            public_id,
+            nickname,
+        });
+    }
+    Ok(candidates)
+}
+
+/// The list of contacts, whether top-level or under `contacts`
+fn records(value: &Value) -> Vec<Value> {
+    let list = value.get("contacts").unwrap_or(value);
+    match list {
+        Value::Array(items) => items.clone(),
+        Value::Object(_) => vec![list.clone()],
+        _ => Vec::new(),
+    }
+}
+
+fn field<'a>(record: &'a Value, names: &[&str]) -> Option<&'a str> {
+    names
+        .iter()
+        .find_map(|name| record.get(name).and_then(Value::as_str))
+        .filter(|s| !s.trim().is_empty())
+}
+
+fn json_candidate(record: &Value) -> Candidate {
+    Candidate {
+        public_id: field(record, &["public_id", "publicId", "public_key", "id"])
+            .map(str::to_string),
+        nickname: field(record, &["nickname", "alias", "name"])
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+/// Signal has no place for a Control ID, so look for one in any text the
+/// user could have put it in
+fn signal_candidate(record: &Value) -> Candidate {
+    let profile = record.get("profile").unwrap_or(&Value::Null);
+    let profile_name = [
+        field(profile, &["givenName"]),
+        field(profile, &["familyName"]),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(" ");
+    let nickname = field(record, &["nickName", "name", "username"])
+        .map(str::to_string)
+        .or_else(|| Some(profile_name).filter(|n| !n.is_empty()))
+        .or_else(|| field(record, &["number"]).map(str::to_string))
+        .unwrap_or_default();
+    Candidate {
+        public_id: strings(record).into_iter().find_map(find_public_id),
+        nickname,
+    }
+}
+
+/// Every string anywhere in a JSON value
+fn strings(value: &Value) -> Vec<&str> {
+    match value {
+        Value::String(s) => vec![s.as_str()],
+        Value::Array(items) => items.iter().flat_map(strings).collect(),
+        Value::Object(map) => map.values().flat_map(strings).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The first word of `text` that is a valid public ID
+fn find_public_id(text: &str) -> Option<String> {
+    text.split(|c: char| c.is_whitespace() || matches!(c, ',' | ';' | '(' | ')' | '<' | '>'))
+        .find_map(|word| normalize_public_id(word).ok())
+}
+
+/// Trim and strip a `control:` prefix, then check it is a public key
+pub fn normalize_public_id(raw: &str) -> Result<String> {
+    let mut id = raw.trim().trim_matches(|c| c == '"' || c == '\'');
+    for prefix in ID_PREFIXES {
+        if let Some(rest) = id.strip_prefix(prefix) {
+            id = rest;
+            break;
+        }
+    }
+    let id = id.trim_end_matches(['.', '/']);
+    decode_public_key(id).context("Not a valid public ID")?;
+    Ok(id.to_string())
+}
+
+fn check_nickname(nickname: &str, public_id: &str) -> String {
+    let nickname: String = nickname.trim().chars().take(MAX_NICKNAME_LEN).collect();
+    if nickname.is_empty() {
+        public_id.chars().take(8).collect()
+    } else {
+        nickname
+    }
+}
+
+fn plan(book: &ContactBook, own_id: &str, candidates: Vec<Candidate>) -> Vec<ImportRow> {
+    let mut seen = HashSet::new();
+    candidates
+        .into_iter()
+        .enumerate()
+        .map(|(i, candidate)| {
+            let row =
+                |public_id: Option<String>, nickname: String, action, reason: Option<&str>| {
+                    ImportRow {
+                        record: i + 1,
+                        public_id,
+                        nickname,
+                        action,
+                        reason: reason.map(str::to_string),
+                    }
+                };
+            let public_id = match candidate.public_id.as_deref().map(normalize_public_id) {
+                Some(Ok(public_id)) => public_id,
+                Some(Err(_)) => {
+                    return row(
+                        candidate.public_id,
+                        candidate.nickname,
+                        ImportAction::Invalid,
+                        Some("Not a valid public ID"),
+                    )
+                }
+                None => {
+                    return row(
+                        None,
+                        candidate.nickname,
+                        ImportAction::Invalid,
+                        Some("No public ID found"),
+                    )
+                }
+            };
+            let nickname = check_nickname(&candidate.nickname, &public_id);
+
+            let (action, reason) = if public_id == own_id {
+                (ImportAction::Own, Some("This is your own public ID"))
+            } else if !seen.insert(public_id.clone()) {
+                (
+                    ImportAction::Duplicate,
+                    Some("Listed earlier in the export"),
+                )
+            } else {
+                match book.get(&public_id) {
+                    None => (ImportAction::Add, None),
+                    Some(known) if known.nickname.trim().is_empty() => (ImportAction::Rename, None),
+                    Some(_) => (ImportAction::Existing, Some("Already a contact")),
+                }
+            };
+            row(Some(public_id), nickname, action, reason)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_csv_import_before_committing() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = Identity::generate();
+        let known = Identity::generate().public_id();
+        let new = Identity::generate().public_id();
+        let mut book = ContactBook::load(dir.path(), &identity).unwrap();
+        book.upsert(&known, "Ana", true).unwrap();
+        book.save().unwrap();
+
+        let path = dir.path().join("contacts.csv");
+        let csv = format!(
+            "Name,Public ID\nAna,{known}\nBo,control:{new}\nBo again,{new}\nMe,{own}\nCy,nope\n",
+            own = identity.public_id()
+        );
+        fs::write(&path, csv).unwrap();
+
+        let report = import_contacts(dir.path(), &identity, &path, None, true).unwrap();
+        assert_eq!(report.format, ImportFormat::Csv);
+        let actions: Vec<_> = report.rows.iter().map(|r| r.action).collect();
+        assert_eq!(
+            actions,
+            [
+                ImportAction::Existing,
+                ImportAction::Add,
+                ImportAction::Duplicate,
+                ImportAction::Own,
+                ImportAction::Invalid
+            ]
+        );
+        assert_eq!(report.rows[1].public_id.as_deref(), Some(new.as_str()));
+        assert!(!report.committed);
+        let book = ContactBook::load(dir.path(), &identity).unwrap();
+        assert!(book.get(&new).is_none());
+
+        let report = import_contacts(dir.path(), &identity, &path, None, false).unwrap();
+        assert!(report.committed);
+        assert_eq!((report.added, report.skipped), (1, 4));
+        let book = ContactBook::load(dir.path(), &identity).unwrap();
+        assert_eq!(book.get(&new).unwrap().nickname, "Bo");
+        assert!(!book.get(&new).unwrap().verified);
+        assert!(book.get(&known).unwrap().verified);
+    }
+
+    #[test]
+    fn finds_ids_in_signal_exports() {
+        let id = Identity::generate().public_id();
+        let data = format!(
+            r#"[{{"number":"+15550100","name":"Dee","profile":{{"about":"Control: {id}"}}}},
+                {{"number":"+15550101","profile":{{"givenName":"Eli","familyName":"Ng"}}}}]"#
+        );
+        assert_eq!(
+            detect_format(Path::new("signal.json"), &data),
+            ImportFormat::Signal
+        );
+        let candidates = parse(ImportFormat::Signal, &data).unwrap();
+        assert_eq!(candidates[0].public_id.as_deref(), Some(id.as_str()));
+        assert_eq!(candidates[0].nickname, "Dee");
+        assert_eq!(candidates[1].public_id, None);
+        assert_eq!(candidates[1].nickname, "Eli Ng");
+
+        let plain = format!(r#"{{"contacts":[{{"public_id":"{id}","nickname":"Dee"}}]}}"#);
+        assert_eq!(
+            detect_format(Path::new("export.txt"), &plain),
+            ImportFormat::Json
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use crate::catalog::now_secs;
+use crate::crypto::{decode_public_key, Identity, SessionKey};
+use crate::introductions::Provenance;
+use crate::no_traces;
+use crate::pgp_drop::{self, PgpKey};
+use crate::proofs::ProofCheck;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
+
+const CONTACTS_FILE: &str = "contacts.enc";
+const MAX_ALIAS_LEN: usize = 64;
+
+/// A known peer, keyed by public ID
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+    pub public_id: String,
+    pub nickname: String,
+    /// Identity was confirmed out of band
+    #[serde(default)]
+    pub verified: bool,
+    pub updated_at: u64,
+    /// Signed introduction this contact was added from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub introduced_by: Option<Provenance>,
+    /// Their ed25519 key (hex) from a contact card
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<String>,
+    /// Relays from their signed contact card, dialed before sending to them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relay_hints: Vec<String>,
+    /// Claims from their contact card and whether each was found
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proofs: Vec<ProofCheck>,
+    /// OpenPGP key that OpenPGP drops to this contact are encrypted to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pgp_key: Option<PgpKey>,
+    #[serde(default, skip_serializing_if = "Appearance::is_empty")]
+    pub appearance: Appearance,
+}
+
+/// How a contact's conversation is drawn, kept here so every frontend and
+/// device shows it the same way
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Appearance {
+    /// Shown in conversations instead of the nickname
+    pub alias: Option<String>,
+    /// `#rrggbb`
+    pub color: Option<String>,
+    /// SHA-256 of the avatar image, hex; the image itself is not stored here
+    pub avatar: Option<String>,
+}
+
+impl Appearance {
+    pub fn is_empty(&self) -> bool {
+        self.alias.is_none() && self.color.is_none() && self.avatar.is_none()
+    }
+
+    /// Trim the alias, lowercase the hex, and reject anything malformed
+    pub fn normalized(self) -> Result<Self> {
+        let alias = self
+            .alias
+            .map(|alias| alias.trim().to_string())
+            .filter(|alias| !alias.is_empty());
+        if alias
+            .as_ref()
+            .is_some_and(|alias| alias.chars().count() > MAX_ALIAS_LEN)
+        {
+            anyhow::bail!("Alias is longer than {} characters", MAX_ALIAS_LEN);
+        }
+        let color = self.color.map(|color| color.to_ascii_lowercase());
+        if let Some(color) = &color {
+            let hex = color.strip_prefix('#').unwrap_or_default();
+            if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                anyhow::bail!("Color must be #rrggbb: {}", color);
+            }
+        }
+        let avatar = self.avatar.map(|avatar| avatar.to_ascii_lowercase());
+        if let Some(avatar) = &avatar {
+            if avatar.len() != 64 || !avatar.chars().all(|c| c.is_ascii_hexdigit()) {
+                anyhow::bail!("Avatar must be a SHA-256 hash in hex");
+            }
+        }
+        Ok(Self {
+            alias,
+            color,
+            avatar,
+        })
+    }
+}
+
+/// Address book, encrypted with the local identity
+pub struct ContactBook {
+    path: PathBuf,
+    storage_key: SessionKey,
+    contacts: Vec<Contact>,
+}
+
+impl ContactBook {
+    /// Load the address book, starting empty if it does not exist yet
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(CONTACTS_FILE);
+        let storage_key = identity.storage_key(b"contacts");
+
+        let contacts = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read contacts")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt contacts")?;
+            let contacts = serde_json::from_slice(&json);
+            json.zeroize();
+            contacts.context("Failed to parse contacts")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            contacts,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut json = serde_json::to_vec(&self.contacts)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write contacts")?;
+        Ok(())
+    }
+
+    pub fn contacts(&self) -> &[Contact] {
+        &self.contacts
+    }
+
+    pub fn get(&self, public_id: &str) -> Option<&Contact> {
+        self.contacts.iter().find(|c| c.public_id == public_id)
+    }
+
+    /// Add or update a contact entered by the user
+    pub fn upsert(&mut self, public_id: &str, nickname: &str, verified: bool) -> Result<Contact> {
+        decode_public_key(public_id).context("Invalid contact public ID")?;
+
+        let existing = self.get(public_id);
+        let contact = Contact {
+            public_id: public_id.to_string(),
+            nickname: nickname.trim().to_string(),
+            verified,
+            updated_at: now_secs(),
+            introduced_by: existing.and_then(|c| c.introduced_by.clone()),
+            signing_key: existing.and_then(|c| c.signing_key.clone()),
+            relay_hints: existing.map(|c| c.relay_hints.clone()).unwrap_or_default(),
+            proofs: existing.map(|c| c.proofs.clone()).unwrap_or_default(),
+            pgp_key: existing.and_then(|c| c.pgp_key.clone()),
+            appearance: existing.map(|c| c.appearance.clone()).unwrap_or_default(),
+        };
+        self.insert(contact.clone());
+        Ok(contact)
+    }
+
+    /// Attach an armored OpenPGP public key to a contact, or remove it with `None`
+    pub fn set_pgp_key(&mut self, public_id: &str, key: Option<&str>) -> Result<Contact> {
+        let pgp_key = key.map(pgp_drop::parse_key).transpose()?;
+        let contact = self
+            .contacts
+            .iter_mut()
+            .find(|c| c.public_id == public_id)
+            .context("Unknown contact")?;
+        contact.pgp_key = pgp_key;
+        contact.updated_at = now_secs();
+        Ok(contact.clone())
+    }
+
+    pub fn set_appearance(&mut self, public_id: &str, appearance: Appearance) -> Result<Contact> {
+        let appearance = appearance.normalized()?;
+        let contact = self
+            .contacts
+            .iter_mut()
+            .find(|c| c.public_id == public_id)
+            .context("Unknown contact")?;
+        contact.appearance = appearance;
+        contact.updated_at = now_secs();
+        Ok(contact.clone())
+    }
+
+    /// Store the results of checking a contact's proofs
+    pub fn set_proof_checks(
+        &mut self,
+        public_id: &str,
+        checks: Vec<ProofCheck>,
+    ) -> Result<Contact> {
+        let contact = self
+            .contacts
+            .iter_mut()
+            .find(|c| c.public_id == public_id)
+            .context("Unknown contact")?;
+        contact.proofs = checks;
+        Ok(contact.clone())
+    }
+
+    /// Add a contact as is, replacing any previous entry for the same public ID
+    pub fn insert(&mut self, contact: Contact) {
+        self.contacts.retain(|c| c.public_id != contact.public_id);
+        self.contacts.push(contact);
+    }
+
+    pub fn remove(&mut self, public_id: &str) -> Option<Contact> {
+        let index = self
+            .contacts
+            .iter()
+            .position(|c| c.public_id == public_id)?;
+        Some(self.contacts.remove(index))
+    }
+}
+
+/// Appearance of a contact for message events; `None` for strangers or if
+/// the address book can't be read
+pub fn appearance(data_dir: &Path, identity: &Identity, public_id: &str) -> Option<Appearance> {
+    let book = ContactBook::load(data_dir, identity).ok()?;
+    book.get(public_id)
+        .map(|c| c.appearance.clone())
+        .filter(|a| !a.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_appearance() {
+        let appearance = Appearance {
+            alias: Some("  Mo ".to_string()),
+            color: Some("#A0B1C2".to_string()),
+            avatar: Some("AB".repeat(32)),
+        }
+        .normalized()
+        .unwrap();
+        assert_eq!(appearance.alias.as_deref(), Some("Mo"));
+        assert_eq!(appearance.color.as_deref(), Some("#a0b1c2"));
+        assert_eq!(appearance.avatar, Some("ab".repeat(32)));
+
+        let blank = Appearance {
+            alias: Some("   ".to_string()),
+            ..Default::default()
+        };
+        assert!(blank.normalized().unwrap().is_empty());
+
+        for color in ["red", "#a0b1c", "a0b1c2", "#a0b1cz"] {
+            let appearance = Appearance {
+                color: Some(color.to_string()),
+                ..Default::default()
+            };
+            assert!(appearance.normalized().is_err(), "{}", color);
+        }
+    }
+}
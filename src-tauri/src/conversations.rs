@@ -0,0 +1,210 @@
+use crate::catalog::now_secs;
+use crate::contacts::ContactBook;
+use crate::crypto::{Identity, SessionKey};
+use crate::events;
+use crate::i18n::t;
+use crate::no_traces;
+use crate::p2p::GhostMessage;
+use crate::session::AppState;
+use crate::storage;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{Manager, Window};
+use tracing::warn;
+use zeroize::Zeroize;
+
+const CONVERSATIONS_FILE: &str = "conversations.enc";
+const PREVIEW_CHARS: usize = 80;
+
+/// Per-contact chat summary
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Conversation {
+    pub contact: String,
+    /// From the address book, filled in when listing
+    #[serde(default, skip_deserializing)]
+    pub nickname: Option<String>,
+    pub unread: u32,
+    pub last_message_preview: String,
+    pub last_message_at: u64,
+    pub last_message_outgoing: bool,
+}
+
+/// Conversation summaries, encrypted with the local identity
+pub struct ConversationStore {
+    path: PathBuf,
+    storage_key: SessionKey,
+    conversations: Vec<Conversation>,
+}
+
+impl ConversationStore {
+    /// Load the store, starting empty if it does not exist yet
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(CONVERSATIONS_FILE);
+        let storage_key = identity.storage_key(b"conversations");
+
+        let conversations = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read conversations")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt conversations")?;
+            let conversations = serde_json::from_slice(&json);
+            json.zeroize();
+            conversations.context("Failed to parse conversations")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            conversations,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut json = serde_json::to_vec(&self.conversations)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write conversations")?;
+        Ok(())
+    }
+
+    /// Conversations, most recent first
+    pub fn list(&self) -> Vec<Conversation> {
+        let mut conversations = self.conversations.clone();
+        conversations.sort_by(|a, b| b.last_message_at.cmp(&a.last_message_at));
+        conversations
+    }
+
+    pub fn conversations(&self) -> &[Conversation] {
+        &self.conversations
+    }
+
+    /// Add a conversation as is, keeping whichever copy has the newer last message
+    pub fn insert(&mut self, conversation: Conversation) {
+        match self
+            .conversations
+            .iter_mut()
+            .find(|c| c.contact == conversation.contact)
+        {
+            Some(existing) if existing.last_message_at >= conversation.last_message_at => {}
+            Some(existing) => *existing = conversation,
+            None => self.conversations.push(conversation),
+        }
+    }
+
+    pub fn total_unread(&self) -> u32 {
+        self.conversations.iter().map(|c| c.unread).sum()
+    }
+
+    pub fn record_incoming(
+        &mut self,
+        contact: &str,
+        content: &str,
+        timestamp: u64,
+    ) -> &Conversation {
+        let conversation = self.update(contact, content, timestamp, false);
+        conversation.unread += 1;
+        conversation
+    }
+
+    pub fn record_outgoing(&mut self, contact: &str, content: &str) -> &Conversation {
+        self.update(contact, content, now_secs(), true)
+    }
+
+    /// Clear the unread count; `None` if there is no conversation with `contact`
+    pub fn mark_read(&mut self, contact: &str) -> Option<&Conversation> {
+        let conversation = self
+            .conversations
+            .iter_mut()
+            .find(|c| c.contact == contact)?;
+        conversation.unread = 0;
+        Some(conversation)
+    }
+
+    fn update(
+        &mut self,
+        contact: &str,
+        content: &str,
+        timestamp: u64,
+        outgoing: bool,
+    ) -> &mut Conversation {
+        let index = match self.conversations.iter().position(|c| c.contact == contact) {
+            Some(index) => index,
+            None => {
+                self.conversations.push(Conversation {
+                    contact: contact.to_string(),
+                    nickname: None,
+                    unread: 0,
+                    last_message_preview: String::new(),
+                    last_message_at: 0,
+                    last_message_outgoing: false,
+                });
+                self.conversations.len() - 1
+            }
+        };
+
+        let conversation = &mut self.conversations[index];
+        // Messages can arrive out of order; only a newer one replaces the preview
+        if timestamp >= conversation.last_message_at {
+            conversation.last_message_preview = content.chars().take(PREVIEW_CHARS).collect();
+            conversation.last_message_at = timestamp;
+            conversation.last_message_outgoing = outgoing;
+        }
+        conversation
+    }
+}
+
+/// Conversations with nicknames from the address book, most recent first
+pub fn list_conversations(data_dir: &Path, identity: &Identity) -> Result<Vec<Conversation>> {
+    let store = ConversationStore::load(data_dir, identity)?;
+    let contacts = ContactBook::load(data_dir, identity)?;
+
+    Ok(store
+        .list()
+        .into_iter()
+        .map(|mut conversation| {
+            conversation.nickname = contacts
+                .get(&conversation.contact)
+                .map(|c| c.nickname.clone())
+                .filter(|n| !n.is_empty());
+            conversation
+        })
+        .collect())
+}
+
+/// Record an incoming message and emit `conversation_updated`
+pub fn message_received(window: &Window, message: &GhostMessage) {
+    let Some(identity) = window.app_handle().state::<AppState>().peek_identity() else {
+        return;
+    };
+    let updated = storage::data_dir().and_then(|data_dir| {
+        let mut store = ConversationStore::load(&data_dir, &identity)?;
+        // The content of a view-once message never reaches the preview
+        let content = if message.view_once {
+            t("conversation.view_once")
+        } else if message.location.is_some() && message.content.is_empty() {
+            t("conversation.location")
+        } else {
+            message.content.clone()
+        };
+        let conversation = store
+            .record_incoming(&message.from, &content, message.timestamp)
+            .clone();
+        store.save()?;
+        Ok(conversation)
+    });
+
+    match updated {
+        Ok(conversation) => {
+            events::emit(&window.app_handle(), "conversation_updated", conversation);
+        }
+        Err(e) => warn!("Failed to record incoming message: {}", e),
+    }
+}
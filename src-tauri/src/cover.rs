@@ -0,0 +1,221 @@
+use crate::ipfs;
+use crate::settings;
+use anyhow::{Context, Result};
+use futures::{SinkExt, StreamExt};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::header::{HeaderValue, HOST, USER_AGENT};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{info, warn};
+
+const FRONT_PORT: u16 = 443;
+const CONNECT_TIMEOUT_SECS: u64 = 20;
+const BUFFER_SIZE: usize = 16 * 1024;
+/// Sent with the upgrade so it looks like any browser opening a WebSocket
+const BROWSER_AGENT: &str =
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:128.0) Gecko/20100101 Firefox/128.0";
+
+/// Port of the local bridge, started the first time the cover is used
+static BRIDGE_PORT: Mutex<Option<u16>> = Mutex::new(None);
+
+/// Reach a relay through HTTPS only: libp2p dials a local bridge, which
+/// carries the bytes as WebSocket frames over TLS to `front`. With `host`
+/// set to a different name, the TLS handshake shows the front while the
+/// CDN routes the request to the relay (domain fronting). The relay needs a
+/// libp2p `/ws` listener behind the CDN.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CoverSettings {
+    pub enabled: bool,
+    /// Domain connected to and named in TLS, e.g. a CDN edge
+    pub front: String,
+    /// Host header of the request; empty to use `front`
+    pub host: String,
+    /// Path of the relay's WebSocket endpoint
+    pub path: String,
+    /// Peer ID of the relay behind the front
+    pub relay: String,
+    /// Dial nothing but the cover, so no raw TCP leaves for relays or mailboxes
+    pub exclusive: bool,
+}
+
+impl Default for CoverSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            front: String::new(),
+            host: String::new(),
+            path: "/".to_string(),
+            relay: String::new(),
+            exclusive: false,
+        }
+    }
+}
+
+impl CoverSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !self.path.starts_with('/') {
+            anyhow::bail!("Cover path must start with /");
+        }
+        for name in [&self.front, &self.host] {
+            if name.contains(|c: char| c == '/' || c == ':' || c.is_whitespace()) {
+                anyhow::bail!("Cover front and host must be plain domain names: {}", name);
+            }
+        }
+        if !self.enabled {
+            return Ok(());
+        }
+        if self.front.is_empty() {
+            anyhow::bail!("Set the front domain to use the HTTPS cover");
+        }
+        self.relay
+            .parse::<PeerId>()
+            .with_context(|| format!("Invalid cover relay peer ID: {}", self.relay))?;
+        Ok(())
+    }
+
+    /// Whether relays and mailboxes are dialed directly as well
+    pub fn direct(&self) -> bool {
+        !(self.enabled && self.exclusive)
+    }
+
+    fn host(&self) -> &str {
+        if self.host.is_empty() {
+            &self.front
+        } else {
+            &self.host
+        }
+    }
+}
+
+/// Multiaddr that reaches the cover relay through the bridge, starting the
+/// bridge if it isn't running yet
+pub fn dial_addr(cover: &CoverSettings) -> Result<String> {
+    let mut port = BRIDGE_PORT.lock().unwrap();
+    let port = match *port {
+        Some(port) => port,
+        None => *port.insert(start_bridge()?),
+    };
+    Ok(format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", port, cover.relay))
+}
+
+/// Listen on loopback and tunnel every connection. The cover settings are
+/// read per connection, so changes apply to the next dial.
+fn start_bridge() -> Result<u16> {
+    let listener =
+        std::net::TcpListener::bind("127.0.0.1:0").context("Failed to start cover bridge")?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener).context("Failed to start cover bridge")?;
+    let port = listener.local_addr()?.port();
+    info!("Cover: bridge listening on 127.0.0.1:{}", port);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((local, _)) => {
+                    let cover = settings::p2p_settings().cover;
+                    tokio::spawn(async move {
+                        if let Err(e) = tunnel(local, &cover).await {
+                            warn!("Cover: tunnel to {} closed: {}", cover.front, e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!("Cover: bridge accept failed: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    });
+    Ok(port)
+}
+
+/// Carry one libp2p connection as binary WebSocket frames
+async fn tunnel(local: TcpStream, cover: &CoverSettings) -> Result<()> {
+    if !cover.enabled {
+        anyhow::bail!("HTTPS cover is disabled");
+    }
+    let url = format!("wss://{}{}", cover.front, cover.path);
+    let mut request = url.as_str().into_client_request()?;
+    request
+        .headers_mut()
+        .insert(HOST, HeaderValue::from_str(cover.host())?);
+    request
+        .headers_mut()
+        .insert(USER_AGENT, HeaderValue::from_static(BROWSER_AGENT));
+
+    let (ws, _) = tokio::time::timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS), async {
+        // Through the Tor proxy if one is set, like all other traffic
+        let stream = ipfs::connect(&cover.front, FRONT_PORT).await?;
+        tokio_tungstenite::client_async_tls(request, stream)
+            .await
+            .map_err(anyhow::Error::from)
+    })
+    .await
+    .context("Timed out reaching the front")?
+    .with_context(|| format!("Failed to reach {}", cover.front))?;
+    info!("Cover: tunnel open via {} to {}", cover.front, cover.host());
+
+    let (mut ws_tx, mut ws_rx) = ws.split();
+    let (mut local_rx, mut local_tx) = local.into_split();
+    let upstream = async {
+        let mut buf = vec![0u8; BUFFER_SIZE];
+        loop {
+            let n = local_rx.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            ws_tx.send(Message::Binary(buf[..n].to_vec())).await?;
+        }
+        ws_tx.close().await?;
+        Ok::<_, anyhow::Error>(())
+    };
+    let downstream = async {
+        while let Some(message) = ws_rx.next().await {
+            match message? {
+                Message::Binary(data) => local_tx.write_all(&data).await?,
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        local_tx.shutdown().await?;
+        Ok::<_, anyhow::Error>(())
+    };
+
+    tokio::select! {
+        result = upstream => result,
+        result = downstream => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_front_and_relay() {
+        let mut cover = CoverSettings {
+            enabled: true,
+            front: "cdn.example.com".to_string(),
+            relay: "12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN".to_string(),
+            ..Default::default()
+        };
+        assert!(cover.validate().is_ok());
+        assert_eq!(cover.host(), "cdn.example.com");
+
+        cover.front = "https://cdn.example.com".to_string();
+        assert!(cover.validate().is_err());
+        cover.front = "cdn.example.com".to_string();
+        cover.relay = "not a peer".to_string();
+        assert!(cover.validate().is_err());
+
+        cover.enabled = false;
+        assert!(cover.validate().is_ok());
+        assert!(cover.direct());
+    }
+}
@@ -8,11 +8,14 @@ use argon2::{
     Argon2,
 };
 use chacha20poly1305::{ChaCha20Poly1305, Key as ChaChaKey};
+use ed25519_dalek::SigningKey;
 use rand::{rngs::OsRng, RngCore};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::info;
 use x25519_dalek::{PublicKey, StaticSecret};
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
@@ -47,6 +50,59 @@ impl Identity {
         self.private_key.diffie_hellman(peer_public).to_bytes()
     }
 
+    /// Open data sealed to this identity with `seal_to`
+    pub fn open_sealed(&self, ephemeral_public: &PublicKey, data: &[u8]) -> Result<Vec<u8>> {
+        let mut shared_secret = self.shared_secret(ephemeral_public);
+        let plaintext = decrypt_message(&shared_secret, data);
+        shared_secret.zeroize();
+        plaintext
+    }
+
+    /// Derive a local storage key bound to this identity and a purpose label
+    pub fn storage_key(&self, label: &[u8]) -> SessionKey {
+        let mut private_bytes = self.private_key.to_bytes();
+        let mut hasher = Sha256::new();
+        hasher.update(b"deaddrop-storage-key");
+        hasher.update(label);
+        hasher.update(private_bytes);
+        private_bytes.zeroize();
+
+        let mut key_bytes: [u8; 32] = hasher.finalize().into();
+        let key = SessionKey::from_bytes(&key_bytes).expect("SHA-256 output is 32 bytes");
+        key_bytes.zeroize();
+        key
+    }
+
+    /// Ed25519 key for signing statements such as introductions, derived from
+    /// the identity so it needs no separate backup
+    pub fn signing_key(&self) -> SigningKey {
+        let mut private_bytes = self.private_key.to_bytes();
+        let mut hasher = Sha256::new();
+        hasher.update(b"deaddrop-signing-key");
+        hasher.update(private_bytes);
+        private_bytes.zeroize();
+
+        let mut seed: [u8; 32] = hasher.finalize().into();
+        let key = SigningKey::from_bytes(&seed);
+        seed.zeroize();
+        key
+    }
+
+    /// Raw private key for recovery backups; the caller must zeroize it
+    pub fn secret_bytes(&self) -> [u8; 32] {
+        self.private_key.to_bytes()
+    }
+
+    /// Rebuild an identity from a backed-up private key
+    pub fn from_secret_bytes(bytes: [u8; 32]) -> Self {
+        let private_key = StaticSecret::from(bytes);
+        let public_key = PublicKey::from(&private_key);
+        Self {
+            public_key,
+            private_key,
+        }
+    }
+
     /// Load or generate identity from encrypted storage
     pub fn load_or_generate(password: &str, data_dir: PathBuf) -> Result<Self> {
         let identity_path = data_dir.join(IDENTITY_FILE);
@@ -60,9 +116,14 @@ impl Identity {
         }
     }
 
+    /// Store an identity restored elsewhere, encrypted with `password`
+    pub fn save(&self, password: &str, data_dir: &Path) -> Result<()> {
+        self.save_to_disk(password, &data_dir.join(IDENTITY_FILE))
+    }
+
     /// Save encrypted identity to disk using Argon2 + AES-GCM
     fn save_to_disk(&self, password: &str, path: &PathBuf) -> Result<()> {
-        println!("Generating encryption key (this may take a moment)...");
+        info!("Generating encryption key (this may take a moment)...");
         
         // Derive key from password using Argon2
         let salt = SaltString::generate(&mut OsRng);
@@ -82,7 +143,7 @@ impl Identity {
             .hash_password(password.as_bytes(), &salt)
             .map_err(|e| anyhow::anyhow!("Failed to hash password: {:?}", e))?;
         
-        println!("Key generated successfully");
+        info!("Key generated successfully");
 
         // Extract 32-byte key from hash
         let key_material = password_hash.hash.context("No hash generated")?;
@@ -114,7 +175,7 @@ impl Identity {
 
     /// Load encrypted identity from disk
     fn load_from_disk(password: &str, path: &PathBuf) -> Result<Self> {
-        println!("Loading identity from disk...");
+        info!("Loading identity from disk...");
         let json = fs::read_to_string(path)?;
         let stored: StoredIdentity = serde_json::from_str(&json)?;
 
@@ -130,7 +191,7 @@ impl Identity {
         let password_hash = argon2.hash_password(password.as_bytes(), &salt)
             .map_err(|e| anyhow::anyhow!("Failed to hash password: {:?}", e))?;
         
-        println!("Identity loaded successfully");
+        info!("Identity loaded successfully");
 
         let key_material = password_hash.hash.context("No hash generated")?;
         let key = &key_material.as_bytes()[..32];
@@ -179,6 +240,68 @@ struct StoredIdentity {
     ciphertext: Vec<u8>,
 }
 
+/// AEAD sealing drop file chunks. Both take a 12-byte nonce and a 16-byte
+/// tag, so the chunk layout is the same either way.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileCipher {
+    /// Fast in software; every drop before cipher selection uses it
+    #[default]
+    #[serde(rename = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+    /// Faster where the CPU has AES instructions
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+}
+
+impl FileCipher {
+    /// AES-256-GCM on CPUs with AES-NI/CLMUL or ARMv8 crypto extensions,
+    /// ChaCha20-Poly1305 everywhere else. Detected once per run.
+    pub fn preferred() -> Self {
+        static PREFERRED: OnceLock<FileCipher> = OnceLock::new();
+        *PREFERRED.get_or_init(|| {
+            let cipher = if has_aes_acceleration() {
+                Self::Aes256Gcm
+            } else {
+                Self::ChaCha20Poly1305
+            };
+            info!("Drop file cipher: {:?}", cipher);
+            cipher
+        })
+    }
+
+    /// Identifier stored in chunk headers
+    pub fn id(self) -> u8 {
+        match self {
+            Self::ChaCha20Poly1305 => 0,
+            Self::Aes256Gcm => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(Self::ChaCha20Poly1305),
+            1 => Ok(Self::Aes256Gcm),
+            _ => anyhow::bail!("Unknown file cipher {}", id),
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn has_aes_acceleration() -> bool {
+    std::arch::is_x86_feature_detected!("aes") && std::arch::is_x86_feature_detected!("pclmulqdq")
+}
+
+#[cfg(target_arch = "aarch64")]
+fn has_aes_acceleration() -> bool {
+    // Covers AESE/AESD and PMULL
+    std::arch::is_aarch64_feature_detected!("aes")
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+fn has_aes_acceleration() -> bool {
+    false
+}
+
 /// Session key for file encryption - auto-zeroized on drop
 #[derive(Clone, ZeroizeOnDrop)]
 pub struct SessionKey {
@@ -186,6 +309,12 @@ pub struct SessionKey {
     key: ChaChaKey,
 }
 
+impl std::fmt::Debug for SessionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SessionKey(<redacted>)")
+    }
+}
+
 impl SessionKey {
     /// Generate a random session key
     pub fn generate() -> Self {
@@ -232,6 +361,23 @@ impl SessionKey {
         Ok(result)
     }
 
+    /// Encrypt with a fixed nonce so equal plaintexts give equal ciphertexts.
+    /// Only safe for keys derived from the plaintext itself (convergent
+    /// encryption), since each such key then seals exactly one message.
+    pub fn encrypt_convergent(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let cipher = ChaCha20Poly1305::new(&self.key);
+        let nonce_bytes = [0u8; NONCE_SIZE];
+        let nonce = chacha20poly1305::Nonce::from_slice(&nonce_bytes);
+
+        let ciphertext = cipher
+            .encrypt(nonce, data)
+            .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+
+        let mut result = nonce_bytes.to_vec();
+        result.extend_from_slice(&ciphertext);
+        Ok(result)
+    }
+
     /// Decrypt file data using ChaCha20-Poly1305
     pub fn decrypt_file(&self, data: &[u8]) -> Result<Vec<u8>> {
         if data.len() < NONCE_SIZE {
@@ -248,6 +394,90 @@ impl SessionKey {
 
         Ok(plaintext)
     }
+
+    /// Encrypt a drop chunk with the given cipher: nonce || ciphertext
+    pub fn encrypt_chunk(&self, cipher: FileCipher, data: &[u8]) -> Result<Vec<u8>> {
+        match cipher {
+            FileCipher::ChaCha20Poly1305 => self.encrypt_file(data),
+            FileCipher::Aes256Gcm => {
+                let mut nonce_bytes = [0u8; NONCE_SIZE];
+                OsRng.fill_bytes(&mut nonce_bytes);
+                let ciphertext = Aes256Gcm::new(&self.key)
+                    .encrypt(Nonce::from_slice(&nonce_bytes), data)
+                    .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+
+                let mut result = nonce_bytes.to_vec();
+                result.extend_from_slice(&ciphertext);
+                Ok(result)
+            }
+        }
+    }
+
+    /// Decrypt a drop chunk sealed with `encrypt_chunk`
+    pub fn decrypt_chunk(&self, cipher: FileCipher, data: &[u8]) -> Result<Vec<u8>> {
+        match cipher {
+            FileCipher::ChaCha20Poly1305 => self.decrypt_file(data),
+            FileCipher::Aes256Gcm => {
+                if data.len() < NONCE_SIZE {
+                    anyhow::bail!("Invalid encrypted data: too short");
+                }
+                let (nonce_bytes, ciphertext) = data.split_at(NONCE_SIZE);
+                Aes256Gcm::new(&self.key)
+                    .decrypt(Nonce::from_slice(nonce_bytes), ciphertext)
+                    .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))
+            }
+        }
+    }
+}
+
+/// Key for password-protected exports, using the identity file's Argon2id
+/// parameters. Pass `None` to pick a new salt; it is returned for storing
+/// next to the data.
+pub fn password_key(password: &str, salt: Option<&str>) -> Result<(SessionKey, String)> {
+    let salt = match salt {
+        Some(salt) => SaltString::from_b64(salt)
+            .map_err(|e| anyhow::anyhow!("Failed to parse salt: {:?}", e))?,
+        None => SaltString::generate(&mut OsRng),
+    };
+
+    use argon2::{Algorithm, Params, Version};
+    let params = Params::new(16384, 3, 1, None)
+        .map_err(|e| anyhow::anyhow!("Failed to create Argon2 params: {:?}", e))?;
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {:?}", e))?;
+
+    let key_material = password_hash.hash.context("No hash generated")?;
+    let key = SessionKey::from_bytes(&key_material.as_bytes()[..32])?;
+    Ok((key, salt.as_str().to_string()))
+}
+
+/// Decode a base58 X25519 public key
+pub fn decode_public_key(public_key_b58: &str) -> Result<PublicKey> {
+    let key_bytes = bs58::decode(public_key_b58)
+        .into_vec()
+        .context("Invalid base58 public key")?;
+
+    if key_bytes.len() != 32 {
+        anyhow::bail!("Invalid public key length");
+    }
+
+    let mut key_array = [0u8; 32];
+    key_array.copy_from_slice(&key_bytes);
+    Ok(PublicKey::from(key_array))
+}
+
+/// Encrypt data to a recipient's public key using a fresh ephemeral keypair.
+/// Returns the ephemeral public key and the ciphertext.
+pub fn seal_to(recipient: &PublicKey, plaintext: &[u8]) -> Result<(PublicKey, Vec<u8>)> {
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let mut shared_secret = ephemeral.diffie_hellman(recipient).to_bytes();
+
+    let ciphertext = encrypt_message(&shared_secret, plaintext);
+    shared_secret.zeroize();
+
+    Ok((ephemeral_public, ciphertext?))
 }
 
 /// Encrypt message for P2P using shared secret
@@ -315,6 +545,21 @@ mod tests {
         assert_eq!(data.as_ref(), decrypted.as_slice());
     }
 
+    #[test]
+    fn test_chunk_ciphers() {
+        let key = SessionKey::generate();
+        let data = b"Secret military intel";
+
+        for cipher in [FileCipher::ChaCha20Poly1305, FileCipher::Aes256Gcm] {
+            let encrypted = key.encrypt_chunk(cipher, data).unwrap();
+            assert_eq!(encrypted.len(), data.len() + NONCE_SIZE + 16);
+            assert_eq!(key.decrypt_chunk(cipher, &encrypted).unwrap(), data);
+            assert_eq!(FileCipher::from_id(cipher.id()).unwrap(), cipher);
+        }
+        let aes = key.encrypt_chunk(FileCipher::Aes256Gcm, data).unwrap();
+        assert!(key.decrypt_chunk(FileCipher::ChaCha20Poly1305, &aes).is_err());
+    }
+
     #[test]
     fn test_identity_key_exchange() {
         let alice = Identity::generate();
@@ -325,4 +570,16 @@ mod tests {
 
         assert_eq!(alice_shared, bob_shared);
     }
+
+    #[test]
+    fn test_seal_and_open() {
+        let recipient = Identity::generate();
+        let intruder = Identity::generate();
+        let data = b"shard material";
+
+        let (ephemeral, sealed) = seal_to(&recipient.public_key, data).unwrap();
+
+        assert_eq!(recipient.open_sealed(&ephemeral, &sealed).unwrap(), data.to_vec());
+        assert!(intruder.open_sealed(&ephemeral, &sealed).is_err());
+    }
 }
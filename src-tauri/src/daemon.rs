@@ -0,0 +1,620 @@
+use crate::away_ping;
+use crate::canary::CanaryAlert;
+use crate::crypto::Identity;
+use crate::errors::{ControlError, ErrorCode};
+use crate::local_api;
+use crate::location::LocationShare;
+use crate::p2p::{init_p2p_actor, GhostMessage, P2PCommand, P2PEvents, ShutdownSignal};
+use crate::secrets::WipeReport;
+use crate::webhooks;
+use anyhow::{Context, Result};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{broadcast, mpsc, Notify};
+use tracing::{info, warn};
+
+const TOKEN_FILE: &str = "daemon.token";
+#[cfg(unix)]
+const SOCKET_FILE: &str = "daemon.sock";
+const STORED_MESSAGES: usize = 1000;
+const EVENT_BUFFER: usize = 256;
+
+/// Requests a client can make; the first one on a connection must be `Auth`
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum DaemonRequest {
+    /// Token from `daemon.token`, readable only by the user running the daemon
+    Auth { token: String },
+    Status,
+    Send {
+        target_public_key: String,
+        content: String,
+        message_id: Option<String>,
+        #[serde(default)]
+        view_once: bool,
+        #[serde(default)]
+        location: Option<LocationShare>,
+    },
+    /// Messages received since a Unix timestamp (all stored messages for `None`)
+    Messages { since: Option<u64> },
+    /// Stream P2P events on this connection
+    Subscribe,
+    Shutdown,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RequestEnvelope {
+    id: u64,
+    #[serde(flatten)]
+    request: DaemonRequest,
+}
+
+/// Lines the daemon writes: replies to requests and subscribed events
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum DaemonMessage {
+    Response {
+        id: u64,
+        result: Option<serde_json::Value>,
+        error: Option<String>,
+    },
+    Event {
+        event: String,
+        payload: serde_json::Value,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DaemonStatus {
+    pub public_id: String,
+    pub stored_messages: usize,
+}
+
+/// Publishes actor events to connected clients and keeps incoming messages
+/// so clients that connect later can catch up
+struct DaemonEvents {
+    events: broadcast::Sender<(String, serde_json::Value)>,
+    messages: Arc<Mutex<VecDeque<GhostMessage>>>,
+}
+
+impl P2PEvents for DaemonEvents {
+    fn emit_event(&self, event: &str, payload: serde_json::Value) -> Result<()> {
+        webhooks::fire(event, &payload);
+        // No subscribers is fine; events are only for connected clients
+        let _ = self.events.send((event.to_string(), payload));
+        Ok(())
+    }
+
+    fn message_received(&self, message: &GhostMessage) {
+        let mut messages = self.messages.lock().unwrap();
+        if messages.len() == STORED_MESSAGES {
+            messages.pop_front();
+        }
+        messages.push_back(message.clone());
+
+        // Nobody is subscribed, so nobody saw it arrive
+        if self.events.receiver_count() == 0 {
+            away_ping::message_waiting();
+        }
+    }
+}
+
+pub(crate) struct Daemon {
+    pub(crate) public_id: String,
+    pub(crate) identity: Identity,
+    pub(crate) data_dir: PathBuf,
+    pub(crate) token: String,
+    pub(crate) sender: mpsc::Sender<P2PCommand>,
+    pub(crate) events: broadcast::Sender<(String, serde_json::Value)>,
+    pub(crate) messages: Arc<Mutex<VecDeque<GhostMessage>>>,
+    pub(crate) shutdown: Notify,
+}
+
+/// Run Ghost Mode as a background service until `Shutdown` or Ctrl-C,
+/// serving clients on a Unix socket (named pipe on Windows) in the data
+/// directory, and on `127.0.0.1:api_port` over HTTP if a port is given
+pub async fn run_daemon(identity: Identity, data_dir: &Path, api_port: Option<u16>) -> Result<()> {
+    if connect_stream(data_dir).await.is_ok() {
+        anyhow::bail!("A daemon is already running for {}", data_dir.display());
+    }
+
+    let token = write_token(data_dir)?;
+    let (events, _) = broadcast::channel(EVENT_BUFFER);
+    let messages = Arc::new(Mutex::new(VecDeque::new()));
+    let stop_actor = ShutdownSignal::new();
+    let sender = init_p2p_actor(
+        identity.clone(),
+        Arc::new(DaemonEvents {
+            events: events.clone(),
+            messages: messages.clone(),
+        }),
+        data_dir.to_path_buf(),
+        &stop_actor,
+    )?;
+
+    let daemon = Arc::new(Daemon {
+        public_id: identity.public_id(),
+        identity,
+        data_dir: data_dir.to_path_buf(),
+        token,
+        sender,
+        events,
+        messages,
+        shutdown: Notify::new(),
+    });
+
+    info!("Daemon for {} listening on {}", daemon.public_id, socket_path(data_dir).display());
+    let result = tokio::select! {
+        result = accept_loop(data_dir, daemon.clone()) => result,
+        result = serve_api(api_port, daemon.clone()) => result,
+        _ = daemon.shutdown.notified() => Ok(()),
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+
+    stop_actor.trigger();
+    let _ = fs::remove_file(data_dir.join(TOKEN_FILE));
+    #[cfg(unix)]
+    let _ = fs::remove_file(data_dir.join(SOCKET_FILE));
+    info!("Daemon stopped");
+    result
+}
+
+/// The local HTTP API if enabled; never resolves otherwise
+async fn serve_api(port: Option<u16>, daemon: Arc<Daemon>) -> Result<()> {
+    match port {
+        Some(port) => local_api::serve(port, daemon).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn write_token(data_dir: &Path) -> Result<String> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+
+    let path = data_dir.join(TOKEN_FILE);
+    fs::write(&path, &token).context("Failed to write daemon token")?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+            .context("Failed to restrict daemon token permissions")?;
+    }
+
+    Ok(token)
+}
+
+#[cfg(unix)]
+async fn accept_loop(data_dir: &Path, daemon: Arc<Daemon>) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = data_dir.join(SOCKET_FILE);
+    // A socket file left by a crashed daemon; nobody answered on it above
+    let _ = fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path).context("Failed to bind daemon socket")?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+        .context("Failed to restrict daemon socket permissions")?;
+
+    loop {
+        let (stream, _) = listener.accept().await.context("Failed to accept client")?;
+        tokio::spawn(serve_client(stream, daemon.clone()));
+    }
+}
+
+#[cfg(windows)]
+async fn accept_loop(data_dir: &Path, daemon: Arc<Daemon>) -> Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let name = pipe_name(data_dir);
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .reject_remote_clients(true)
+        .create(&name)
+        .context("Failed to create daemon pipe")?;
+
+    loop {
+        server.connect().await.context("Failed to accept client")?;
+        let client = std::mem::replace(
+            &mut server,
+            ServerOptions::new().reject_remote_clients(true).create(&name)?,
+        );
+        tokio::spawn(serve_client(client, daemon.clone()));
+    }
+}
+
+/// One pipe per data directory, so separate profiles get separate daemons
+#[cfg(windows)]
+fn pipe_name(data_dir: &Path) -> String {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::digest(data_dir.to_string_lossy().as_bytes());
+    format!(r"\\.\pipe\control-daemon-{}", hex::encode(&digest[..8]))
+}
+
+async fn serve_client<S>(stream: S, daemon: Arc<Daemon>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    if let Err(e) = handle_client(stream, &daemon).await {
+        warn!("Daemon client error: {}", e);
+    }
+}
+
+async fn handle_client<S>(stream: S, daemon: &Daemon) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    // Nothing is answered before the client proves it can read the token file
+    let Some(line) = lines.next_line().await? else {
+        return Ok(());
+    };
+    let envelope: RequestEnvelope = serde_json::from_str(&line).context("Invalid request")?;
+    let authenticated = matches!(
+        &envelope.request,
+        DaemonRequest::Auth { token } if constant_time_eq(token.as_bytes(), daemon.token.as_bytes())
+    );
+    if !authenticated {
+        write_message(&mut writer, &error_response(envelope.id, "Not authenticated")).await?;
+        return Ok(());
+    }
+    write_message(&mut writer, &ok_response(envelope.id, serde_json::Value::Null)).await?;
+
+    let mut subscription: Option<broadcast::Receiver<(String, serde_json::Value)>> = None;
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                let reply = match serde_json::from_str::<RequestEnvelope>(&line) {
+                    Ok(envelope) => {
+                        if matches!(envelope.request, DaemonRequest::Subscribe) {
+                            subscription = Some(daemon.events.subscribe());
+                        }
+                        match handle_request(envelope.request, daemon).await {
+                            Ok(result) => ok_response(envelope.id, result),
+                            Err(e) => error_response(envelope.id, &e.to_string()),
+                        }
+                    }
+                    Err(e) => error_response(0, &format!("Invalid request: {}", e)),
+                };
+                write_message(&mut writer, &reply).await?;
+            }
+            Some((event, payload)) = next_event(&mut subscription) => {
+                write_message(&mut writer, &DaemonMessage::Event { event, payload }).await?;
+            }
+        }
+    }
+}
+
+pub(crate) async fn handle_request(
+    request: DaemonRequest,
+    daemon: &Daemon,
+) -> Result<serde_json::Value> {
+    match request {
+        DaemonRequest::Auth { .. } | DaemonRequest::Subscribe => Ok(serde_json::Value::Null),
+        DaemonRequest::Status => Ok(serde_json::to_value(DaemonStatus {
+            public_id: daemon.public_id.clone(),
+            stored_messages: daemon.messages.lock().unwrap().len(),
+        })?),
+        DaemonRequest::Send {
+            target_public_key,
+            content,
+            message_id,
+            view_once,
+            location,
+        } => {
+            let message_id = message_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+            daemon
+                .sender
+                .send(P2PCommand::SendMessage {
+                    target_public_key,
+                    content,
+                    message_id: message_id.clone(),
+                    view_once,
+                    location,
+                })
+                .await
+                .context("Ghost Mode stopped")?;
+            Ok(serde_json::Value::String(message_id))
+        }
+        DaemonRequest::Messages { since } => {
+            let messages: Vec<GhostMessage> = daemon
+                .messages
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|m| since.map_or(true, |since| m.timestamp >= since))
+                .cloned()
+                .collect();
+            Ok(serde_json::to_value(messages)?)
+        }
+        DaemonRequest::Shutdown => {
+            daemon.shutdown.notify_one();
+            Ok(serde_json::Value::Null)
+        }
+    }
+}
+
+/// Next subscribed event; never resolves without a subscription
+async fn next_event(
+    subscription: &mut Option<broadcast::Receiver<(String, serde_json::Value)>>,
+) -> Option<(String, serde_json::Value)> {
+    let Some(receiver) = subscription else {
+        return std::future::pending().await;
+    };
+    loop {
+        match receiver.recv().await {
+            Ok(event) => return Some(event),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Daemon client missed {} events", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+fn ok_response(id: u64, result: serde_json::Value) -> DaemonMessage {
+    DaemonMessage::Response {
+        id,
+        result: Some(result),
+        error: None,
+    }
+}
+
+fn error_response(id: u64, error: &str) -> DaemonMessage {
+    DaemonMessage::Response {
+        id,
+        result: None,
+        error: Some(error.to_string()),
+    }
+}
+
+async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &DaemonMessage) -> Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+trait IpcStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> IpcStream for T {}
+
+#[cfg(unix)]
+async fn connect_stream(data_dir: &Path) -> Result<Box<dyn IpcStream>> {
+    let stream = tokio::net::UnixStream::connect(data_dir.join(SOCKET_FILE)).await?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(windows)]
+async fn connect_stream(data_dir: &Path) -> Result<Box<dyn IpcStream>> {
+    let client = tokio::net::windows::named_pipe::ClientOptions::new().open(pipe_name(data_dir))?;
+    Ok(Box::new(client))
+}
+
+/// Attach to a running daemon instead of starting a local P2P actor.
+/// Returns `None` if no daemon runs for this data directory. The returned
+/// sender behaves like the actor's: `Shutdown` or `shutdown` only
+/// disconnects, the daemon stays online.
+pub async fn connect(
+    data_dir: &Path,
+    events: Arc<dyn P2PEvents>,
+    shutdown: &ShutdownSignal,
+) -> Result<Option<mpsc::Sender<P2PCommand>>> {
+    let Ok(stream) = connect_stream(data_dir).await else {
+        return Ok(None);
+    };
+    let token = fs::read_to_string(data_dir.join(TOKEN_FILE)).context("Failed to read daemon token")?;
+
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut next_id = 0u64;
+    let mut request = move |request: DaemonRequest| {
+        next_id += 1;
+        RequestEnvelope { id: next_id, request }
+    };
+
+    write_request(&mut writer, &request(DaemonRequest::Auth { token: token.trim().to_string() })).await?;
+    match read_message(&mut lines).await? {
+        DaemonMessage::Response { error: None, .. } => {}
+        _ => anyhow::bail!("Daemon rejected the token"),
+    }
+    write_request(&mut writer, &request(DaemonRequest::Subscribe)).await?;
+    let backlog_request = request(DaemonRequest::Messages { since: None });
+    let backlog_id = backlog_request.id;
+    write_request(&mut writer, &backlog_request).await?;
+
+    let (tx, mut rx) = mpsc::channel::<P2PCommand>(100);
+    let pending: Arc<Mutex<Vec<(u64, String)>>> = Arc::new(Mutex::new(Vec::new())); // request id -> message id
+    let daemon_path = data_dir.to_path_buf();
+    let shutdown = shutdown.clone();
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = shutdown.triggered() => break,
+                command = rx.recv() => match command {
+                    Some(P2PCommand::SendMessage { target_public_key, content, message_id, view_once, location }) => {
+                        let envelope = request(DaemonRequest::Send {
+                            target_public_key,
+                            content,
+                            message_id: Some(message_id.clone()),
+                            view_once,
+                            location,
+                        });
+                        pending.lock().unwrap().push((envelope.id, message_id));
+                        if let Err(e) = write_request(&mut writer, &envelope).await {
+                            let _ = events.emit("ghost_error", ControlError::wrap("error.send")(e));
+                        }
+                    }
+                    Some(P2PCommand::RequestBurnKey { reply, .. }) => {
+                        let _ = reply.send(Err(
+                            "Burn-after-read keys can't be requested through the daemon".to_string(),
+                        ));
+                    }
+                    Some(P2PCommand::SendContactRequest { reply, .. })
+                    | Some(P2PCommand::RespondContactRequest { reply, .. }) => {
+                        let _ = reply.send(Err(
+                            "Contact requests can't be answered through the daemon".to_string(),
+                        ));
+                    }
+                    Some(P2PCommand::SendIntroduction { reply, .. }) => {
+                        let _ = reply.send(Err(
+                            "Introductions can't be sent through the daemon".to_string(),
+                        ));
+                    }
+                    Some(P2PCommand::SendGroupShards { reply, .. }) => {
+                        let _ = reply.send(Err(
+                            "Group shards can't be sent through the daemon".to_string(),
+                        ));
+                    }
+                    Some(P2PCommand::AnnounceRevocation { reply, .. })
+                    | Some(P2PCommand::SendAttestation { reply, .. }) => {
+                        let _ = reply.send(Err(
+                            "Signed notices can't be sent through the daemon".to_string(),
+                        ));
+                    }
+                    Some(P2PCommand::SendRemoteWipe { reply, .. }) => {
+                        let _ = reply.send(Err(
+                            "Wipe orders can't be sent through the daemon".to_string(),
+                        ));
+                    }
+                    Some(P2PCommand::SendSealed { reply, .. }) => {
+                        let _ = reply.send(Err(
+                            "Sealed envelopes can't be sent through the daemon".to_string(),
+                        ));
+                    }
+                    Some(P2PCommand::SendEscrowRequest { reply, .. }) => {
+                        let _ = reply.send(Err(
+                            "Release requests can't be sent through the daemon".to_string(),
+                        ));
+                    }
+                    Some(P2PCommand::SendEscrowDecision { reply, .. }) => {
+                        let _ = reply.send(Err(
+                            "Release decisions can't be sent through the daemon".to_string(),
+                        ));
+                    }
+                    Some(P2PCommand::SendPollMessage { reply, .. }) => {
+                        let _ = reply.send(Err(
+                            "Polls can't be sent through the daemon".to_string(),
+                        ));
+                    }
+                    // Dropping the reply tells the caller the status is unknown
+                    Some(P2PCommand::Status { .. }) => {}
+                    Some(P2PCommand::Shutdown) | None => break,
+                },
+                message = read_message(&mut lines) => match message {
+                    Ok(message) => forward(message, backlog_id, &pending, events.as_ref()),
+                    Err(e) => {
+                        warn!("Lost connection to daemon at {}: {}", daemon_path.display(), e);
+                        let _ = events.emit(
+                            "ghost_error",
+                            ControlError::localized(
+                                ErrorCode::NetworkUnavailable,
+                                "error.daemon_connection_lost",
+                            ),
+                        );
+                        break;
+                    }
+                },
+            }
+        }
+    });
+
+    info!("Connected to daemon at {}", data_dir.display());
+    Ok(Some(tx))
+}
+
+/// Pass daemon output on as the local actor would have reported it
+fn forward(
+    message: DaemonMessage,
+    backlog_id: u64,
+    pending: &Mutex<Vec<(u64, String)>>,
+    events: &dyn P2PEvents,
+) {
+    match message {
+        DaemonMessage::Event { event, payload } => {
+            match event.as_str() {
+                "ghost_msg" => {
+                    if let Ok(message) = serde_json::from_value::<GhostMessage>(payload.clone()) {
+                        events.message_received(&message);
+                    }
+                }
+                "msg_failed" => events.message_failed(
+                    payload["target"].as_str().unwrap_or_default(),
+                    payload["message_id"].as_str().unwrap_or_default(),
+                    payload["reason"].as_str().unwrap_or_default(),
+                ),
+                "canary_alert" => {
+                    if let Ok(alert) = serde_json::from_value::<CanaryAlert>(payload.clone()) {
+                        events.canary_tripped(&alert);
+                    }
+                }
+                "remote_wiped" => {
+                    if let Ok(report) = serde_json::from_value::<WipeReport>(payload.clone()) {
+                        events.remote_wiped(&report);
+                    }
+                }
+                _ => {}
+            }
+            let _ = events.emit_event(&event, payload);
+        }
+        // Messages that arrived before we connected
+        DaemonMessage::Response { id, result: Some(result), .. } if id == backlog_id => {
+            let _ = events.emit_event("ghost_msg_backlog", result);
+        }
+        DaemonMessage::Response { id, error: Some(error), .. } => {
+            let mut pending = pending.lock().unwrap();
+            if pending.iter().any(|(request_id, _)| *request_id == id) {
+                pending.retain(|(request_id, _)| *request_id != id);
+                let _ = events.emit(
+                    "ghost_error",
+                    ControlError::localized(ErrorCode::NetworkUnavailable, "error.send")
+                        .with_cause(error),
+                );
+            }
+        }
+        DaemonMessage::Response { id, .. } => {
+            pending.lock().unwrap().retain(|(request_id, _)| *request_id != id);
+        }
+    }
+}
+
+async fn write_request<W: AsyncWrite + Unpin>(writer: &mut W, request: &RequestEnvelope) -> Result<()> {
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+async fn read_message<R: tokio::io::AsyncBufRead + Unpin>(
+    lines: &mut tokio::io::Lines<R>,
+) -> Result<DaemonMessage> {
+    let line = lines.next_line().await?.context("Daemon closed the connection")?;
+    serde_json::from_str(&line).context("Invalid daemon message")
+}
+
+/// Where clients reach the daemon for this data directory
+pub fn socket_path(data_dir: &Path) -> PathBuf {
+    #[cfg(unix)]
+    {
+        data_dir.join(SOCKET_FILE)
+    }
+    #[cfg(windows)]
+    {
+        PathBuf::from(pipe_name(data_dir))
+    }
+}
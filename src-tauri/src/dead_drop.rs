@@ -1,23 +1,268 @@
-use crate::crypto::SessionKey;
+use crate::catalog::{now_secs, DropCatalog};
+use crate::chunk_index::{index_manifest_cid, retrieve_indexed_drop};
+use crate::crypto::{FileCipher, SessionKey};
+use crate::dedup::{dedup_manifest_cid, retrieve_dedup_drop};
+use crate::erasure::erasure_manifest_cid;
+use crate::escrow::escrow_manifest_cid;
+use crate::ipfs;
+use crate::mapped_io;
+use crate::mfs;
+use crate::no_traces;
+use crate::padding::padded_manifest_cid;
+use crate::password_drop::password_manifest_cid;
+use crate::recipients::recipients_manifest_cid;
+use crate::shred::ShredReport;
+use crate::stego::stego_cid;
+use crate::staging;
+use crate::shards::{recover_session_key, split_session_key};
+use crate::timelock::{retrieve_timelock_drop, timelock_manifest_cid};
+use crate::unixfs;
+use crate::upload_schedule::{self, PendingUpload, PendingUploads, Throttle, UploadSchedule};
 use anyhow::{Context, Result};
 use futures::StreamExt;
 use reqwest::multipart;
 use serde::{Deserialize, Serialize};
-use sharks::{Share, Sharks};
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::AsyncReadExt;
+use tracing::{error, info, warn};
 use zeroize::Zeroize;
 
-const IPFS_API_URL: &str = "http://127.0.0.1:5001/api/v0";
 const CHUNK_SIZE: usize = 4 * 1024 * 1024; // 4MB chunks for streaming
+pub const MIN_CHUNK_SIZE: usize = 1024 * 1024; // 1MB
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024; // 16MB
+const IN_FLIGHT_BUFFERS: usize = 2;
+pub const MAX_IN_FLIGHT_BUFFERS: usize = 8;
+const DROP_CACHE_DIR: &str = "drop_cache";
+const GATEWAY_TIMEOUT_SECS: u64 = 15;
+const FIND_PROVIDERS_LIMIT: usize = 10;
+const STAT_TIMEOUT_SECS: u64 = 30;
+const SPEED_PROBE_BYTES: u64 = 1024 * 1024;
+pub const CHUNK_OVERHEAD: u64 = 4 + 12 + 16; // length prefix + nonce + tag
+/// Chunk length prefixes carry the cipher ID in their top bits, which no
+/// chunk length reaches; chunks from before cipher selection read as 0
+const CIPHER_SHIFT: u32 = 28;
+const CHUNK_LEN_MASK: u32 = (1 << CIPHER_SHIFT) - 1;
 
 /// Result of creating a dead drop
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DeadDropCreated {
     pub cid: String,
     pub shards: Vec<String>,
+    pub drop_id: Option<String>, // Stable ID for versioned drops
+    pub piece_cids: Vec<String>, // Erasure-coded pieces, pinnable on other backends
+    pub shred: Option<ShredReport>, // Set when the source file was shredded
+    #[serde(default)]
+    pub cipher: Option<FileCipher>, // Chunk cipher; `None` for age-format drops
+    #[serde(default)]
+    pub pending: bool, // Upload still queued; `drop_ready` follows once it lands
+}
+
+/// Progress update for a drop operation, emitted as `drop_progress`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DropProgress {
+    pub stage: String,
+    pub bytes_done: u64,
+    pub total_bytes: u64,
+    pub memory_budget: u64,
+}
+
+pub type ProgressCallback = Arc<dyn Fn(DropProgress) + Send + Sync>;
+
+/// Streaming tunables and hooks for drop I/O
+#[derive(Clone)]
+pub struct DropOptions {
+    pub cache_dir: Option<PathBuf>,
+    pub chunk_size: usize,
+    pub in_flight_buffers: usize,
+    pub progress: Option<ProgressCallback>,
+    /// Only read blocks already on the local node (e.g. from an imported CAR)
+    pub offline: bool,
+    pub upload_schedule: UploadSchedule,
+    /// Where encrypted temp files are staged; the OS temp dir if unset
+    pub staging_dir: Option<PathBuf>,
+    /// Most bytes a drop may stage, e.g. when staging in RAM
+    pub staging_limit: Option<u64>,
+    /// Decrypt while downloading instead of staging the ciphertext first
+    pub pipelined: bool,
+    /// Stage the ciphertext and leave the upload to the background queue
+    pub background_upload: bool,
+    /// Map large local files into memory instead of reading them in chunks
+    pub mmap: bool,
+    /// Cipher for new chunks; any cipher is read back
+    pub cipher: FileCipher,
+}
+
+impl Default for DropOptions {
+    fn default() -> Self {
+        Self {
+            cache_dir: None,
+            chunk_size: CHUNK_SIZE,
+            in_flight_buffers: IN_FLIGHT_BUFFERS,
+            progress: None,
+            offline: false,
+            upload_schedule: UploadSchedule::default(),
+            staging_dir: None,
+            staging_limit: None,
+            pipelined: false,
+            background_upload: false,
+            mmap: false,
+            cipher: FileCipher::preferred(),
+        }
+    }
+}
+
+impl DropOptions {
+    /// Upper bound on plaintext/ciphertext buffers held in RAM at once:
+    /// the in-flight read buffers plus one plaintext and one ciphertext chunk
+    pub fn memory_budget(&self) -> u64 {
+        (self.chunk_size * (self.in_flight_buffers + 2)) as u64
+    }
+
+    /// Temp file in the staging directory
+    pub fn staging_file(&self) -> Result<tempfile::NamedTempFile> {
+        staging::staging_file(self.staging_dir.as_deref())
+    }
+
+    /// Refuse to stage `bytes` of plaintext over the staging limit, counting
+    /// what encryption adds
+    pub fn check_staging(&self, bytes: u64) -> Result<()> {
+        let Some(limit) = self.staging_limit else {
+            return Ok(());
+        };
+        let chunks = bytes.div_ceil(self.chunk_size as u64).max(1);
+        let staged = bytes + chunks * CHUNK_OVERHEAD;
+        if staged > limit {
+            anyhow::bail!(
+                "Drop needs {} bytes of staging, more than the {} bytes allowed",
+                staged,
+                limit
+            );
+        }
+        Ok(())
+    }
+
+    pub fn report(&self, stage: &str, bytes_done: u64, total_bytes: u64) {
+        if let Some(progress) = &self.progress {
+            progress(DropProgress {
+                stage: stage.to_string(),
+                bytes_done,
+                total_bytes,
+                memory_budget: self.memory_budget(),
+            });
+        }
+    }
+}
+
+/// Length prefix of an encrypted chunk, tagged with its cipher
+pub fn chunk_prefix(cipher: FileCipher, chunk_len: usize) -> [u8; 4] {
+    (((cipher.id() as u32) << CIPHER_SHIFT) | chunk_len as u32).to_le_bytes()
+}
+
+/// Cipher and length of the encrypted chunk behind a prefix
+pub fn parse_chunk_prefix(prefix: [u8; 4]) -> Result<(FileCipher, usize)> {
+    let prefix = u32::from_le_bytes(prefix);
+    let cipher = FileCipher::from_id((prefix >> CIPHER_SHIFT) as u8)?;
+    let chunk_len = (prefix & CHUNK_LEN_MASK) as usize;
+    if chunk_len > MAX_CHUNK_SIZE + 64 {
+        anyhow::bail!("Encrypted chunk too large: {} bytes", chunk_len);
+    }
+    Ok((cipher, chunk_len))
+}
+
+/// Read a chunk length prefix: `None` at a clean end of the stream, an
+/// error if the stream stops partway through one
+fn read_chunk_prefix<R: Read>(reader: &mut R) -> Result<Option<[u8; 4]>> {
+    let mut prefix = [0u8; 4];
+    let mut filled = 0;
+    while filled < prefix.len() {
+        match reader.read(&mut prefix[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => anyhow::bail!("Truncated chunk size"),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e).context("Failed to read chunk size"),
+        }
+    }
+    Ok(Some(prefix))
+}
+
+/// Availability of a single public gateway for a drop
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GatewayStatus {
+    pub gateway: String,
+    pub reachable: bool,
+    pub error: Option<String>,
+}
+
+/// Block-level availability report for a drop
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DropAvailability {
+    pub cid: String,
+    pub pinned_locally: bool,
+    pub total_blocks: usize,
+    pub local_blocks: usize,
+    pub missing_blocks: Vec<String>,
+    pub providers: Vec<String>,
+    pub gateways: Vec<GatewayStatus>,
+    pub cached_ciphertext: bool,
+}
+
+impl DropAvailability {
+    /// Content is fully available on the local node
+    pub fn locally_complete(&self) -> bool {
+        self.total_blocks > 0 && self.missing_blocks.is_empty()
+    }
+}
+
+/// Outcome of a repair attempt
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DropRepairResult {
+    pub cid: String,
+    pub action: String,
+    pub availability: DropAvailability,
+}
+
+/// Result of a bulk drop cleanup
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DropCleanupReport {
+    pub unpinned: Vec<String>,
+    pub failed: Vec<String>,
+    pub gc_ran: bool,
+    pub reclaimed_bytes: u64,
+}
+
+/// Directory holding local ciphertext copies of created drops
+pub fn drop_cache_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join(DROP_CACHE_DIR)
+}
+
+/// Cache for the ciphertext of new drops; none in no-traces mode, so their
+/// ciphertext never lands on disk (and uploads can't be deferred)
+pub fn new_drop_cache(data_dir: &Path) -> Option<PathBuf> {
+    (!no_traces::active()).then(|| drop_cache_dir(data_dir))
+}
+
+/// CID behind a prefixed drop reference: the manifest of an erasure-coded,
+/// indexed, deduplicated, multi-recipient, password, padded, timelocked or
+/// escrowed drop, or the carrier image of a hidden drop
+pub fn drop_manifest_cid(cid: &str) -> Option<&str> {
+    erasure_manifest_cid(cid)
+        .or_else(|| index_manifest_cid(cid))
+        .or_else(|| dedup_manifest_cid(cid))
+        .or_else(|| recipients_manifest_cid(cid))
+        .or_else(|| password_manifest_cid(cid))
+        .or_else(|| padded_manifest_cid(cid))
+        .or_else(|| timelock_manifest_cid(cid))
+        .or_else(|| escrow_manifest_cid(cid))
+        .or_else(|| stego_cid(cid))
+}
+
+fn cached_ciphertext_path(cache_dir: &Path, cid: &str) -> PathBuf {
+    cache_dir.join(format!("{}.enc", cid))
 }
 
 /// Create a dead drop: encrypt file, upload to IPFS, split key
@@ -26,6 +271,25 @@ pub async fn create_dead_drop(
     file_path: &str,
     threshold: u8,
     total_shards: u8,
+    options: &DropOptions,
+) -> Result<DeadDropCreated> {
+    // Get file size without loading into memory
+    let file_size = std::fs::metadata(file_path)
+        .context("Failed to read file metadata")?
+        .len();
+    info!("Processing file: {} ({} bytes)", file_path, file_size);
+    let input_file = File::open(file_path).context("Failed to open input file")?;
+    create_dead_drop_from_reader(input_file, file_size, threshold, total_shards, options).await
+}
+
+/// Create a dead drop from data that never was a file, such as pasted text,
+/// a screenshot or stdin. Only the ciphertext touches the disk.
+pub async fn create_dead_drop_from_reader<R: Read + Send>(
+    input: R,
+    total_bytes: u64,
+    threshold: u8,
+    total_shards: u8,
+    options: &DropOptions,
 ) -> Result<DeadDropCreated> {
     // Validate parameters
     if threshold > total_shards {
@@ -35,61 +299,30 @@ pub async fn create_dead_drop(
         anyhow::bail!("Threshold must be at least 2");
     }
 
-    // Get file size without loading into memory
-    let metadata = std::fs::metadata(file_path).context("Failed to read file metadata")?;
-    let file_size = metadata.len();
-    println!("Processing file: {} ({} bytes)", file_path, file_size);
-
     // Generate session key
     let session_key = SessionKey::generate();
 
-    // Create temporary file for encrypted data
-    let temp_file = tempfile::NamedTempFile::new().context("Failed to create temp file")?;
-    let temp_path = temp_file.path().to_path_buf();
-
-    // Stream encrypt: Read chunks -> Encrypt -> Write to temp file
-    let encrypted_size = stream_encrypt_file(file_path, &temp_path, &session_key)
-        .context("Failed to encrypt file")?;
-
-    println!("Encrypted file: {} bytes (streaming)", encrypted_size);
-
-    // Upload encrypted file to IPFS (streaming)
-    let cid = upload_file_to_ipfs(&temp_path).await?;
-    println!("Uploaded to IPFS: {}", cid);
+    let cid = encrypt_reader_and_upload(input, total_bytes, &session_key, options).await?;
 
     // Split session key using Shamir's Secret Sharing
-    let key_bytes = session_key.as_bytes();
-    let sharks = Sharks(threshold);
-    let dealer = sharks.dealer(&key_bytes);
-
-    let shares: Vec<Share> = dealer.take(total_shards as usize).collect();
-
-    // Convert shares to hex strings
-    let shard_strings: Vec<String> = shares
-        .iter()
-        .map(|share| {
-            // Serialize Share to bytes using Vec::from
-            let share_vec: Vec<u8> = Vec::from(share);
-            hex::encode(share_vec)
-        })
-        .collect();
+    let shard_strings = split_session_key(&session_key, threshold, total_shards);
 
-    // CRITICAL: Explicitly zeroize the session key
-    let mut key_bytes_mut = key_bytes;
-    key_bytes_mut.zeroize();
+    // CRITICAL: Explicitly drop (and zeroize) the session key
     drop(session_key);
 
-    // Clean up temp file
-    drop(temp_file);
-
-    println!(
+    info!(
         "Created {} shards with threshold {}",
         total_shards, threshold
     );
 
     Ok(DeadDropCreated {
+        pending: is_upload_pending(options, &cid),
         cid,
         shards: shard_strings,
+        drop_id: None,
+        piece_cids: Vec::new(),
+        shred: None,
+        cipher: Some(options.cipher),
     })
 }
 
@@ -99,132 +332,855 @@ pub async fn retrieve_dead_drop(
     cid: &str,
     shard_strings: Vec<String>,
     output_path: &str,
+    options: &DropOptions,
 ) -> Result<()> {
-    // Parse shards from hex
-    let shares: Result<Vec<Share>> = shard_strings
-        .iter()
-        .map(|s| {
-            hex::decode(s)
-                .context("Invalid hex shard")
-                .and_then(|bytes| {
-                    Share::try_from(bytes.as_slice())
-                        .map_err(|e| anyhow::anyhow!("Invalid share: {:?}", e))
-                })
-        })
-        .collect();
+    // Indexed drops verify each chunk against their index
+    if let Some(index_cid) = index_manifest_cid(cid) {
+        return retrieve_indexed_drop(index_cid, shard_strings, output_path, options).await;
+    }
+    if let Some(manifest_cid) = dedup_manifest_cid(cid) {
+        return retrieve_dedup_drop(manifest_cid, shard_strings, output_path, options).await;
+    }
+    if let Some(manifest_cid) = timelock_manifest_cid(cid) {
+        return retrieve_timelock_drop(manifest_cid, shard_strings, output_path, options).await;
+    }
 
-    let shares = shares?;
+    let session_key = recover_session_key(&shard_strings)?;
 
-    // Recover session key using Shamir's Secret Sharing
-    let sharks = Sharks(0); // Threshold is encoded in shares
-    let mut recovered_key_bytes = sharks
-        .recover(&shares)
-        .map_err(|e| anyhow::anyhow!("Failed to recover key: {:?}", e))?;
+    download_and_decrypt(cid, &session_key, output_path, options).await?;
+
+    Ok(())
+}
 
-    if recovered_key_bytes.len() != 32 {
-        recovered_key_bytes.zeroize();
-        anyhow::bail!("Invalid recovered key length");
+/// Encrypt a file with the given session key and upload the ciphertext to IPFS.
+/// If `options.cache_dir` is set, the ciphertext is kept there for later repair.
+pub async fn encrypt_and_upload(
+    file_path: &str,
+    session_key: &SessionKey,
+    options: &DropOptions,
+) -> Result<String> {
+    // Get file size without loading into memory
+    let metadata = std::fs::metadata(file_path).context("Failed to read file metadata")?;
+    let file_size = metadata.len();
+    info!("Processing file: {} ({} bytes)", file_path, file_size);
+
+    let input_file = File::open(file_path).context("Failed to open input file")?;
+    encrypt_reader_and_upload(input_file, file_size, session_key, options).await
+}
+
+/// Encrypt everything `input` yields and upload the ciphertext; only the
+/// ciphertext is staged on disk
+pub async fn encrypt_reader_and_upload<R: Read + Send>(
+    input: R,
+    total_bytes: u64,
+    session_key: &SessionKey,
+    options: &DropOptions,
+) -> Result<String> {
+    // Create temporary file for encrypted data
+    options.check_staging(total_bytes)?;
+    let temp_file = options.staging_file()?;
+    let temp_path = temp_file.path().to_path_buf();
+
+    // Stream encrypt: Read chunks -> Encrypt -> Write to temp file
+    let encrypted_size =
+        stream_encrypt_reader(input, total_bytes, &temp_path, session_key, options)
+            .context("Failed to encrypt file")?;
+
+    info!("Encrypted file: {} bytes (streaming)", encrypted_size);
+
+    upload_ciphertext(temp_file, options).await
+}
+
+/// Upload an encrypted staging file, or queue it for the background worker
+/// outside the upload window. The ciphertext is cached for repair if
+/// `options.cache_dir` is set.
+pub async fn upload_ciphertext(
+    temp_file: tempfile::NamedTempFile,
+    options: &DropOptions,
+) -> Result<String> {
+    let temp_path = temp_file.path().to_path_buf();
+
+    // Outside the upload window, or when asked to, the ciphertext is staged
+    // and uploaded by the background queue; the CID is computed locally so
+    // the drop can be shared right away
+    if options.background_upload || !options.upload_schedule.in_window() {
+        let cache_dir = options
+            .cache_dir
+            .as_ref()
+            .context("Deferred uploads need a drop cache directory")?;
+        let cid = stage_upload(&temp_path, cache_dir)?;
+        upload_schedule::wake_worker();
+        info!("Queued upload of {}", cid);
+        return Ok(cid);
+    }
+
+    // Stage first so an interrupted upload resumes after a restart
+    let staged = options.cache_dir.as_ref().and_then(|cache_dir| {
+        stage_upload(&temp_path, cache_dir)
+            .map(|cid| (cache_dir, cid))
+            .map_err(|e| error!("Failed to stage ciphertext: {}", e))
+            .ok()
+    });
+    if let Some((_, cid)) = &staged {
+        upload_schedule::claim(cid);
+    }
+
+    // Upload encrypted file to IPFS (streaming)
+    let uploaded = upload_file_to_ipfs(&temp_path, options).await;
+    if let Some((cache_dir, cid)) = &staged {
+        upload_schedule::release(cid);
+        let updated = match &uploaded {
+            Ok(_) => finish_staged_upload(cache_dir, cid),
+            Err(e) => fail_staged_upload(cache_dir, cid, e),
+        };
+        if let Err(e) = updated {
+            error!("Failed to update pending uploads: {}", e);
+        }
     }
+    let cid = match (uploaded, staged) {
+        (Ok(cid), _) => cid,
+        // The staged copy stays queued and is retried in the background
+        (Err(e), Some((_, cid))) => {
+            warn!("Upload of {} failed, retrying in the background: {:#}", cid, e);
+            upload_schedule::wake_worker();
+            return Ok(cid);
+        }
+        (Err(e), None) => return Err(e),
+    };
+    info!("Uploaded to IPFS: {}", cid);
 
-    // Create session key from recovered bytes
-    let session_key = SessionKey::from_bytes(&recovered_key_bytes)?;
-    recovered_key_bytes.zeroize();
+    // Clean up temp file
+    drop(temp_file);
+
+    Ok(cid)
+}
+
+/// Download a drop's ciphertext from IPFS and decrypt it to `output_path`.
+/// Returns the decrypted size.
+pub async fn download_and_decrypt(
+    cid: &str,
+    session_key: &SessionKey,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<u64> {
+    if options.pipelined {
+        return download_and_decrypt_pipelined(cid, session_key, output_path, options).await;
+    }
 
     // Download encrypted file to temp location (streaming)
-    let temp_file = tempfile::NamedTempFile::new().context("Failed to create temp file")?;
+    let temp_file = options.staging_file()?;
     let temp_path = temp_file.path().to_path_buf();
 
-    download_file_from_ipfs(cid, &temp_path).await?;
-    println!("Downloaded encrypted file from IPFS (streaming)");
+    download_file_from_ipfs(cid, &temp_path, options).await?;
+    info!("Downloaded encrypted file from IPFS (streaming)");
 
     // Stream decrypt: Read encrypted chunks -> Decrypt -> Write to output
-    let decrypted_size = stream_decrypt_file(&temp_path, output_path, &session_key)
+    let decrypted_size = stream_decrypt_file(&temp_path, output_path, session_key, options)
         .context("Failed to decrypt file")?;
 
-    println!("Decrypted {} bytes to {}", decrypted_size, output_path);
+    info!("Decrypted {} bytes to {}", decrypted_size, output_path);
 
     // Clean up temp file
     drop(temp_file);
 
-    Ok(())
+    Ok(decrypted_size)
 }
 
-/// Stream encrypt a file in chunks to avoid loading entire file into RAM
-/// Returns the total encrypted size
-fn stream_encrypt_file(
-    input_path: &str,
+/// Preflight estimate for retrieving a drop, computed before downloading
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DropStat {
+    pub cid: String,
+    pub download_size: u64,
+    pub estimated_plaintext_size: u64,
+    /// Downloaded ciphertext plus decrypted output
+    pub required_disk_space: u64,
+    pub available_disk_space: Option<u64>,
+    pub enough_disk_space: Option<bool>,
+    pub estimated_seconds: Option<u64>,
+    pub size_source: String, // "api" or the gateway that answered
+    /// Size matches the drop manifest; `None` if no manifest was available
+    pub manifest_verified: Option<bool>,
+}
+
+/// Estimate download size, disk space and time for a plain drop.
+/// `expected_size` is the ciphertext size from a manifest, if known.
+pub async fn stat_dead_drop(
+    cid: &str,
+    expected_size: Option<u64>,
     output_path: &Path,
+    options: &DropOptions,
+) -> Result<DropStat> {
+    let client = ipfs::client()?;
+    let (size, size_source) = object_size(&client, cid).await?;
+    let bytes_per_sec = probe_download_speed(&client, cid).await;
+
+    let mut stat = build_drop_stat(
+        cid,
+        size,
+        size,
+        size_source,
+        expected_size.map(|expected| expected == size),
+        bytes_per_sec,
+        output_path,
+        options,
+    );
+
+    // Pipelined retrieval never stages the ciphertext
+    if options.pipelined {
+        stat.required_disk_space = stat.estimated_plaintext_size;
+        stat.enough_disk_space = stat
+            .available_disk_space
+            .map(|free| free >= stat.required_disk_space);
+    }
+
+    Ok(stat)
+}
+
+/// Assemble a `DropStat` from a measured download size
+#[allow(clippy::too_many_arguments)]
+pub fn build_drop_stat(
+    cid: &str,
+    download_size: u64,
+    ciphertext_size: u64,
+    size_source: String,
+    manifest_verified: Option<bool>,
+    bytes_per_sec: Option<u64>,
+    output_path: &Path,
+    options: &DropOptions,
+) -> DropStat {
+    // The creator's chunk size is unknown, so assume ours for the overhead
+    let encrypted_chunk = options.chunk_size as u64 + CHUNK_OVERHEAD;
+    let chunks = ciphertext_size.div_ceil(encrypted_chunk);
+    let estimated_plaintext_size = ciphertext_size.saturating_sub(chunks * CHUNK_OVERHEAD);
+
+    let output_dir = output_path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let available_disk_space = fs2::available_space(output_dir)
+        .map_err(|e| warn!("Could not read free space for {}: {}", output_dir.display(), e))
+        .ok();
+
+    let required_disk_space = download_size + estimated_plaintext_size;
+
+    DropStat {
+        cid: cid.to_string(),
+        download_size,
+        estimated_plaintext_size,
+        required_disk_space,
+        available_disk_space,
+        enough_disk_space: available_disk_space.map(|free| free >= required_disk_space),
+        estimated_seconds: bytes_per_sec
+            .filter(|&rate| rate > 0)
+            .map(|rate| download_size.div_ceil(rate)),
+        size_source,
+        manifest_verified,
+    }
+}
+
+/// Size of a CID from the IPFS API, falling back to a gateway HEAD request
+pub async fn object_size(client: &reqwest::Client, cid: &str) -> Result<(u64, String)> {
+    let ipfs_path = format!("/ipfs/{}", cid);
+    let response = client
+        .post(ipfs::api("files/stat"))
+        .query(&[("arg", ipfs_path.as_str())])
+        .timeout(Duration::from_secs(STAT_TIMEOUT_SECS))
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => {
+            let json: serde_json::Value = response.json().await?;
+            if let Some(size) = json["Size"].as_u64() {
+                return Ok((size, "api".to_string()));
+            }
+        }
+        Ok(response) => warn!("IPFS stat of {} failed: {}", cid, response.status()),
+        Err(e) => warn!("IPFS stat of {} failed: {}", cid, e),
+    }
+
+    for gateway in ipfs::gateways() {
+        let response = client
+            .head(format!("{}/ipfs/{}", gateway, cid))
+            .timeout(Duration::from_secs(GATEWAY_TIMEOUT_SECS))
+            .send()
+            .await;
+        let size = response
+            .ok()
+            .filter(|r| r.status().is_success())
+            .and_then(|r| r.content_length());
+        if let Some(size) = size {
+            return Ok((size, gateway));
+        }
+    }
+
+    anyhow::bail!("Could not determine the size of {}", cid)
+}
+
+/// Time a short read of the drop to estimate download speed (bytes/sec)
+pub async fn probe_download_speed(client: &reqwest::Client, cid: &str) -> Option<u64> {
+    let probe_length = SPEED_PROBE_BYTES.to_string();
+    let started = std::time::Instant::now();
+    let response = client
+        .post(ipfs::api("cat"))
+        .query(&[("arg", cid), ("length", probe_length.as_str())])
+        .timeout(Duration::from_secs(STAT_TIMEOUT_SECS))
+        .send()
+        .await
+        .ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+
+    let bytes = response.bytes().await.ok()?.len() as f64;
+    let elapsed = started.elapsed().as_secs_f64();
+    Some((bytes / elapsed.max(0.001)) as u64)
+}
+
+/// Download and decrypt in one pass: chunks are decrypted as they arrive and
+/// plaintext goes straight to `output_path`, so no ciphertext copy hits disk.
+/// Each chunk is authenticated on arrival. At the end the CID is checked,
+/// or for CIDv0 drops the size the node reports for it, so a stream cut
+/// at a frame boundary is caught; the output is removed if a check fails.
+pub async fn download_and_decrypt_pipelined(
+    cid: &str,
     session_key: &SessionKey,
+    output_path: &str,
+    options: &DropOptions,
 ) -> Result<u64> {
-    let input_file = File::open(input_path).context("Failed to open input file")?;
-    let mut reader = BufReader::new(input_file);
+    let result = pipelined_download(cid, session_key, output_path, options).await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(output_path).await;
+    }
+    result
+}
 
-    let output_file = File::create(output_path).context("Failed to create output file")?;
-    let mut writer = BufWriter::new(output_file);
+async fn pipelined_download(
+    cid: &str,
+    session_key: &SessionKey,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<u64> {
+    let client = ipfs::client()?;
 
-    let mut total_encrypted = 0u64;
-    let mut chunk_buffer = vec![0u8; CHUNK_SIZE];
+    let mut request = client.post(ipfs::api("cat")).query(&[("arg", cid)]);
+    if options.offline {
+        request = request.query(&[("offline", "true")]);
+    }
 
-    loop {
-        // Read chunk
-        let bytes_read = reader.read(&mut chunk_buffer).context("Failed to read chunk")?;
-        if bytes_read == 0 {
-            break; // EOF
+    let response = request
+        .send()
+        .await
+        .context("Failed to download from IPFS")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("IPFS download failed: {}", response.status());
+    }
+
+    let output_file = tokio::fs::File::create(output_path)
+        .await
+        .context("Failed to create output file")?;
+    let mut writer = tokio::io::BufWriter::new(output_file);
+
+    // CIDv1 drops are verified as the ciphertext streams past
+    let mut cid_builder = cid.starts_with('b').then(unixfs::CidBuilder::default);
+    let expected_size = match cid_builder {
+        Some(_) => None,
+        None => Some(
+            object_size(&client, cid)
+                .await
+                .context("Could not determine the drop's size")?
+                .0,
+        ),
+    };
+
+    let content_length = response.content_length().unwrap_or(0);
+    let mut stream = response.bytes_stream();
+    let mut pending: Vec<u8> = Vec::new();
+    let mut total_downloaded = 0u64;
+    let mut total_decrypted = 0u64;
+
+    while let Some(bytes) = stream.next().await {
+        let bytes = bytes.context("Failed to read chunk from IPFS")?;
+        if let Some(builder) = &mut cid_builder {
+            builder.update(&bytes);
+        }
+        total_downloaded += bytes.len() as u64;
+        if expected_size.is_some_and(|size| total_downloaded > size) {
+            anyhow::bail!("Download is larger than drop {}", cid);
         }
+        pending.extend_from_slice(&bytes);
+
+        // Decrypt every complete frame: chunk size (4 bytes) + encrypted chunk
+        let mut offset = 0;
+        while pending.len() - offset >= 4 {
+            let size_bytes: [u8; 4] = pending[offset..offset + 4].try_into()?;
+            let (cipher, chunk_size) = parse_chunk_prefix(size_bytes)?;
+
+            let start = offset + 4;
+            if pending.len() - start < chunk_size {
+                break; // Rest of the chunk is still in flight
+            }
+
+            let mut decrypted_chunk = session_key
+                .decrypt_chunk(cipher, &pending[start..start + chunk_size])
+                .context("Failed to decrypt chunk")?;
+            tokio::io::AsyncWriteExt::write_all(&mut writer, &decrypted_chunk)
+                .await
+                .context("Failed to write decrypted chunk")?;
+            total_decrypted += decrypted_chunk.len() as u64;
+            decrypted_chunk.zeroize();
+
+            offset = start + chunk_size;
+        }
+        pending.drain(..offset);
 
-        // Encrypt chunk
-        let chunk_data = &chunk_buffer[..bytes_read];
-        let encrypted_chunk = session_key
-            .encrypt_file(chunk_data)
-            .context("Failed to encrypt chunk")?;
+        options.report("downloading", total_downloaded, content_length);
+    }
 
-        // Write encrypted chunk size (4 bytes) + encrypted data
-        let chunk_size = encrypted_chunk.len() as u32;
-        writer
-            .write_all(&chunk_size.to_le_bytes())
-            .context("Failed to write chunk size")?;
-        writer
-            .write_all(&encrypted_chunk)
-            .context("Failed to write encrypted chunk")?;
+    if !pending.is_empty() {
+        anyhow::bail!("Download ended mid-chunk - drop is truncated");
+    }
+    if let Some(size) = expected_size.filter(|&size| size != total_downloaded) {
+        anyhow::bail!(
+            "Download ended after {} of {} bytes - drop is truncated",
+            total_downloaded,
+            size
+        );
+    }
 
-        total_encrypted += 4 + encrypted_chunk.len() as u64;
+    tokio::io::AsyncWriteExt::flush(&mut writer)
+        .await
+        .context("Failed to flush output")?;
 
-        // Progress indicator for large files
-        if total_encrypted % (50 * 1024 * 1024) == 0 {
-            println!("Encrypted {} MB...", total_encrypted / (1024 * 1024));
+    if let Some(builder) = cid_builder {
+        if builder.finish() != cid {
+            anyhow::bail!("Downloaded content does not match CID {}", cid);
         }
     }
 
-    writer.flush().context("Failed to flush output")?;
+    info!("Decrypted {} bytes to {} (pipelined)", total_decrypted, output_path);
+
+    Ok(total_decrypted)
+}
+
+/// Check whether a drop is still retrievable from the local node, remote
+/// providers and public gateways
+pub async fn verify_dead_drop(cid: &str, cache_dir: Option<&Path>) -> Result<DropAvailability> {
+    // Erasure-coded and indexed drops are checked through their manifest
+    let cid = drop_manifest_cid(cid).unwrap_or(cid);
+    let client = ipfs::client()?;
+
+    let pinned_locally = is_pinned_locally(&client, cid).await?;
+
+    // Walk the DAG using only locally stored blocks
+    let mut blocks = vec![cid.to_string()];
+    let mut missing_blocks = Vec::new();
+    match list_local_refs(&client, cid).await {
+        Ok(refs) => blocks.extend(refs),
+        Err(e) => info!("Could not walk DAG for {} locally: {}", cid, e),
+    }
+
+    for block in &blocks {
+        if !has_local_block(&client, block).await {
+            missing_blocks.push(block.clone());
+        }
+    }
+
+    let providers = find_providers(&client, cid).await.unwrap_or_else(|e| {
+        warn!("Provider lookup failed for {}: {}", cid, e);
+        Vec::new()
+    });
+
+    let mut gateways = Vec::new();
+    for gateway in ipfs::gateways() {
+        gateways.push(check_gateway(&client, &gateway, cid).await);
+    }
+
+    let cached_ciphertext = cache_dir
+        .map(|dir| cached_ciphertext_path(dir, cid).exists())
+        .unwrap_or(false);
+
+    let availability = DropAvailability {
+        cid: cid.to_string(),
+        pinned_locally,
+        total_blocks: blocks.len(),
+        local_blocks: blocks.len() - missing_blocks.len(),
+        missing_blocks,
+        providers,
+        gateways,
+        cached_ciphertext,
+    };
+
+    info!(
+        "Verified {}: {}/{} blocks local, pinned: {}, {} providers",
+        cid,
+        availability.local_blocks,
+        availability.total_blocks,
+        availability.pinned_locally,
+        availability.providers.len()
+    );
+
+    Ok(availability)
+}
+
+/// Repair a drop: re-pin it if the blocks are reachable, otherwise re-upload
+/// the locally cached ciphertext copy
+pub async fn repair_dead_drop(cid: &str, cache_dir: Option<&Path>) -> Result<DropRepairResult> {
+    let cid = drop_manifest_cid(cid).unwrap_or(cid);
+    let client = ipfs::client()?;
+    let before = verify_dead_drop(cid, cache_dir).await?;
+
+    let action = if before.pinned_locally && before.locally_complete() {
+        "none".to_string()
+    } else if before.locally_complete() || !before.providers.is_empty() {
+        // Blocks are local or fetchable from the network - pinning is enough
+        pin_cid(&client, cid).await?;
+        "repinned".to_string()
+    } else if before.cached_ciphertext {
+        let cache_dir = cache_dir.context("Cache directory missing")?;
+        let cached_path = cached_ciphertext_path(cache_dir, cid);
+        let uploaded_cid = upload_file_to_ipfs(&cached_path, &DropOptions::default()).await?;
+        if uploaded_cid != cid {
+            anyhow::bail!(
+                "Re-uploaded ciphertext produced a different CID: {}",
+                uploaded_cid
+            );
+        }
+        "reuploaded".to_string()
+    } else {
+        anyhow::bail!("Drop {} is unavailable and no cached ciphertext exists", cid);
+    };
+
+    info!("Repair of {}: {}", cid, action);
+
+    let availability = verify_dead_drop(cid, cache_dir).await?;
+
+    Ok(DropRepairResult {
+        cid: cid.to_string(),
+        action,
+        availability,
+    })
+}
+
+/// Unpin a drop from the local node and discard its cached ciphertext,
+/// together with any erasure pieces or indexed ciphertext it references
+pub async fn unpin_dead_drop(
+    cid: &str,
+    piece_cids: &[String],
+    cache_dir: Option<&Path>,
+) -> Result<()> {
+    let client = ipfs::client()?;
+    unpin_cid(&client, drop_manifest_cid(cid).unwrap_or(cid)).await?;
+    for piece_cid in piece_cids {
+        unpin_cid(&client, piece_cid).await?;
+    }
+
+    if let Some(cache_dir) = cache_dir {
+        let cached_path = cached_ciphertext_path(cache_dir, cid);
+        if cached_path.exists() {
+            std::fs::remove_file(&cached_path).context("Failed to remove cached ciphertext")?;
+        }
+    }
+
+    info!("Unpinned drop {}", cid);
+    Ok(())
+}
+
+/// Unpin every catalogued drop that has expired or is older than `max_age_secs`,
+/// optionally running IPFS repo GC afterwards to reclaim the space
+pub async fn cleanup_dead_drops(
+    catalog: &mut DropCatalog,
+    max_age_secs: Option<u64>,
+    run_gc: bool,
+    cache_dir: Option<&Path>,
+) -> Result<DropCleanupReport> {
+    let client = ipfs::client()?;
+    let now = now_secs();
+
+    let stale: Vec<String> = catalog
+        .records()
+        .iter()
+        .filter(|r| r.pinned)
+        .filter(|r| {
+            r.is_expired(now)
+                || max_age_secs
+                    .map(|max_age| now.saturating_sub(r.created_at) >= max_age)
+                    .unwrap_or(false)
+        })
+        .map(|r| r.cid.clone())
+        .collect();
+
+    let size_before = if run_gc { repo_size(&client).await.ok() } else { None };
+
+    let mut unpinned = Vec::new();
+    let mut failed = Vec::new();
+    for cid in stale {
+        // Asked per drop, so chunks shared only among stale drops go with the last
+        let piece_cids = catalog.unshared_pieces(&cid);
+        match unpin_dead_drop(&cid, &piece_cids, cache_dir).await {
+            Ok(()) => {
+                if let Some(record) = catalog.get_mut(&cid) {
+                    record.pinned = false;
+                    // Still linked, GC would keep the blocks
+                    if let Err(e) = mfs::unlink_drop(record).await {
+                        warn!("Failed to unlink {} from MFS: {}", cid, e);
+                    }
+                }
+                unpinned.push(cid);
+            }
+            Err(e) => {
+                error!("Failed to unpin {}: {}", cid, e);
+                failed.push(cid);
+            }
+        }
+    }
+
+    let mut reclaimed_bytes = 0;
+    let mut gc_ran = false;
+    if run_gc {
+        // The drops are already unpinned; the catalog must still be saved
+        match run_repo_gc(&client).await {
+            Ok(()) => {
+                gc_ran = true;
+                if let (Some(before), Ok(after)) = (size_before, repo_size(&client).await) {
+                    reclaimed_bytes = before.saturating_sub(after);
+                }
+                info!("IPFS repo GC reclaimed {} bytes", reclaimed_bytes);
+            }
+            Err(e) => error!("IPFS repo GC failed: {}", e),
+        }
+    }
+
+    Ok(DropCleanupReport {
+        unpinned,
+        failed,
+        gc_ran,
+        reclaimed_bytes,
+    })
+}
+
+/// Copy ciphertext into the cache and record it as a pending upload
+fn stage_upload(temp_path: &Path, cache_dir: &Path) -> Result<String> {
+    let cid = unixfs::compute_file_cid(temp_path)?;
+    cache_ciphertext(temp_path, cache_dir, &cid)?;
+
+    let mut pending = PendingUploads::load(cache_dir)?;
+    pending.push(PendingUpload {
+        cid: cid.clone(),
+        ciphertext_path: cached_ciphertext_path(cache_dir, &cid),
+        queued_at: now_secs(),
+        attempts: 0,
+        next_attempt_at: 0,
+        last_error: None,
+    });
+    pending.save()?;
+
+    Ok(cid)
+}
+
+fn finish_staged_upload(cache_dir: &Path, cid: &str) -> Result<()> {
+    let mut pending = PendingUploads::load(cache_dir)?;
+    pending.remove(cid);
+    pending.save()
+}
+
+fn fail_staged_upload(cache_dir: &Path, cid: &str, error: &anyhow::Error) -> Result<()> {
+    let mut pending = PendingUploads::load(cache_dir)?;
+    pending.record_failure(cid, &format!("{:#}", error));
+    pending.save()
+}
+
+/// Whether a drop's ciphertext is still waiting in the upload queue
+pub fn is_upload_pending(options: &DropOptions, cid: &str) -> bool {
+    options
+        .cache_dir
+        .as_ref()
+        .and_then(|cache_dir| PendingUploads::load(cache_dir).ok())
+        .is_some_and(|pending| pending.contains(cid))
+}
+
+/// Upload staged ciphertext that has not reached IPFS yet, waiting for the
+/// upload window. Entries backing off after a failure are skipped until due.
+/// Returns the CIDs uploaded.
+pub async fn upload_pending(options: &DropOptions) -> Result<Vec<String>> {
+    let cache_dir = options.cache_dir.as_ref().context("Drop cache directory missing")?;
+    let pending = PendingUploads::load(cache_dir)?;
+    let now = now_secs();
+
+    let mut uploaded = Vec::new();
+    for entry in pending.entries() {
+        if entry.next_attempt_at > now {
+            continue;
+        }
+        if !upload_schedule::claim(&entry.cid) {
+            continue; // Already being uploaded by create_drop
+        }
+
+        options.upload_schedule.wait_for_window().await;
+        let result = upload_file_to_ipfs(&entry.ciphertext_path, options).await;
+        upload_schedule::release(&entry.cid);
+
+        match result {
+            Ok(cid) if cid == entry.cid => {
+                finish_staged_upload(cache_dir, &cid)?;
+                info!("Uploaded queued drop {}", cid);
+                uploaded.push(cid);
+            }
+            Ok(cid) => {
+                let e = anyhow::anyhow!("Upload produced CID {}", cid);
+                warn!("Queued upload {} failed: {}", entry.cid, e);
+                fail_staged_upload(cache_dir, &entry.cid, &e)?;
+            }
+            Err(e) => {
+                warn!("Queued upload {} failed: {:#}", entry.cid, e);
+                fail_staged_upload(cache_dir, &entry.cid, &e)?;
+            }
+        }
+    }
 
-    Ok(total_encrypted)
+    Ok(uploaded)
+}
+
+/// Copy the encrypted temp file into the drop cache under its CID
+fn cache_ciphertext(temp_path: &Path, cache_dir: &Path, cid: &str) -> Result<()> {
+    std::fs::create_dir_all(cache_dir).context("Failed to create drop cache directory")?;
+    std::fs::copy(temp_path, cached_ciphertext_path(cache_dir, cid))
+        .context("Failed to copy ciphertext into cache")?;
+    Ok(())
+}
+
+/// Stream encrypt a file in chunks to avoid loading entire file into RAM.
+/// A reader thread keeps up to `in_flight_buffers` chunks queued ahead of the cipher;
+/// large files are mapped instead when `options.mmap` is set.
+/// Returns the total encrypted size
+pub fn stream_encrypt_file(
+    input_path: &str,
+    output_path: &Path,
+    session_key: &SessionKey,
+    options: &DropOptions,
+) -> Result<u64> {
+    let input_file = File::open(input_path).context("Failed to open input file")?;
+    let total_bytes = input_file.metadata().map(|m| m.len()).unwrap_or(0);
+    if mapped_io::use_mmap(options, total_bytes) {
+        return mapped_io::encrypt_mapped(&input_file, output_path, session_key, options);
+    }
+    stream_encrypt_reader(input_file, total_bytes, output_path, session_key, options)
+}
+
+/// Stream encrypt from any reader, e.g. a buffer or stdin, so plaintext that
+/// never was a file isn't written to disk. `total_bytes` is only used for
+/// progress and may be 0 if unknown.
+pub fn stream_encrypt_reader<R: Read + Send>(
+    input: R,
+    total_bytes: u64,
+    output_path: &Path,
+    session_key: &SessionKey,
+    options: &DropOptions,
+) -> Result<u64> {
+    let mut reader = BufReader::new(input);
+
+    let output_file = File::create(output_path).context("Failed to create output file")?;
+    let mut writer = BufWriter::new(output_file);
+
+    let chunk_size = options.chunk_size;
+    let (chunk_tx, chunk_rx) =
+        std::sync::mpsc::sync_channel::<std::io::Result<Vec<u8>>>(options.in_flight_buffers);
+
+    std::thread::scope(|scope| {
+        // Reader: fill chunks and queue them (blocks when the queue is full)
+        scope.spawn(move || loop {
+            let mut chunk_buffer = vec![0u8; chunk_size];
+            match reader.read(&mut chunk_buffer) {
+                Ok(0) => break, // EOF
+                Ok(bytes_read) => {
+                    chunk_buffer.truncate(bytes_read);
+                    if chunk_tx.send(Ok(chunk_buffer)).is_err() {
+                        break; // Encryptor gave up
+                    }
+                }
+                Err(e) => {
+                    let _ = chunk_tx.send(Err(e));
+                    break;
+                }
+            }
+        });
+
+        let mut total_read = 0u64;
+        let mut total_encrypted = 0u64;
+
+        for chunk in chunk_rx {
+            let mut chunk_data = chunk.context("Failed to read chunk")?;
+
+            // Encrypt chunk
+            let encrypted_chunk = session_key
+                .encrypt_chunk(options.cipher, &chunk_data)
+                .context("Failed to encrypt chunk")?;
+            total_read += chunk_data.len() as u64;
+            chunk_data.zeroize();
+
+            // Write encrypted chunk size and cipher (4 bytes) + encrypted data
+            writer
+                .write_all(&chunk_prefix(options.cipher, encrypted_chunk.len()))
+                .context("Failed to write chunk size")?;
+            writer
+                .write_all(&encrypted_chunk)
+                .context("Failed to write encrypted chunk")?;
+
+            total_encrypted += 4 + encrypted_chunk.len() as u64;
+
+            options.report("encrypting", total_read, total_bytes);
+
+            // Progress indicator for large files
+            if total_encrypted % (50 * 1024 * 1024) == 0 {
+                info!("Encrypted {} MB...", total_encrypted / (1024 * 1024));
+            }
+        }
+
+        writer.flush().context("Failed to flush output")?;
+
+        Ok(total_encrypted)
+    })
 }
 
 /// Stream decrypt a file in chunks to avoid loading entire file into RAM
+/// (mapped when `options.mmap` is set and the file is large).
 /// Returns the total decrypted size
-fn stream_decrypt_file(
+pub fn stream_decrypt_file(
     input_path: &Path,
     output_path: &str,
     session_key: &SessionKey,
+    options: &DropOptions,
+) -> Result<u64> {
+    let input_len = std::fs::metadata(input_path).map_or(0, |m| m.len());
+    if mapped_io::use_mmap(options, input_len) {
+        return mapped_io::decrypt_mapped(input_path, output_path, session_key, options);
+    }
+    let output_file = File::create(output_path).context("Failed to create output file")?;
+    stream_decrypt_to(input_path, BufWriter::new(output_file), session_key, options)
+}
+
+/// Stream decrypt into any writer, e.g. a capped in-memory buffer
+pub fn stream_decrypt_to<W: Write>(
+    input_path: &Path,
+    mut writer: W,
+    session_key: &SessionKey,
+    options: &DropOptions,
 ) -> Result<u64> {
     let input_file = File::open(input_path).context("Failed to open encrypted file")?;
+    let total_bytes = input_file.metadata().map(|m| m.len()).unwrap_or(0);
     let mut reader = BufReader::new(input_file);
 
-    let output_file = File::create(output_path).context("Failed to create output file")?;
-    let mut writer = BufWriter::new(output_file);
-
+    let mut total_read = 0u64;
     let mut total_decrypted = 0u64;
-    let mut size_buffer = [0u8; 4];
 
     loop {
         // Read chunk size
-        match reader.read_exact(&mut size_buffer) {
-            Ok(_) => {}
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break, // EOF
-            Err(e) => return Err(e).context("Failed to read chunk size"),
-        }
+        let Some(size_buffer) = read_chunk_prefix(&mut reader)? else {
+            break; // EOF
+        };
 
-        let chunk_size = u32::from_le_bytes(size_buffer) as usize;
+        let (cipher, chunk_size) = parse_chunk_prefix(size_buffer)?;
 
         // Read encrypted chunk
         let mut encrypted_chunk = vec![0u8; chunk_size];
@@ -234,7 +1190,7 @@ fn stream_decrypt_file(
 
         // Decrypt chunk
         let decrypted_chunk = session_key
-            .decrypt_file(&encrypted_chunk)
+            .decrypt_chunk(cipher, &encrypted_chunk)
             .context("Failed to decrypt chunk")?;
 
         // Write decrypted data
@@ -243,10 +1199,13 @@ fn stream_decrypt_file(
             .context("Failed to write decrypted chunk")?;
 
         total_decrypted += decrypted_chunk.len() as u64;
+        total_read += 4 + chunk_size as u64;
+
+        options.report("decrypting", total_read, total_bytes);
 
         // Progress indicator for large files
         if total_decrypted % (50 * 1024 * 1024) == 0 {
-            println!("Decrypted {} MB...", total_decrypted / (1024 * 1024));
+            info!("Decrypted {} MB...", total_decrypted / (1024 * 1024));
         }
     }
 
@@ -255,41 +1214,126 @@ fn stream_decrypt_file(
     Ok(total_decrypted)
 }
 
-/// Upload file to IPFS using streaming (avoids loading entire file into RAM)
-async fn upload_file_to_ipfs(file_path: &Path) -> Result<String> {
-    let client = reqwest::Client::new();
+/// In-memory output that refuses to grow past a cap
+struct CappedBuffer {
+    data: Vec<u8>,
+    cap: u64,
+}
+
+impl Write for CappedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.data.len() as u64 + buf.len() as u64 > self.cap {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "Drop is larger than the memory limit",
+            ));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for CappedBuffer {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
+}
+
+/// Retrieve a plain drop into memory so a note or image can be shown without
+/// writing the plaintext to disk. Fails if it would exceed `max_size` bytes.
+pub async fn retrieve_dead_drop_to_memory(
+    cid: &str,
+    shard_strings: Vec<String>,
+    max_size: u64,
+    options: &DropOptions,
+) -> Result<Vec<u8>> {
+    if drop_manifest_cid(cid).is_some() {
+        anyhow::bail!("Only plain drops can be retrieved into memory");
+    }
+    // Refuse early when even the smallest chunk size leaves too much plaintext
+    let client = ipfs::client()?;
+    if let Ok((size, _)) = object_size(&client, cid).await {
+        let chunks = size.div_ceil(MIN_CHUNK_SIZE as u64 + CHUNK_OVERHEAD);
+        if size.saturating_sub(chunks * CHUNK_OVERHEAD) > max_size {
+            anyhow::bail!("Drop is larger than the memory limit");
+        }
+    }
+
+    let session_key = recover_session_key(&shard_strings)?;
+    let temp_file = options.staging_file()?;
+    download_file_from_ipfs(cid, temp_file.path(), options).await?;
+
+    let mut buffer = CappedBuffer {
+        data: Vec::new(),
+        cap: max_size,
+    };
+    stream_decrypt_to(temp_file.path(), &mut buffer, &session_key, options)
+        .context("Failed to decrypt drop")?;
+    info!("Decrypted {} bytes into memory", buffer.data.len());
+    Ok(std::mem::take(&mut buffer.data))
+}
+
+/// Upload file to IPFS as a streaming multipart body (never buffers the whole file)
+pub async fn upload_file_to_ipfs(file_path: &Path, options: &DropOptions) -> Result<String> {
+    let client = ipfs::client()?;
+
+    // Compute the CID ourselves so the node can't substitute different content
+    let expected_cid = unixfs::compute_file_cid(file_path)?;
 
     // Open file for streaming
     let file = tokio::fs::File::open(file_path)
         .await
         .context("Failed to open file for upload")?;
 
-    let _file_size = file
+    let file_size = file
         .metadata()
         .await
         .context("Failed to get file metadata")?
         .len();
 
-    // Create async reader
-    let mut reader = tokio::io::BufReader::new(file);
-    let mut buffer = Vec::new();
-
-    // Read entire file into buffer (for multipart upload)
-    // Note: For truly massive files, we'd need to implement chunked IPFS upload
-    // which requires using IPFS's chunking API directly
-    reader
-        .read_to_end(&mut buffer)
-        .await
-        .context("Failed to read file")?;
+    // Stream the file in chunk_size pieces so only one chunk is in RAM at a time
+    let reader = tokio::io::BufReader::new(file);
+    let throttle = Throttle::new(options.upload_schedule.max_bytes_per_sec);
+    let upload_options = options.clone();
+    let stream = futures::stream::try_unfold(
+        (reader, 0u64, throttle),
+        move |(mut reader, uploaded, mut throttle)| {
+            let options = upload_options.clone();
+            async move {
+                // Pause mid-upload if the upload window closes
+                if !options.upload_schedule.in_window() {
+                    options.report("paused", uploaded, file_size);
+                    options.upload_schedule.wait_for_window().await;
+                    throttle.reset();
+                }
+
+                let mut chunk = vec![0u8; options.chunk_size];
+                let bytes_read = reader.read(&mut chunk).await?;
+                if bytes_read == 0 {
+                    return Ok::<_, std::io::Error>(None); // EOF
+                }
+                chunk.truncate(bytes_read);
+                throttle.pace(bytes_read as u64).await;
+                let uploaded = uploaded + bytes_read as u64;
+                options.report("uploading", uploaded, file_size);
+                Ok(Some((bytes::Bytes::from(chunk), (reader, uploaded, throttle))))
+            }
+        },
+    );
 
-    let part = multipart::Part::bytes(buffer.to_vec())
+    let part = multipart::Part::stream_with_length(reqwest::Body::wrap_stream(stream), file_size)
         .file_name("encrypted_file")
         .mime_str("application/octet-stream")?;
 
     let form = multipart::Form::new().part("file", part);
 
     let response = client
-        .post(format!("{}/add", IPFS_API_URL))
+        .post(ipfs::api("add"))
+        .query(&unixfs::add_params())
         .multipart(form)
         .send()
         .await
@@ -305,15 +1349,72 @@ async fn upload_file_to_ipfs(file_path: &Path) -> Result<String> {
         .context("No Hash in IPFS response")?
         .to_string();
 
+    unixfs::verify_cid(&expected_cid, &cid)?;
+
     Ok(cid)
 }
 
-/// Download file from IPFS by CID (streaming to disk)
-async fn download_file_from_ipfs(cid: &str, output_path: &Path) -> Result<()> {
-    let client = reqwest::Client::new();
+/// Upload a small in-memory blob to IPFS
+pub async fn upload_bytes_to_ipfs(data: Vec<u8>) -> Result<String> {
+    let client = ipfs::client()?;
+    let expected_cid = unixfs::compute_bytes_cid(&data);
+
+    let part = multipart::Part::bytes(data)
+        .file_name("blob")
+        .mime_str("application/octet-stream")?;
+    let form = multipart::Form::new().part("file", part);
+
+    let response = client
+        .post(ipfs::api("add"))
+        .query(&unixfs::add_params())
+        .multipart(form)
+        .send()
+        .await
+        .context("Failed to upload to IPFS")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("IPFS upload failed: {}", response.status());
+    }
+
+    let json: serde_json::Value = response.json().await?;
+    let cid = json["Hash"]
+        .as_str()
+        .context("No Hash in IPFS response")?
+        .to_string();
+
+    unixfs::verify_cid(&expected_cid, &cid)?;
+
+    Ok(cid)
+}
+
+/// Fetch a small blob from IPFS into memory
+pub async fn cat_bytes_from_ipfs(path: &str) -> Result<Vec<u8>> {
+    let client = ipfs::client()?;
 
     let response = client
-        .post(format!("{}/cat?arg={}", IPFS_API_URL, cid))
+        .post(ipfs::api("cat"))
+        .query(&[("arg", path)])
+        .send()
+        .await
+        .context("Failed to download from IPFS")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("IPFS download failed: {}", response.status());
+    }
+
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Download file from IPFS by CID (streaming to disk)
+pub async fn download_file_from_ipfs(cid: &str, output_path: &Path, options: &DropOptions) -> Result<()> {
+    let client = ipfs::client()?;
+
+    let mut request = client.post(ipfs::api("cat")).query(&[("arg", cid)]);
+    if options.offline {
+        request = request.query(&[("offline", "true")]);
+    }
+
+    let response = request
         .send()
         .await
         .context("Failed to download from IPFS")?;
@@ -327,6 +1428,7 @@ async fn download_file_from_ipfs(cid: &str, output_path: &Path) -> Result<()> {
         .await
         .context("Failed to create output file")?;
 
+    let content_length = response.content_length().unwrap_or(0);
     let mut stream = response.bytes_stream();
     let mut total_downloaded = 0u64;
 
@@ -337,10 +1439,11 @@ async fn download_file_from_ipfs(cid: &str, output_path: &Path) -> Result<()> {
             .context("Failed to write chunk to file")?;
 
         total_downloaded += chunk.len() as u64;
+        options.report("downloading", total_downloaded, content_length);
 
         // Progress indicator
         if total_downloaded % (50 * 1024 * 1024) == 0 {
-            println!("Downloaded {} MB...", total_downloaded / (1024 * 1024));
+            info!("Downloaded {} MB...", total_downloaded / (1024 * 1024));
         }
     }
 
@@ -348,7 +1451,290 @@ async fn download_file_from_ipfs(cid: &str, output_path: &Path) -> Result<()> {
         .await
         .context("Failed to flush file")?;
 
-    println!("Downloaded {} bytes total", total_downloaded);
+    info!("Downloaded {} bytes total", total_downloaded);
+
+    // CIDv1 drops were added with known parameters, so the content can be
+    // checked against the CID; legacy CIDv0 drops are trusted as before
+    if cid.starts_with('b') {
+        let actual_cid = unixfs::compute_file_cid(output_path)?;
+        if actual_cid != cid {
+            anyhow::bail!("Downloaded content does not match CID {}", cid);
+        }
+    }
 
     Ok(())
 }
+
+/// Check whether a CID is recursively pinned on the local node
+pub async fn is_pinned_locally(client: &reqwest::Client, cid: &str) -> Result<bool> {
+    let response = client
+        .post(ipfs::api("pin/ls"))
+        .query(&[("arg", cid), ("type", "recursive")])
+        .send()
+        .await
+        .context("Failed to query IPFS pins")?;
+
+    // Kubo answers with an error status when the CID is not pinned
+    Ok(response.status().is_success())
+}
+
+/// List all blocks referenced by a CID that are reachable without network access
+async fn list_local_refs(client: &reqwest::Client, cid: &str) -> Result<Vec<String>> {
+    let response = client
+        .post(ipfs::api("refs"))
+        .query(&[
+            ("arg", cid),
+            ("recursive", "true"),
+            ("unique", "true"),
+            ("offline", "true"),
+        ])
+        .send()
+        .await
+        .context("Failed to list refs")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("IPFS refs failed: {}", response.status());
+    }
+
+    let body = response.text().await?;
+    let mut refs = Vec::new();
+    for line in body.lines().filter(|l| !l.trim().is_empty()) {
+        let entry: serde_json::Value = serde_json::from_str(line)?;
+        if let Some(err) = entry["Err"].as_str().filter(|e| !e.is_empty()) {
+            anyhow::bail!("{}", err);
+        }
+        if let Some(r) = entry["Ref"].as_str() {
+            refs.push(r.to_string());
+        }
+    }
+
+    Ok(refs)
+}
+
+/// Check whether a single block is present in the local blockstore
+async fn has_local_block(client: &reqwest::Client, cid: &str) -> bool {
+    match client
+        .post(ipfs::api("block/stat"))
+        .query(&[("arg", cid), ("offline", "true")])
+        .send()
+        .await
+    {
+        Ok(response) => response.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+/// Ask the DHT for peers providing a CID
+async fn find_providers(client: &reqwest::Client, cid: &str) -> Result<Vec<String>> {
+    let limit = FIND_PROVIDERS_LIMIT.to_string();
+    let response = client
+        .post(ipfs::api("routing/findprovs"))
+        .query(&[("arg", cid), ("num-providers", limit.as_str())])
+        .timeout(Duration::from_secs(GATEWAY_TIMEOUT_SECS))
+        .send()
+        .await
+        .context("Failed to query providers")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("IPFS findprovs failed: {}", response.status());
+    }
+
+    let body = response.text().await?;
+    let mut providers = Vec::new();
+    for line in body.lines().filter(|l| !l.trim().is_empty()) {
+        let entry: serde_json::Value = serde_json::from_str(line)?;
+        // Type 4 is a provider record
+        if entry["Type"].as_u64() != Some(4) {
+            continue;
+        }
+        if let Some(responses) = entry["Responses"].as_array() {
+            for peer in responses {
+                if let Some(id) = peer["ID"].as_str() {
+                    if !providers.iter().any(|p| p == id) {
+                        providers.push(id.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(providers)
+}
+
+/// Probe a public gateway for a CID with a HEAD request
+async fn check_gateway(client: &reqwest::Client, gateway: &str, cid: &str) -> GatewayStatus {
+    let result = client
+        .head(format!("{}/ipfs/{}", gateway, cid))
+        .timeout(Duration::from_secs(GATEWAY_TIMEOUT_SECS))
+        .send()
+        .await;
+
+    let (reachable, error) = match result {
+        Ok(response) if response.status().is_success() => (true, None),
+        Ok(response) => (false, Some(format!("HTTP {}", response.status()))),
+        Err(e) => (false, Some(e.to_string())),
+    };
+
+    GatewayStatus {
+        gateway: gateway.to_string(),
+        reachable,
+        error,
+    }
+}
+
+/// Recursively pin a CID on the local node
+pub async fn pin_cid(client: &reqwest::Client, cid: &str) -> Result<()> {
+    let response = client
+        .post(ipfs::api("pin/add"))
+        .query(&[("arg", cid), ("recursive", "true")])
+        .send()
+        .await
+        .context("Failed to pin on IPFS")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("IPFS pin failed: {}", response.status());
+    }
+
+    Ok(())
+}
+
+/// Remove a recursive pin from the local node
+async fn unpin_cid(client: &reqwest::Client, cid: &str) -> Result<()> {
+    let response = client
+        .post(ipfs::api("pin/rm"))
+        .query(&[("arg", cid), ("recursive", "true")])
+        .send()
+        .await
+        .context("Failed to unpin on IPFS")?;
+
+    if !response.status().is_success() {
+        let body = response.text().await.unwrap_or_default();
+        // Already unpinned is not an error for our purposes
+        if !body.contains("not pinned") {
+            anyhow::bail!("IPFS unpin failed: {}", body);
+        }
+    }
+
+    Ok(())
+}
+
+/// Run garbage collection on the local IPFS repo
+async fn run_repo_gc(client: &reqwest::Client) -> Result<()> {
+    let response = client
+        .post(ipfs::api("repo/gc"))
+        .send()
+        .await
+        .context("Failed to run IPFS repo GC")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("IPFS repo GC failed: {}", response.status());
+    }
+
+    // GC streams removed keys; drain the body so it runs to completion
+    response.bytes().await.context("IPFS repo GC interrupted")?;
+
+    Ok(())
+}
+
+/// Current size of the local IPFS repo in bytes
+async fn repo_size(client: &reqwest::Client) -> Result<u64> {
+    let response = client
+        .post(ipfs::api("repo/stat"))
+        .query(&[("size-only", "true")])
+        .send()
+        .await
+        .context("Failed to query IPFS repo stats")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("IPFS repo stat failed: {}", response.status());
+    }
+
+    let json: serde_json::Value = response.json().await?;
+    json["RepoSize"]
+        .as_u64()
+        .context("No RepoSize in IPFS response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn decrypt_bytes(ciphertext: &[u8], key: &SessionKey) -> Result<Vec<u8>> {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        let output = dir.path().join("output");
+        std::fs::write(&input, ciphertext).unwrap();
+        stream_decrypt_file(
+            &input,
+            output.to_str().unwrap(),
+            key,
+            &DropOptions::default(),
+        )?;
+        Ok(std::fs::read(&output).unwrap())
+    }
+
+    fn encrypt_bytes(plaintext: &[u8], key: &SessionKey, cipher: FileCipher) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        let output = dir.path().join("output");
+        std::fs::write(&input, plaintext).unwrap();
+        let options = DropOptions {
+            chunk_size: MIN_CHUNK_SIZE,
+            cipher,
+            ..DropOptions::default()
+        };
+        stream_encrypt_file(input.to_str().unwrap(), &output, key, &options).unwrap();
+        std::fs::read(&output).unwrap()
+    }
+
+    #[test]
+    fn truncated_chunk_prefix_is_rejected() {
+        let key = SessionKey::generate();
+        let mut ciphertext = encrypt_bytes(b"hello", &key, FileCipher::ChaCha20Poly1305);
+        ciphertext.extend_from_slice(&[0, 0]);
+
+        let err = decrypt_bytes(&ciphertext, &key).unwrap_err();
+        assert_eq!(err.to_string(), "Truncated chunk size");
+    }
+
+    proptest! {
+        #[test]
+        fn chunk_prefix_round_trips(aes in any::<bool>(), len in 0usize..=MAX_CHUNK_SIZE + 64) {
+            let cipher = if aes { FileCipher::Aes256Gcm } else { FileCipher::ChaCha20Poly1305 };
+            prop_assert_eq!(parse_chunk_prefix(chunk_prefix(cipher, len)).unwrap(), (cipher, len));
+        }
+
+        #[test]
+        fn parsing_any_chunk_prefix_never_panics(prefix in any::<[u8; 4]>()) {
+            if let Ok((_, len)) = parse_chunk_prefix(prefix) {
+                prop_assert!(len <= MAX_CHUNK_SIZE + 64);
+            }
+        }
+
+        #[test]
+        fn decrypting_garbage_fails_cleanly(data in proptest::collection::vec(any::<u8>(), 0..4096)) {
+            let key = SessionKey::generate();
+            // Only an empty file is a valid drop without the key
+            if let Ok(plaintext) = decrypt_bytes(&data, &key) {
+                prop_assert!(data.is_empty() && plaintext.is_empty());
+            }
+        }
+
+        #[test]
+        fn tampered_ciphertext_is_rejected(
+            aes in any::<bool>(),
+            plaintext in proptest::collection::vec(any::<u8>(), 1..2048),
+            position in any::<prop::sample::Index>(),
+            flip in 1u8..,
+        ) {
+            let cipher = if aes { FileCipher::Aes256Gcm } else { FileCipher::ChaCha20Poly1305 };
+            let key = SessionKey::generate();
+            let mut ciphertext = encrypt_bytes(&plaintext, &key, cipher);
+            prop_assert_eq!(decrypt_bytes(&ciphertext, &key).unwrap(), plaintext);
+
+            ciphertext[position.index(ciphertext.len())] ^= flip;
+            prop_assert!(decrypt_bytes(&ciphertext, &key).is_err());
+        }
+    }
+}
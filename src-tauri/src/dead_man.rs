@@ -0,0 +1,735 @@
+use crate::catalog::now_secs;
+use crate::crypto::{decode_public_key, Identity, SessionKey};
+use crate::dead_drop::{upload_bytes_to_ipfs, DeadDropCreated};
+use crate::devices::verifying_key;
+use crate::events;
+use crate::i18n::t_args;
+use crate::ipfs;
+use crate::message_drop::LINK_PREFIX;
+use crate::no_traces;
+use crate::p2p::{seal_p2p_message, GhostMessage, P2PCommand, P2PMessage};
+use crate::session::AppState;
+use crate::settings;
+use crate::unixfs::compute_bytes_cid;
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, Verifier};
+use libp2p::identity::Keypair;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::Manager;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+const DEAD_MAN_FILE: &str = "dead_man.enc";
+const KEY_NAME_PREFIX: &str = "control-dead-man-";
+const IPNS_LIFETIME: &str = "8760h"; // 1 year
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const MIN_INTERVAL_SECS: u64 = 60 * 60;
+const MAX_INTERVAL_SECS: u64 = 365 * 24 * 60 * 60;
+/// Reminders start this long before the deadline, or at a tenth of the
+/// interval for short ones
+const REMINDER_SECS: u64 = 24 * 60 * 60;
+
+/// Loads and saves of the switch file are serialized
+static STORE_LOCK: Mutex<()> = Mutex::new(());
+
+/// How a new switch releases its drop
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SwitchConfig {
+    pub name: String,
+    /// The drop is released unless checked in within this long
+    pub interval_secs: u64,
+    /// Contacts who each get a shard over Ghost Mode on release
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// Publish every shard under the switch's IPNS name on release, so
+    /// anyone who knows the name can open the drop
+    #[serde(default)]
+    pub publish: bool,
+    /// Sent along with the shards
+    #[serde(default)]
+    pub note: String,
+}
+
+impl SwitchConfig {
+    /// Check the config and return how many shards the drop needs
+    pub fn shard_count(&self, threshold: u8) -> Result<u8> {
+        if self.name.trim().is_empty() {
+            anyhow::bail!("A switch needs a name");
+        }
+        if !(MIN_INTERVAL_SECS..=MAX_INTERVAL_SECS).contains(&self.interval_secs) {
+            anyhow::bail!(
+                "Check-in interval must be between {} and {} seconds",
+                MIN_INTERVAL_SECS,
+                MAX_INTERVAL_SECS
+            );
+        }
+        let mut seen = HashSet::new();
+        for recipient in &self.recipients {
+            decode_public_key(recipient).context("Invalid recipient public ID")?;
+            if !seen.insert(recipient) {
+                anyhow::bail!("Recipient {} is listed twice", recipient);
+            }
+        }
+        if !self.publish && self.recipients.len() < threshold as usize {
+            anyhow::bail!(
+                "{} recipients can't reach the threshold of {}",
+                self.recipients.len(),
+                threshold
+            );
+        }
+        u8::try_from(self.recipients.len().max(threshold as usize)).context("Too many recipients")
+    }
+}
+
+/// A check-in: the owner was here at `at`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CheckIn {
+    pub switch_id: String,
+    pub at: u64,
+}
+
+/// What a switch publishes on release, signed when the switch is armed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReleaseRecord {
+    pub owner: String,
+    pub name: String,
+    pub cid: String,
+    pub threshold: u8,
+    pub note: String,
+    pub armed_at: u64,
+    /// Message links, one per recipient, each opening only for them
+    pub links: BTreeMap<String, String>,
+    /// Every shard, if the switch publishes them
+    pub shards: Vec<String>,
+}
+
+/// A statement signed with the owner's ed25519 key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedStatement {
+    /// JSON, signed byte for byte
+    pub statement: String,
+    pub signature: String,   // hex
+    pub signing_key: String, // hex
+}
+
+impl SignedStatement {
+    fn sign(identity: &Identity, statement: &impl Serialize) -> Result<Self> {
+        let statement = serde_json::to_string(statement)?;
+        let signing_key = identity.signing_key();
+        let signature = signing_key.sign(statement.as_bytes());
+        Ok(Self {
+            statement,
+            signature: hex::encode(signature.to_bytes()),
+            signing_key: hex::encode(signing_key.verifying_key().to_bytes()),
+        })
+    }
+
+    /// Check the signature is by `owner_key` and return what was signed
+    fn verify<T: DeserializeOwned>(&self, owner_key: &str) -> Result<T> {
+        if self.signing_key != owner_key {
+            anyhow::bail!("Signed with another key than the owner's");
+        }
+        let key = verifying_key(&self.signing_key)?;
+        let signature = hex::decode(&self.signature).context("Invalid signature")?;
+        let signature = Signature::from_slice(&signature).context("Invalid signature")?;
+        key.verify(self.statement.as_bytes(), &signature)
+            .context("Signature does not match")?;
+        serde_json::from_str(&self.statement).context("Invalid signed statement")
+    }
+}
+
+/// The signed release record as the store keeps it
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredRecord {
+    Plain(SignedStatement),
+    /// A record with every shard in it, encrypted with the identity; the
+    /// settings key only obfuscates the store. Hex.
+    Sealed {
+        sealed: String,
+    },
+}
+
+impl StoredRecord {
+    fn new(identity: &Identity, signed: SignedStatement, publish: bool) -> Result<Self> {
+        if !publish {
+            return Ok(StoredRecord::Plain(signed));
+        }
+        let mut json = serde_json::to_vec(&signed)?;
+        let sealed = identity.storage_key(b"dead_man").encrypt_file(&json);
+        json.zeroize();
+        Ok(StoredRecord::Sealed {
+            sealed: hex::encode(sealed?),
+        })
+    }
+
+    /// The signed record, or `None` while a sealed one waits for the
+    /// identity to be unlocked
+    fn open(&self, identity: Option<&Identity>) -> Result<Option<SignedStatement>> {
+        match self {
+            StoredRecord::Plain(signed) => Ok(Some(signed.clone())),
+            StoredRecord::Sealed { sealed } => {
+                let Some(identity) = identity else {
+                    return Ok(None);
+                };
+                let mut json = identity
+                    .storage_key(b"dead_man")
+                    .decrypt_file(&hex::decode(sealed).context("Invalid release record")?)
+                    .context("Failed to decrypt release record")?;
+                let signed = serde_json::from_slice(&json);
+                json.zeroize();
+                Ok(Some(signed.context("Invalid release record")?))
+            }
+        }
+    }
+}
+
+/// A released switch
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Release {
+    pub released_at: u64,
+    /// CID of the signed release record the IPNS name points to
+    pub record_cid: String,
+    /// Recipients whose shard went out over Ghost Mode; the others can
+    /// still open their message link
+    pub delivered: Vec<String>,
+}
+
+/// A switch as the app shows it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadManSwitch {
+    pub id: String,
+    pub name: String,
+    pub cid: String,
+    pub threshold: u8,
+    pub recipients: Vec<String>,
+    pub publish: bool,
+    pub interval_secs: u64,
+    pub created_at: u64,
+    /// Time of the last check-in whose signature holds
+    pub last_check_in: u64,
+    pub deadline: u64,
+    /// IPNS name the release record is published under; can be shared
+    /// ahead of time, as nothing resolves before the release
+    pub ipns_name: String,
+    pub release: Option<Release>,
+}
+
+/// A switch with what it releases: shards sealed to each recipient, the
+/// signed release record and the latest signed check-in
+#[derive(Serialize, Deserialize)]
+struct StoredSwitch {
+    #[serde(flatten)]
+    switch: DeadManSwitch,
+    owner_key: String, // hex
+    check_in: SignedStatement,
+    record: StoredRecord,
+    /// Recipient -> sealed Ghost Mode envelope, hex
+    envelopes: BTreeMap<String, String>,
+}
+
+impl StoredSwitch {
+    /// Seal each recipient's shard and sign the release record. `config`
+    /// must have passed `shard_count`.
+    fn build(
+        identity: &Identity,
+        config: &SwitchConfig,
+        threshold: u8,
+        created: &DeadDropCreated,
+        now: u64,
+    ) -> Result<Self> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut envelopes = BTreeMap::new();
+        let mut links = BTreeMap::new();
+        for (recipient, shard) in config.recipients.iter().zip(&created.shards) {
+            let content = t_args(
+                "dead_man.release_message",
+                &[
+                    ("name", config.name.as_str()),
+                    ("cid", created.cid.as_str()),
+                    ("shard", shard.as_str()),
+                    ("note", config.note.as_str()),
+                ],
+            );
+            let message = GhostMessage {
+                id: uuid::Uuid::new_v4().to_string(),
+                from: identity.public_id(),
+                content,
+                timestamp: now,
+                view_once: false,
+                location: None,
+            };
+            let envelope = seal_p2p_message(
+                identity,
+                &decode_public_key(recipient)?,
+                &P2PMessage::Message(message),
+            )?;
+            // The link is known now; the envelope is only uploaded on release
+            links.insert(
+                recipient.clone(),
+                format!("{}{}", LINK_PREFIX, compute_bytes_cid(&envelope)),
+            );
+            envelopes.insert(recipient.clone(), hex::encode(envelope));
+        }
+
+        let record = ReleaseRecord {
+            owner: identity.public_id(),
+            name: config.name.clone(),
+            cid: created.cid.clone(),
+            threshold,
+            note: config.note.clone(),
+            armed_at: now,
+            links,
+            shards: if config.publish {
+                created.shards.clone()
+            } else {
+                Vec::new()
+            },
+        };
+        let check_in = CheckIn {
+            switch_id: id.clone(),
+            at: now,
+        };
+
+        Ok(Self {
+            switch: DeadManSwitch {
+                id,
+                name: config.name.clone(),
+                cid: created.cid.clone(),
+                threshold,
+                recipients: config.recipients.clone(),
+                publish: config.publish,
+                interval_secs: config.interval_secs,
+                created_at: now,
+                last_check_in: now,
+                deadline: now + config.interval_secs,
+                // Set once the node holds the switch key
+                ipns_name: String::new(),
+                release: None,
+            },
+            owner_key: hex::encode(identity.signing_key().verifying_key().to_bytes()),
+            check_in: SignedStatement::sign(identity, &check_in)?,
+            record: StoredRecord::new(
+                identity,
+                SignedStatement::sign(identity, &record)?,
+                config.publish,
+            )?,
+            envelopes,
+        })
+    }
+
+    /// Recompute the deadline from the signed check-in; one that doesn't
+    /// verify counts as no check-in since the switch was armed
+    fn refresh(&mut self) {
+        let at = match self.check_in.verify::<CheckIn>(&self.owner_key) {
+            Ok(check_in) if check_in.switch_id == self.switch.id => check_in.at,
+            Ok(_) => {
+                warn!(
+                    "Check-in of switch {} is for another switch",
+                    self.switch.id
+                );
+                self.switch.created_at
+            }
+            Err(e) => {
+                warn!("Check-in of switch {} is invalid: {}", self.switch.id, e);
+                self.switch.created_at
+            }
+        };
+        self.switch.last_check_in = at;
+        self.switch.deadline = at + self.switch.interval_secs;
+    }
+}
+
+/// Switches, encrypted with the settings key rather than the identity so
+/// they still fire while the session is locked. Records that carry shards
+/// are sealed with the identity inside it (see `StoredRecord`).
+struct DeadManStore {
+    path: PathBuf,
+    key: SessionKey,
+    switches: Vec<StoredSwitch>,
+}
+
+impl DeadManStore {
+    fn load(data_dir: &Path) -> Result<Self> {
+        let path = data_dir.join(DEAD_MAN_FILE);
+        let key = settings::storage_key().context("Settings key unavailable")?;
+
+        let mut switches: Vec<StoredSwitch> = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read dead-man's switches")?
+        {
+            let mut json = key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt dead-man's switches")?;
+            let switches = serde_json::from_slice(&json);
+            json.zeroize();
+            switches.context("Failed to parse dead-man's switches")?
+        } else {
+            Vec::new()
+        };
+        switches.iter_mut().for_each(StoredSwitch::refresh);
+
+        Ok(Self {
+            path,
+            key,
+            switches,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut json = serde_json::to_vec(&self.switches)?;
+        let encrypted = self.key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?)
+            .context("Failed to write dead-man's switches")?;
+        Ok(())
+    }
+
+    fn list(&self) -> Vec<DeadManSwitch> {
+        self.switches.iter().map(|s| s.switch.clone()).collect()
+    }
+
+    fn get_mut(&mut self, id: &str) -> Option<&mut StoredSwitch> {
+        self.switches.iter_mut().find(|s| s.switch.id == id)
+    }
+}
+
+/// IPNS key of a switch, derived from the identity; the node holds it from
+/// arming on, so the release needs no unlocked identity
+fn switch_keypair(identity: &Identity, id: &str) -> Result<Keypair> {
+    let label = format!("dead-man-ipns:{}", id);
+    let mut seed = identity.storage_key(label.as_bytes()).as_bytes();
+    let keypair = Keypair::ed25519_from_bytes(&mut seed);
+    seed.zeroize();
+    keypair.context("Failed to derive switch key")
+}
+
+/// Arm a switch over a drop just created with `config.shard_count` shards.
+/// The shards only leave this device on release.
+pub async fn arm(
+    data_dir: &Path,
+    identity: &Identity,
+    config: &SwitchConfig,
+    threshold: u8,
+    created: &DeadDropCreated,
+) -> Result<DeadManSwitch> {
+    let mut stored = StoredSwitch::build(identity, config, threshold, created, now_secs())?;
+    let keypair = switch_keypair(identity, &stored.switch.id)?;
+    let key_name = format!("{}{}", KEY_NAME_PREFIX, stored.switch.id);
+    ipfs::ensure_key(&ipfs::client()?, &key_name, &keypair).await?;
+    stored.switch.ipns_name = keypair.public().to_peer_id().to_string();
+
+    let _guard = STORE_LOCK.lock().unwrap();
+    let mut store = DeadManStore::load(data_dir)?;
+    let switch = stored.switch.clone();
+    store.switches.push(stored);
+    store.save()?;
+    info!("Armed dead-man's switch {} over {}", switch.id, switch.cid);
+    Ok(switch)
+}
+
+/// Check in to one switch, or to every armed switch if `id` is unset.
+/// Returns the switches checked in to.
+pub fn check_in(
+    data_dir: &Path,
+    identity: &Identity,
+    id: Option<&str>,
+) -> Result<Vec<DeadManSwitch>> {
+    let _guard = STORE_LOCK.lock().unwrap();
+    let mut store = DeadManStore::load(data_dir)?;
+    let now = now_secs();
+
+    let mut checked = Vec::new();
+    for stored in store.switches.iter_mut() {
+        if id.is_some_and(|id| id != stored.switch.id) || stored.switch.release.is_some() {
+            continue;
+        }
+        let check_in = CheckIn {
+            switch_id: stored.switch.id.clone(),
+            at: now,
+        };
+        stored.check_in = SignedStatement::sign(identity, &check_in)?;
+        stored.refresh();
+        // Switches armed before publish records were sealed get sealed now
+        if let StoredRecord::Plain(signed) = &stored.record {
+            stored.record = StoredRecord::new(identity, signed.clone(), stored.switch.publish)?;
+        }
+        checked.push(stored.switch.clone());
+    }
+    if id.is_some() && checked.is_empty() {
+        anyhow::bail!("No armed switch with that ID");
+    }
+    store.save()?;
+    Ok(checked)
+}
+
+pub fn list(data_dir: &Path) -> Result<Vec<DeadManSwitch>> {
+    let _guard = STORE_LOCK.lock().unwrap();
+    Ok(DeadManStore::load(data_dir)?.list())
+}
+
+/// Forget a switch and the sealed shards it holds. The drop itself stays
+/// pinned. False if there was no such switch.
+pub fn disarm(data_dir: &Path, id: &str) -> Result<bool> {
+    let _guard = STORE_LOCK.lock().unwrap();
+    let mut store = DeadManStore::load(data_dir)?;
+    let before = store.switches.len();
+    store.switches.retain(|s| s.switch.id != id);
+    if store.switches.len() == before {
+        return Ok(false);
+    }
+    store.save()?;
+    info!("Disarmed dead-man's switch {}", id);
+    Ok(true)
+}
+
+/// What a due switch uploads, copied out so the store isn't held meanwhile
+struct DueRelease {
+    id: String,
+    record: Vec<u8>,
+    envelopes: Vec<Vec<u8>>,
+}
+
+/// Release every switch past its deadline, then send undelivered shards
+/// over Ghost Mode if `p2p` is running. Switches that publish their shards
+/// wait for `identity`, as their record is sealed with it. Returns the
+/// switches released now.
+pub async fn release_due(
+    data_dir: &Path,
+    identity: Option<&Identity>,
+    p2p: Option<mpsc::Sender<P2PCommand>>,
+) -> Result<Vec<DeadManSwitch>> {
+    if !no_traces::file_exists(&data_dir.join(DEAD_MAN_FILE)) {
+        return Ok(Vec::new());
+    }
+    let now = now_secs();
+    let due = {
+        let _guard = STORE_LOCK.lock().unwrap();
+        let store = DeadManStore::load(data_dir)?;
+        let mut due = Vec::new();
+        for stored in &store.switches {
+            if stored.switch.release.is_some() || now < stored.switch.deadline {
+                continue;
+            }
+            let record = match stored.record.open(identity) {
+                Ok(Some(record)) => record,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Switch {} can't be released: {:#}", stored.switch.id, e);
+                    continue;
+                }
+            };
+            due.push(DueRelease {
+                id: stored.switch.id.clone(),
+                record: serde_json::to_vec(&record)?,
+                envelopes: stored
+                    .envelopes
+                    .values()
+                    .map(hex::decode)
+                    .collect::<Result<_, _>>()?,
+            });
+        }
+        due
+    };
+
+    let mut released = Vec::new();
+    for release in due {
+        warn!(
+            "Dead-man's switch {} missed its check-in, releasing",
+            release.id
+        );
+        match publish_release(&release).await {
+            Ok(record_cid) => {
+                let _guard = STORE_LOCK.lock().unwrap();
+                let mut store = DeadManStore::load(data_dir)?;
+                if let Some(stored) = store.get_mut(&release.id) {
+                    stored.switch.release = Some(Release {
+                        released_at: now_secs(),
+                        record_cid,
+                        delivered: Vec::new(),
+                    });
+                    released.push(stored.switch.clone());
+                }
+                store.save()?;
+            }
+            // Retried on the next check
+            Err(e) => warn!("Release of switch {} failed: {:#}", release.id, e),
+        }
+    }
+
+    if let Some(p2p) = p2p {
+        deliver(data_dir, &p2p).await?;
+    }
+    Ok(released)
+}
+
+/// Upload the envelopes and the signed record, then point the switch's
+/// IPNS name at the record
+async fn publish_release(release: &DueRelease) -> Result<String> {
+    for envelope in &release.envelopes {
+        upload_bytes_to_ipfs(envelope.clone()).await?;
+    }
+    let record_cid = upload_bytes_to_ipfs(release.record.clone()).await?;
+    let key_name = format!("{}{}", KEY_NAME_PREFIX, release.id);
+    ipfs::publish_name(&ipfs::client()?, &key_name, &record_cid, IPNS_LIFETIME)
+        .await
+        .context("Failed to publish release record")?;
+    Ok(record_cid)
+}
+
+/// Send the sealed shards of released switches to recipients who haven't
+/// had theirs yet
+async fn deliver(data_dir: &Path, p2p: &mpsc::Sender<P2PCommand>) -> Result<()> {
+    let undelivered: Vec<(String, String, Vec<u8>)> = {
+        let _guard = STORE_LOCK.lock().unwrap();
+        let store = DeadManStore::load(data_dir)?;
+        let mut undelivered = Vec::new();
+        for stored in &store.switches {
+            let Some(release) = &stored.switch.release else {
+                continue;
+            };
+            for (recipient, envelope) in &stored.envelopes {
+                if !release.delivered.contains(recipient) {
+                    undelivered.push((
+                        stored.switch.id.clone(),
+                        recipient.clone(),
+                        hex::decode(envelope)?,
+                    ));
+                }
+            }
+        }
+        undelivered
+    };
+    if undelivered.is_empty() {
+        return Ok(());
+    }
+
+    let mut delivered = Vec::new();
+    for (id, recipient, envelope) in undelivered {
+        let (reply, response) = oneshot::channel();
+        let command = P2PCommand::SendSealed {
+            recipient: recipient.clone(),
+            envelope,
+            reply,
+        };
+        if p2p.send(command).await.is_err() {
+            break;
+        }
+        match response.await {
+            Ok(Ok(())) => delivered.push((id, recipient)),
+            Ok(Err(e)) => warn!("Shard of switch {} not sent to {}: {}", id, recipient, e),
+            Err(_) => break,
+        }
+    }
+
+    let _guard = STORE_LOCK.lock().unwrap();
+    let mut store = DeadManStore::load(data_dir)?;
+    for (id, recipient) in delivered {
+        if let Some(release) = store.get_mut(&id).and_then(|s| s.switch.release.as_mut()) {
+            release.delivered.push(recipient);
+        }
+    }
+    store.save()
+}
+
+/// Check the switches every minute: remind the user ahead of a deadline
+/// and release the ones past it. Runs whether or not the session is
+/// unlocked, except for switches that publish their shards; shards go out
+/// over Ghost Mode when it is running.
+pub fn start_dead_man_scheduler(app: tauri::AppHandle, data_dir: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        // Switches reminded of since their last check-in
+        let mut reminded: HashSet<(String, u64)> = HashSet::new();
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+
+            let state = app.state::<AppState>();
+            let (identity, p2p) = (state.peek_identity(), state.p2p_sender().ok());
+            match release_due(&data_dir, identity.as_ref(), p2p).await {
+                Ok(released) => {
+                    for switch in released {
+                        events::emit(&app, "dead_man_released", switch);
+                    }
+                }
+                Err(e) => warn!("Dead-man's switches not checked: {:#}", e),
+            }
+
+            let Ok(switches) = list(&data_dir) else {
+                continue;
+            };
+            let now = now_secs();
+            for switch in switches.into_iter().filter(|s| s.release.is_none()) {
+                let lead = REMINDER_SECS.min(switch.interval_secs / 10);
+                let key = (switch.id.clone(), switch.last_check_in);
+                if now + lead >= switch.deadline && reminded.insert(key) {
+                    events::emit(&app, "dead_man_check_in_due", switch);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_ins_move_the_deadline_only_when_signed_by_the_owner() {
+        let identity = Identity::generate();
+        let config = SwitchConfig {
+            name: "Archive".to_string(),
+            interval_secs: MIN_INTERVAL_SECS,
+            recipients: vec![Identity::generate().public_id()],
+            publish: true,
+            note: String::new(),
+        };
+        assert!(SwitchConfig {
+            publish: false,
+            ..config.clone()
+        }
+        .shard_count(2)
+        .is_err());
+        assert_eq!(config.shard_count(2).unwrap(), 2);
+
+        let created = DeadDropCreated {
+            cid: "bafytest".to_string(),
+            shards: vec!["shard-a".to_string(), "shard-b".to_string()],
+            drop_id: None,
+            piece_cids: Vec::new(),
+            shred: None,
+            cipher: None,
+            pending: false,
+        };
+        let mut stored = StoredSwitch::build(&identity, &config, 2, &created, 1_000).unwrap();
+        assert_eq!(stored.switch.deadline, 1_000 + MIN_INTERVAL_SECS);
+        // The shards are only readable with the identity
+        assert!(stored.record.open(None).unwrap().is_none());
+        assert!(stored.record.open(Some(&Identity::generate())).is_err());
+        let record: ReleaseRecord = stored
+            .record
+            .open(Some(&identity))
+            .unwrap()
+            .unwrap()
+            .verify(&stored.owner_key)
+            .unwrap();
+        assert_eq!(record.shards.len(), 2);
+        assert!(record.links[&config.recipients[0]].starts_with(LINK_PREFIX));
+
+        let later = CheckIn {
+            switch_id: stored.switch.id.clone(),
+            at: 5_000,
+        };
+        stored.check_in = SignedStatement::sign(&identity, &later).unwrap();
+        stored.refresh();
+        assert_eq!(stored.switch.deadline, 5_000 + MIN_INTERVAL_SECS);
+
+        // A check-in by anyone else doesn't count
+        stored.check_in = SignedStatement::sign(&Identity::generate(), &later).unwrap();
+        stored.refresh();
+        assert_eq!(stored.switch.last_check_in, 1_000);
+    }
+}
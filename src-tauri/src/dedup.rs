@@ -0,0 +1,318 @@
+use crate::crypto::{FileCipher, Identity, SessionKey};
+use crate::dead_drop::{
+    build_drop_stat, cat_bytes_from_ipfs, is_pinned_locally, probe_download_speed,
+    upload_bytes_to_ipfs, DeadDropCreated, DropOptions, DropStat,
+};
+use crate::ipfs;
+use crate::shards::{recover_session_key, split_session_key};
+use crate::unixfs;
+use crate::wire;
+use anyhow::{Context, Result};
+use fastcdc::v2020::StreamCDC;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+use zeroize::Zeroize;
+
+/// Drop references pointing at a dedup manifest carry this prefix
+pub const DEDUP_CID_PREFIX: &str = "cd:";
+const MIN_CHUNK: u32 = 256 * 1024;
+const AVG_CHUNK: u32 = 1024 * 1024;
+const MAX_CHUNK: u32 = 4 * 1024 * 1024;
+/// Nonce and tag around every sealed chunk
+const SEAL_OVERHEAD: u64 = 12 + 16;
+
+/// One content-defined chunk of a deduplicated drop
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DedupChunk {
+    pub cid: String,
+    /// Plaintext length
+    pub len: u64,
+    /// Convergent key of this chunk (hex)
+    pub key: String,
+}
+
+/// Layout of a deduplicated drop, stored on IPFS encrypted with the session
+/// key. Chunks are listed in file order and may repeat.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DedupManifest {
+    pub plaintext_len: u64,
+    pub chunks: Vec<DedupChunk>,
+}
+
+impl DedupManifest {
+    fn validate(&self) -> Result<()> {
+        let mut total = 0u64;
+        for chunk in &self.chunks {
+            if chunk.len > MAX_CHUNK as u64 {
+                anyhow::bail!("Chunk {} is larger than chunking allows", chunk.cid);
+            }
+            total += chunk.len;
+        }
+        if total != self.plaintext_len {
+            anyhow::bail!("Dedup manifest chunks do not add up to the file size");
+        }
+        Ok(())
+    }
+}
+
+/// Strip the dedup prefix from a drop reference, if present
+pub fn dedup_manifest_cid(cid: &str) -> Option<&str> {
+    cid.strip_prefix(DEDUP_CID_PREFIX)
+}
+
+/// Key for one chunk: the same whenever this identity stores the same bytes,
+/// so the sealed chunk and its CID repeat too. Other identities get other
+/// keys, so nobody can confirm a file by encrypting a guess of it.
+fn chunk_key(convergence_key: &SessionKey, chunk: &[u8]) -> SessionKey {
+    let mut secret = convergence_key.as_bytes();
+    let mut hasher = Sha256::new();
+    hasher.update(b"control-convergent-chunk");
+    hasher.update(secret);
+    hasher.update(Sha256::digest(chunk));
+    secret.zeroize();
+
+    let mut key_bytes: [u8; 32] = hasher.finalize().into();
+    let key = SessionKey::from_bytes(&key_bytes).expect("SHA-256 output is 32 bytes");
+    key_bytes.zeroize();
+    key
+}
+
+/// Create a deduplicated drop: the file is cut at content-defined boundaries
+/// (FastCDC) and every chunk is sealed with a key derived from its contents
+/// and our identity. Chunks already pinned from an earlier upload are not
+/// uploaded again, so re-dropping an edited file only sends what changed.
+pub async fn create_dedup_drop(
+    file_path: &str,
+    threshold: u8,
+    total_shards: u8,
+    identity: &Identity,
+    options: &DropOptions,
+) -> Result<DeadDropCreated> {
+    if threshold > total_shards {
+        anyhow::bail!("Threshold cannot exceed total shards");
+    }
+    if threshold < 2 {
+        anyhow::bail!("Threshold must be at least 2");
+    }
+
+    let file = File::open(file_path).context("Failed to open file")?;
+    let total_bytes = file.metadata()?.len();
+    let convergence_key = identity.storage_key(b"convergence");
+    let client = ipfs::client()?;
+
+    let mut chunks = Vec::new();
+    let mut piece_cids: Vec<String> = Vec::new();
+    let mut reused = 0;
+    let mut bytes_done = 0u64;
+    for chunk in StreamCDC::new(file, MIN_CHUNK, AVG_CHUNK, MAX_CHUNK) {
+        let mut chunk = chunk.context("Failed to read file")?;
+        let key = chunk_key(&convergence_key, &chunk.data);
+        let sealed = key.encrypt_convergent(&chunk.data);
+        chunk.data.zeroize();
+        let sealed = sealed?;
+
+        let expected_cid = unixfs::compute_bytes_cid(&sealed);
+        let cid = if piece_cids.contains(&expected_cid)
+            || is_pinned_locally(&client, &expected_cid)
+                .await
+                .unwrap_or(false)
+        {
+            reused += 1;
+            expected_cid
+        } else {
+            upload_bytes_to_ipfs(sealed).await?
+        };
+        if !piece_cids.contains(&cid) {
+            piece_cids.push(cid.clone());
+        }
+
+        let mut key_bytes = key.as_bytes();
+        chunks.push(DedupChunk {
+            cid,
+            len: chunk.length as u64,
+            key: hex::encode(key_bytes),
+        });
+        key_bytes.zeroize();
+
+        bytes_done += chunk.length as u64;
+        options.report("uploading", bytes_done, total_bytes);
+    }
+
+    let manifest = DedupManifest {
+        plaintext_len: bytes_done,
+        chunks,
+    };
+    let session_key = SessionKey::generate();
+    let mut manifest_bytes = wire::encode(&manifest)?;
+    let encrypted_manifest = session_key.encrypt_file(&manifest_bytes);
+    manifest_bytes.zeroize();
+    let manifest_cid = upload_bytes_to_ipfs(encrypted_manifest?).await?;
+
+    let shards = split_session_key(&session_key, threshold, total_shards);
+    drop(session_key);
+
+    info!(
+        "Created deduplicated drop {} ({} chunks, {} already stored)",
+        manifest_cid,
+        manifest.chunks.len(),
+        reused
+    );
+
+    Ok(DeadDropCreated {
+        cid: format!("{}{}", DEDUP_CID_PREFIX, manifest_cid),
+        shards,
+        drop_id: None,
+        piece_cids,
+        shred: None,
+        cipher: Some(FileCipher::ChaCha20Poly1305),
+        pending: false,
+    })
+}
+
+/// Decrypt and check the manifest of a deduplicated drop
+pub async fn fetch_dedup_manifest(
+    manifest_cid: &str,
+    session_key: &SessionKey,
+) -> Result<DedupManifest> {
+    let encrypted = cat_bytes_from_ipfs(manifest_cid).await?;
+    let mut manifest_bytes = session_key
+        .decrypt_file(&encrypted)
+        .context("Failed to decrypt dedup manifest - wrong shards?")?;
+    let manifest = wire::decode::<DedupManifest>(&manifest_bytes);
+    manifest_bytes.zeroize();
+    let manifest = manifest.context("Invalid dedup manifest")?;
+    manifest.validate()?;
+    Ok(manifest)
+}
+
+/// Retrieve a deduplicated drop by fetching its chunks in order
+pub async fn retrieve_dedup_drop(
+    manifest_cid: &str,
+    shard_strings: Vec<String>,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<()> {
+    let session_key = recover_session_key(&shard_strings)?;
+    let manifest = fetch_dedup_manifest(manifest_cid, &session_key).await?;
+    drop(session_key);
+
+    let result = reassemble(&manifest, output_path, options).await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(output_path).await;
+    }
+    let decrypted_size = result?;
+
+    info!(
+        "Decrypted {} bytes to {} ({} chunks)",
+        decrypted_size,
+        output_path,
+        manifest.chunks.len()
+    );
+    Ok(())
+}
+
+async fn reassemble(
+    manifest: &DedupManifest,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<u64> {
+    let output_file = tokio::fs::File::create(output_path)
+        .await
+        .context("Failed to create output file")?;
+    let mut writer = tokio::io::BufWriter::new(output_file);
+
+    let mut total_decrypted = 0u64;
+    for (i, chunk) in manifest.chunks.iter().enumerate() {
+        let sealed = cat_bytes_from_ipfs(&chunk.cid).await?;
+        let mut key_bytes = hex::decode(&chunk.key).context("Invalid chunk key")?;
+        let key = SessionKey::from_bytes(&key_bytes);
+        key_bytes.zeroize();
+
+        let mut plaintext = key?
+            .decrypt_file(&sealed)
+            .with_context(|| format!("Failed to decrypt chunk {}", i))?;
+        if plaintext.len() as u64 != chunk.len {
+            plaintext.zeroize();
+            anyhow::bail!("Chunk {} has the wrong length", i);
+        }
+        let written = writer.write_all(&plaintext).await;
+        plaintext.zeroize();
+        written.context("Failed to write decrypted chunk")?;
+
+        total_decrypted += chunk.len;
+        options.report("downloading", total_decrypted, manifest.plaintext_len);
+    }
+
+    writer.flush().await.context("Failed to flush output")?;
+    Ok(total_decrypted)
+}
+
+/// Preflight estimate for a deduplicated drop, sized from its manifest
+pub async fn stat_dedup_drop(
+    manifest_cid: &str,
+    shard_strings: Vec<String>,
+    output_path: &Path,
+    options: &DropOptions,
+) -> Result<DropStat> {
+    let session_key = recover_session_key(&shard_strings)?;
+    let manifest = fetch_dedup_manifest(manifest_cid, &session_key).await?;
+    drop(session_key);
+
+    let download_size = manifest
+        .chunks
+        .iter()
+        .map(|chunk| chunk.len + SEAL_OVERHEAD)
+        .sum();
+    let bytes_per_sec = match manifest.chunks.first() {
+        Some(chunk) => probe_download_speed(&ipfs::client()?, &chunk.cid).await,
+        None => None,
+    };
+
+    // Chunks are decrypted one at a time, so only the plaintext needs room
+    let mut stat = build_drop_stat(
+        &format!("{}{}", DEDUP_CID_PREFIX, manifest_cid),
+        download_size,
+        download_size,
+        "manifest".to_string(),
+        None,
+        bytes_per_sec,
+        output_path,
+        options,
+    );
+    stat.estimated_plaintext_size = manifest.plaintext_len;
+    stat.required_disk_space = manifest.plaintext_len;
+    stat.enough_disk_space = stat
+        .available_disk_space
+        .map(|free| free >= stat.required_disk_space);
+    Ok(stat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_converge_per_identity() {
+        let alice = Identity::generate().storage_key(b"convergence");
+        let bob = Identity::generate().storage_key(b"convergence");
+        let chunk = vec![7u8; 4096];
+
+        let first = chunk_key(&alice, &chunk)
+            .encrypt_convergent(&chunk)
+            .unwrap();
+        let again = chunk_key(&alice, &chunk)
+            .encrypt_convergent(&chunk)
+            .unwrap();
+        let other = chunk_key(&bob, &chunk).encrypt_convergent(&chunk).unwrap();
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+        assert_eq!(
+            chunk_key(&alice, &chunk).decrypt_file(&first).unwrap(),
+            chunk
+        );
+    }
+}
@@ -0,0 +1,517 @@
+use crate::catalog::now_secs;
+use crate::crypto::{decode_public_key, seal_to, Identity, SessionKey};
+use crate::no_traces;
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
+
+const DEVICES_FILE: &str = "devices.enc";
+const MAX_DEVICE_NAME_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceStatus {
+    /// Announced itself; waits for an active device to approve it
+    Pending,
+    Active,
+    /// Cut off: it holds no sync key from this epoch on
+    Revoked,
+}
+
+/// One device sharing the identity
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    /// Public ID of the device's own key
+    pub device_id: String,
+    pub name: String,
+    /// The device's ed25519 key, hex, for messages only it may send
+    pub signing_key: String,
+    pub status: DeviceStatus,
+    pub added_at: u64,
+    #[serde(default)]
+    pub revoked_at: Option<u64>,
+    /// Whether this is the device listing it; filled in when listing
+    #[serde(default, skip_deserializing)]
+    pub current: bool,
+}
+
+/// The sync key of the current epoch, sealed to one device
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WrappedKey {
+    pub ephemeral: String, // base58
+    pub sealed: String,    // hex
+}
+
+/// Every device of an identity, signed with the identity's key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeviceList {
+    pub owner: String,
+    /// The highest version wins when devices compare lists
+    pub version: u64,
+    pub issued_at: u64,
+    pub devices: Vec<Device>,
+    /// Goes up each time a revocation replaces the sync key
+    pub epoch: u64,
+    /// The sync key, by device ID, for active devices only
+    pub keys: BTreeMap<String, WrappedKey>,
+}
+
+/// Device list as carried in sync snapshots
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedDeviceList {
+    /// JSON of a `DeviceList`, signed byte for byte
+    pub statement: String,
+    pub signature: String, // hex
+}
+
+impl SignedDeviceList {
+    fn sign(identity: &Identity, list: &DeviceList) -> Result<Self> {
+        let statement = serde_json::to_string(list)?;
+        let signature = identity.signing_key().sign(statement.as_bytes());
+        Ok(Self {
+            statement,
+            signature: hex::encode(signature.to_bytes()),
+        })
+    }
+
+    /// Check that our identity signed it, and return the list
+    pub fn verify(&self, identity: &Identity) -> Result<DeviceList> {
+        let key = identity.signing_key().verifying_key();
+        let signature = hex::decode(&self.signature).context("Invalid device list signature")?;
+        let signature =
+            Signature::from_slice(&signature).context("Invalid device list signature")?;
+        key.verify(self.statement.as_bytes(), &signature)
+            .context("Device list signature does not match")?;
+        let list: DeviceList =
+            serde_json::from_str(&self.statement).context("Invalid device list")?;
+        if list.owner != identity.public_id() {
+            anyhow::bail!("Device list belongs to {}", list.owner);
+        }
+        Ok(list)
+    }
+}
+
+/// What this device announces to contacts when it revokes another
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeviceRevocation {
+    pub owner: String,
+    pub device_id: String,
+    pub name: String,
+    pub revoked_at: u64,
+}
+
+/// Revocation as sent over Ghost Mode
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedRevocation {
+    /// JSON of a `DeviceRevocation`, signed byte for byte
+    pub statement: String,
+    pub signature: String,   // hex
+    pub signing_key: String, // hex, the owner's ed25519 key
+}
+
+impl SignedRevocation {
+    /// Check the signature and return what was signed. Whether the owner is
+    /// who they claim is up to the caller, e.g. by the Ghost Mode sender key.
+    pub fn verify(&self) -> Result<DeviceRevocation> {
+        let key = verifying_key(&self.signing_key)?;
+        let signature = hex::decode(&self.signature).context("Invalid revocation signature")?;
+        let signature =
+            Signature::from_slice(&signature).context("Invalid revocation signature")?;
+        key.verify(self.statement.as_bytes(), &signature)
+            .context("Revocation signature does not match")?;
+        serde_json::from_str(&self.statement).context("Invalid revocation")
+    }
+}
+
+/// Parse an ed25519 key in hex, as in device lists and contact cards
+pub fn verifying_key(hex_key: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(hex_key)
+        .context("Invalid signing key")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Signing key has the wrong length"))?;
+    VerifyingKey::from_bytes(&bytes).context("Invalid signing key")
+}
+
+/// Outcome of revoking a device
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RevocationReport {
+    pub device: Device,
+    /// Whether the new list and sync key were published for the other devices
+    pub synced: bool,
+    /// Contacts told about the revocation
+    pub notified: Vec<String>,
+}
+
+/// This device's key and last known device list
+#[derive(Serialize, Deserialize)]
+struct DeviceState {
+    secret: String, // hex
+    name: String,
+    list: Option<SignedDeviceList>,
+}
+
+/// This device's own key and the identity's device list, encrypted with the
+/// local identity
+pub struct DeviceStore {
+    path: PathBuf,
+    storage_key: SessionKey,
+    device: Identity,
+    name: String,
+    list: Option<DeviceList>,
+    signed: Option<SignedDeviceList>,
+}
+
+impl DeviceStore {
+    /// Load the store, making this device's key the first time
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(DEVICES_FILE);
+        let storage_key = identity.storage_key(b"devices");
+
+        let state = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read devices")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt devices")?;
+            let state = serde_json::from_slice::<DeviceState>(&json);
+            json.zeroize();
+            Some(state.context("Failed to parse devices")?)
+        } else {
+            None
+        };
+
+        let (device, name, signed) = match state {
+            Some(mut state) => {
+                let mut secret: [u8; 32] = hex::decode(&state.secret)
+                    .context("Invalid device key")?
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Device key has the wrong length"))?;
+                state.secret.zeroize();
+                let device = Identity::from_secret_bytes(secret);
+                secret.zeroize();
+                (device, state.name, state.list)
+            }
+            None => (Identity::generate(), default_name(), None),
+        };
+        let list = signed.as_ref().map(|s| s.verify(identity)).transpose()?;
+
+        let store = Self {
+            path,
+            storage_key,
+            device,
+            name,
+            list,
+            signed,
+        };
+        if !no_traces::file_exists(&store.path) {
+            store.save()?;
+        }
+        Ok(store)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut secret = self.device.secret_bytes();
+        let mut state = DeviceState {
+            secret: hex::encode(secret),
+            name: self.name.clone(),
+            list: self.signed.clone(),
+        };
+        secret.zeroize();
+        let json = serde_json::to_vec(&state);
+        state.secret.zeroize();
+        let mut json = json?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write devices")?;
+        Ok(())
+    }
+
+    pub fn device_id(&self) -> String {
+        self.device.public_id()
+    }
+
+    /// This device's own key, for signing what only it may send
+    pub fn device_identity(&self) -> &Identity {
+        &self.device
+    }
+
+    /// Devices in the list, with this one marked
+    pub fn list(&self) -> Vec<Device> {
+        let device_id = self.device_id();
+        self.list
+            .iter()
+            .flat_map(|list| list.devices.iter().cloned())
+            .map(|mut device| {
+                device.current = device.device_id == device_id;
+                device
+            })
+            .collect()
+    }
+
+    pub fn get(&self, device_id: &str) -> Option<&Device> {
+        self.list
+            .as_ref()?
+            .devices
+            .iter()
+            .find(|d| d.device_id == device_id)
+    }
+
+    /// Status of this device; `None` before it was ever listed
+    pub fn status(&self) -> Option<DeviceStatus> {
+        self.get(&self.device_id()).map(|d| d.status)
+    }
+
+    pub fn signed(&self) -> Option<&SignedDeviceList> {
+        self.signed.as_ref()
+    }
+
+    /// Epoch of the current sync key; 0 before there is a list
+    pub fn epoch(&self) -> u64 {
+        self.list.as_ref().map_or(0, |l| l.epoch)
+    }
+
+    /// Put this device on the list: active with a fresh sync key if there is
+    /// no list yet, pending otherwise. True if the list changed.
+    pub fn register(&mut self, identity: &Identity) -> Result<bool> {
+        if self.status().is_some() {
+            return Ok(false);
+        }
+        let device = Device {
+            device_id: self.device_id(),
+            name: self.name.clone(),
+            signing_key: hex::encode(self.device.signing_key().verifying_key().to_bytes()),
+            status: DeviceStatus::Pending,
+            added_at: now_secs(),
+            revoked_at: None,
+            current: false,
+        };
+        let mut list = match self.list.clone() {
+            Some(list) => list,
+            None => DeviceList {
+                owner: identity.public_id(),
+                version: 0,
+                issued_at: 0,
+                devices: Vec::new(),
+                epoch: 0,
+                keys: BTreeMap::new(),
+            },
+        };
+        let first = list.devices.is_empty();
+        list.devices.push(device);
+        if first {
+            let device_id = self.device_id();
+            activate(&mut list, &device_id);
+            rotate_key(&mut list)?;
+        }
+        self.publish(identity, list)?;
+        Ok(true)
+    }
+
+    /// Adopt a list from another device if it is newer. True if it was.
+    pub fn merge(&mut self, identity: &Identity, remote: &SignedDeviceList) -> Result<bool> {
+        let theirs = remote.verify(identity)?;
+        if self
+            .list
+            .as_ref()
+            .is_some_and(|ours| ours.version >= theirs.version)
+        {
+            return Ok(false);
+        }
+        self.list = Some(theirs);
+        self.signed = Some(remote.clone());
+        Ok(true)
+    }
+
+    /// Let a pending device in and hand it the sync key
+    pub fn approve(&mut self, identity: &Identity, device_id: &str) -> Result<Device> {
+        let mut sync_key = self
+            .sync_key_bytes()?
+            .context("This device can't approve others")?;
+        let mut list = self.list.clone().context("No device list yet")?;
+        let device = list
+            .devices
+            .iter()
+            .find(|d| d.device_id == device_id)
+            .context("Unknown device")?;
+        if device.status == DeviceStatus::Revoked {
+            anyhow::bail!("A revoked device can't be approved again");
+        }
+        activate(&mut list, device_id);
+        let wrapped = wrap_key(device_id, &sync_key);
+        sync_key.zeroize();
+        list.keys.insert(device_id.to_string(), wrapped?);
+        self.publish(identity, list)?;
+        self.get(device_id).cloned().context("Unknown device")
+    }
+
+    /// Cut a device off: it is marked revoked and a new sync key goes to
+    /// the remaining active devices only
+    pub fn revoke(&mut self, identity: &Identity, device_id: &str) -> Result<Device> {
+        if device_id == self.device_id() {
+            anyhow::bail!("Revoke this device from another one");
+        }
+        if self.status() != Some(DeviceStatus::Active) {
+            anyhow::bail!("Only an active device can revoke others");
+        }
+        let mut list = self.list.clone().context("No device list yet")?;
+        let device = list
+            .devices
+            .iter_mut()
+            .find(|d| d.device_id == device_id)
+            .context("Unknown device")?;
+        if device.status == DeviceStatus::Revoked {
+            anyhow::bail!("Device is already revoked");
+        }
+        device.status = DeviceStatus::Revoked;
+        device.revoked_at = Some(now_secs());
+        rotate_key(&mut list)?;
+        self.publish(identity, list)?;
+        self.get(device_id).cloned().context("Unknown device")
+    }
+
+    /// Key the sync snapshot entries are sealed with, if this device holds it
+    pub fn sync_key(&self) -> Result<Option<SessionKey>> {
+        let Some(mut bytes) = self.sync_key_bytes()? else {
+            return Ok(None);
+        };
+        let key = SessionKey::from_bytes(&bytes);
+        bytes.zeroize();
+        key.map(Some)
+    }
+
+    fn sync_key_bytes(&self) -> Result<Option<[u8; 32]>> {
+        if self.status() != Some(DeviceStatus::Active) {
+            return Ok(None);
+        }
+        let Some(wrapped) = self
+            .list
+            .as_ref()
+            .and_then(|l| l.keys.get(&self.device_id()))
+        else {
+            return Ok(None);
+        };
+        let ephemeral = decode_public_key(&wrapped.ephemeral)?;
+        let sealed = hex::decode(&wrapped.sealed).context("Invalid wrapped sync key")?;
+        let mut bytes = self.device.open_sealed(&ephemeral, &sealed)?;
+        let key: Option<[u8; 32]> = bytes.as_slice().try_into().ok();
+        bytes.zeroize();
+        key.map(Some)
+            .context("Wrapped sync key has the wrong length")
+    }
+
+    fn publish(&mut self, identity: &Identity, mut list: DeviceList) -> Result<()> {
+        list.version += 1;
+        list.issued_at = now_secs();
+        self.signed = Some(SignedDeviceList::sign(identity, &list)?);
+        self.list = Some(list);
+        Ok(())
+    }
+}
+
+/// Sign a revocation for sending to contacts
+pub fn revocation(identity: &Identity, device: &Device) -> Result<SignedRevocation> {
+    let statement = serde_json::to_string(&DeviceRevocation {
+        owner: identity.public_id(),
+        device_id: device.device_id.clone(),
+        name: device.name.clone(),
+        revoked_at: device.revoked_at.unwrap_or_else(now_secs),
+    })?;
+    let signing_key = identity.signing_key();
+    let signature = signing_key.sign(statement.as_bytes());
+    Ok(SignedRevocation {
+        statement,
+        signature: hex::encode(signature.to_bytes()),
+        signing_key: hex::encode(signing_key.verifying_key().to_bytes()),
+    })
+}
+
+fn activate(list: &mut DeviceList, device_id: &str) {
+    if let Some(device) = list.devices.iter_mut().find(|d| d.device_id == device_id) {
+        device.status = DeviceStatus::Active;
+    }
+}
+
+/// Start a new epoch with a random sync key sealed to every active device
+fn rotate_key(list: &mut DeviceList) -> Result<()> {
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    let keys = list
+        .devices
+        .iter()
+        .filter(|d| d.status == DeviceStatus::Active)
+        .map(|d| Ok((d.device_id.clone(), wrap_key(&d.device_id, &key)?)))
+        .collect::<Result<_>>();
+    key.zeroize();
+    list.keys = keys?;
+    list.epoch += 1;
+    Ok(())
+}
+
+fn wrap_key(device_id: &str, key: &[u8; 32]) -> Result<WrappedKey> {
+    let (ephemeral, sealed) = seal_to(&decode_public_key(device_id)?, key)?;
+    Ok(WrappedKey {
+        ephemeral: bs58::encode(ephemeral.as_bytes()).into_string(),
+        sealed: hex::encode(sealed),
+    })
+}
+
+fn default_name() -> String {
+    let name = std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_else(|_| std::env::consts::OS.to_string());
+    name.chars().take(MAX_DEVICE_NAME_LEN).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revoked_device_loses_the_sync_key() {
+        let identity = Identity::generate();
+        let (laptop_dir, phone_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let mut laptop = DeviceStore::load(laptop_dir.path(), &identity).unwrap();
+        let mut phone = DeviceStore::load(phone_dir.path(), &identity).unwrap();
+
+        assert!(laptop.register(&identity).unwrap());
+        assert_eq!(laptop.status(), Some(DeviceStatus::Active));
+        assert!(laptop.sync_key().unwrap().is_some());
+
+        // The phone joins through the laptop's list and waits for approval
+        assert!(phone.merge(&identity, laptop.signed().unwrap()).unwrap());
+        assert!(phone.register(&identity).unwrap());
+        assert_eq!(phone.status(), Some(DeviceStatus::Pending));
+        assert!(phone.sync_key().unwrap().is_none());
+
+        laptop.merge(&identity, phone.signed().unwrap()).unwrap();
+        laptop.approve(&identity, &phone.device_id()).unwrap();
+        phone.merge(&identity, laptop.signed().unwrap()).unwrap();
+        let shared = phone.sync_key().unwrap().unwrap();
+        assert_eq!(
+            shared.as_bytes(),
+            laptop.sync_key().unwrap().unwrap().as_bytes()
+        );
+
+        // The phone is stolen
+        let revoked = laptop.revoke(&identity, &phone.device_id()).unwrap();
+        assert_eq!(revoked.status, DeviceStatus::Revoked);
+        phone.merge(&identity, laptop.signed().unwrap()).unwrap();
+        assert!(phone.sync_key().unwrap().is_none());
+        assert_ne!(
+            laptop.sync_key().unwrap().unwrap().as_bytes(),
+            shared.as_bytes()
+        );
+        assert!(phone.approve(&identity, &phone.device_id()).is_err());
+
+        let notice = revocation(&identity, &revoked).unwrap();
+        assert_eq!(notice.verify().unwrap().device_id, phone.device_id());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::burn::WrappedShard;
+use crate::catalog::{now_secs, DropCatalog, DropRecord};
+use crate::crypto::Identity;
+use crate::dead_drop::{
+    create_dead_drop, create_dead_drop_from_reader, new_drop_cache, retrieve_dead_drop,
+    DeadDropCreated, DropOptions,
+};
+use crate::erasure::{erasure_manifest_cid, retrieve_erasure_drop};
+use crate::mfs;
+use crate::settings;
+use crate::shards::{open_sealed_shards, seal_shards};
+use anyhow::Result;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// A drop to create and how to split its key
+#[derive(Debug, Clone)]
+pub struct NewDrop {
+    /// Catalog name; defaults to the file name for file drops
+    pub name: Option<String>,
+    pub threshold: u8,
+    pub total_shards: u8,
+    /// Seal shard N to the Nth public ID; empty leaves shards plain
+    pub recipients: Vec<String>,
+    pub expires_in_secs: Option<u64>,
+}
+
+/// Configures a [`DropManager`]
+pub struct DropManagerBuilder {
+    data_dir: PathBuf,
+    options: Option<DropOptions>,
+    identity: Option<Identity>,
+    catalog: bool,
+}
+
+impl DropManagerBuilder {
+    /// Override the drop settings and cache of the data directory
+    pub fn options(mut self, options: DropOptions) -> Self {
+        self.options = Some(options);
+        self
+    }
+
+    /// Needed to open shards sealed to this identity
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Record created drops in the catalog and MFS (on by default)
+    pub fn catalog(mut self, catalog: bool) -> Self {
+        self.catalog = catalog;
+        self
+    }
+
+    pub fn build(self) -> DropManager {
+        let options = self.options.unwrap_or_else(|| DropOptions {
+            cache_dir: new_drop_cache(&self.data_dir),
+            ..settings::drop_settings().drop_options(&self.data_dir)
+        });
+        DropManager {
+            data_dir: self.data_dir,
+            options,
+            identity: self.identity,
+            catalog: self.catalog,
+        }
+    }
+}
+
+/// Creates and retrieves Shamir-split dead drops for one data directory
+pub struct DropManager {
+    data_dir: PathBuf,
+    options: DropOptions,
+    identity: Option<Identity>,
+    catalog: bool,
+}
+
+impl DropManager {
+    pub fn builder(data_dir: impl Into<PathBuf>) -> DropManagerBuilder {
+        DropManagerBuilder {
+            data_dir: data_dir.into(),
+            options: None,
+            identity: None,
+            catalog: true,
+        }
+    }
+
+    pub fn options(&self) -> &DropOptions {
+        &self.options
+    }
+
+    /// Encrypt and upload a file
+    pub async fn create_file(
+        &self,
+        file_path: &str,
+        mut request: NewDrop,
+    ) -> Result<DeadDropCreated> {
+        let created = create_dead_drop(
+            file_path,
+            request.threshold,
+            request.total_shards,
+            &self.options,
+        )
+        .await?;
+        request.name.get_or_insert_with(|| {
+            Path::new(file_path)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+        self.finish(created, request).await
+    }
+
+    /// Encrypt and upload a stream without writing the plaintext to disk
+    pub async fn create_from_reader<R: Read + Send>(
+        &self,
+        reader: R,
+        request: NewDrop,
+    ) -> Result<DeadDropCreated> {
+        let created = create_dead_drop_from_reader(
+            reader,
+            0,
+            request.threshold,
+            request.total_shards,
+            &self.options,
+        )
+        .await?;
+        self.finish(created, request).await
+    }
+
+    /// Download and decrypt a plain or erasure-coded drop. Sealed shards are
+    /// opened with the manager's identity.
+    pub async fn retrieve(&self, cid: &str, shards: Vec<String>, output_path: &str) -> Result<()> {
+        let shards = open_sealed_shards(shards, self.identity.as_ref())?;
+        if shards.iter().any(|s| WrappedShard::parse(s).is_some()) {
+            anyhow::bail!("Burn-after-read drops need Ghost Mode to fetch their key");
+        }
+
+        match erasure_manifest_cid(cid) {
+            Some(manifest_cid) => {
+                retrieve_erasure_drop(manifest_cid, shards, output_path, &self.options).await
+            }
+            None => retrieve_dead_drop(cid, shards, output_path, &self.options).await,
+        }
+    }
+
+    async fn finish(
+        &self,
+        mut created: DeadDropCreated,
+        request: NewDrop,
+    ) -> Result<DeadDropCreated> {
+        if !request.recipients.is_empty() {
+            created.shards = seal_shards(&created.shards, &request.recipients)?;
+        }
+        if !self.catalog {
+            return Ok(created);
+        }
+
+        let created_at = now_secs();
+        let record = DropRecord {
+            cid: created.cid.clone(),
+            file_name: request.name.unwrap_or_default(),
+            threshold: request.threshold,
+            total_shards: request.total_shards,
+            created_at,
+            expires_at: request.expires_in_secs.map(|secs| created_at.saturating_add(secs)),
+            pinned: true,
+            drop_id: None,
+            piece_cids: Vec::new(),
+            reshared_at: None,
+            group: None,
+            imported: false,
+            cipher: created.cipher,
+        };
+        let mut catalog = DropCatalog::load(&self.data_dir)?;
+        catalog.insert(record.clone());
+        catalog.save()?;
+        if !created.pending {
+            if let Err(e) = mfs::link_drop(&record).await {
+                warn!("Drop {} not linked into MFS: {:#}", record.cid, e);
+            }
+        }
+        Ok(created)
+    }
+}
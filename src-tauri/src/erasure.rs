@@ -0,0 +1,441 @@
+use crate::crypto::SessionKey;
+use crate::dead_drop::{
+    build_drop_stat, cat_bytes_from_ipfs, download_file_from_ipfs, object_size,
+    probe_download_speed, stream_decrypt_file, stream_encrypt_file, upload_bytes_to_ipfs,
+    upload_file_to_ipfs, DeadDropCreated, DropOptions, DropStat,
+};
+use crate::ipfs;
+use crate::shards::{recover_session_key, split_session_key};
+use crate::wire;
+use anyhow::{Context, Result};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+/// Drop references pointing at an erasure manifest carry this prefix
+pub const ERASURE_CID_PREFIX: &str = "ec:";
+const MAX_TOTAL_PIECES: usize = 255; // GF(2^8) limit
+const PIECE_PROBE_TIMEOUT_SECS: u64 = 30;
+
+/// Erasure coding parameters chosen at creation time
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ErasureConfig {
+    pub data_pieces: usize,
+    pub parity_pieces: usize,
+}
+
+/// Layout of an erasure-coded drop, stored on IPFS encrypted with the session key
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErasureManifest {
+    pub data_pieces: usize,
+    pub parity_pieces: usize,
+    pub stripe_shard_len: usize,
+    pub ciphertext_len: u64,
+    pub piece_cids: Vec<String>,
+}
+
+impl ErasureConfig {
+    fn validate(&self) -> Result<()> {
+        if self.data_pieces < 1 || self.parity_pieces < 1 {
+            anyhow::bail!("Erasure coding needs at least 1 data and 1 parity piece");
+        }
+        if self.data_pieces + self.parity_pieces > MAX_TOTAL_PIECES {
+            anyhow::bail!("Erasure coding supports at most {} pieces", MAX_TOTAL_PIECES);
+        }
+        Ok(())
+    }
+}
+
+/// Strip the erasure prefix from a drop reference, if present
+pub fn erasure_manifest_cid(cid: &str) -> Option<&str> {
+    cid.strip_prefix(ERASURE_CID_PREFIX)
+}
+
+/// Create an erasure-coded drop: the ciphertext is split into
+/// `data_pieces + parity_pieces` pieces, each uploaded as its own CID, so any
+/// `data_pieces` of them are enough to rebuild it
+pub async fn create_erasure_drop(
+    file_path: &str,
+    threshold: u8,
+    total_shards: u8,
+    config: ErasureConfig,
+    options: &DropOptions,
+) -> Result<DeadDropCreated> {
+    if threshold > total_shards {
+        anyhow::bail!("Threshold cannot exceed total shards");
+    }
+    if threshold < 2 {
+        anyhow::bail!("Threshold must be at least 2");
+    }
+    config.validate()?;
+
+    let session_key = SessionKey::generate();
+
+    // Encrypt to a temp file exactly like a normal drop
+    let ciphertext_file = options.staging_file()?;
+    let ciphertext_len = stream_encrypt_file(file_path, ciphertext_file.path(), &session_key, options)
+        .context("Failed to encrypt file")?;
+
+    // Each stripe holds one chunk of ciphertext split across the data pieces
+    let stripe_shard_len = options.chunk_size.div_ceil(config.data_pieces);
+    let piece_files = encode_pieces(ciphertext_file.path(), config, stripe_shard_len, options)?;
+    drop(ciphertext_file);
+
+    let mut piece_cids = Vec::with_capacity(piece_files.len());
+    for (index, piece) in piece_files.iter().enumerate() {
+        let cid = upload_file_to_ipfs(piece.path(), options).await?;
+        info!("Uploaded erasure piece {}: {}", index, cid);
+        piece_cids.push(cid);
+    }
+
+    let manifest = ErasureManifest {
+        data_pieces: config.data_pieces,
+        parity_pieces: config.parity_pieces,
+        stripe_shard_len,
+        ciphertext_len,
+        piece_cids: piece_cids.clone(),
+    };
+    let mut manifest_bytes = wire::encode(&manifest)?;
+    let encrypted_manifest = session_key.encrypt_file(&manifest_bytes);
+    manifest_bytes.zeroize();
+    let manifest_cid = upload_bytes_to_ipfs(encrypted_manifest?).await?;
+
+    let shards = split_session_key(&session_key, threshold, total_shards);
+    drop(session_key);
+
+    info!(
+        "Created erasure-coded drop {} ({}+{} pieces)",
+        manifest_cid, config.data_pieces, config.parity_pieces
+    );
+
+    Ok(DeadDropCreated {
+        cid: format!("{}{}", ERASURE_CID_PREFIX, manifest_cid),
+        shards,
+        drop_id: None,
+        piece_cids,
+        shred: None,
+        cipher: Some(options.cipher),
+        pending: false,
+    })
+}
+
+/// Retrieve an erasure-coded drop from any `data_pieces` of its pieces
+pub async fn retrieve_erasure_drop(
+    manifest_cid: &str,
+    shard_strings: Vec<String>,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<()> {
+    let session_key = recover_session_key(&shard_strings)?;
+    let manifest = fetch_erasure_manifest(manifest_cid, &session_key).await?;
+
+    // Fetch pieces until we have enough to rebuild the ciphertext
+    let mut pieces: Vec<Option<NamedTempFile>> = Vec::with_capacity(manifest.piece_cids.len());
+    let mut available = 0;
+    for (index, cid) in manifest.piece_cids.iter().enumerate() {
+        if available >= manifest.data_pieces {
+            pieces.push(None);
+            continue;
+        }
+        match fetch_piece(cid, options).await {
+            Ok(piece) => {
+                available += 1;
+                pieces.push(Some(piece));
+            }
+            Err(e) => {
+                warn!("Erasure piece {} ({}) unavailable: {}", index, cid, e);
+                pieces.push(None);
+            }
+        }
+    }
+
+    if available < manifest.data_pieces {
+        anyhow::bail!(
+            "Only {} of {} pieces reachable, need {}",
+            available,
+            manifest.piece_cids.len(),
+            manifest.data_pieces
+        );
+    }
+
+    let ciphertext_file = options.staging_file()?;
+    decode_pieces(&pieces, &manifest, ciphertext_file.path())?;
+    drop(pieces);
+
+    let decrypted_size =
+        stream_decrypt_file(ciphertext_file.path(), output_path, &session_key, options)
+            .context("Failed to decrypt file")?;
+
+    info!("Decrypted {} bytes to {}", decrypted_size, output_path);
+
+    Ok(())
+}
+
+/// Preflight estimate for an erasure-coded drop; piece sizes are checked
+/// against the manifest
+pub async fn stat_erasure_drop(
+    manifest_cid: &str,
+    shard_strings: Vec<String>,
+    output_path: &Path,
+    options: &DropOptions,
+) -> Result<DropStat> {
+    let session_key = recover_session_key(&shard_strings)?;
+    let manifest = fetch_erasure_manifest(manifest_cid, &session_key).await?;
+    drop(session_key);
+
+    let stripe_len = (manifest.stripe_shard_len * manifest.data_pieces) as u64;
+    let piece_len = manifest.ciphertext_len.div_ceil(stripe_len) * manifest.stripe_shard_len as u64;
+
+    let client = ipfs::client()?;
+    let mut reachable = Vec::new();
+    let mut size_source = String::new();
+    let mut verified = true;
+    for cid in &manifest.piece_cids {
+        match object_size(&client, cid).await {
+            Ok((size, source)) => {
+                if size != piece_len {
+                    warn!("Erasure piece {} is {} bytes, manifest says {}", cid, size, piece_len);
+                    verified = false;
+                }
+                size_source = source;
+                reachable.push(cid);
+            }
+            Err(e) => warn!("Erasure piece {} unavailable: {}", cid, e),
+        }
+    }
+
+    if reachable.len() < manifest.data_pieces {
+        anyhow::bail!(
+            "Only {} of {} pieces reachable, need {}",
+            reachable.len(),
+            manifest.piece_cids.len(),
+            manifest.data_pieces
+        );
+    }
+
+    let bytes_per_sec = probe_download_speed(&client, reachable[0]).await;
+
+    Ok(build_drop_stat(
+        &format!("{}{}", ERASURE_CID_PREFIX, manifest_cid),
+        piece_len * manifest.data_pieces as u64,
+        manifest.ciphertext_len,
+        size_source,
+        Some(verified),
+        bytes_per_sec,
+        output_path,
+        options,
+    ))
+}
+
+/// Split the ciphertext file stripe by stripe into data + parity piece files
+fn encode_pieces(
+    ciphertext_path: &Path,
+    config: ErasureConfig,
+    stripe_shard_len: usize,
+    options: &DropOptions,
+) -> Result<Vec<NamedTempFile>> {
+    let codec = ReedSolomon::new(config.data_pieces, config.parity_pieces)
+        .map_err(|e| anyhow::anyhow!("Invalid erasure parameters: {:?}", e))?;
+
+    let mut reader = BufReader::new(File::open(ciphertext_path).context("Failed to open ciphertext")?);
+
+    let total_pieces = config.data_pieces + config.parity_pieces;
+    let piece_files = (0..total_pieces)
+        .map(|_| options.staging_file())
+        .collect::<Result<Vec<_>>>()?;
+    let mut writers = piece_files
+        .iter()
+        .map(|f| f.reopen().map(BufWriter::new))
+        .collect::<std::io::Result<Vec<_>>>()
+        .context("Failed to open piece file")?;
+
+    let mut stripe = vec![vec![0u8; stripe_shard_len]; total_pieces];
+    loop {
+        // Fill the data shards; the last stripe is zero-padded
+        let mut stripe_bytes = 0;
+        for shard in stripe.iter_mut().take(config.data_pieces) {
+            shard.fill(0);
+            stripe_bytes += read_fill(&mut reader, shard)?;
+        }
+        if stripe_bytes == 0 {
+            break; // EOF
+        }
+
+        codec
+            .encode(&mut stripe)
+            .map_err(|e| anyhow::anyhow!("Erasure encoding failed: {:?}", e))?;
+
+        for (writer, shard) in writers.iter_mut().zip(&stripe) {
+            writer.write_all(shard).context("Failed to write piece")?;
+        }
+    }
+
+    for writer in &mut writers {
+        writer.flush().context("Failed to flush piece")?;
+    }
+
+    Ok(piece_files)
+}
+
+/// Rebuild the ciphertext from the available pieces
+fn decode_pieces(
+    pieces: &[Option<NamedTempFile>],
+    manifest: &ErasureManifest,
+    output_path: &Path,
+) -> Result<()> {
+    let codec = ReedSolomon::new(manifest.data_pieces, manifest.parity_pieces)
+        .map_err(|e| anyhow::anyhow!("Invalid erasure parameters: {:?}", e))?;
+
+    let mut readers = pieces
+        .iter()
+        .map(|piece| {
+            piece
+                .as_ref()
+                .map(|f| f.reopen().map(BufReader::new))
+                .transpose()
+        })
+        .collect::<std::io::Result<Vec<_>>>()
+        .context("Failed to open piece file")?;
+
+    let mut writer = BufWriter::new(File::create(output_path).context("Failed to create output file")?);
+    let mut remaining = manifest.ciphertext_len;
+
+    while remaining > 0 {
+        let mut stripe: Vec<Option<Vec<u8>>> = Vec::with_capacity(readers.len());
+        for reader in readers.iter_mut() {
+            match reader {
+                Some(reader) => {
+                    let mut shard = vec![0u8; manifest.stripe_shard_len];
+                    reader
+                        .read_exact(&mut shard)
+                        .context("Erasure piece is truncated")?;
+                    stripe.push(Some(shard));
+                }
+                None => stripe.push(None),
+            }
+        }
+
+        codec
+            .reconstruct_data(&mut stripe)
+            .map_err(|e| anyhow::anyhow!("Erasure reconstruction failed: {:?}", e))?;
+
+        for shard in stripe.iter().take(manifest.data_pieces) {
+            let shard = shard.as_ref().context("Missing data shard after reconstruction")?;
+            let take = remaining.min(shard.len() as u64) as usize;
+            writer.write_all(&shard[..take]).context("Failed to write ciphertext")?;
+            remaining -= take as u64;
+            if remaining == 0 {
+                break;
+            }
+        }
+    }
+
+    writer.flush().context("Failed to flush ciphertext")?;
+    Ok(())
+}
+
+/// Read until the buffer is full or EOF; returns bytes read
+fn read_fill(reader: &mut impl Read, buffer: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let bytes_read = reader.read(&mut buffer[filled..]).context("Failed to read ciphertext")?;
+        if bytes_read == 0 {
+            break;
+        }
+        filled += bytes_read;
+    }
+    Ok(filled)
+}
+
+/// Download a single piece, probing first so a lost piece fails fast
+async fn fetch_piece(cid: &str, options: &DropOptions) -> Result<NamedTempFile> {
+    let client = ipfs::client()?;
+    let probe = client
+        .post(ipfs::api("cat"))
+        .query(&[("arg", cid), ("length", "1")])
+        .timeout(Duration::from_secs(PIECE_PROBE_TIMEOUT_SECS))
+        .send()
+        .await
+        .context("Piece probe failed")?;
+    if !probe.status().is_success() {
+        anyhow::bail!("Piece probe failed: {}", probe.status());
+    }
+
+    let piece = options.staging_file()?;
+    download_file_from_ipfs(cid, piece.path(), options).await?;
+    Ok(piece)
+}
+
+async fn fetch_erasure_manifest(manifest_cid: &str, session_key: &SessionKey) -> Result<ErasureManifest> {
+    let encrypted = cat_bytes_from_ipfs(manifest_cid).await?;
+    let mut manifest_bytes = session_key
+        .decrypt_file(&encrypted)
+        .context("Failed to decrypt erasure manifest - wrong shards?")?;
+    let manifest = wire::decode(&manifest_bytes);
+    manifest_bytes.zeroize();
+    manifest.context("Invalid erasure manifest")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const CONFIG: ErasureConfig = ErasureConfig {
+        data_pieces: 3,
+        parity_pieces: 2,
+    };
+    const STRIPE_SHARD_LEN: usize = 16;
+
+    /// Encode `data`, lose the pieces in `lost` and decode what is left
+    fn round_trip(data: &[u8], lost: &[usize]) -> Result<Vec<u8>> {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("ciphertext");
+        fs::write(&input, data).unwrap();
+        let options = DropOptions::default();
+        let pieces = encode_pieces(&input, CONFIG, STRIPE_SHARD_LEN, &options)?;
+        let pieces: Vec<_> = pieces
+            .into_iter()
+            .enumerate()
+            .map(|(index, piece)| (!lost.contains(&index)).then_some(piece))
+            .collect();
+
+        let manifest = ErasureManifest {
+            data_pieces: CONFIG.data_pieces,
+            parity_pieces: CONFIG.parity_pieces,
+            stripe_shard_len: STRIPE_SHARD_LEN,
+            ciphertext_len: data.len() as u64,
+            piece_cids: Vec::new(),
+        };
+        let output = dir.path().join("rebuilt");
+        decode_pieces(&pieces, &manifest, &output)?;
+        Ok(fs::read(output).unwrap())
+    }
+
+    #[test]
+    fn rebuilds_from_any_data_pieces() {
+        // 20 full stripes of 48 bytes and a last one holding 40
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+        assert_eq!(round_trip(&data, &[]).unwrap(), data);
+        for lost in [[0, 1], [1, 3], [2, 4], [3, 4]] {
+            assert_eq!(round_trip(&data, &lost).unwrap(), data, "lost {:?}", lost);
+        }
+
+        // Ending exactly on a stripe
+        let data = vec![0xab; STRIPE_SHARD_LEN * CONFIG.data_pieces * 4];
+        assert_eq!(round_trip(&data, &[0, 2]).unwrap(), data);
+    }
+
+    #[test]
+    fn too_few_pieces_fail() {
+        let data = vec![0x5a; 1000];
+        assert!(round_trip(&data, &[0, 1, 4]).is_err());
+        assert!(round_trip(&data, &[2, 3, 4]).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use crate::i18n;
+use crate::p2p::P2PCommand;
+use crate::session::StateError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tokio::sync::mpsc::error::SendError;
+
+/// Broad area an error comes from, for the frontend to pick how to present it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    State,
+    Auth,
+    Input,
+    Network,
+    Storage,
+    Crypto,
+    Internal,
+}
+
+/// Stable error codes the frontend can branch on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    SessionLocked,
+    GhostModeRunning,
+    GhostModeStarting,
+    GhostModeNotRunning,
+    WrongPassword,
+    NoIdentity,
+    InvalidInput,
+    NetworkUnavailable,
+    Timeout,
+    NotFound,
+    StorageFailed,
+    CryptoFailed,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn category(self) -> ErrorCategory {
+        match self {
+            ErrorCode::SessionLocked
+            | ErrorCode::GhostModeRunning
+            | ErrorCode::GhostModeStarting
+            | ErrorCode::GhostModeNotRunning => ErrorCategory::State,
+            ErrorCode::WrongPassword | ErrorCode::NoIdentity => ErrorCategory::Auth,
+            ErrorCode::InvalidInput => ErrorCategory::Input,
+            ErrorCode::NetworkUnavailable | ErrorCode::Timeout => ErrorCategory::Network,
+            ErrorCode::NotFound | ErrorCode::StorageFailed => ErrorCategory::Storage,
+            ErrorCode::CryptoFailed => ErrorCategory::Crypto,
+            ErrorCode::Internal => ErrorCategory::Internal,
+        }
+    }
+
+    /// Whether the same call may succeed if simply tried again later
+    pub fn retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::GhostModeStarting | ErrorCode::NetworkUnavailable | ErrorCode::Timeout
+        )
+    }
+
+    /// Most specific code for an error chain, or `fallback` if nothing is recognised
+    fn classify(error: &anyhow::Error, fallback: ErrorCode) -> ErrorCode {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<StateError>() {
+                return (*e).into();
+            }
+            if cause.is::<SendError<P2PCommand>>() {
+                return ErrorCode::GhostModeNotRunning;
+            }
+            if cause.is::<tokio::time::error::Elapsed>() {
+                return ErrorCode::Timeout;
+            }
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                return if e.is_timeout() {
+                    ErrorCode::Timeout
+                } else {
+                    ErrorCode::NetworkUnavailable
+                };
+            }
+            if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+                return match e.kind() {
+                    std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+                    std::io::ErrorKind::TimedOut => ErrorCode::Timeout,
+                    _ => ErrorCode::StorageFailed,
+                };
+            }
+        }
+        fallback
+    }
+}
+
+impl From<StateError> for ErrorCode {
+    fn from(error: StateError) -> Self {
+        match error {
+            StateError::Locked => ErrorCode::SessionLocked,
+            StateError::GhostModeRunning => ErrorCode::GhostModeRunning,
+            StateError::GhostModeStarting => ErrorCode::GhostModeStarting,
+            StateError::GhostModeNotRunning => ErrorCode::GhostModeNotRunning,
+        }
+    }
+}
+
+/// Error returned by every Tauri command and carried by error events.
+/// Serializes as `{category, code, message, message_id?, retryable}`; the
+/// message is in the locale from settings.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ControlError {
+    pub category: ErrorCategory,
+    pub code: ErrorCode,
+    pub message: String,
+    /// Catalog ID of the message, for UIs that translate on their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    pub retryable: bool,
+}
+
+impl ControlError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            category: code.category(),
+            code,
+            message: message.into(),
+            message_id: None,
+            retryable: code.retryable(),
+        }
+    }
+
+    /// Error whose message is looked up in the message catalog
+    pub fn localized(code: ErrorCode, message_id: &'static str) -> Self {
+        Self {
+            message_id: Some(message_id.to_string()),
+            ..Self::new(code, i18n::t(message_id))
+        }
+    }
+
+    pub fn invalid_input(message_id: &'static str) -> Self {
+        Self::localized(ErrorCode::InvalidInput, message_id)
+    }
+
+    /// Append the underlying cause, which stays untranslated
+    pub fn with_cause(mut self, cause: impl fmt::Display) -> Self {
+        self.message = format!("{}: {}", self.message, cause);
+        self
+    }
+
+    /// For `map_err`: prefix the message with the `context` message and
+    /// classify the cause
+    pub fn wrap<E: Into<anyhow::Error>>(context: &'static str) -> impl FnOnce(E) -> Self {
+        Self::wrap_as(ErrorCode::Internal, context)
+    }
+
+    /// Like `wrap`, using `fallback` when the cause isn't a recognised error type
+    pub fn wrap_as<E: Into<anyhow::Error>>(
+        fallback: ErrorCode,
+        context: &'static str,
+    ) -> impl FnOnce(E) -> Self {
+        move |error| {
+            let error = error.into();
+            Self::localized(ErrorCode::classify(&error, fallback), context).with_cause(error)
+        }
+    }
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+impl From<StateError> for ControlError {
+    fn from(error: StateError) -> Self {
+        Self::localized(error.into(), error.message_id())
+    }
+}
+
+impl From<anyhow::Error> for ControlError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::new(
+            ErrorCode::classify(&error, ErrorCode::Internal),
+            error.to_string(),
+        )
+    }
+}
+
+/// Compatibility with the old plain-string errors: the message is kept as is
+impl From<String> for ControlError {
+    fn from(message: String) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+}
+
+impl From<&str> for ControlError {
+    fn from(message: &str) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+}
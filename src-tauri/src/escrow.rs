@@ -0,0 +1,704 @@
+use crate::catalog::now_secs;
+use crate::contacts::ContactBook;
+use crate::crypto::{decode_public_key, Identity, SessionKey};
+use crate::dead_drop::{
+    cat_bytes_from_ipfs, create_dead_drop, stat_dead_drop, upload_bytes_to_ipfs, DeadDropCreated,
+    DropOptions, DropStat,
+};
+use crate::devices::verifying_key;
+use crate::no_traces;
+use crate::shards::{seal_shards, SealedShard};
+use crate::wire;
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, Verifier};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use tracing::info;
+use zeroize::Zeroize;
+
+/// Drop references pointing at an escrow manifest carry this prefix
+pub const ESCROW_CID_PREFIX: &str = "es:";
+const ESCROW_FILE: &str = "escrow.enc";
+const MANIFEST_VERSION: u32 = 1;
+/// Requests wait this long for a decision, then are dropped
+const REQUEST_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+/// How far ahead of our clock a request's timestamp may be
+const MAX_CLOCK_SKEW_SECS: u64 = 5 * 60;
+const MAX_REASON_CHARS: usize = 500;
+
+/// Public manifest of an escrowed drop. Each approver's shard is sealed to
+/// them, so the key is only rebuilt once `threshold` of them hand theirs on.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EscrowManifest {
+    pub version: u32,
+    pub cid: String,
+    pub owner: String,
+    pub threshold: u8,
+    pub approvers: Vec<EscrowApprover>,
+    pub created_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EscrowApprover {
+    pub public_id: String,
+    pub sealed_shard: String,
+}
+
+/// What a requester signs to ask the approvers for a drop's key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReleaseRequest {
+    pub request_id: String,
+    pub manifest_cid: String,
+    pub requester: String,
+    pub reason: String,
+    pub issued_at: u64,
+}
+
+/// An approver's answer. An approval carries their shard, sealed to the
+/// requester.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReleaseDecision {
+    pub request_id: String,
+    pub approver: String,
+    pub approved: bool,
+    pub shard: Option<String>,
+    pub reason: String,
+    pub decided_at: u64,
+}
+
+/// Manifest, request or decision as uploaded or sent over Ghost Mode
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedEscrowStatement {
+    /// JSON of the statement, signed byte for byte
+    pub statement: String,
+    pub signature: String,   // hex
+    pub signing_key: String, // hex, the signer's ed25519 key
+}
+
+impl SignedEscrowStatement {
+    fn sign<T: Serialize>(identity: &Identity, statement: &T) -> Result<Self> {
+        let statement = serde_json::to_string(statement)?;
+        let signing_key = identity.signing_key();
+        let signature = signing_key.sign(statement.as_bytes());
+        Ok(Self {
+            statement,
+            signature: hex::encode(signature.to_bytes()),
+            signing_key: hex::encode(signing_key.verifying_key().to_bytes()),
+        })
+    }
+
+    /// Check the signature and return the statement. Whether the key belongs
+    /// to the claimed signer is up to the caller.
+    pub fn verify<T: DeserializeOwned>(&self) -> Result<T> {
+        let key = verifying_key(&self.signing_key)?;
+        let signature = hex::decode(&self.signature).context("Invalid escrow signature")?;
+        let signature = Signature::from_slice(&signature).context("Invalid escrow signature")?;
+        key.verify(self.statement.as_bytes(), &signature)
+            .context("Escrow signature does not match")?;
+        serde_json::from_str(&self.statement).context("Invalid escrow statement")
+    }
+}
+
+/// A release we asked for, as shown to the requester
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EscrowRequestStatus {
+    pub request_id: String,
+    /// The `es:` drop reference
+    pub drop: String,
+    pub reason: String,
+    pub issued_at: u64,
+    pub threshold: u8,
+    pub approvers: Vec<String>,
+    pub approved: Vec<String>,
+    /// Approver and the reason they gave
+    pub denied: BTreeMap<String, String>,
+    /// Enough approvals arrived to open the drop
+    pub ready: bool,
+}
+
+/// A release someone asked us to approve, as shown to the approver
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingApproval {
+    pub request_id: String,
+    pub requester: String,
+    pub drop: String,
+    pub reason: String,
+    pub issued_at: u64,
+    pub received_at: u64,
+    /// Set once we answered
+    pub approved: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct OutgoingRequest {
+    request: ReleaseRequest,
+    cid: String,
+    threshold: u8,
+    approvers: Vec<String>,
+    /// Approver -> their shard, sealed to us
+    shards: BTreeMap<String, String>,
+    denied: BTreeMap<String, String>,
+}
+
+impl OutgoingRequest {
+    fn status(&self) -> EscrowRequestStatus {
+        EscrowRequestStatus {
+            request_id: self.request.request_id.clone(),
+            drop: format!("{}{}", ESCROW_CID_PREFIX, self.request.manifest_cid),
+            reason: self.request.reason.clone(),
+            issued_at: self.request.issued_at,
+            threshold: self.threshold,
+            approvers: self.approvers.clone(),
+            approved: self.shards.keys().cloned().collect(),
+            denied: self.denied.clone(),
+            ready: self.shards.len() >= self.threshold as usize,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct IncomingRequest {
+    request: ReleaseRequest,
+    received_at: u64,
+    approved: Option<bool>,
+}
+
+impl IncomingRequest {
+    fn pending(&self) -> PendingApproval {
+        PendingApproval {
+            request_id: self.request.request_id.clone(),
+            requester: self.request.requester.clone(),
+            drop: format!("{}{}", ESCROW_CID_PREFIX, self.request.manifest_cid),
+            reason: self.request.reason.clone(),
+            issued_at: self.request.issued_at,
+            received_at: self.received_at,
+            approved: self.approved,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct EscrowState {
+    outgoing: BTreeMap<String, OutgoingRequest>,
+    incoming: BTreeMap<String, IncomingRequest>,
+}
+
+/// Our release requests and those waiting for our approval, encrypted with
+/// the local identity
+struct EscrowStore {
+    path: PathBuf,
+    storage_key: SessionKey,
+    state: EscrowState,
+}
+
+impl EscrowStore {
+    fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(ESCROW_FILE);
+        let storage_key = identity.storage_key(b"escrow");
+
+        let mut state: EscrowState = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read escrow requests")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt escrow requests")?;
+            let state = serde_json::from_slice(&json);
+            json.zeroize();
+            state.context("Failed to parse escrow requests")?
+        } else {
+            EscrowState::default()
+        };
+        let cutoff = now_secs().saturating_sub(REQUEST_TTL_SECS);
+        state.incoming.retain(|_, r| r.request.issued_at >= cutoff);
+
+        Ok(Self {
+            path,
+            storage_key,
+            state,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut json = serde_json::to_vec(&self.state)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write escrow requests")?;
+        Ok(())
+    }
+}
+
+/// Strip the escrow prefix from a drop reference, if present
+pub fn escrow_manifest_cid(cid: &str) -> Option<&str> {
+    cid.strip_prefix(ESCROW_CID_PREFIX)
+}
+
+fn check_reason(reason: &str) -> Result<()> {
+    if reason.chars().count() > MAX_REASON_CHARS {
+        anyhow::bail!("Reason can be at most {} characters", MAX_REASON_CHARS);
+    }
+    Ok(())
+}
+
+/// Create a drop whose shards are sealed to `approvers` inside a signed
+/// manifest; opening it takes `threshold` of them approving a request.
+/// No shards are returned.
+pub async fn create_escrow_drop(
+    file_path: &str,
+    threshold: u8,
+    approvers: &[String],
+    identity: &Identity,
+    options: &DropOptions,
+) -> Result<DeadDropCreated> {
+    let mut seen = HashSet::new();
+    for approver in approvers {
+        decode_public_key(approver).context("Invalid approver public ID")?;
+        if !seen.insert(approver) {
+            anyhow::bail!("Approver {} is listed twice", approver);
+        }
+    }
+    let total_shards = u8::try_from(approvers.len()).context("Too many approvers")?;
+    let mut created = create_dead_drop(file_path, threshold, total_shards, options).await?;
+    let sealed = seal_shards(&created.shards, approvers)?;
+    created.shards.zeroize();
+
+    let manifest = EscrowManifest {
+        version: MANIFEST_VERSION,
+        cid: created.cid.clone(),
+        owner: identity.public_id(),
+        threshold,
+        approvers: approvers
+            .iter()
+            .zip(sealed)
+            .map(|(public_id, sealed_shard)| EscrowApprover {
+                public_id: public_id.clone(),
+                sealed_shard,
+            })
+            .collect(),
+        created_at: now_secs(),
+    };
+    let signed = SignedEscrowStatement::sign(identity, &manifest)?;
+    let manifest_cid = upload_bytes_to_ipfs(wire::encode(&signed)?).await?;
+    info!(
+        "Created escrow drop {} ({} of {} approvers)",
+        manifest_cid, threshold, total_shards
+    );
+
+    Ok(DeadDropCreated {
+        cid: format!("{}{}", ESCROW_CID_PREFIX, manifest_cid),
+        shards: Vec::new(),
+        piece_cids: vec![manifest.cid],
+        ..created
+    })
+}
+
+/// Fetch an escrow manifest and check it is signed by its owner's key as
+/// far as our contacts know it
+pub async fn fetch_escrow_manifest(
+    manifest_cid: &str,
+    data_dir: &Path,
+    identity: Option<&Identity>,
+) -> Result<EscrowManifest> {
+    let bytes = cat_bytes_from_ipfs(manifest_cid).await?;
+    let signed: SignedEscrowStatement = wire::decode(&bytes).context("Invalid escrow manifest")?;
+    let manifest: EscrowManifest = signed.verify()?;
+    if manifest.version > MANIFEST_VERSION {
+        anyhow::bail!(
+            "Escrow manifest version {} is not supported",
+            manifest.version
+        );
+    }
+    if let Some(identity) = identity {
+        let book = ContactBook::load(data_dir, identity)?;
+        if let Some(key) = book
+            .get(&manifest.owner)
+            .and_then(|c| c.signing_key.as_ref())
+        {
+            if *key != signed.signing_key {
+                anyhow::bail!("Escrow manifest is not signed by {}", manifest.owner);
+            }
+        }
+    }
+    Ok(manifest)
+}
+
+/// Preflight estimate; the manifest is public, so no approvals are needed
+pub async fn stat_escrow_drop(
+    manifest_cid: &str,
+    data_dir: &Path,
+    output_path: &Path,
+    options: &DropOptions,
+) -> Result<DropStat> {
+    let manifest = fetch_escrow_manifest(manifest_cid, data_dir, None).await?;
+    stat_dead_drop(&manifest.cid, None, output_path, options).await
+}
+
+/// Sign a request to open an escrowed drop. Returns it with the approvers
+/// to send it to.
+pub async fn request_release(
+    data_dir: &Path,
+    identity: &Identity,
+    manifest_cid: &str,
+    reason: &str,
+) -> Result<(EscrowRequestStatus, SignedEscrowStatement)> {
+    check_reason(reason)?;
+    let manifest = fetch_escrow_manifest(manifest_cid, data_dir, Some(identity)).await?;
+    let request = ReleaseRequest {
+        request_id: uuid::Uuid::new_v4().to_string(),
+        manifest_cid: manifest_cid.to_string(),
+        requester: identity.public_id(),
+        reason: reason.to_string(),
+        issued_at: now_secs(),
+    };
+    let signed = SignedEscrowStatement::sign(identity, &request)?;
+    let outgoing = OutgoingRequest {
+        request,
+        cid: manifest.cid,
+        threshold: manifest.threshold,
+        approvers: manifest
+            .approvers
+            .into_iter()
+            .map(|a| a.public_id)
+            .collect(),
+        shards: BTreeMap::new(),
+        denied: BTreeMap::new(),
+    };
+    let status = outgoing.status();
+
+    let mut store = EscrowStore::load(data_dir, identity)?;
+    store
+        .state
+        .outgoing
+        .insert(status.request_id.clone(), outgoing);
+    store.save()?;
+    Ok((status, signed))
+}
+
+/// Take in a release request sent to us as an approver. Returns `None` for
+/// one we already have.
+pub fn receive_request(
+    data_dir: &Path,
+    identity: &Identity,
+    sender_id: &str,
+    signed: &SignedEscrowStatement,
+) -> Result<Option<PendingApproval>> {
+    let request: ReleaseRequest = signed.verify()?;
+    if request.requester != sender_id {
+        anyhow::bail!("Release request claims to be from {}", request.requester);
+    }
+    check_reason(&request.reason)?;
+    let now = now_secs();
+    if request.issued_at > now + MAX_CLOCK_SKEW_SECS || request.issued_at + REQUEST_TTL_SECS < now {
+        anyhow::bail!("Release request is out of date");
+    }
+
+    let mut store = EscrowStore::load(data_dir, identity)?;
+    if store.state.incoming.contains_key(&request.request_id) {
+        return Ok(None);
+    }
+    let incoming = IncomingRequest {
+        request,
+        received_at: now,
+        approved: None,
+    };
+    let pending = incoming.pending();
+    store
+        .state
+        .incoming
+        .insert(pending.request_id.clone(), incoming);
+    store.save()?;
+    Ok(Some(pending))
+}
+
+/// Approve or deny a request sent to us. Approving opens our shard and seals
+/// it to the requester. Returns the requester and the signed decision.
+pub async fn decide(
+    data_dir: &Path,
+    identity: &Identity,
+    request_id: &str,
+    approve: bool,
+    reason: &str,
+) -> Result<(String, SignedEscrowStatement)> {
+    check_reason(reason)?;
+    let incoming = EscrowStore::load(data_dir, identity)?
+        .state
+        .incoming
+        .remove(request_id)
+        .context("No such release request")?;
+    if incoming.approved.is_some() {
+        anyhow::bail!("This request was already answered");
+    }
+    let request = incoming.request;
+
+    let shard = if approve {
+        let manifest =
+            fetch_escrow_manifest(&request.manifest_cid, data_dir, Some(identity)).await?;
+        let ours = manifest
+            .approvers
+            .iter()
+            .find(|a| a.public_id == identity.public_id())
+            .context("We are not an approver of this drop")?;
+        let mut shard = SealedShard::parse(&ours.sealed_shard)
+            .context("Approver shard is not sealed")??
+            .open(identity)?;
+        let resealed = seal_shards(std::slice::from_ref(&shard), &[request.requester.clone()]);
+        shard.zeroize();
+        resealed?.pop()
+    } else {
+        None
+    };
+
+    let decision = ReleaseDecision {
+        request_id: request_id.to_string(),
+        approver: identity.public_id(),
+        approved: approve,
+        shard,
+        reason: reason.to_string(),
+        decided_at: now_secs(),
+    };
+    let signed = SignedEscrowStatement::sign(identity, &decision)?;
+
+    let mut store = EscrowStore::load(data_dir, identity)?;
+    if let Some(incoming) = store.state.incoming.get_mut(request_id) {
+        incoming.approved = Some(approve);
+    }
+    store.save()?;
+    Ok((request.requester, signed))
+}
+
+/// Take in an approver's answer to one of our requests. Returns the updated
+/// request, or `None` if it is unknown or already answered by them.
+pub fn receive_decision(
+    data_dir: &Path,
+    identity: &Identity,
+    sender_id: &str,
+    signed: &SignedEscrowStatement,
+) -> Result<Option<EscrowRequestStatus>> {
+    let decision: ReleaseDecision = signed.verify()?;
+    if decision.approver != sender_id {
+        anyhow::bail!("Release decision claims to be from {}", decision.approver);
+    }
+
+    let mut store = EscrowStore::load(data_dir, identity)?;
+    let Some(outgoing) = store.state.outgoing.get_mut(&decision.request_id) else {
+        return Ok(None);
+    };
+    if !outgoing.approvers.contains(&decision.approver) {
+        anyhow::bail!("{} is not an approver of this drop", decision.approver);
+    }
+    if outgoing.shards.contains_key(sender_id) || outgoing.denied.contains_key(sender_id) {
+        return Ok(None);
+    }
+    match (decision.approved, decision.shard) {
+        (true, Some(shard)) => {
+            // Only our identity can open it, but a shard sealed to anyone
+            // else is no approval
+            let sealed = SealedShard::parse(&shard).context("Approved shard is not sealed")??;
+            if sealed.recipient_id != identity.public_id() {
+                anyhow::bail!("Approved shard is sealed to someone else");
+            }
+            outgoing.shards.insert(decision.approver, shard);
+        }
+        (true, None) => anyhow::bail!("Approval without a shard"),
+        (false, _) => {
+            outgoing.denied.insert(decision.approver, decision.reason);
+        }
+    }
+    let status = outgoing.status();
+    store.save()?;
+    Ok(Some(status))
+}
+
+/// Our release requests, newest first
+pub fn requests(data_dir: &Path, identity: &Identity) -> Result<Vec<EscrowRequestStatus>> {
+    let store = EscrowStore::load(data_dir, identity)?;
+    let mut requests: Vec<_> = store
+        .state
+        .outgoing
+        .values()
+        .map(OutgoingRequest::status)
+        .collect();
+    requests.sort_by(|a, b| b.issued_at.cmp(&a.issued_at));
+    Ok(requests)
+}
+
+/// Requests sent to us as an approver that haven't expired, newest first
+pub fn pending_approvals(data_dir: &Path, identity: &Identity) -> Result<Vec<PendingApproval>> {
+    let store = EscrowStore::load(data_dir, identity)?;
+    let mut pending: Vec<_> = store
+        .state
+        .incoming
+        .values()
+        .map(IncomingRequest::pending)
+        .collect();
+    pending.sort_by(|a, b| b.issued_at.cmp(&a.issued_at));
+    Ok(pending)
+}
+
+/// The drop's CID and the approved shards, opened, once enough approvals
+/// arrived
+pub fn assemble(
+    data_dir: &Path,
+    identity: &Identity,
+    request_id: &str,
+) -> Result<(String, Vec<String>)> {
+    let store = EscrowStore::load(data_dir, identity)?;
+    let outgoing = store
+        .state
+        .outgoing
+        .get(request_id)
+        .context("No such release request")?;
+    if outgoing.shards.len() < outgoing.threshold as usize {
+        anyhow::bail!(
+            "{} of {} approvals so far",
+            outgoing.shards.len(),
+            outgoing.threshold
+        );
+    }
+    let shards = outgoing
+        .shards
+        .values()
+        .map(|shard| {
+            SealedShard::parse(shard)
+                .context("Approved shard is not sealed")??
+                .open(identity)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((outgoing.cid.clone(), shards))
+}
+
+/// Forget one of our requests, e.g. once the drop is retrieved
+pub fn forget_request(data_dir: &Path, identity: &Identity, request_id: &str) -> Result<bool> {
+    let mut store = EscrowStore::load(data_dir, identity)?;
+    let removed = store.state.outgoing.remove(request_id).is_some();
+    if removed {
+        store.save()?;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shards::{recover_session_key, split_session_key};
+
+    /// An outgoing request for a 2 of 3 drop, as `request_release` would
+    /// store it after fetching the manifest
+    fn store_request(dir: &Path, requester: &Identity, approvers: &[Identity]) {
+        let request = ReleaseRequest {
+            request_id: "r1".to_string(),
+            manifest_cid: "bafkreimanifest".to_string(),
+            requester: requester.public_id(),
+            reason: "Audit".to_string(),
+            issued_at: now_secs(),
+        };
+        let mut store = EscrowStore::load(dir, requester).unwrap();
+        store.state.outgoing.insert(
+            "r1".to_string(),
+            OutgoingRequest {
+                request,
+                cid: "bafkreidrop".to_string(),
+                threshold: 2,
+                approvers: approvers.iter().map(Identity::public_id).collect(),
+                shards: BTreeMap::new(),
+                denied: BTreeMap::new(),
+            },
+        );
+        store.save().unwrap();
+    }
+
+    fn approval(approver: &Identity, shard: &str, sealed_to: &Identity) -> SignedEscrowStatement {
+        let sealed = seal_shards(&[shard.to_string()], &[sealed_to.public_id()]).unwrap();
+        let decision = ReleaseDecision {
+            request_id: "r1".to_string(),
+            approver: approver.public_id(),
+            approved: true,
+            shard: sealed.into_iter().next(),
+            reason: String::new(),
+            decided_at: now_secs(),
+        };
+        SignedEscrowStatement::sign(approver, &decision).unwrap()
+    }
+
+    #[test]
+    fn statements_verify_only_untouched() {
+        let identity = Identity::generate();
+        let request = ReleaseRequest {
+            request_id: "r1".to_string(),
+            manifest_cid: "bafkreiabc".to_string(),
+            requester: identity.public_id(),
+            reason: "Audit".to_string(),
+            issued_at: 1,
+        };
+        let signed = SignedEscrowStatement::sign(&identity, &request).unwrap();
+        assert_eq!(signed.verify::<ReleaseRequest>().unwrap(), request);
+
+        let mut tampered = signed.clone();
+        tampered.statement = tampered.statement.replace("Audit", "Other");
+        assert!(tampered.verify::<ReleaseRequest>().is_err());
+    }
+
+    #[test]
+    fn approvals_from_outsiders_or_sealed_elsewhere_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let requester = Identity::generate();
+        let approvers: Vec<_> = (0..3).map(|_| Identity::generate()).collect();
+        let outsider = Identity::generate();
+        store_request(dir.path(), &requester, &approvers);
+        let shards = split_session_key(&SessionKey::generate(), 2, 3);
+
+        let from_outsider = approval(&outsider, &shards[0], &requester);
+        let err = receive_decision(
+            dir.path(),
+            &requester,
+            &outsider.public_id(),
+            &from_outsider,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("is not an approver"));
+
+        let sealed_elsewhere = approval(&approvers[0], &shards[0], &outsider);
+        let err = receive_decision(
+            dir.path(),
+            &requester,
+            &approvers[0].public_id(),
+            &sealed_elsewhere,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Approved shard is sealed to someone else");
+
+        let status = requests(dir.path(), &requester).unwrap().remove(0);
+        assert!(status.approved.is_empty());
+    }
+
+    #[test]
+    fn assembles_once_threshold_is_reached() {
+        let dir = tempfile::tempdir().unwrap();
+        let requester = Identity::generate();
+        let approvers: Vec<_> = (0..3).map(|_| Identity::generate()).collect();
+        store_request(dir.path(), &requester, &approvers);
+        let key = SessionKey::generate();
+        let shards = split_session_key(&key, 2, 3);
+
+        let first = approval(&approvers[0], &shards[0], &requester);
+        let status = receive_decision(dir.path(), &requester, &approvers[0].public_id(), &first)
+            .unwrap()
+            .unwrap();
+        assert!(!status.ready);
+        let err = assemble(dir.path(), &requester, "r1").unwrap_err();
+        assert_eq!(err.to_string(), "1 of 2 approvals so far");
+
+        let second = approval(&approvers[2], &shards[2], &requester);
+        let status = receive_decision(dir.path(), &requester, &approvers[2].public_id(), &second)
+            .unwrap()
+            .unwrap();
+        assert!(status.ready);
+        let (cid, opened) = assemble(dir.path(), &requester, "r1").unwrap();
+        assert_eq!(cid, "bafkreidrop");
+        assert_eq!(
+            recover_session_key(&opened).unwrap().as_bytes(),
+            key.as_bytes()
+        );
+    }
+}
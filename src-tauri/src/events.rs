@@ -0,0 +1,106 @@
+use crate::catalog::now_secs;
+use crate::webhooks;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+use tauri::{AppHandle, Manager};
+use tracing::warn;
+
+/// Bumped whenever an event payload changes shape
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+const JOURNAL_CAPACITY: usize = 500;
+
+/// What every journaled event carries to the frontend
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EventEnvelope {
+    pub schema_version: u32,
+    pub seq: u64,
+    pub event: String,
+    pub emitted_at: u64,
+    pub payload: Value,
+}
+
+/// Reply to `get_events_since`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EventReplay {
+    pub schema_version: u32,
+    /// Changes when the backend restarts and sequence numbers start over
+    pub boot_id: String,
+    pub latest_seq: u64,
+    /// Some events after the requested one have already been dropped
+    pub truncated: bool,
+    pub events: Vec<EventEnvelope>,
+}
+
+struct Journal {
+    next_seq: u64,
+    entries: VecDeque<EventEnvelope>,
+}
+
+static JOURNAL: Mutex<Journal> = Mutex::new(Journal {
+    next_seq: 1,
+    entries: VecDeque::new(),
+});
+static BOOT_ID: OnceLock<String> = OnceLock::new();
+
+fn boot_id() -> &'static str {
+    BOOT_ID.get_or_init(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Record an event in the journal and emit it to the frontend wrapped in an
+/// `EventEnvelope`. High-frequency transient events such as `drop_progress`
+/// are emitted directly instead.
+pub fn emit<T: Serialize>(app: &AppHandle, event: &str, payload: T) {
+    match serde_json::to_value(payload) {
+        Ok(payload) => emit_value(app, event, payload),
+        Err(e) => warn!("Failed to serialize {} event: {}", event, e),
+    }
+}
+
+pub fn emit_value(app: &AppHandle, event: &str, payload: Value) {
+    webhooks::fire(event, &payload);
+    let envelope = {
+        let mut journal = JOURNAL.lock().unwrap();
+        let envelope = EventEnvelope {
+            schema_version: EVENT_SCHEMA_VERSION,
+            seq: journal.next_seq,
+            event: event.to_string(),
+            emitted_at: now_secs(),
+            payload,
+        };
+        journal.next_seq += 1;
+        if journal.entries.len() == JOURNAL_CAPACITY {
+            journal.entries.pop_front();
+        }
+        journal.entries.push_back(envelope.clone());
+        envelope
+    };
+
+    let _ = app.emit_all(event, envelope);
+}
+
+/// Journaled events after `seq`, for the frontend to catch up after a reload
+pub fn events_since(seq: u64) -> EventReplay {
+    let journal = JOURNAL.lock().unwrap();
+    let oldest = journal.entries.front().map_or(journal.next_seq, |e| e.seq);
+
+    EventReplay {
+        schema_version: EVENT_SCHEMA_VERSION,
+        boot_id: boot_id().to_string(),
+        latest_seq: journal.next_seq - 1,
+        truncated: seq + 1 < oldest,
+        events: journal
+            .entries
+            .iter()
+            .filter(|e| e.seq > seq)
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Forget journaled events, so message contents aren't replayable after a lock.
+/// Sequence numbers keep counting.
+pub fn clear() {
+    JOURNAL.lock().unwrap().entries.clear();
+}
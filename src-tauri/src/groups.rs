@@ -0,0 +1,454 @@
+use crate::audit::{self, AuditAction};
+use crate::catalog::{now_secs, DropCatalog, DropRecord};
+use crate::crypto::{decode_public_key, Identity, SessionKey};
+use crate::dead_drop::{upload_bytes_to_ipfs, DeadDropCreated};
+use crate::mfs;
+use crate::no_traces;
+use crate::p2p::P2PCommand;
+use crate::session::AppState;
+use crate::shards::{seal_shards, SealedShard};
+use crate::wire;
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::Manager;
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+const GROUPS_FILE: &str = "groups.enc";
+pub const MANIFEST_VERSION: u32 = 1;
+const MAX_NAME_LEN: usize = 64;
+/// Every member holds one shard, and shard counts are a u8
+const MAX_MEMBERS: usize = 255;
+
+/// Named set of contacts a drop can be shared with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub id: String,
+    pub name: String,
+    pub members: Vec<String>,
+    pub updated_at: u64,
+}
+
+/// Who may open a group drop: each member holds one shard and any
+/// `threshold` of them together can recover the key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AccessPolicy {
+    pub owner: String,
+    pub group_id: String,
+    pub group_name: String,
+    pub members: Vec<String>,
+    pub threshold: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GroupManifest {
+    pub version: u32,
+    pub cid: String,
+    pub policy: AccessPolicy,
+    pub created_at: u64,
+}
+
+/// Manifest as uploaded and sent to members, signed by the drop owner
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedManifest {
+    /// JSON of a `GroupManifest`, signed byte for byte
+    pub manifest: String,
+    pub signature: String,   // hex
+    pub signing_key: String, // hex, the owner's ed25519 key
+}
+
+/// One member's shard of a group drop
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MemberShard {
+    pub public_id: String,
+    /// Sealed to the member, so it is safe to keep and resend
+    pub shard: String,
+    pub sent_at: Option<u64>,
+    pub confirmed_at: Option<u64>,
+}
+
+/// Catalog entry of a drop shared with a group
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupDrop {
+    pub group_id: String,
+    pub group_name: String,
+    pub manifest_cid: String,
+    pub manifest: SignedManifest,
+    pub members: Vec<MemberShard>,
+}
+
+impl GroupDrop {
+    /// Record that `member` has their shard; returns when, or None if they
+    /// are not a member
+    pub fn confirm(&mut self, member: &str) -> Option<u64> {
+        let entry = self.members.iter_mut().find(|m| m.public_id == member)?;
+        Some(*entry.confirmed_at.get_or_insert_with(now_secs))
+    }
+}
+
+/// A member's shard as sent over Ghost Mode
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupShard {
+    pub manifest_cid: String,
+    pub manifest: SignedManifest,
+    pub shard: String,
+}
+
+/// A shard of someone else's group drop held by us
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReceivedShard {
+    pub cid: String,
+    pub manifest_cid: String,
+    pub policy: AccessPolicy,
+    /// Still sealed to us; `retrieve_drop` opens it
+    pub shard: String,
+    pub received_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct GroupState {
+    groups: Vec<Group>,
+    received: Vec<ReceivedShard>,
+}
+
+/// Groups and the group shards we hold, encrypted with the local identity
+pub struct GroupStore {
+    path: PathBuf,
+    storage_key: SessionKey,
+    state: GroupState,
+}
+
+impl GroupStore {
+    /// Load the store, starting empty if it does not exist yet
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(GROUPS_FILE);
+        let storage_key = identity.storage_key(b"groups");
+
+        let state = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read groups")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt groups")?;
+            let state = serde_json::from_slice(&json);
+            json.zeroize();
+            state.context("Failed to parse groups")?
+        } else {
+            GroupState::default()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            state,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut json = serde_json::to_vec(&self.state)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write groups")?;
+        Ok(())
+    }
+
+    pub fn groups(&self) -> &[Group] {
+        &self.state.groups
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Group> {
+        self.state.groups.iter().find(|g| g.id == id)
+    }
+
+    /// Create a group, or update it when `id` is given
+    pub fn upsert(&mut self, id: Option<&str>, name: &str, members: &[String]) -> Result<Group> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            anyhow::bail!("Group name must be 1 to {} characters", MAX_NAME_LEN);
+        }
+        let mut unique: Vec<String> = Vec::with_capacity(members.len());
+        for member in members {
+            decode_public_key(member)
+                .with_context(|| format!("Invalid member public ID {}", member))?;
+            if !unique.contains(member) {
+                unique.push(member.clone());
+            }
+        }
+        if unique.len() < 2 || unique.len() > MAX_MEMBERS {
+            anyhow::bail!("A group needs 2 to {} members", MAX_MEMBERS);
+        }
+
+        let id = match id {
+            Some(id) => {
+                self.get(id).context("No such group")?;
+                id.to_string()
+            }
+            None => uuid::Uuid::new_v4().to_string(),
+        };
+        let group = Group {
+            id,
+            name: name.to_string(),
+            members: unique,
+            updated_at: now_secs(),
+        };
+        self.state.groups.retain(|g| g.id != group.id);
+        self.state.groups.push(group.clone());
+        Ok(group)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<Group> {
+        let index = self.state.groups.iter().position(|g| g.id == id)?;
+        Some(self.state.groups.remove(index))
+    }
+
+    pub fn received(&self) -> &[ReceivedShard] {
+        &self.state.received
+    }
+
+    /// Keep a shard, replacing an earlier delivery for the same drop
+    pub fn receive(&mut self, shard: ReceivedShard) {
+        self.state.received.retain(|s| s.cid != shard.cid);
+        self.state.received.push(shard);
+    }
+}
+
+/// Sign the access policy of a group drop and upload it. Only members learn
+/// the manifest CID.
+pub async fn publish_manifest(
+    identity: &Identity,
+    cid: &str,
+    group: &Group,
+    threshold: u8,
+) -> Result<(String, SignedManifest)> {
+    let manifest = serde_json::to_string(&GroupManifest {
+        version: MANIFEST_VERSION,
+        cid: cid.to_string(),
+        policy: AccessPolicy {
+            owner: identity.public_id(),
+            group_id: group.id.clone(),
+            group_name: group.name.clone(),
+            members: group.members.clone(),
+            threshold,
+        },
+        created_at: now_secs(),
+    })?;
+    let signing_key = identity.signing_key();
+    let signature = signing_key.sign(manifest.as_bytes());
+    let signed = SignedManifest {
+        manifest,
+        signature: hex::encode(signature.to_bytes()),
+        signing_key: hex::encode(signing_key.verifying_key().to_bytes()),
+    };
+
+    let manifest_cid = upload_bytes_to_ipfs(wire::encode(&signed)?).await?;
+    info!("Group manifest for {} uploaded as {}", cid, manifest_cid);
+    Ok((manifest_cid, signed))
+}
+
+/// Group for an automated drop, checked against the threshold it will use
+pub fn load_recipient_group(
+    data_dir: &Path,
+    identity: &Identity,
+    group_id: &str,
+    threshold: u8,
+) -> Result<Group> {
+    let store = GroupStore::load(data_dir, identity)?;
+    let group = store.get(group_id).cloned().context("No such group")?;
+    if threshold as usize > group.members.len() {
+        anyhow::bail!("Threshold cannot exceed the group size");
+    }
+    Ok(group)
+}
+
+/// Finish a drop created in the background for `group`: seal one shard to
+/// each member, publish the manifest, then catalog, audit and link the drop.
+/// Shards go out at once if Ghost Mode is running and can be resent later.
+pub async fn record_group_drop(
+    app: &tauri::AppHandle,
+    data_dir: &Path,
+    identity: &Identity,
+    group: &Group,
+    threshold: u8,
+    file_name: String,
+    created: DeadDropCreated,
+) -> Result<DropRecord> {
+    let shards = seal_shards(&created.shards, &group.members)?;
+    let (manifest_cid, manifest) =
+        publish_manifest(identity, &created.cid, group, threshold).await?;
+
+    let record = DropRecord {
+        cid: created.cid.clone(),
+        file_name,
+        threshold,
+        total_shards: shards.len() as u8,
+        created_at: now_secs(),
+        expires_at: None,
+        pinned: true,
+        drop_id: None,
+        piece_cids: created.piece_cids.clone(),
+        reshared_at: None,
+        group: Some(GroupDrop {
+            group_id: group.id.clone(),
+            group_name: group.name.clone(),
+            manifest_cid,
+            manifest,
+            members: group
+                .members
+                .iter()
+                .zip(shards)
+                .map(|(member, shard)| MemberShard {
+                    public_id: member.clone(),
+                    shard,
+                    sent_at: None,
+                    confirmed_at: None,
+                })
+                .collect(),
+        }),
+        imported: false,
+        cipher: created.cipher,
+    };
+    let mut catalog = DropCatalog::load(data_dir)?;
+    catalog.insert(record.clone());
+    catalog.save()?;
+
+    audit::record(
+        data_dir,
+        AuditAction::Create,
+        &record.cid,
+        serde_json::json!({
+            "file_name": record.file_name,
+            "threshold": threshold,
+            "total_shards": record.total_shards,
+            "group": group.id,
+            "sealed": true,
+            "pending": created.pending,
+            "automated": true,
+        }),
+        None,
+    );
+    if !created.pending {
+        if let Err(e) = mfs::link_drop(&record).await {
+            warn!("Drop {} not linked into MFS: {}", record.cid, e);
+        }
+    }
+
+    if let Ok(sender) = app.state::<AppState>().p2p_sender() {
+        let (reply, _) = tokio::sync::oneshot::channel();
+        let command = P2PCommand::SendGroupShards {
+            cid: record.cid.clone(),
+            reply,
+        };
+        if sender.send(command).await.is_err() {
+            warn!("Group shards of {} not sent", record.cid);
+        }
+    }
+    Ok(record)
+}
+
+/// Check the owner's signature and return the manifest
+pub fn verify_manifest(signed: &SignedManifest) -> Result<GroupManifest> {
+    let key_bytes: [u8; 32] = hex::decode(&signed.signing_key)
+        .context("Invalid manifest key")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Manifest key has the wrong length"))?;
+    let key = VerifyingKey::from_bytes(&key_bytes).context("Invalid manifest key")?;
+    let signature = hex::decode(&signed.signature).context("Invalid manifest signature")?;
+    let signature = Signature::from_slice(&signature).context("Invalid manifest signature")?;
+    key.verify(signed.manifest.as_bytes(), &signature)
+        .context("Manifest signature does not match")?;
+
+    let manifest: GroupManifest =
+        serde_json::from_str(&signed.manifest).context("Invalid group manifest")?;
+    if manifest.version > MANIFEST_VERSION {
+        anyhow::bail!(
+            "Group manifest version {} is not supported",
+            manifest.version
+        );
+    }
+    Ok(manifest)
+}
+
+/// Check a shard delivered by `sender` and turn it into one we hold. The
+/// sender must be the owner named in the manifest, and the shard must open
+/// with our identity.
+pub fn accept_group_shard(
+    identity: &Identity,
+    sender: &str,
+    delivery: GroupShard,
+) -> Result<ReceivedShard> {
+    let manifest = verify_manifest(&delivery.manifest)?;
+    let policy = manifest.policy;
+    if policy.owner != sender {
+        anyhow::bail!("Group shard for a drop owned by {}", policy.owner);
+    }
+    if !policy.members.contains(&identity.public_id()) {
+        anyhow::bail!("Not a member of group {}", policy.group_name);
+    }
+
+    let sealed = SealedShard::parse(&delivery.shard).context("Group shard is not sealed")??;
+    let mut opened = sealed.open(identity)?;
+    opened.zeroize();
+
+    Ok(ReceivedShard {
+        cid: manifest.cid,
+        manifest_cid: delivery.manifest_cid,
+        policy,
+        shard: delivery.shard,
+        received_at: now_secs(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shards::{seal_shards, split_session_key};
+
+    #[test]
+    fn accepts_only_own_shard_from_owner() {
+        let owner = Identity::generate();
+        let member = Identity::generate();
+        let other = Identity::generate();
+        let members = vec![member.public_id(), other.public_id()];
+
+        let manifest = serde_json::to_string(&GroupManifest {
+            version: MANIFEST_VERSION,
+            cid: "bafkreiabc".to_string(),
+            policy: AccessPolicy {
+                owner: owner.public_id(),
+                group_id: "g".to_string(),
+                group_name: "Team".to_string(),
+                members: members.clone(),
+                threshold: 2,
+            },
+            created_at: 0,
+        })
+        .unwrap();
+        let signing_key = owner.signing_key();
+        let signed = SignedManifest {
+            signature: hex::encode(signing_key.sign(manifest.as_bytes()).to_bytes()),
+            signing_key: hex::encode(signing_key.verifying_key().to_bytes()),
+            manifest,
+        };
+        let shards = split_session_key(&SessionKey::generate(), 2, 2);
+        let sealed = seal_shards(&shards, &members).unwrap();
+
+        let delivery = |shard: &str| GroupShard {
+            manifest_cid: "bafkreimanifest".to_string(),
+            manifest: signed.clone(),
+            shard: shard.to_string(),
+        };
+        let received =
+            accept_group_shard(&member, &owner.public_id(), delivery(&sealed[0])).unwrap();
+        assert_eq!(received.cid, "bafkreiabc");
+        assert_eq!(received.policy.threshold, 2);
+
+        assert!(accept_group_shard(&member, &owner.public_id(), delivery(&sealed[1])).is_err());
+        assert!(accept_group_shard(&member, &other.public_id(), delivery(&sealed[0])).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use crate::no_traces;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Split a hash-chained log (the drop audit log, the key log) into
+/// `[u32 LE length][nonce | ciphertext]` records, each including its length
+/// prefix. `log` names the log in errors.
+pub(crate) fn read_records<'a>(bytes: &'a [u8], log: &str) -> Result<Vec<&'a [u8]>> {
+    let mut records = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            anyhow::bail!("Record in the {} is truncated", log);
+        }
+        let len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+        if rest.len() < 4 + len {
+            anyhow::bail!("Record in the {} is truncated", log);
+        }
+        records.push(&rest[..4 + len]);
+        rest = &rest[4 + len..];
+    }
+    Ok(records)
+}
+
+/// SHA-256 of a record as stored, hex; the next entry's `prev`
+pub(crate) fn record_hash(record: &[u8]) -> String {
+    hex::encode(Sha256::digest(record))
+}
+
+/// Frame an encrypted entry and append it to the file
+pub(crate) fn append_record(path: &Path, encrypted: &[u8], log: &str) -> Result<()> {
+    let mut record = (encrypted.len() as u32).to_le_bytes().to_vec();
+    record.extend_from_slice(encrypted);
+
+    no_traces::append_file(path, &record).with_context(|| format!("Failed to write {}", log))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_framed_records() {
+        let mut bytes = 3u32.to_le_bytes().to_vec();
+        bytes.extend_from_slice(b"abc");
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.push(b'd');
+
+        let records = read_records(&bytes, "test log").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(&records[1][4..], b"d");
+        assert!(read_records(&bytes[..bytes.len() - 1], "test log").is_err());
+    }
+
+    #[test]
+    fn appends_framed_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.enc");
+        append_record(&path, b"abc", "test log").unwrap();
+        append_record(&path, b"d", "test log").unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let records = read_records(&bytes, "test log").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(&records[0][4..], b"abc");
+    }
+}
@@ -0,0 +1,446 @@
+use crate::catalog::now_secs;
+use crate::events;
+use crate::ipfs;
+use crate::p2p::{relay_peer_id, P2PCommand, P2PStatus};
+use crate::session::AppState;
+use crate::settings;
+use anyhow::{Context, Result};
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::Manager;
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+const PROBE_INTERVAL_SECS: u64 = 120;
+/// Cached reports younger than this are returned without probing again
+const CACHE_MAX_AGE_SECS: u64 = 60;
+const PROBE_TIMEOUT_SECS: u64 = 10;
+// Empty UnixFS directory: every gateway can serve it without a network lookup
+const GATEWAY_PROBE_CID: &str = "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354";
+
+/// Last report and when it was taken
+static CACHE: Mutex<Option<(Instant, InfrastructureHealth)>> = Mutex::new(None);
+/// Only one probe runs at a time; callers arriving mid-probe get its result
+static PROBE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Down,
+    /// Not configured or not checked (e.g. relays while Ghost Mode is off)
+    Unknown,
+}
+
+/// Result of probing one piece of infrastructure
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ComponentHealth {
+    pub name: String,
+    pub status: HealthStatus,
+    pub latency_ms: Option<u64>,
+    pub detail: Option<String>,
+}
+
+impl ComponentHealth {
+    fn new(name: &str, status: HealthStatus) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            latency_ms: None,
+            detail: None,
+        }
+    }
+
+    fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// Relay from the P2P settings: reachable over TCP, and reserved if Ghost Mode runs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RelayHealth {
+    #[serde(flatten)]
+    pub health: ComponentHealth,
+    /// `None` when Ghost Mode is not running
+    pub reserved: Option<bool>,
+}
+
+/// IPFS node bootstrap peers and how many of them it is connected to
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BootstrapHealth {
+    pub status: HealthStatus,
+    pub configured: usize,
+    pub connected: usize,
+    pub swarm_peers: usize,
+}
+
+impl BootstrapHealth {
+    fn unknown() -> Self {
+        Self {
+            status: HealthStatus::Unknown,
+            configured: 0,
+            connected: 0,
+            swarm_peers: 0,
+        }
+    }
+}
+
+/// Everything the app depends on, probed together
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InfrastructureHealth {
+    pub checked_at: u64,
+    /// Worst status among the parts needed to create and retrieve drops
+    pub overall: HealthStatus,
+    pub ipfs: ComponentHealth,
+    pub gateways: Vec<ComponentHealth>,
+    pub pinning_services: Vec<ComponentHealth>,
+    pub relays: Vec<RelayHealth>,
+    pub bootstrap: BootstrapHealth,
+}
+
+/// IPFS daemon version string, or an error if the API doesn't answer
+pub async fn ipfs_version(client: &reqwest::Client) -> Result<String> {
+    let response = client
+        .post(ipfs::api("version"))
+        .send()
+        .await
+        .context("IPFS not running")?;
+    if !response.status().is_success() {
+        anyhow::bail!("IPFS returned error: {}", response.status());
+    }
+    Ok(response.text().await.unwrap_or_default())
+}
+
+/// Cached report if recent enough, otherwise a fresh probe
+pub async fn infrastructure_health(
+    p2p: Option<tokio::sync::mpsc::Sender<P2PCommand>>,
+    refresh: bool,
+) -> Result<InfrastructureHealth> {
+    let max_age = Duration::from_secs(CACHE_MAX_AGE_SECS);
+    if !refresh {
+        if let Some(report) = cached(max_age) {
+            return Ok(report);
+        }
+    }
+
+    let _guard = PROBE_LOCK.lock().await;
+    // Another caller may have probed while we waited
+    if let Some(report) = cached(max_age).filter(|_| !refresh) {
+        return Ok(report);
+    }
+
+    let report = probe(p2p).await?;
+    *CACHE.lock().unwrap() = Some((Instant::now(), report.clone()));
+    Ok(report)
+}
+
+fn cached(max_age: Duration) -> Option<InfrastructureHealth> {
+    CACHE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .filter(|(taken, _)| taken.elapsed() < max_age)
+        .map(|(_, report)| report.clone())
+}
+
+/// Re-probe in the background and emit `infrastructure_health` when the overall
+/// status changes
+pub fn start_health_prober(app: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut last_overall = None;
+        loop {
+            let p2p = app.state::<AppState>().p2p_sender().ok();
+            match infrastructure_health(p2p, true).await {
+                Ok(report) => {
+                    if last_overall != Some(report.overall) {
+                        info!("Infrastructure health: {:?}", report.overall);
+                        last_overall = Some(report.overall);
+                        events::emit(&app, "infrastructure_health", &report);
+                    }
+                }
+                Err(e) => warn!("Infrastructure probe failed: {}", e),
+            }
+            tokio::time::sleep(Duration::from_secs(PROBE_INTERVAL_SECS)).await;
+        }
+    });
+}
+
+async fn probe(p2p: Option<tokio::sync::mpsc::Sender<P2PCommand>>) -> Result<InfrastructureHealth> {
+    let client = ipfs::client()?;
+    let timeout = Duration::from_secs(PROBE_TIMEOUT_SECS);
+
+    let ipfs = check_ipfs(&client).await;
+    let ipfs_up = ipfs.status == HealthStatus::Healthy;
+
+    let mut gateways = Vec::new();
+    for gateway in ipfs::gateways() {
+        gateways.push(check_gateway(&client, &gateway, timeout).await);
+    }
+
+    let pinning_services = if ipfs_up {
+        check_pinning_services(&client).await
+    } else {
+        Vec::new()
+    };
+
+    let bootstrap = if ipfs_up {
+        check_bootstrap(&client).await.unwrap_or_else(|e| {
+            warn!("Bootstrap check failed: {}", e);
+            BootstrapHealth::unknown()
+        })
+    } else {
+        BootstrapHealth::unknown()
+    };
+
+    let relays = check_relays(p2p, timeout).await;
+
+    let overall = overall_status(&ipfs, &gateways, &pinning_services);
+    Ok(InfrastructureHealth {
+        checked_at: now_secs(),
+        overall,
+        ipfs,
+        gateways,
+        pinning_services,
+        relays,
+        bootstrap,
+    })
+}
+
+/// The local node is required; gateways and pinning services only degrade
+fn overall_status(
+    ipfs: &ComponentHealth,
+    gateways: &[ComponentHealth],
+    pinning_services: &[ComponentHealth],
+) -> HealthStatus {
+    if ipfs.status != HealthStatus::Healthy {
+        return HealthStatus::Down;
+    }
+    let all_fine = gateways
+        .iter()
+        .chain(pinning_services)
+        .all(|c| c.status == HealthStatus::Healthy);
+    if all_fine {
+        HealthStatus::Healthy
+    } else {
+        HealthStatus::Degraded
+    }
+}
+
+async fn check_ipfs(client: &reqwest::Client) -> ComponentHealth {
+    let started = Instant::now();
+    let mut health = match ipfs_version(client).await {
+        Ok(version) => ComponentHealth::new("ipfs", HealthStatus::Healthy).with_detail(version),
+        Err(e) => ComponentHealth::new("ipfs", HealthStatus::Down).with_detail(format!("{:#}", e)),
+    };
+    health.latency_ms = Some(started.elapsed().as_millis() as u64);
+    health
+}
+
+async fn check_gateway(
+    client: &reqwest::Client,
+    gateway: &str,
+    timeout: Duration,
+) -> ComponentHealth {
+    let url = format!(
+        "{}/ipfs/{}",
+        gateway.trim_end_matches('/'),
+        GATEWAY_PROBE_CID
+    );
+    let started = Instant::now();
+
+    let mut health = match client.head(&url).timeout(timeout).send().await {
+        Ok(response) if response.status().is_success() => {
+            ComponentHealth::new(gateway, HealthStatus::Healthy)
+        }
+        Ok(response) => ComponentHealth::new(gateway, HealthStatus::Degraded)
+            .with_detail(format!("HTTP {}", response.status())),
+        Err(e) => ComponentHealth::new(gateway, HealthStatus::Down).with_detail(e.to_string()),
+    };
+    health.latency_ms = Some(started.elapsed().as_millis() as u64);
+    health
+}
+
+/// Pinning services from the pin health settings, as registered on the node
+async fn check_pinning_services(client: &reqwest::Client) -> Vec<ComponentHealth> {
+    let configured = settings::pin_health_settings().pinning_services;
+    if configured.is_empty() {
+        return Vec::new();
+    }
+
+    let registered = match list_pinning_services(client).await {
+        Ok(registered) => registered,
+        Err(e) => {
+            return configured
+                .iter()
+                .map(|name| {
+                    ComponentHealth::new(name, HealthStatus::Unknown).with_detail(e.to_string())
+                })
+                .collect()
+        }
+    };
+
+    configured
+        .iter()
+        .map(|name| match registered.iter().find(|(n, _)| n == name) {
+            None => ComponentHealth::new(name, HealthStatus::Down)
+                .with_detail("Not registered on the IPFS node"),
+            Some((_, stat)) if stat == "valid" => ComponentHealth::new(name, HealthStatus::Healthy),
+            Some((_, stat)) => ComponentHealth::new(name, HealthStatus::Degraded)
+                .with_detail(format!("Service status: {}", stat)),
+        })
+        .collect()
+}
+
+/// (name, stat status) of each remote pinning service registered on the node
+async fn list_pinning_services(client: &reqwest::Client) -> Result<Vec<(String, String)>> {
+    let response = client
+        .post(ipfs::api("pin/remote/service/ls"))
+        .query(&[("stat", "true")])
+        .send()
+        .await
+        .context("Failed to list pinning services")?;
+    if !response.status().is_success() {
+        anyhow::bail!("Pinning service list failed: {}", response.status());
+    }
+
+    let json: serde_json::Value = response.json().await?;
+    Ok(json["RemoteServices"]
+        .as_array()
+        .map(|services| {
+            services
+                .iter()
+                .filter_map(|s| {
+                    let name = s["Service"].as_str()?.to_string();
+                    let stat = s["Stat"]["Status"]
+                        .as_str()
+                        .unwrap_or("unknown")
+                        .to_string();
+                    Some((name, stat))
+                })
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// How many of the node's bootstrap peers it is currently connected to
+async fn check_bootstrap(client: &reqwest::Client) -> Result<BootstrapHealth> {
+    let response = client
+        .post(ipfs::api("bootstrap/list"))
+        .send()
+        .await
+        .context("Failed to list bootstrap peers")?;
+    let json: serde_json::Value = response.json().await?;
+    let bootstrap_peers = json["Peers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|p| relay_peer_id(p.as_str()?))
+        .collect::<HashSet<_>>();
+
+    let response = client
+        .post(ipfs::api("swarm/peers"))
+        .send()
+        .await
+        .context("Failed to list swarm peers")?;
+    let json: serde_json::Value = response.json().await?;
+    let swarm_peers = json["Peers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|p| p["Peer"].as_str()?.parse().ok())
+        .collect::<HashSet<_>>();
+
+    let connected = bootstrap_peers.intersection(&swarm_peers).count();
+    let status = if swarm_peers.is_empty() {
+        HealthStatus::Down
+    } else if connected == 0 && !bootstrap_peers.is_empty() {
+        HealthStatus::Degraded
+    } else {
+        HealthStatus::Healthy
+    };
+
+    Ok(BootstrapHealth {
+        status,
+        configured: bootstrap_peers.len(),
+        connected,
+        swarm_peers: swarm_peers.len(),
+    })
+}
+
+/// TCP reachability of each configured relay, plus its reservation state
+/// when the P2P actor is running
+async fn check_relays(
+    p2p: Option<tokio::sync::mpsc::Sender<P2PCommand>>,
+    timeout: Duration,
+) -> Vec<RelayHealth> {
+    let status = match p2p {
+        Some(sender) => {
+            let (reply, response) = oneshot::channel::<P2PStatus>();
+            match sender.send(P2PCommand::Status { reply }).await {
+                Ok(()) => tokio::time::timeout(timeout, response)
+                    .await
+                    .ok()
+                    .and_then(Result::ok),
+                Err(_) => None,
+            }
+        }
+        None => None,
+    };
+
+    let mut relays = Vec::new();
+    for addr in settings::p2p_settings().relays {
+        let reserved = status.as_ref().map(|s| {
+            relay_peer_id(&addr).is_some_and(|peer_id| s.reserved_relays.contains(&peer_id))
+        });
+
+        let started = Instant::now();
+        let mut health =
+            match tcp_target(&addr) {
+                Some(target) => {
+                    match tokio::time::timeout(timeout, TcpStream::connect(&target)).await {
+                        Ok(Ok(_)) if reserved == Some(false) => {
+                            ComponentHealth::new(&addr, HealthStatus::Degraded)
+                                .with_detail("Reachable but no reservation")
+                        }
+                        Ok(Ok(_)) => ComponentHealth::new(&addr, HealthStatus::Healthy),
+                        Ok(Err(e)) => ComponentHealth::new(&addr, HealthStatus::Down)
+                            .with_detail(e.to_string()),
+                        Err(_) => {
+                            ComponentHealth::new(&addr, HealthStatus::Down).with_detail("Timed out")
+                        }
+                    }
+                }
+                None => ComponentHealth::new(&addr, HealthStatus::Unknown)
+                    .with_detail("Not a TCP address"),
+            };
+        health.latency_ms = Some(started.elapsed().as_millis() as u64);
+        relays.push(RelayHealth { health, reserved });
+    }
+    relays
+}
+
+/// `host:port` for a relay multiaddr over TCP
+fn tcp_target(addr: &str) -> Option<String> {
+    let addr = addr.parse::<Multiaddr>().ok()?;
+    let mut host = None;
+    let mut port = None;
+    for protocol in addr.iter() {
+        match protocol {
+            Protocol::Ip4(ip) => host = Some(ip.to_string()),
+            Protocol::Ip6(ip) => host = Some(format!("[{}]", ip)),
+            Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name) => {
+                host = Some(name.to_string())
+            }
+            Protocol::Tcp(p) => port = Some(p),
+            _ => {}
+        }
+    }
+    Some(format!("{}:{}", host?, port?))
+}
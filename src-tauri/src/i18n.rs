@@ -0,0 +1,132 @@
+use crate::settings;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+use tracing::warn;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Built-in catalogs; English is the fallback for anything a locale lacks
+const CATALOG_SOURCES: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.json")),
+    ("de", include_str!("../locales/de.json")),
+];
+
+#[derive(Deserialize)]
+struct CatalogFile {
+    name: String,
+    messages: HashMap<String, String>,
+}
+
+/// A locale the backend can serve
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LocaleInfo {
+    pub code: String,
+    pub name: String,
+}
+
+/// Every message in one locale, for the frontend
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageCatalog {
+    pub locale: String,
+    pub messages: BTreeMap<String, String>,
+}
+
+fn catalogs() -> &'static HashMap<&'static str, CatalogFile> {
+    static CATALOGS: OnceLock<HashMap<&'static str, CatalogFile>> = OnceLock::new();
+    CATALOGS.get_or_init(|| {
+        CATALOG_SOURCES
+            .iter()
+            .filter_map(|(code, json)| match serde_json::from_str(json) {
+                Ok(catalog) => Some((*code, catalog)),
+                Err(e) => {
+                    warn!("Ignoring invalid {} message catalog: {}", code, e);
+                    None
+                }
+            })
+            .collect()
+    })
+}
+
+pub fn is_supported(locale: &str) -> bool {
+    catalogs().contains_key(locale)
+}
+
+pub fn locales() -> Vec<LocaleInfo> {
+    let mut locales: Vec<LocaleInfo> = catalogs()
+        .iter()
+        .map(|(code, catalog)| LocaleInfo {
+            code: code.to_string(),
+            name: catalog.name.clone(),
+        })
+        .collect();
+    locales.sort_by(|a, b| a.code.cmp(&b.code));
+    locales
+}
+
+/// Text for `id` in `locale`, falling back to English and then to the ID itself
+pub fn translate(locale: &str, id: &str) -> String {
+    [locale, DEFAULT_LOCALE]
+        .iter()
+        .filter_map(|code| catalogs().get(code)?.messages.get(id))
+        .next()
+        .cloned()
+        .unwrap_or_else(|| {
+            warn!("Missing message {}", id);
+            id.to_string()
+        })
+}
+
+/// Text for `id` in the locale from settings
+pub fn t(id: &str) -> String {
+    translate(&settings::locale(), id)
+}
+
+/// Like `t`, replacing `{name}` placeholders with `args`
+pub fn t_args(id: &str, args: &[(&str, &str)]) -> String {
+    args.iter().fold(t(id), |text, (name, value)| {
+        text.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+/// All messages in `locale`, with English filling any gaps
+pub fn catalog(locale: &str) -> MessageCatalog {
+    let mut messages = BTreeMap::new();
+    for code in [DEFAULT_LOCALE, locale] {
+        if let Some(catalog) = catalogs().get(code) {
+            messages.extend(
+                catalog
+                    .messages
+                    .iter()
+                    .map(|(id, text)| (id.clone(), text.clone())),
+            );
+        }
+    }
+    MessageCatalog {
+        locale: locale.to_string(),
+        messages,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_locale_covers_english() {
+        let english = &catalogs()[DEFAULT_LOCALE].messages;
+        for (code, catalog) in catalogs() {
+            let missing: Vec<_> = english
+                .keys()
+                .filter(|id| !catalog.messages.contains_key(*id))
+                .collect();
+            assert!(missing.is_empty(), "{} is missing {:?}", code, missing);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_english_then_id() {
+        assert_eq!(translate("xx", "tray.lock"), "Lock");
+        assert_eq!(translate("de", "no.such.message"), "no.such.message");
+    }
+}
@@ -0,0 +1,80 @@
+use crate::crypto::Identity;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+const IDENTITY_FILE: &str = "identity.enc";
+
+/// The password-protected identity kept in a data directory
+#[derive(Debug, Clone)]
+pub struct IdentityStore {
+    data_dir: PathBuf,
+}
+
+impl IdentityStore {
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            data_dir: data_dir.into(),
+        }
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub fn exists(&self) -> bool {
+        self.data_dir.join(IDENTITY_FILE).exists()
+    }
+
+    /// Generate a new identity, refusing to replace an existing one
+    pub fn create(&self, password: &str) -> Result<Identity> {
+        if self.exists() {
+            anyhow::bail!("An identity already exists in {}", self.data_dir.display());
+        }
+        Identity::load_or_generate(password, self.data_dir.clone())
+    }
+
+    /// Unlock the existing identity
+    pub fn unlock(&self, password: &str) -> Result<Identity> {
+        if !self.exists() {
+            anyhow::bail!("No identity in {}", self.data_dir.display());
+        }
+        Identity::load_or_generate(password, self.data_dir.clone())
+            .context("Wrong password or damaged identity file")
+    }
+
+    /// Unlock the identity, generating one first if there is none
+    pub fn open(&self, password: &str) -> Result<Identity> {
+        Identity::load_or_generate(password, self.data_dir.clone())
+    }
+
+    /// Re-encrypt the identity under a new password
+    pub fn change_password(&self, password: &str, new_password: &str) -> Result<()> {
+        self.unlock(password)?.save(new_password, &self.data_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlocks_only_with_its_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = IdentityStore::new(dir.path());
+        assert!(store.unlock("hunter22").is_err());
+
+        let created = store.create("hunter22").unwrap();
+        assert!(store.create("hunter22").is_err());
+        assert_eq!(
+            store.unlock("hunter22").unwrap().public_id(),
+            created.public_id()
+        );
+        assert!(store.unlock("hunter23").is_err());
+
+        store.change_password("hunter22", "hunter23").unwrap();
+        assert_eq!(
+            store.unlock("hunter23").unwrap().public_id(),
+            created.public_id()
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use crate::catalog::now_secs;
+use crate::contacts::{Appearance, Contact, ContactBook};
+use crate::crypto::{decode_public_key, Identity, SessionKey};
+use crate::no_traces;
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
+
+const INTRODUCTIONS_FILE: &str = "introductions.enc";
+const MAX_NOTE_LEN: usize = 500;
+
+/// What the introducer vouches for: `contact` is who they say it is
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Introduction {
+    pub introducer: String,
+    pub contact: String,
+    /// What the introducer calls the contact
+    pub nickname: String,
+    #[serde(default)]
+    pub note: String,
+    pub issued_at: u64,
+}
+
+/// Introduction as sent over Ghost Mode, kept with accepted contacts so the
+/// signature can be checked again later
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedIntroduction {
+    /// JSON of an `Introduction`, signed byte for byte
+    pub statement: String,
+    pub signature: String,   // hex
+    pub signing_key: String, // hex, the introducer's ed25519 key
+}
+
+/// How a contact came to be in the address book
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub introduced_by: String,
+    pub note: String,
+    pub issued_at: u64,
+    pub accepted_at: u64,
+    pub signed: SignedIntroduction,
+}
+
+/// Introduction waiting for the user to accept or decline it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingIntroduction {
+    pub id: String,
+    pub introduction: Introduction,
+    /// Nickname of the introducer in our address book, if any
+    #[serde(default)]
+    pub introducer_nickname: Option<String>,
+    pub received_at: u64,
+    signed: SignedIntroduction,
+}
+
+/// Sign an introduction of `contact` for sending to someone else
+pub fn introduce(
+    identity: &Identity,
+    contact: &str,
+    nickname: &str,
+    note: &str,
+) -> Result<SignedIntroduction> {
+    decode_public_key(contact).context("Invalid contact public ID")?;
+    if note.chars().count() > MAX_NOTE_LEN {
+        anyhow::bail!(
+            "Introduction note is longer than {} characters",
+            MAX_NOTE_LEN
+        );
+    }
+
+    let statement = serde_json::to_string(&Introduction {
+        introducer: identity.public_id(),
+        contact: contact.to_string(),
+        nickname: nickname.trim().to_string(),
+        note: note.trim().to_string(),
+        issued_at: now_secs(),
+    })?;
+    let signing_key = identity.signing_key();
+    let signature = signing_key.sign(statement.as_bytes());
+
+    Ok(SignedIntroduction {
+        statement,
+        signature: hex::encode(signature.to_bytes()),
+        signing_key: hex::encode(signing_key.verifying_key().to_bytes()),
+    })
+}
+
+/// Check the signature and return what was signed. Whether the introducer
+/// is who they claim is up to the caller, e.g. by the Ghost Mode sender key.
+pub fn verify_introduction(signed: &SignedIntroduction) -> Result<Introduction> {
+    let key_bytes: [u8; 32] = hex::decode(&signed.signing_key)
+        .context("Invalid introducer key")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Introducer key has the wrong length"))?;
+    let key = VerifyingKey::from_bytes(&key_bytes).context("Invalid introducer key")?;
+    let signature = hex::decode(&signed.signature).context("Invalid introduction signature")?;
+    let signature = Signature::from_slice(&signature).context("Invalid introduction signature")?;
+    key.verify(signed.statement.as_bytes(), &signature)
+        .context("Introduction signature does not match")?;
+
+    let introduction: Introduction =
+        serde_json::from_str(&signed.statement).context("Invalid introduction")?;
+    decode_public_key(&introduction.contact).context("Invalid introduced public ID")?;
+    if introduction.introducer == introduction.contact {
+        anyhow::bail!("Introduction of the introducer themselves");
+    }
+    Ok(introduction)
+}
+
+/// Introductions waiting for an answer, encrypted with the local identity
+pub struct IntroductionStore {
+    path: PathBuf,
+    storage_key: SessionKey,
+    pending: Vec<PendingIntroduction>,
+}
+
+impl IntroductionStore {
+    /// Load the store, starting empty if it does not exist yet
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(INTRODUCTIONS_FILE);
+        let storage_key = identity.storage_key(b"introductions");
+
+        let pending = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read introductions")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt introductions")?;
+            let pending = serde_json::from_slice(&json);
+            json.zeroize();
+            pending.context("Failed to parse introductions")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            pending,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut json = serde_json::to_vec(&self.pending)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write introductions")?;
+        Ok(())
+    }
+
+    pub fn pending(&self) -> &[PendingIntroduction] {
+        &self.pending
+    }
+
+    /// Keep a verified introduction, replacing an earlier one of the same
+    /// contact by the same introducer
+    pub fn add(
+        &mut self,
+        introduction: Introduction,
+        signed: SignedIntroduction,
+        introducer_nickname: Option<String>,
+    ) -> PendingIntroduction {
+        let id = hex::encode(&Sha256::digest(signed.signature.as_bytes())[..8]);
+        self.pending.retain(|p| {
+            p.introduction.introducer != introduction.introducer
+                || p.introduction.contact != introduction.contact
+        });
+        let pending = PendingIntroduction {
+            id,
+            introduction,
+            introducer_nickname,
+            received_at: now_secs(),
+            signed,
+        };
+        self.pending.push(pending.clone());
+        pending
+    }
+
+    pub fn take(&mut self, id: &str) -> Option<PendingIntroduction> {
+        let index = self.pending.iter().position(|p| p.id == id)?;
+        Some(self.pending.remove(index))
+    }
+}
+
+/// Add the introduced contact, recording who introduced them. An existing
+/// contact keeps its nickname and verification and gains the provenance.
+pub fn accept_introduction(
+    data_dir: &Path,
+    identity: &Identity,
+    id: &str,
+    nickname: Option<&str>,
+) -> Result<Contact> {
+    let mut store = IntroductionStore::load(data_dir, identity)?;
+    let pending = store.take(id).context("No such introduction")?;
+    // Checked again in case the stored file was tampered with
+    let introduction = verify_introduction(&pending.signed)?;
+
+    let provenance = Provenance {
+        introduced_by: introduction.introducer.clone(),
+        note: introduction.note.clone(),
+        issued_at: introduction.issued_at,
+        accepted_at: now_secs(),
+        signed: pending.signed,
+    };
+    let mut book = ContactBook::load(data_dir, identity)?;
+    let contact = match book.get(&introduction.contact) {
+        Some(existing) => Contact {
+            introduced_by: Some(provenance),
+            updated_at: now_secs(),
+            ..existing.clone()
+        },
+        None => Contact {
+            public_id: introduction.contact.clone(),
+            nickname: nickname
+                .map(str::trim)
+                .filter(|n| !n.is_empty())
+                .unwrap_or(&introduction.nickname)
+                .to_string(),
+            verified: false,
+            updated_at: now_secs(),
+            introduced_by: Some(provenance),
+            signing_key: None,
+            relay_hints: Vec::new(),
+            proofs: Vec::new(),
+            pgp_key: None,
+            appearance: Appearance::default(),
+        },
+    };
+    book.insert(contact.clone());
+    book.save()?;
+    store.save()?;
+    Ok(contact)
+}
+
+/// Drop an introduction without adding anyone
+pub fn decline_introduction(data_dir: &Path, identity: &Identity, id: &str) -> Result<()> {
+    let mut store = IntroductionStore::load(data_dir, identity)?;
+    store.take(id).context("No such introduction")?;
+    store.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_tampered_introductions() {
+        let introducer = Identity::generate();
+        let contact = Identity::generate().public_id();
+        let signed = introduce(&introducer, &contact, "Carol", "Met at the meetup").unwrap();
+
+        let introduction = verify_introduction(&signed).unwrap();
+        assert_eq!(introduction.introducer, introducer.public_id());
+        assert_eq!(introduction.contact, contact);
+
+        let other = Identity::generate().public_id();
+        let mut tampered = signed.clone();
+        tampered.statement = tampered.statement.replace(&contact, &other);
+        assert!(verify_introduction(&tampered).is_err());
+
+        let mut forged = signed;
+        forged.signing_key = hex::encode(
+            Identity::generate()
+                .signing_key()
+                .verifying_key()
+                .to_bytes(),
+        );
+        assert!(verify_introduction(&forged).is_err());
+    }
+}
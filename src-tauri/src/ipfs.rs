@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use libp2p::identity::Keypair;
+use reqwest::multipart;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
+use tracing::info;
+use zeroize::Zeroize;
+
+const DEFAULT_API_URL: &str = "http://127.0.0.1:5001/api/v0";
+const DEFAULT_GATEWAYS: &[&str] = &["https://ipfs.io", "https://dweb.link"];
+/// Hosts reached without the Tor proxy, in `NO_PROXY` syntax
+const LOOPBACK_HOSTS: &str = "localhost,127.0.0.0/8,::1";
+
+/// Where IPFS traffic goes and how it gets there
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IpfsNetworkSettings {
+    pub api_url: String,
+    /// SOCKS5 proxy for all IPFS HTTP traffic, e.g. `socks5h://127.0.0.1:9050` for Tor
+    pub tor_proxy: Option<String>,
+    pub gateways: Vec<String>,
+}
+
+impl Default for IpfsNetworkSettings {
+    fn default() -> Self {
+        Self {
+            api_url: DEFAULT_API_URL.to_string(),
+            tor_proxy: None,
+            gateways: DEFAULT_GATEWAYS.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+}
+
+impl IpfsNetworkSettings {
+    /// Reject settings that would leak traffic outside the proxy
+    pub fn validate(&self) -> Result<()> {
+        if let Some(proxy) = &self.tor_proxy {
+            if !proxy.starts_with("socks5h://") && !proxy.starts_with("socks5://") {
+                anyhow::bail!("Tor proxy must be a socks5:// or socks5h:// URL");
+            }
+        }
+
+        let uses_onion = std::iter::once(&self.api_url)
+            .chain(&self.gateways)
+            .any(|url| is_onion_url(url));
+        if uses_onion && !self.tor_proxy.as_deref().unwrap_or("").starts_with("socks5h://") {
+            anyhow::bail!("Onion addresses require a socks5h:// Tor proxy");
+        }
+
+        Ok(())
+    }
+}
+
+fn is_onion_url(url: &str) -> bool {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.ends_with(".onion")))
+        .unwrap_or(false)
+}
+
+/// Current network settings, read live from the settings store
+pub fn network() -> IpfsNetworkSettings {
+    crate::settings::network_settings()
+}
+
+/// API in use: the managed Kubo node when enabled, otherwise the configured one
+pub fn api_url() -> String {
+    let kubo = crate::settings::kubo_settings();
+    if kubo.managed {
+        kubo.api_url()
+    } else {
+        network().api_url
+    }
+}
+
+/// Full URL for an IPFS API endpoint, e.g. `api("add")`
+pub fn api(endpoint: &str) -> String {
+    format!("{}/{}", api_url().trim_end_matches('/'), endpoint)
+}
+
+/// Gateways used for availability checks
+pub fn gateways() -> Vec<String> {
+    network().gateways
+}
+
+/// HTTP client for IPFS traffic, routed through the configured proxy.
+/// Loopback stays direct: Tor refuses it, and a local Kubo API is never
+/// seen from outside anyway.
+pub fn client() -> Result<reqwest::Client> {
+    crate::settings::check_loaded()?;
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = network().tor_proxy {
+        let proxy = reqwest::Proxy::all(&proxy)
+            .context("Invalid Tor proxy URL")?
+            .no_proxy(reqwest::NoProxy::from_string(LOOPBACK_HOSTS));
+        builder = builder.proxy(proxy);
+    }
+    builder.build().context("Failed to build IPFS HTTP client")
+}
+
+/// Raw TCP connection for traffic that isn't HTTP, through the Tor proxy if
+/// one is set. The proxy resolves `host`, so lookups don't leak either.
+pub async fn connect(host: &str, port: u16) -> Result<TcpStream> {
+    crate::settings::check_loaded()?;
+    match network().tor_proxy {
+        Some(proxy) => {
+            let proxy = reqwest::Url::parse(&proxy).context("Invalid Tor proxy")?;
+            let proxy_host = proxy.host_str().context("Tor proxy has no host")?;
+            let proxy_port = proxy.port().unwrap_or(1080);
+            let stream = Socks5Stream::connect((proxy_host, proxy_port), (host, port))
+                .await
+                .with_context(|| format!("Tor proxy refused the connection to {}", host))?;
+            Ok(stream.into_inner())
+        }
+        None => TcpStream::connect((host, port))
+            .await
+            .with_context(|| format!("Failed to connect to {}", host)),
+    }
+}
+
+/// Make sure the node holds `keypair` as the IPNS key `name` so it can
+/// publish with it, importing it the first time
+pub async fn ensure_key(client: &reqwest::Client, name: &str, keypair: &Keypair) -> Result<()> {
+    let response = client
+        .post(api("key/list"))
+        .send()
+        .await
+        .context("Failed to list IPNS keys")?;
+    if !response.status().is_success() {
+        anyhow::bail!("IPNS key list failed: {}", response.status());
+    }
+
+    let json: serde_json::Value = response.json().await?;
+    let imported = json["Keys"]
+        .as_array()
+        .is_some_and(|keys| keys.iter().any(|k| k["Name"] == name));
+    if imported {
+        return Ok(());
+    }
+
+    let mut encoded = keypair
+        .to_protobuf_encoding()
+        .context("Failed to encode IPNS key")?;
+    let part = multipart::Part::bytes(encoded.clone())
+        .file_name("key")
+        .mime_str("application/octet-stream")?;
+    encoded.zeroize();
+
+    let response = client
+        .post(api("key/import"))
+        .query(&[("arg", name), ("format", "libp2p-protobuf-cleartext")])
+        .multipart(multipart::Form::new().part("file", part))
+        .send()
+        .await
+        .context("Failed to import IPNS key")?;
+    if !response.status().is_success() {
+        anyhow::bail!("IPNS key import failed: {}", response.status());
+    }
+
+    info!("Imported IPNS key {} into the IPFS node", name);
+    Ok(())
+}
+
+/// Point the IPNS name of the node's key `key_name` at `cid`
+pub async fn publish_name(
+    client: &reqwest::Client,
+    key_name: &str,
+    cid: &str,
+    lifetime: &str,
+) -> Result<()> {
+    let ipfs_path = format!("/ipfs/{}", cid);
+    let response = client
+        .post(api("name/publish"))
+        .query(&[
+            ("arg", ipfs_path.as_str()),
+            ("key", key_name),
+            ("lifetime", lifetime),
+            ("allow-offline", "true"),
+        ])
+        .send()
+        .await
+        .context("Failed to reach the IPFS node")?;
+    if !response.status().is_success() {
+        anyhow::bail!("IPNS publish failed: {}", response.status());
+    }
+    Ok(())
+}
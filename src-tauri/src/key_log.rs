@@ -0,0 +1,261 @@
+use crate::catalog::now_secs;
+use crate::crypto::{Identity, SessionKey};
+use crate::hash_chain::{append_record, read_records, record_hash};
+use crate::no_traces;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::warn;
+
+const KEY_LOG_FILE: &str = "key_log.enc";
+/// Name of the log in errors
+const KEY_LOG: &str = "key log";
+
+/// Which of a contact's keys was seen. Their public ID is their identity
+/// key, so it can't change without them becoming another contact.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyKind {
+    /// ed25519 key their cards, introductions and notices are signed with
+    Signing,
+    /// OpenPGP key, by fingerprint
+    Pgp,
+}
+
+impl KeyKind {
+    fn label(self) -> &'static str {
+        match self {
+            KeyKind::Signing => "Signing",
+            KeyKind::Pgp => "OpenPGP",
+        }
+    }
+}
+
+/// Where a key was seen
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    ContactCard,
+    Introduction,
+    GroupShard,
+    DeviceRevocation,
+    Attestation,
+    Escrow,
+    Poll,
+    ProfileImport,
+    /// Set by the user, so a change is expected
+    User,
+}
+
+/// A key seen for a contact that differs from the last one seen for them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyEntry {
+    pub seq: u64,
+    pub at: u64,
+    pub contact: String,
+    pub kind: KeyKind,
+    /// Hex ed25519 key, or OpenPGP fingerprint
+    pub key: String,
+    pub source: KeySource,
+    /// Key this one replaced; `None` the first time
+    pub replaces: Option<String>,
+    /// SHA-256 of the previous record as stored, hex
+    pub prev: String,
+}
+
+/// Entries read back, and whether the chain between them is unbroken
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyLog {
+    pub entries: Vec<KeyEntry>,
+    /// False if a record was removed, reordered or altered
+    pub intact: bool,
+    /// SHA-256 of the last record, hex; empty for an empty log. Covers the
+    /// whole chain, so two copies can be compared by this alone.
+    pub head: String,
+}
+
+/// A contact's key changed without the user changing it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyChange {
+    pub contact: String,
+    pub kind: KeyKind,
+    pub old: String,
+    pub new: String,
+    pub source: KeySource,
+    pub at: u64,
+}
+
+/// Appends are serialized so `seq` and `prev` follow the file order
+static APPEND: Mutex<()> = Mutex::new(());
+
+/// Log a key seen for `contact` if it isn't the last one seen for them.
+/// Returns the change when a different key was seen before and the user
+/// didn't set it. Failures are logged, not returned, so whatever carried
+/// the key is never blocked by the log.
+pub fn observe(
+    data_dir: &Path,
+    identity: &Identity,
+    contact: &str,
+    kind: KeyKind,
+    key: &str,
+    source: KeySource,
+) -> Option<KeyChange> {
+    match append(data_dir, identity, contact, kind, key, source) {
+        Ok(Some(entry)) => {
+            let old = entry.replaces.filter(|_| source != KeySource::User)?;
+            warn!("{} key of {} changed", kind.label(), contact);
+            Some(KeyChange {
+                contact: entry.contact,
+                kind,
+                old,
+                new: entry.key,
+                source,
+                at: entry.at,
+            })
+        }
+        Ok(None) => None,
+        Err(e) => {
+            warn!("Failed to write key log: {}", e);
+            None
+        }
+    }
+}
+
+fn append(
+    data_dir: &Path,
+    identity: &Identity,
+    contact: &str,
+    kind: KeyKind,
+    key: &str,
+    source: KeySource,
+) -> Result<Option<KeyEntry>> {
+    let _guard = APPEND.lock().unwrap();
+    let storage_key = identity.storage_key(b"key-log");
+    let path = data_dir.join(KEY_LOG_FILE);
+    let bytes = no_traces::read_file(&path)
+        .context("Failed to read key log")?
+        .unwrap_or_default();
+    let records = read_records(&bytes, KEY_LOG)?;
+
+    let mut last = None;
+    for record in &records {
+        let entry = decrypt_entry(&storage_key, record)?;
+        if entry.contact == contact && entry.kind == kind {
+            last = Some(entry.key);
+        }
+    }
+    if last.as_deref() == Some(key) {
+        return Ok(None);
+    }
+
+    let entry = KeyEntry {
+        seq: records.len() as u64,
+        at: now_secs(),
+        contact: contact.to_string(),
+        kind,
+        key: key.to_string(),
+        source,
+        replaces: last,
+        prev: records.last().map(|r| record_hash(r)).unwrap_or_default(),
+    };
+    let encrypted = storage_key.encrypt_file(&serde_json::to_vec(&entry)?)?;
+    append_record(&path, &encrypted, KEY_LOG)?;
+    Ok(Some(entry))
+}
+
+/// Read the key log, optionally only the entries for one contact. The chain
+/// is checked over the whole log either way.
+pub fn read_log(data_dir: &Path, identity: &Identity, contact: Option<&str>) -> Result<KeyLog> {
+    let path = data_dir.join(KEY_LOG_FILE);
+    let Some(bytes) = no_traces::read_file(&path).context("Failed to read key log")? else {
+        return Ok(KeyLog {
+            entries: Vec::new(),
+            intact: true,
+            head: String::new(),
+        });
+    };
+    let storage_key = identity.storage_key(b"key-log");
+    let records = read_records(&bytes, KEY_LOG)?;
+
+    let mut entries = Vec::with_capacity(records.len());
+    let mut intact = true;
+    let mut prev = String::new();
+    for (seq, record) in records.iter().enumerate() {
+        let entry = decrypt_entry(&storage_key, record)?;
+        intact &= entry.seq == seq as u64 && entry.prev == prev;
+        prev = record_hash(record);
+        entries.push(entry);
+    }
+    if !intact {
+        warn!("Key log chain is broken");
+    }
+
+    entries.retain(|e| contact.map_or(true, |c| e.contact == c));
+    Ok(KeyLog {
+        entries,
+        intact,
+        head: prev,
+    })
+}
+
+/// Write the key log as plain JSON for an audit. Returns the number of
+/// entries written.
+pub fn export_log(
+    data_dir: &Path,
+    identity: &Identity,
+    contact: Option<&str>,
+    path: &Path,
+) -> Result<usize> {
+    let log = read_log(data_dir, identity, contact)?;
+    fs::write(path, serde_json::to_vec_pretty(&log)?).context("Failed to write key log export")?;
+    Ok(log.entries.len())
+}
+
+fn decrypt_entry(storage_key: &SessionKey, record: &[u8]) -> Result<KeyEntry> {
+    let json = storage_key
+        .decrypt_file(&record[4..])
+        .context("Failed to decrypt key log")?;
+    serde_json::from_slice(&json).context("Failed to parse key log")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logs_each_new_key_and_reports_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = Identity::generate();
+        let contact = Identity::generate().public_id();
+        let see = |key: &str, source| {
+            observe(
+                dir.path(),
+                &identity,
+                &contact,
+                KeyKind::Signing,
+                key,
+                source,
+            )
+        };
+
+        assert!(see("aa", KeySource::ContactCard).is_none());
+        assert!(see("aa", KeySource::Introduction).is_none());
+        let change = see("bb", KeySource::Introduction).unwrap();
+        assert_eq!((change.old.as_str(), change.new.as_str()), ("aa", "bb"));
+        assert!(see("cc", KeySource::User).is_none());
+
+        let log = read_log(dir.path(), &identity, Some(&contact)).unwrap();
+        assert!(log.intact);
+        assert_eq!(log.entries.len(), 3);
+        assert_eq!(log.entries[2].replaces.as_deref(), Some("bb"));
+
+        // Dropping a record breaks the chain
+        let path = dir.path().join(KEY_LOG_FILE);
+        let bytes = fs::read(&path).unwrap();
+        let first = read_records(&bytes, KEY_LOG).unwrap()[0].len();
+        fs::write(&path, &bytes[first..]).unwrap();
+        assert!(!read_log(dir.path(), &identity, None).unwrap().intact);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::catalog::now_secs;
+use crate::contacts::ContactBook;
+use crate::crypto::{Identity, SessionKey};
+use crate::no_traces;
+use crate::p2p::GhostMessage;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
+
+const KNOCKS_FILE: &str = "knocks.enc";
+/// Per contact and direction; an unknown sender can't fill the disk
+const MAX_HELD_PER_CONTACT: usize = 100;
+/// Open incoming requests at once. Further requesters are ignored until the
+/// user answers some, so a flood of fresh identities can't fill the disk.
+const MAX_PENDING_REQUESTS: usize = 200;
+/// Incoming messages held across all contacts; the oldest go first
+const MAX_HELD_INCOMING: usize = 1000;
+
+/// Where a contact request stands with one peer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KnockStatus {
+    /// We asked them and are waiting for an answer
+    Requested,
+    /// They asked us and are waiting for our answer
+    Pending,
+    Accepted,
+    /// We declined them; their messages are dropped
+    Declined,
+    /// They declined us
+    Rejected,
+}
+
+/// Which incoming contact requests are accepted without asking
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoAccept {
+    Never,
+    /// Peers already in the address book
+    #[default]
+    Contacts,
+    Everyone,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KnockRecord {
+    pub public_id: String,
+    pub status: KnockStatus,
+    /// Note sent with their request
+    #[serde(default)]
+    pub note: String,
+    pub requested_at: u64,
+    pub updated_at: u64,
+    /// Messages waiting on this request, filled in when listing
+    #[serde(default, skip_deserializing)]
+    pub held_messages: usize,
+}
+
+/// Message kept back until the contact request is answered
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct HeldMessage {
+    contact: String,
+    outgoing: bool,
+    message: GhostMessage,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct KnockState {
+    records: Vec<KnockRecord>,
+    held: Vec<HeldMessage>,
+}
+
+/// Contact request state and held messages, encrypted with the local identity
+pub struct KnockStore {
+    path: PathBuf,
+    storage_key: SessionKey,
+    state: KnockState,
+}
+
+impl KnockStore {
+    /// Load the store, starting empty if it does not exist yet
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(KNOCKS_FILE);
+        let storage_key = identity.storage_key(b"knocks");
+
+        let state = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read contact requests")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt contact requests")?;
+            let state = serde_json::from_slice(&json);
+            json.zeroize();
+            state.context("Failed to parse contact requests")?
+        } else {
+            KnockState::default()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            state,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut json = serde_json::to_vec(&self.state)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?)
+            .context("Failed to write contact requests")?;
+        Ok(())
+    }
+
+    /// Requests with the number of messages held for each, newest first
+    pub fn list(&self) -> Vec<KnockRecord> {
+        let mut records: Vec<KnockRecord> = self
+            .state
+            .records
+            .iter()
+            .map(|record| KnockRecord {
+                held_messages: self
+                    .state
+                    .held
+                    .iter()
+                    .filter(|h| h.contact == record.public_id)
+                    .count(),
+                ..record.clone()
+            })
+            .collect();
+        records.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        records
+    }
+
+    pub fn get(&self, public_id: &str) -> Option<&KnockRecord> {
+        self.state.records.iter().find(|r| r.public_id == public_id)
+    }
+
+    pub fn status(&self, public_id: &str) -> Option<KnockStatus> {
+        self.get(public_id).map(|r| r.status)
+    }
+
+    /// Whether a request from `public_id` can be recorded: theirs is already
+    /// open, or fewer than the limit are
+    pub fn has_room_for(&self, public_id: &str) -> bool {
+        self.status(public_id) == Some(KnockStatus::Pending)
+            || self
+                .state
+                .records
+                .iter()
+                .filter(|r| r.status == KnockStatus::Pending)
+                .count()
+                < MAX_PENDING_REQUESTS
+    }
+
+    /// Record a new status; `note` replaces the stored note when given
+    pub fn set_status(
+        &mut self,
+        public_id: &str,
+        status: KnockStatus,
+        note: Option<&str>,
+    ) -> KnockRecord {
+        let now = now_secs();
+        let index = match self
+            .state
+            .records
+            .iter()
+            .position(|r| r.public_id == public_id)
+        {
+            Some(index) => index,
+            None => {
+                self.state.records.push(KnockRecord {
+                    public_id: public_id.to_string(),
+                    status,
+                    note: String::new(),
+                    requested_at: now,
+                    updated_at: now,
+                    held_messages: 0,
+                });
+                self.state.records.len() - 1
+            }
+        };
+
+        let record = &mut self.state.records[index];
+        if matches!(status, KnockStatus::Requested | KnockStatus::Pending)
+            && record.status != status
+        {
+            record.requested_at = now;
+        }
+        record.status = status;
+        record.updated_at = now;
+        if let Some(note) = note {
+            record.note = note.to_string();
+        }
+        record.clone()
+    }
+
+    /// Keep a message until the request is answered, dropping the oldest
+    /// beyond the per-contact and overall limits
+    pub fn hold(&mut self, contact: &str, outgoing: bool, message: GhostMessage) {
+        let held = &mut self.state.held;
+        let count = held
+            .iter()
+            .filter(|h| h.contact == contact && h.outgoing == outgoing)
+            .count();
+        if count >= MAX_HELD_PER_CONTACT {
+            if let Some(oldest) = held
+                .iter()
+                .position(|h| h.contact == contact && h.outgoing == outgoing)
+            {
+                held.remove(oldest);
+            }
+        }
+        if !outgoing && held.iter().filter(|h| !h.outgoing).count() >= MAX_HELD_INCOMING {
+            if let Some(oldest) = held.iter().position(|h| !h.outgoing) {
+                held.remove(oldest);
+            }
+        }
+        held.push(HeldMessage {
+            contact: contact.to_string(),
+            outgoing,
+            message,
+        });
+    }
+
+    /// Remove and return the messages held for `contact` in one direction
+    pub fn take_held(&mut self, contact: &str, outgoing: bool) -> Vec<GhostMessage> {
+        let (taken, kept) = std::mem::take(&mut self.state.held)
+            .into_iter()
+            .partition(|h| h.contact == contact && h.outgoing == outgoing);
+        self.state.held = kept;
+        taken.into_iter().map(|h: HeldMessage| h.message).collect()
+    }
+}
+
+/// Whether a request from `public_id` is accepted without asking the user
+pub fn auto_accepts(
+    policy: AutoAccept,
+    data_dir: &Path,
+    identity: &Identity,
+    public_id: &str,
+) -> bool {
+    match policy {
+        AutoAccept::Never => false,
+        AutoAccept::Everyone => true,
+        AutoAccept::Contacts => ContactBook::load(data_dir, identity)
+            .map(|book| book.get(public_id).is_some())
+            .unwrap_or(false),
+    }
+}
@@ -0,0 +1,578 @@
+use crate::ipfs;
+use crate::settings;
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+use tracing::{error, info, warn};
+
+const KUBO_DIR: &str = "kubo";
+const REPO_DIR: &str = "repo";
+const DIST_URL: &str = "https://dist.ipfs.tech/kubo";
+const DEFAULT_VERSION: &str = "v0.29.0";
+/// Release archives Control may install, pinned in the source so a
+/// compromised dist server can't serve a build together with its checksum
+const PINNED_CHECKSUMS: &str = include_str!("../checksums/kubo.sha512");
+/// Away from Kubo's defaults so a node the user runs themselves keeps working
+const DEFAULT_API_PORT: u16 = 45001;
+const DEFAULT_GATEWAY_PORT: u16 = 48080;
+const DEFAULT_SWARM_PORT: u16 = 44001;
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(600);
+const START_TIMEOUT_SECS: u64 = 60;
+const STOP_TIMEOUT_SECS: u64 = 15;
+const HEALTH_CHECK_SECS: u64 = 30;
+/// Failed health checks in a row before a hung daemon is restarted
+const MAX_FAILED_CHECKS: u32 = 3;
+/// Restarts in a row before supervision gives up until the next start
+const MAX_RESTARTS: u32 = 5;
+
+/// Kubo downloaded and run by Control itself, for users without an IPFS node
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KuboSettings {
+    /// Start Kubo with the app and point all IPFS traffic at it
+    pub managed: bool,
+    /// Release from dist.ipfs.tech, e.g. "v0.29.0"; it must be pinned in
+    /// `checksums/kubo.sha512`
+    pub version: String,
+    pub api_port: u16,
+    pub gateway_port: u16,
+    pub swarm_port: u16,
+}
+
+impl Default for KuboSettings {
+    fn default() -> Self {
+        Self {
+            managed: false,
+            version: DEFAULT_VERSION.to_string(),
+            api_port: DEFAULT_API_PORT,
+            gateway_port: DEFAULT_GATEWAY_PORT,
+            swarm_port: DEFAULT_SWARM_PORT,
+        }
+    }
+}
+
+impl KuboSettings {
+    pub fn validate(&self) -> Result<()> {
+        let numbers: Vec<&str> = self
+            .version
+            .strip_prefix('v')
+            .unwrap_or_default()
+            .split('.')
+            .collect();
+        if numbers.len() != 3
+            || numbers
+                .iter()
+                .any(|n| n.is_empty() || !n.chars().all(|c| c.is_ascii_digit()))
+        {
+            anyhow::bail!("Kubo version must look like v0.29.0");
+        }
+        let ports = [self.api_port, self.gateway_port, self.swarm_port];
+        if ports.contains(&0)
+            || ports[0] == ports[1]
+            || ports[0] == ports[2]
+            || ports[1] == ports[2]
+        {
+            anyhow::bail!("Kubo needs three different non-zero ports");
+        }
+        Ok(())
+    }
+
+    /// API of the managed node, used in place of the network settings' URL
+    pub fn api_url(&self) -> String {
+        format!("http://127.0.0.1:{}/api/v0", self.api_port)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KuboStatus {
+    pub managed: bool,
+    pub installed: bool,
+    pub version: String,
+    pub running: bool,
+    /// Set while our own child process runs; a node left over from an
+    /// earlier run answers without one
+    pub pid: Option<u32>,
+    pub api_url: String,
+    pub repo_path: PathBuf,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+/// Progress of the Kubo download, emitted as `kubo_progress`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KuboProgress {
+    pub bytes_done: u64,
+    pub total_bytes: u64,
+}
+
+/// The supervised daemon. `generation` changes on every start and stop so a
+/// supervisor from an earlier start knows to exit.
+struct Supervised {
+    child: Option<Child>,
+    generation: u64,
+    restarts: u32,
+    last_error: Option<String>,
+}
+
+static DAEMON: tokio::sync::Mutex<Supervised> = tokio::sync::Mutex::const_new(Supervised {
+    child: None,
+    generation: 0,
+    restarts: 0,
+    last_error: None,
+});
+
+fn kubo_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join(KUBO_DIR)
+}
+
+fn repo_path(data_dir: &Path) -> PathBuf {
+    kubo_dir(data_dir).join(REPO_DIR)
+}
+
+fn binary_path(data_dir: &Path, version: &str) -> PathBuf {
+    let name = if cfg!(windows) { "ipfs.exe" } else { "ipfs" };
+    kubo_dir(data_dir).join(version).join(name)
+}
+
+/// SHA-512 pinned for a release archive; lines are "<hex>  <file name>"
+fn pinned_checksum(file_name: &str) -> Option<String> {
+    PINNED_CHECKSUMS
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .find_map(|line| {
+            let (hash, name) = line.split_once(char::is_whitespace)?;
+            (name.trim_start() == file_name && hash.len() == 128).then(|| hash.to_lowercase())
+        })
+}
+
+/// Platform part of the release file name, e.g. `linux-amd64`
+fn dist_target() -> Result<String> {
+    let os = match std::env::consts::OS {
+        "macos" => "darwin",
+        os @ ("linux" | "windows" | "freebsd" | "openbsd") => os,
+        os => anyhow::bail!("Kubo is not published for {}", os),
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        "arm" => "arm",
+        arch => anyhow::bail!("Kubo is not published for {}", arch),
+    };
+    Ok(format!("{}-{}", os, arch))
+}
+
+pub async fn status(data_dir: &Path) -> KuboStatus {
+    let settings = settings::kubo_settings();
+    let mut daemon = DAEMON.lock().await;
+    let pid = match daemon.child.as_mut() {
+        Some(child) if matches!(child.try_wait(), Ok(None)) => child.id(),
+        _ => None,
+    };
+    KuboStatus {
+        managed: settings.managed,
+        installed: binary_path(data_dir, &settings.version).exists(),
+        running: api_responds().await,
+        pid,
+        api_url: settings.api_url(),
+        repo_path: repo_path(data_dir),
+        restarts: daemon.restarts,
+        last_error: daemon.last_error.clone(),
+        version: settings.version,
+    }
+}
+
+/// Download the configured Kubo release and check it against the SHA-512
+/// pinned for it. Does nothing if that version is already installed.
+pub async fn install(data_dir: &Path, on_progress: impl Fn(KuboProgress)) -> Result<PathBuf> {
+    let settings = settings::kubo_settings();
+    settings.validate()?;
+    let binary = binary_path(data_dir, &settings.version);
+    if binary.exists() {
+        return Ok(binary);
+    }
+
+    let target = dist_target()?;
+    let extension = if cfg!(windows) { "zip" } else { "tar.gz" };
+    let file_name = format!("kubo_{}_{}.{}", settings.version, target, extension);
+    let url = format!("{}/{}/{}", DIST_URL, settings.version, file_name);
+    let expected = pinned_checksum(&file_name)
+        .with_context(|| format!("Kubo {} is not pinned for {}", settings.version, target))?;
+    // Release downloads follow the network settings, Tor proxy included
+    let client = ipfs::client()?;
+
+    let dir = kubo_dir(data_dir);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .context("Failed to create Kubo directory")?;
+    let archive = dir.join(format!("{}.part", file_name));
+    let response = client
+        .get(&url)
+        .timeout(DOWNLOAD_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?;
+    let total_bytes = response.content_length().unwrap_or(0);
+    let mut stream = response.bytes_stream();
+    let mut file = tokio::fs::File::create(&archive)
+        .await
+        .context("Failed to create Kubo download")?;
+    let mut hasher = Sha512::new();
+    let mut bytes_done = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        bytes_done += chunk.len() as u64;
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        on_progress(KuboProgress {
+            bytes_done,
+            total_bytes,
+        });
+    }
+    file.flush().await?;
+    drop(file);
+
+    if hex::encode(hasher.finalize()) != expected {
+        let _ = tokio::fs::remove_file(&archive).await;
+        anyhow::bail!("Kubo download does not match its pinned checksum");
+    }
+
+    let extracted = tokio::task::spawn_blocking({
+        let archive = archive.clone();
+        let binary = binary.clone();
+        move || extract_binary(&archive, &binary)
+    })
+    .await?;
+    let _ = tokio::fs::remove_file(&archive).await;
+    extracted?;
+
+    info!(
+        "Installed Kubo {} to {}",
+        settings.version,
+        binary.display()
+    );
+    Ok(binary)
+}
+
+/// Copy `kubo/ipfs` out of the release archive
+fn extract_binary(archive: &Path, binary: &Path) -> Result<()> {
+    let parent = binary.parent().context("Invalid Kubo path")?;
+    std::fs::create_dir_all(parent).context("Failed to create Kubo directory")?;
+    let partial = binary.with_extension("part");
+    let wanted = format!(
+        "kubo/{}",
+        binary.file_name().unwrap_or_default().to_string_lossy()
+    );
+
+    let mut out = std::fs::File::create(&partial).context("Failed to write Kubo binary")?;
+    let found = if cfg!(windows) {
+        let mut zip =
+            zip::ZipArchive::new(std::fs::File::open(archive)?).context("Invalid Kubo archive")?;
+        match zip.by_name(&wanted) {
+            Ok(mut entry) => {
+                std::io::copy(&mut entry, &mut out)?;
+                true
+            }
+            Err(_) => false,
+        }
+    } else {
+        let gz = flate2::read::GzDecoder::new(std::fs::File::open(archive)?);
+        let mut tar = tar::Archive::new(gz);
+        let mut found = false;
+        for entry in tar.entries().context("Invalid Kubo archive")? {
+            let mut entry = entry?;
+            if entry.path()?.to_string_lossy() == wanted {
+                std::io::copy(&mut entry, &mut out)?;
+                found = true;
+                break;
+            }
+        }
+        found
+    };
+    drop(out);
+    if !found {
+        let _ = std::fs::remove_file(&partial);
+        anyhow::bail!("Kubo archive has no {}", wanted);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o755))
+            .context("Failed to make Kubo executable")?;
+    }
+    std::fs::rename(&partial, binary).context("Failed to store Kubo binary")?;
+    Ok(())
+}
+
+/// Whatever answers on the managed API port
+async fn api_responds() -> bool {
+    let url = format!("{}/version", settings::kubo_settings().api_url());
+    // Straight to loopback; the Tor proxy can't reach it anyway
+    match reqwest::Client::new()
+        .post(url)
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+    {
+        Ok(response) => response.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+async fn run_ipfs(binary: &Path, repo: &Path, args: &[&str]) -> Result<()> {
+    let output = Command::new(binary)
+        .args(args)
+        .env("IPFS_PATH", repo)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .with_context(|| format!("Failed to run ipfs {}", args.join(" ")))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "ipfs {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Create the repo on first use and pin its addresses to our ports; the API
+/// and gateway only ever listen on loopback
+async fn configure(binary: &Path, repo: &Path, settings: &KuboSettings) -> Result<()> {
+    if !repo.join("config").exists() {
+        tokio::fs::create_dir_all(repo)
+            .await
+            .context("Failed to create Kubo repo")?;
+        run_ipfs(binary, repo, &["init", "--profile=lowpower"]).await?;
+        info!("Initialized Kubo repo at {}", repo.display());
+    }
+
+    let api = format!("/ip4/127.0.0.1/tcp/{}", settings.api_port);
+    let gateway = format!("/ip4/127.0.0.1/tcp/{}", settings.gateway_port);
+    let swarm = serde_json::to_string(&[
+        format!("/ip4/0.0.0.0/tcp/{}", settings.swarm_port),
+        format!("/ip6/::/tcp/{}", settings.swarm_port),
+        format!("/ip4/0.0.0.0/udp/{}/quic-v1", settings.swarm_port),
+        format!("/ip6/::/udp/{}/quic-v1", settings.swarm_port),
+    ])?;
+    run_ipfs(binary, repo, &["config", "Addresses.API", &api]).await?;
+    run_ipfs(binary, repo, &["config", "Addresses.Gateway", &gateway]).await?;
+    run_ipfs(
+        binary,
+        repo,
+        &["config", "--json", "Addresses.Swarm", &swarm],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Install if needed, configure and start Kubo, then supervise it until
+/// `stop`. Returns once the API answers.
+pub async fn start(data_dir: &Path) -> Result<()> {
+    let settings = settings::kubo_settings();
+    settings.validate()?;
+    let binary = install(data_dir, |_| {}).await?;
+    let repo = repo_path(data_dir);
+
+    let mut daemon = DAEMON.lock().await;
+    if let Some(child) = daemon.child.as_mut() {
+        if matches!(child.try_wait(), Ok(None)) {
+            return Ok(());
+        }
+    }
+    if api_responds().await {
+        // Left running by an earlier session that did not shut down cleanly
+        warn!("A Kubo node already answers on port {}", settings.api_port);
+        return Ok(());
+    }
+
+    configure(&binary, &repo, &settings).await?;
+    daemon.child = Some(spawn_daemon(&binary, &repo)?);
+    daemon.generation += 1;
+    daemon.restarts = 0;
+    daemon.last_error = None;
+    let generation = daemon.generation;
+    drop(daemon);
+
+    wait_until_ready().await?;
+    info!(
+        "Kubo {} running on {}",
+        settings.version,
+        settings.api_url()
+    );
+    tauri::async_runtime::spawn(supervise(binary, repo, generation));
+    Ok(())
+}
+
+fn spawn_daemon(binary: &Path, repo: &Path) -> Result<Child> {
+    let mut child = Command::new(binary)
+        .args(["daemon", "--enable-gc", "--migrate=true"])
+        .env("IPFS_PATH", repo)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to start Kubo")?;
+
+    if let Some(stderr) = child.stderr.take() {
+        tauri::async_runtime::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                warn!("kubo: {}", line);
+            }
+        });
+    }
+    Ok(child)
+}
+
+async fn wait_until_ready() -> Result<()> {
+    for _ in 0..START_TIMEOUT_SECS * 2 {
+        if api_responds().await {
+            return Ok(());
+        }
+        let mut daemon = DAEMON.lock().await;
+        if let Some(child) = daemon.child.as_mut() {
+            if let Ok(Some(status)) = child.try_wait() {
+                daemon.child = None;
+                anyhow::bail!("Kubo exited during startup ({})", status);
+            }
+        }
+        drop(daemon);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    anyhow::bail!("Kubo did not answer within {} seconds", START_TIMEOUT_SECS)
+}
+
+/// Restart Kubo when it exits or stops answering, backing off between tries
+async fn supervise(binary: PathBuf, repo: PathBuf, generation: u64) {
+    let mut failed_checks = 0;
+    loop {
+        tokio::time::sleep(Duration::from_secs(HEALTH_CHECK_SECS)).await;
+        let healthy = api_responds().await;
+
+        let mut daemon = DAEMON.lock().await;
+        if daemon.generation != generation {
+            return;
+        }
+        let exited = match daemon.child.as_mut() {
+            Some(child) => match child.try_wait() {
+                Ok(Some(status)) => Some(status.to_string()),
+                _ => None,
+            },
+            None => Some("no process".to_string()),
+        };
+        failed_checks = if healthy { 0 } else { failed_checks + 1 };
+        let reason = match exited {
+            Some(status) => format!("Kubo exited ({})", status),
+            None if failed_checks >= MAX_FAILED_CHECKS => "Kubo stopped answering".to_string(),
+            None => {
+                if healthy {
+                    daemon.restarts = 0;
+                }
+                continue;
+            }
+        };
+
+        if daemon.restarts >= MAX_RESTARTS {
+            error!("{}; giving up after {} restarts", reason, MAX_RESTARTS);
+            daemon.last_error = Some(reason);
+            daemon.child = None;
+            return;
+        }
+        warn!("{}, restarting", reason);
+        if let Some(mut child) = daemon.child.take() {
+            let _ = child.kill().await;
+        }
+        daemon.restarts += 1;
+        daemon.last_error = Some(reason);
+        let backoff = Duration::from_secs(2u64.pow(daemon.restarts));
+        drop(daemon);
+        tokio::time::sleep(backoff).await;
+
+        let mut daemon = DAEMON.lock().await;
+        if daemon.generation != generation {
+            return;
+        }
+        match spawn_daemon(&binary, &repo) {
+            Ok(child) => daemon.child = Some(child),
+            Err(e) => {
+                error!("Failed to restart Kubo: {:#}", e);
+                daemon.last_error = Some(format!("{:#}", e));
+            }
+        }
+        failed_checks = 0;
+    }
+}
+
+/// Stop the supervised daemon, asking it to shut down cleanly before
+/// killing it. A node Control did not start is left alone.
+pub async fn stop() -> Result<()> {
+    let mut daemon = DAEMON.lock().await;
+    daemon.generation += 1;
+    let Some(mut child) = daemon.child.take() else {
+        return Ok(());
+    };
+    drop(daemon);
+
+    let client = reqwest::Client::new();
+    let _ = client
+        .post(format!("{}/shutdown", settings::kubo_settings().api_url()))
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await;
+    match tokio::time::timeout(Duration::from_secs(STOP_TIMEOUT_SECS), child.wait()).await {
+        Ok(_) => info!("Kubo stopped"),
+        Err(_) => {
+            warn!("Kubo did not shut down in time, killing it");
+            child.kill().await.context("Failed to kill Kubo")?;
+        }
+    }
+    Ok(())
+}
+
+/// Start the managed node with the app if it is enabled and installed;
+/// downloading waits until the user starts it explicitly
+pub fn start_on_launch(data_dir: PathBuf) {
+    let settings = settings::kubo_settings();
+    if !settings.managed {
+        return;
+    }
+    if !binary_path(&data_dir, &settings.version).exists() {
+        warn!("Managed Kubo {} is not installed yet", settings.version);
+        return;
+    }
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = start(&data_dir).await {
+            error!("Failed to start Kubo: {:#}", e);
+            DAEMON.lock().await.last_error = Some(format!("{:#}", e));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_versions_and_ports() {
+        assert!(KuboSettings::default().validate().is_ok());
+        let bad_version = KuboSettings {
+            version: "latest".to_string(),
+            ..KuboSettings::default()
+        };
+        assert!(bad_version.validate().is_err());
+        let same_ports = KuboSettings {
+            gateway_port: DEFAULT_API_PORT,
+            ..KuboSettings::default()
+        };
+        assert!(same_ports.validate().is_err());
+    }
+}
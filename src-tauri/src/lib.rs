@@ -0,0 +1,100 @@
+//! Core of Control: identity and crypto, Ghost Mode P2P messaging and IPFS
+//! dead drops. Shared by the Tauri app (`main.rs`) and `control-cli`.
+//!
+//! Embedders should start from [`IdentityStore`], [`Node`] and
+//! [`DropManager`]; the modules behind them follow the app's needs and
+//! change more freely.
+
+pub mod age_drop;
+pub mod archive;
+pub mod audit;
+pub mod away_ping;
+pub mod backup;
+pub mod benchmark;
+pub mod burn;
+pub mod canary;
+pub mod car;
+pub mod catalog;
+pub mod chunk_index;
+pub mod clipboard;
+pub mod conformance;
+pub mod contact_card;
+pub mod contact_import;
+pub mod contacts;
+pub mod conversations;
+pub mod cover;
+pub mod crypto;
+pub mod daemon;
+pub mod dead_drop;
+pub mod dead_man;
+pub mod dedup;
+pub mod devices;
+pub mod drop_manager;
+pub mod erasure;
+pub mod escrow;
+pub mod errors;
+pub mod events;
+pub mod groups;
+pub mod hash_chain;
+pub mod health;
+pub mod i18n;
+pub mod identity_store;
+pub mod introductions;
+pub mod ipfs;
+pub mod key_log;
+pub mod knock;
+pub mod kubo;
+pub mod local_api;
+pub mod location;
+pub mod logging;
+pub mod loopback_http;
+pub mod mailbox;
+pub mod mapped_io;
+pub mod media_stream;
+pub mod mesh;
+pub mod message_drop;
+pub mod mfs;
+pub mod no_traces;
+pub mod node;
+pub mod nostr;
+pub mod notifications;
+pub mod operations;
+pub mod p2p;
+pub mod padding;
+pub mod password_drop;
+pub mod pgp_drop;
+pub mod pin_health;
+pub mod plugins;
+pub mod polls;
+pub mod profile;
+pub mod proofs;
+pub mod recipients;
+pub mod recovery;
+pub mod remote_wipe;
+pub mod search;
+pub mod secrets;
+pub mod session;
+pub mod settings;
+pub mod shards;
+pub mod shred;
+pub mod staging;
+pub mod stego;
+pub mod storage;
+pub mod sync;
+pub mod timelock;
+pub mod transcript;
+pub mod tray;
+pub mod trust;
+pub mod unixfs;
+pub mod updater;
+pub mod upload_schedule;
+pub mod usage;
+pub mod versions;
+pub mod view_once;
+pub mod watch_folder;
+pub mod webhooks;
+pub mod wire;
+
+pub use drop_manager::{DropManager, DropManagerBuilder, NewDrop};
+pub use identity_store::IdentityStore;
+pub use node::{ChannelEvents, Node, NodeBuilder, NodeEvent};
@@ -0,0 +1,317 @@
+use crate::daemon::{constant_time_eq, handle_request, Daemon, DaemonRequest};
+use crate::drop_manager::{DropManager, NewDrop};
+use crate::location::LocationShare;
+use crate::loopback_http::{read_head, RequestHead};
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::{info, warn};
+
+/// Enough for a drop of about 24 MB sent as base64
+const MAX_BODY_BYTES: usize = 32 * 1024 * 1024;
+/// Time an authenticated client gets to send its body
+const BODY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// `Authorization: Bearer`, or `access_token` in the query for WebSocket
+/// clients that can't set headers
+fn token(head: &RequestHead) -> Option<&str> {
+    head.header("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| head.query_param("access_token"))
+}
+
+/// `POST /v1/messages`
+#[derive(Deserialize)]
+struct SendBody {
+    target_public_key: String,
+    content: String,
+    message_id: Option<String>,
+    #[serde(default)]
+    view_once: bool,
+    #[serde(default)]
+    location: Option<LocationShare>,
+}
+
+/// `POST /v1/drops`: a file path on this machine, or the data itself
+#[derive(Deserialize)]
+struct CreateDropBody {
+    file: Option<String>,
+    /// Base64
+    data: Option<String>,
+    name: Option<String>,
+    threshold: u8,
+    shards: u8,
+    #[serde(default)]
+    recipients: Vec<String>,
+    expires_in_secs: Option<u64>,
+}
+
+/// `POST /v1/drops/retrieve`
+#[derive(Deserialize)]
+struct RetrieveDropBody {
+    cid: String,
+    shards: Vec<String>,
+    output: String,
+}
+
+/// HTTP and WebSocket mirror of the daemon socket on 127.0.0.1, for scripts
+/// and home automation. Every request carries the token from `daemon.token`.
+pub(crate) async fn serve(port: u16, daemon: Arc<Daemon>) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .await
+        .with_context(|| format!("Failed to bind local API on port {}", port))?;
+    info!("Local API listening on http://127.0.0.1:{}", port);
+
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .context("Failed to accept API client")?;
+        let daemon = daemon.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, port, &daemon).await {
+                warn!("Local API request failed: {}", e);
+            }
+        });
+    }
+}
+
+/// One request per connection, except for upgraded event streams. The body
+/// is only read once the head has passed the host and token checks.
+async fn handle_connection(mut stream: TcpStream, port: u16, daemon: &Daemon) -> Result<()> {
+    let head = match read_head(&mut stream).await {
+        Ok(Some(head)) => head,
+        Ok(None) => {
+            return respond_error(&mut stream, "400 Bad Request", "Malformed request").await
+        }
+        Err(e) => return respond_error(&mut stream, "400 Bad Request", &e.to_string()).await,
+    };
+    if !head.is_loopback_host(port) {
+        return respond_error(&mut stream, "403 Forbidden", "Unexpected host").await;
+    }
+    let authenticated = token(&head)
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), daemon.token.as_bytes()));
+    if !authenticated {
+        return respond_error(&mut stream, "401 Unauthorized", "Not authenticated").await;
+    }
+    let body = match head.method.as_str() {
+        "POST" => match read_body(&mut stream, &head).await {
+            Ok(body) => body,
+            Err(e) => return respond_error(&mut stream, "400 Bad Request", &e.to_string()).await,
+        },
+        _ => Vec::new(),
+    };
+
+    let result = match (head.method.as_str(), head.path.as_str()) {
+        ("GET", "/v1/events") => {
+            let Some(key) = head.header("sec-websocket-key") else {
+                return respond_error(&mut stream, "426 Upgrade Required", "WebSocket only").await;
+            };
+            let subscription = daemon.events.subscribe();
+            return stream_events(stream, key, subscription).await;
+        }
+        ("GET", "/v1/status") => handle_request(DaemonRequest::Status, daemon).await,
+        ("GET", "/v1/messages") => {
+            let since = match head.query_param("since").map(str::parse) {
+                Some(Ok(since)) => Some(since),
+                Some(Err(_)) => {
+                    return respond_error(&mut stream, "400 Bad Request", "Invalid since").await
+                }
+                None => None,
+            };
+            handle_request(DaemonRequest::Messages { since }, daemon).await
+        }
+        ("POST", "/v1/messages") => match serde_json::from_slice::<SendBody>(&body) {
+            Ok(body) => handle_request(
+                DaemonRequest::Send {
+                    target_public_key: body.target_public_key,
+                    content: body.content,
+                    message_id: body.message_id,
+                    view_once: body.view_once,
+                    location: body.location,
+                },
+                daemon,
+            )
+            .await
+            .map(|message_id| serde_json::json!({ "message_id": message_id })),
+            Err(e) => return respond_error(&mut stream, "400 Bad Request", &e.to_string()).await,
+        },
+        ("POST", "/v1/drops") => match serde_json::from_slice(&body) {
+            Ok(body) => create_drop(daemon, body).await,
+            Err(e) => return respond_error(&mut stream, "400 Bad Request", &e.to_string()).await,
+        },
+        ("POST", "/v1/drops/retrieve") => match serde_json::from_slice(&body) {
+            Ok(body) => retrieve_drop(daemon, body).await,
+            Err(e) => return respond_error(&mut stream, "400 Bad Request", &e.to_string()).await,
+        },
+        (_, "/v1/status" | "/v1/messages" | "/v1/events" | "/v1/drops" | "/v1/drops/retrieve") => {
+            return respond_error(&mut stream, "405 Method Not Allowed", "Method not allowed").await
+        }
+        _ => return respond_error(&mut stream, "404 Not Found", "No such endpoint").await,
+    };
+
+    match result {
+        Ok(value) => respond_json(&mut stream, "200 OK", &value).await,
+        Err(e) => respond_error(&mut stream, "500 Internal Server Error", &e.to_string()).await,
+    }
+}
+
+async fn create_drop(daemon: &Daemon, body: CreateDropBody) -> Result<serde_json::Value> {
+    let drops = DropManager::builder(&daemon.data_dir).build();
+    let request = NewDrop {
+        name: body.name,
+        threshold: body.threshold,
+        total_shards: body.shards,
+        recipients: body.recipients,
+        expires_in_secs: body.expires_in_secs,
+    };
+    let created = match (body.file, body.data) {
+        (Some(file), None) => drops.create_file(&file, request).await?,
+        (None, Some(data)) => {
+            let data = BASE64
+                .decode(data)
+                .context("Drop data is not valid base64")?;
+            drops
+                .create_from_reader(std::io::Cursor::new(data), request)
+                .await?
+        }
+        _ => anyhow::bail!("Give either a file or data"),
+    };
+    Ok(serde_json::to_value(created)?)
+}
+
+async fn retrieve_drop(daemon: &Daemon, body: RetrieveDropBody) -> Result<serde_json::Value> {
+    let drops = DropManager::builder(&daemon.data_dir)
+        .identity(daemon.identity.clone())
+        .build();
+    drops.retrieve(&body.cid, body.shards, &body.output).await?;
+    Ok(serde_json::json!({ "output": body.output }))
+}
+
+/// Accept the WebSocket upgrade and forward daemon events until the client leaves
+async fn stream_events(
+    mut stream: TcpStream,
+    key: &str,
+    mut subscription: broadcast::Receiver<(String, serde_json::Value)>,
+) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    );
+    stream.write_all(head.as_bytes()).await?;
+    let mut socket = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+
+    loop {
+        tokio::select! {
+            event = subscription.recv() => match event {
+                Ok((event, payload)) => {
+                    let text = serde_json::json!({ "event": event, "payload": payload }).to_string();
+                    socket.send(Message::Text(text)).await?;
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("API client missed {} events", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            incoming = socket.next() => match incoming {
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+            },
+        }
+    }
+    let _ = socket.close(None).await;
+    Ok(())
+}
+
+/// Read the body announced by `Content-Length`, within `BODY_TIMEOUT`
+async fn read_body<S: AsyncRead + Unpin>(stream: &mut S, head: &RequestHead) -> Result<Vec<u8>> {
+    let content_length: usize = match head.header("content-length") {
+        Some(value) => value.parse().context("Invalid Content-Length")?,
+        None => 0,
+    };
+    if content_length > MAX_BODY_BYTES {
+        anyhow::bail!("Request body too large");
+    }
+
+    let mut body = head.rest.clone();
+    if body.len() < content_length {
+        let already = body.len();
+        body.resize(content_length, 0);
+        tokio::time::timeout(BODY_TIMEOUT, stream.read_exact(&mut body[already..]))
+            .await
+            .context("Timed out reading the request body")??;
+    }
+    body.truncate(content_length);
+    Ok(body)
+}
+
+async fn respond_json(
+    stream: &mut TcpStream,
+    status: &str,
+    value: &serde_json::Value,
+) -> Result<()> {
+    let body = serde_json::to_vec(value)?;
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn respond_error(stream: &mut TcpStream, status: &str, error: &str) -> Result<()> {
+    respond_json(stream, status, &serde_json::json!({ "error": error })).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_request_with_body() {
+        let raw = b"POST /v1/messages?x=1 HTTP/1.1\r\nHost: 127.0.0.1:7340\r\nAuthorization: Bearer abc\r\nContent-Length: 4\r\n\r\n{}{}";
+        let mut stream = &raw[..];
+        let head = read_head(&mut stream).await.unwrap().unwrap();
+
+        assert_eq!(head.method, "POST");
+        assert_eq!(head.path, "/v1/messages");
+        assert_eq!(head.query_param("x"), Some("1"));
+        assert!(head.is_loopback_host(7340));
+        assert_eq!(token(&head), Some("abc"));
+        assert_eq!(read_body(&mut stream, &head).await.unwrap(), b"{}{}");
+    }
+
+    #[tokio::test]
+    async fn token_can_come_from_the_query() {
+        let raw = b"GET /v1/events?access_token=abc HTTP/1.1\r\nHost: localhost:7340\r\n\r\n";
+        let head = read_head(&mut &raw[..]).await.unwrap().unwrap();
+
+        assert_eq!(head.path, "/v1/events");
+        assert_eq!(token(&head), Some("abc"));
+    }
+
+    #[tokio::test]
+    async fn oversized_body_is_refused() {
+        let raw = format!(
+            "POST /v1/drops HTTP/1.1\r\nHost: localhost:7340\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_BYTES + 1
+        );
+        let mut stream = raw.as_bytes();
+        let head = read_head(&mut stream).await.unwrap().unwrap();
+        assert!(read_body(&mut stream, &head).await.is_err());
+    }
+}
@@ -0,0 +1,368 @@
+use crate::catalog::now_secs;
+use crate::settings;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+
+const MAX_PRECISION_DECIMALS: u8 = 6;
+const DEFAULT_PRECISION_DECIMALS: u8 = 3;
+const DEFAULT_EXPIRES_IN_SECS: u64 = 60 * 60;
+const MAX_EXPIRES_IN_SECS: u64 = 7 * 24 * 60 * 60;
+const DEFAULT_MAX_LIVE_SECS: u64 = 8 * 60 * 60;
+const MAX_LIVE_SECS: u64 = 24 * 60 * 60;
+const DEFAULT_MIN_UPDATE_INTERVAL_SECS: u64 = 10;
+/// Length of a degree of latitude, for the uncertainty rounding adds
+const METRES_PER_DEGREE: f64 = 111_320.0;
+
+/// How precisely and for how long locations are shared
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LocationSettings {
+    /// Decimal places kept of latitude and longitude; 3 is about 100 m,
+    /// 2 about 1 km. Exact if unset.
+    pub precision_decimals: Option<u8>,
+    /// A shared location is hidden after this long unless the sender says
+    /// otherwise
+    pub expires_in_secs: u64,
+    /// Longest a live-location session may run
+    pub max_live_secs: u64,
+    /// Live updates closer together than this are skipped
+    pub min_update_interval_secs: u64,
+}
+
+impl Default for LocationSettings {
+    fn default() -> Self {
+        Self {
+            precision_decimals: Some(DEFAULT_PRECISION_DECIMALS),
+            expires_in_secs: DEFAULT_EXPIRES_IN_SECS,
+            max_live_secs: DEFAULT_MAX_LIVE_SECS,
+            min_update_interval_secs: DEFAULT_MIN_UPDATE_INTERVAL_SECS,
+        }
+    }
+}
+
+impl LocationSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.precision_decimals > Some(MAX_PRECISION_DECIMALS) {
+            anyhow::bail!(
+                "Precision can be at most {} decimal places",
+                MAX_PRECISION_DECIMALS
+            );
+        }
+        if !(1..=MAX_EXPIRES_IN_SECS).contains(&self.expires_in_secs) {
+            anyhow::bail!(
+                "Locations must expire within {} seconds",
+                MAX_EXPIRES_IN_SECS
+            );
+        }
+        if !(1..=MAX_LIVE_SECS).contains(&self.max_live_secs) {
+            anyhow::bail!(
+                "Live locations can run for at most {} seconds",
+                MAX_LIVE_SECS
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A point on the map as shared
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Metres, including what rounding took away
+    pub accuracy_m: Option<f64>,
+}
+
+impl Position {
+    pub fn validate(&self) -> Result<()> {
+        if !(-90.0..=90.0).contains(&self.latitude) || !(-180.0..=180.0).contains(&self.longitude) {
+            anyhow::bail!("Coordinates are out of range");
+        }
+        if self.accuracy_m.is_some_and(|a| !a.is_finite() || a < 0.0) {
+            anyhow::bail!("Invalid accuracy");
+        }
+        Ok(())
+    }
+
+    /// Round to `decimals` places so the exact spot never leaves the device
+    fn rounded(self, decimals: Option<u8>) -> Self {
+        let Some(decimals) = decimals else {
+            return self;
+        };
+        let scale = 10f64.powi(decimals as i32);
+        // Half a step in each direction, at worst along the diagonal
+        let rounding_m = METRES_PER_DEGREE / scale * std::f64::consts::FRAC_1_SQRT_2;
+        Self {
+            latitude: (self.latitude * scale).round() / scale,
+            longitude: (self.longitude * scale).round() / scale,
+            accuracy_m: Some(self.accuracy_m.unwrap_or(0.0).max(rounding_m)),
+        }
+    }
+}
+
+/// Location carried by a Ghost Mode message, end-to-end encrypted with it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LocationShare {
+    /// Unset only on the message that stops a live session
+    pub position: Option<Position>,
+    /// Recipients hide the location after this time
+    pub expires_at: u64,
+    /// Set on every message of a live-location session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Ends the live session, sent by either side
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stopped: bool,
+}
+
+impl LocationShare {
+    /// Part of a live session rather than a one-off location
+    pub fn is_live(&self) -> bool {
+        self.session_id.is_some()
+    }
+
+    fn validate(&self) -> Result<()> {
+        match (&self.position, self.stopped) {
+            (Some(position), false) => position.validate(),
+            (None, true) if self.session_id.is_some() => Ok(()),
+            _ => anyhow::bail!("Invalid location message"),
+        }
+    }
+}
+
+/// A live-location session with one contact, ours or theirs. Sessions live
+/// in memory only and end with the app.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LiveSession {
+    pub id: String,
+    pub contact: String,
+    /// We are the one sharing
+    pub outgoing: bool,
+    pub started_at: u64,
+    pub ends_at: u64,
+    pub updated_at: Option<u64>,
+    pub position: Option<Position>,
+}
+
+/// What a received location message changed
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LiveEvent {
+    Updated(LiveSession),
+    Stopped(LiveSession),
+}
+
+#[derive(Default)]
+struct Sessions {
+    live: HashMap<String, LiveSession>,
+    /// Ended sessions, so late updates don't start them again
+    ended: HashSet<String>,
+}
+
+fn sessions() -> &'static Mutex<Sessions> {
+    static SESSIONS: OnceLock<Mutex<Sessions>> = OnceLock::new();
+    SESSIONS.get_or_init(Default::default)
+}
+
+/// A one-off location, rounded and set to expire per the settings
+pub fn share(position: Position, expires_in_secs: Option<u64>) -> Result<LocationShare> {
+    position.validate()?;
+    let settings = settings::location_settings();
+    let expires_in = expires_in_secs
+        .unwrap_or(settings.expires_in_secs)
+        .min(MAX_EXPIRES_IN_SECS);
+    Ok(LocationShare {
+        position: Some(position.rounded(settings.precision_decimals)),
+        expires_at: now_secs() + expires_in,
+        session_id: None,
+        stopped: false,
+    })
+}
+
+/// Start sharing our location live with `contact` for `duration_secs`
+pub fn start(contact: &str, duration_secs: Option<u64>) -> Result<LiveSession> {
+    let max_live_secs = settings::location_settings().max_live_secs;
+    let now = now_secs();
+    let session = LiveSession {
+        id: uuid::Uuid::new_v4().to_string(),
+        contact: contact.to_string(),
+        outgoing: true,
+        started_at: now,
+        ends_at: now + duration_secs.unwrap_or(max_live_secs).min(max_live_secs),
+        updated_at: None,
+        position: None,
+    };
+    sessions()
+        .lock()
+        .unwrap()
+        .live
+        .insert(session.id.clone(), session.clone());
+    Ok(session)
+}
+
+/// The next update of one of our live sessions, or `None` if it comes too
+/// soon after the last one. Returns the contact to send it to.
+pub fn update(session_id: &str, position: Position) -> Result<Option<(String, LocationShare)>> {
+    position.validate()?;
+    let settings = settings::location_settings();
+    let now = now_secs();
+    let mut sessions = sessions().lock().unwrap();
+    let session = sessions
+        .live
+        .get_mut(session_id)
+        .filter(|s| s.outgoing && s.ends_at > now)
+        .context("No such live location session")?;
+    if session
+        .updated_at
+        .is_some_and(|at| now < at + settings.min_update_interval_secs)
+    {
+        return Ok(None);
+    }
+    let position = position.rounded(settings.precision_decimals);
+    session.updated_at = Some(now);
+    session.position = Some(position);
+    Ok(Some((
+        session.contact.clone(),
+        LocationShare {
+            position: Some(position),
+            expires_at: session.ends_at,
+            session_id: Some(session.id.clone()),
+            stopped: false,
+        },
+    )))
+}
+
+/// End a live session, ours or theirs. Returns it with the message that
+/// tells the other side.
+pub fn stop(session_id: &str) -> Result<(LiveSession, LocationShare)> {
+    let mut sessions = sessions().lock().unwrap();
+    let session = sessions
+        .live
+        .remove(session_id)
+        .context("No such live location session")?;
+    sessions.ended.insert(session.id.clone());
+    let share = LocationShare {
+        position: None,
+        expires_at: session.ends_at,
+        session_id: Some(session.id.clone()),
+        stopped: true,
+    };
+    Ok((session, share))
+}
+
+/// Take in a live-session message from `sender`. One-off locations are
+/// plain messages and return `None`, as do expired or late updates.
+pub fn received(sender: &str, share: &LocationShare) -> Result<Option<LiveEvent>> {
+    share.validate()?;
+    let Some(session_id) = &share.session_id else {
+        return Ok(None);
+    };
+    let now = now_secs();
+    let mut sessions = sessions().lock().unwrap();
+    if share.stopped {
+        // Either side may stop, but only a session they are part of
+        let ours = sessions
+            .live
+            .get(session_id)
+            .is_some_and(|s| s.contact == sender);
+        if !ours {
+            return Ok(None);
+        }
+        sessions.ended.insert(session_id.clone());
+        return Ok(sessions.live.remove(session_id).map(LiveEvent::Stopped));
+    }
+    if share.expires_at <= now || sessions.ended.contains(session_id) {
+        return Ok(None);
+    }
+
+    let max_live_secs = settings::location_settings().max_live_secs;
+    let session = sessions
+        .live
+        .entry(session_id.clone())
+        .or_insert_with(|| LiveSession {
+            id: session_id.clone(),
+            contact: sender.to_string(),
+            outgoing: false,
+            started_at: now,
+            ends_at: share.expires_at.min(now + max_live_secs),
+            updated_at: None,
+            position: None,
+        });
+    if session.contact != sender || session.outgoing {
+        anyhow::bail!("Live location update for someone else's session");
+    }
+    session.updated_at = Some(now);
+    session.position = share.position;
+    Ok(Some(LiveEvent::Updated(session.clone())))
+}
+
+/// End sessions past their time, returning them
+pub fn expire() -> Vec<LiveSession> {
+    let now = now_secs();
+    let mut sessions = sessions().lock().unwrap();
+    let expired: Vec<String> = sessions
+        .live
+        .values()
+        .filter(|s| s.ends_at <= now)
+        .map(|s| s.id.clone())
+        .collect();
+    expired
+        .into_iter()
+        .filter_map(|id| {
+            sessions.ended.insert(id.clone());
+            sessions.live.remove(&id)
+        })
+        .collect()
+}
+
+/// Running live sessions, ours and theirs
+pub fn live() -> Vec<LiveSession> {
+    let mut live: Vec<_> = sessions().lock().unwrap().live.values().cloned().collect();
+    live.sort_by(|a, b| a.started_at.cmp(&b.started_at));
+    live
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounding_widens_accuracy() {
+        let position = Position {
+            latitude: 52.520_008,
+            longitude: 13.404_954,
+            accuracy_m: Some(5.0),
+        };
+        let rounded = position.rounded(Some(2));
+        assert_eq!(rounded.latitude, 52.52);
+        assert_eq!(rounded.longitude, 13.4);
+        assert!(rounded.accuracy_m.unwrap() > 500.0);
+        assert_eq!(position.rounded(None), position);
+    }
+
+    #[test]
+    fn either_side_stops_a_session() {
+        let share = LocationShare {
+            position: Some(Position {
+                latitude: 1.0,
+                longitude: 2.0,
+                accuracy_m: None,
+            }),
+            expires_at: now_secs() + 60,
+            session_id: Some("s1".to_string()),
+            stopped: false,
+        };
+        assert!(matches!(
+            received("alice", &share).unwrap(),
+            Some(LiveEvent::Updated(_))
+        ));
+        assert!(received("mallory", &share).is_err());
+
+        let (session, stop_share) = stop("s1").unwrap();
+        assert!(!session.outgoing && stop_share.stopped);
+        // Late updates don't bring it back
+        assert!(received("alice", &share).unwrap().is_none());
+    }
+}
@@ -0,0 +1,406 @@
+use crate::crypto::SessionKey;
+use crate::settings;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tracing::warn;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+use zeroize::Zeroizing;
+
+const LOG_DIR: &str = "logs";
+const LOG_FILE: &str = "control.log";
+const ENCRYPTED_LOG_FILE: &str = "control.log.enc";
+const RECENT_LOG_LINES: usize = 1000;
+const MIN_LOG_FILE_BYTES: u64 = 64 * 1024;
+/// Values registered with `register_sensitive` that are remembered for redaction
+const MAX_SENSITIVE_VALUES: usize = 64;
+
+/// Log levels and the on-disk log file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogSettings {
+    /// Level for the app's own modules: error, warn, info, debug or trace
+    pub level: String,
+    /// Per-module overrides, e.g. "p2p" -> "debug"
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>,
+    /// Encrypt the log file with the settings key. That key is stored in the
+    /// clear next to it, so this only obfuscates the log
+    pub encrypt: bool,
+    pub max_file_bytes: u64,
+    /// Log files kept, including the current one
+    pub max_files: usize,
+    /// Write the log file at all; recent lines are kept in memory either way
+    #[serde(default = "default_to_file")]
+    pub to_file: bool,
+}
+
+fn default_to_file() -> bool {
+    true
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            modules: BTreeMap::new(),
+            encrypt: true,
+            max_file_bytes: 5 * 1024 * 1024,
+            max_files: 3,
+            to_file: default_to_file(),
+        }
+    }
+}
+
+impl LogSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_files == 0 {
+            anyhow::bail!("At least one log file must be kept");
+        }
+        if self.max_file_bytes < MIN_LOG_FILE_BYTES {
+            anyhow::bail!("Log files must be at least {} bytes", MIN_LOG_FILE_BYTES);
+        }
+        self.filter()?;
+        Ok(())
+    }
+
+    /// Dependencies only log warnings; the app's modules use the configured levels
+    fn filter(&self) -> Result<EnvFilter> {
+        let crate_name = env!("CARGO_CRATE_NAME");
+        let mut directives = vec!["warn".to_string(), format!("{}={}", crate_name, self.level)];
+        for (module, level) in &self.modules {
+            if !module.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':') {
+                anyhow::bail!("Invalid module name '{}'", module);
+            }
+            directives.push(format!("{}::{}={}", crate_name, module, level));
+        }
+
+        EnvFilter::builder()
+            .parse(directives.join(","))
+            .context("Invalid log level")
+    }
+}
+
+/// Size-rotated log file, optionally encrypted record by record
+/// ([u32 LE length][nonce | ciphertext] per line)
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    key: Option<SessionKey>,
+    max_file_bytes: u64,
+    max_files: usize,
+}
+
+impl LogFile {
+    fn open(dir: &Path, settings: &LogSettings, key: Option<SessionKey>) -> Result<Self> {
+        fs::create_dir_all(dir).context("Failed to create log directory")?;
+        let name = if key.is_some() { ENCRYPTED_LOG_FILE } else { LOG_FILE };
+        let path = dir.join(name);
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .context("Failed to open log file")?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+                .context("Failed to restrict log file permissions")?;
+        }
+
+        Ok(Self {
+            written: file.metadata()?.len(),
+            path,
+            file,
+            key,
+            max_file_bytes: settings.max_file_bytes,
+            max_files: settings.max_files,
+        })
+    }
+
+    fn append(&mut self, line: &str) -> Result<()> {
+        let record = match &self.key {
+            Some(key) => {
+                let encrypted = key.encrypt_file(line.as_bytes())?;
+                let mut record = (encrypted.len() as u32).to_le_bytes().to_vec();
+                record.extend_from_slice(&encrypted);
+                record
+            }
+            None => format!("{}\n", line).into_bytes(),
+        };
+
+        if self.written > 0 && self.written + record.len() as u64 > self.max_file_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(&record)?;
+        self.written += record.len() as u64;
+        Ok(())
+    }
+
+    /// control.log -> control.log.1 -> control.log.2 ..., dropping the oldest
+    fn rotate(&mut self) -> Result<()> {
+        let rotated = |index: usize| PathBuf::from(format!("{}.{}", self.path.display(), index));
+
+        let _ = fs::remove_file(rotated(self.max_files.saturating_sub(1).max(1)));
+        for index in (1..self.max_files.saturating_sub(1)).rev() {
+            let _ = fs::rename(rotated(index), rotated(index + 1));
+        }
+        if self.max_files > 1 {
+            fs::rename(&self.path, rotated(1)).context("Failed to rotate log file")?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)
+            .context("Failed to open log file")?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static LOG_FILE_STATE: Mutex<Option<LogFile>> = Mutex::new(None);
+static LOG_DIR_PATH: OnceLock<PathBuf> = OnceLock::new();
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+static SENSITIVE: Mutex<VecDeque<Zeroizing<String>>> = Mutex::new(VecDeque::new());
+
+/// Redact `value` wherever it appears in log lines written from now on
+pub fn register_sensitive(value: &str) {
+    let mut sensitive = SENSITIVE.lock().unwrap();
+    if value.is_empty() || sensitive.iter().any(|v| v.as_str() == value) {
+        return;
+    }
+    if sensitive.len() == MAX_SENSITIVE_VALUES {
+        sensitive.pop_front();
+    }
+    sensitive.push_back(Zeroizing::new(value.to_string()));
+}
+
+fn redact_sensitive(mut line: String) -> String {
+    for value in SENSITIVE.lock().unwrap().iter() {
+        if line.contains(value.as_str()) {
+            line = line.replace(value.as_str(), "[redacted]");
+        }
+    }
+    line
+}
+
+/// Writer behind the file layer: keeps recent lines for the log viewer and
+/// appends them to the log file once it is open
+struct LogSink;
+
+impl Write for LogSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = redact_sensitive(String::from_utf8_lossy(buf).trim_end().to_string());
+
+        if let Some(file) = LOG_FILE_STATE.lock().unwrap().as_mut() {
+            // Nowhere left to report a failed log write
+            let _ = file.append(&line);
+        }
+
+        let mut recent = RECENT.lock().unwrap();
+        if recent.len() == RECENT_LOG_LINES {
+            recent.pop_front();
+        }
+        recent.push_back(line);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Install the global logger with default levels; call first thing at startup.
+/// Lines are kept in memory until `start_file_logging` opens the log file.
+pub fn init() {
+    let (filter, handle) = reload::Layer::new(
+        LogSettings::default()
+            .filter()
+            .expect("default log filter is valid"),
+    );
+
+    let result = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .with(fmt::layer().with_ansi(false).with_writer(|| LogSink))
+        .try_init();
+
+    if result.is_ok() {
+        let _ = FILTER.set(handle);
+    }
+}
+
+/// Start writing the log file in the data directory with the saved log settings
+pub fn start_file_logging(data_dir: &Path) {
+    let _ = LOG_DIR_PATH.set(data_dir.join(LOG_DIR));
+    apply(&settings::log_settings());
+}
+
+/// Apply log settings at runtime: levels take effect immediately and the
+/// log file is reopened with the new encryption and rotation settings, or
+/// closed if it is turned off
+pub fn apply(log_settings: &LogSettings) {
+    match log_settings.filter() {
+        Ok(filter) => {
+            if let Some(handle) = FILTER.get() {
+                let _ = handle.reload(filter);
+            }
+        }
+        Err(e) => warn!("Ignoring log levels: {}", e),
+    }
+
+    let Some(dir) = LOG_DIR_PATH.get() else {
+        return;
+    };
+    if !log_settings.to_file {
+        *LOG_FILE_STATE.lock().unwrap() = None;
+        return;
+    }
+    let key = if log_settings.encrypt {
+        match settings::storage_key() {
+            Some(key) => Some(key),
+            None => {
+                warn!("Settings key unavailable, log file is not written");
+                *LOG_FILE_STATE.lock().unwrap() = None;
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    let file = LogFile::open(dir, log_settings, key);
+    let mut state = LOG_FILE_STATE.lock().unwrap();
+    match file {
+        Ok(file) => *state = Some(file),
+        Err(e) => {
+            *state = None;
+            drop(state);
+            warn!("Failed to open log file: {}", e);
+        }
+    }
+}
+
+/// Most recent log lines, oldest first
+pub fn recent_logs(limit: usize) -> Vec<String> {
+    let recent = RECENT.lock().unwrap();
+    recent
+        .iter()
+        .skip(recent.len().saturating_sub(limit))
+        .cloned()
+        .collect()
+}
+
+/// Write a diagnostics report (app version, platform, log levels and recent
+/// logs) with keys, CIDs and network addresses scrubbed, for attaching to bug reports
+pub fn export_diagnostics(path: &Path) -> Result<usize> {
+    let log_settings = settings::log_settings();
+    let lines = recent_logs(RECENT_LOG_LINES);
+
+    let mut report = String::new();
+    report.push_str(&format!("Control {} diagnostics\n", env!("CARGO_PKG_VERSION")));
+    report.push_str(&format!("Generated: {}\n", chrono::Utc::now().to_rfc3339()));
+    report.push_str(&format!(
+        "Platform: {} {}\n",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    ));
+    report.push_str(&format!("Log level: {}\n", log_settings.level));
+    for (module, level) in &log_settings.modules {
+        report.push_str(&format!("Log level {}: {}\n", module, level));
+    }
+    report.push_str("\nRecent logs:\n");
+    for line in &lines {
+        report.push_str(&scrub(line));
+        report.push('\n');
+    }
+
+    fs::write(path, report).context("Failed to write diagnostics")?;
+    Ok(lines.len())
+}
+
+/// Replace key material, CIDs, peer IDs, IP addresses, multiaddrs and URLs
+/// with placeholders
+pub fn scrub(line: &str) -> String {
+    let is_token_char =
+        |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | '/' | '+' | '=' | '-' | '_');
+
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(is_token_char) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c: char| !is_token_char(c)).unwrap_or(rest.len());
+        let token = &rest[..end];
+        rest = &rest[end..];
+
+        // Keep sentence punctuation out of the classification
+        let trimmed = token.trim_end_matches(['.', ':']);
+        out.push_str(scrub_token(trimmed).unwrap_or(trimmed));
+        out.push_str(&token[trimmed.len()..]);
+    }
+    out.push_str(rest);
+    out
+}
+
+fn scrub_token(token: &str) -> Option<&'static str> {
+    let is_multiaddr = ["/ip4/", "/ip6/", "/dns", "/onion"]
+        .iter()
+        .any(|prefix| token.starts_with(prefix));
+    if is_multiaddr || token.contains("://") || token.contains(".onion") {
+        return Some("[address]");
+    }
+
+    let host = token.rsplit_once(':').map_or(token, |(host, port)| {
+        if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) {
+            host
+        } else {
+            token
+        }
+    });
+    let is_ipv4 = host.split('.').count() == 4
+        && host.split('.').all(|part| !part.is_empty() && part.parse::<u8>().is_ok());
+    let is_ipv6 = (token.contains("::") || token.matches(':').count() == 7)
+        && token.chars().all(|c| c.is_ascii_hexdigit() || c == ':');
+    if is_ipv4 || is_ipv6 {
+        return Some("[address]");
+    }
+
+    // Keys, shards, CIDs and peer IDs are long runs of base58/base64/hex characters
+    let is_encoded = token
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=' | '-' | '_'));
+    if token.len() >= 32 && is_encoded {
+        return Some("[redacted]");
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrub_removes_keys_and_addresses() {
+        let line = "Dialing relay: /ip4/1.2.3.4/tcp/4001/p2p/12D3KooWabc. Uploaded \
+                    bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku from 10.0.0.1:5001";
+        assert_eq!(
+            scrub(line),
+            "Dialing relay: [address]. Uploaded [redacted] from [address]"
+        );
+        assert_eq!(scrub("Shredded 3 files (1024 bytes)"), "Shredded 3 files (1024 bytes)");
+    }
+}
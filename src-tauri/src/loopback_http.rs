@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Largest request head the loopback servers read
+const MAX_HEAD_BYTES: usize = 8 * 1024;
+/// Time a client gets to send its request head, so idle connections don't pile up
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Request line and headers of an HTTP/1.1 request to one of the servers on
+/// 127.0.0.1 (the local API and the media stream endpoint)
+pub(crate) struct RequestHead {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    /// Names in lower case
+    headers: Vec<(String, String)>,
+    /// What was read past the head: the start of the body
+    pub rest: Vec<u8>,
+}
+
+impl RequestHead {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Whether the request was addressed to the server on `port` by a
+    /// loopback name. A page elsewhere could resolve its own host name to
+    /// 127.0.0.1, so anything else is refused.
+    pub fn is_loopback_host(&self, port: u16) -> bool {
+        let Some(host) = self.header("host") else {
+            return false;
+        };
+        [format!("127.0.0.1:{}", port), format!("localhost:{}", port)]
+            .iter()
+            .any(|allowed| host.eq_ignore_ascii_case(allowed))
+    }
+}
+
+/// Read a request head, giving up after `HEAD_TIMEOUT`. `None` means the
+/// head was malformed, too large, or the client left.
+pub(crate) async fn read_head<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<RequestHead>> {
+    tokio::time::timeout(HEAD_TIMEOUT, read_head_untimed(stream))
+        .await
+        .context("Timed out reading the request")?
+}
+
+async fn read_head_untimed<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<RequestHead>> {
+    let mut buffer = Vec::with_capacity(1024);
+    let mut read = [0u8; 4096];
+    let head_len = loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        if buffer.len() > MAX_HEAD_BYTES {
+            return Ok(None);
+        }
+        let n = stream.read(&mut read).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&read[..n]);
+    };
+
+    let Ok(head) = std::str::from_utf8(&buffer[..head_len]) else {
+        return Ok(None);
+    };
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+
+    Ok(Some(RequestHead {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        headers,
+        rest: buffer.split_off(head_len + 4),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_head_and_keeps_the_rest() {
+        let raw = b"POST /v1/messages?x=1 HTTP/1.1\r\nHost: LOCALHOST:7340\r\nContent-Length: 4\r\n\r\n{}{}";
+        let head = read_head(&mut &raw[..]).await.unwrap().unwrap();
+
+        assert_eq!(head.method, "POST");
+        assert_eq!(head.path, "/v1/messages");
+        assert_eq!(head.query_param("x"), Some("1"));
+        assert_eq!(head.header("content-length"), Some("4"));
+        assert!(head.is_loopback_host(7340));
+        assert!(!head.is_loopback_host(7341));
+        assert_eq!(head.rest, b"{}{}");
+    }
+
+    #[tokio::test]
+    async fn oversized_head_is_refused() {
+        let raw = format!(
+            "GET / HTTP/1.1\r\nX: {}\r\n\r\n",
+            "a".repeat(MAX_HEAD_BYTES)
+        );
+        assert!(read_head(&mut raw.as_bytes()).await.unwrap().is_none());
+    }
+}
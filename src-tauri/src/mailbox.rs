@@ -0,0 +1,444 @@
+use crate::catalog::now_secs;
+use crate::crypto::{decode_public_key, decrypt_message, encrypt_message, Identity, SessionKey};
+use crate::no_traces;
+use crate::p2p::relay_peer_id;
+use crate::wire;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response::{self, ResponseChannel};
+use libp2p::{PeerId, StreamProtocol};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use x25519_dalek::PublicKey;
+use zeroize::Zeroize;
+
+pub const PROTOCOL: StreamProtocol = StreamProtocol::new("/deaddrop/mailbox/1.0.0");
+const MAILBOX_FILE: &str = "mailbox.enc";
+const MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+/// Per inbox; the oldest envelopes go first
+const MAX_ENVELOPES_PER_INBOX: usize = 1000;
+/// Envelopes per fetch response
+const PAGE_SIZE: usize = 100;
+/// Envelope IDs remembered to drop copies arriving both live and from a mailbox
+const SEEN_CAPACITY: usize = 2000;
+
+/// A peer that keeps envelopes for us while we are offline
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MailboxPeer {
+    /// Public ID of the Control identity running the mailbox
+    pub public_id: String,
+    /// Multiaddr ending in `/p2p/<peer id>`
+    pub address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MailboxSettings {
+    /// Mailboxes we fetch from on reconnect
+    pub peers: Vec<MailboxPeer>,
+    /// Public IDs whose inboxes we keep (contribute mode)
+    pub serve_for: Vec<String>,
+    /// How long kept envelopes live
+    pub retention_days: u32,
+}
+
+impl Default for MailboxSettings {
+    fn default() -> Self {
+        Self {
+            peers: Vec::new(),
+            serve_for: Vec::new(),
+            retention_days: 7,
+        }
+    }
+}
+
+impl MailboxSettings {
+    pub fn validate(&self) -> Result<()> {
+        for peer in &self.peers {
+            decode_public_key(&peer.public_id).context("Invalid mailbox public ID")?;
+            if relay_peer_id(&peer.address).is_none() {
+                anyhow::bail!("Mailbox address must end in /p2p/<peer id>: {}", peer.address);
+            }
+        }
+        for public_id in &self.serve_for {
+            decode_public_key(public_id).context("Invalid public ID to keep mail for")?;
+        }
+        if !(1..=90).contains(&self.retention_days) {
+            anyhow::bail!("Mailbox retention must be between 1 and 90 days");
+        }
+        Ok(())
+    }
+
+    pub fn addresses(&self) -> Vec<String> {
+        self.peers.iter().map(|p| p.address.clone()).collect()
+    }
+
+    fn peer(&self, peer_id: &PeerId) -> Option<&MailboxPeer> {
+        self.peers
+            .iter()
+            .find(|p| relay_peer_id(&p.address).as_ref() == Some(peer_id))
+    }
+}
+
+/// Sealed request: the owner's public key followed by an encrypted `MailboxOp`
+#[derive(Debug, Clone)]
+pub struct MailboxRequest(pub Vec<u8>);
+
+/// Sealed response: the mailbox's public key followed by an encrypted `MailboxReply`
+#[derive(Debug, Clone)]
+pub struct MailboxResponse(pub Vec<u8>);
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum MailboxOp {
+    /// Delete `ack`, then return the oldest remaining envelopes
+    Fetch { ack: Vec<String> },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+enum MailboxReply {
+    Envelopes { envelopes: Vec<Envelope> },
+    Refused { reason: String },
+}
+
+/// A gossip payload as it was published to an inbox topic
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Envelope {
+    pub id: String,
+    pub data: String, // hex
+    pub received_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct StoredEnvelope {
+    owner: String,
+    envelope: Envelope,
+}
+
+/// Length-prefixed frames over a request-response stream
+#[derive(Debug, Clone, Default)]
+pub struct MailboxCodec;
+
+#[async_trait]
+impl request_response::Codec for MailboxCodec {
+    type Protocol = StreamProtocol;
+    type Request = MailboxRequest;
+    type Response = MailboxResponse;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<MailboxRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_frame(io).await.map(MailboxRequest)
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+    ) -> io::Result<MailboxResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_frame(io).await.map(MailboxResponse)
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        MailboxRequest(data): MailboxRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_frame(io, &data).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        MailboxResponse(data): MailboxResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_frame(io, &data).await
+    }
+}
+
+pub async fn read_frame<T: AsyncRead + Unpin + Send>(io: &mut T) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    io.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Mailbox frame too large",
+        ));
+    }
+    let mut data = vec![0u8; len];
+    io.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+pub async fn write_frame<T: AsyncWrite + Unpin + Send>(io: &mut T, data: &[u8]) -> io::Result<()> {
+    io.write_all(&(data.len() as u32).to_be_bytes()).await?;
+    io.write_all(data).await?;
+    io.flush().await
+}
+
+pub fn behaviour() -> request_response::Behaviour<MailboxCodec> {
+    request_response::Behaviour::new(
+        [(PROTOCOL, request_response::ProtocolSupport::Full)],
+        request_response::Config::default(),
+    )
+}
+
+/// Mailbox state owned by the P2P actor: envelopes kept for others, and
+/// which envelopes we already handled
+pub struct Mailbox {
+    path: PathBuf,
+    storage_key: SessionKey,
+    stored: Vec<StoredEnvelope>,
+    seen: HashSet<String>,
+    seen_order: VecDeque<String>,
+}
+
+impl Mailbox {
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(MAILBOX_FILE);
+        let storage_key = identity.storage_key(b"mailbox");
+
+        let stored = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read mailbox")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt mailbox")?;
+            let stored = serde_json::from_slice(&json);
+            json.zeroize();
+            stored.context("Failed to parse mailbox")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            stored,
+            seen: HashSet::new(),
+            seen_order: VecDeque::new(),
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        if self.stored.is_empty() && !no_traces::file_exists(&self.path) {
+            return Ok(());
+        }
+        let mut json = serde_json::to_vec(&self.stored)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write mailbox")?;
+        Ok(())
+    }
+
+    /// Whether `data` is new; later copies of the same envelope return false
+    pub fn first_seen(&mut self, data: &[u8]) -> bool {
+        let id = envelope_id(data);
+        if !self.seen.insert(id.clone()) {
+            return false;
+        }
+        self.seen_order.push_back(id);
+        if self.seen_order.len() > SEEN_CAPACITY {
+            if let Some(oldest) = self.seen_order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Keep an envelope published to the inbox of `owner`, if we serve them
+    pub fn keep(&mut self, settings: &MailboxSettings, owner: &str, data: &[u8]) {
+        if !settings.serve_for.iter().any(|id| id == owner) {
+            return;
+        }
+        let id = envelope_id(data);
+        if self.stored.iter().any(|s| s.owner == owner && s.envelope.id == id) {
+            return;
+        }
+
+        let count = self.stored.iter().filter(|s| s.owner == owner).count();
+        if count >= MAX_ENVELOPES_PER_INBOX {
+            if let Some(oldest) = self.stored.iter().position(|s| s.owner == owner) {
+                self.stored.remove(oldest);
+            }
+        }
+        self.stored.push(StoredEnvelope {
+            owner: owner.to_string(),
+            envelope: Envelope {
+                id,
+                data: hex::encode(data),
+                received_at: now_secs(),
+            },
+        });
+        if let Err(e) = self.save() {
+            warn!("Failed to save mailbox: {}", e);
+        }
+    }
+
+    /// Drop expired envelopes and those of owners we no longer serve
+    pub fn prune(&mut self, settings: &MailboxSettings) {
+        let cutoff = now_secs().saturating_sub(settings.retention_days as u64 * 24 * 60 * 60);
+        let before = self.stored.len();
+        self.stored.retain(|s| {
+            s.envelope.received_at >= cutoff && settings.serve_for.iter().any(|id| *id == s.owner)
+        });
+        if self.stored.len() != before {
+            info!("Mailbox: pruned {} envelopes", before - self.stored.len());
+            if let Err(e) = self.save() {
+                warn!("Failed to save mailbox: {}", e);
+            }
+        }
+    }
+
+    /// Answer a fetch from an inbox owner we serve
+    pub fn serve(
+        &mut self,
+        behaviour: &mut request_response::Behaviour<MailboxCodec>,
+        identity: &Identity,
+        settings: &MailboxSettings,
+        request: MailboxRequest,
+        channel: ResponseChannel<MailboxResponse>,
+    ) -> Result<()> {
+        let (owner_pk, op) = open::<MailboxOp>(identity, &request.0)?;
+        let owner = bs58::encode(owner_pk.as_bytes()).into_string();
+
+        let reply = if settings.serve_for.iter().any(|id| *id == owner) {
+            let MailboxOp::Fetch { ack } = op;
+            self.stored
+                .retain(|s| s.owner != owner || !ack.contains(&s.envelope.id));
+            if !ack.is_empty() {
+                self.save()?;
+            }
+            let envelopes = self
+                .stored
+                .iter()
+                .filter(|s| s.owner == owner)
+                .take(PAGE_SIZE)
+                .map(|s| s.envelope.clone())
+                .collect();
+            MailboxReply::Envelopes { envelopes }
+        } else {
+            MailboxReply::Refused {
+                reason: "Not keeping mail for this identity".to_string(),
+            }
+        };
+
+        let response = MailboxResponse(seal(identity, &owner_pk, &reply)?);
+        behaviour
+            .send_response(channel, response)
+            .map_err(|_| anyhow::anyhow!("Mailbox fetch from {} was dropped", owner))?;
+        Ok(())
+    }
+
+    /// Ask a configured mailbox peer for what it kept, acknowledging `ack`
+    pub fn fetch(
+        behaviour: &mut request_response::Behaviour<MailboxCodec>,
+        identity: &Identity,
+        settings: &MailboxSettings,
+        peer_id: &PeerId,
+        ack: Vec<String>,
+    ) -> Result<()> {
+        let Some(peer) = settings.peer(peer_id) else {
+            return Ok(());
+        };
+        let mailbox_pk = decode_public_key(&peer.public_id)?;
+        let request = seal(identity, &mailbox_pk, &MailboxOp::Fetch { ack })?;
+        behaviour.send_request(peer_id, MailboxRequest(request));
+        Ok(())
+    }
+
+    /// Open a fetch response from a configured mailbox. Returns the envelope
+    /// IDs to acknowledge and the payloads not seen before.
+    pub fn received(
+        &mut self,
+        identity: &Identity,
+        settings: &MailboxSettings,
+        peer_id: &PeerId,
+        response: MailboxResponse,
+    ) -> Result<(Vec<String>, Vec<Vec<u8>>)> {
+        let peer = settings
+            .peer(peer_id)
+            .context("Mailbox response from a peer that is not our mailbox")?;
+        let (mailbox_pk, reply) = open::<MailboxReply>(identity, &response.0)?;
+        if bs58::encode(mailbox_pk.as_bytes()).into_string() != peer.public_id {
+            anyhow::bail!("Mailbox response is not from {}", peer.public_id);
+        }
+
+        let envelopes = match reply {
+            MailboxReply::Envelopes { envelopes } => envelopes,
+            MailboxReply::Refused { reason } => anyhow::bail!("Mailbox refused: {}", reason),
+        };
+        if !envelopes.is_empty() {
+            info!("Mailbox {}: {} envelopes", peer.public_id, envelopes.len());
+        }
+
+        let mut ack = Vec::new();
+        let mut payloads = Vec::new();
+        for envelope in envelopes {
+            ack.push(envelope.id);
+            match hex::decode(&envelope.data) {
+                Ok(data) if self.first_seen(&data) => payloads.push(data),
+                Ok(_) => {}
+                Err(e) => warn!("Skipping malformed mailbox envelope: {}", e),
+            }
+        }
+        Ok((ack, payloads))
+    }
+}
+
+pub fn envelope_id(data: &[u8]) -> String {
+    hex::encode(&Sha256::digest(data)[..16])
+}
+
+/// Encrypt `value` for `peer` with our ECDH key, prefixed with our public key
+pub fn seal<T: Serialize>(identity: &Identity, peer: &PublicKey, value: &T) -> Result<Vec<u8>> {
+    let mut encoded = wire::encode(value)?;
+    let mut shared_secret = identity.shared_secret(peer);
+    let encrypted = encrypt_message(&shared_secret, &encoded);
+    shared_secret.zeroize();
+    encoded.zeroize();
+
+    let mut data = identity.public_key.as_bytes().to_vec();
+    data.extend_from_slice(&encrypted?);
+    Ok(data)
+}
+
+/// Decrypt a sealed value, returning the sender's public key. Decryption
+/// only succeeds if the sender holds the private key for it.
+pub fn open<T: DeserializeOwned>(identity: &Identity, data: &[u8]) -> Result<(PublicKey, T)> {
+    if data.len() < 32 {
+        anyhow::bail!("Sealed message too short");
+    }
+    let (key_bytes, encrypted) = data.split_at(32);
+    let mut key = [0u8; 32];
+    key.copy_from_slice(key_bytes);
+    let sender = PublicKey::from(key);
+
+    let mut shared_secret = identity.shared_secret(&sender);
+    let mut decrypted = decrypt_message(&shared_secret, encrypted)?;
+    shared_secret.zeroize();
+    let value = wire::decode(&decrypted);
+    decrypted.zeroize();
+    let value = value.context("Invalid sealed message")?;
+    Ok((sender, value))
+}
@@ -3,70 +3,273 @@
     windows_subsystem = "windows"
 )]
 
-mod crypto;
-mod dead_drop;
-mod p2p;
+use age::secrecy::ExposeSecret;
+use control::age_drop::{
+    age_identity, age_recipient, create_age_drop, is_age_encrypted, retrieve_age_drop,
+    AgeDropConfig,
+};
+use control::archive::{
+    self, start_retention_worker, ArchiveInfo, ArchiveStore, ConversationUsage, RetentionSettings,
+};
+use control::audit::{AuditAction, AuditLog};
+use control::away_ping::{self, AwayPingSettings};
+use control::backup::{start_backup_scheduler, BackupRecord, BackupSettings};
+use control::benchmark::BenchmarkReport;
+use control::burn::{unwrap_shards, wrap_shards, BurnRegistry, DropBurned, WrappedShard};
+use control::canary::{
+    self, Canaries, CanaryAlert, CanaryContact, CanaryDrop, CanarySettings, CanaryStore,
+};
+use control::car::CarImportResult;
+use control::catalog::{now_secs, DropCatalog, DropRecord};
+use control::chunk_index::{
+    create_indexed_drop, fetch_chunk_index, index_manifest_cid, read_range, stat_indexed_drop,
+};
+use control::conformance::ConformanceReport;
+use control::contact_card::{ContactCard, ContactCardImport};
+use control::contact_import::{self, ImportFormat, ImportReport};
+use control::contacts::{self, Appearance, Contact, ContactBook};
+use control::conversations::{Conversation, ConversationStore};
+use control::crypto::Identity;
+use control::dead_drop::{
+    cleanup_dead_drops, create_dead_drop, create_dead_drop_from_reader, drop_cache_dir,
+    drop_manifest_cid, is_upload_pending, new_drop_cache, repair_dead_drop, retrieve_dead_drop,
+    retrieve_dead_drop_to_memory, stat_dead_drop, unpin_dead_drop, verify_dead_drop,
+    DeadDropCreated, DropAvailability, DropCleanupReport, DropOptions, DropProgress,
+    DropRepairResult, DropStat,
+};
+use control::dead_man::{self, start_dead_man_scheduler, DeadManSwitch, SwitchConfig};
+use control::dedup::{create_dedup_drop, dedup_manifest_cid, stat_dedup_drop};
+use control::devices::{self, Device, DeviceStore, RevocationReport};
+use control::erasure::{
+    create_erasure_drop, erasure_manifest_cid, retrieve_erasure_drop, stat_erasure_drop,
+    ErasureConfig,
+};
+use control::errors::{ControlError, ErrorCode};
+use control::escrow::{
+    self, create_escrow_drop, escrow_manifest_cid, stat_escrow_drop, EscrowRequestStatus,
+    PendingApproval,
+};
+use control::events::EventReplay;
+use control::groups::{publish_manifest, Group, GroupDrop, GroupStore, MemberShard, ReceivedShard};
+use control::health::{start_health_prober, InfrastructureHealth};
+use control::i18n::{LocaleInfo, MessageCatalog};
+use control::introductions::{
+    accept_introduction, decline_introduction, IntroductionStore, PendingIntroduction,
+};
+use control::ipfs::IpfsNetworkSettings;
+use control::key_log::{self, KeyKind, KeyLog, KeySource};
+use control::knock::{KnockRecord, KnockStore};
+use control::kubo::{KuboSettings, KuboStatus};
+use control::location::{self, LiveSession, LocationSettings, Position};
+use control::media_stream::DropStream;
+use control::message_drop::MessageDrop;
+use control::no_traces::{self, NoTracesSettings, NoTracesStatus};
+use control::operations::{
+    OperationJob, OperationKind, OperationManager, OperationStatus, Priority,
+};
+use control::p2p::{GhostMessage, MessageEvent, P2PCommand, P2PEvents};
+use control::padding::{
+    create_padded_drop, padded_manifest_cid, retrieve_padded_drop, stat_padded_drop,
+};
+use control::password_drop::{
+    create_password_drop, password_manifest_cid, retrieve_password_drop, stat_password_drop,
+};
+use control::pgp_drop::{create_pgp_drop, PgpDropConfig};
+use control::pin_health::{start_pin_monitor, DropPinHealth, PinHealthSettings};
+use control::plugins::{self, PluginInfo, PluginSettings};
+use control::polls::{self, PollMessage, PollResults, Reveal};
+use control::profile::{ProfileImportOptions, ProfileImportReport};
+use control::proofs::{self, Claim, OwnProof, ProofCheck, ProofStore};
+use control::recipients::{
+    create_recipients_drop, recipients_manifest_cid, retrieve_recipients_drop, stat_recipients_drop,
+};
+use control::recovery::{start_recovery_reminders, RecoveryStatus};
+use control::remote_wipe::{self, PendingWipe, RemoteWipeSettings};
+use control::search::{self, SearchHit, SearchIndex, SearchSettings};
+use control::secrets::StoredSecret;
+use control::session::{lock_session, start_idle_watcher, AppPhase, AppState};
+use control::settings::{AppSettings, DropSettings};
+use control::shards::{
+    open_sealed_shards, recover_session_key, reshare_session_key, seal_shards, SealedShard,
+};
+use control::shred::{shred_file, ShredReport};
+use control::staging::cleanup_staging;
+use control::stego::{create_stego_drop, retrieve_stego_drop, stego_cid};
+use control::storage::StoragePaths;
+use control::sync::{start_sync_worker, SyncReport};
+use control::timelock::{
+    create_timelock_drop, stat_timelock_drop, timelock_manifest_cid, timelock_status,
+    TimelockStatus,
+};
+use control::transcript::{self, ComplianceSettings, Transcript};
+use control::trust::{self, AttestationStore, ReceivedAttestation, Trust, TrustSettings};
+use control::updater::{DownloadedUpdate, UpdateCheck};
+use control::upload_schedule::{self, start_upload_worker, PendingUpload, PendingUploads};
+use control::usage::{start_usage_tracker, UsageEvent, UsageStats};
+use control::versions::{
+    create_versioned_drop, list_drop_versions, retrieve_versioned_drop, update_versioned_drop,
+    DropVersionInfo,
+};
+use control::view_once::{ViewOnceRecord, ViewOnceStore};
+use control::watch_folder::{start_watch_folder, WatchFolderSettings};
+use control::webhooks::{self, WebhookEndpoint, WebhookSettings};
+use control::IdentityStore;
+use control::{
+    audit, backup, benchmark, car, clipboard, conformance, contact_card, conversations, events,
+    health, i18n, ipfs, kubo, logging, media_stream, message_drop, mfs, notifications, pin_health,
+    profile, recovery, secrets, session, settings, storage, sync, tray, updater, usage,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::{Manager, State};
+use tracing::{error, info, warn};
+use zeroize::Zeroize;
 
-use crypto::Identity;
-use dead_drop::{create_dead_drop, retrieve_dead_drop, DeadDropCreated};
-use p2p::{init_p2p_actor, P2PCommand};
-use std::sync::Mutex;
-use tauri::State;
-use tokio::sync::mpsc;
-
-/// Application state shared across commands
-pub struct AppState {
-    pub identity: Mutex<Option<Identity>>,
-    pub p2p_sender: Mutex<Option<mpsc::Sender<P2PCommand>>>,
+/// Resolve the application data directory (resolved from the app config at startup)
+fn app_data_dir() -> Result<PathBuf, ControlError> {
+    storage::data_dir().map_err(ControlError::wrap("error.get_app_data_directory"))
 }
 
-impl AppState {
-    fn new() -> Self {
-        Self {
-            identity: Mutex::new(None),
-            p2p_sender: Mutex::new(None),
-        }
+/// Add a drop action to the audit log, with the error it ended in if any
+fn audit_drop<T>(
+    action: AuditAction,
+    drop: &str,
+    params: serde_json::Value,
+    result: &Result<T, ControlError>,
+) {
+    if let Ok(data_dir) = app_data_dir() {
+        let error = result.as_ref().err().map(|e| e.message.clone());
+        audit::record(&data_dir, action, drop, params, error);
     }
 }
 
+/// Streaming options for a drop command: saved settings, the ciphertext cache
+/// (none in no-traces mode) and `drop_progress` events to the window
+fn drop_options(window: &tauri::Window) -> Result<DropOptions, ControlError> {
+    let data_dir = app_data_dir()?;
+    let settings = settings::drop_settings();
+    let progress_window = window.clone();
+
+    Ok(DropOptions {
+        cache_dir: new_drop_cache(&data_dir),
+        progress: Some(std::sync::Arc::new(move |progress: DropProgress| {
+            let _ = progress_window.emit("drop_progress", progress);
+        })),
+        ..settings.drop_options(&data_dir)
+    })
+}
+
+/// Clone the unlocked identity out of the app state
+fn current_identity(state: &State<'_, AppState>) -> Result<Identity, ControlError> {
+    Ok(state.identity()?)
+}
+
 /// Initialize identity with password
 #[tauri::command]
-async fn init_identity(password: String, state: State<'_, AppState>) -> Result<String, String> {
-    let app_data_dir = tauri::api::path::app_data_dir(&tauri::Config::default())
-        .ok_or("Failed to get app data directory")?;
+async fn init_identity(
+    password: String,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<String, ControlError> {
+    let app_data_dir = app_data_dir()?;
+    let store = IdentityStore::new(&app_data_dir);
 
     // Try to load or generate identity
-    let identity = match Identity::load_or_generate(&password, app_data_dir.clone()) {
+    let identity = match store.open(&password) {
         Ok(id) => id,
         Err(e) => {
             // If loading fails, delete old identity file and create new one
-            eprintln!("Failed to load identity: {}. Creating new identity...", e);
+            warn!("Failed to load identity: {}. Creating new identity...", e);
             let identity_path = app_data_dir.join("identity.enc");
             if identity_path.exists() {
                 std::fs::remove_file(&identity_path)
-                    .map_err(|e| format!("Failed to delete old identity: {}", e))?;
+                    .map_err(ControlError::wrap("error.delete_old_identity"))?;
             }
-            Identity::load_or_generate(&password, app_data_dir)
-                .map_err(|e| format!("Failed to create new identity: {}", e))?
+            store
+                .create(&password)
+                .map_err(ControlError::wrap("error.create_new_identity"))?
         }
     };
 
     let public_id = identity.public_id();
 
-    *state.identity.lock().unwrap() = Some(identity);
+    state.unlock(identity)?;
+    auto_start_ghost_mode(&window, &state, app_data_dir).await?;
 
     Ok(public_id)
 }
 
-/// Get current public identity
+/// Unlock a locked session with the identity password, restarting Ghost Mode
+/// if it was running when the session locked or is set to start automatically
+#[tauri::command]
+async fn unlock(
+    password: String,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<String, ControlError> {
+    let app_data_dir = app_data_dir()?;
+    let store = IdentityStore::new(&app_data_dir);
+    if !store.exists() {
+        return Err(ControlError::localized(
+            ErrorCode::NoIdentity,
+            "error.no_identity",
+        ));
+    }
+
+    let identity = store
+        .unlock(&password)
+        .map_err(|_| ControlError::localized(ErrorCode::WrongPassword, "error.wrong_password"))?;
+    let public_id = identity.public_id();
+    state.unlock(identity)?;
+    auto_start_ghost_mode(&window, &state, app_data_dir).await?;
+
+    events::emit(&window.app_handle(), "unlocked", &public_id);
+    Ok(public_id)
+}
+
+/// Start Ghost Mode right after unlocking if it was running when the session
+/// locked, or if the user has it set to start automatically
+async fn auto_start_ghost_mode(
+    window: &tauri::Window,
+    state: &AppState,
+    data_dir: PathBuf,
+) -> Result<(), ControlError> {
+    let resume = state.take_resume_ghost_mode();
+    if state.phase() != AppPhase::Unlocked || !(resume || settings::p2p_settings().auto_start) {
+        return Ok(());
+    }
+
+    session::start_ghost_mode(state, Arc::new(window.clone()), data_dir)
+        .await
+        .map_err(ControlError::wrap("error.start_p2p"))?;
+    tray::ghost_mode_changed(&window.app_handle(), true);
+    Ok(())
+}
+
+/// Lock the session now, dropping all key material
+#[tauri::command]
+async fn lock(app: tauri::AppHandle) -> Result<(), ControlError> {
+    lock_session(&app, "manual");
+    Ok(())
+}
+
+/// Report user activity from the frontend to postpone the idle lock
+#[tauri::command]
+async fn record_activity(state: State<'_, AppState>) -> Result<(), ControlError> {
+    state.touch();
+    Ok(())
+}
+
+/// Current lifecycle phase, so the frontend knows which commands are allowed
 #[tauri::command]
-async fn get_public_id(state: State<'_, AppState>) -> Result<String, String> {
-    let identity_guard = state.identity.lock().unwrap();
-    let identity = identity_guard
-        .as_ref()
-        .ok_or("Identity not initialized")?;
+async fn get_app_state(state: State<'_, AppState>) -> Result<AppPhase, ControlError> {
+    Ok(state.phase())
+}
 
-    Ok(identity.public_id())
+/// Get current public identity
+#[tauri::command]
+async fn get_public_id(state: State<'_, AppState>) -> Result<String, ControlError> {
+    Ok(current_identity(&state)?.public_id())
 }
 
 /// Start Ghost Mode (P2P messaging)
@@ -74,19 +277,13 @@ async fn get_public_id(state: State<'_, AppState>) -> Result<String, String> {
 async fn start_ghost_mode(
     window: tauri::Window,
     state: State<'_, AppState>,
-) -> Result<String, String> {
-    let identity = {
-        let identity_guard = state.identity.lock().unwrap();
-        identity_guard
-            .as_ref()
-            .ok_or("Identity not initialized")?
-            .clone()
-    };
-
-    let p2p_sender = init_p2p_actor(identity.clone(), window)
-        .map_err(|e| format!("Failed to start P2P: {}", e))?;
+) -> Result<String, ControlError> {
+    let app = window.app_handle();
+    session::start_ghost_mode(&state, Arc::new(window), app_data_dir()?)
+        .await
+        .map_err(ControlError::wrap("error.start_p2p"))?;
 
-    *state.p2p_sender.lock().unwrap() = Some(p2p_sender);
+    tray::ghost_mode_changed(&app, true);
 
     Ok("Ghost Mode activated".to_string())
 }
@@ -96,42 +293,724 @@ async fn start_ghost_mode(
 async fn send_ghost_message(
     target_public_key: String,
     content: String,
+    view_once: Option<bool>,
     state: State<'_, AppState>,
-) -> Result<String, String> {
-    let sender = {
-        let sender_guard = state.p2p_sender.lock().unwrap();
-        sender_guard
-            .as_ref()
-            .ok_or("Ghost Mode not started")?
-            .clone()
-    };
+) -> Result<String, ControlError> {
+    state.touch();
+    let sender = state.p2p_sender()?;
+    let identity = current_identity(&state)?;
 
     // Generate UUID for message tracking
     let message_id = uuid::Uuid::new_v4().to_string();
 
     sender
         .send(P2PCommand::SendMessage {
-            target_public_key,
-            content,
+            target_public_key: target_public_key.clone(),
+            content: content.clone(),
             message_id: message_id.clone(),
+            view_once: view_once.unwrap_or(false),
+            location: None,
         })
         .await
-        .map_err(|e| format!("Failed to send message: {}", e))?;
+        .map_err(ControlError::wrap("error.send_message"))?;
+    usage::record(UsageEvent::MessageSent {
+        bytes: content.len(),
+    });
+
+    let recorded = app_data_dir().and_then(|data_dir| {
+        let mut store = ConversationStore::load(&data_dir, &identity)?;
+        let preview = if view_once.unwrap_or(false) {
+            i18n::t("conversation.view_once")
+        } else {
+            content
+        };
+        store.record_outgoing(&target_public_key, &preview);
+        Ok(store.save()?)
+    });
+    if let Err(e) = recorded {
+        warn!("Failed to record sent message: {}", e);
+    }
 
     // Return message_id so frontend can track delivery
     Ok(message_id)
 }
 
+/// Send our location, rounded and set to expire per the location settings
+#[tauri::command]
+async fn send_location(
+    target_public_key: String,
+    position: Position,
+    caption: Option<String>,
+    expires_in_secs: Option<u64>,
+    state: State<'_, AppState>,
+) -> Result<String, ControlError> {
+    state.touch();
+    let sender = state.p2p_sender()?;
+    let identity = current_identity(&state)?;
+    let share = location::share(position, expires_in_secs).map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.send_location",
+    ))?;
+    let content = caption.unwrap_or_default();
+
+    let message_id = uuid::Uuid::new_v4().to_string();
+    sender
+        .send(P2PCommand::SendMessage {
+            target_public_key: target_public_key.clone(),
+            content: content.clone(),
+            message_id: message_id.clone(),
+            view_once: false,
+            location: Some(share),
+        })
+        .await
+        .map_err(ControlError::wrap("error.send_location"))?;
+
+    let recorded = app_data_dir().and_then(|data_dir| {
+        let mut store = ConversationStore::load(&data_dir, &identity)?;
+        let preview = if content.is_empty() {
+            i18n::t("conversation.location")
+        } else {
+            content
+        };
+        store.record_outgoing(&target_public_key, &preview);
+        Ok(store.save()?)
+    });
+    if let Err(e) = recorded {
+        warn!("Failed to record sent location: {}", e);
+    }
+    Ok(message_id)
+}
+
+/// Start sharing our location live with a contact; updates follow through
+/// `update_live_location`
+#[tauri::command]
+async fn start_live_location(
+    target_public_key: String,
+    duration_secs: Option<u64>,
+    state: State<'_, AppState>,
+) -> Result<LiveSession, ControlError> {
+    state.touch();
+    state.p2p_sender()?;
+    location::start(&target_public_key, duration_secs)
+        .map_err(ControlError::wrap("error.live_location"))
+}
+
+/// Send the next position of a live session. Returns false when it came
+/// too soon after the last one and was skipped.
+#[tauri::command]
+async fn update_live_location(
+    session_id: String,
+    position: Position,
+    state: State<'_, AppState>,
+) -> Result<bool, ControlError> {
+    state.touch();
+    let sender = state.p2p_sender()?;
+    let Some((target_public_key, share)) = location::update(&session_id, position).map_err(
+        ControlError::wrap_as(ErrorCode::InvalidInput, "error.live_location"),
+    )?
+    else {
+        return Ok(false);
+    };
+    sender
+        .send(P2PCommand::SendMessage {
+            target_public_key,
+            content: String::new(),
+            message_id: uuid::Uuid::new_v4().to_string(),
+            view_once: false,
+            location: Some(share),
+        })
+        .await
+        .map_err(ControlError::wrap("error.live_location"))?;
+    Ok(true)
+}
+
+/// End a live session, ours or a contact's, and tell the other side
+#[tauri::command]
+async fn stop_live_location(
+    window: tauri::Window,
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<(), ControlError> {
+    let (session, share) = location::stop(&session_id).map_err(ControlError::wrap_as(
+        ErrorCode::NotFound,
+        "error.live_location",
+    ))?;
+    events::emit(&window.app_handle(), "live_location_stopped", &session);
+    // Without Ghost Mode the other side finds out when the session expires
+    if let Ok(sender) = state.p2p_sender() {
+        sender
+            .send(P2PCommand::SendMessage {
+                target_public_key: session.contact,
+                content: String::new(),
+                message_id: uuid::Uuid::new_v4().to_string(),
+                view_once: false,
+                location: Some(share),
+            })
+            .await
+            .map_err(ControlError::wrap("error.live_location"))?;
+    }
+    Ok(())
+}
+
+/// Running live-location sessions, ours and our contacts'
+#[tauri::command]
+async fn list_live_locations() -> Result<Vec<LiveSession>, ControlError> {
+    Ok(location::live())
+}
+
+#[tauri::command]
+async fn get_location_settings() -> Result<LocationSettings, ControlError> {
+    Ok(settings::location_settings())
+}
+
+#[tauri::command]
+async fn set_location_settings(
+    settings: LocationSettings,
+) -> Result<LocationSettings, ControlError> {
+    settings::modify(|s| s.location = settings)
+        .map(|s| s.location)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Package a message that could not be delivered as a tiny dead drop and
+/// return a link to share out of band
+#[tauri::command]
+async fn create_message_drop(
+    target_public_key: String,
+    content: String,
+    message_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<MessageDrop, ControlError> {
+    let identity = current_identity(&state)?;
+    let message = GhostMessage {
+        id: message_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+        from: identity.public_id(),
+        content,
+        timestamp: now_secs(),
+        view_once: false,
+        location: None,
+    };
+    message_drop::create_message_drop(&identity, &target_public_key, &message)
+        .await
+        .map_err(ControlError::wrap("error.create_message_drop"))
+}
+
+/// Fetch a message link shared with us and deliver it like a Ghost Mode message
+#[tauri::command]
+async fn open_message_link(
+    link: String,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<GhostMessage, ControlError> {
+    state.touch();
+    let identity = current_identity(&state)?;
+    let message = message_drop::open_message_link(&identity, &link)
+        .await
+        .map_err(ControlError::wrap("error.open_message_link"))?;
+
+    let event = MessageEvent {
+        message: &message,
+        appearance: contacts::appearance(&app_data_dir()?, &identity, &message.from),
+        trust: trust::sender_trust(
+            &app_data_dir()?,
+            &identity,
+            &settings::trust_settings(),
+            &message.from,
+        ),
+    };
+    let events: &dyn P2PEvents = &window;
+    events
+        .emit("ghost_msg", &event)
+        .map_err(ControlError::wrap("error.open_message_link"))?;
+    events.message_received(&message);
+    Ok(message)
+}
+
 /// Create a dead drop (encrypt, upload to IPFS, split key)
 #[tauri::command]
 async fn create_drop(
     file_path: String,
     threshold: u8,
     total_shards: u8,
-) -> Result<DeadDropCreated, String> {
-    create_dead_drop(&file_path, threshold, total_shards)
+    expires_in_secs: Option<u64>,
+    burn_after_read: Option<bool>,
+    versioned: Option<bool>,
+    erasure: Option<ErasureConfig>,
+    indexed: Option<bool>,
+    deduplicated: Option<bool>,
+    background: Option<bool>,
+    shard_recipients: Option<Vec<String>>,
+    recipients: Option<Vec<String>>,
+    age: Option<AgeDropConfig>,
+    pgp: Option<PgpDropConfig>,
+    password: Option<String>,
+    carrier: Option<String>,
+    padded: Option<bool>,
+    unlock_at: Option<u64>,
+    approvers: Option<Vec<String>>,
+    group: Option<String>,
+    shred_source: Option<bool>,
+    shred_confirmation: Option<String>,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<DeadDropCreated, ControlError> {
+    let data_dir = app_data_dir()?;
+    // In the background the drop returns as pending and `drop_ready` follows
+    let options = DropOptions {
+        background_upload: background.unwrap_or(false),
+        ..drop_options(&window)?
+    };
+    let burn_after_read = burn_after_read.unwrap_or(false);
+    let versioned = versioned.unwrap_or(false);
+    let indexed = indexed.unwrap_or(false);
+    let deduplicated = deduplicated.unwrap_or(false);
+    let shred_source = shred_source.unwrap_or(false);
+    let padded = padded.unwrap_or(false);
+
+    let source_path = std::path::Path::new(&file_path);
+    let file_name = source_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let source_size = std::fs::metadata(source_path).map_or(0, |m| m.len());
+
+    // Shredding is irreversible, so the caller must confirm by repeating the file name
+    if shred_source && shred_confirmation.as_deref() != Some(file_name.as_str()) {
+        return Err(ControlError::invalid_input("error.shred_unconfirmed"));
+    }
+
+    if versioned && erasure.is_some() {
+        return Err(ControlError::invalid_input("error.versioned_erasure"));
+    }
+    if indexed && (versioned || erasure.is_some()) {
+        return Err(ControlError::invalid_input("error.indexed_drop_options"));
+    }
+    if deduplicated && (versioned || indexed || erasure.is_some()) {
+        return Err(ControlError::invalid_input("error.dedup_drop_options"));
+    }
+    // Recipient drops have no shards to wrap, seal or deal
+    let shared_with_shards = versioned
+        || indexed
+        || deduplicated
+        || erasure.is_some()
+        || burn_after_read
+        || shard_recipients.is_some()
+        || group.is_some();
+    if recipients.is_some() && shared_with_shards {
+        return Err(ControlError::invalid_input("error.recipients_drop_options"));
+    }
+    if age.is_some() && (shared_with_shards || recipients.is_some()) {
+        return Err(ControlError::invalid_input("error.age_drop_options"));
+    }
+    if pgp.is_some() && (shared_with_shards || recipients.is_some() || age.is_some()) {
+        return Err(ControlError::invalid_input("error.pgp_drop_options"));
+    }
+    if password.is_some()
+        && (shared_with_shards || recipients.is_some() || age.is_some() || pgp.is_some())
+    {
+        return Err(ControlError::invalid_input("error.password_drop_options"));
+    }
+    // A hidden drop is a plain shard drop; only the upload is wrapped
+    let single_ciphertext = !(versioned || indexed || deduplicated || erasure.is_some());
+    let keyless = recipients.is_some() || age.is_some() || pgp.is_some() || password.is_some();
+    if carrier.is_some() && (!single_ciphertext || keyless) {
+        return Err(ControlError::invalid_input("error.stego_drop_options"));
+    }
+    if padded && (!single_ciphertext || keyless || carrier.is_some()) {
+        return Err(ControlError::invalid_input("error.padded_drop_options"));
+    }
+    // The timelock wraps the key of one ciphertext; shards still open it
+    if unlock_at.is_some() && (!single_ciphertext || keyless || carrier.is_some() || padded) {
+        return Err(ControlError::invalid_input("error.timelock_drop_options"));
+    }
+    // Escrowed shards go to the approvers, sealed inside the manifest
+    if approvers.is_some()
+        && (shared_with_shards
+            || keyless
+            || carrier.is_some()
+            || padded
+            || unlock_at.is_some())
+    {
+        return Err(ControlError::invalid_input("error.escrow_drop_options"));
+    }
+
+    // Group drops deal exactly one shard to each member
+    let group = match group {
+        Some(group_id) => {
+            if shard_recipients.is_some() {
+                return Err(ControlError::invalid_input("error.group_recipients"));
+            }
+            let store = GroupStore::load(&data_dir, &current_identity(&state)?)
+                .map_err(ControlError::wrap("error.load_groups"))?;
+            let group = store
+                .get(&group_id)
+                .cloned()
+                .ok_or_else(|| ControlError::invalid_input("error.unknown_group"))?;
+            if group.members.len() != total_shards as usize {
+                return Err(ControlError::invalid_input("error.group_shard_count"));
+            }
+            Some(group)
+        }
+        None => None,
+    };
+
+    // Burn-after-read, versioned and group drops keep key material under our
+    // identity; deduplicated drops derive their chunk keys from it, and
+    // escrow manifests are signed with it
+    let identity = if burn_after_read
+        || versioned
+        || deduplicated
+        || group.is_some()
+        || approvers.is_some()
+    {
+        Some(current_identity(&state)?)
+    } else {
+        None
+    };
+
+    let mut created = match (&identity, versioned, erasure) {
+        _ if recipients.is_some() => {
+            let recipients = recipients.as_deref().unwrap_or_default();
+            create_recipients_drop(&file_path, recipients, &options).await
+        }
+        _ if age.is_some() => {
+            let config = age.clone().unwrap_or_default();
+            create_age_drop(&file_path, &config, &options).await
+        }
+        _ if pgp.is_some() => {
+            let config = pgp.clone().unwrap_or_default();
+            // Contacts are only needed to look up stored keys
+            let contacts = match current_identity(&state) {
+                Ok(identity) => ContactBook::load(&data_dir, &identity)
+                    .map_err(ControlError::wrap("error.load_contacts"))?
+                    .contacts()
+                    .to_vec(),
+                Err(_) => Vec::new(),
+            };
+            create_pgp_drop(&file_path, &config, &contacts, &options).await
+        }
+        _ if password.is_some() => {
+            let password = password.as_deref().unwrap_or_default();
+            create_password_drop(&file_path, password, &options).await
+        }
+        (Some(identity), _, _) if approvers.is_some() => {
+            let approvers = approvers.as_deref().unwrap_or_default();
+            create_escrow_drop(&file_path, threshold, approvers, identity, &options).await
+        }
+        _ if carrier.is_some() => {
+            let carrier = carrier.as_deref().unwrap_or_default();
+            create_stego_drop(&file_path, carrier, threshold, total_shards, &options).await
+        }
+        _ if padded => create_padded_drop(&file_path, threshold, total_shards, &options).await,
+        _ if unlock_at.is_some() => {
+            let unlock_at = unlock_at.unwrap_or_default();
+            create_timelock_drop(&file_path, threshold, total_shards, unlock_at, &options).await
+        }
+        (_, _, Some(config)) => {
+            create_erasure_drop(&file_path, threshold, total_shards, config, &options).await
+        }
+        (Some(identity), _, None) if deduplicated => {
+            create_dedup_drop(&file_path, threshold, total_shards, identity, &options).await
+        }
+        (Some(identity), true, None) => {
+            create_versioned_drop(
+                &file_path,
+                threshold,
+                total_shards,
+                &options,
+                &data_dir,
+                identity,
+            )
+            .await
+        }
+        _ if indexed => create_indexed_drop(&file_path, threshold, total_shards, &options).await,
+        _ => create_dead_drop(&file_path, threshold, total_shards, &options).await,
+    }
+    .map_err(ControlError::wrap("error.create_dead_drop"))?;
+    // Any one recipient can open a recipient, age or OpenPGP drop, the
+    // passphrase alone a password drop
+    let (threshold, total_shards) = match (&recipients, &age) {
+        (Some(recipients), _) => (1, recipients.len().min(u8::MAX as usize) as u8),
+        (_, Some(age)) => (1, age.recipients.len().clamp(1, u8::MAX as usize) as u8),
+        _ if pgp.is_some() => {
+            let count = pgp.as_ref().map_or(1, |p| p.recipients.len());
+            (1, count.clamp(1, u8::MAX as usize) as u8)
+        }
+        _ if password.is_some() => (1, 1),
+        _ if approvers.is_some() => {
+            let count = approvers.as_ref().map_or(0, Vec::len);
+            (threshold, count.min(u8::MAX as usize) as u8)
+        }
+        _ => (threshold, total_shards),
+    };
+
+    if let Some(identity) = identity.as_ref().filter(|_| burn_after_read) {
+        let drop_id = uuid::Uuid::new_v4().to_string();
+        let (wrapped, wrap_key) = wrap_shards(&created.shards, &drop_id, &identity.public_id())
+            .map_err(ControlError::wrap("error.wrap_shards"))?;
+
+        let mut registry = BurnRegistry::load(&data_dir, identity)?;
+        registry.register(&drop_id, &created.cid, &wrap_key);
+        registry
+            .save()
+            .map_err(ControlError::wrap("error.save_burn_key"))?;
+
+        created.shards = wrapped;
+    }
+
+    // Seal each shard to its holder so a leaked shard is useless to anyone else
+    let shard_holders = match &group {
+        Some(group) => Some(group.members.clone()),
+        None => shard_recipients,
+    };
+    if let Some(holders) = shard_holders {
+        created.shards = seal_shards(&created.shards, &holders)
+            .map_err(ControlError::wrap("error.seal_shards"))?;
+    }
+
+    // The signed manifest records who may open the drop
+    let group_drop = match (&group, &identity) {
+        (Some(group), Some(identity)) => {
+            let (manifest_cid, manifest) =
+                publish_manifest(identity, &created.cid, group, threshold)
+                    .await
+                    .map_err(ControlError::wrap("error.publish_group_manifest"))?;
+            Some(GroupDrop {
+                group_id: group.id.clone(),
+                group_name: group.name.clone(),
+                manifest_cid,
+                manifest,
+                members: group
+                    .members
+                    .iter()
+                    .zip(&created.shards)
+                    .map(|(member, shard)| MemberShard {
+                        public_id: member.clone(),
+                        shard: shard.clone(),
+                        sent_at: None,
+                        confirmed_at: None,
+                    })
+                    .collect(),
+            })
+        }
+        _ => None,
+    };
+    let is_group_drop = group_drop.is_some();
+
+    // Record the drop so it can be cleaned up later
+    let created_at = now_secs();
+    let mut catalog = DropCatalog::load(&data_dir)?;
+    let record = DropRecord {
+        cid: created.cid.clone(),
+        file_name,
+        threshold,
+        total_shards,
+        created_at,
+        expires_at: expires_in_secs.map(|secs| created_at.saturating_add(secs)),
+        pinned: true,
+        drop_id: created.drop_id.clone(),
+        piece_cids: created.piece_cids.clone(),
+        reshared_at: None,
+        group: group_drop,
+        imported: false,
+        cipher: created.cipher,
+    };
+    catalog.insert(record.clone());
+    catalog
+        .save()
+        .map_err(ControlError::wrap("error.update_drop_catalog"))?;
+    audit::record(
+        &data_dir,
+        AuditAction::Create,
+        created.drop_id.as_deref().unwrap_or(&created.cid),
+        serde_json::json!({
+            "file_name": record.file_name,
+            "threshold": threshold,
+            "total_shards": total_shards,
+            "expires_at": record.expires_at,
+            "versioned": versioned,
+            "indexed": indexed,
+            "deduplicated": deduplicated,
+            "erasure": erasure.is_some(),
+            "burn_after_read": burn_after_read,
+            "group": record.group.as_ref().map(|g| &g.group_id),
+            "recipients": recipients.as_ref().map(Vec::len),
+            "age": age.is_some(),
+            "pgp": pgp.is_some(),
+            "password": password.is_some(),
+            "hidden": carrier.is_some(),
+            "padded": padded,
+            "unlock_at": unlock_at,
+            "approvers": approvers.as_ref().map(Vec::len),
+            "sealed": created.shards.iter().any(|s| SealedShard::parse(s).is_some()),
+            "pending": created.pending,
+        }),
+        None,
+    );
+    // Queued uploads are linked by the upload worker once they land
+    if !created.pending {
+        if let Err(e) = mfs::link_drop(&record).await {
+            warn!("Drop {} not linked into MFS: {}", created.cid, e);
+        }
+    }
+    usage::record(UsageEvent::DropCreated { bytes: source_size });
+
+    // Members not reached now can be sent their shard again later
+    if is_group_drop {
+        if let Err(e) = deliver_group_shards(&state, &created.cid).await {
+            warn!("Group shards of {} not sent: {}", created.cid, e);
+        }
+    }
+
+    // Only shred once the ciphertext is safe and the shards exist
+    if shred_source {
+        created.shred =
+            Some(shred_file(source_path).unwrap_or_else(|e| ShredReport::failed(source_path, e)));
+    }
+
+    Ok(created)
+}
+
+/// Largest buffer accepted from the frontend; it crosses the IPC bridge as JSON
+const MAX_MEMORY_DROP_LEN: usize = 64 * 1024 * 1024;
+
+/// Create a dead drop from bytes held by the frontend, e.g. pasted text or a
+/// screenshot. The plaintext is never written to disk.
+#[tauri::command]
+async fn create_drop_from_bytes(
+    mut data: Vec<u8>,
+    name: String,
+    threshold: u8,
+    total_shards: u8,
+    expires_in_secs: Option<u64>,
+    shard_recipients: Option<Vec<String>>,
+    window: tauri::Window,
+) -> Result<DeadDropCreated, ControlError> {
+    if data.len() > MAX_MEMORY_DROP_LEN {
+        data.zeroize();
+        return Err(ControlError::invalid_input("error.memory_drop_too_large"));
+    }
+    let options = drop_options(&window)?;
+    let size = data.len() as u64;
+
+    let created = create_dead_drop_from_reader(
+        std::io::Cursor::new(data.as_slice()),
+        size,
+        threshold,
+        total_shards,
+        &options,
+    )
+    .await;
+    data.zeroize();
+    let mut created = created.map_err(ControlError::wrap("error.create_dead_drop"))?;
+
+    if let Some(holders) = shard_recipients {
+        created.shards = seal_shards(&created.shards, &holders)
+            .map_err(ControlError::wrap("error.seal_shards"))?;
+    }
+
+    let drop = PlainDrop {
+        file_name: name,
+        threshold,
+        total_shards,
+        expires_in_secs,
+        size,
+    };
+    record_plain_drop(&created, drop, "from_memory").await?;
+    Ok(created)
+}
+
+/// What the catalog and audit log need about a plain shard drop
+struct PlainDrop {
+    file_name: String,
+    threshold: u8,
+    total_shards: u8,
+    expires_in_secs: Option<u64>,
+    size: u64,
+}
+
+/// Catalog, audit, link and count a plain shard drop created outside
+/// `create_drop`. `origin` names the audit flag saying where it came from.
+async fn record_plain_drop(
+    created: &DeadDropCreated,
+    drop: PlainDrop,
+    origin: &str,
+) -> Result<(), ControlError> {
+    let data_dir = app_data_dir()?;
+    let created_at = now_secs();
+    let mut catalog = DropCatalog::load(&data_dir)?;
+    let record = DropRecord {
+        cid: created.cid.clone(),
+        file_name: drop.file_name,
+        threshold: drop.threshold,
+        total_shards: drop.total_shards,
+        created_at,
+        expires_at: drop.expires_in_secs.map(|secs| created_at.saturating_add(secs)),
+        pinned: true,
+        drop_id: None,
+        piece_cids: Vec::new(),
+        reshared_at: None,
+        group: None,
+        imported: false,
+        cipher: created.cipher,
+    };
+    catalog.insert(record.clone());
+    catalog
+        .save()
+        .map_err(ControlError::wrap("error.update_drop_catalog"))?;
+    let mut params = serde_json::json!({
+        "file_name": record.file_name,
+        "threshold": drop.threshold,
+        "total_shards": drop.total_shards,
+        "expires_at": record.expires_at,
+        "sealed": created.shards.iter().any(|s| SealedShard::parse(s).is_some()),
+        "pending": created.pending,
+    });
+    params[origin] = true.into();
+    audit::record(&data_dir, AuditAction::Create, &created.cid, params, None);
+    if !created.pending {
+        if let Err(e) = mfs::link_drop(&record).await {
+            warn!("Drop {} not linked into MFS: {}", created.cid, e);
+        }
+    }
+    usage::record(UsageEvent::DropCreated { bytes: drop.size });
+    Ok(())
+}
+
+/// Retrieve a small plain drop straight into memory, so a note or image can
+/// be shown without writing it to disk. `max_size` is capped at the IPC limit.
+#[tauri::command]
+async fn retrieve_drop_to_memory(
+    cid: String,
+    shards: Vec<String>,
+    max_size: Option<u64>,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<Vec<u8>, ControlError> {
+    let shard_count = shards.len();
+    let shards = resolve_shards(shards, &window, &state).await?;
+    let options = drop_options(&window)?;
+    let max_size = max_size
+        .unwrap_or(MAX_MEMORY_DROP_LEN as u64)
+        .min(MAX_MEMORY_DROP_LEN as u64);
+
+    let result = retrieve_dead_drop_to_memory(&cid, shards, max_size, &options)
         .await
-        .map_err(|e| format!("Failed to create dead drop: {}", e))
+        .map_err(ControlError::wrap("error.retrieve_dead_drop"));
+    audit_drop(
+        AuditAction::Retrieve,
+        &cid,
+        serde_json::json!({ "shards": shard_count, "to_memory": true }),
+        &result,
+    );
+    let data = result?;
+    usage::record(UsageEvent::DropRetrieved {
+        bytes: data.len() as u64,
+    });
+    Ok(data)
 }
 
 /// Retrieve a dead drop (download from IPFS, combine shards, decrypt)
@@ -140,65 +1019,3141 @@ async fn retrieve_drop(
     cid: String,
     shards: Vec<String>,
     output_path: String,
-) -> Result<(), String> {
-    retrieve_dead_drop(&cid, shards, &output_path)
-        .await
-        .map_err(|e| format!("Failed to retrieve dead drop: {}", e))
-}
+    car_path: Option<String>,
+    passphrase: Option<String>,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<(), ControlError> {
+    // Escrowed drops open only with approved shards, see `retrieve_escrow_drop`
+    if escrow_manifest_cid(&cid).is_some() {
+        return Err(ControlError::invalid_input("error.escrow_needs_approval"));
+    }
+    let shard_count = shards.len();
+    let shards = resolve_shards(shards, &window, &state).await?;
+    let mut options = drop_options(&window)?;
 
-/// Shutdown P2P actor
-#[tauri::command]
-async fn stop_ghost_mode(state: State<'_, AppState>) -> Result<(), String> {
-    let sender = {
-        let sender_guard = state.p2p_sender.lock().unwrap();
-        sender_guard.as_ref().cloned()
-    };
-    
-    if let Some(sender) = sender {
-        sender
-            .send(P2PCommand::Shutdown)
+    // Source blocks from a CAR archive instead of the network
+    if let Some(car_path) = car_path {
+        car::import_drop_car(Path::new(&car_path), &options)
             .await
-            .map_err(|e| format!("Failed to stop P2P: {}", e))?;
+            .map_err(ControlError::wrap("error.import_car"))?;
+        options.offline = true;
     }
 
+    // A plain CID without shards may be an age file, ours or from `age`/`rage`
+    let age_encrypted = shards.is_empty()
+        && drop_manifest_cid(&cid).is_none()
+        && is_age_encrypted(&cid, &options).await.unwrap_or(false);
+    let result = if let Some(manifest_cid) = erasure_manifest_cid(&cid) {
+        retrieve_erasure_drop(manifest_cid, shards, &output_path, &options).await
+    } else if let Some(manifest_cid) = recipients_manifest_cid(&cid) {
+        // Recipient drops open with our identity instead of shards
+        let identity = current_identity(&state)?;
+        retrieve_recipients_drop(manifest_cid, &identity, &output_path, &options).await
+    } else if let Some(manifest_cid) = password_manifest_cid(&cid) {
+        let passphrase = passphrase.as_deref();
+        retrieve_password_drop(manifest_cid, passphrase, &output_path, &options).await
+    } else if let Some(manifest_cid) = padded_manifest_cid(&cid) {
+        retrieve_padded_drop(manifest_cid, shards, &output_path, &options).await
+    } else if let Some(image_cid) = stego_cid(&cid) {
+        retrieve_stego_drop(image_cid, shards, &output_path, &options).await
+    } else if age_encrypted {
+        let identity = state.peek_identity();
+        let passphrase = passphrase.as_deref();
+        retrieve_age_drop(&cid, identity.as_ref(), passphrase, &output_path, &options)
+            .await
+            .map(|_| ())
+    } else {
+        retrieve_dead_drop(&cid, shards, &output_path, &options).await
+    }
+    .map_err(ControlError::wrap("error.retrieve_dead_drop"));
+    audit_drop(
+        AuditAction::Retrieve,
+        &cid,
+        serde_json::json!({
+            "shards": shard_count,
+            "offline": options.offline,
+            "age": age_encrypted,
+        }),
+        &result,
+    );
+    result?;
+
+    record_retrieval(&output_path);
     Ok(())
 }
 
-/// Test IPFS connection
+fn record_retrieval(output_path: &str) {
+    let bytes = std::fs::metadata(output_path).map_or(0, |m| m.len());
+    usage::record(UsageEvent::DropRetrieved { bytes });
+}
+
+/// Queue a plain shard drop with the operation manager. Returns the operation
+/// ID; the created drop arrives as its result in `operation_updated`.
 #[tauri::command]
-async fn test_ipfs() -> Result<String, String> {
-    let client = reqwest::Client::new();
-    
-    match client
-        .post("http://127.0.0.1:5001/api/v0/version")
-        .send()
-        .await
+async fn queue_create_drop(
+    file_path: String,
+    threshold: u8,
+    total_shards: u8,
+    expires_in_secs: Option<u64>,
+    priority: Option<Priority>,
+    window: tauri::Window,
+    operations: State<'_, OperationManager>,
+) -> Result<String, ControlError> {
+    let options = drop_options(&window)?;
+    let source_path = Path::new(&file_path);
+    let file_name = source_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let size = std::fs::metadata(source_path).map_or(0, |m| m.len());
+    let label = file_name.clone();
+
+    let job: OperationJob = Box::new(move |options| {
+        Box::pin(async move {
+            let created = create_dead_drop(&file_path, threshold, total_shards, &options)
+                .await
+                .map_err(ControlError::wrap("error.create_dead_drop"))?;
+            let drop = PlainDrop {
+                file_name,
+                threshold,
+                total_shards,
+                expires_in_secs,
+                size,
+            };
+            record_plain_drop(&created, drop, "queued").await?;
+            Ok::<_, anyhow::Error>(serde_json::to_value(created)?)
+        })
+    });
+    Ok(operations.submit(
+        OperationKind::Create,
+        label,
+        priority.unwrap_or_default(),
+        options,
+        job,
+    ))
+}
+
+/// Queue the retrieval of a shard drop with the operation manager. Returns
+/// the operation ID.
+#[tauri::command]
+async fn queue_retrieve_drop(
+    cid: String,
+    shards: Vec<String>,
+    output_path: String,
+    priority: Option<Priority>,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+    operations: State<'_, OperationManager>,
+) -> Result<String, ControlError> {
+    // Identity-, passphrase- and approval-keyed drops prompt, so they aren't queued
+    if recipients_manifest_cid(&cid).is_some()
+        || password_manifest_cid(&cid).is_some()
+        || escrow_manifest_cid(&cid).is_some()
     {
-        Ok(response) => {
-            if response.status().is_success() {
-                let text = response.text().await.unwrap_or_default();
-                Ok(format!("IPFS Connected: {}", text))
+        return Err(ControlError::invalid_input("error.queue_needs_shards"));
+    }
+    let shard_count = shards.len();
+    let shards = resolve_shards(shards, &window, &state).await?;
+    let options = drop_options(&window)?;
+    let label = cid.clone();
+
+    let job: OperationJob = Box::new(move |options| {
+        Box::pin(async move {
+            let result = if let Some(manifest_cid) = erasure_manifest_cid(&cid) {
+                retrieve_erasure_drop(manifest_cid, shards, &output_path, &options).await
+            } else if let Some(manifest_cid) = padded_manifest_cid(&cid) {
+                retrieve_padded_drop(manifest_cid, shards, &output_path, &options).await
+            } else if let Some(image_cid) = stego_cid(&cid) {
+                retrieve_stego_drop(image_cid, shards, &output_path, &options).await
             } else {
-                Err(format!("IPFS returned error: {}", response.status()))
+                retrieve_dead_drop(&cid, shards, &output_path, &options).await
             }
-        }
-        Err(e) => Err(format!("IPFS not running: {}", e)),
-    }
+            .map_err(ControlError::wrap("error.retrieve_dead_drop"));
+            audit_drop(
+                AuditAction::Retrieve,
+                &cid,
+                serde_json::json!({ "shards": shard_count, "queued": true }),
+                &result,
+            );
+            result?;
+            record_retrieval(&output_path);
+            Ok::<_, anyhow::Error>(serde_json::json!({ "output_path": output_path }))
+        })
+    });
+    Ok(operations.submit(
+        OperationKind::Retrieve,
+        label,
+        priority.unwrap_or_default(),
+        options,
+        job,
+    ))
 }
 
-fn main() {
-    tauri::Builder::default()
-        .manage(AppState::new())
-        .invoke_handler(tauri::generate_handler![
-            init_identity,
-            get_public_id,
-            start_ghost_mode,
-            send_ghost_message,
-            create_drop,
-            retrieve_drop,
-            stop_ghost_mode,
-            test_ipfs,
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+/// Queued, running and recently finished drop operations
+#[tauri::command]
+fn list_operations(operations: State<'_, OperationManager>) -> Vec<OperationStatus> {
+    operations.list()
+}
+
+#[tauri::command]
+fn get_operation(
+    id: String,
+    operations: State<'_, OperationManager>,
+) -> Result<OperationStatus, ControlError> {
+    operations
+        .get(&id)
+        .ok_or_else(|| ControlError::invalid_input("error.unknown_operation"))
+}
+
+/// Cancel a queued or running drop operation
+#[tauri::command]
+fn cancel_operation(
+    id: String,
+    operations: State<'_, OperationManager>,
+) -> Result<OperationStatus, ControlError> {
+    operations
+        .cancel(&id)
+        .ok_or_else(|| ControlError::invalid_input("error.unknown_operation"))
+}
+
+/// Reorder the queue; a higher priority operation starts first
+#[tauri::command]
+fn set_operation_priority(
+    id: String,
+    priority: Priority,
+    operations: State<'_, OperationManager>,
+) -> Result<OperationStatus, ControlError> {
+    operations
+        .set_priority(&id, priority)
+        .ok_or_else(|| ControlError::invalid_input("error.unknown_operation"))
+}
+
+/// Estimate download size, disk space and time before retrieving a drop.
+/// Erasure-coded and indexed drops need shards to read their manifest;
+/// burn-after-read shards are never unwrapped for a preflight.
+#[tauri::command]
+async fn stat_drop(
+    cid: String,
+    output_path: String,
+    shards: Option<Vec<String>>,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<DropStat, ControlError> {
+    let options = drop_options(&window)?;
+    let output_path = Path::new(&output_path);
+
+    let stat = if let Some(manifest_cid) = erasure_manifest_cid(&cid) {
+        let shards = inspection_shards(shards, &state, "error.erasure_estimate_needs_shards")?;
+        stat_erasure_drop(manifest_cid, shards, output_path, &options).await
+    } else if let Some(index_cid) = index_manifest_cid(&cid) {
+        let shards = inspection_shards(shards, &state, "error.indexed_estimate_needs_shards")?;
+        stat_indexed_drop(index_cid, shards, output_path, &options).await
+    } else if let Some(manifest_cid) = dedup_manifest_cid(&cid) {
+        let shards = inspection_shards(shards, &state, "error.dedup_estimate_needs_shards")?;
+        stat_dedup_drop(manifest_cid, shards, output_path, &options).await
+    } else if let Some(manifest_cid) = padded_manifest_cid(&cid) {
+        let shards = inspection_shards(shards, &state, "error.padded_estimate_needs_shards")?;
+        stat_padded_drop(manifest_cid, shards, output_path, &options).await
+    } else if let Some(manifest_cid) = recipients_manifest_cid(&cid) {
+        stat_recipients_drop(manifest_cid, output_path, &options).await
+    } else if let Some(manifest_cid) = password_manifest_cid(&cid) {
+        stat_password_drop(manifest_cid, output_path, &options).await
+    } else if let Some(manifest_cid) = timelock_manifest_cid(&cid) {
+        stat_timelock_drop(manifest_cid, output_path, &options).await
+    } else if let Some(manifest_cid) = escrow_manifest_cid(&cid) {
+        stat_escrow_drop(manifest_cid, &app_data_dir()?, output_path, &options).await
+    } else if let Some(image_cid) = stego_cid(&cid) {
+        stat_dead_drop(image_cid, None, output_path, &options).await
+    } else {
+        stat_dead_drop(&cid, None, output_path, &options).await
+    };
+    stat.map_err(ControlError::wrap("error.stat_dead_drop"))
+}
+
+/// When a timelocked drop can be opened; no shards are needed to ask
+#[tauri::command]
+async fn get_timelock_status(cid: String) -> Result<TimelockStatus, ControlError> {
+    let manifest_cid = timelock_manifest_cid(&cid)
+        .ok_or_else(|| ControlError::invalid_input("error.not_timelocked"))?;
+    timelock_status(manifest_cid)
+        .await
+        .map_err(ControlError::wrap("error.timelock_status"))
+}
+
+/// Ask the approvers of an escrowed drop to release its key. Their answers
+/// arrive as `escrow_decision`, and `escrow_approved` once enough approved.
+#[tauri::command]
+async fn request_escrow_release(
+    cid: String,
+    reason: String,
+    state: State<'_, AppState>,
+) -> Result<EscrowRequestStatus, ControlError> {
+    let identity = current_identity(&state)?;
+    let manifest_cid = escrow_manifest_cid(&cid)
+        .ok_or_else(|| ControlError::invalid_input("error.not_escrowed"))?;
+    let (status, request) =
+        escrow::request_release(&app_data_dir()?, &identity, manifest_cid, &reason)
+            .await
+            .map_err(ControlError::wrap("error.request_escrow_release"))?;
+
+    let sender = state.p2p_sender()?;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    sender
+        .send(P2PCommand::SendEscrowRequest {
+            request,
+            approvers: status.approvers.clone(),
+            reply: reply_tx,
+        })
+        .await
+        .map_err(ControlError::wrap("error.request_escrow_release"))?;
+    let sent = reply_rx
+        .await
+        .map_err(|_| {
+            ControlError::localized(ErrorCode::GhostModeNotRunning, "error.ghost_mode_stopped")
+        })?
+        .map_err(|e| {
+            ControlError::localized(ErrorCode::NetworkUnavailable, "error.request_escrow_release")
+                .with_cause(e)
+        })?;
+    if sent.is_empty() {
+        return Err(ControlError::localized(
+            ErrorCode::NetworkUnavailable,
+            "error.request_escrow_release",
+        ));
+    }
+    Ok(status)
+}
+
+#[tauri::command]
+async fn list_escrow_requests(
+    state: State<'_, AppState>,
+) -> Result<Vec<EscrowRequestStatus>, ControlError> {
+    let identity = current_identity(&state)?;
+    escrow::requests(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_escrow_requests"))
+}
+
+/// Release requests sent to us as an approver; new ones arrive as `escrow_request`
+#[tauri::command]
+async fn list_escrow_approvals(
+    state: State<'_, AppState>,
+) -> Result<Vec<PendingApproval>, ControlError> {
+    let identity = current_identity(&state)?;
+    escrow::pending_approvals(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_escrow_requests"))
+}
+
+/// Approve or deny a release request; approving sends our shard, sealed to
+/// the requester
+#[tauri::command]
+async fn answer_escrow_request(
+    request_id: String,
+    approve: bool,
+    reason: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), ControlError> {
+    let identity = current_identity(&state)?;
+    let reason = reason.unwrap_or_default();
+    let (recipient, decision) =
+        escrow::decide(&app_data_dir()?, &identity, &request_id, approve, &reason)
+            .await
+            .map_err(ControlError::wrap("error.answer_escrow_request"))?;
+
+    let sender = state.p2p_sender()?;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    sender
+        .send(P2PCommand::SendEscrowDecision {
+            recipient,
+            decision,
+            reply: reply_tx,
+        })
+        .await
+        .map_err(ControlError::wrap("error.answer_escrow_request"))?;
+    reply_rx
+        .await
+        .map_err(|_| {
+            ControlError::localized(ErrorCode::GhostModeNotRunning, "error.ghost_mode_stopped")
+        })?
+        .map_err(|e| {
+            ControlError::localized(ErrorCode::NetworkUnavailable, "error.answer_escrow_request")
+                .with_cause(e)
+        })
+}
+
+/// Retrieve an escrowed drop with the shards its approvers released
+#[tauri::command]
+async fn retrieve_escrow_drop(
+    request_id: String,
+    output_path: String,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<(), ControlError> {
+    let identity = current_identity(&state)?;
+    let data_dir = app_data_dir()?;
+    let (cid, shards) = escrow::assemble(&data_dir, &identity, &request_id).map_err(
+        ControlError::wrap_as(ErrorCode::InvalidInput, "error.escrow_not_approved"),
+    )?;
+    let options = drop_options(&window)?;
+
+    let result = retrieve_dead_drop(&cid, shards, &output_path, &options)
+        .await
+        .map_err(ControlError::wrap("error.retrieve_dead_drop"));
+    audit_drop(
+        AuditAction::Retrieve,
+        &cid,
+        serde_json::json!({ "escrow_request": request_id }),
+        &result,
+    );
+    result?;
+    if let Err(e) = escrow::forget_request(&data_dir, &identity, &request_id) {
+        warn!("Escrow request {} not forgotten: {}", request_id, e);
+    }
+
+    record_retrieval(&output_path);
+    Ok(())
+}
+
+/// Largest range returned in one call; it crosses the IPC bridge as JSON
+const MAX_RANGE_LEN: u64 = 16 * 1024 * 1024;
+
+/// Fetch and decrypt `len` bytes at `offset` of an indexed drop, without
+/// downloading the rest. Burn-after-read shards are never unwrapped for this.
+#[tauri::command]
+async fn retrieve_drop_range(
+    cid: String,
+    shards: Vec<String>,
+    offset: u64,
+    len: u64,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<Vec<u8>, ControlError> {
+    let index_cid = index_manifest_cid(&cid)
+        .ok_or_else(|| ControlError::invalid_input("error.range_needs_index"))?;
+    if len > MAX_RANGE_LEN {
+        return Err(ControlError::invalid_input("error.range_too_large"));
+    }
+    let shards = inspection_shards(Some(shards), &state, "error.range_needs_index")?;
+    let options = drop_options(&window)?;
+
+    let session_key = recover_session_key(&shards).map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.retrieve_drop_range",
+    ))?;
+    let index = fetch_chunk_index(index_cid, &session_key)
+        .await
+        .map_err(ControlError::wrap("error.retrieve_drop_range"))?;
+    read_range(&index, &session_key, offset, len, &options)
+        .await
+        .map_err(ControlError::wrap("error.retrieve_drop_range"))
+}
+
+/// Serve an indexed drop on a loopback URL the player can stream and seek in,
+/// decrypting on demand instead of writing plaintext to disk
+#[tauri::command]
+async fn open_drop_stream(
+    cid: String,
+    shards: Vec<String>,
+    content_type: Option<String>,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<DropStream, ControlError> {
+    let index_cid = index_manifest_cid(&cid)
+        .ok_or_else(|| ControlError::invalid_input("error.stream_needs_index"))?;
+    let shards = inspection_shards(Some(shards), &state, "error.stream_needs_index")?;
+    let options = drop_options(&window)?;
+
+    let session_key = recover_session_key(&shards).map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.open_drop_stream",
+    ))?;
+    let index = fetch_chunk_index(index_cid, &session_key)
+        .await
+        .map_err(ControlError::wrap("error.open_drop_stream"))?;
+    media_stream::open_stream(index, session_key, content_type.as_deref(), options)
+        .await
+        .map_err(ControlError::wrap("error.open_drop_stream"))
+}
+
+/// Stop serving a stream and forget its key
+#[tauri::command]
+fn close_drop_stream(token: String) -> bool {
+    media_stream::close_stream(&token)
+}
+
+/// Shards for reading a manifest without retrieving the drop: sealed shards
+/// are opened, burn-after-read shards refused
+fn inspection_shards(
+    shards: Option<Vec<String>>,
+    state: &State<'_, AppState>,
+    missing_id: &'static str,
+) -> Result<Vec<String>, ControlError> {
+    let shards = shards.ok_or_else(|| ControlError::invalid_input(missing_id))?;
+    let identity = state.identity().ok();
+    let shards = open_sealed_shards(shards, identity.as_ref())?;
+    if shards.iter().any(|s| WrappedShard::parse(s).is_some()) {
+        return Err(ControlError::invalid_input("error.burn_inspect"));
+    }
+    Ok(shards)
+}
+
+/// Turn user-supplied shards into plain Shamir shards: open shards sealed to us,
+/// then unwrap burn-after-read shards
+async fn resolve_shards(
+    shards: Vec<String>,
+    window: &tauri::Window,
+    state: &State<'_, AppState>,
+) -> Result<Vec<String>, ControlError> {
+    let identity = state.identity().ok();
+    let shards = open_sealed_shards(shards, identity.as_ref())?;
+
+    unwrap_burn_shards(shards, window, state).await
+}
+
+/// Open a shard sealed to our identity so it can be contributed to a retrieval
+#[tauri::command]
+async fn open_sealed_shard(
+    shard: String,
+    state: State<'_, AppState>,
+) -> Result<String, ControlError> {
+    let identity = current_identity(&state)?;
+    let sealed = SealedShard::parse(&shard)
+        .ok_or_else(|| ControlError::invalid_input("error.shard_not_sealed"))?
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.invalid_sealed_shard",
+        ))?;
+
+    sealed.open(&identity).map_err(ControlError::wrap_as(
+        ErrorCode::CryptoFailed,
+        "error.open_shard",
+    ))
+}
+
+/// Resolve burn-after-read shards by consuming the creator's one-time unwrap key.
+/// Plain shards are returned unchanged.
+async fn unwrap_burn_shards(
+    shards: Vec<String>,
+    window: &tauri::Window,
+    state: &State<'_, AppState>,
+) -> Result<Vec<String>, ControlError> {
+    let parsed: Vec<WrappedShard> = match shards
+        .iter()
+        .map(|s| WrappedShard::parse(s))
+        .collect::<Option<anyhow::Result<Vec<_>>>>()
+    {
+        None => return Ok(shards),
+        Some(parsed) => parsed.map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.invalid_burn_shard",
+        ))?,
+    };
+
+    let first = parsed
+        .first()
+        .ok_or_else(|| ControlError::invalid_input("error.no_shards"))?;
+    let (drop_id, creator_id) = (first.drop_id.clone(), first.creator_id.clone());
+    if parsed.iter().any(|s| s.drop_id != drop_id) {
+        return Err(ControlError::invalid_input("error.mixed_burn_shards"));
+    }
+
+    let identity = state.identity().ok();
+
+    let unwrap_key = match identity {
+        // We created this drop - consume the key from our own registry
+        Some(identity) if identity.public_id() == creator_id => {
+            if let Err(e) = canary::check_retrieval(&app_data_dir()?, &identity, window, &drop_id) {
+                warn!("Failed to check canaries: {}", e);
+            }
+            let mut registry = BurnRegistry::load(&app_data_dir()?, &identity)?;
+            let (key, info) = registry.consume(&drop_id)?;
+            registry.save()?;
+            events::emit(
+                &window.app_handle(),
+                "drop_burned",
+                DropBurned {
+                    drop_id: drop_id.clone(),
+                    cid: info.cid,
+                    burned_at: info.burned_at,
+                    consumed_by: identity.public_id(),
+                },
+            );
+            key
+        }
+        // Ask the creator's node over Ghost Mode
+        _ => {
+            let sender = state.p2p_sender().map_err(|e| {
+                ControlError::localized(e.into(), "error.burn_needs_ghost_mode").with_cause(e)
+            })?;
+
+            let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+            sender
+                .send(P2PCommand::RequestBurnKey {
+                    creator_public_key: creator_id,
+                    drop_id,
+                    reply: reply_tx,
+                })
+                .await
+                .map_err(ControlError::wrap("error.request_burn_key"))?;
+
+            tokio::time::timeout(std::time::Duration::from_secs(60), reply_rx)
+                .await
+                .map_err(|_| ControlError::localized(ErrorCode::Timeout, "error.burn_key_timeout"))?
+                .map_err(|_| {
+                    ControlError::localized(
+                        ErrorCode::GhostModeNotRunning,
+                        "error.ghost_mode_stopped",
+                    )
+                })??
+        }
+    };
+
+    unwrap_shards(&parsed, &unwrap_key).map_err(ControlError::wrap_as(
+        ErrorCode::CryptoFailed,
+        "error.unwrap_shards",
+    ))
+}
+
+/// Deal a fresh shard set for an existing drop from a quorum of its shards.
+/// The drop key does not change: old shards remain usable among themselves
+/// but cannot be combined with new ones.
+#[tauri::command]
+async fn reshare_drop(
+    drop_id: String,
+    shards: Vec<String>,
+    new_threshold: u8,
+    new_total: u8,
+    shard_recipients: Option<Vec<String>>,
+    state: State<'_, AppState>,
+) -> Result<Vec<String>, ControlError> {
+    let identity = state.identity().ok();
+    let shards = open_sealed_shards(shards, identity.as_ref())?;
+
+    // Unwrapping would consume the one-time burn key
+    if shards.iter().any(|s| WrappedShard::parse(s).is_some()) {
+        return Err(ControlError::invalid_input("error.burn_reshare"));
+    }
+
+    let mut new_shards = reshare_session_key(&shards, new_threshold, new_total)
+        .map_err(ControlError::wrap("error.reshare_drop"))?;
+
+    if let Some(recipients) = shard_recipients {
+        new_shards = seal_shards(&new_shards, &recipients)
+            .map_err(ControlError::wrap("error.seal_shards"))?;
+    }
+
+    let data_dir = app_data_dir()?;
+    let mut catalog = DropCatalog::load(&data_dir)?;
+    if let Some(record) = catalog.find_mut(&drop_id) {
+        record.threshold = new_threshold;
+        record.total_shards = new_total;
+        record.reshared_at = Some(now_secs());
+        catalog
+            .save()
+            .map_err(ControlError::wrap("error.update_drop_catalog"))?;
+    }
+
+    info!(
+        "Reshared {} as {} of {} shards",
+        drop_id, new_threshold, new_total
+    );
+
+    Ok(new_shards)
+}
+
+/// Publish a new version of a versioned drop under its stable drop ID
+#[tauri::command]
+async fn update_drop(
+    drop_id: String,
+    file_path: String,
+    rotate_key: Option<bool>,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<DropVersionInfo, ControlError> {
+    let identity = current_identity(&state)?;
+    let data_dir = app_data_dir()?;
+    let options = drop_options(&window)?;
+
+    let rotate_key = rotate_key.unwrap_or(false);
+    let result = update_versioned_drop(
+        &drop_id, &file_path, rotate_key, &options, &data_dir, &identity,
+    )
+    .await
+    .map_err(ControlError::wrap("error.update_dead_drop"));
+    audit_drop(
+        AuditAction::Update,
+        &drop_id,
+        serde_json::json!({
+            "version": result.as_ref().ok().map(|info| info.version),
+            "cid": result.as_ref().ok().map(|info| &info.cid),
+            "rotate_key": rotate_key,
+        }),
+        &result,
+    );
+    let info = result?;
+
+    let mut catalog = DropCatalog::load(&data_dir)?;
+    let previous = catalog
+        .records()
+        .iter()
+        .find(|r| r.drop_id.as_deref() == Some(drop_id.as_str()))
+        .cloned();
+    if let Some(mut record) = previous {
+        record.cid = info.cid.clone();
+        record.created_at = now_secs();
+        record.pinned = true;
+        catalog.insert(record.clone());
+        catalog
+            .save()
+            .map_err(ControlError::wrap("error.update_drop_catalog"))?;
+        if !is_upload_pending(&options, &info.cid) {
+            if let Err(e) = mfs::link_drop(&record).await {
+                warn!("Drop {} not linked into MFS: {}", drop_id, e);
+            }
+        }
+    }
+
+    Ok(info)
+}
+
+/// Retrieve a versioned drop - the latest version unless one is specified
+#[tauri::command]
+async fn retrieve_drop_version(
+    drop_id: String,
+    version: Option<u32>,
+    shards: Vec<String>,
+    output_path: String,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<DropVersionInfo, ControlError> {
+    let shard_count = shards.len();
+    let shards = resolve_shards(shards, &window, &state).await?;
+    let options = drop_options(&window)?;
+
+    let result = retrieve_versioned_drop(&drop_id, version, shards, &output_path, &options)
+        .await
+        .map_err(ControlError::wrap("error.retrieve_dead_drop"));
+    audit_drop(
+        AuditAction::Retrieve,
+        &drop_id,
+        serde_json::json!({ "shards": shard_count, "version": version }),
+        &result,
+    );
+    let info = result?;
+
+    record_retrieval(&output_path);
+    Ok(info)
+}
+
+/// List all published versions of a versioned drop
+#[tauri::command]
+async fn get_drop_versions(
+    drop_id: String,
+    shards: Vec<String>,
+) -> Result<Vec<DropVersionInfo>, ControlError> {
+    list_drop_versions(&drop_id, shards)
+        .await
+        .map_err(ControlError::wrap("error.list_drop_versions"))
+}
+
+/// Export a drop's encrypted blocks to a CAR archive for offline transport
+#[tauri::command]
+async fn export_drop_car(
+    cid: String,
+    path: String,
+    window: tauri::Window,
+) -> Result<u64, ControlError> {
+    let options = drop_options(&window)?;
+    car::export_drop_car(&cid, Path::new(&path), &options)
+        .await
+        .map_err(ControlError::wrap("error.export_car"))
+}
+
+/// Import a CAR archive into the local IPFS node and pin its roots
+#[tauri::command]
+async fn import_drop_car(
+    path: String,
+    window: tauri::Window,
+) -> Result<CarImportResult, ControlError> {
+    let options = drop_options(&window)?;
+    car::import_drop_car(Path::new(&path), &options)
+        .await
+        .map_err(ControlError::wrap("error.import_car"))
+}
+
+/// Get drop streaming settings
+#[tauri::command]
+async fn get_drop_settings() -> Result<DropSettings, ControlError> {
+    Ok(settings::drop_settings())
+}
+
+/// Update drop streaming and upload scheduling settings (values are clamped to safe bounds)
+#[tauri::command]
+async fn set_drop_settings(settings: DropSettings) -> Result<DropSettings, ControlError> {
+    settings::modify(|s| s.drop = settings)
+        .map(|s| s.drop)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Measure Argon2, cipher, disk and IPFS speed on this machine. With `apply`,
+/// the recommended chunk size and parallelism are saved to the drop settings.
+#[tauri::command]
+async fn run_benchmark(
+    include_ipfs: Option<bool>,
+    apply: Option<bool>,
+    window: tauri::Window,
+) -> Result<BenchmarkReport, ControlError> {
+    let options = drop_options(&window)?;
+    let report = benchmark::run_benchmark(&options, include_ipfs.unwrap_or(true))
+        .await
+        .map_err(ControlError::wrap("error.benchmark"))?;
+    if apply.unwrap_or(false) {
+        settings::modify(|s| s.drop = report.recommended.apply(s.drop.clone()))
+            .map_err(ControlError::wrap("error.save_settings"))?;
+    }
+    Ok(report)
+}
+
+/// Check this build against the published protocol test vectors, for
+/// developers of compatible clients
+#[tauri::command]
+async fn verify_conformance() -> Result<ConformanceReport, ControlError> {
+    conformance::verify_conformance().map_err(ControlError::wrap("error.conformance"))
+}
+
+/// Get IPFS network settings (API endpoint, Tor proxy, gateways)
+#[tauri::command]
+async fn get_network_settings() -> Result<IpfsNetworkSettings, ControlError> {
+    Ok(ipfs::network())
+}
+
+/// Update IPFS network settings and apply them immediately
+#[tauri::command]
+async fn set_network_settings(settings: IpfsNetworkSettings) -> Result<(), ControlError> {
+    settings::modify(|s| s.network = settings)
+        .map(|_| ())
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_network_settings",
+        ))
+}
+
+/// Check whether a dead drop is still retrievable
+#[tauri::command]
+async fn verify_drop(cid: String) -> Result<DropAvailability, ControlError> {
+    let cache_dir = drop_cache_dir(&app_data_dir()?);
+
+    let result = verify_dead_drop(&cid, Some(&cache_dir))
+        .await
+        .map_err(ControlError::wrap("error.verify_dead_drop"));
+    audit_drop(AuditAction::Verify, &cid, serde_json::json!({}), &result);
+    result
+}
+
+/// Re-pin or re-upload a dead drop from the local ciphertext cache
+#[tauri::command]
+async fn repair_drop(cid: String) -> Result<DropRepairResult, ControlError> {
+    let cache_dir = drop_cache_dir(&app_data_dir()?);
+
+    repair_dead_drop(&cid, Some(&cache_dir))
+        .await
+        .map_err(ControlError::wrap("error.repair_dead_drop"))
+}
+
+/// Check pin status of all catalogued drops now, re-pinning anything missing
+#[tauri::command]
+async fn check_pin_health() -> Result<Vec<DropPinHealth>, ControlError> {
+    let data_dir = app_data_dir()?;
+    let settings = settings::pin_health_settings();
+
+    pin_health::check_pin_health(&data_dir, &settings)
+        .await
+        .map_err(ControlError::wrap("error.check_pin_health"))
+}
+
+/// Get pin monitoring settings
+#[tauri::command]
+async fn get_pin_health_settings() -> Result<PinHealthSettings, ControlError> {
+    Ok(settings::pin_health_settings())
+}
+
+/// Update pin monitoring settings; the monitor picks them up on its next pass
+#[tauri::command]
+async fn set_pin_health_settings(settings: PinHealthSettings) -> Result<(), ControlError> {
+    settings::modify(|s| s.pin_health = settings)
+        .map(|_| ())
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Get the watched folder settings
+#[tauri::command]
+async fn get_watch_folder_settings() -> Result<WatchFolderSettings, ControlError> {
+    Ok(settings::watch_folder_settings())
+}
+
+/// Update the watched folder; the watcher picks it up on its next scan
+#[tauri::command]
+async fn set_watch_folder_settings(
+    settings: WatchFolderSettings,
+) -> Result<WatchFolderSettings, ControlError> {
+    settings::modify(|s| s.watch_folder = settings)
+        .map(|s| s.watch_folder)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Get the scheduled backup settings
+#[tauri::command]
+async fn get_backup_settings() -> Result<BackupSettings, ControlError> {
+    Ok(settings::backup_settings())
+}
+
+/// Update the scheduled backup settings; the scheduler picks them up on its next check
+#[tauri::command]
+async fn set_backup_settings(settings: BackupSettings) -> Result<BackupSettings, ControlError> {
+    settings::modify(|s| s.backup = settings)
+        .map(|s| s.backup)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Get the away ping settings
+#[tauri::command]
+async fn get_away_ping_settings() -> Result<AwayPingSettings, ControlError> {
+    Ok(settings::away_ping_settings())
+}
+
+/// Update where away pings go
+#[tauri::command]
+async fn set_away_ping_settings(
+    settings: AwayPingSettings,
+) -> Result<AwayPingSettings, ControlError> {
+    settings::modify(|s| s.away_ping = settings)
+        .map(|s| s.away_ping)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Send a ping to every configured backend now, to check the setup
+#[tauri::command]
+async fn send_test_away_ping() -> Result<(), ControlError> {
+    away_ping::send_pings(&settings::away_ping_settings())
+        .await
+        .map_err(ControlError::wrap_as(
+            ErrorCode::NetworkUnavailable,
+            "error.away_ping",
+        ))
+}
+
+#[tauri::command]
+async fn get_webhook_settings() -> Result<WebhookSettings, ControlError> {
+    Ok(settings::webhook_settings())
+}
+
+/// Update the endpoints notified of messages and drop events
+#[tauri::command]
+async fn set_webhook_settings(settings: WebhookSettings) -> Result<WebhookSettings, ControlError> {
+    settings::modify(|s| s.webhooks = settings)
+        .map(|s| s.webhooks)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Post a `test` event to an endpoint, to check it before saving
+#[tauri::command]
+async fn send_test_webhook(endpoint: WebhookEndpoint) -> Result<(), ControlError> {
+    webhooks::send_test(&endpoint)
+        .await
+        .map_err(ControlError::wrap_as(
+            ErrorCode::NetworkUnavailable,
+            "error.webhook",
+        ))
+}
+
+/// Plugins found in the plugins folder and what each may currently do
+#[tauri::command]
+async fn list_plugins() -> Result<Vec<PluginInfo>, ControlError> {
+    Ok(plugins::list())
+}
+
+/// Rescan the plugins folder after adding or replacing a plugin
+#[tauri::command]
+async fn reload_plugins() -> Result<Vec<PluginInfo>, ControlError> {
+    plugins::load(&app_data_dir()?).map_err(ControlError::wrap("error.load_plugins"))?;
+    Ok(plugins::list())
+}
+
+#[tauri::command]
+async fn get_plugin_settings() -> Result<PluginSettings, ControlError> {
+    Ok(settings::plugin_settings())
+}
+
+/// Turn plugins on or off and change the capabilities granted to each
+#[tauri::command]
+async fn set_plugin_settings(settings: PluginSettings) -> Result<PluginSettings, ControlError> {
+    settings::modify(|s| s.plugins = settings)
+        .map(|s| s.plugins)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Backups still kept, oldest first
+#[tauri::command]
+async fn list_backups(state: State<'_, AppState>) -> Result<Vec<BackupRecord>, ControlError> {
+    let identity = current_identity(&state)?;
+    backup::list_backups(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.list_backups"))
+}
+
+/// Back up the configured folders now instead of waiting for the schedule
+#[tauri::command]
+async fn run_backup_now(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<BackupRecord, ControlError> {
+    let identity = current_identity(&state)?;
+    backup::run_backup(&app, &app_data_dir()?, &identity)
+        .await
+        .map_err(ControlError::wrap("error.run_backup"))
+}
+
+/// Get the data, cache and staging directories in use
+#[tauri::command]
+async fn get_storage_paths() -> Result<StoragePaths, ControlError> {
+    storage::paths().map_err(ControlError::wrap("error.get_storage_paths"))
+}
+
+/// Move all app data to another directory (e.g. on an encrypted volume), or
+/// back to the default with `None`. Takes effect on the next start.
+#[tauri::command]
+async fn set_data_dir(path: Option<String>) -> Result<StoragePaths, ControlError> {
+    storage::set_data_dir(path.map(PathBuf::from)).map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.change_data_directory",
+    ))
+}
+
+/// Most recent log lines for the in-app log viewer, oldest first
+#[tauri::command]
+async fn get_recent_logs(limit: Option<usize>) -> Result<Vec<String>, ControlError> {
+    Ok(logging::recent_logs(limit.unwrap_or(200)))
+}
+
+/// Write recent logs and app info to a file for bug reports, with keys,
+/// CIDs and network addresses scrubbed. Returns the number of log lines written.
+#[tauri::command]
+async fn export_diagnostics(path: String) -> Result<usize, ControlError> {
+    logging::export_diagnostics(Path::new(&path))
+        .map_err(ControlError::wrap("error.export_diagnostics"))
+}
+
+/// Which identity backups exist and whether one has been verified
+#[tauri::command]
+async fn get_recovery_status(state: State<'_, AppState>) -> Result<RecoveryStatus, ControlError> {
+    let identity = current_identity(&state)?;
+    RecoveryStatus::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_recovery_status"))
+}
+
+/// Export the identity as a 24-word recovery phrase
+#[tauri::command]
+async fn export_recovery_mnemonic(state: State<'_, AppState>) -> Result<String, ControlError> {
+    let identity = current_identity(&state)?;
+    recovery::export_mnemonic(&app_data_dir()?, &identity).map_err(ControlError::wrap_as(
+        ErrorCode::CryptoFailed,
+        "error.export_recovery_phrase",
+    ))
+}
+
+/// Our public ID as an age recipient (`age1…`), so `age`/`rage` users can
+/// encrypt files only this identity opens
+#[tauri::command]
+async fn get_age_recipient(state: State<'_, AppState>) -> Result<String, ControlError> {
+    let identity = current_identity(&state)?;
+    age_recipient(&identity.public_id()).map_err(ControlError::wrap("error.age_identity"))
+}
+
+/// Export the identity as an age secret key for decrypting drops with
+/// `age -d -i`
+#[tauri::command]
+async fn export_age_identity(state: State<'_, AppState>) -> Result<String, ControlError> {
+    let identity = current_identity(&state)?;
+    age_identity(&identity)
+        .map(|key| key.expose_secret().clone())
+        .map_err(ControlError::wrap_as(
+            ErrorCode::CryptoFailed,
+            "error.age_identity",
+        ))
+}
+
+/// Split the identity into Shamir backup shards
+#[tauri::command]
+async fn export_recovery_shards(
+    threshold: u8,
+    total_shards: u8,
+    state: State<'_, AppState>,
+) -> Result<Vec<String>, ControlError> {
+    let identity = current_identity(&state)?;
+    recovery::export_shards(&app_data_dir()?, &identity, threshold, total_shards).map_err(
+        ControlError::wrap_as(ErrorCode::InvalidInput, "error.export_recovery_shards"),
+    )
+}
+
+/// Check that a recovery phrase (single entry) or set of shards restores the
+/// current identity. Nothing from the backup is stored.
+#[tauri::command]
+async fn verify_backup(
+    backup: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<RecoveryStatus, ControlError> {
+    let identity = current_identity(&state)?;
+    recovery::verify_backup(&app_data_dir()?, &identity, &backup).map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.verify_backup",
+    ))
+}
+
+/// List saved contacts
+#[tauri::command]
+async fn list_contacts(state: State<'_, AppState>) -> Result<Vec<Contact>, ControlError> {
+    let identity = current_identity(&state)?;
+    let book = ContactBook::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_contacts"))?;
+    Ok(book.contacts().to_vec())
+}
+
+/// Add or update a contact
+#[tauri::command]
+async fn save_contact(
+    public_id: String,
+    nickname: String,
+    verified: bool,
+    state: State<'_, AppState>,
+) -> Result<Contact, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut book = ContactBook::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_contacts"))?;
+    let contact = book
+        .upsert(&public_id, &nickname, verified)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_contact",
+        ))?;
+    book.save()
+        .map_err(ControlError::wrap("error.save_contacts"))?;
+    Ok(contact)
+}
+
+/// Remove a contact; returns whether it existed
+#[tauri::command]
+async fn remove_contact(
+    public_id: String,
+    state: State<'_, AppState>,
+) -> Result<bool, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut book = ContactBook::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_contacts"))?;
+    let removed = book.remove(&public_id).is_some();
+    book.save()
+        .map_err(ControlError::wrap("error.save_contacts"))?;
+    Ok(removed)
+}
+
+/// Attach an OpenPGP public key to a contact for OpenPGP drops, or remove it
+#[tauri::command]
+async fn set_contact_pgp_key(
+    public_id: String,
+    key: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Contact, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut book = ContactBook::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_contacts"))?;
+    let contact = book
+        .set_pgp_key(&public_id, key.as_deref())
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.import_pgp_key",
+        ))?;
+    book.save()
+        .map_err(ControlError::wrap("error.save_contacts"))?;
+    if let Some(pgp_key) = &contact.pgp_key {
+        key_log::observe(
+            &app_data_dir()?,
+            &identity,
+            &public_id,
+            KeyKind::Pgp,
+            &pgp_key.fingerprint,
+            KeySource::User,
+        );
+    }
+    Ok(contact)
+}
+
+/// How a contact's conversation is drawn; empty for unknown contacts
+#[tauri::command]
+async fn get_contact_appearance(
+    public_id: String,
+    state: State<'_, AppState>,
+) -> Result<Appearance, ControlError> {
+    let identity = current_identity(&state)?;
+    let book = ContactBook::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_contacts"))?;
+    Ok(book
+        .get(&public_id)
+        .map(|c| c.appearance.clone())
+        .unwrap_or_default())
+}
+
+/// Set a contact's alias, color and avatar hash
+#[tauri::command]
+async fn set_contact_appearance(
+    public_id: String,
+    appearance: Appearance,
+    state: State<'_, AppState>,
+) -> Result<Contact, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut book = ContactBook::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_contacts"))?;
+    let contact = book
+        .set_appearance(&public_id, appearance)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_contact",
+        ))?;
+    book.save()
+        .map_err(ControlError::wrap("error.save_contacts"))?;
+    Ok(contact)
+}
+
+/// Write a signed contact card with our public key for out-of-band exchange
+#[tauri::command]
+async fn export_contact_card(
+    path: String,
+    alias: String,
+    avatar_hash: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<ContactCard, ControlError> {
+    let identity = current_identity(&state)?;
+    let proofs = ProofStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_proofs"))?;
+    contact_card::export_contact_card(
+        &identity,
+        &alias,
+        avatar_hash.as_deref(),
+        proofs.claims(),
+        Path::new(&path),
+    )
+    .map_err(ControlError::wrap("error.export_contact_card"))
+}
+
+/// Our identity proofs with the text to publish for each
+#[tauri::command]
+async fn list_proofs(state: State<'_, AppState>) -> Result<Vec<OwnProof>, ControlError> {
+    let identity = current_identity(&state)?;
+    let store = ProofStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_proofs"))?;
+    Ok(store.own_proofs(&identity.public_id()))
+}
+
+/// Claim a domain or repository; contact cards exported from now on carry it
+#[tauri::command]
+async fn add_proof(claim: Claim, state: State<'_, AppState>) -> Result<OwnProof, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut store = ProofStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_proofs"))?;
+    let proof = store
+        .add(claim, &identity.public_id())
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_proofs",
+        ))?;
+    store
+        .save()
+        .map_err(ControlError::wrap("error.save_proofs"))?;
+    Ok(proof)
+}
+
+/// Drop a claim; returns whether it existed
+#[tauri::command]
+async fn remove_proof(claim: Claim, state: State<'_, AppState>) -> Result<bool, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut store = ProofStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_proofs"))?;
+    let removed = store.remove(&claim);
+    store
+        .save()
+        .map_err(ControlError::wrap("error.save_proofs"))?;
+    Ok(removed)
+}
+
+/// Check that our own proofs are published where they should be
+#[tauri::command]
+async fn check_proofs(state: State<'_, AppState>) -> Result<Vec<ProofCheck>, ControlError> {
+    let identity = current_identity(&state)?;
+    let store = ProofStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_proofs"))?;
+    Ok(proofs::check_all(store.claims(), &identity.public_id()).await)
+}
+
+/// Look up the proofs from a contact's card and remember the results
+#[tauri::command]
+async fn verify_contact_proofs(
+    public_id: String,
+    state: State<'_, AppState>,
+) -> Result<Contact, ControlError> {
+    let identity = current_identity(&state)?;
+    let data_dir = app_data_dir()?;
+    let claims: Vec<Claim> = ContactBook::load(&data_dir, &identity)
+        .map_err(ControlError::wrap("error.load_contacts"))?
+        .get(&public_id)
+        .ok_or_else(|| ControlError::invalid_input("error.unknown_contact"))?
+        .proofs
+        .iter()
+        .map(|p| p.claim.clone())
+        .collect();
+    let checks = proofs::check_all(&claims, &public_id).await;
+
+    // Reloaded, since the book may have changed while the checks ran
+    let mut book = ContactBook::load(&data_dir, &identity)
+        .map_err(ControlError::wrap("error.load_contacts"))?;
+    let contact = book
+        .set_proof_checks(&public_id, checks)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.unknown_contact",
+        ))?;
+    book.save()
+        .map_err(ControlError::wrap("error.save_contacts"))?;
+    Ok(contact)
+}
+
+/// Verify a contact card file and add its owner as a contact
+#[tauri::command]
+async fn import_contact_card(
+    path: String,
+    nickname: Option<String>,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<ContactCardImport, ControlError> {
+    let identity = current_identity(&state)?;
+    let import = contact_card::import_contact_card(
+        &app_data_dir()?,
+        &identity,
+        Path::new(&path),
+        nickname.as_deref(),
+    )
+    .map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.import_contact_card",
+    ))?;
+    if let Some(change) = &import.key_change {
+        events::emit(&app, "contact_key_changed", change);
+    }
+    Ok(import)
+}
+
+/// Import contacts from a CSV, Signal or JSON export. With `dry_run` only
+/// the report of what would be added and skipped is returned.
+#[tauri::command]
+async fn import_contacts(
+    path: String,
+    format: Option<ImportFormat>,
+    dry_run: bool,
+    state: State<'_, AppState>,
+) -> Result<ImportReport, ControlError> {
+    let identity = current_identity(&state)?;
+    contact_import::import_contacts(
+        &app_data_dir()?,
+        &identity,
+        Path::new(&path),
+        format,
+        dry_run,
+    )
+    .map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.import_contacts",
+    ))
+}
+
+/// Contact requests in both directions, with the messages held for each
+#[tauri::command]
+async fn list_contact_requests(
+    state: State<'_, AppState>,
+) -> Result<Vec<KnockRecord>, ControlError> {
+    let identity = current_identity(&state)?;
+    let store = KnockStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_contact_requests"))?;
+    Ok(store.list())
+}
+
+/// Ask a contact to accept our messages
+#[tauri::command]
+async fn send_contact_request(
+    public_id: String,
+    note: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<KnockRecord, ControlError> {
+    let sender = state.p2p_sender()?;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    sender
+        .send(P2PCommand::SendContactRequest {
+            public_id,
+            note: note.unwrap_or_default(),
+            reply: reply_tx,
+        })
+        .await
+        .map_err(ControlError::wrap("error.send_contact_request"))?;
+    contact_request_reply(reply_rx, "error.send_contact_request").await
+}
+
+/// Accept or decline a pending contact request. Accepting delivers the
+/// messages held for it; declining discards them.
+#[tauri::command]
+async fn respond_contact_request(
+    public_id: String,
+    accept: bool,
+    state: State<'_, AppState>,
+) -> Result<KnockRecord, ControlError> {
+    let sender = state.p2p_sender()?;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    sender
+        .send(P2PCommand::RespondContactRequest {
+            public_id,
+            accept,
+            reply: reply_tx,
+        })
+        .await
+        .map_err(ControlError::wrap("error.respond_contact_request"))?;
+    contact_request_reply(reply_rx, "error.respond_contact_request").await
+}
+
+/// Introduce `contact` to `recipient` with a signed introduction
+#[tauri::command]
+async fn send_introduction(
+    recipient: String,
+    contact: String,
+    note: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), ControlError> {
+    let sender = state.p2p_sender()?;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    sender
+        .send(P2PCommand::SendIntroduction {
+            recipient,
+            contact,
+            note: note.unwrap_or_default(),
+            reply: reply_tx,
+        })
+        .await
+        .map_err(ControlError::wrap("error.send_introduction"))?;
+    reply_rx
+        .await
+        .map_err(|_| {
+            ControlError::localized(ErrorCode::GhostModeNotRunning, "error.ghost_mode_stopped")
+        })?
+        .map_err(|e| {
+            ControlError::localized(ErrorCode::InvalidInput, "error.send_introduction")
+                .with_cause(e)
+        })
+}
+
+/// Introductions received from contacts and not answered yet
+#[tauri::command]
+async fn list_introductions(
+    state: State<'_, AppState>,
+) -> Result<Vec<PendingIntroduction>, ControlError> {
+    let identity = current_identity(&state)?;
+    let store = IntroductionStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_introductions"))?;
+    Ok(store.pending().to_vec())
+}
+
+/// Accept an introduction, adding the contact with who introduced them, or
+/// decline it. Returns the contact when accepted.
+#[tauri::command]
+async fn respond_introduction(
+    id: String,
+    accept: bool,
+    nickname: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Option<Contact>, ControlError> {
+    let identity = current_identity(&state)?;
+    let data_dir = app_data_dir()?;
+    if !accept {
+        decline_introduction(&data_dir, &identity, &id).map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.respond_introduction",
+        ))?;
+        return Ok(None);
+    }
+    let contact = accept_introduction(&data_dir, &identity, &id, nickname.as_deref()).map_err(
+        ControlError::wrap_as(ErrorCode::InvalidInput, "error.respond_introduction"),
+    )?;
+    Ok(Some(contact))
+}
+
+/// Vouch for a verified contact to our other verified contacts, or to
+/// `recipients` among them. Returns the contacts it went out to.
+#[tauri::command]
+async fn vouch_for_contact(
+    public_id: String,
+    recipients: Option<Vec<String>>,
+    state: State<'_, AppState>,
+) -> Result<Vec<String>, ControlError> {
+    let identity = current_identity(&state)?;
+    let book = ContactBook::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_contacts"))?;
+    let subject = book.get(&public_id).ok_or_else(|| {
+        ControlError::localized(ErrorCode::InvalidInput, "error.unknown_contact")
+    })?;
+    let attestation = trust::attest(&identity, subject).map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.vouch_for_contact",
+    ))?;
+
+    let recipients = match recipients {
+        Some(recipients) if recipients.iter().all(|r| book.get(r).is_some_and(|c| c.verified)) => {
+            recipients
+        }
+        Some(_) => {
+            return Err(ControlError::localized(
+                ErrorCode::InvalidInput,
+                "error.vouch_unverified_recipient",
+            ))
+        }
+        None => book
+            .contacts()
+            .iter()
+            .filter(|c| c.verified && c.public_id != public_id)
+            .map(|c| c.public_id.clone())
+            .collect(),
+    };
+
+    let sender = state.p2p_sender()?;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    sender
+        .send(P2PCommand::SendAttestation {
+            attestation,
+            recipients,
+            reply: reply_tx,
+        })
+        .await
+        .map_err(ControlError::wrap("error.vouch_for_contact"))?;
+    reply_rx
+        .await
+        .map_err(|_| {
+            ControlError::localized(ErrorCode::GhostModeNotRunning, "error.ghost_mode_stopped")
+        })?
+        .map_err(|e| {
+            ControlError::localized(ErrorCode::InvalidInput, "error.vouch_for_contact")
+                .with_cause(e)
+        })
+}
+
+/// Attestations received from verified contacts, optionally only those
+/// about one person
+#[tauri::command]
+async fn list_attestations(
+    subject: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<ReceivedAttestation>, ControlError> {
+    let identity = current_identity(&state)?;
+    let store = AttestationStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_attestations"))?;
+    Ok(store
+        .received()
+        .iter()
+        .filter(|r| subject.as_ref().map_or(true, |s| r.attestation.subject == *s))
+        .cloned()
+        .collect())
+}
+
+/// How far we trust a peer, from our own verification and attestations
+#[tauri::command]
+async fn get_contact_trust(
+    public_id: String,
+    state: State<'_, AppState>,
+) -> Result<Trust, ControlError> {
+    let identity = current_identity(&state)?;
+    let data_dir = app_data_dir()?;
+    let book = ContactBook::load(&data_dir, &identity)
+        .map_err(ControlError::wrap("error.load_contacts"))?;
+    let store = AttestationStore::load(&data_dir, &identity)
+        .map_err(ControlError::wrap("error.load_attestations"))?;
+    Ok(trust::evaluate(
+        &book,
+        &store,
+        &public_id,
+        &settings::trust_settings(),
+    ))
+}
+
+#[tauri::command]
+async fn get_trust_settings() -> Result<TrustSettings, ControlError> {
+    Ok(settings::trust_settings())
+}
+
+/// Set how many verified contacts must vouch for someone
+#[tauri::command]
+async fn set_trust_settings(settings: TrustSettings) -> Result<TrustSettings, ControlError> {
+    settings::modify(|s| s.trust = settings)
+        .map(|s| s.trust)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+#[tauri::command]
+async fn get_no_traces_settings() -> Result<NoTracesSettings, ControlError> {
+    Ok(settings::no_traces_settings())
+}
+
+/// Turn no-traces mode on or off; refused while a compliance hold is on or
+/// without a RAM-backed staging directory
+#[tauri::command]
+async fn set_no_traces_settings(
+    settings: NoTracesSettings,
+) -> Result<NoTracesSettings, ControlError> {
+    settings::modify(|s| s.no_traces = settings)
+        .map(|s| s.no_traces)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Whether no-traces mode is on, where it stages drops and whether swap
+/// could still page memory out
+#[tauri::command]
+async fn get_no_traces_status() -> Result<NoTracesStatus, ControlError> {
+    Ok(no_traces::status())
+}
+
+/// Groups drops can be shared with
+#[tauri::command]
+async fn list_groups(state: State<'_, AppState>) -> Result<Vec<Group>, ControlError> {
+    let identity = current_identity(&state)?;
+    let store = GroupStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_groups"))?;
+    Ok(store.groups().to_vec())
+}
+
+/// Create a group, or update the one with `id`
+#[tauri::command]
+async fn save_group(
+    id: Option<String>,
+    name: String,
+    members: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<Group, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut store = GroupStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_groups"))?;
+    let group = store
+        .upsert(id.as_deref(), &name, &members)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_group",
+        ))?;
+    store
+        .save()
+        .map_err(ControlError::wrap("error.save_groups"))?;
+    Ok(group)
+}
+
+/// Remove a group; drops already shared with it are unaffected
+#[tauri::command]
+async fn remove_group(id: String, state: State<'_, AppState>) -> Result<bool, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut store = GroupStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_groups"))?;
+    let removed = store.remove(&id).is_some();
+    store
+        .save()
+        .map_err(ControlError::wrap("error.save_groups"))?;
+    Ok(removed)
+}
+
+/// Shards of other people's group drops that were sent to us
+#[tauri::command]
+async fn list_group_shards(state: State<'_, AppState>) -> Result<Vec<ReceivedShard>, ControlError> {
+    let identity = current_identity(&state)?;
+    let store = GroupStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_groups"))?;
+    Ok(store.received().to_vec())
+}
+
+/// Send shards of a group drop again to members who haven't confirmed them
+#[tauri::command]
+async fn send_group_shards(
+    cid: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<String>, ControlError> {
+    deliver_group_shards(&state, &cid).await
+}
+
+async fn deliver_group_shards(
+    state: &State<'_, AppState>,
+    cid: &str,
+) -> Result<Vec<String>, ControlError> {
+    let sender = state.p2p_sender()?;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    sender
+        .send(P2PCommand::SendGroupShards {
+            cid: cid.to_string(),
+            reply: reply_tx,
+        })
+        .await
+        .map_err(ControlError::wrap("error.send_group_shards"))?;
+    reply_rx
+        .await
+        .map_err(|_| {
+            ControlError::localized(ErrorCode::GhostModeNotRunning, "error.ghost_mode_stopped")
+        })?
+        .map_err(|e| {
+            ControlError::localized(ErrorCode::InvalidInput, "error.send_group_shards")
+                .with_cause(e)
+        })
+}
+
+/// Start a poll among a group's members; their clients get a `poll` event
+#[tauri::command]
+async fn create_poll(
+    group_id: String,
+    question: String,
+    options: Vec<String>,
+    multiple_choice: Option<bool>,
+    anonymous: Option<bool>,
+    reveal: Option<Reveal>,
+    state: State<'_, AppState>,
+) -> Result<PollResults, ControlError> {
+    let identity = current_identity(&state)?;
+    state.p2p_sender()?;
+    let data_dir = app_data_dir()?;
+    let group = GroupStore::load(&data_dir, &identity)
+        .map_err(ControlError::wrap("error.load_groups"))?
+        .get(&group_id)
+        .cloned()
+        .ok_or_else(|| ControlError::invalid_input("error.unknown_group"))?;
+    let (results, recipients, message) = polls::create(
+        &data_dir,
+        &identity,
+        &group,
+        &question,
+        &options,
+        multiple_choice.unwrap_or(false),
+        anonymous.unwrap_or(false),
+        reveal.unwrap_or(Reveal::Live),
+    )
+    .map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.create_poll",
+    ))?;
+    send_poll_message(&state, recipients, message, "error.create_poll").await?;
+    Ok(results)
+}
+
+/// Vote on a poll, replacing any earlier vote of ours while it is open
+#[tauri::command]
+async fn vote_poll(
+    poll_id: String,
+    choices: Vec<usize>,
+    state: State<'_, AppState>,
+) -> Result<PollResults, ControlError> {
+    let identity = current_identity(&state)?;
+    state.p2p_sender()?;
+    let (results, recipients, message) =
+        polls::vote(&app_data_dir()?, &identity, &poll_id, choices).map_err(
+            ControlError::wrap_as(ErrorCode::InvalidInput, "error.vote_poll"),
+        )?;
+    send_poll_message(&state, recipients, message, "error.vote_poll").await?;
+    Ok(results)
+}
+
+/// Close one of our polls and send everyone the final results
+#[tauri::command]
+async fn close_poll(
+    poll_id: String,
+    state: State<'_, AppState>,
+) -> Result<PollResults, ControlError> {
+    let identity = current_identity(&state)?;
+    state.p2p_sender()?;
+    let (results, recipients, message) = polls::close(&app_data_dir()?, &identity, &poll_id)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.close_poll",
+        ))?;
+    send_poll_message(&state, recipients, message, "error.close_poll").await?;
+    Ok(results)
+}
+
+/// Polls we created or take part in; changes arrive as `poll_results`
+#[tauri::command]
+async fn list_polls(state: State<'_, AppState>) -> Result<Vec<PollResults>, ControlError> {
+    let identity = current_identity(&state)?;
+    polls::list(&app_data_dir()?, &identity).map_err(ControlError::wrap("error.load_polls"))
+}
+
+#[tauri::command]
+async fn get_poll(
+    poll_id: String,
+    state: State<'_, AppState>,
+) -> Result<PollResults, ControlError> {
+    let identity = current_identity(&state)?;
+    polls::get(&app_data_dir()?, &identity, &poll_id).map_err(ControlError::wrap_as(
+        ErrorCode::NotFound,
+        "error.load_polls",
+    ))
+}
+
+/// Remove a poll from this device; other participants keep theirs
+#[tauri::command]
+async fn forget_poll(poll_id: String, state: State<'_, AppState>) -> Result<bool, ControlError> {
+    let identity = current_identity(&state)?;
+    polls::forget(&app_data_dir()?, &identity, &poll_id)
+        .map_err(ControlError::wrap("error.forget_poll"))
+}
+
+async fn send_poll_message(
+    state: &State<'_, AppState>,
+    recipients: Vec<String>,
+    message: PollMessage,
+    message_id: &'static str,
+) -> Result<Vec<String>, ControlError> {
+    let sender = state.p2p_sender()?;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    sender
+        .send(P2PCommand::SendPollMessage {
+            recipients,
+            message,
+            reply: reply_tx,
+        })
+        .await
+        .map_err(ControlError::wrap(message_id))?;
+    reply_rx
+        .await
+        .map_err(|_| {
+            ControlError::localized(ErrorCode::GhostModeNotRunning, "error.ghost_mode_stopped")
+        })?
+        .map_err(|e| {
+            ControlError::localized(ErrorCode::NetworkUnavailable, message_id).with_cause(e)
+        })
+}
+
+async fn contact_request_reply(
+    reply_rx: tokio::sync::oneshot::Receiver<Result<KnockRecord, String>>,
+    message_id: &str,
+) -> Result<KnockRecord, ControlError> {
+    reply_rx
+        .await
+        .map_err(|_| {
+            ControlError::localized(ErrorCode::GhostModeNotRunning, "error.ghost_mode_stopped")
+        })?
+        .map_err(|e| ControlError::localized(ErrorCode::InvalidInput, message_id).with_cause(e))
+}
+
+/// Conversations with unread counts and last-message previews, most recent first
+#[tauri::command]
+async fn list_conversations(state: State<'_, AppState>) -> Result<Vec<Conversation>, ControlError> {
+    let identity = current_identity(&state)?;
+    conversations::list_conversations(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_conversations"))
+}
+
+/// Mark a conversation as read; returns the unread total across all conversations
+#[tauri::command]
+async fn mark_read(
+    contact: String,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<u32, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut store = ConversationStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_conversations"))?;
+    if let Some(conversation) = store.mark_read(&contact) {
+        events::emit(&window.app_handle(), "conversation_updated", conversation);
+        store
+            .save()
+            .map_err(ControlError::wrap("error.save_conversations"))?;
+    }
+    Ok(store.total_unread())
+}
+
+/// View-once messages received: pending ones and tombstones of viewed ones
+#[tauri::command]
+async fn list_view_once(state: State<'_, AppState>) -> Result<Vec<ViewOnceRecord>, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut store = ViewOnceStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_view_once"))?;
+    if store.expire_unconfirmed() {
+        store
+            .save()
+            .map_err(ControlError::wrap("error.save_view_once"))?;
+    }
+    Ok(store.list())
+}
+
+/// Read a view-once message. It is marked opened before the content is
+/// returned, so a second call fails; `confirm_view_once_displayed` wipes it.
+#[tauri::command]
+async fn open_view_once_message(
+    message_id: String,
+    state: State<'_, AppState>,
+) -> Result<String, ControlError> {
+    state.touch();
+    let identity = current_identity(&state)?;
+    let mut store = ViewOnceStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_view_once"))?;
+    store.expire_unconfirmed();
+    let content = store.open(&message_id).map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.open_view_once",
+    ))?;
+    store
+        .save()
+        .map_err(ControlError::wrap("error.save_view_once"))?;
+    Ok(content)
+}
+
+/// The frontend showed a view-once message: destroy its content and key
+#[tauri::command]
+async fn confirm_view_once_displayed(
+    message_id: String,
+    state: State<'_, AppState>,
+) -> Result<ViewOnceRecord, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut store = ViewOnceStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_view_once"))?;
+    let record = store
+        .confirm_displayed(&message_id)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.open_view_once",
+        ))?;
+    store
+        .save()
+        .map_err(ControlError::wrap("error.save_view_once"))?;
+    Ok(record)
+}
+
+#[tauri::command]
+async fn get_compliance_settings() -> Result<ComplianceSettings, ControlError> {
+    Ok(settings::compliance_settings())
+}
+
+/// Turn admin mode on or off and choose the conversations under hold
+#[tauri::command]
+async fn set_compliance_settings(
+    settings: ComplianceSettings,
+) -> Result<ComplianceSettings, ControlError> {
+    settings::modify(|s| s.compliance = settings)
+        .map(|s| s.compliance)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Write the signed, hash-chained transcript of a held conversation
+#[tauri::command]
+async fn export_transcript(
+    contact: String,
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<Transcript, ControlError> {
+    if !settings::compliance_settings().enabled {
+        return Err(ControlError::invalid_input("error.compliance_disabled"));
+    }
+    let identity = current_identity(&state)?;
+    transcript::export_transcript(&app_data_dir()?, &identity, &contact, Path::new(&path))
+        .map_err(ControlError::wrap("error.export_transcript"))
+}
+
+/// Check the signature and hash chain of an exported transcript
+#[tauri::command]
+async fn verify_transcript(path: String) -> Result<Transcript, ControlError> {
+    transcript::verify_transcript(Path::new(&path)).map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.verify_transcript",
+    ))
+}
+
+/// Canary contacts and canary drops planted so far
+#[tauri::command]
+async fn list_canaries(state: State<'_, AppState>) -> Result<Canaries, ControlError> {
+    let identity = current_identity(&state)?;
+    let store = CanaryStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_canaries"))?;
+    Ok(store.list())
+}
+
+/// Designate a public ID that should never get in touch
+#[tauri::command]
+async fn add_canary_contact(
+    public_id: String,
+    label: String,
+    state: State<'_, AppState>,
+) -> Result<CanaryContact, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut store = CanaryStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_canaries"))?;
+    let contact = store
+        .add_contact(&public_id, &label)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.canary",
+        ))?;
+    store
+        .save()
+        .map_err(ControlError::wrap("error.save_canaries"))?;
+    Ok(contact)
+}
+
+/// Make shards of a drop that doesn't exist, to plant where a leak would show
+#[tauri::command]
+async fn create_canary_drop(
+    label: String,
+    shards: u8,
+    state: State<'_, AppState>,
+) -> Result<CanaryDrop, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut store = CanaryStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_canaries"))?;
+    let drop = store
+        .create_drop(&identity, &label, shards)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.canary",
+        ))?;
+    store
+        .save()
+        .map_err(ControlError::wrap("error.save_canaries"))?;
+    Ok(drop)
+}
+
+/// Stop watching a canary contact or drop; false if there was none
+#[tauri::command]
+async fn remove_canary(canary: String, state: State<'_, AppState>) -> Result<bool, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut store = CanaryStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_canaries"))?;
+    let removed = store.remove(&canary);
+    if removed {
+        store
+            .save()
+            .map_err(ControlError::wrap("error.save_canaries"))?;
+    }
+    Ok(removed)
+}
+
+/// Canary alerts, most recent first
+#[tauri::command]
+async fn list_canary_alerts(state: State<'_, AppState>) -> Result<Vec<CanaryAlert>, ControlError> {
+    let identity = current_identity(&state)?;
+    let store = CanaryStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_canaries"))?;
+    Ok(store.alerts())
+}
+
+#[tauri::command]
+async fn get_canary_settings() -> Result<CanarySettings, ControlError> {
+    Ok(settings::canary_settings())
+}
+
+/// Choose whether a tripped canary locks the session
+#[tauri::command]
+async fn set_canary_settings(settings: CanarySettings) -> Result<CanarySettings, ControlError> {
+    settings::modify(|s| s.canary = settings)
+        .map(|s| s.canary)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Messages containing every word of `query`, newest first, optionally
+/// only from the conversation with `contact`
+#[tauri::command]
+async fn search_messages(
+    query: String,
+    contact: Option<String>,
+    limit: Option<usize>,
+    state: State<'_, AppState>,
+) -> Result<Vec<SearchHit>, ControlError> {
+    let identity = current_identity(&state)?;
+    SearchIndex::open(&app_data_dir()?, &identity)
+        .search(
+            &query,
+            contact.as_deref(),
+            limit.unwrap_or(search::MAX_RESULTS),
+        )
+        .map_err(ControlError::wrap("error.search_messages"))
+}
+
+#[tauri::command]
+async fn get_search_settings() -> Result<SearchSettings, ControlError> {
+    Ok(settings::search_settings())
+}
+
+/// Turn the message index on or off; turning it off deletes the index
+#[tauri::command]
+async fn set_search_settings(
+    settings: SearchSettings,
+    state: State<'_, AppState>,
+) -> Result<SearchSettings, ControlError> {
+    if !settings.enabled {
+        let identity = current_identity(&state)?;
+        SearchIndex::open(&app_data_dir()?, &identity)
+            .clear()
+            .map_err(ControlError::wrap("error.clear_search_index"))?;
+    }
+    settings::modify(|s| s.search = settings)
+        .map(|s| s.search)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+#[tauri::command]
+async fn get_retention_settings() -> Result<RetentionSettings, ControlError> {
+    Ok(settings::retention_settings())
+}
+
+/// Set how long and how much history is kept before it is archived
+#[tauri::command]
+async fn set_retention_settings(
+    settings: RetentionSettings,
+) -> Result<RetentionSettings, ControlError> {
+    settings::modify(|s| s.retention = settings)
+        .map(|s| s.retention)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Archive what the retention settings allow now instead of within the hour
+#[tauri::command]
+async fn apply_retention(state: State<'_, AppState>) -> Result<Vec<ArchiveInfo>, ControlError> {
+    let identity = current_identity(&state)?;
+    archive::apply_retention(&app_data_dir()?, &identity, &settings::retention_settings())
+        .map_err(ControlError::wrap("error.archive_history"))
+}
+
+/// Message archives, newest first
+#[tauri::command]
+async fn list_archives(state: State<'_, AppState>) -> Result<Vec<ArchiveInfo>, ControlError> {
+    let identity = current_identity(&state)?;
+    let store = ArchiveStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_archives"))?;
+    Ok(store.list())
+}
+
+/// Bring an archive's messages back into search and delete the archive
+#[tauri::command]
+async fn restore_archive(
+    archive_id: String,
+    state: State<'_, AppState>,
+) -> Result<ArchiveInfo, ControlError> {
+    let identity = current_identity(&state)?;
+    archive::restore_archive(&app_data_dir()?, &identity, &archive_id)
+        .map_err(ControlError::wrap("error.restore_archive"))
+}
+
+#[tauri::command]
+async fn delete_archive(
+    archive_id: String,
+    state: State<'_, AppState>,
+) -> Result<ArchiveInfo, ControlError> {
+    let identity = current_identity(&state)?;
+    archive::delete_archive(&app_data_dir()?, &identity, &archive_id)
+        .map_err(ControlError::wrap("error.delete_archive"))
+}
+
+/// Messages and bytes kept per conversation, in search and in archives
+#[tauri::command]
+async fn get_history_usage(
+    state: State<'_, AppState>,
+) -> Result<Vec<ConversationUsage>, ControlError> {
+    let identity = current_identity(&state)?;
+    archive::storage_usage(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.history_usage"))
+}
+
+/// Devices sharing this identity, this one included
+#[tauri::command]
+async fn list_devices(state: State<'_, AppState>) -> Result<Vec<Device>, ControlError> {
+    let identity = current_identity(&state)?;
+    let mut store = DeviceStore::load(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_devices"))?;
+    if store
+        .register(&identity)
+        .map_err(ControlError::wrap("error.load_devices"))?
+    {
+        store
+            .save()
+            .map_err(ControlError::wrap("error.load_devices"))?;
+    }
+    Ok(store.list())
+}
+
+/// Let a pending device sync, handing it the sync key
+#[tauri::command]
+async fn approve_device(
+    device_id: String,
+    state: State<'_, AppState>,
+) -> Result<Device, ControlError> {
+    let identity = current_identity(&state)?;
+    let data_dir = app_data_dir()?;
+    let mut store = DeviceStore::load(&data_dir, &identity)
+        .map_err(ControlError::wrap("error.load_devices"))?;
+    let device = store
+        .approve(&identity, &device_id)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.approve_device",
+        ))?;
+    store
+        .save()
+        .map_err(ControlError::wrap("error.approve_device"))?;
+    if let Err(e) = sync::sync_now(&data_dir, &identity).await {
+        warn!("Approved device will get the sync key on the next sync: {}", e);
+    }
+    Ok(device)
+}
+
+/// Cut off a lost or stolen device: replace the sync key, publish the new
+/// device list and tell contacts while Ghost Mode runs
+#[tauri::command]
+async fn revoke_device(
+    device_id: String,
+    state: State<'_, AppState>,
+) -> Result<RevocationReport, ControlError> {
+    let identity = current_identity(&state)?;
+    let data_dir = app_data_dir()?;
+    let mut store = DeviceStore::load(&data_dir, &identity)
+        .map_err(ControlError::wrap("error.load_devices"))?;
+    let device = store
+        .revoke(&identity, &device_id)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.revoke_device",
+        ))?;
+    store
+        .save()
+        .map_err(ControlError::wrap("error.revoke_device"))?;
+
+    let synced = match sync::sync_now(&data_dir, &identity).await {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("Revocation not published yet: {}", e);
+            false
+        }
+    };
+
+    let notice = devices::revocation(&identity, &device)
+        .map_err(ControlError::wrap("error.revoke_device"))?;
+    let mut notified = Vec::new();
+    if let Ok(sender) = state.p2p_sender() {
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        let sent = sender
+            .send(P2PCommand::AnnounceRevocation {
+                notice,
+                reply: reply_tx,
+            })
+            .await;
+        match (sent, reply_rx.await) {
+            (Ok(()), Ok(Ok(contacts))) => notified = contacts,
+            _ => warn!("Could not tell contacts about the revoked device"),
+        }
+    }
+
+    Ok(RevocationReport {
+        device,
+        synced,
+        notified,
+    })
+}
+
+#[tauri::command]
+async fn get_remote_wipe_settings() -> Result<RemoteWipeSettings, ControlError> {
+    Ok(settings::remote_wipe_settings())
+}
+
+/// Set who may wipe this device from afar; turning it off cancels a pending wipe
+#[tauri::command]
+async fn set_remote_wipe_settings(
+    settings: RemoteWipeSettings,
+    state: State<'_, AppState>,
+) -> Result<RemoteWipeSettings, ControlError> {
+    if !settings.enabled {
+        let identity = current_identity(&state)?;
+        remote_wipe::cancel(&app_data_dir()?, &identity)
+            .map_err(ControlError::wrap("error.cancel_remote_wipe"))?;
+    }
+    settings::modify(|s| s.remote_wipe = settings)
+        .map(|s| s.remote_wipe)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_settings",
+        ))
+}
+
+/// Order a wipe of one of our devices, or of all others when `device_id` is
+/// unset. With `owner`, order it for a contact who made us their guardian.
+#[tauri::command]
+async fn send_remote_wipe(
+    owner: Option<String>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), ControlError> {
+    let identity = current_identity(&state)?;
+    let (recipient, wipe) = remote_wipe::order(
+        &app_data_dir()?,
+        &identity,
+        owner.as_deref(),
+        device_id,
+    )
+    .map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.send_remote_wipe",
+    ))?;
+
+    let sender = state.p2p_sender()?;
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    sender
+        .send(P2PCommand::SendRemoteWipe {
+            recipient,
+            wipe,
+            reply: reply_tx,
+        })
+        .await
+        .map_err(ControlError::wrap("error.send_remote_wipe"))?;
+    reply_rx
+        .await
+        .map_err(|_| {
+            ControlError::localized(ErrorCode::GhostModeNotRunning, "error.ghost_mode_stopped")
+        })?
+        .map_err(|e| {
+            ControlError::localized(ErrorCode::InvalidInput, "error.send_remote_wipe")
+                .with_cause(e)
+        })
+}
+
+/// A wipe ordered for this device that waits out its confirmation delay
+#[tauri::command]
+async fn get_pending_remote_wipe(
+    state: State<'_, AppState>,
+) -> Result<Option<PendingWipe>, ControlError> {
+    let identity = current_identity(&state)?;
+    remote_wipe::pending(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.load_remote_wipe"))
+}
+
+/// Call off a pending wipe before it is due
+#[tauri::command]
+async fn cancel_remote_wipe(
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<Option<PendingWipe>, ControlError> {
+    let identity = current_identity(&state)?;
+    let cancelled = remote_wipe::cancel(&app_data_dir()?, &identity)
+        .map_err(ControlError::wrap("error.cancel_remote_wipe"))?;
+    if let Some(pending) = &cancelled {
+        events::emit(&window.app_handle(), "remote_wipe_cancelled", pending);
+    }
+    Ok(cancelled)
+}
+
+/// Drop `file_path` and hold its shards back until a check-in is missed;
+/// `dead_man_released` is emitted when a switch fires
+#[tauri::command]
+async fn create_dead_man_switch(
+    file_path: String,
+    threshold: u8,
+    config: SwitchConfig,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<DeadManSwitch, ControlError> {
+    let identity = current_identity(&state)?;
+    let total_shards = config.shard_count(threshold).map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.create_dead_man_switch",
+    ))?;
+
+    let source_path = Path::new(&file_path);
+    let file_name = source_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let size = std::fs::metadata(source_path).map_or(0, |m| m.len());
+    let created = create_dead_drop(&file_path, threshold, total_shards, &drop_options(&window)?)
+        .await
+        .map_err(ControlError::wrap("error.create_dead_drop"))?;
+    let drop = PlainDrop {
+        file_name,
+        threshold,
+        total_shards,
+        expires_in_secs: None,
+        size,
+    };
+    record_plain_drop(&created, drop, "dead_man").await?;
+
+    dead_man::arm(&app_data_dir()?, &identity, &config, threshold, &created)
+        .await
+        .map_err(ControlError::wrap("error.create_dead_man_switch"))
+}
+
+#[tauri::command]
+async fn list_dead_man_switches() -> Result<Vec<DeadManSwitch>, ControlError> {
+    dead_man::list(&app_data_dir()?).map_err(ControlError::wrap("error.load_dead_man_switches"))
+}
+
+/// Check in to one switch, or to all armed switches if `id` is unset
+#[tauri::command]
+async fn check_in_dead_man_switch(
+    id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<DeadManSwitch>, ControlError> {
+    let identity = current_identity(&state)?;
+    dead_man::check_in(&app_data_dir()?, &identity, id.as_deref())
+        .map_err(ControlError::wrap("error.check_in_dead_man_switch"))
+}
+
+/// Forget a switch and its held-back shards; the drop stays pinned
+#[tauri::command]
+async fn disarm_dead_man_switch(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<bool, ControlError> {
+    // Only the unlocked owner may call a switch off
+    current_identity(&state)?;
+    dead_man::disarm(&app_data_dir()?, &id)
+        .map_err(ControlError::wrap("error.disarm_dead_man_switch"))
+}
+
+/// Sync contacts and portable settings with this identity's other devices now
+#[tauri::command]
+async fn sync_now(
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<SyncReport, ControlError> {
+    let identity = current_identity(&state)?;
+    let report =
+        sync::sync_now(&app_data_dir()?, &identity)
+            .await
+            .map_err(ControlError::wrap_as(
+                ErrorCode::NetworkUnavailable,
+                "error.sync",
+            ))?;
+    events::emit(&window.app_handle(), "sync_completed", &report);
+    Ok(report)
+}
+
+/// Copy a shard or key to the clipboard, clearing it after `ttl_secs` or on
+/// lock. Returns the TTL actually applied.
+#[tauri::command]
+async fn copy_sensitive(
+    value: String,
+    ttl_secs: Option<u64>,
+    app: tauri::AppHandle,
+) -> Result<u64, ControlError> {
+    clipboard::copy_sensitive(&app, value, ttl_secs)
+        .map_err(ControlError::wrap("error.copy_to_clipboard"))
+}
+
+/// Export identity, contacts, settings, conversations and the drop catalog as
+/// one password-encrypted file for moving to another machine
+#[tauri::command]
+async fn export_profile(
+    path: String,
+    password: String,
+    state: State<'_, AppState>,
+) -> Result<(), ControlError> {
+    let identity = current_identity(&state)?;
+    profile::export_profile(&app_data_dir()?, &identity, Path::new(&path), &password)
+        .map_err(ControlError::wrap("error.export_profile"))
+}
+
+/// Import a profile file, optionally only some of its parts. Works before an
+/// identity exists on this device, or while unlocked as the same identity.
+#[tauri::command]
+async fn import_profile(
+    path: String,
+    password: String,
+    options: Option<ProfileImportOptions>,
+    window: tauri::Window,
+    state: State<'_, AppState>,
+) -> Result<ProfileImportReport, ControlError> {
+    let unlocked = state.identity().ok();
+    let report = profile::import_profile(
+        &app_data_dir()?,
+        unlocked.as_ref(),
+        Path::new(&path),
+        &password,
+        &options.unwrap_or_default(),
+    )
+    .map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.import_profile",
+    ))?;
+
+    if report.settings {
+        events::emit(
+            &window.app_handle(),
+            "settings_changed",
+            settings::saved(),
+        );
+    }
+    Ok(report)
+}
+
+/// Journaled events after `seq`, for the frontend to catch up after a reload
+#[tauri::command]
+async fn get_events_since(seq: u64) -> Result<EventReplay, ControlError> {
+    Ok(events::events_since(seq))
+}
+
+/// Locales the backend has message catalogs for
+#[tauri::command]
+async fn list_locales() -> Result<Vec<LocaleInfo>, ControlError> {
+    Ok(i18n::locales())
+}
+
+/// Every message in the configured locale, for localizing the UI
+#[tauri::command]
+async fn get_message_catalog() -> Result<MessageCatalog, ControlError> {
+    Ok(i18n::catalog(&settings::locale()))
+}
+
+/// Get all application settings as saved, without no-traces overrides
+#[tauri::command]
+async fn get_settings() -> Result<AppSettings, ControlError> {
+    settings::check_loaded().map_err(ControlError::wrap_as(
+        ErrorCode::StorageFailed,
+        "error.load_settings",
+    ))?;
+    Ok(settings::saved())
+}
+
+/// Replace all application settings; emits `settings_changed` once saved
+#[tauri::command]
+async fn update_settings(
+    settings: AppSettings,
+    window: tauri::Window,
+) -> Result<AppSettings, ControlError> {
+    let settings = settings::update(settings).map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.save_settings",
+    ))?;
+    events::emit(&window.app_handle(), "settings_changed", &settings);
+    Ok(settings)
+}
+
+/// List drops created on this device
+#[tauri::command]
+async fn list_drops() -> Result<Vec<DropRecord>, ControlError> {
+    let catalog = DropCatalog::load(&app_data_dir()?)?;
+    Ok(catalog.records().to_vec())
+}
+
+/// Drop uploads waiting in the background queue, with their retry state
+#[tauri::command]
+async fn list_pending_uploads() -> Result<Vec<PendingUpload>, ControlError> {
+    let pending = PendingUploads::load(&drop_cache_dir(&app_data_dir()?))
+        .map_err(ControlError::wrap("error.list_pending_uploads"))?;
+    Ok(pending.entries().to_vec())
+}
+
+/// Retry every queued upload now instead of waiting out its backoff
+#[tauri::command]
+async fn retry_pending_uploads() -> Result<(), ControlError> {
+    let mut pending = PendingUploads::load(&drop_cache_dir(&app_data_dir()?))
+        .map_err(ControlError::wrap("error.retry_pending_uploads"))?;
+    pending.reset_backoff();
+    pending
+        .save()
+        .map_err(ControlError::wrap("error.retry_pending_uploads"))?;
+    upload_schedule::wake_worker();
+    Ok(())
+}
+
+/// Adopt content already on the IPFS node into the drop catalog and link it
+/// under `/control/drops`
+#[tauri::command]
+async fn import_existing_cid(
+    cid: String,
+    name: Option<String>,
+) -> Result<DropRecord, ControlError> {
+    let result = mfs::import_existing_cid(&app_data_dir()?, &cid, name.as_deref())
+        .await
+        .map_err(ControlError::wrap("error.import_existing_cid"));
+    audit_drop(
+        AuditAction::Import,
+        &cid,
+        serde_json::json!({ "name": name }),
+        &result,
+    );
+    result
+}
+
+/// Audit log of drop actions, for one drop (CID or versioned drop ID) or all
+#[tauri::command]
+async fn get_drop_audit_log(drop_id: Option<String>) -> Result<AuditLog, ControlError> {
+    audit::read_log(&app_data_dir()?, drop_id.as_deref())
+        .map_err(ControlError::wrap("error.load_drop_audit_log"))
+}
+
+/// Write the audit log as readable JSON. Returns the number of entries written.
+#[tauri::command]
+async fn export_drop_audit_log(
+    path: String,
+    drop_id: Option<String>,
+) -> Result<usize, ControlError> {
+    audit::export_log(&app_data_dir()?, drop_id.as_deref(), Path::new(&path))
+        .map_err(ControlError::wrap("error.export_drop_audit_log"))
+}
+
+/// Every key seen for contacts, or for one contact, and whether the log's
+/// hash chain is intact
+#[tauri::command]
+async fn get_key_log(
+    contact: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<KeyLog, ControlError> {
+    let identity = current_identity(&state)?;
+    key_log::read_log(&app_data_dir()?, &identity, contact.as_deref())
+        .map_err(ControlError::wrap("error.load_key_log"))
+}
+
+/// Write the key log as readable JSON. Returns the number of entries written.
+#[tauri::command]
+async fn export_key_log(
+    path: String,
+    contact: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<usize, ControlError> {
+    let identity = current_identity(&state)?;
+    key_log::export_log(
+        &app_data_dir()?,
+        &identity,
+        contact.as_deref(),
+        Path::new(&path),
+    )
+    .map_err(ControlError::wrap("error.export_key_log"))
+}
+
+/// Unpin a dead drop from the local IPFS node
+#[tauri::command]
+async fn unpin_drop(cid: String) -> Result<(), ControlError> {
+    let data_dir = app_data_dir()?;
+    let cache_dir = drop_cache_dir(&data_dir);
+    let mut catalog = DropCatalog::load(&data_dir)?;
+    let piece_cids = catalog.unshared_pieces(&cid);
+
+    let result = unpin_dead_drop(&cid, &piece_cids, Some(&cache_dir))
+        .await
+        .map_err(ControlError::wrap("error.unpin_dead_drop"));
+    audit_drop(
+        AuditAction::Unpin,
+        &cid,
+        serde_json::json!({ "pieces": piece_cids.len() }),
+        &result,
+    );
+    result?;
+
+    if let Some(record) = catalog.get_mut(&cid) {
+        record.pinned = false;
+        if let Err(e) = mfs::unlink_drop(record).await {
+            warn!("Failed to unlink {} from MFS: {}", cid, e);
+        }
+        catalog
+            .save()
+            .map_err(ControlError::wrap("error.update_drop_catalog"))?;
+    }
+
+    Ok(())
+}
+
+/// Unpin expired or old drops and optionally run IPFS repo GC
+#[tauri::command]
+async fn cleanup_drops(
+    max_age_secs: Option<u64>,
+    run_gc: bool,
+) -> Result<DropCleanupReport, ControlError> {
+    let data_dir = app_data_dir()?;
+    let cache_dir = drop_cache_dir(&data_dir);
+    let mut catalog = DropCatalog::load(&data_dir)?;
+
+    let report = cleanup_dead_drops(&mut catalog, max_age_secs, run_gc, Some(&cache_dir))
+        .await
+        .map_err(ControlError::wrap("error.clean_up_dead_drops"))?;
+    let params = serde_json::json!({ "cleanup": true, "max_age_secs": max_age_secs });
+    for cid in &report.unpinned {
+        audit::record(&data_dir, AuditAction::Unpin, cid, params.clone(), None);
+    }
+    for cid in &report.failed {
+        let error = Some("Cleanup could not unpin the drop".to_string());
+        audit::record(&data_dir, AuditAction::Unpin, cid, params.clone(), error);
+    }
+
+    catalog
+        .save()
+        .map_err(ControlError::wrap("error.update_drop_catalog"))?;
+
+    Ok(report)
+}
+
+/// Shutdown P2P actor
+#[tauri::command]
+async fn stop_ghost_mode(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), ControlError> {
+    let sender = state.stop_ghost_mode()?;
+    sender
+        .send(P2PCommand::Shutdown)
+        .await
+        .map_err(ControlError::wrap("error.stop_p2p"))?;
+    tray::ghost_mode_changed(&app, false);
+
+    Ok(())
+}
+
+/// Test IPFS connection
+#[tauri::command]
+async fn test_ipfs() -> Result<String, ControlError> {
+    let client = ipfs::client()?;
+    let version = health::ipfs_version(&client)
+        .await
+        .map_err(|e| ControlError::new(ErrorCode::NetworkUnavailable, format!("{:#}", e)))?;
+    Ok(i18n::t_args(
+        "status.ipfs_connected",
+        &[("version", &version)],
+    ))
+}
+
+/// IPFS node, gateways, pinning services, relays and bootstrap peers in one
+/// report. Served from cache unless `refresh` is set or it is stale.
+#[tauri::command]
+async fn get_infrastructure_health(
+    refresh: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<InfrastructureHealth, ControlError> {
+    let p2p = state.p2p_sender().ok();
+    health::infrastructure_health(p2p, refresh.unwrap_or(false))
+        .await
+        .map_err(ControlError::wrap("error.check_infrastructure_health"))
+}
+
+/// Identity, keys, caches and logs currently stored on disk
+#[tauri::command]
+async fn list_stored_secrets() -> Result<Vec<StoredSecret>, ControlError> {
+    secrets::list_stored_secrets(&app_data_dir()?)
+        .map_err(ControlError::wrap("error.list_stored_secrets"))
+}
+
+/// Delete one stored item. Deleting the identity needs its file name as
+/// confirmation and locks the session.
+#[tauri::command]
+async fn delete_stored_secret(
+    name: String,
+    confirmation: Option<String>,
+    app: tauri::AppHandle,
+) -> Result<(), ControlError> {
+    secrets::delete_stored_secret(&app_data_dir()?, &name, confirmation.as_deref()).map_err(
+        ControlError::wrap_as(ErrorCode::InvalidInput, "error.delete_stored_secret"),
+    )?;
+    if name == "identity.enc" {
+        lock_session(&app, "identity_deleted");
+    }
+    Ok(())
+}
+
+/// Local usage counters; empty unless usage statistics are enabled
+#[tauri::command]
+async fn get_usage_stats() -> Result<UsageStats, ControlError> {
+    usage::usage_stats().map_err(ControlError::wrap("error.load_usage_stats"))
+}
+
+#[tauri::command]
+async fn reset_usage_stats() -> Result<UsageStats, ControlError> {
+    usage::reset_usage_stats().map_err(ControlError::wrap("error.reset_usage_stats"))
+}
+
+/// Ask the update endpoint whether a newer signed release exists
+#[tauri::command]
+async fn check_for_updates() -> Result<UpdateCheck, ControlError> {
+    updater::check_for_updates()
+        .await
+        .map_err(ControlError::wrap_as(
+            ErrorCode::NetworkUnavailable,
+            "error.check_updates",
+        ))
+}
+
+/// Download and verify the newer release, emitting `update_progress`.
+/// Nothing is installed; the frontend shows the user where the file is.
+#[tauri::command]
+async fn download_update(window: tauri::Window) -> Result<DownloadedUpdate, ControlError> {
+    updater::download_update(&app_data_dir()?, |progress| {
+        let _ = window.emit("update_progress", progress);
+    })
+    .await
+    .map_err(ControlError::wrap_as(
+        ErrorCode::NetworkUnavailable,
+        "error.download_update",
+    ))
+}
+
+/// Get the managed Kubo settings
+#[tauri::command]
+async fn get_kubo_settings() -> Result<KuboSettings, ControlError> {
+    Ok(settings::kubo_settings())
+}
+
+/// Update the managed Kubo settings; ports apply on the next start
+#[tauri::command]
+async fn set_kubo_settings(settings: KuboSettings) -> Result<KuboSettings, ControlError> {
+    settings::modify(|s| s.kubo = settings)
+        .map(|s| s.kubo)
+        .map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_kubo_settings",
+        ))
+}
+
+/// Whether the managed Kubo node is installed and answering
+#[tauri::command]
+async fn kubo_status() -> Result<KuboStatus, ControlError> {
+    Ok(kubo::status(&app_data_dir()?).await)
+}
+
+/// Switch to the managed Kubo node, downloading it first if needed
+/// (emitting `kubo_progress`), and start it
+#[tauri::command]
+async fn start_kubo(window: tauri::Window) -> Result<KuboStatus, ControlError> {
+    let data_dir = app_data_dir()?;
+    settings::modify(|s| s.kubo.managed = true).map_err(ControlError::wrap_as(
+        ErrorCode::InvalidInput,
+        "error.save_kubo_settings",
+    ))?;
+    kubo::install(&data_dir, |progress| {
+        let _ = window.emit("kubo_progress", progress);
+    })
+    .await
+    .map_err(ControlError::wrap_as(
+        ErrorCode::NetworkUnavailable,
+        "error.install_kubo",
+    ))?;
+    kubo::start(&data_dir)
+        .await
+        .map_err(ControlError::wrap("error.start_kubo"))?;
+    Ok(kubo::status(&data_dir).await)
+}
+
+/// Stop the managed Kubo node; with `disable` the configured IPFS API is used
+/// again and Kubo no longer starts with the app
+#[tauri::command]
+async fn stop_kubo(disable: Option<bool>) -> Result<KuboStatus, ControlError> {
+    kubo::stop()
+        .await
+        .map_err(ControlError::wrap("error.stop_kubo"))?;
+    if disable.unwrap_or(false) {
+        settings::modify(|s| s.kubo.managed = false).map_err(ControlError::wrap_as(
+            ErrorCode::InvalidInput,
+            "error.save_kubo_settings",
+        ))?;
+    }
+    Ok(kubo::status(&app_data_dir()?).await)
+}
+
+fn main() {
+    logging::init();
+    let context = tauri::generate_context!();
+
+    // Resolve storage from the real app identifier, then load settings before
+    // any drop or P2P traffic so the Tor proxy applies from the start
+    match storage::init(context.config()) {
+        Ok(data_dir) => {
+            if let Err(e) = settings::init(&data_dir) {
+                error!("Failed to load settings, network access is blocked: {}", e);
+            }
+            logging::start_file_logging(&data_dir);
+        }
+        Err(e) => error!("Failed to initialize storage: {}", e),
+    }
+
+    tauri::Builder::default()
+        .manage(AppState::new())
+        .setup(|app| {
+            app.manage(OperationManager::new(app.handle()));
+            tray::start_label_updater(app.handle());
+            match app_data_dir() {
+                Ok(data_dir) => {
+                    // Staged ciphertext from a crashed run is never resumed, only removed
+                    let staging_dir = settings::drop_settings().staging_dir(&data_dir);
+                    if let Err(e) = cleanup_staging(&staging_dir) {
+                        error!("Failed to clean staging directory: {}", e);
+                    }
+
+                    kubo::start_on_launch(data_dir.clone());
+                    start_usage_tracker(&data_dir);
+                    start_idle_watcher(app.handle());
+                    start_pin_monitor(app.handle(), data_dir.clone());
+                    start_recovery_reminders(app.handle(), data_dir.clone());
+                    start_sync_worker(app.handle(), data_dir.clone());
+                    start_health_prober(app.handle());
+                    start_watch_folder(app.handle(), data_dir.clone());
+                    start_backup_scheduler(app.handle(), data_dir.clone());
+                    start_dead_man_scheduler(app.handle(), data_dir.clone());
+                    start_retention_worker(app.handle(), data_dir.clone());
+                    start_upload_worker(app.handle(), data_dir);
+                }
+                Err(e) => warn!("Background workers disabled: {}", e),
+            }
+            Ok(())
+        })
+        .system_tray(tray::build())
+        .on_system_tray_event(tray::handle_event)
+        .on_window_event(|event| match event.event() {
+            // The window coming back after a notification is the closest thing
+            // to a click-through Tauri v1 offers
+            tauri::WindowEvent::Focused(true) => {
+                tray::clear_unread(&event.window().app_handle());
+                if let Some(target) = notifications::take_pending_activation() {
+                    events::emit(
+                        &event.window().app_handle(),
+                        "notification_activated",
+                        target,
+                    );
+                }
+            }
+            tauri::WindowEvent::CloseRequested { api, .. } => {
+                tray::close_requested(event.window(), api)
+            }
+            _ => {}
+        })
+        .invoke_handler(tauri::generate_handler![
+            init_identity,
+            unlock,
+            lock,
+            record_activity,
+            get_app_state,
+            get_events_since,
+            get_public_id,
+            start_ghost_mode,
+            send_ghost_message,
+            send_location,
+            start_live_location,
+            update_live_location,
+            stop_live_location,
+            list_live_locations,
+            create_message_drop,
+            open_message_link,
+            create_drop,
+            create_drop_from_bytes,
+            retrieve_drop_to_memory,
+            queue_create_drop,
+            queue_retrieve_drop,
+            list_operations,
+            get_operation,
+            cancel_operation,
+            set_operation_priority,
+            retrieve_drop,
+            stat_drop,
+            get_timelock_status,
+            request_escrow_release,
+            list_escrow_requests,
+            list_escrow_approvals,
+            answer_escrow_request,
+            retrieve_escrow_drop,
+            retrieve_drop_range,
+            open_drop_stream,
+            close_drop_stream,
+            open_sealed_shard,
+            reshare_drop,
+            update_drop,
+            retrieve_drop_version,
+            get_drop_versions,
+            verify_drop,
+            repair_drop,
+            check_pin_health,
+            get_pin_health_settings,
+            set_pin_health_settings,
+            get_watch_folder_settings,
+            set_watch_folder_settings,
+            get_away_ping_settings,
+            set_away_ping_settings,
+            send_test_away_ping,
+            list_plugins,
+            reload_plugins,
+            get_plugin_settings,
+            set_plugin_settings,
+            get_webhook_settings,
+            set_webhook_settings,
+            send_test_webhook,
+            get_backup_settings,
+            set_backup_settings,
+            list_backups,
+            run_backup_now,
+            list_drops,
+            import_existing_cid,
+            get_drop_audit_log,
+            export_drop_audit_log,
+            get_key_log,
+            export_key_log,
+            list_pending_uploads,
+            retry_pending_uploads,
+            unpin_drop,
+            cleanup_drops,
+            get_drop_settings,
+            set_drop_settings,
+            run_benchmark,
+            verify_conformance,
+            export_drop_car,
+            import_drop_car,
+            get_network_settings,
+            set_network_settings,
+            list_locales,
+            get_message_catalog,
+            get_settings,
+            update_settings,
+            get_storage_paths,
+            set_data_dir,
+            get_recent_logs,
+            export_diagnostics,
+            copy_sensitive,
+            export_profile,
+            import_profile,
+            get_recovery_status,
+            export_recovery_mnemonic,
+            get_age_recipient,
+            export_age_identity,
+            export_recovery_shards,
+            verify_backup,
+            list_contacts,
+            save_contact,
+            remove_contact,
+            set_contact_pgp_key,
+            get_contact_appearance,
+            set_contact_appearance,
+            list_proofs,
+            add_proof,
+            remove_proof,
+            check_proofs,
+            verify_contact_proofs,
+            export_contact_card,
+            import_contact_card,
+            import_contacts,
+            list_contact_requests,
+            send_contact_request,
+            respond_contact_request,
+            send_introduction,
+            list_introductions,
+            respond_introduction,
+            vouch_for_contact,
+            list_attestations,
+            get_contact_trust,
+            get_trust_settings,
+            set_trust_settings,
+            get_location_settings,
+            set_location_settings,
+            get_no_traces_settings,
+            set_no_traces_settings,
+            get_no_traces_status,
+            list_groups,
+            save_group,
+            remove_group,
+            list_group_shards,
+            send_group_shards,
+            create_poll,
+            vote_poll,
+            close_poll,
+            list_polls,
+            get_poll,
+            forget_poll,
+            list_conversations,
+            mark_read,
+            list_view_once,
+            open_view_once_message,
+            confirm_view_once_displayed,
+            get_compliance_settings,
+            set_compliance_settings,
+            export_transcript,
+            verify_transcript,
+            list_canaries,
+            add_canary_contact,
+            create_canary_drop,
+            remove_canary,
+            list_canary_alerts,
+            get_canary_settings,
+            set_canary_settings,
+            search_messages,
+            get_search_settings,
+            set_search_settings,
+            get_retention_settings,
+            set_retention_settings,
+            apply_retention,
+            list_archives,
+            restore_archive,
+            delete_archive,
+            get_history_usage,
+            list_devices,
+            approve_device,
+            revoke_device,
+            get_remote_wipe_settings,
+            set_remote_wipe_settings,
+            send_remote_wipe,
+            get_pending_remote_wipe,
+            cancel_remote_wipe,
+            create_dead_man_switch,
+            list_dead_man_switches,
+            check_in_dead_man_switch,
+            disarm_dead_man_switch,
+            sync_now,
+            stop_ghost_mode,
+            test_ipfs,
+            get_infrastructure_health,
+            check_for_updates,
+            download_update,
+            get_kubo_settings,
+            set_kubo_settings,
+            kubo_status,
+            start_kubo,
+            stop_kubo,
+            list_stored_secrets,
+            delete_stored_secret,
+            get_usage_stats,
+            reset_usage_stats,
+        ])
+        .build(context)
+        .expect("error while running tauri application")
+        .run(|_, event| {
+            // Kubo would otherwise outlive the app holding its repo lock
+            if let tauri::RunEvent::Exit = event {
+                if let Err(e) = tauri::async_runtime::block_on(kubo::stop()) {
+                    error!("Failed to stop Kubo: {}", e);
+                }
+            }
+        });
 }
@@ -0,0 +1,181 @@
+use crate::crypto::{FileCipher, SessionKey};
+use crate::dead_drop::{chunk_prefix, parse_chunk_prefix, DropOptions, CHUNK_OVERHEAD};
+use anyhow::{Context, Result};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{IoSlice, Write};
+use std::path::Path;
+use tracing::info;
+
+/// Below this, buffered reads are as fast and mapping isn't worth the setup
+pub const MMAP_MIN_LEN: u64 = 64 * 1024 * 1024;
+
+/// Whether a file of `len` bytes should take the mapped path
+pub fn use_mmap(options: &DropOptions, len: u64) -> bool {
+    options.mmap && len >= MMAP_MIN_LEN
+}
+
+/// Encrypt a mapped file into the same chunk format as `stream_encrypt_file`.
+/// Chunks are sealed straight from the page cache and written with their
+/// length prefix in one vectored write to a preallocated output.
+pub fn encrypt_mapped(
+    input: &File,
+    output_path: &Path,
+    session_key: &SessionKey,
+    options: &DropOptions,
+) -> Result<u64> {
+    // SAFETY: the map is read-only and dropped before returning. A file
+    // truncated by another process while mapped can fault; that risk is why
+    // this path is opt-in.
+    let map = unsafe { Mmap::map(input) }.context("Failed to map input file")?;
+    let total_bytes = map.len() as u64;
+    let chunks = total_bytes.div_ceil(options.chunk_size as u64);
+    let expected = total_bytes + chunks * CHUNK_OVERHEAD;
+
+    let mut output = File::create(output_path).context("Failed to create output file")?;
+    output
+        .set_len(expected)
+        .context("Failed to preallocate output file")?;
+
+    let mut total_read = 0u64;
+    let mut total_encrypted = 0u64;
+    for chunk in map.chunks(options.chunk_size) {
+        let encrypted_chunk = session_key
+            .encrypt_chunk(options.cipher, chunk)
+            .context("Failed to encrypt chunk")?;
+        let prefix = chunk_prefix(options.cipher, encrypted_chunk.len());
+        write_prefixed(&mut output, &prefix, &encrypted_chunk)
+            .context("Failed to write encrypted chunk")?;
+
+        total_read += chunk.len() as u64;
+        total_encrypted += 4 + encrypted_chunk.len() as u64;
+        options.report("encrypting", total_read, total_bytes);
+    }
+
+    if total_encrypted != expected {
+        output.set_len(total_encrypted)?;
+    }
+    output.flush().context("Failed to flush output")?;
+    info!("Encrypted {} bytes from a mapped file", total_read);
+    Ok(total_encrypted)
+}
+
+/// Decrypt a mapped ciphertext file into a preallocated output, reading each
+/// chunk in place instead of copying it into a buffer first
+pub fn decrypt_mapped(
+    input_path: &Path,
+    output_path: &str,
+    session_key: &SessionKey,
+    options: &DropOptions,
+) -> Result<u64> {
+    let input = File::open(input_path).context("Failed to open encrypted file")?;
+    // SAFETY: as in `encrypt_mapped`; staged ciphertext is ours alone
+    let map = unsafe { Mmap::map(&input) }.context("Failed to map encrypted file")?;
+    let total_bytes = map.len() as u64;
+    let frames = chunk_frames(&map)?;
+    let plaintext_len = total_bytes - frames.len() as u64 * CHUNK_OVERHEAD;
+
+    let mut output = File::create(output_path).context("Failed to create output file")?;
+    output
+        .set_len(plaintext_len)
+        .context("Failed to preallocate output file")?;
+
+    let mut total_decrypted = 0u64;
+    for (cipher, start, end) in frames {
+        let decrypted_chunk = session_key
+            .decrypt_chunk(cipher, &map[start..end])
+            .context("Failed to decrypt chunk")?;
+        output
+            .write_all(&decrypted_chunk)
+            .context("Failed to write decrypted chunk")?;
+        total_decrypted += decrypted_chunk.len() as u64;
+        options.report("decrypting", end as u64, total_bytes);
+    }
+
+    output.flush().context("Failed to flush output")?;
+    Ok(total_decrypted)
+}
+
+/// Byte ranges of the sealed chunks, checked up front so the output can be
+/// sized exactly before anything is decrypted
+fn chunk_frames(data: &[u8]) -> Result<Vec<(FileCipher, usize, usize)>> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let prefix = data
+            .get(offset..offset + 4)
+            .context("Truncated chunk size")?;
+        let (cipher, chunk_size) = parse_chunk_prefix(prefix.try_into().unwrap())?;
+        if (chunk_size as u64) < CHUNK_OVERHEAD - 4 {
+            anyhow::bail!("Encrypted chunk too small: {} bytes", chunk_size);
+        }
+        let start = offset + 4;
+        let end = start + chunk_size;
+        if end > data.len() {
+            anyhow::bail!("Truncated encrypted chunk");
+        }
+        frames.push((cipher, start, end));
+        offset = end;
+    }
+    Ok(frames)
+}
+
+/// Write a length prefix and its chunk with one syscall where possible
+fn write_prefixed(output: &mut File, prefix: &[u8], body: &[u8]) -> std::io::Result<()> {
+    let written = output.write_vectored(&[IoSlice::new(prefix), IoSlice::new(body)])?;
+    // Short writes are rare on local files; finish them plainly
+    if written < prefix.len() {
+        output.write_all(&prefix[written..])?;
+        output.write_all(body)
+    } else {
+        output.write_all(&body[written - prefix.len()..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dead_drop::{stream_decrypt_file, stream_encrypt_file};
+    use proptest::prelude::*;
+
+    #[test]
+    fn mapped_and_streamed_formats_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let plaintext = dir.path().join("plain");
+        let mapped = dir.path().join("mapped");
+        let streamed = dir.path().join("streamed");
+        let restored = dir.path().join("restored");
+        let data: Vec<u8> = (0..3 * 1024 * 1024 + 17).map(|i| i as u8).collect();
+        std::fs::write(&plaintext, &data).unwrap();
+
+        let key = SessionKey::generate();
+        let options = DropOptions {
+            chunk_size: 1024 * 1024,
+            ..DropOptions::default()
+        };
+        let mapped_len =
+            encrypt_mapped(&File::open(&plaintext).unwrap(), &mapped, &key, &options).unwrap();
+        let streamed_len =
+            stream_encrypt_file(plaintext.to_str().unwrap(), &streamed, &key, &options).unwrap();
+        assert_eq!(mapped_len, streamed_len);
+        assert_eq!(std::fs::metadata(&mapped).unwrap().len(), mapped_len);
+
+        // Each path reads what the other wrote
+        let restored_str = restored.to_str().unwrap();
+        stream_decrypt_file(&mapped, restored_str, &key, &options).unwrap();
+        assert_eq!(std::fs::read(&restored).unwrap(), data);
+        decrypt_mapped(&streamed, restored_str, &key, &options).unwrap();
+        assert_eq!(std::fs::read(&restored).unwrap(), data);
+    }
+
+    proptest! {
+        #[test]
+        fn frames_of_garbage_stay_in_bounds(data in proptest::collection::vec(any::<u8>(), 0..4096)) {
+            if let Ok(frames) = chunk_frames(&data) {
+                for (_, start, end) in frames {
+                    prop_assert!(start <= end && end <= data.len());
+                }
+            }
+        }
+    }
+}
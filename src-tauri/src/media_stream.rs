@@ -0,0 +1,285 @@
+use crate::chunk_index::{read_range, ChunkIndex};
+use crate::crypto::SessionKey;
+use crate::daemon::constant_time_eq;
+use crate::dead_drop::DropOptions;
+use crate::loopback_http::read_head;
+use anyhow::{Context, Result};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+const MAX_CONTENT_TYPE_LEN: usize = 128;
+/// Opening another stream past this closes the oldest one
+const MAX_STREAMS: usize = 8;
+
+/// A drop served on the local stream endpoint
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DropStream {
+    /// Loopback URL a media element can play and seek in
+    pub url: String,
+    /// Closes the stream again
+    pub token: String,
+    pub size: u64,
+    pub content_type: String,
+}
+
+/// What a token unlocks; the key lives here until the stream is closed
+struct Source {
+    token: String,
+    index: ChunkIndex,
+    session_key: SessionKey,
+    content_type: String,
+    options: DropOptions,
+}
+
+static SOURCES: Mutex<Vec<Arc<Source>>> = Mutex::new(Vec::new());
+static SERVER_PORT: tokio::sync::Mutex<Option<u16>> = tokio::sync::Mutex::const_new(None);
+
+/// Serve an indexed drop's plaintext at a random URL on 127.0.0.1. Frames are
+/// fetched, verified and decrypted per request, so nothing is written to disk.
+pub async fn open_stream(
+    index: ChunkIndex,
+    session_key: SessionKey,
+    content_type: Option<&str>,
+    options: DropOptions,
+) -> Result<DropStream> {
+    let content_type = match content_type.map(str::trim) {
+        Some(value) if !value.is_empty() => {
+            if value.len() > MAX_CONTENT_TYPE_LEN
+                || !value.chars().all(|c| c.is_ascii_graphic() || c == ' ')
+            {
+                anyhow::bail!("Invalid content type");
+            }
+            value.to_string()
+        }
+        _ => DEFAULT_CONTENT_TYPE.to_string(),
+    };
+    let port = ensure_server().await?;
+
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    let size = index.plaintext_len;
+
+    let mut sources = SOURCES.lock().unwrap();
+    if sources.len() >= MAX_STREAMS {
+        sources.remove(0);
+    }
+    sources.push(Arc::new(Source {
+        token: token.clone(),
+        index,
+        session_key,
+        content_type: content_type.clone(),
+        options: DropOptions {
+            progress: None,
+            ..options
+        },
+    }));
+
+    Ok(DropStream {
+        url: format!("http://127.0.0.1:{}/stream/{}", port, token),
+        token,
+        size,
+        content_type,
+    })
+}
+
+/// Stop serving a stream; requests already running finish their current range
+pub fn close_stream(token: &str) -> bool {
+    let mut sources = SOURCES.lock().unwrap();
+    let before = sources.len();
+    sources.retain(|s| !constant_time_eq(s.token.as_bytes(), token.as_bytes()));
+    sources.len() != before
+}
+
+/// Forget every stream and its key, e.g. when the session locks
+pub fn close_all() {
+    SOURCES.lock().unwrap().clear();
+}
+
+fn find_source(token: &str) -> Option<Arc<Source>> {
+    SOURCES
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|s| constant_time_eq(s.token.as_bytes(), token.as_bytes()))
+        .cloned()
+}
+
+/// Start the loopback server the first time a stream is opened
+async fn ensure_server() -> Result<u16> {
+    let mut port = SERVER_PORT.lock().await;
+    if let Some(port) = *port {
+        return Ok(port);
+    }
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("Failed to bind stream server")?;
+    let bound = listener.local_addr()?.port();
+    tauri::async_runtime::spawn(accept_loop(listener, bound));
+    info!("Stream server listening on 127.0.0.1:{}", bound);
+    *port = Some(bound);
+    Ok(bound)
+}
+
+async fn accept_loop(listener: TcpListener, port: u16) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = handle_connection(stream, port).await {
+                        warn!("Stream request failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Stream server failed to accept: {}", e),
+        }
+    }
+}
+
+/// One request per connection; players reconnect to seek anyway
+async fn handle_connection(mut stream: TcpStream, port: u16) -> Result<()> {
+    let Some(request) = read_head(&mut stream).await? else {
+        return respond(&mut stream, "400 Bad Request", &[]).await;
+    };
+    if !request.is_loopback_host(port) {
+        return respond(&mut stream, "403 Forbidden", &[]).await;
+    }
+    if request.method != "GET" && request.method != "HEAD" {
+        return respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            &[("Allow", "GET, HEAD")],
+        )
+        .await;
+    }
+    let source = request.path.strip_prefix("/stream/").and_then(find_source);
+    let Some(source) = source else {
+        return respond(&mut stream, "404 Not Found", &[]).await;
+    };
+
+    let size = source.index.plaintext_len;
+    let (status, start, end) = match request.header("range").and_then(|r| parse_range(r, size)) {
+        None => ("200 OK", 0, size),
+        Some(Some((start, end))) => ("206 Partial Content", start, end),
+        Some(None) => {
+            let unsatisfied = format!("bytes */{}", size);
+            return respond(
+                &mut stream,
+                "416 Range Not Satisfiable",
+                &[("Content-Range", &unsatisfied)],
+            )
+            .await;
+        }
+    };
+
+    let length = (end - start).to_string();
+    let content_range = format!("bytes {}-{}/{}", start, end.saturating_sub(1), size);
+    let mut headers = vec![
+        ("Content-Type", source.content_type.as_str()),
+        ("Content-Length", length.as_str()),
+        ("Accept-Ranges", "bytes"),
+    ];
+    if status != "200 OK" {
+        headers.push(("Content-Range", &content_range));
+    }
+    write_head(&mut stream, status, &headers).await?;
+    if request.method == "HEAD" {
+        return Ok(());
+    }
+
+    // A chunk at a time, so memory stays flat however large the range is
+    let chunk_size = source.index.chunk_size as u64;
+    let mut position = start;
+    while position < end {
+        let piece_end = ((position / chunk_size + 1) * chunk_size).min(end);
+        let mut piece = read_range(
+            &source.index,
+            &source.session_key,
+            position,
+            piece_end - position,
+            &source.options,
+        )
+        .await?;
+        let written = stream.write_all(&piece).await;
+        piece.zeroize();
+        if written.is_err() {
+            // The player seeked away or was closed
+            return Ok(());
+        }
+        position = piece_end;
+    }
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Parse a single `bytes=` range into `start..end`. `None` means serve the
+/// whole body (no usable range header); `Some(None)` means unsatisfiable.
+fn parse_range(header: &str, size: u64) -> Option<Option<(u64, u64)>> {
+    let spec = header.strip_prefix("bytes=")?.trim();
+    // Multiple ranges are allowed to be answered with the full body
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let range = match (first.trim(), last.trim()) {
+        ("", "") => return None,
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (suffix > 0 && size > 0).then(|| (size - suffix.min(size), size))
+        }
+        (start, "") => {
+            let start: u64 = start.parse().ok()?;
+            (start < size).then_some((start, size))
+        }
+        (start, last) => {
+            let start: u64 = start.parse().ok()?;
+            let last: u64 = last.parse().ok()?;
+            if last < start {
+                return None;
+            }
+            (start < size).then(|| (start, (last + 1).min(size)))
+        }
+    };
+    Some(range)
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, headers: &[(&str, &str)]) -> Result<()> {
+    let mut headers = headers.to_vec();
+    headers.push(("Content-Length", "0"));
+    write_head(stream, status, &headers).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn write_head(stream: &mut TcpStream, status: &str, headers: &[(&str, &str)]) -> Result<()> {
+    let mut head = format!("HTTP/1.1 {}\r\n", status);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("Cache-Control: no-store\r\nConnection: close\r\n\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Some((0, 100))));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(Some((900, 1000))));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Some((900, 1000))));
+        assert_eq!(parse_range("bytes=990-2000", 1000), Some(Some((990, 1000))));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(None));
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), None);
+        assert_eq!(parse_range("bytes=9-1", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+    }
+}
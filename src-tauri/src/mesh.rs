@@ -0,0 +1,766 @@
+use crate::catalog::now_secs;
+use crate::crypto::{decode_public_key, Identity, SessionKey};
+use crate::mailbox::{self, envelope_id, Envelope};
+use crate::no_traces;
+use crate::settings;
+use crate::wire;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::request_response;
+use libp2p::{PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+pub const PROTOCOL: StreamProtocol = StreamProtocol::new("/deaddrop/mesh/1.0.0");
+const MESH_FILE: &str = "mesh.enc";
+/// Across all recipients; the oldest envelopes go first
+const MAX_ENVELOPES: usize = 500;
+const MAX_CARRY_BYTES: u64 = 256 * 1024 * 1024;
+/// Envelopes per response
+const PAGE_SIZE: usize = 50;
+
+/// The outbox of the running P2P actor, opened with its identity
+static OUTBOX: Mutex<Option<MeshOutbox>> = Mutex::new(None);
+
+/// Offline delivery between trusted devices on the same local network
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MeshSettings {
+    pub enabled: bool,
+    /// Public IDs we exchange envelopes with when they are nearby
+    pub contacts: Vec<String>,
+    /// How long an envelope waits for its recipient to come near
+    pub ttl_hours: u32,
+    /// Also queue envelopes for people who aren't mesh contacts, and hand
+    /// them to trusted devices that carry for others
+    pub use_carriers: bool,
+    pub carry: CarrySettings,
+}
+
+impl Default for MeshSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            contacts: Vec::new(),
+            ttl_hours: 72,
+            use_carriers: false,
+            carry: CarrySettings::default(),
+        }
+    }
+}
+
+/// Carry mode: hold envelopes that trusted devices hand us for third
+/// parties, and deliver them once the recipient or one of their mailboxes
+/// is reachable. Carried envelopes are sealed for their recipient; we only
+/// learn who they are for and how big they are.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CarrySettings {
+    pub enabled: bool,
+    /// Total size of the envelopes carried at once
+    pub max_bytes: u64,
+    pub max_envelopes: usize,
+    /// How long a carried envelope is kept before it is dropped
+    pub ttl_hours: u32,
+}
+
+impl Default for CarrySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_bytes: 16 * 1024 * 1024,
+            max_envelopes: 200,
+            ttl_hours: 48,
+        }
+    }
+}
+
+impl MeshSettings {
+    pub fn validate(&self) -> Result<()> {
+        for contact in &self.contacts {
+            decode_public_key(contact)
+                .with_context(|| format!("Invalid mesh contact: {}", contact))?;
+        }
+        if !(1..=720).contains(&self.ttl_hours) {
+            anyhow::bail!("Mesh envelopes must live between 1 and 720 hours");
+        }
+        let carry = &self.carry;
+        if carry.max_bytes > MAX_CARRY_BYTES {
+            anyhow::bail!("Carry quota can be at most {} MiB", MAX_CARRY_BYTES >> 20);
+        }
+        if !(1..=MAX_ENVELOPES).contains(&carry.max_envelopes) {
+            anyhow::bail!(
+                "Carry quota must be between 1 and {} envelopes",
+                MAX_ENVELOPES
+            );
+        }
+        if !(1..=720).contains(&carry.ttl_hours) {
+            anyhow::bail!("Carried envelopes must live between 1 and 720 hours");
+        }
+        Ok(())
+    }
+
+    pub fn trusts(&self, public_id: &str) -> bool {
+        self.enabled && self.contacts.iter().any(|c| c == public_id)
+    }
+
+    /// Whether an envelope for `recipient` that no peer took is kept for
+    /// the mesh
+    fn queues_for(&self, recipient: &str) -> bool {
+        self.trusts(recipient) || (self.enabled && self.use_carriers && !self.contacts.is_empty())
+    }
+
+    fn carries(&self) -> bool {
+        self.enabled && self.carry.enabled
+    }
+}
+
+/// One length-prefixed frame; the mesh protocol only needs a link that can
+/// move these
+#[derive(Debug, Clone)]
+pub struct MeshFrame(pub Vec<u8>);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum MeshRequest {
+    /// Ask for what is queued for `public_id`. Only they can open the reply.
+    Hello {
+        public_id: String,
+        /// They carry envelopes for others
+        #[serde(default)]
+        carry: bool,
+    },
+    /// Sealed `MeshAck` from the recipient, hex: delete these, send the next page
+    Ack { sealed: String },
+}
+
+#[derive(Serialize, Deserialize)]
+struct MeshAck {
+    /// Every envelope of the last page: those for us are delivered, those
+    /// for others were taken or declined
+    ids: Vec<String>,
+    #[serde(default)]
+    carry: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+enum MeshResponse {
+    /// Sealed list of `Handover`s for whoever asked, hex
+    Envelopes {
+        sealed: String,
+    },
+    Refused,
+}
+
+/// A local link that carries mesh frames to nearby devices. LAN is libp2p
+/// request-response over mDNS; a Bluetooth LE link only needs to move the
+/// same frames and hand responses to `received`.
+pub trait MeshLink {
+    type Peer: Display;
+
+    /// Shown in logs, e.g. "lan"
+    fn name(&self) -> &'static str;
+
+    fn request(&mut self, peer: &Self::Peer, frame: MeshFrame);
+}
+
+impl MeshLink for request_response::Behaviour<MeshCodec> {
+    type Peer = PeerId;
+
+    fn name(&self) -> &'static str {
+        "lan"
+    }
+
+    fn request(&mut self, peer: &PeerId, frame: MeshFrame) {
+        self.send_request(peer, frame);
+    }
+}
+
+/// Length-prefixed frames over a request-response stream
+#[derive(Debug, Clone, Default)]
+pub struct MeshCodec;
+
+#[async_trait]
+impl request_response::Codec for MeshCodec {
+    type Protocol = StreamProtocol;
+    type Request = MeshFrame;
+    type Response = MeshFrame;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<MeshFrame>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        mailbox::read_frame(io).await.map(MeshFrame)
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<MeshFrame>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        mailbox::read_frame(io).await.map(MeshFrame)
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        MeshFrame(data): MeshFrame,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        mailbox::write_frame(io, &data).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        MeshFrame(data): MeshFrame,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        mailbox::write_frame(io, &data).await
+    }
+}
+
+pub fn behaviour() -> request_response::Behaviour<MeshCodec> {
+    request_response::Behaviour::new(
+        [(PROTOCOL, request_response::ProtocolSupport::Full)],
+        request_response::Config::default(),
+    )
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct QueuedEnvelope {
+    recipient: String,
+    envelope: Envelope,
+    /// Handed to us to carry for someone else
+    #[serde(default)]
+    carried: bool,
+    /// Carriers it was handed to already
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    carriers: Vec<String>,
+}
+
+/// An envelope as handed from one device to another
+#[derive(Serialize, Deserialize, Clone)]
+struct Handover {
+    recipient: String,
+    envelope: Envelope,
+}
+
+/// Envelopes no peer took, waiting for their recipient to come near.
+/// Encrypted with the local identity.
+pub struct MeshOutbox {
+    path: PathBuf,
+    storage_key: SessionKey,
+    queued: Vec<QueuedEnvelope>,
+}
+
+impl MeshOutbox {
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(MESH_FILE);
+        let storage_key = identity.storage_key(b"mesh");
+
+        let queued = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read mesh outbox")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt mesh outbox")?;
+            let queued = serde_json::from_slice(&json);
+            json.zeroize();
+            queued.context("Failed to parse mesh outbox")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            queued,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        if self.queued.is_empty() && !no_traces::file_exists(&self.path) {
+            return Ok(());
+        }
+        let mut json = serde_json::to_vec(&self.queued)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write mesh outbox")?;
+        Ok(())
+    }
+
+    fn push(&mut self, recipient: &str, data: &[u8]) {
+        let envelope = Envelope {
+            id: envelope_id(data),
+            data: hex::encode(data),
+            received_at: now_secs(),
+        };
+        self.insert(recipient, envelope, false);
+    }
+
+    fn insert(&mut self, recipient: &str, envelope: Envelope, carried: bool) {
+        if self.queued.iter().any(|q| q.envelope.id == envelope.id) {
+            return;
+        }
+        if self.queued.len() >= MAX_ENVELOPES {
+            self.queued.remove(0);
+        }
+        self.queued.push(QueuedEnvelope {
+            recipient: recipient.to_string(),
+            envelope,
+            carried,
+            carriers: Vec::new(),
+        });
+    }
+
+    /// Take an envelope to carry for someone else, within the quota
+    fn carry(&mut self, carry: &CarrySettings, handover: Handover) -> bool {
+        let carried = self.queued.iter().filter(|q| q.carried);
+        let (count, bytes) = carried.fold((0, 0), |(count, bytes), q| {
+            (count + 1, bytes + q.envelope.data.len() as u64 / 2)
+        });
+        let size = handover.envelope.data.len() as u64 / 2;
+        if count >= carry.max_envelopes || bytes + size > carry.max_bytes {
+            return false;
+        }
+        let envelope = Envelope {
+            received_at: now_secs(),
+            ..handover.envelope
+        };
+        self.insert(&handover.recipient, envelope, true);
+        true
+    }
+
+    fn waiting_for(&self, recipient: &str) -> bool {
+        self.queued
+            .iter()
+            .any(|q| !q.carried && q.recipient == recipient)
+    }
+
+    /// Drop expired envelopes, those we no longer queue for and carried
+    /// ones once carry mode is off; true if any were
+    fn prune(&mut self, settings: &MeshSettings) -> bool {
+        let now = now_secs();
+        let cutoff = now.saturating_sub(settings.ttl_hours as u64 * 60 * 60);
+        let carry_cutoff = now.saturating_sub(settings.carry.ttl_hours as u64 * 60 * 60);
+        let before = self.queued.len();
+        self.queued.retain(|q| {
+            if q.carried {
+                settings.carries() && q.envelope.received_at >= carry_cutoff
+            } else {
+                settings.queues_for(&q.recipient) && q.envelope.received_at >= cutoff
+            }
+        });
+        self.queued.len() != before
+    }
+
+    /// Serve a nearby device asking for its envelopes
+    fn answer(
+        &mut self,
+        identity: &Identity,
+        settings: &MeshSettings,
+        frame: &[u8],
+    ) -> Result<MeshFrame> {
+        let (requester, carry) = match wire::decode::<MeshRequest>(frame)? {
+            MeshRequest::Hello { public_id, carry } => (public_id, carry),
+            MeshRequest::Ack { sealed } => {
+                let sealed = hex::decode(sealed).context("Invalid mesh ack")?;
+                let (requester_pk, ack) = mailbox::open::<MeshAck>(identity, &sealed)?;
+                let requester = bs58::encode(requester_pk.as_bytes()).into_string();
+                self.acknowledged(&requester, &ack.ids)?;
+                (requester, ack.carry)
+            }
+        };
+
+        // Their own envelopes if we trust them or carried them; envelopes
+        // for others only if they carry and we hand off to carriers. What
+        // we carry ourselves goes only to its recipient.
+        let trusted = settings.trusts(&requester);
+        let hand_off = trusted && carry && settings.use_carriers;
+        let handed: Vec<Handover> = self
+            .queued
+            .iter()
+            .filter(|q| {
+                if q.recipient == requester {
+                    trusted || q.carried
+                } else {
+                    hand_off && !q.carried && !q.carriers.contains(&requester)
+                }
+            })
+            .take(PAGE_SIZE)
+            .map(|q| Handover {
+                recipient: q.recipient.clone(),
+                envelope: q.envelope.clone(),
+            })
+            .collect();
+
+        let response = if trusted || !handed.is_empty() {
+            let requester_pk = decode_public_key(&requester)?;
+            let sealed = mailbox::seal(identity, &requester_pk, &handed)?;
+            MeshResponse::Envelopes {
+                sealed: hex::encode(sealed),
+            }
+        } else {
+            MeshResponse::Refused
+        };
+        Ok(MeshFrame(wire::encode(&response)?))
+    }
+
+    /// Envelopes for `requester` are delivered and deleted; those for
+    /// others aren't offered to them again
+    fn acknowledged(&mut self, requester: &str, ids: &[String]) -> Result<()> {
+        let before = self.queued.len();
+        self.queued
+            .retain(|q| q.recipient != requester || !ids.contains(&q.envelope.id));
+        let delivered = before - self.queued.len();
+        let mut handed = 0;
+        for q in &mut self.queued {
+            if ids.contains(&q.envelope.id) && !q.carriers.iter().any(|c| c == requester) {
+                q.carriers.push(requester.to_string());
+                handed += 1;
+            }
+        }
+        if delivered + handed > 0 {
+            info!(
+                "Mesh: {} envelopes delivered to {}, {} handed to carry",
+                delivered, requester, handed
+            );
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Publish carried envelopes with `publish`, dropping those it took
+    fn deliver_carried(&mut self, mut publish: impl FnMut(&str, &[u8]) -> bool) -> usize {
+        let before = self.queued.len();
+        self.queued.retain(|q| {
+            if !q.carried {
+                return true;
+            }
+            match hex::decode(&q.envelope.data) {
+                Ok(data) => !publish(&q.recipient, &data),
+                Err(_) => false,
+            }
+        });
+        before - self.queued.len()
+    }
+}
+
+/// Take over the outbox for the P2P actor's identity
+pub fn open(data_dir: &Path, identity: &Identity) -> Result<()> {
+    let outbox = MeshOutbox::load(data_dir, identity)?;
+    *OUTBOX.lock().unwrap() = Some(outbox);
+    Ok(())
+}
+
+/// Forget the outbox and its key when the actor stops
+pub fn close() {
+    OUTBOX.lock().unwrap().take();
+}
+
+/// Queue an envelope no peer took for when its recipient, or a carrier,
+/// comes near. Returns false if the mesh doesn't take it.
+pub fn queue(recipient: &str, data: &[u8]) -> bool {
+    if !settings::p2p_settings().mesh.queues_for(recipient) {
+        return false;
+    }
+    let mut outbox = OUTBOX.lock().unwrap();
+    let Some(outbox) = outbox.as_mut() else {
+        return false;
+    };
+    outbox.push(recipient, data);
+    if let Err(e) = outbox.save() {
+        warn!("Failed to save mesh outbox: {}", e);
+    }
+    true
+}
+
+/// Whether anything for `recipient` is still waiting in the outbox
+pub fn waiting_for(recipient: &str) -> bool {
+    OUTBOX
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|outbox| outbox.waiting_for(recipient))
+}
+
+pub fn prune() {
+    let settings = settings::p2p_settings().mesh;
+    if let Some(outbox) = OUTBOX.lock().unwrap().as_mut() {
+        if outbox.prune(&settings) {
+            if let Err(e) = outbox.save() {
+                warn!("Failed to save mesh outbox: {}", e);
+            }
+        }
+    }
+}
+
+/// Hand carried envelopes to the network. `publish` returns whether a peer
+/// for the recipient's inbox (them or one of their mailboxes) took it.
+pub fn deliver_carried(publish: impl FnMut(&str, &[u8]) -> bool) {
+    if let Some(outbox) = OUTBOX.lock().unwrap().as_mut() {
+        let delivered = outbox.deliver_carried(publish);
+        if delivered > 0 {
+            info!("Mesh: delivered {} carried envelopes", delivered);
+            if let Err(e) = outbox.save() {
+                warn!("Failed to save mesh outbox: {}", e);
+            }
+        }
+    }
+}
+
+/// Ask a nearby device for what it holds for us
+pub fn hello<L: MeshLink>(link: &mut L, peer: &L::Peer, identity: &Identity) -> Result<()> {
+    let request = MeshRequest::Hello {
+        public_id: identity.public_id(),
+        carry: settings::p2p_settings().mesh.carries(),
+    };
+    link.request(peer, MeshFrame(wire::encode(&request)?));
+    Ok(())
+}
+
+/// Answer a request from a nearby device
+pub fn answer(identity: &Identity, frame: &MeshFrame) -> Result<MeshFrame> {
+    let settings = settings::p2p_settings().mesh;
+    let mut outbox = OUTBOX.lock().unwrap();
+    let outbox = outbox.as_mut().context("Mesh is not running")?;
+    outbox.answer(identity, &settings, &frame.0)
+}
+
+/// Open a response from a nearby device, acknowledging what it sent and
+/// asking for more. Returns the sealed P2P messages for us, which are only
+/// taken from trusted mesh contacts; envelopes for others are carried.
+pub fn received<L: MeshLink>(
+    link: &mut L,
+    peer: &L::Peer,
+    identity: &Identity,
+    frame: &MeshFrame,
+) -> Result<Vec<Vec<u8>>> {
+    let sealed = match wire::decode::<MeshResponse>(&frame.0)? {
+        MeshResponse::Envelopes { sealed } => {
+            hex::decode(sealed).context("Invalid mesh response")?
+        }
+        MeshResponse::Refused => return Ok(Vec::new()),
+    };
+    let (device_pk, handed) = mailbox::open::<Vec<Handover>>(identity, &sealed)?;
+    let device = bs58::encode(device_pk.as_bytes()).into_string();
+    let settings = settings::p2p_settings().mesh;
+    if !settings.trusts(&device) {
+        anyhow::bail!("Mesh response from {}, who is not a mesh contact", device);
+    }
+    if handed.is_empty() {
+        return Ok(Vec::new());
+    }
+    info!(
+        "Mesh: {} envelopes from {} over {} ({})",
+        handed.len(),
+        device,
+        link.name(),
+        peer
+    );
+
+    let own_id = identity.public_id();
+    let mut ids = Vec::new();
+    let mut payloads = Vec::new();
+    let mut carried = 0;
+    {
+        let mut outbox = OUTBOX.lock().unwrap();
+        for handover in handed {
+            ids.push(handover.envelope.id.clone());
+            if handover.recipient != own_id {
+                let outbox = outbox.as_mut().filter(|_| settings.carries());
+                if outbox.is_some_and(|outbox| outbox.carry(&settings.carry, handover)) {
+                    carried += 1;
+                }
+                continue;
+            }
+            match hex::decode(&handover.envelope.data) {
+                Ok(data) => payloads.push(data),
+                Err(e) => warn!("Skipping malformed mesh envelope: {}", e),
+            }
+        }
+        if carried > 0 {
+            info!("Mesh: carrying {} envelopes for others", carried);
+            if let Some(Err(e)) = outbox.as_ref().map(MeshOutbox::save) {
+                warn!("Failed to save mesh outbox: {}", e);
+            }
+        }
+    }
+    let ack = MeshAck {
+        ids,
+        carry: settings.carries(),
+    };
+    let ack = mailbox::seal(identity, &device_pk, &ack)?;
+    let request = MeshRequest::Ack {
+        sealed: hex::encode(ack),
+    };
+    link.request(peer, MeshFrame(wire::encode(&request)?));
+    Ok(payloads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(who: &Identity, carry: bool) -> Vec<u8> {
+        wire::encode(&MeshRequest::Hello {
+            public_id: who.public_id(),
+            carry,
+        })
+        .unwrap()
+    }
+
+    fn ack(from: &Identity, to: &Identity, handed: &[Handover], carry: bool) -> Vec<u8> {
+        let ids = handed.iter().map(|h| h.envelope.id.clone()).collect();
+        let ack = mailbox::seal(from, &to.public_key, &MeshAck { ids, carry }).unwrap();
+        wire::encode(&MeshRequest::Ack {
+            sealed: hex::encode(ack),
+        })
+        .unwrap()
+    }
+
+    /// Open a response as `who`; `None` if it was refused
+    fn handed(who: &Identity, frame: MeshFrame) -> Option<Vec<Handover>> {
+        match wire::decode::<MeshResponse>(&frame.0).unwrap() {
+            MeshResponse::Envelopes { sealed } => {
+                let sealed = hex::decode(sealed).unwrap();
+                Some(mailbox::open::<Vec<Handover>>(who, &sealed).unwrap().1)
+            }
+            MeshResponse::Refused => None,
+        }
+    }
+
+    #[test]
+    fn serves_only_the_recipient() {
+        let dir = tempfile::tempdir().unwrap();
+        let device = Identity::generate();
+        let recipient = Identity::generate();
+        let stranger = Identity::generate();
+        let settings = MeshSettings {
+            enabled: true,
+            contacts: vec![recipient.public_id()],
+            ..Default::default()
+        };
+
+        let mut outbox = MeshOutbox::load(dir.path(), &device).unwrap();
+        outbox.push(&recipient.public_id(), b"sealed envelope");
+        outbox.push(&recipient.public_id(), b"sealed envelope");
+        assert_eq!(outbox.queued.len(), 1);
+
+        let frame = outbox
+            .answer(&device, &settings, &hello(&recipient, false))
+            .unwrap();
+        let MeshResponse::Envelopes { sealed } = wire::decode::<MeshResponse>(&frame.0).unwrap()
+        else {
+            panic!("recipient was refused");
+        };
+        let sealed = hex::decode(sealed).unwrap();
+        let (_, envelopes) = mailbox::open::<Vec<Handover>>(&recipient, &sealed).unwrap();
+        assert_eq!(
+            hex::decode(&envelopes[0].envelope.data).unwrap(),
+            b"sealed envelope"
+        );
+        // Someone claiming the recipient's ID can't open the reply
+        assert!(mailbox::open::<Vec<Handover>>(&stranger, &sealed).is_err());
+
+        let frame = outbox
+            .answer(&device, &settings, &hello(&stranger, false))
+            .unwrap();
+        assert!(handed(&stranger, frame).is_none());
+
+        let frame = outbox
+            .answer(
+                &device,
+                &settings,
+                &ack(&recipient, &device, &envelopes, false),
+            )
+            .unwrap();
+        assert!(handed(&recipient, frame).unwrap().is_empty());
+        assert!(!outbox.waiting_for(&recipient.public_id()));
+    }
+
+    #[test]
+    fn carriers_take_envelopes_for_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let sender = Identity::generate();
+        let mule = Identity::generate();
+        let recipient = Identity::generate();
+        let sender_settings = MeshSettings {
+            enabled: true,
+            contacts: vec![mule.public_id()],
+            use_carriers: true,
+            ..Default::default()
+        };
+        let mule_settings = MeshSettings {
+            enabled: true,
+            contacts: vec![sender.public_id()],
+            carry: CarrySettings {
+                enabled: true,
+                max_envelopes: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut outbox = MeshOutbox::load(dir.path(), &sender).unwrap();
+        outbox.push(&recipient.public_id(), b"first");
+        outbox.push(&recipient.public_id(), b"second");
+        // Only devices that say they carry get envelopes for others
+        let frame = outbox
+            .answer(&sender, &sender_settings, &hello(&mule, false))
+            .unwrap();
+        assert!(handed(&mule, frame).unwrap().is_empty());
+
+        let frame = outbox
+            .answer(&sender, &sender_settings, &hello(&mule, true))
+            .unwrap();
+        let handovers = handed(&mule, frame).unwrap();
+        assert_eq!(handovers.len(), 2);
+
+        let mule_dir = tempfile::tempdir().unwrap();
+        let mut carried = MeshOutbox::load(mule_dir.path(), &mule).unwrap();
+        let taken: Vec<bool> = handovers
+            .iter()
+            .map(|h| carried.carry(&mule_settings.carry, h.clone()))
+            .collect();
+        assert_eq!(taken, [true, false]);
+
+        // Taken or declined, they aren't offered to the mule again, and the
+        // sender keeps them for the recipient
+        let frame = outbox
+            .answer(
+                &sender,
+                &sender_settings,
+                &ack(&mule, &sender, &handovers, true),
+            )
+            .unwrap();
+        assert!(handed(&mule, frame).unwrap().is_empty());
+        assert!(outbox.waiting_for(&recipient.public_id()));
+
+        // The recipient is no contact of the mule but gets what it carries
+        let frame = carried
+            .answer(&mule, &mule_settings, &hello(&recipient, false))
+            .unwrap();
+        assert_eq!(handed(&recipient, frame).unwrap().len(), 1);
+        let mut published = Vec::new();
+        let delivered = carried.deliver_carried(|recipient, data| {
+            published.push((recipient.to_string(), data.to_vec()));
+            true
+        });
+        assert_eq!(delivered, 1);
+        assert_eq!(published, [(recipient.public_id(), b"first".to_vec())]);
+    }
+}
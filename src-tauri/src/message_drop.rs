@@ -0,0 +1,87 @@
+use crate::crypto::{decode_public_key, Identity};
+use crate::dead_drop::{cat_bytes_from_ipfs, upload_bytes_to_ipfs};
+use crate::p2p::{open_p2p_message, seal_p2p_message, GhostMessage, P2PMessage};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// Links look like `control://message/<cid>`
+pub const LINK_PREFIX: &str = "control://message/";
+/// A sealed text message is tiny; anything larger is not one of ours
+const MAX_DROP_SIZE: usize = 64 * 1024;
+
+/// A message packaged as a dead drop after P2P delivery failed, emitted as
+/// `msg_fallback`. The link is shared out of band.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageDrop {
+    pub message_id: String,
+    pub target: String,
+    pub cid: String,
+    pub link: String,
+}
+
+/// Seal `message` to `target` exactly as Ghost Mode would and upload it.
+/// Only `target` can open it, and only they learn who sent it.
+pub async fn create_message_drop(
+    identity: &Identity,
+    target: &str,
+    message: &GhostMessage,
+) -> Result<MessageDrop> {
+    let target_pk = decode_public_key(target)?;
+    let sealed = seal_p2p_message(identity, &target_pk, &P2PMessage::Message(message.clone()))?;
+    let cid = upload_bytes_to_ipfs(sealed).await?;
+
+    info!("Message {} packaged as drop {}", message.id, cid);
+    Ok(MessageDrop {
+        message_id: message.id.clone(),
+        target: target.to_string(),
+        link: format!("{}{}", LINK_PREFIX, cid),
+        cid,
+    })
+}
+
+/// The CID of the first message link in `text`, if any
+pub fn find_message_link(text: &str) -> Option<String> {
+    let start = text.find(LINK_PREFIX)? + LINK_PREFIX.len();
+    let cid: String = text[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect();
+    (!cid.is_empty()).then_some(cid)
+}
+
+/// Fetch and open a message link addressed to us
+pub async fn open_message_link(identity: &Identity, link: &str) -> Result<GhostMessage> {
+    let cid = find_message_link(link).context("Not a message link")?;
+    let data = cat_bytes_from_ipfs(&cid).await?;
+    if data.len() > MAX_DROP_SIZE {
+        anyhow::bail!("Message drop is too large");
+    }
+
+    let (sender_pk, p2p_message) = open_p2p_message(identity, &data)
+        .context("Message drop is not addressed to this identity")?;
+    let P2PMessage::Message(message) = p2p_message else {
+        anyhow::bail!("Drop does not contain a message");
+    };
+    if message.from != bs58::encode(sender_pk.as_bytes()).into_string() {
+        anyhow::bail!("Message drop claims to be from {}", message.from);
+    }
+
+    info!("Opened message {} from drop {}", message.id, cid);
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_links_in_text() {
+        assert_eq!(
+            find_message_link("got it? control://message/bafkreiabc123.").as_deref(),
+            Some("bafkreiabc123")
+        );
+        assert_eq!(find_message_link("control://message/"), None);
+        assert_eq!(find_message_link("no link here"), None);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::catalog::{now_secs, DropCatalog, DropRecord};
+use crate::dead_drop::{drop_manifest_cid, pin_cid};
+use crate::ipfs;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Every drop gets a folder here in the node's MFS
+pub const DROPS_DIR: &str = "/control/drops";
+/// Copying only resolves the root block, but a missing one is searched for
+const MFS_TIMEOUT_SECS: u64 = 60;
+
+/// MFS folder of a drop: its stable ID for versioned drops, its CID otherwise
+pub fn drop_path(record: &DropRecord) -> String {
+    let name = record
+        .drop_id
+        .as_deref()
+        .unwrap_or_else(|| drop_manifest_cid(&record.cid).unwrap_or(&record.cid));
+    format!("{}/{}", DROPS_DIR, name)
+}
+
+/// Link a drop under `/control/drops/<drop-id>`, one entry per CID it is made
+/// of, so `ipfs files` and the WebUI can browse and export it. Replaces any
+/// earlier folder of the drop, e.g. the previous version.
+pub async fn link_drop(record: &DropRecord) -> Result<()> {
+    let client = ipfs::client()?;
+    let path = drop_path(record);
+    remove(&client, &path).await?;
+    files_call(
+        &client,
+        "files/mkdir",
+        &[("arg", path.as_str()), ("parents", "true")],
+    )
+    .await
+    .context("Failed to create MFS folder")?;
+
+    let root = drop_manifest_cid(&record.cid).unwrap_or(&record.cid);
+    for cid in std::iter::once(root).chain(record.piece_cids.iter().map(String::as_str)) {
+        let source = format!("/ipfs/{}", cid);
+        let target = format!("{}/{}", path, cid);
+        files_call(&client, "files/cp", &[("arg", source.as_str()), ("arg", target.as_str())])
+            .await
+            .with_context(|| format!("Failed to link {} into MFS", cid))?;
+    }
+
+    info!("Linked drop {} at {}", record.cid, path);
+    Ok(())
+}
+
+/// Link a catalogued drop whose upload has just finished
+pub async fn link_catalogued(data_dir: &Path, cid: &str) -> Result<()> {
+    let catalog = DropCatalog::load(data_dir)?;
+    // Manifest drops upload their ciphertext as a piece, hidden drops their
+    // carrier image
+    let record = catalog.records().iter().find(|r| {
+        r.cid == cid
+            || drop_manifest_cid(&r.cid) == Some(cid)
+            || r.piece_cids.iter().any(|p| p == cid)
+    });
+    if let Some(record) = record {
+        link_drop(record).await?;
+    }
+    Ok(())
+}
+
+/// Remove a drop's folder. MFS entries keep blocks from being collected, so
+/// this has to happen before repo GC can reclaim an unpinned drop.
+pub async fn unlink_drop(record: &DropRecord) -> Result<()> {
+    remove(&ipfs::client()?, &drop_path(record)).await
+}
+
+/// Adopt content already on the node, e.g. added with `ipfs add`, into the
+/// catalog. It is pinned and linked like a drop, but no shards are held here.
+pub async fn import_existing_cid(
+    data_dir: &Path,
+    cid: &str,
+    name: Option<&str>,
+) -> Result<DropRecord> {
+    let cid = cid.trim();
+    if cid.is_empty() || !cid.chars().all(|c| c.is_ascii_alphanumeric()) {
+        anyhow::bail!("Not a plain CID: {}", cid);
+    }
+    let mut catalog = DropCatalog::load(data_dir)?;
+    if let Some(record) = catalog.records().iter().find(|r| r.cid == cid) {
+        return Ok(record.clone());
+    }
+
+    let client = ipfs::client()?;
+    let ipfs_path = format!("/ipfs/{}", cid);
+    files_call(&client, "files/stat", &[("arg", ipfs_path.as_str())])
+        .await
+        .with_context(|| format!("{} is not reachable from the IPFS node", cid))?;
+    pin_cid(&client, cid).await?;
+
+    let record = DropRecord {
+        cid: cid.to_string(),
+        file_name: name
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .unwrap_or(cid)
+            .to_string(),
+        threshold: 0,
+        total_shards: 0,
+        created_at: now_secs(),
+        expires_at: None,
+        pinned: true,
+        drop_id: None,
+        piece_cids: Vec::new(),
+        reshared_at: None,
+        group: None,
+        imported: true,
+        cipher: None,
+    };
+    catalog.insert(record.clone());
+    catalog.save()?;
+
+    if let Err(e) = link_drop(&record).await {
+        warn!("Imported {} but could not link it into MFS: {}", cid, e);
+    }
+    info!("Imported {} into the drop catalog", cid);
+    Ok(record)
+}
+
+async fn remove(client: &reqwest::Client, path: &str) -> Result<()> {
+    let result = files_call(
+        client,
+        "files/rm",
+        &[("arg", path), ("recursive", "true"), ("force", "true")],
+    )
+    .await;
+    match result {
+        // Nothing linked yet is fine
+        Err(e) if format!("{:#}", e).contains("does not exist") => Ok(()),
+        other => other.context("Failed to remove MFS folder"),
+    }
+}
+
+async fn files_call(
+    client: &reqwest::Client,
+    endpoint: &str,
+    query: &[(&str, &str)],
+) -> Result<()> {
+    let response = client
+        .post(ipfs::api(endpoint))
+        .query(query)
+        .timeout(Duration::from_secs(MFS_TIMEOUT_SECS))
+        .send()
+        .await
+        .with_context(|| format!("IPFS {} failed", endpoint))?;
+
+    if !response.status().is_success() {
+        let body = response.text().await.unwrap_or_default();
+        anyhow::bail!("IPFS {} failed: {}", endpoint, body);
+    }
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use crate::settings::{self, AppSettings};
+use crate::staging::create_staging_dir;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use zeroize::Zeroize;
+
+const RAM_STAGING_DIR: &str = "control-staging";
+const DEFAULT_MAX_STAGING_BYTES: u64 = 256 * 1024 * 1024;
+const MIN_MAX_STAGING_BYTES: u64 = 1024 * 1024;
+const DEFAULT_CLIPBOARD_TTL_SECS: u64 = 15;
+const MIN_CLIPBOARD_TTL_SECS: u64 = 5;
+const MAX_CLIPBOARD_TTL_SECS: u64 = 60;
+
+/// Store files saved while the mode is on, by path. They stay in memory
+/// until they are saved with the mode off, or the app exits.
+static HELD_FILES: Mutex<BTreeMap<PathBuf, Vec<u8>>> = Mutex::new(BTreeMap::new());
+
+/// Operation mode that writes no plaintext or history to disk. What it
+/// overrides is applied to the settings everyone reads (see `enforce`), so
+/// the user's own choices come back when it is turned off.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NoTracesSettings {
+    pub enabled: bool,
+    /// RAM-backed directory drops are staged in; /dev/shm on Linux if unset
+    pub ram_dir: Option<PathBuf>,
+    /// Drops whose ciphertext would take more RAM than this are refused
+    pub max_staging_bytes: u64,
+    /// Sensitive values are cleared from the clipboard after at most this
+    pub clipboard_ttl_secs: u64,
+}
+
+impl Default for NoTracesSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ram_dir: None,
+            max_staging_bytes: DEFAULT_MAX_STAGING_BYTES,
+            clipboard_ttl_secs: DEFAULT_CLIPBOARD_TTL_SECS,
+        }
+    }
+}
+
+impl NoTracesSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_staging_bytes < MIN_MAX_STAGING_BYTES {
+            anyhow::bail!(
+                "Staging limit must be at least {} bytes",
+                MIN_MAX_STAGING_BYTES
+            );
+        }
+        if !(MIN_CLIPBOARD_TTL_SECS..=MAX_CLIPBOARD_TTL_SECS).contains(&self.clipboard_ttl_secs) {
+            anyhow::bail!(
+                "Clipboard timeout must be between {} and {} seconds",
+                MIN_CLIPBOARD_TTL_SECS,
+                MAX_CLIPBOARD_TTL_SECS
+            );
+        }
+        if let Some(ram_dir) = &self.ram_dir {
+            if !ram_dir.is_absolute() {
+                anyhow::bail!("RAM directory must be an absolute path");
+            }
+        }
+        if self.enabled {
+            let dir = self
+                .staging_dir()
+                .context("No RAM-backed directory for staging, choose one (e.g. a RAM disk)")?;
+            create_staging_dir(&dir).context("RAM staging directory is not usable")?;
+        }
+        Ok(())
+    }
+
+    /// Where drops are staged in this mode, if there is a RAM-backed place
+    pub fn staging_dir(&self) -> Option<PathBuf> {
+        let ram_dir = self.ram_dir.clone().or_else(default_ram_dir)?;
+        Some(ram_dir.join(RAM_STAGING_DIR))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn default_ram_dir() -> Option<PathBuf> {
+    Some(PathBuf::from("/dev/shm")).filter(|dir| dir.is_dir())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_ram_dir() -> Option<PathBuf> {
+    None
+}
+
+/// What the mode currently guarantees, for the settings screen
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NoTracesStatus {
+    pub enabled: bool,
+    /// A tmpfs such as /dev/shm, which is not locked memory: staged drops
+    /// are paged out like any other RAM
+    pub staging_dir: Option<PathBuf>,
+    /// RAM staging and memory can still be paged out while swap is on;
+    /// `None` where this can't be told
+    pub swap_active: Option<bool>,
+}
+
+pub fn active() -> bool {
+    settings::no_traces_settings().enabled
+}
+
+/// Override the settings that would leave traces on disk: no log file, no
+/// search index, no usage counters, no compliance hold, drops staged in RAM
+/// and decrypted while downloading. Applied to every read of the settings,
+/// never saved.
+pub fn enforce(settings: &mut AppSettings) {
+    let no_traces = &settings.no_traces;
+    if !no_traces.enabled {
+        return;
+    }
+    settings.logging.to_file = false;
+    settings.search.enabled = false;
+    settings.usage.enabled = false;
+    settings.compliance.enabled = false;
+    settings.drop.staging_dir = no_traces.staging_dir();
+    settings.drop.pipelined_retrieval = true;
+    settings.drop.max_staging_bytes = Some(
+        settings
+            .drop
+            .max_staging_bytes
+            .map_or(no_traces.max_staging_bytes, |max| {
+                max.min(no_traces.max_staging_bytes)
+            }),
+    );
+}
+
+/// Time a sensitive value may stay on the clipboard, shortened in this mode
+pub fn clipboard_ttl(requested: Option<u64>) -> Option<u64> {
+    let no_traces = settings::no_traces_settings();
+    if !no_traces.enabled {
+        return requested;
+    }
+    Some(requested.map_or(no_traces.clipboard_ttl_secs, |ttl| {
+        ttl.min(no_traces.clipboard_ttl_secs)
+    }))
+}
+
+/// Read a store file, preferring the copy held in memory by `write_file`.
+/// `None` if there is neither.
+pub fn read_file(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    if let Some(bytes) = HELD_FILES.lock().unwrap().get(path) {
+        return Ok(Some(bytes.clone()));
+    }
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Write a store file, or hold it in memory while the mode is on. Every
+/// store in the data directory saves through here, so none can leave its
+/// state on disk in this mode.
+pub fn write_file(path: &Path, bytes: impl Into<Vec<u8>>) -> io::Result<()> {
+    store_file(path, bytes.into(), active())
+}
+
+fn store_file(path: &Path, bytes: Vec<u8>, hold: bool) -> io::Result<()> {
+    let mut held = HELD_FILES.lock().unwrap();
+    let replaced = if hold {
+        held.insert(path.to_path_buf(), bytes)
+    } else {
+        fs::write(path, &bytes)?;
+        held.remove(path)
+    };
+    if let Some(mut old) = replaced {
+        old.zeroize();
+    }
+    Ok(())
+}
+
+/// Append to a store file, in memory while the mode is on or the file is
+/// already held there
+pub fn append_file(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    append_to_file(path, bytes, active())
+}
+
+fn append_to_file(path: &Path, bytes: &[u8], hold: bool) -> io::Result<()> {
+    if !hold && !HELD_FILES.lock().unwrap().contains_key(path) {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        return file.write_all(bytes);
+    }
+    let mut contents = read_file(path)?.unwrap_or_default();
+    contents.extend_from_slice(bytes);
+    store_file(path, contents, hold)
+}
+
+/// Whether a store file is held in memory or on disk
+pub fn file_exists(path: &Path) -> bool {
+    HELD_FILES.lock().unwrap().contains_key(path) || path.exists()
+}
+
+/// Delete a store file, from memory and from disk
+pub fn remove_file(path: &Path) -> io::Result<()> {
+    forget_file(path);
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Drop the store files held in memory at or under `path`; false if none
+/// were held
+pub fn forget_files_under(path: &Path) -> bool {
+    let mut held = HELD_FILES.lock().unwrap();
+    let before = held.len();
+    held.retain(|held_path, bytes| {
+        let keep = !held_path.starts_with(path);
+        if !keep {
+            bytes.zeroize();
+        }
+        keep
+    });
+    held.len() < before
+}
+
+/// Drop a store file held in memory; false if it isn't held
+pub fn forget_file(path: &Path) -> bool {
+    match HELD_FILES.lock().unwrap().remove(path) {
+        Some(mut bytes) => {
+            bytes.zeroize();
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn status() -> NoTracesStatus {
+    let no_traces = settings::no_traces_settings();
+    NoTracesStatus {
+        enabled: no_traces.enabled,
+        staging_dir: no_traces.staging_dir(),
+        swap_active: swap_active(Path::new("/proc/swaps")),
+    }
+}
+
+/// Whether any swap device is in use, from the Linux swap table
+fn swap_active(swaps: &Path) -> Option<bool> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let table = std::fs::read_to_string(swaps).ok()?;
+    // The first line is the header
+    Some(table.lines().skip(1).any(|line| !line.trim().is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_only_while_enabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut settings = AppSettings::default();
+        settings.search.enabled = true;
+        settings.compliance.enabled = true;
+        settings.no_traces.ram_dir = Some(dir.path().to_path_buf());
+        settings.drop.max_staging_bytes = Some(u64::MAX);
+
+        let mut effective = settings.clone();
+        enforce(&mut effective);
+        assert!(effective.search.enabled && effective.compliance.enabled);
+
+        settings.no_traces.enabled = true;
+        settings.compliance.enabled = false;
+        settings.validate().unwrap();
+        // A hold that got into the settings anyway is overridden too
+        settings.compliance.enabled = true;
+        let mut effective = settings.clone();
+        enforce(&mut effective);
+        assert!(!effective.search.enabled && !effective.logging.to_file);
+        assert!(!effective.compliance.enabled);
+        assert_eq!(
+            effective.drop.staging_dir,
+            Some(dir.path().join(RAM_STAGING_DIR))
+        );
+        assert_eq!(
+            effective.drop.max_staging_bytes,
+            Some(DEFAULT_MAX_STAGING_BYTES)
+        );
+    }
+
+    #[test]
+    fn store_files_stay_in_memory_while_active() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.enc");
+        store_file(&path, b"first".to_vec(), true).unwrap();
+        append_to_file(&path, b"+more", true).unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        assert!(file_exists(&path));
+        assert_eq!(read_file(&path).unwrap().unwrap(), b"first+more");
+
+        // Saved with the mode off, it goes to disk and leaves memory
+        store_file(&path, b"second".to_vec(), false).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert!(!forget_file(&path));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::crypto::Identity;
+use crate::daemon;
+use crate::knock::KnockRecord;
+use crate::p2p::{init_p2p_actor, P2PCommand, P2PEvents, P2PStatus, ShutdownSignal};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+/// An event from a running node: the same names and payloads the app's
+/// frontend receives, e.g. `ghost_msg` or `msg_delivered`
+#[derive(Debug, Clone)]
+pub struct NodeEvent {
+    pub name: String,
+    pub payload: serde_json::Value,
+}
+
+/// Forwards node events into a channel, for embedders without a UI
+pub struct ChannelEvents(mpsc::UnboundedSender<NodeEvent>);
+
+impl ChannelEvents {
+    pub fn new() -> (Arc<Self>, mpsc::UnboundedReceiver<NodeEvent>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Arc::new(Self(tx)), rx)
+    }
+}
+
+impl P2PEvents for ChannelEvents {
+    fn emit_event(&self, event: &str, payload: serde_json::Value) -> Result<()> {
+        let _ = self.0.send(NodeEvent {
+            name: event.to_string(),
+            payload,
+        });
+        Ok(())
+    }
+}
+
+/// Drops every event
+struct NoEvents;
+
+impl P2PEvents for NoEvents {
+    fn emit_event(&self, _event: &str, _payload: serde_json::Value) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Configures a Ghost Mode node started in this process
+pub struct NodeBuilder {
+    identity: Identity,
+    data_dir: PathBuf,
+    events: Arc<dyn P2PEvents>,
+}
+
+impl NodeBuilder {
+    /// Where events go; by default they are dropped
+    pub fn events(mut self, events: Arc<dyn P2PEvents>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Start the P2P actor. Needs a Tokio runtime.
+    pub fn start(self) -> Result<Node> {
+        let shutdown = ShutdownSignal::new();
+        let commands = init_p2p_actor(self.identity, self.events, self.data_dir, &shutdown)?;
+        Ok(Node { commands, shutdown })
+    }
+}
+
+/// A running Ghost Mode node, local or a daemon attached to with
+/// [`Node::attach`]. Dropping it leaves the node running until every
+/// clone of its command channel is gone.
+#[derive(Clone)]
+pub struct Node {
+    commands: mpsc::Sender<P2PCommand>,
+    shutdown: ShutdownSignal,
+}
+
+impl Node {
+    pub fn builder(identity: Identity, data_dir: impl Into<PathBuf>) -> NodeBuilder {
+        NodeBuilder {
+            identity,
+            data_dir: data_dir.into(),
+            events: Arc::new(NoEvents),
+        }
+    }
+
+    /// Attach to the daemon running for `data_dir`, if any. No identity is
+    /// needed, but contact requests and burn keys aren't available.
+    pub async fn attach(data_dir: &Path, events: Arc<dyn P2PEvents>) -> Result<Option<Node>> {
+        let shutdown = ShutdownSignal::new();
+        let commands = daemon::connect(data_dir, events, &shutdown).await?;
+        Ok(commands.map(|commands| Node { commands, shutdown }))
+    }
+
+    /// Send a message and return its ID; `msg_delivered` or `msg_failed`
+    /// with that ID follows
+    pub async fn send_message(&self, target_public_key: &str, content: &str) -> Result<String> {
+        let message_id = uuid::Uuid::new_v4().to_string();
+        self.command(P2PCommand::SendMessage {
+            target_public_key: target_public_key.to_string(),
+            content: content.to_string(),
+            message_id: message_id.clone(),
+            view_once: false,
+            location: None,
+        })
+        .await?;
+        Ok(message_id)
+    }
+
+    pub async fn status(&self) -> Result<P2PStatus> {
+        let (reply, response) = oneshot::channel();
+        self.command(P2PCommand::Status { reply }).await?;
+        response.await.context("Node status unavailable")
+    }
+
+    /// Ask a new contact to accept our messages
+    pub async fn send_contact_request(&self, public_id: &str, note: &str) -> Result<KnockRecord> {
+        let (reply, response) = oneshot::channel();
+        self.command(P2PCommand::SendContactRequest {
+            public_id: public_id.to_string(),
+            note: note.to_string(),
+            reply,
+        })
+        .await?;
+        reply_result(response).await
+    }
+
+    pub async fn respond_contact_request(
+        &self,
+        public_id: &str,
+        accept: bool,
+    ) -> Result<KnockRecord> {
+        let (reply, response) = oneshot::channel();
+        self.command(P2PCommand::RespondContactRequest {
+            public_id: public_id.to_string(),
+            accept,
+            reply,
+        })
+        .await?;
+        reply_result(response).await
+    }
+
+    /// The raw command channel, for commands without a method here
+    pub fn commands(&self) -> mpsc::Sender<P2PCommand> {
+        self.commands.clone()
+    }
+
+    /// Stop a local node; an attached daemon stays online
+    pub async fn shutdown(self) {
+        self.shutdown.trigger();
+    }
+
+    async fn command(&self, command: P2PCommand) -> Result<()> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| anyhow::anyhow!("Ghost Mode stopped"))
+    }
+}
+
+async fn reply_result<T>(response: oneshot::Receiver<Result<T, String>>) -> Result<T> {
+    response
+        .await
+        .context("Ghost Mode stopped")?
+        .map_err(anyhow::Error::msg)
+}
@@ -0,0 +1,493 @@
+use crate::catalog::now_secs;
+use crate::crypto::Identity;
+use crate::ipfs;
+use crate::no_traces;
+use crate::settings;
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures::{SinkExt, StreamExt};
+use k256::schnorr::{Signature, SigningKey, VerifyingKey};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{info, warn};
+use x25519_dalek::PublicKey;
+use zeroize::Zeroize;
+
+/// Kind of the events carrying envelopes, after NIP-59 gift wraps: signed
+/// with a throwaway key and addressed with a `p` tag
+pub const ENVELOPE_KIND: u64 = 1059;
+const STATE_FILE: &str = "nostr.enc";
+const RELAY_TIMEOUT_SECS: u64 = 15;
+/// Events polled for again each time, in case a relay stored them late
+const POLL_OVERLAP_SECS: u64 = 600;
+/// How far back the very first poll looks
+const FIRST_POLL_SECS: u64 = 7 * 24 * 60 * 60;
+/// Event IDs remembered so overlapping polls don't deliver twice
+const SEEN_CAPACITY: usize = 2000;
+/// Largest envelope wrapped; most relays refuse bigger events
+const MAX_ENVELOPE_SIZE: usize = 64 * 1024;
+
+/// Nostr relays used when no libp2p peer takes an envelope
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NostrSettings {
+    pub enabled: bool,
+    /// `wss://` relay URLs, or `ws://` for onion services
+    pub relays: Vec<String>,
+    /// How often the relays are asked for envelopes addressed to us
+    pub poll_secs: u64,
+    /// Expiration asked of relays for published envelopes (NIP-40)
+    pub retention_days: u32,
+}
+
+impl Default for NostrSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            relays: Vec::new(),
+            poll_secs: 60,
+            retention_days: 7,
+        }
+    }
+}
+
+impl NostrSettings {
+    pub fn validate(&self) -> Result<()> {
+        for relay in &self.relays {
+            let url = reqwest::Url::parse(relay)
+                .with_context(|| format!("Invalid Nostr relay URL: {}", relay))?;
+            let onion = url.host_str().is_some_and(|h| h.ends_with(".onion"));
+            match url.scheme() {
+                "wss" => {}
+                // Tor encrypts the way to an onion service
+                "ws" if onion => {}
+                _ => anyhow::bail!(
+                    "Nostr relay must be a wss:// URL, or ws:// for an onion service: {}",
+                    relay
+                ),
+            }
+        }
+        if self.enabled && self.relays.is_empty() {
+            anyhow::bail!("Add at least one Nostr relay to use the Nostr fallback");
+        }
+        if !(15..=3600).contains(&self.poll_secs) {
+            anyhow::bail!("Nostr poll interval must be between 15 and 3600 seconds");
+        }
+        if !(1..=90).contains(&self.retention_days) {
+            anyhow::bail!("Nostr retention must be between 1 and 90 days");
+        }
+        Ok(())
+    }
+
+    fn active(&self) -> bool {
+        self.enabled && !self.relays.is_empty()
+    }
+}
+
+/// `p` tag addressing envelopes to an identity. Hashed so it looks like any
+/// other Nostr key, though anyone with the public ID can compute it.
+pub fn inbox_tag(public_key: &PublicKey) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"control-nostr-inbox");
+    hasher.update(public_key.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// A NIP-01 event
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NostrEvent {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: u64,
+    pub kind: u64,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+
+impl NostrEvent {
+    /// Wrap a sealed envelope for `recipient` in an event signed with a
+    /// throwaway key, so events can't be linked to their sender
+    pub fn wrap(recipient: &PublicKey, envelope: &[u8], retention_days: u32) -> Result<Self> {
+        if envelope.len() > MAX_ENVELOPE_SIZE {
+            anyhow::bail!("Envelope too large for a Nostr relay");
+        }
+        let signing_key = SigningKey::random(&mut OsRng);
+        let created_at = now_secs();
+        let expiration = created_at + retention_days as u64 * 24 * 60 * 60;
+
+        let mut event = Self {
+            id: String::new(),
+            pubkey: hex::encode(signing_key.verifying_key().to_bytes()),
+            created_at,
+            kind: ENVELOPE_KIND,
+            tags: vec![
+                vec!["p".to_string(), inbox_tag(recipient)],
+                vec!["expiration".to_string(), expiration.to_string()],
+            ],
+            content: BASE64.encode(envelope),
+            sig: String::new(),
+        };
+        let id = event.compute_id()?;
+        let mut aux_rand = [0u8; 32];
+        OsRng.fill_bytes(&mut aux_rand);
+        let sig = signing_key
+            .sign_raw(&id, &aux_rand)
+            .map_err(|_| anyhow::anyhow!("Failed to sign Nostr event"))?;
+        event.id = hex::encode(id);
+        event.sig = hex::encode(sig.to_bytes());
+        Ok(event)
+    }
+
+    /// SHA-256 of `[0, pubkey, created_at, kind, tags, content]`
+    fn compute_id(&self) -> Result<[u8; 32]> {
+        let serialized = serde_json::to_vec(&serde_json::json!([
+            0,
+            self.pubkey,
+            self.created_at,
+            self.kind,
+            self.tags,
+            self.content,
+        ]))?;
+        Ok(Sha256::digest(serialized).into())
+    }
+
+    /// Check the ID and signature, as relays are not trusted to
+    pub fn verify(&self) -> Result<()> {
+        let id = self.compute_id()?;
+        if hex::encode(id) != self.id {
+            anyhow::bail!("Nostr event ID does not match its content");
+        }
+        let pubkey = hex::decode(&self.pubkey).context("Invalid Nostr event key")?;
+        let sig = hex::decode(&self.sig).context("Invalid Nostr event signature")?;
+        let verifying_key =
+            VerifyingKey::from_bytes(&pubkey).map_err(|_| anyhow::anyhow!("Invalid Nostr event key"))?;
+        let sig = Signature::try_from(sig.as_slice())
+            .map_err(|_| anyhow::anyhow!("Invalid Nostr event signature"))?;
+        verifying_key
+            .verify_raw(&id, &sig)
+            .map_err(|_| anyhow::anyhow!("Nostr event signature does not verify"))
+    }
+
+    /// The sealed envelope inside, if the event is one addressed to `tag`
+    pub fn envelope(&self, tag: &str) -> Result<Vec<u8>> {
+        if self.kind != ENVELOPE_KIND {
+            anyhow::bail!("Not an envelope event");
+        }
+        let addressed = self
+            .tags
+            .iter()
+            .any(|t| t.first().map(String::as_str) == Some("p") && t.get(1).map(String::as_str) == Some(tag));
+        if !addressed {
+            anyhow::bail!("Envelope is not addressed to us");
+        }
+        self.verify()?;
+        let envelope = BASE64.decode(&self.content).context("Invalid envelope encoding")?;
+        if envelope.len() > MAX_ENVELOPE_SIZE {
+            anyhow::bail!("Envelope too large");
+        }
+        Ok(envelope)
+    }
+}
+
+/// Publish a sealed envelope for `recipient` to the configured relays in the
+/// background. Returns false if the Nostr fallback is off.
+pub fn publish(recipient: &PublicKey, envelope: &[u8]) -> bool {
+    let nostr = settings::p2p_settings().nostr;
+    if !nostr.active() {
+        return false;
+    }
+    let event = match NostrEvent::wrap(recipient, envelope, nostr.retention_days) {
+        Ok(event) => event,
+        Err(e) => {
+            warn!("Can't send envelope through Nostr: {}", e);
+            return false;
+        }
+    };
+
+    tokio::spawn(async move {
+        let mut accepted = 0;
+        for relay in &nostr.relays {
+            match publish_to_relay(relay, &event).await {
+                Ok(()) => accepted += 1,
+                Err(e) => warn!("Nostr relay {} did not take envelope: {}", relay, e),
+            }
+        }
+        info!(
+            "Envelope {} published to {}/{} Nostr relays",
+            event.id,
+            accepted,
+            nostr.relays.len()
+        );
+    });
+    true
+}
+
+/// Open a relay connection, through the Tor proxy from the network settings
+/// if one is set. The proxy resolves the relay's name, so lookups don't leak.
+async fn connect_relay(relay: &str) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+    settings::check_loaded()?;
+    let url = reqwest::Url::parse(relay)?;
+    let host = url.host_str().context("Relay URL has no host")?;
+    let port = url
+        .port_or_known_default()
+        .context("Relay URL has no port")?;
+
+    let stream = ipfs::connect(host, port).await?;
+    let (socket, _) = tokio_tungstenite::client_async_tls(relay, stream).await?;
+    Ok(socket)
+}
+
+async fn publish_to_relay(relay: &str, event: &NostrEvent) -> Result<()> {
+    let mut socket = tokio::time::timeout(
+        Duration::from_secs(RELAY_TIMEOUT_SECS),
+        connect_relay(relay),
+    )
+    .await
+    .context("Timed out connecting")??;
+
+    let request = serde_json::json!(["EVENT", event]).to_string();
+    socket.send(Message::Text(request)).await?;
+
+    // Wait for the relay's ["OK", id, accepted, message]
+    let result = tokio::time::timeout(Duration::from_secs(RELAY_TIMEOUT_SECS), async {
+        while let Some(message) = socket.next().await {
+            let Message::Text(text) = message? else {
+                continue;
+            };
+            let Ok(reply) = serde_json::from_str::<Vec<serde_json::Value>>(&text) else {
+                continue;
+            };
+            if reply.first().and_then(|v| v.as_str()) == Some("OK")
+                && reply.get(1).and_then(|v| v.as_str()) == Some(event.id.as_str())
+            {
+                if reply.get(2).and_then(|v| v.as_bool()) == Some(true) {
+                    return Ok(());
+                }
+                let reason = reply.get(3).and_then(|v| v.as_str()).unwrap_or_default();
+                anyhow::bail!("Rejected: {}", reason);
+            }
+        }
+        anyhow::bail!("Connection closed before the relay answered")
+    })
+    .await
+    .context("Timed out waiting for the relay");
+    let _ = socket.close(None).await;
+    result?
+}
+
+/// Poll cursor and delivered events, kept across restarts
+#[derive(Serialize, Deserialize, Default)]
+struct PollState {
+    since: Option<u64>,
+    seen: VecDeque<String>,
+}
+
+/// Ask the relays for envelopes addressed to `identity` every poll interval
+/// and send them to the P2P actor. Stops when the receiver is dropped.
+pub fn start_poller(identity: Identity, data_dir: PathBuf) -> mpsc::Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel(100);
+    tokio::spawn(async move {
+        let tag = inbox_tag(&identity.public_key);
+        let state_path = data_dir.join(STATE_FILE);
+        let mut state = load_state(&state_path, &identity).unwrap_or_else(|e| {
+            warn!("Starting Nostr polling afresh: {}", e);
+            PollState::default()
+        });
+        let mut seen: HashSet<String> = state.seen.iter().cloned().collect();
+
+        loop {
+            let nostr = settings::p2p_settings().nostr;
+            if nostr.active() {
+                let started = now_secs();
+                let since = state
+                    .since
+                    .unwrap_or_else(|| started.saturating_sub(FIRST_POLL_SECS));
+                let mut delivered = 0;
+                for relay in &nostr.relays {
+                    let events = match fetch_from_relay(relay, &tag, since).await {
+                        Ok(events) => events,
+                        Err(e) => {
+                            warn!("Failed to poll Nostr relay {}: {}", relay, e);
+                            continue;
+                        }
+                    };
+                    for event in events {
+                        if seen.contains(&event.id) {
+                            continue;
+                        }
+                        match event.envelope(&tag) {
+                            Ok(envelope) => {
+                                if tx.send(envelope).await.is_err() {
+                                    return;
+                                }
+                                delivered += 1;
+                            }
+                            Err(e) => warn!("Skipping Nostr event {}: {}", event.id, e),
+                        }
+                        remember(&mut state, &mut seen, event.id);
+                    }
+                }
+                if delivered > 0 {
+                    info!("Nostr: {} envelopes", delivered);
+                }
+                state.since = Some(started.saturating_sub(POLL_OVERLAP_SECS));
+                if let Err(e) = save_state(&state_path, &identity, &state) {
+                    warn!("Failed to save Nostr poll state: {}", e);
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(nostr.poll_secs.max(15))) => {}
+                _ = tx.closed() => return,
+            }
+        }
+    });
+    rx
+}
+
+fn remember(state: &mut PollState, seen: &mut HashSet<String>, id: String) {
+    if !seen.insert(id.clone()) {
+        return;
+    }
+    state.seen.push_back(id);
+    if state.seen.len() > SEEN_CAPACITY {
+        if let Some(oldest) = state.seen.pop_front() {
+            seen.remove(&oldest);
+        }
+    }
+}
+
+/// Events addressed to `tag` since `since`, up to the relay's end of stored events
+async fn fetch_from_relay(relay: &str, tag: &str, since: u64) -> Result<Vec<NostrEvent>> {
+    let mut socket = tokio::time::timeout(
+        Duration::from_secs(RELAY_TIMEOUT_SECS),
+        connect_relay(relay),
+    )
+    .await
+    .context("Timed out connecting")??;
+
+    let subscription = uuid::Uuid::new_v4().simple().to_string();
+    let filter = serde_json::json!({
+        "kinds": [ENVELOPE_KIND],
+        "#p": [tag],
+        "since": since,
+    });
+    let request = serde_json::json!(["REQ", subscription, filter]).to_string();
+    socket.send(Message::Text(request)).await?;
+
+    let mut events = Vec::new();
+    let result = tokio::time::timeout(Duration::from_secs(RELAY_TIMEOUT_SECS), async {
+        while let Some(message) = socket.next().await {
+            let Message::Text(text) = message? else {
+                continue;
+            };
+            let Ok(reply) = serde_json::from_str::<Vec<serde_json::Value>>(&text) else {
+                continue;
+            };
+            if reply.get(1).and_then(|v| v.as_str()) != Some(subscription.as_str()) {
+                continue;
+            }
+            match reply.first().and_then(|v| v.as_str()) {
+                Some("EVENT") => match reply.get(2).cloned().map(serde_json::from_value) {
+                    Some(Ok(event)) => events.push(event),
+                    _ => warn!("Skipping malformed event from {}", relay),
+                },
+                Some("EOSE") => return Ok(()),
+                Some("CLOSED") => {
+                    let reason = reply.get(2).and_then(|v| v.as_str()).unwrap_or_default();
+                    anyhow::bail!("Subscription closed: {}", reason);
+                }
+                _ => {}
+            }
+        }
+        anyhow::bail!("Connection closed before the stored events ended")
+    })
+    .await
+    .context("Timed out waiting for the relay");
+
+    let close = serde_json::json!(["CLOSE", subscription]).to_string();
+    let _ = socket.send(Message::Text(close)).await;
+    let _ = socket.close(None).await;
+    result??;
+    Ok(events)
+}
+
+fn load_state(path: &Path, identity: &Identity) -> Result<PollState> {
+    let Some(encrypted) =
+        no_traces::read_file(path).context("Failed to read Nostr poll state")?
+    else {
+        return Ok(PollState::default());
+    };
+    let mut json = identity
+        .storage_key(b"nostr")
+        .decrypt_file(&encrypted)
+        .context("Failed to decrypt Nostr poll state")?;
+    let state = serde_json::from_slice(&json);
+    json.zeroize();
+    state.context("Failed to parse Nostr poll state")
+}
+
+fn save_state(path: &Path, identity: &Identity, state: &PollState) -> Result<()> {
+    let mut json = serde_json::to_vec(state)?;
+    let encrypted = identity.storage_key(b"nostr").encrypt_file(&json);
+    json.zeroize();
+    no_traces::write_file(path, encrypted?).context("Failed to write Nostr poll state")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrapped_envelope_round_trips() {
+        let recipient = Identity::generate();
+        let event = NostrEvent::wrap(&recipient.public_key, b"sealed envelope", 7).unwrap();
+
+        assert_eq!(event.kind, ENVELOPE_KIND);
+        let tag = inbox_tag(&recipient.public_key);
+        assert_eq!(event.envelope(&tag).unwrap(), b"sealed envelope");
+    }
+
+    #[test]
+    fn tampered_event_is_rejected() {
+        let recipient = Identity::generate();
+        let tag = inbox_tag(&recipient.public_key);
+        let mut event = NostrEvent::wrap(&recipient.public_key, b"sealed envelope", 7).unwrap();
+        event.content = BASE64.encode(b"other envelope");
+
+        assert!(event.envelope(&tag).is_err());
+    }
+
+    #[test]
+    fn envelope_for_someone_else_is_rejected() {
+        let (recipient, other) = (Identity::generate(), Identity::generate());
+        let event = NostrEvent::wrap(&recipient.public_key, b"sealed envelope", 7).unwrap();
+
+        assert!(event.envelope(&inbox_tag(&other.public_key)).is_err());
+    }
+
+    #[test]
+    fn enabled_without_relays_is_invalid() {
+        let settings = NostrSettings {
+            enabled: true,
+            ..NostrSettings::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let settings = NostrSettings {
+            relays: vec!["https://relay.example".to_string()],
+            ..NostrSettings::default()
+        };
+        assert!(settings.validate().is_err());
+    }
+}
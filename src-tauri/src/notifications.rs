@@ -0,0 +1,199 @@
+use crate::canary::CanaryAlert;
+use crate::i18n::{t, t_args};
+use crate::p2p::GhostMessage;
+use crate::pin_health::DropPinHealth;
+use crate::settings;
+use crate::upload_schedule::{in_daily_window, parse_minutes};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use tauri::api::notification::Notification;
+use tauri::{AppHandle, Manager, Window};
+use tracing::warn;
+
+const PREVIEW_CHARS: usize = 80;
+
+/// When and what the backend shows as OS notifications
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NotificationSettings {
+    pub enabled: bool,
+    /// Show message text; otherwise only the sender is shown
+    pub show_preview: bool,
+    /// Public IDs whose messages never raise a notification
+    pub muted_contacts: Vec<String>,
+    pub quiet_hours_start: Option<String>, // "HH:MM" local time
+    pub quiet_hours_end: Option<String>,   // "HH:MM", may wrap past midnight
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            show_preview: false,
+            muted_contacts: Vec::new(),
+            quiet_hours_start: None,
+            quiet_hours_end: None,
+        }
+    }
+}
+
+impl NotificationSettings {
+    pub fn validate(&self) -> Result<()> {
+        match (&self.quiet_hours_start, &self.quiet_hours_end) {
+            (Some(start), Some(end)) => {
+                parse_minutes(start).context("Invalid quiet hours start")?;
+                parse_minutes(end).context("Invalid quiet hours end")?;
+            }
+            (None, None) => {}
+            _ => anyhow::bail!("Quiet hours need both a start and an end time"),
+        }
+        Ok(())
+    }
+
+    fn in_quiet_hours(&self) -> bool {
+        match (&self.quiet_hours_start, &self.quiet_hours_end) {
+            (Some(start), Some(end)) => in_daily_window(start, end).unwrap_or(false),
+            _ => false,
+        }
+    }
+}
+
+/// What a notification was about, emitted as `notification_activated` when the
+/// user brings the window back after it was shown
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NotificationTarget {
+    Message { contact: String, message_id: String },
+    MessageFailed { contact: String, message_id: String },
+    DropAtRisk { cid: String },
+    Canary { canary: String },
+}
+
+/// Last notification shown while the window was in the background
+static PENDING_ACTIVATION: Mutex<Option<NotificationTarget>> = Mutex::new(None);
+
+/// Notify about an incoming message unless the window is in front or the sender is muted
+pub fn message_received(window: &Window, message: &GhostMessage) {
+    let settings = settings::notification_settings();
+    if settings.muted_contacts.contains(&message.from) {
+        return;
+    }
+    if in_front(window) {
+        return;
+    }
+
+    let body = if message.view_once {
+        t("conversation.view_once")
+    } else if message.location.is_some() && message.content.is_empty() {
+        t("conversation.location")
+    } else if settings.show_preview {
+        message.content.chars().take(PREVIEW_CHARS).collect()
+    } else {
+        t_args(
+            "notification.from",
+            &[("contact", &short_id(&message.from))],
+        )
+    };
+    show(
+        &window.app_handle(),
+        &settings,
+        &t("notification.new_message"),
+        &body,
+        NotificationTarget::Message {
+            contact: message.from.clone(),
+            message_id: message.id.clone(),
+        },
+    );
+}
+
+/// Whether the user is looking at the window right now
+pub fn in_front(window: &Window) -> bool {
+    window.is_focused().unwrap_or(false) && window.is_visible().unwrap_or(false)
+}
+
+/// Notify that a canary was tripped, in front or not and even in quiet hours
+pub fn canary_alert(window: &Window, alert: &CanaryAlert) {
+    if !settings::notification_settings().enabled {
+        return;
+    }
+    show_now(
+        &window.app_handle(),
+        &t("notification.canary_alert"),
+        &t_args("notification.canary_alert_body", &[("label", &alert.label)]),
+        NotificationTarget::Canary {
+            canary: alert.canary.clone(),
+        },
+    );
+}
+
+/// Notify that a message was not delivered
+pub fn message_failed(window: &Window, contact: &str, message_id: &str, reason: &str) {
+    let settings = settings::notification_settings();
+    if settings.muted_contacts.iter().any(|c| c == contact) {
+        return;
+    }
+
+    show(
+        &window.app_handle(),
+        &settings,
+        &t("notification.not_delivered"),
+        &t_args(
+            "notification.not_delivered_body",
+            &[("contact", &short_id(contact)), ("reason", reason)],
+        ),
+        NotificationTarget::MessageFailed {
+            contact: contact.to_string(),
+            message_id: message_id.to_string(),
+        },
+    );
+}
+
+/// Notify that a drop has fewer pinned copies than it should
+pub fn drop_at_risk(app: &AppHandle, health: &DropPinHealth) {
+    show(
+        app,
+        &settings::notification_settings(),
+        &t("notification.drop_at_risk"),
+        &t_args(
+            "notification.drop_at_risk_body",
+            &[
+                ("file", &health.file_name),
+                ("replicas", &health.replicas.to_string()),
+                ("required", &health.replication_factor.to_string()),
+            ],
+        ),
+        NotificationTarget::DropAtRisk {
+            cid: health.cid.clone(),
+        },
+    );
+}
+
+/// Target of the last background notification, cleared once taken
+pub fn take_pending_activation() -> Option<NotificationTarget> {
+    PENDING_ACTIVATION.lock().unwrap().take()
+}
+
+fn show(
+    app: &AppHandle,
+    settings: &NotificationSettings,
+    title: &str,
+    body: &str,
+    target: NotificationTarget,
+) {
+    if !settings.enabled || settings.in_quiet_hours() {
+        return;
+    }
+    show_now(app, title, body, target);
+}
+
+fn show_now(app: &AppHandle, title: &str, body: &str, target: NotificationTarget) {
+    let identifier = app.config().tauri.bundle.identifier.clone();
+    match Notification::new(identifier).title(title).body(body).show() {
+        Ok(()) => *PENDING_ACTIVATION.lock().unwrap() = Some(target),
+        Err(e) => warn!("Failed to show notification: {}", e),
+    }
+}
+
+fn short_id(public_id: &str) -> String {
+    public_id.chars().take(8).collect()
+}
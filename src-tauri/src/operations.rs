@@ -0,0 +1,350 @@
+use crate::catalog::now_secs;
+use crate::dead_drop::{DropOptions, DropProgress};
+use crate::events;
+use crate::settings;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager};
+use tracing::{info, warn};
+
+/// Progress of one operation is emitted at most this often, so a fast
+/// operation can't crowd out the others
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// Finished operations kept for status queries
+const MAX_FINISHED: usize = 100;
+
+/// Queued operations start highest priority first, then in submission order
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    Create,
+    Retrieve,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl OperationState {
+    fn is_finished(self) -> bool {
+        matches!(self, Self::Completed | Self::Failed | Self::Cancelled)
+    }
+}
+
+/// Status of a drop operation, emitted as `operation_updated` whenever its
+/// state changes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OperationStatus {
+    pub id: String,
+    pub kind: OperationKind,
+    /// File name or CID, for display
+    pub label: String,
+    pub priority: Priority,
+    pub state: OperationState,
+    pub queued_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub progress: Option<DropProgress>,
+    /// What the operation returned, e.g. the created drop
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+/// Emitted as `operation_progress`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OperationProgress {
+    pub id: String,
+    pub progress: DropProgress,
+}
+
+pub type OperationFuture = Pin<Box<dyn Future<Output = Result<serde_json::Value>> + Send>>;
+/// Starts the work once a slot is free. The options carry the operation's
+/// share of the bandwidth budget and its progress hook.
+pub type OperationJob = Box<dyn FnOnce(DropOptions) -> OperationFuture + Send>;
+
+struct QueuedJob {
+    id: String,
+    seq: u64,
+    options: DropOptions,
+    job: OperationJob,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_seq: u64,
+    statuses: Vec<OperationStatus>,
+    queue: Vec<QueuedJob>,
+    running: HashMap<String, JoinHandle<()>>,
+    last_progress: HashMap<String, Instant>,
+}
+
+impl Inner {
+    fn status_mut(&mut self, id: &str) -> Option<&mut OperationStatus> {
+        self.statuses.iter_mut().find(|s| s.id == id)
+    }
+
+    fn prune_finished(&mut self) {
+        let finished = self
+            .statuses
+            .iter()
+            .filter(|s| s.state.is_finished())
+            .count();
+        let mut excess = finished.saturating_sub(MAX_FINISHED);
+        self.statuses.retain(|s| {
+            let drop_it = excess > 0 && s.state.is_finished();
+            if drop_it {
+                excess -= 1;
+            }
+            !drop_it
+        });
+    }
+}
+
+/// Runs create and retrieve jobs side by side, at most
+/// `DropSettings::max_concurrent_operations` at a time. The upload rate limit
+/// is the global bandwidth budget, split evenly among the operations running
+/// when each one starts.
+#[derive(Clone)]
+pub struct OperationManager {
+    app: AppHandle,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl OperationManager {
+    pub fn new(app: AppHandle) -> Self {
+        Self {
+            app,
+            inner: Arc::default(),
+        }
+    }
+
+    /// Queue a job and start it as soon as a slot is free. Returns its ID.
+    pub fn submit(
+        &self,
+        kind: OperationKind,
+        label: String,
+        priority: Priority,
+        options: DropOptions,
+        job: OperationJob,
+    ) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let status = OperationStatus {
+            id: id.clone(),
+            kind,
+            label,
+            priority,
+            state: OperationState::Queued,
+            queued_at: now_secs(),
+            started_at: None,
+            finished_at: None,
+            progress: None,
+            result: None,
+            error: None,
+        };
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let seq = inner.next_seq;
+            inner.next_seq += 1;
+            inner.statuses.push(status.clone());
+            inner.queue.push(QueuedJob {
+                id: id.clone(),
+                seq,
+                options,
+                job,
+            });
+        }
+        events::emit(&self.app, "operation_updated", status);
+        self.dispatch();
+        id
+    }
+
+    pub fn list(&self) -> Vec<OperationStatus> {
+        self.inner.lock().unwrap().statuses.clone()
+    }
+
+    pub fn get(&self, id: &str) -> Option<OperationStatus> {
+        let inner = self.inner.lock().unwrap();
+        inner.statuses.iter().find(|s| s.id == id).cloned()
+    }
+
+    /// Change the priority of a queued operation; running ones keep going
+    pub fn set_priority(&self, id: &str, priority: Priority) -> Option<OperationStatus> {
+        let status = {
+            let mut inner = self.inner.lock().unwrap();
+            let status = inner.status_mut(id)?;
+            status.priority = priority;
+            status.clone()
+        };
+        events::emit(&self.app, "operation_updated", &status);
+        Some(status)
+    }
+
+    /// Cancel a queued or running operation. A running create may already
+    /// have uploaded ciphertext; nothing is catalogued for it.
+    pub fn cancel(&self, id: &str) -> Option<OperationStatus> {
+        let status = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.queue.retain(|q| q.id != id);
+            if let Some(handle) = inner.running.remove(id) {
+                handle.abort();
+            }
+            inner.last_progress.remove(id);
+            let status = inner.status_mut(id)?;
+            if status.state.is_finished() {
+                return Some(status.clone());
+            }
+            status.state = OperationState::Cancelled;
+            status.finished_at = Some(now_secs());
+            status.clone()
+        };
+        info!("Cancelled operation {}", id);
+        events::emit(&self.app, "operation_updated", &status);
+        self.dispatch();
+        Some(status)
+    }
+
+    /// Start queued jobs while there are free slots
+    fn dispatch(&self) {
+        let max_running = settings::drop_settings().max_concurrent_operations;
+        let mut started = Vec::new();
+        {
+            let mut inner = self.inner.lock().unwrap();
+            while inner.running.len() < max_running && !inner.queue.is_empty() {
+                let next = {
+                    let statuses = &inner.statuses;
+                    let priority = |id: &str| {
+                        statuses
+                            .iter()
+                            .find(|s| s.id == id)
+                            .map_or(Priority::Normal, |s| s.priority)
+                    };
+                    inner
+                        .queue
+                        .iter()
+                        .enumerate()
+                        .max_by_key(|(_, q)| (priority(&q.id), std::cmp::Reverse(q.seq)))
+                        .map(|(index, _)| index)
+                };
+                let Some(index) = next else { break };
+                let queued = inner.queue.remove(index);
+                let share = inner.running.len() as u64 + 1;
+                let handle = self.start(queued.id.clone(), queued.options, queued.job, share);
+                inner.running.insert(queued.id.clone(), handle);
+                if let Some(status) = inner.status_mut(&queued.id) {
+                    status.state = OperationState::Running;
+                    status.started_at = Some(now_secs());
+                    started.push(status.clone());
+                }
+            }
+        }
+        for status in started {
+            events::emit(&self.app, "operation_updated", status);
+        }
+    }
+
+    fn start(
+        &self,
+        id: String,
+        mut options: DropOptions,
+        job: OperationJob,
+        share: u64,
+    ) -> JoinHandle<()> {
+        options.upload_schedule.max_bytes_per_sec = options
+            .upload_schedule
+            .max_bytes_per_sec
+            .map(|budget| (budget / share).max(1));
+        let manager = self.clone();
+        let progress_id = id.clone();
+        options.progress = Some(Arc::new(move |progress: DropProgress| {
+            manager.report(&progress_id, progress)
+        }));
+
+        let manager = self.clone();
+        tauri::async_runtime::spawn(async move {
+            let result = job(options).await;
+            manager.finish(&id, result);
+        })
+    }
+
+    fn report(&self, id: &str, progress: DropProgress) {
+        let emit = {
+            let mut inner = self.inner.lock().unwrap();
+            let Some(status) = inner.status_mut(id) else {
+                return;
+            };
+            status.progress = Some(progress.clone());
+            let done = progress.bytes_done >= progress.total_bytes;
+            let due = inner
+                .last_progress
+                .get(id)
+                .map_or(true, |at| at.elapsed() >= PROGRESS_INTERVAL);
+            if due || done {
+                inner.last_progress.insert(id.to_string(), Instant::now());
+            }
+            due || done
+        };
+        // Transient, so not journaled
+        if emit {
+            let _ = self.app.emit_all(
+                "operation_progress",
+                OperationProgress {
+                    id: id.to_string(),
+                    progress,
+                },
+            );
+        }
+    }
+
+    fn finish(&self, id: &str, result: Result<serde_json::Value>) {
+        let status = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.running.remove(id);
+            inner.last_progress.remove(id);
+            // Cancelled while finishing
+            let Some(status) = inner
+                .status_mut(id)
+                .filter(|s| s.state == OperationState::Running)
+            else {
+                return;
+            };
+            status.finished_at = Some(now_secs());
+            match result {
+                Ok(value) => {
+                    status.state = OperationState::Completed;
+                    status.result = Some(value);
+                }
+                Err(e) => {
+                    warn!("Operation {} failed: {:#}", id, e);
+                    status.state = OperationState::Failed;
+                    status.error = Some(format!("{:#}", e));
+                }
+            }
+            let status = status.clone();
+            inner.prune_finished();
+            status
+        };
+        events::emit(&self.app, "operation_updated", status);
+        self.dispatch();
+    }
+}
@@ -1,21 +1,71 @@
-use crate::crypto::{decrypt_message, encrypt_message, Identity};
+use crate::away_ping;
+use crate::burn::{BurnRegistry, DropBurned};
+use crate::canary::{self, CanaryAlert};
+use crate::catalog::{now_secs, DropCatalog};
+use crate::contacts::{self, Appearance, ContactBook};
+use crate::conversations;
+use crate::cover::{self, CoverSettings};
+use crate::crypto::{decode_public_key, decrypt_message, encrypt_message, Identity, SessionKey};
+use crate::devices::SignedRevocation;
+use crate::errors::{ControlError, ErrorCode};
+use crate::escrow::{self, SignedEscrowStatement};
+use crate::events;
+use crate::groups::{accept_group_shard, GroupShard, GroupStore};
+use crate::introductions::{introduce, verify_introduction, IntroductionStore, SignedIntroduction};
+use crate::key_log::{self, KeyKind, KeySource};
+use crate::knock::{auto_accepts, AutoAccept, KnockRecord, KnockStatus, KnockStore};
+use crate::location::{self, LiveEvent, LocationShare};
+use crate::mailbox::{self, Mailbox, MailboxCodec, MailboxRequest, MailboxResponse, MailboxSettings};
+use crate::mesh::{self, MeshCodec, MeshFrame, MeshSettings};
+use crate::message_drop::create_message_drop;
+use crate::nostr::{self, NostrSettings};
+use crate::notifications;
+use crate::plugins;
+use crate::polls::{self, PollMessage};
+use crate::remote_wipe::{self, SignedWipe};
+use crate::search;
+use crate::secrets::{self, WipeReport};
+use crate::session::{self, SuspendDetector};
+use crate::settings::{self, AppSettings};
+use crate::transcript::{self, TranscriptKind};
+use crate::tray;
+use crate::trust::{self, AttestationStore, SignedAttestation, Trust};
+use crate::usage::{self, UsageEvent};
+use crate::view_once;
+use crate::wire;
 use anyhow::{Context, Result};
 use futures::StreamExt;
 use libp2p::{
     dcutr,
     gossipsub::{self, IdentTopic, MessageAuthenticity, ValidationMode},
     identify, identity::Keypair, mdns, noise,
-    relay,
+    relay, request_response,
     swarm::{NetworkBehaviour, SwarmEvent},
+    multiaddr::Protocol,
     tcp, yamux, Multiaddr, PeerId, Swarm, Transport,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, hash_map::DefaultHasher};
+use std::collections::{HashMap, HashSet, hash_map::DefaultHasher};
 use std::hash::{Hash, Hasher};
-use std::time::Duration;
-use tauri::Window;
-use tokio::sync::mpsc;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::{Manager, Window};
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{error, info, warn};
 use x25519_dalek::PublicKey;
+use zeroize::Zeroize;
+
+const CONNECTIVITY_CHECK_SECS: u64 = 15;
+/// How long `connecting` is shown before giving up on outstanding dials
+const CONNECT_TIMEOUT_SECS: u64 = 30;
+const RECONNECT_DEBOUNCE_SECS: u64 = 2;
+/// Listen addresses reported this soon after start are the initial ones, not a network change
+const STARTUP_GRACE_SECS: u64 = 10;
+/// How often connected mailboxes are asked for mail kept while we were away
+const MAILBOX_FETCH_SECS: u64 = 120;
+/// How often nearby devices are asked again for envelopes queued for us
+const MESH_SYNC_SECS: u64 = 60;
 
 /// Commands sent to the P2P actor
 #[derive(Debug)]
@@ -24,10 +74,120 @@ pub enum P2PCommand {
         target_public_key: String,
         content: String,
         message_id: String, // UUID for tracking ACKs
+        view_once: bool,
+        location: Option<LocationShare>,
+    },
+    RequestBurnKey {
+        creator_public_key: String,
+        drop_id: String,
+        reply: oneshot::Sender<Result<SessionKey, String>>,
+    },
+    Status {
+        reply: oneshot::Sender<P2PStatus>,
+    },
+    /// Ask a new contact to accept our messages
+    SendContactRequest {
+        public_id: String,
+        note: String,
+        reply: oneshot::Sender<Result<KnockRecord, String>>,
+    },
+    RespondContactRequest {
+        public_id: String,
+        accept: bool,
+        reply: oneshot::Sender<Result<KnockRecord, String>>,
+    },
+    /// Send `recipient` a signed introduction of `contact`
+    SendIntroduction {
+        recipient: String,
+        contact: String,
+        note: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Send each member of a group drop who hasn't confirmed it their shard;
+    /// replies with the members it went out to
+    SendGroupShards {
+        cid: String,
+        reply: oneshot::Sender<Result<Vec<String>, String>>,
+    },
+    /// Tell every contact that one of our devices was revoked; replies with
+    /// the contacts it went out to
+    AnnounceRevocation {
+        notice: SignedRevocation,
+        reply: oneshot::Sender<Result<Vec<String>, String>>,
+    },
+    /// Send an attestation to verified contacts; replies with the contacts
+    /// it went out to
+    SendAttestation {
+        attestation: SignedAttestation,
+        recipients: Vec<String>,
+        reply: oneshot::Sender<Result<Vec<String>, String>>,
+    },
+    /// Send a signed wipe order to our own devices or to a contact who made
+    /// us their guardian
+    SendRemoteWipe {
+        recipient: String,
+        wipe: SignedWipe,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Deliver an envelope sealed ahead of time, e.g. by a dead-man's switch
+    SendSealed {
+        recipient: String,
+        envelope: Vec<u8>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Ask the approvers of an escrowed drop to release their shards,
+    /// returning those the request went out to
+    SendEscrowRequest {
+        request: SignedEscrowStatement,
+        approvers: Vec<String>,
+        reply: oneshot::Sender<Result<Vec<String>, String>>,
+    },
+    /// Answer a release request as one of its approvers
+    SendEscrowDecision {
+        recipient: String,
+        decision: SignedEscrowStatement,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Send a poll, vote or tally to each recipient, returning those it
+    /// went out to
+    SendPollMessage {
+        recipients: Vec<String>,
+        message: PollMessage,
+        reply: oneshot::Sender<Result<Vec<String>, String>>,
     },
     Shutdown,
 }
 
+/// Stops a P2P actor or a daemon connection. A `Shutdown` command waits
+/// behind everything already queued, and `try_send` drops it when the queue
+/// is full; this signal always gets through.
+#[derive(Clone)]
+pub struct ShutdownSignal(Arc<watch::Sender<bool>>);
+
+impl ShutdownSignal {
+    pub fn new() -> Self {
+        Self(Arc::new(watch::channel(false).0))
+    }
+
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+
+    /// Resolves once `trigger` has been called, including before this call
+    pub async fn triggered(&self) {
+        let _ = self.0.subscribe().wait_for(|stop| *stop).await;
+    }
+}
+
+impl Default for ShutdownSignal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Identify protocol version, also advertised in contact cards
+pub const PROTOCOL_VERSION: &str = "/deaddrop/1.0.0";
+
 /// Message structure for Ghost Mode with UUID for ACK tracking
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GhostMessage {
@@ -35,6 +195,27 @@ pub struct GhostMessage {
     pub from: String,
     pub content: String,
     pub timestamp: u64,
+    /// The recipient shows it once and then keeps only a tombstone. Older
+    /// clients don't know the flag and show it as a normal message.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub view_once: bool,
+    /// A shared location; older clients show only the content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<LocationShare>,
+}
+
+/// Payload of `ghost_msg`: the message, and how the sender's conversation
+/// is drawn if they are a contact
+#[derive(Serialize, Debug)]
+pub struct MessageEvent<'a> {
+    #[serde(flatten)]
+    pub message: &'a GhostMessage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub appearance: Option<Appearance>,
+    /// How far verified contacts vouch for the sender; unset for senders
+    /// we verified ourselves
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trust: Option<Trust>,
 }
 
 /// ACK/Receipt message
@@ -45,6 +226,45 @@ pub struct MessageReceipt {
     pub timestamp: u64,
 }
 
+/// Member's confirmation that their shard of a group drop arrived
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShardReceipt {
+    pub cid: String,
+    pub from: String,
+    pub timestamp: u64,
+}
+
+/// Request for the one-time unwrap key of a burn-after-read drop
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BurnKeyRequest {
+    pub drop_id: String,
+    pub from: String,
+}
+
+/// Creator's answer to a burn key request
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BurnKeyResponse {
+    pub drop_id: String,
+    pub unwrap_key: Option<String>, // hex, None if denied
+    pub error: Option<String>,
+}
+
+/// Knock sent before the first message to a new contact
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContactRequest {
+    pub from: String,
+    pub note: String,
+    pub timestamp: u64,
+}
+
+/// Answer to a contact request
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContactResponse {
+    pub from: String,
+    pub accepted: bool,
+    pub timestamp: u64,
+}
+
 /// Message type enum for routing
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
@@ -53,6 +273,32 @@ pub enum P2PMessage {
     Message(GhostMessage),
     #[serde(rename = "receipt")]
     Receipt(MessageReceipt),
+    #[serde(rename = "burn_key_request")]
+    BurnKeyRequest(BurnKeyRequest),
+    #[serde(rename = "burn_key_response")]
+    BurnKeyResponse(BurnKeyResponse),
+    #[serde(rename = "contact_request")]
+    ContactRequest(ContactRequest),
+    #[serde(rename = "contact_response")]
+    ContactResponse(ContactResponse),
+    #[serde(rename = "introduction")]
+    Introduction(SignedIntroduction),
+    #[serde(rename = "group_shard")]
+    GroupShard(GroupShard),
+    #[serde(rename = "shard_receipt")]
+    ShardReceipt(ShardReceipt),
+    #[serde(rename = "device_revoked")]
+    DeviceRevoked(SignedRevocation),
+    #[serde(rename = "remote_wipe")]
+    RemoteWipe(SignedWipe),
+    #[serde(rename = "attestation")]
+    Attestation(SignedAttestation),
+    #[serde(rename = "escrow_request")]
+    EscrowRequest(SignedEscrowStatement),
+    #[serde(rename = "escrow_decision")]
+    EscrowDecision(SignedEscrowStatement),
+    #[serde(rename = "poll")]
+    Poll(PollMessage),
 }
 
 /// P2P Network Behavior with Relay, Identify, and DCUtR
@@ -64,56 +310,368 @@ struct DeadDropBehaviour {
     dcutr: dcutr::Behaviour,
     identify: identify::Behaviour,
     ping: libp2p::ping::Behaviour,
+    mailbox: request_response::Behaviour<MailboxCodec>,
+    mesh: request_response::Behaviour<MeshCodec>,
 }
 
-/// Pending ACKs tracker
+/// Pending ACKs tracker; messages are kept for the drop fallback
 struct PendingAcks {
-    pending: HashMap<String, (String, u64)>, // message_id -> (target_public_key, timestamp)
+    pending: HashMap<String, (String, GhostMessage, u64)>, // message_id -> (target_public_key, message, sent_at)
+    /// Messages already sent through the Nostr relays
+    relayed: HashSet<String>,
+    /// Messages waiting in the mesh outbox for their recipient to come near
+    meshed: HashSet<String>,
 }
 
 impl PendingAcks {
     fn new() -> Self {
         Self {
             pending: HashMap::new(),
+            relayed: HashSet::new(),
+            meshed: HashSet::new(),
         }
     }
 
-    fn add(&mut self, message_id: String, target: String) {
+    fn add(&mut self, target: String, message: GhostMessage) {
         let timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs();
-        self.pending.insert(message_id, (target, timestamp));
+        self.pending.insert(message.id.clone(), (target, message, timestamp));
     }
 
-    fn remove(&mut self, message_id: &str) -> Option<(String, u64)> {
+    fn remove(&mut self, message_id: &str) -> Option<(String, GhostMessage, u64)> {
+        self.relayed.remove(message_id);
+        self.meshed.remove(message_id);
         self.pending.remove(message_id)
     }
 
-    fn cleanup_old(&mut self, max_age_secs: u64) {
+    fn mark_relayed(&mut self, message_id: &str) {
+        self.relayed.insert(message_id.to_string());
+    }
+
+    fn mark_meshed(&mut self, message_id: &str) {
+        self.meshed.insert(message_id.to_string());
+    }
+
+    /// Whether a message went into the mesh outbox, forgetting it
+    fn take_meshed(&mut self, message_id: &str) -> bool {
+        self.meshed.remove(message_id)
+    }
+
+    /// Whether a message went through the Nostr relays, forgetting it
+    fn take_relayed(&mut self, message_id: &str) -> bool {
+        self.relayed.remove(message_id)
+    }
+
+    fn contains(&self, message_id: &str) -> bool {
+        self.pending.contains_key(message_id)
+    }
+
+    /// Drop ACKs older than `max_age_secs`, returning the expired (target, message) pairs
+    fn cleanup_old(&mut self, max_age_secs: u64) -> Vec<(String, GhostMessage)> {
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs();
 
-        self.pending.retain(|_, (_, timestamp)| {
-            now - *timestamp < max_age_secs
+        let expired: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, _, timestamp))| now - *timestamp >= max_age_secs)
+            .map(|(message_id, _)| message_id.clone())
+            .collect();
+        expired
+            .iter()
+            .filter_map(|message_id| self.pending.remove(message_id))
+            .map(|(target, message, _)| (target, message))
+            .collect()
+    }
+}
+
+/// Burn-after-read coordination state owned by the actor
+struct BurnState {
+    data_dir: PathBuf,
+    pending: HashMap<String, oneshot::Sender<Result<SessionKey, String>>>, // drop_id -> reply
+}
+
+impl BurnState {
+    /// Release a burn key we hold, exactly once
+    fn release(
+        &self,
+        identity: &Identity,
+        events: &dyn P2PEvents,
+        drop_id: &str,
+        requester: &str,
+    ) -> BurnKeyResponse {
+        let result = BurnRegistry::load(&self.data_dir, identity).and_then(|mut registry| {
+            let released = registry.consume(drop_id)?;
+            registry.save()?;
+            Ok(released)
         });
+
+        match result {
+            Ok((key, info)) => {
+                let _ = events.emit(
+                    "drop_burned",
+                    DropBurned {
+                        drop_id: drop_id.to_string(),
+                        cid: info.cid,
+                        burned_at: info.burned_at,
+                        consumed_by: requester.to_string(),
+                    },
+                );
+                let mut key_bytes = key.as_bytes();
+                let unwrap_key = hex::encode(key_bytes);
+                key_bytes.zeroize();
+                BurnKeyResponse {
+                    drop_id: drop_id.to_string(),
+                    unwrap_key: Some(unwrap_key),
+                    error: None,
+                }
+            }
+            Err(e) => BurnKeyResponse {
+                drop_id: drop_id.to_string(),
+                unwrap_key: None,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// Relay servers dialed for NAT traversal, editable in the settings store
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct P2PSettings {
+    pub relays: Vec<String>, // multiaddrs, e.g. /ip4/1.2.3.4/tcp/4001/p2p/12D3KooW...
+    /// Start Ghost Mode as soon as the session is unlocked
+    #[serde(default)]
+    pub auto_start: bool,
+    /// Contact requests accepted without asking
+    #[serde(default)]
+    pub auto_accept: AutoAccept,
+    /// Package undelivered messages as dead drops and offer the link
+    #[serde(default)]
+    pub drop_fallback: bool,
+    #[serde(default)]
+    pub mailbox: MailboxSettings,
+    /// Nostr relays that carry envelopes no libp2p peer took
+    #[serde(default)]
+    pub nostr: NostrSettings,
+    /// Reach a relay through HTTPS to a front when raw p2p traffic is blocked
+    #[serde(default)]
+    pub cover: CoverSettings,
+    /// Hand envelopes to trusted devices on the local network when offline
+    #[serde(default)]
+    pub mesh: MeshSettings,
+    /// Send messages and write manifests as JSON, which releases before the
+    /// CBOR wire format can read. Goes away with the JSON fallback.
+    #[serde(default)]
+    pub legacy_json_wire: bool,
+}
+
+impl P2PSettings {
+    pub fn validate(&self) -> Result<()> {
+        for relay in &self.relays {
+            relay
+                .parse::<Multiaddr>()
+                .with_context(|| format!("Invalid relay address: {}", relay))?;
+        }
+        self.mailbox.validate()?;
+        self.nostr.validate()?;
+        self.cover.validate()?;
+        self.mesh.validate()
+    }
+}
+
+/// Whether Ghost Mode can currently reach anyone
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectivityState {
+    Offline,
+    Connecting,
+    Online,
+}
+
+/// Payload of the `connectivity` event, emitted on every state transition
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Connectivity {
+    pub state: ConnectivityState,
+    pub peers: usize,
+    pub relays: usize,
+}
+
+/// Reply to `P2PCommand::Status`
+#[derive(Debug, Clone)]
+pub struct P2PStatus {
+    pub connectivity: Connectivity,
+    pub reserved_relays: Vec<PeerId>,
+}
+
+/// Connected peers and relay reservations, and when to redial relays
+struct ConnectivityTracker {
+    peers: HashSet<PeerId>,
+    relays: HashSet<PeerId>,
+    connecting_until: Option<Instant>,
+    reconnect_at: Option<Instant>,
+    started: Instant,
+    last_state: Option<ConnectivityState>,
+}
+
+impl ConnectivityTracker {
+    fn new() -> Self {
+        Self {
+            peers: HashSet::new(),
+            relays: HashSet::new(),
+            connecting_until: None,
+            reconnect_at: None,
+            started: Instant::now(),
+            last_state: None,
+        }
+    }
+
+    fn state(&self) -> ConnectivityState {
+        if !self.peers.is_empty() {
+            ConnectivityState::Online
+        } else if self.connecting_until.is_some() {
+            ConnectivityState::Connecting
+        } else {
+            ConnectivityState::Offline
+        }
+    }
+
+    /// Dials are in flight; show `connecting` until something answers or it times out
+    fn dialing(&mut self) {
+        self.connecting_until = Some(Instant::now() + Duration::from_secs(CONNECT_TIMEOUT_SECS));
+    }
+
+    /// Redial shortly, collapsing bursts of interface or connection changes into one
+    fn schedule_reconnect(&mut self) {
+        self.reconnect_at
+            .get_or_insert_with(|| Instant::now() + Duration::from_secs(RECONNECT_DEBOUNCE_SECS));
+    }
+
+    fn reconnect_due(&mut self) -> bool {
+        match self.reconnect_at {
+            Some(at) if at <= Instant::now() => {
+                self.reconnect_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn status(&self) -> P2PStatus {
+        P2PStatus {
+            connectivity: Connectivity {
+                state: self.state(),
+                peers: self.peers.len(),
+                relays: self.relays.len(),
+            },
+            reserved_relays: self.relays.iter().copied().collect(),
+        }
+    }
+
+    /// Emit `connectivity` if the state changed since the last call
+    fn publish(&mut self, events: &dyn P2PEvents) {
+        if self.connecting_until.is_some_and(|until| until <= Instant::now()) {
+            self.connecting_until = None;
+        }
+        let state = self.state();
+        if self.last_state == Some(state) {
+            return;
+        }
+        self.last_state = Some(state);
+        info!("Connectivity: {:?}", state);
+        let _ = events.emit(
+            "connectivity",
+            Connectivity {
+                state,
+                peers: self.peers.len(),
+                relays: self.relays.len(),
+            },
+        );
+    }
+}
+
+/// Receives what the P2P actor reports: the Tauri window in the app, stdout in the CLI
+pub trait P2PEvents: Send + Sync + 'static {
+    fn emit_event(&self, event: &str, payload: serde_json::Value) -> Result<()>;
+
+    /// Called for each incoming message after `ghost_msg` is emitted
+    fn message_received(&self, _message: &GhostMessage) {}
+
+    /// Called for each undelivered message after `msg_failed` is emitted
+    fn message_failed(&self, _contact: &str, _message_id: &str, _reason: &str) {}
+
+    /// Called for each tripped canary after `canary_alert` is emitted
+    fn canary_tripped(&self, _alert: &CanaryAlert) {}
+
+    /// Called once a remote wipe ran, after `remote_wiped` is emitted
+    fn remote_wiped(&self, _report: &WipeReport) {}
+}
+
+impl dyn P2PEvents {
+    pub fn emit<T: Serialize>(&self, event: &str, payload: T) -> Result<()> {
+        self.emit_event(event, serde_json::to_value(payload)?)
+    }
+}
+
+impl P2PEvents for Window {
+    fn emit_event(&self, event: &str, payload: serde_json::Value) -> Result<()> {
+        events::emit_value(&self.app_handle(), event, payload);
+        Ok(())
+    }
+
+    fn message_received(&self, message: &GhostMessage) {
+        conversations::message_received(self, message);
+        notifications::message_received(self, message);
+        tray::message_received(self);
+        if !notifications::in_front(self) {
+            away_ping::message_waiting();
+        }
+        usage::record(UsageEvent::MessageReceived {
+            bytes: message.content.len(),
+        });
+    }
+
+    fn message_failed(&self, contact: &str, message_id: &str, reason: &str) {
+        notifications::message_failed(self, contact, message_id, reason);
+    }
+
+    fn canary_tripped(&self, alert: &CanaryAlert) {
+        notifications::canary_alert(self, alert);
+        if settings::canary_settings().auto_lock {
+            session::lock_session(&self.app_handle(), "canary");
+        }
+    }
+
+    fn remote_wiped(&self, _report: &WipeReport) {
+        session::lock_session(&self.app_handle(), "remote_wipe");
     }
 }
 
+/// Outgoing replies queued while handling an event: (recipient_key, recipient_id, message)
+type ReplyQueue = Vec<(PublicKey, String, P2PMessage)>;
+
 /// Initialize P2P actor with the Actor Model pattern
-/// Returns a channel sender to communicate with the actor
-pub fn init_p2p_actor(identity: Identity, window: Window) -> Result<mpsc::Sender<P2PCommand>> {
+/// Returns a channel sender to communicate with the actor; `shutdown` stops it
+pub fn init_p2p_actor(
+    identity: Identity,
+    events: Arc<dyn P2PEvents>,
+    data_dir: PathBuf,
+    shutdown: &ShutdownSignal,
+) -> Result<mpsc::Sender<P2PCommand>> {
+    settings::check_loaded()?;
     let (tx, mut rx) = mpsc::channel::<P2PCommand>(100);
 
     // Clone identity for the actor thread
     let actor_identity = identity.clone();
     let public_id = identity.public_id();
+    let shutdown = shutdown.clone();
 
     tokio::spawn(async move {
-        if let Err(e) = run_p2p_actor(actor_identity, public_id, &mut rx, window).await {
-            eprintln!("P2P Actor error: {}", e);
+        if let Err(e) = run_p2p_actor(actor_identity, public_id, &mut rx, shutdown, events, data_dir).await {
+            error!("P2P Actor error: {}", e);
         }
     });
 
@@ -125,13 +683,15 @@ async fn run_p2p_actor(
     identity: Identity,
     public_id: String,
     rx: &mut mpsc::Receiver<P2PCommand>,
-    window: Window,
+    shutdown: ShutdownSignal,
+    events: Arc<dyn P2PEvents>,
+    data_dir: PathBuf,
 ) -> Result<()> {
     // Create libp2p identity from random keypair (separate from X25519)
     let local_key = Keypair::generate_ed25519();
     let local_peer_id = PeerId::from(local_key.public());
-    println!("Local PeerID: {}", local_peer_id);
-    println!("Public Identity: {}", public_id);
+    info!("Local PeerID: {}", local_peer_id);
+    info!("Public Identity: {}", public_id);
 
     // Build transport with relay support
     let (relay_transport, relay_client) = relay::client::new(local_peer_id);
@@ -166,14 +726,14 @@ async fn run_p2p_actor(
     // Subscribe to personal inbox topic
     let inbox_topic = IdentTopic::new(format!("/deaddrop/inbox/{}", public_id));
     gossipsub.subscribe(&inbox_topic)?;
-    println!("Subscribed to topic: {}", inbox_topic);
+    info!("Subscribed to topic: {}", inbox_topic);
 
     // Create mDNS for local peer discovery
     let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id)?;
 
     // Create Identify protocol for peer information exchange
     let identify = identify::Behaviour::new(identify::Config::new(
-        "/deaddrop/1.0.0".to_string(),
+        PROTOCOL_VERSION.to_string(),
         local_key.public(),
     ));
 
@@ -191,6 +751,8 @@ async fn run_p2p_actor(
         dcutr,
         identify,
         ping,
+        mailbox: mailbox::behaviour(),
+        mesh: mesh::behaviour(),
     };
 
     let mut swarm = Swarm::new(
@@ -203,24 +765,41 @@ async fn run_p2p_actor(
     // Listen on all interfaces
     swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
 
-    // Connect to public relay servers for NAT traversal
-    // These are example relay addresses - in production, use your own or public relays
-    let relay_addresses: Vec<&str> = vec![
-        // Add public relay multiaddrs here when available
-        // Example: "/ip4/relay.example.com/tcp/4001/p2p/12D3KooW..."
-    ];
-
-    for addr_str in relay_addresses {
-        if let Ok(addr) = addr_str.parse::<Multiaddr>() {
-            if let Err(e) = swarm.dial(addr.clone()) {
-                eprintln!("Failed to dial relay {}: {}", addr, e);
-            } else {
-                println!("Dialing relay: {}", addr);
-            }
-        }
+    // Connect to configured relay servers for NAT traversal; relays added
+    // to the settings later are dialed as soon as they are saved
+    let mut connectivity = ConnectivityTracker::new();
+    let mut dialed_relays = HashSet::new();
+    if dial_relays(&mut swarm, &settings::p2p_settings(), &mut dialed_relays) {
+        connectivity.dialing();
+    }
+
+    // Keep mail for the inboxes we serve as a mailbox
+    let mut mailbox = Mailbox::load(&data_dir, &identity)?;
+    let mut served_inboxes = HashSet::new();
+    follow_served_inboxes(&mut swarm, &settings::p2p_settings().mailbox, &mut served_inboxes);
+    let mut mailbox_check = tokio::time::interval(Duration::from_secs(MAILBOX_FETCH_SECS));
+
+    // Envelopes waiting for trusted devices that show up on the local network
+    mesh::open(&data_dir, &identity)?;
+    let mut nearby: HashSet<PeerId> = HashSet::new();
+    let mut mesh_sync = tokio::time::interval(Duration::from_secs(MESH_SYNC_SECS));
+
+    // Message processors from plugins/, run once the user grants them capabilities
+    if let Err(e) = plugins::load(&data_dir) {
+        warn!("Plugins unavailable: {}", e);
     }
 
-    println!("P2P Actor started successfully with Relay & Identify support");
+    // Envelopes addressed to us on the Nostr relays, when the fallback is on
+    let mut nostr_inbound = nostr::start_poller(identity.clone(), data_dir.clone());
+
+    connectivity.publish(events.as_ref());
+    let mut suspend = SuspendDetector::new(Duration::from_secs(CONNECTIVITY_CHECK_SECS));
+    let mut connectivity_check =
+        tokio::time::interval(Duration::from_secs(CONNECTIVITY_CHECK_SECS));
+    let mut settings_rx = settings::subscribe()
+        .unwrap_or_else(|| watch::channel(AppSettings::default()).1);
+
+    info!("P2P Actor started successfully with Relay & Identify support");
 
     // Track pending ACKs
     let mut pending_acks = PendingAcks::new();
@@ -228,63 +807,417 @@ async fn run_p2p_actor(
     // Queue for receipts to send
     let mut receipt_queue: ReceiptQueue = Vec::new();
 
+    // Queue for other replies (burn keys)
+    let mut reply_queue: ReplyQueue = Vec::new();
+
+    // Messages waiting for their recipient to show up through a relay hint
+    let mut awaiting_peer: Vec<(String, GhostMessage)> = Vec::new();
+
+    let mut burn_state = BurnState {
+        data_dir,
+        pending: HashMap::new(),
+    };
+
     // Main event loop
     loop {
         tokio::select! {
+            // A locked session stops the actor even when the command queue is full
+            _ = shutdown.triggered() => {
+                shut_down(&mut connectivity, events.as_ref());
+                break;
+            }
+
             // Handle incoming P2P events
             event = swarm.select_next_some() => {
-                if let Err(e) = handle_swarm_event(
+                match &event {
+                    SwarmEvent::Behaviour(DeadDropBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Subscribed { topic, .. },
+                    )) => {
+                        send_awaiting(&mut swarm, &identity, topic.as_str(), &mut awaiting_peer, &pending_acks);
+                    }
+                    // Collect what a mailbox kept while we were away
+                    SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                        let mailbox_settings = settings::p2p_settings().mailbox;
+                        if let Err(e) = Mailbox::fetch(&mut swarm.behaviour_mut().mailbox, &identity, &mailbox_settings, peer_id, Vec::new()) {
+                            warn!("Failed to fetch from mailbox {}: {}", peer_id, e);
+                        }
+                    }
+                    // Devices on the local network may hold envelopes for us
+                    SwarmEvent::Behaviour(DeadDropBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                        for (peer_id, _) in peers {
+                            if nearby.insert(*peer_id) && settings::p2p_settings().mesh.enabled {
+                                if let Err(e) = mesh::hello(&mut swarm.behaviour_mut().mesh, peer_id, &identity) {
+                                    warn!("Failed to sync with nearby device {}: {}", peer_id, e);
+                                }
+                            }
+                        }
+                    }
+                    SwarmEvent::Behaviour(DeadDropBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+                        for (peer_id, _) in peers {
+                            nearby.remove(peer_id);
+                        }
+                    }
+                    _ => {}
+                }
+                if let SwarmEvent::Behaviour(DeadDropBehaviourEvent::Mailbox(event)) = event {
+                    handle_mailbox_event(
+                        &mut swarm,
+                        event,
+                        &identity,
+                        events.as_ref(),
+                        &mut mailbox,
+                        &mut pending_acks,
+                        &mut receipt_queue,
+                        &mut reply_queue,
+                        &mut burn_state,
+                    );
+                } else if let SwarmEvent::Behaviour(DeadDropBehaviourEvent::Mesh(event)) = event {
+                    handle_mesh_event(
+                        &mut swarm,
+                        event,
+                        &identity,
+                        events.as_ref(),
+                        &mut mailbox,
+                        &mut pending_acks,
+                        &mut receipt_queue,
+                        &mut reply_queue,
+                        &mut burn_state,
+                    );
+                } else if let Err(e) = handle_swarm_event(
                     event,
                     &identity,
-                    &window,
+                    events.as_ref(),
                     &mut pending_acks,
                     &mut receipt_queue,
+                    &mut reply_queue,
+                    &mut burn_state,
+                    &mut connectivity,
+                    &mut mailbox,
                 ).await {
-                    eprintln!("Error handling swarm event: {}", e);
-                }
-                
-                // Process queued receipts
-                while let Some((sender_pk, msg_id, sender_id)) = receipt_queue.pop() {
-                    if let Err(e) = send_receipt(
-                        &mut swarm,
-                        &identity,
-                        &sender_pk,
-                        &msg_id,
-                        &sender_id,
-                    ) {
-                        eprintln!("Failed to send receipt: {}", e);
-                    }
+                    error!("Error handling swarm event: {}", e);
                 }
+                connectivity.publish(events.as_ref());
+                flush_queues(&mut swarm, &identity, &mut receipt_queue, &mut reply_queue);
             }
 
             // Handle incoming commands from application
             Some(cmd) = rx.recv() => {
                 match cmd {
-                    P2PCommand::SendMessage { target_public_key, content, message_id } => {
-                        // Track this message for ACK
-                        pending_acks.add(message_id.clone(), target_public_key.clone());
+                    P2PCommand::SendMessage { target_public_key, mut content, message_id, view_once, location } => {
+                        plugins::outgoing(&target_public_key, &message_id, &mut content, events.as_ref());
 
-                        if let Err(e) = send_ghost_message(
+                        // Their preferred relays first, so we can meet behind NATs
+                        let hinted = dial_relay_hints(
                             &mut swarm,
+                            &burn_state.data_dir,
+                            &identity,
+                            &target_public_key,
+                            &mut dialed_relays,
+                            &mut connectivity,
+                        );
+
+                        let ghost_msg = GhostMessage {
+                            id: message_id.clone(),
+                            from: identity.public_id(),
+                            content,
+                            timestamp: now_secs(),
+                            view_once,
+                            location,
+                        };
+
+                        // New contacts get a contact request first; the message waits for the answer
+                        let allowed = may_send(
+                            &burn_state.data_dir,
                             &identity,
+                            events.as_ref(),
                             &target_public_key,
-                            &content,
-                            &message_id,
-                        ) {
-                            eprintln!("Failed to send message: {}", e);
-                            let _ = window.emit("ghost_error", format!("Send failed: {}", e));
+                            &ghost_msg,
+                            &mut pending_acks,
+                            &mut receipt_queue,
+                            &mut reply_queue,
+                        );
+                        match allowed {
+                            Ok(true) => {}
+                            Ok(false) => {
+                                flush_queues(&mut swarm, &identity, &mut receipt_queue, &mut reply_queue);
+                                continue;
+                            }
+                            Err(e) => {
+                                error!("Failed to check contact request state: {}", e);
+                                message_failed(events.as_ref(), &message_id, &target_public_key, &e.to_string());
+                                continue;
+                            }
+                        }
+                        flush_queues(&mut swarm, &identity, &mut receipt_queue, &mut reply_queue);
+
+                        // Live-location updates are never kept, like on the receiving side
+                        if !ghost_msg.location.as_ref().is_some_and(LocationShare::is_live) {
+                            transcript::record(&burn_state.data_dir, &identity, &target_public_key, TranscriptKind::Sent, &ghost_msg);
+                            search::record(&burn_state.data_dir, &identity, &target_public_key, true, &ghost_msg);
+                        }
+                        // Track this message for ACK
+                        pending_acks.add(target_public_key.clone(), ghost_msg.clone());
+
+                        match send_ghost_message(&mut swarm, &identity, &target_public_key, &ghost_msg) {
+                            Ok(Route::Gossip) => {}
+                            Ok(Route::Nostr) => {
+                                pending_acks.mark_relayed(&message_id);
+                                let _ = events.emit(
+                                    "msg_relayed",
+                                    serde_json::json!({
+                                        "message_id": message_id,
+                                        "target": target_public_key,
+                                    }),
+                                );
+                            }
+                            Ok(Route::Mesh) => {
+                                pending_acks.mark_meshed(&message_id);
+                                let _ = events.emit(
+                                    "msg_queued",
+                                    serde_json::json!({
+                                        "message_id": message_id,
+                                        "target": target_public_key,
+                                    }),
+                                );
+                            }
+                            Err(e) if hinted => {
+                                // Sent once they subscribe, or failed when the ACK times out
+                                info!("Waiting for {} through their relay hints: {}", target_public_key, e);
+                                awaiting_peer.push((target_public_key, ghost_msg));
+                            }
+                            Err(e) => {
+                                error!("Failed to send message: {}", e);
+                                let _ = events.emit(
+                                    "ghost_error",
+                                    ControlError::localized(ErrorCode::NetworkUnavailable, "error.send")
+                                        .with_cause(&e),
+                                );
+                                pending_acks.remove(&message_id);
+                                delivery_failed(&events, &identity, &target_public_key, ghost_msg, &e.to_string());
+                            }
+                        }
+                    }
+                    P2PCommand::RequestBurnKey { creator_public_key, drop_id, reply } => {
+                        let request = P2PMessage::BurnKeyRequest(BurnKeyRequest {
+                            drop_id: drop_id.clone(),
+                            from: identity.public_id(),
+                        });
+                        let sent = decode_public_key(&creator_public_key).and_then(|creator_pk| {
+                            publish_p2p_message(
+                                &mut swarm,
+                                &identity,
+                                &creator_pk,
+                                &creator_public_key,
+                                &request,
+                            )
+                        });
+                        match sent {
+                            Ok(_) => {
+                                burn_state.pending.insert(drop_id, reply);
+                            }
+                            Err(e) => {
+                                let _ = reply.send(Err(format!("Burn key request failed: {}", e)));
+                            }
                         }
                     }
+                    P2PCommand::Status { reply } => {
+                        let _ = reply.send(connectivity.status());
+                    }
+                    P2PCommand::SendContactRequest { public_id, note, reply } => {
+                        let result = send_contact_request(
+                            &burn_state.data_dir,
+                            &identity,
+                            events.as_ref(),
+                            &public_id,
+                            &note,
+                            &mut pending_acks,
+                            &mut receipt_queue,
+                            &mut reply_queue,
+                        );
+                        let _ = reply.send(result.map_err(|e| e.to_string()));
+                        flush_queues(&mut swarm, &identity, &mut receipt_queue, &mut reply_queue);
+                    }
+                    P2PCommand::RespondContactRequest { public_id, accept, reply } => {
+                        let result = respond_contact_request(
+                            &burn_state.data_dir,
+                            &identity,
+                            events.as_ref(),
+                            &public_id,
+                            accept,
+                            &mut pending_acks,
+                            &mut receipt_queue,
+                            &mut reply_queue,
+                        );
+                        let _ = reply.send(result.map_err(|e| e.to_string()));
+                        flush_queues(&mut swarm, &identity, &mut receipt_queue, &mut reply_queue);
+                    }
+                    P2PCommand::SendIntroduction { recipient, contact, note, reply } => {
+                        let result = send_introduction(
+                            &mut swarm,
+                            &burn_state.data_dir,
+                            &identity,
+                            &recipient,
+                            &contact,
+                            &note,
+                        );
+                        let _ = reply.send(result.map_err(|e| e.to_string()));
+                    }
+                    P2PCommand::SendGroupShards { cid, reply } => {
+                        let result = send_group_shards(&mut swarm, &burn_state.data_dir, &identity, &cid);
+                        let _ = reply.send(result.map_err(|e| e.to_string()));
+                    }
+                    P2PCommand::AnnounceRevocation { notice, reply } => {
+                        let result = announce_revocation(&mut swarm, &burn_state.data_dir, &identity, notice);
+                        let _ = reply.send(result.map_err(|e| e.to_string()));
+                    }
+                    P2PCommand::SendAttestation { attestation, recipients, reply } => {
+                        let sent = send_attestation(&mut swarm, &identity, attestation, &recipients);
+                        let _ = reply.send(Ok(sent));
+                    }
+                    P2PCommand::SendRemoteWipe { recipient, wipe, reply } => {
+                        let result = decode_public_key(&recipient).and_then(|recipient_pk| {
+                            publish_p2p_message(&mut swarm, &identity, &recipient_pk, &recipient, &P2PMessage::RemoteWipe(wipe))
+                        });
+                        let _ = reply.send(result.map(|_| ()).map_err(|e| e.to_string()));
+                    }
+                    P2PCommand::SendSealed { recipient, envelope, reply } => {
+                        let result = decode_public_key(&recipient).and_then(|recipient_pk| {
+                            publish_sealed(&mut swarm, &recipient_pk, &recipient, envelope)
+                        });
+                        let _ = reply.send(result.map(|_| ()).map_err(|e| e.to_string()));
+                    }
+                    P2PCommand::SendEscrowRequest { request, approvers, reply } => {
+                        let sent = send_escrow_request(&mut swarm, &identity, request, &approvers);
+                        let _ = reply.send(Ok(sent));
+                    }
+                    P2PCommand::SendEscrowDecision { recipient, decision, reply } => {
+                        let result = decode_public_key(&recipient).and_then(|recipient_pk| {
+                            publish_p2p_message(&mut swarm, &identity, &recipient_pk, &recipient, &P2PMessage::EscrowDecision(decision))
+                        });
+                        let _ = reply.send(result.map(|_| ()).map_err(|e| e.to_string()));
+                    }
+                    P2PCommand::SendPollMessage { recipients, message, reply } => {
+                        let sent = send_poll_message(&mut swarm, &identity, message, &recipients);
+                        let _ = reply.send(Ok(sent));
+                    }
                     P2PCommand::Shutdown => {
-                        println!("P2P Actor shutting down");
+                        shut_down(&mut connectivity, events.as_ref());
+                        break;
+                    }
+                }
+            }
+
+            // Pick up settings changes live
+            Ok(()) = settings_rx.changed() => {
+                let p2p_settings = settings_rx.borrow_and_update().p2p.clone();
+                if dial_relays(&mut swarm, &p2p_settings, &mut dialed_relays) {
+                    connectivity.dialing();
+                }
+                follow_served_inboxes(&mut swarm, &p2p_settings.mailbox, &mut served_inboxes);
+            }
+
+            // Drain connected mailboxes and expire mail kept for others
+            _ = mailbox_check.tick() => {
+                let mailbox_settings = settings::p2p_settings().mailbox;
+                mailbox.prune(&mailbox_settings);
+                for peer in &mailbox_settings.peers {
+                    let Some(peer_id) = relay_peer_id(&peer.address) else {
+                        continue;
+                    };
+                    if swarm.is_connected(&peer_id) {
+                        if let Err(e) = Mailbox::fetch(&mut swarm.behaviour_mut().mailbox, &identity, &mailbox_settings, &peer_id, Vec::new()) {
+                            warn!("Failed to fetch from mailbox {}: {}", peer_id, e);
+                        }
+                    }
+                }
+            }
+
+            // Ask nearby devices again, expire what waited too long and
+            // deliver what we carry
+            _ = mesh_sync.tick() => {
+                mesh::prune();
+                // Carried envelopes go out once their recipient or one of
+                // their mailboxes is reachable
+                mesh::deliver_carried(|recipient, data| {
+                    let topic = IdentTopic::new(format!("/deaddrop/inbox/{}", recipient));
+                    swarm.behaviour_mut().gossipsub.publish(topic, data.to_vec()).is_ok()
+                });
+                if settings::p2p_settings().mesh.enabled {
+                    for peer_id in &nearby {
+                        if let Err(e) = mesh::hello(&mut swarm.behaviour_mut().mesh, peer_id, &identity) {
+                            warn!("Failed to sync with nearby device {}: {}", peer_id, e);
+                        }
+                    }
+                }
+            }
+
+            // Envelopes the Nostr relays kept for us
+            Some(data) = nostr_inbound.recv() => {
+                if mailbox.first_seen(&data) {
+                    if let Err(e) = handle_incoming_p2p_message(
+                        &data,
+                        &identity,
+                        events.as_ref(),
+                        &mut pending_acks,
+                        &mut receipt_queue,
+                        &mut reply_queue,
+                        &mut burn_state,
+                    ) {
+                        error!("Failed to handle Nostr message: {}", e);
+                    }
+                    flush_queues(&mut swarm, &identity, &mut receipt_queue, &mut reply_queue);
+                }
+            }
+
+            // Redial after sleep or network changes, and time out `connecting`
+            _ = connectivity_check.tick() => {
+                if suspend.tick() {
+                    info!("Woke from sleep, reconnecting to relays");
+                    reconnect(&mut swarm, &mut dialed_relays, &mut connectivity);
+                } else if connectivity.reconnect_due() {
+                    info!("Network changed, reconnecting to relays");
+                    reconnect(&mut swarm, &mut dialed_relays, &mut connectivity);
+                }
+                connectivity.publish(events.as_ref());
+
+                for session in location::expire() {
+                    let _ = events.emit("live_location_stopped", &session);
+                }
+
+                // A remote wipe whose confirmation delay is over
+                match remote_wipe::due(&burn_state.data_dir, &identity) {
+                    Ok(Some(pending)) => {
+                        warn!("Wiping this device as ordered by {}", pending.order.issuer);
+                        mesh::close();
+                        let report = secrets::panic_wipe(&burn_state.data_dir);
+                        let _ = events.emit("remote_wiped", &report);
+                        events.remote_wiped(&report);
                         break;
                     }
+                    Ok(None) => {}
+                    Err(e) => warn!("Failed to check for a remote wipe: {}", e),
                 }
             }
 
             // Periodic cleanup of old pending ACKs (every 60 seconds)
             _ = tokio::time::sleep(Duration::from_secs(60)) => {
-                pending_acks.cleanup_old(300); // Remove ACKs older than 5 minutes
+                // ACKs older than 5 minutes count as failed deliveries
+                for (target, message) in pending_acks.cleanup_old(300) {
+                    // Still waiting for its recipient to come near
+                    if pending_acks.take_meshed(&message.id) && mesh::waiting_for(&target) {
+                        pending_acks.add(target, message.clone());
+                        pending_acks.mark_meshed(&message.id);
+                        continue;
+                    }
+                    // One more try through the Nostr relays before giving up
+                    if !pending_acks.take_relayed(&message.id) && relay_through_nostr(&identity, &target, &message) {
+                        info!("No receipt for {}, sent it through Nostr", message.id);
+                        pending_acks.add(target, message.clone());
+                        pending_acks.mark_relayed(&message.id);
+                        continue;
+                    }
+                    delivery_failed(&events, &identity, &target, message, "No delivery receipt");
+                }
             }
         }
     }
@@ -292,17 +1225,346 @@ async fn run_p2p_actor(
     Ok(())
 }
 
-/// Handle Swarm events including Relay, Identify, and DCUtR
-async fn handle_swarm_event<THandlerErr>(
-    event: SwarmEvent<DeadDropBehaviourEvent, THandlerErr>,
+fn shut_down(connectivity: &mut ConnectivityTracker, events: &dyn P2PEvents) {
+    info!("P2P Actor shutting down");
+    connectivity.peers.clear();
+    connectivity.connecting_until = None;
+    connectivity.publish(events);
+    mesh::close();
+}
+
+/// Report a message that was not delivered as `msg_failed`
+fn message_failed(events: &dyn P2PEvents, message_id: &str, target: &str, reason: &str) {
+    let _ = events.emit(
+        "msg_failed",
+        serde_json::json!({
+            "message_id": message_id,
+            "target": target,
+            "reason": reason,
+        }),
+    );
+    events.message_failed(target, message_id, reason);
+}
+
+/// Report an undelivered message. With the drop fallback on, it is also
+/// packaged as a message drop in the background and the link emitted as
+/// `msg_fallback` for sharing out of band.
+fn delivery_failed(
+    events: &Arc<dyn P2PEvents>,
+    identity: &Identity,
+    target: &str,
+    message: GhostMessage,
+    reason: &str,
+) {
+    message_failed(events.as_ref(), &message.id, target, reason);
+    if !settings::p2p_settings().drop_fallback {
+        return;
+    }
+
+    let events = events.clone();
+    let identity = identity.clone();
+    let target = target.to_string();
+    tokio::spawn(async move {
+        match create_message_drop(&identity, &target, &message).await {
+            Ok(drop) => {
+                let _ = events.emit("msg_fallback", &drop);
+            }
+            Err(e) => warn!("Failed to package message {} as a drop: {}", message.id, e),
+        }
+    });
+}
+
+/// Dial relays that have not been dialed yet; returns whether any dial started
+fn dial_relays(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    p2p_settings: &P2PSettings,
+    dialed: &mut HashSet<String>,
+) -> bool {
+    let mut addrs = Vec::new();
+    if p2p_settings.cover.direct() {
+        addrs.extend(p2p_settings.relays.iter().cloned());
+        addrs.extend(p2p_settings.mailbox.addresses());
+    }
+    if p2p_settings.cover.enabled {
+        match cover::dial_addr(&p2p_settings.cover) {
+            Ok(addr) => addrs.push(addr),
+            Err(e) => warn!("HTTPS cover unavailable: {}", e),
+        }
+    }
+    dial_addrs(swarm, &addrs, dialed)
+}
+
+/// Follow the inbox topics of everyone we keep mail for, and stop following
+/// those removed from the settings
+fn follow_served_inboxes(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    mailbox_settings: &MailboxSettings,
+    served: &mut HashSet<String>,
+) {
+    let wanted: HashSet<String> = mailbox_settings.serve_for.iter().cloned().collect();
+    for owner in served.difference(&wanted) {
+        let topic = IdentTopic::new(format!("/deaddrop/inbox/{}", owner));
+        if let Err(e) = swarm.behaviour_mut().gossipsub.unsubscribe(&topic) {
+            warn!("Failed to leave inbox of {}: {}", owner, e);
+        }
+    }
+    for owner in wanted.difference(served) {
+        let topic = IdentTopic::new(format!("/deaddrop/inbox/{}", owner));
+        match swarm.behaviour_mut().gossipsub.subscribe(&topic) {
+            Ok(_) => info!("Mailbox: keeping mail for {}", owner),
+            Err(e) => warn!("Failed to follow inbox of {}: {}", owner, e),
+        }
+    }
+    *served = wanted;
+}
+
+/// Serve fetches from inbox owners, and deliver what our own mailboxes send
+#[allow(clippy::too_many_arguments)]
+fn handle_mailbox_event(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    event: request_response::Event<MailboxRequest, MailboxResponse>,
     identity: &Identity,
-    window: &Window,
+    events: &dyn P2PEvents,
+    mailbox: &mut Mailbox,
     pending_acks: &mut PendingAcks,
     receipt_queue: &mut ReceiptQueue,
-) -> Result<()>
-where
-    THandlerErr: std::fmt::Debug,
-{
+    reply_queue: &mut ReplyQueue,
+    burn_state: &mut BurnState,
+) {
+    let mailbox_settings = settings::p2p_settings().mailbox;
+    match event {
+        request_response::Event::Message { peer, message } => match message {
+            request_response::Message::Request { request, channel, .. } => {
+                if let Err(e) = mailbox.serve(
+                    &mut swarm.behaviour_mut().mailbox,
+                    identity,
+                    &mailbox_settings,
+                    request,
+                    channel,
+                ) {
+                    warn!("Mailbox request from {} failed: {}", peer, e);
+                }
+            }
+            request_response::Message::Response { response, .. } => {
+                let (ack, payloads) = match mailbox.received(identity, &mailbox_settings, &peer, response) {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!("Rejected mailbox response from {}: {}", peer, e);
+                        return;
+                    }
+                };
+                for data in payloads {
+                    if let Err(e) = handle_incoming_p2p_message(
+                        &data,
+                        identity,
+                        events,
+                        pending_acks,
+                        receipt_queue,
+                        reply_queue,
+                        burn_state,
+                    ) {
+                        error!("Failed to handle mailbox message: {}", e);
+                    }
+                }
+                // Acknowledge and ask for the next page until one comes back empty
+                if !ack.is_empty() {
+                    if let Err(e) = Mailbox::fetch(&mut swarm.behaviour_mut().mailbox, identity, &mailbox_settings, &peer, ack) {
+                        warn!("Failed to acknowledge mailbox {}: {}", peer, e);
+                    }
+                }
+            }
+        },
+        request_response::Event::OutboundFailure { peer, error, .. } => {
+            warn!("Mailbox fetch from {} failed: {:?}", peer, error);
+        }
+        request_response::Event::InboundFailure { peer, error, .. } => {
+            warn!("Mailbox request from {} failed: {:?}", peer, error);
+        }
+        request_response::Event::ResponseSent { .. } => {}
+    }
+}
+
+/// Answer nearby devices asking for their envelopes, and deliver what they
+/// hand us
+#[allow(clippy::too_many_arguments)]
+fn handle_mesh_event(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    event: request_response::Event<MeshFrame, MeshFrame>,
+    identity: &Identity,
+    events: &dyn P2PEvents,
+    mailbox: &mut Mailbox,
+    pending_acks: &mut PendingAcks,
+    receipt_queue: &mut ReceiptQueue,
+    reply_queue: &mut ReplyQueue,
+    burn_state: &mut BurnState,
+) {
+    match event {
+        request_response::Event::Message { peer, message } => match message {
+            request_response::Message::Request { request, channel, .. } => {
+                match mesh::answer(identity, &request) {
+                    Ok(response) => {
+                        if swarm.behaviour_mut().mesh.send_response(channel, response).is_err() {
+                            warn!("Mesh response to {} was dropped", peer);
+                        }
+                    }
+                    Err(e) => warn!("Mesh request from {} failed: {}", peer, e),
+                }
+            }
+            request_response::Message::Response { response, .. } => {
+                let payloads = match mesh::received(&mut swarm.behaviour_mut().mesh, &peer, identity, &response) {
+                    Ok(payloads) => payloads,
+                    Err(e) => {
+                        warn!("Rejected mesh response from {}: {}", peer, e);
+                        return;
+                    }
+                };
+                for data in payloads {
+                    // Also arrived live or from a mailbox
+                    if !mailbox.first_seen(&data) {
+                        continue;
+                    }
+                    if let Err(e) = handle_incoming_p2p_message(
+                        &data,
+                        identity,
+                        events,
+                        pending_acks,
+                        receipt_queue,
+                        reply_queue,
+                        burn_state,
+                    ) {
+                        error!("Failed to handle mesh message: {}", e);
+                    }
+                }
+            }
+        },
+        request_response::Event::OutboundFailure { peer, error, .. } => {
+            warn!("Mesh sync with {} failed: {:?}", peer, error);
+        }
+        request_response::Event::InboundFailure { peer, error, .. } => {
+            warn!("Mesh request from {} failed: {:?}", peer, error);
+        }
+        request_response::Event::ResponseSent { .. } => {}
+    }
+}
+
+/// Dial the relays a contact published in their signed contact card;
+/// returns whether any were dialed. None are in cover-only mode, where the
+/// only way out is the cover tunnel.
+fn dial_relay_hints(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    data_dir: &Path,
+    identity: &Identity,
+    target: &str,
+    dialed: &mut HashSet<String>,
+    connectivity: &mut ConnectivityTracker,
+) -> bool {
+    if !settings::p2p_settings().cover.direct() {
+        return false;
+    }
+    let hints = match ContactBook::load(data_dir, identity) {
+        Ok(book) => match book.get(target) {
+            Some(contact) => contact.relay_hints.clone(),
+            None => return false,
+        },
+        Err(e) => {
+            warn!("Failed to load relay hints: {}", e);
+            return false;
+        }
+    };
+    if dial_addrs(swarm, &hints, dialed) {
+        connectivity.dialing();
+    }
+    !hints.is_empty()
+}
+
+/// Publish messages held for whoever just subscribed to `topic`. Messages
+/// whose ACK already timed out are dropped.
+fn send_awaiting(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    identity: &Identity,
+    topic: &str,
+    awaiting: &mut Vec<(String, GhostMessage)>,
+    pending_acks: &PendingAcks,
+) {
+    awaiting.retain(|(target, message)| {
+        if !pending_acks.contains(&message.id) {
+            return false;
+        }
+        if topic != format!("/deaddrop/inbox/{}", target) {
+            return true;
+        }
+        match send_ghost_message(swarm, identity, target, message) {
+            Ok(_) => false,
+            Err(e) => {
+                warn!("Still can't reach {}: {}", target, e);
+                true
+            }
+        }
+    });
+}
+
+fn dial_addrs(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    addrs: &[String],
+    dialed: &mut HashSet<String>,
+) -> bool {
+    let mut dialing = false;
+    for addr_str in addrs {
+        if !dialed.insert(addr_str.clone()) {
+            continue;
+        }
+        match addr_str.parse::<Multiaddr>() {
+            Ok(addr) => {
+                if let Err(e) = swarm.dial(addr.clone()) {
+                    error!("Failed to dial relay {}: {}", addr, e);
+                } else {
+                    info!("Dialing relay: {}", addr);
+                    dialing = true;
+                }
+            }
+            Err(e) => warn!("Invalid relay address {}: {}", addr_str, e),
+        }
+    }
+    dialing
+}
+
+/// Redial every relay we are no longer connected to
+fn reconnect(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    dialed: &mut HashSet<String>,
+    connectivity: &mut ConnectivityTracker,
+) {
+    dialed.retain(|addr| {
+        relay_peer_id(addr).is_some_and(|peer_id| swarm.is_connected(&peer_id))
+    });
+    if dial_relays(swarm, &settings::p2p_settings(), dialed) {
+        connectivity.dialing();
+    }
+}
+
+/// Peer ID at the end of a relay multiaddr (`.../p2p/<peer id>`)
+pub fn relay_peer_id(addr: &str) -> Option<PeerId> {
+    addr.parse::<Multiaddr>().ok()?.iter().find_map(|protocol| match protocol {
+        Protocol::P2p(peer_id) => Some(peer_id),
+        _ => None,
+    })
+}
+
+/// Handle Swarm events including Relay, Identify, and DCUtR
+async fn handle_swarm_event<THandlerErr>(
+    event: SwarmEvent<DeadDropBehaviourEvent, THandlerErr>,
+    identity: &Identity,
+    events: &dyn P2PEvents,
+    pending_acks: &mut PendingAcks,
+    receipt_queue: &mut ReceiptQueue,
+    reply_queue: &mut ReplyQueue,
+    burn_state: &mut BurnState,
+    connectivity: &mut ConnectivityTracker,
+    mailbox: &mut Mailbox,
+) -> Result<()>
+where
+    THandlerErr: std::fmt::Debug,
+{
     match event {
         SwarmEvent::Behaviour(DeadDropBehaviourEvent::Gossipsub(
             gossipsub::Event::Message {
@@ -311,59 +1573,102 @@ where
                 message,
             },
         )) => {
+            // Someone else's inbox we keep mail for
+            let own_topic = format!("/deaddrop/inbox/{}", identity.public_id());
+            if message.topic.as_str() != own_topic {
+                if let Some(owner) = message.topic.as_str().strip_prefix("/deaddrop/inbox/") {
+                    mailbox.keep(&settings::p2p_settings().mailbox, owner, &message.data);
+                }
+                return Ok(());
+            }
+            // Already fetched from a mailbox
+            if !mailbox.first_seen(&message.data) {
+                return Ok(());
+            }
+
             // Handle incoming message or receipt
-            if let Err(e) = handle_incoming_p2p_message(message, identity, window, pending_acks, receipt_queue) {
-                eprintln!("Failed to handle incoming message: {}", e);
+            if let Err(e) = handle_incoming_p2p_message(
+                &message.data,
+                identity,
+                events,
+                pending_acks,
+                receipt_queue,
+                reply_queue,
+                burn_state,
+            ) {
+                error!("Failed to handle incoming message: {}", e);
             }
         }
         SwarmEvent::Behaviour(DeadDropBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
             for (peer_id, _) in peers {
-                println!("mDNS: Discovered peer: {}", peer_id);
+                info!("mDNS: Discovered peer: {}", peer_id);
             }
         }
         SwarmEvent::Behaviour(DeadDropBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
             for (peer_id, _) in peers {
-                println!("mDNS: Peer expired: {}", peer_id);
+                info!("mDNS: Peer expired: {}", peer_id);
             }
         }
         SwarmEvent::Behaviour(DeadDropBehaviourEvent::Identify(identify::Event::Received {
             peer_id,
             info,
         })) => {
-            println!("Identify: Received info from {}", peer_id);
-            println!("  Protocol Version: {}", info.protocol_version);
-            println!("  Agent Version: {}", info.agent_version);
-            println!("  Listen Addrs: {:?}", info.listen_addrs);
+            info!("Identify: Received info from {}", peer_id);
+            info!("  Protocol Version: {}", info.protocol_version);
+            info!("  Agent Version: {}", info.agent_version);
+            info!("  Listen Addrs: {:?}", info.listen_addrs);
         }
         SwarmEvent::Behaviour(DeadDropBehaviourEvent::RelayClient(
             relay::client::Event::ReservationReqAccepted { relay_peer_id, .. },
         )) => {
-            println!("Relay: Reservation accepted by {}", relay_peer_id);
-            let _ = window.emit("relay_connected", relay_peer_id.to_string());
+            info!("Relay: Reservation accepted by {}", relay_peer_id);
+            connectivity.relays.insert(relay_peer_id);
+            let _ = events.emit("relay_connected", relay_peer_id.to_string());
         }
         SwarmEvent::Behaviour(DeadDropBehaviourEvent::Dcutr(event)) => {
             match event {
                 dcutr::Event::RemoteInitiatedDirectConnectionUpgrade { remote_peer_id, .. } => {
-                    println!("DCUtR: Remote initiated hole punch with {}", remote_peer_id);
+                    info!("DCUtR: Remote initiated hole punch with {}", remote_peer_id);
                 }
                 dcutr::Event::InitiatedDirectConnectionUpgrade { remote_peer_id, .. } => {
-                    println!("DCUtR: Initiated hole punch with {}", remote_peer_id);
+                    info!("DCUtR: Initiated hole punch with {}", remote_peer_id);
                 }
                 dcutr::Event::DirectConnectionUpgradeSucceeded { remote_peer_id } => {
-                    println!("DCUtR: Hole punch successful with {}", remote_peer_id);
+                    info!("DCUtR: Hole punch successful with {}", remote_peer_id);
                 }
                 dcutr::Event::DirectConnectionUpgradeFailed { remote_peer_id, error } => {
-                    eprintln!("DCUtR: Hole punch failed with {}: {:?}", remote_peer_id, error);
+                    warn!("DCUtR: Hole punch failed with {}: {:?}", remote_peer_id, error);
                 }
             }
         }
         SwarmEvent::NewListenAddr { address, .. } => {
-            println!("Listening on: {}", address);
+            info!("Listening on: {}", address);
+            // Addresses appearing after startup mean an interface came (back) up
+            if connectivity.started.elapsed() > Duration::from_secs(STARTUP_GRACE_SECS) {
+                connectivity.schedule_reconnect();
+            }
+        }
+        SwarmEvent::ExpiredListenAddr { address, .. } => {
+            info!("No longer listening on: {}", address);
+            connectivity.schedule_reconnect();
         }
         SwarmEvent::ConnectionEstablished {
             peer_id, endpoint, ..
         } => {
-            println!("Connection established with {} via {}", peer_id, endpoint.get_remote_address());
+            info!("Connection established with {} via {}", peer_id, endpoint.get_remote_address());
+            connectivity.peers.insert(peer_id);
+            connectivity.connecting_until = None;
+        }
+        SwarmEvent::ConnectionClosed {
+            peer_id,
+            num_established: 0,
+            ..
+        } => {
+            connectivity.peers.remove(&peer_id);
+            // Losing a relay loses its reservation; get it back
+            if connectivity.relays.remove(&peer_id) {
+                connectivity.schedule_reconnect();
+            }
         }
         _ => {}
     }
@@ -375,63 +1680,108 @@ type ReceiptQueue = Vec<(PublicKey, String, String)>; // (sender_public_key, mes
 
 /// Handle incoming P2P message (either GhostMessage or Receipt)
 fn handle_incoming_p2p_message(
-    message: gossipsub::Message,
+    data: &[u8],
     identity: &Identity,
-    window: &Window,
+    events: &dyn P2PEvents,
     pending_acks: &mut PendingAcks,
     receipt_queue: &mut ReceiptQueue,
+    reply_queue: &mut ReplyQueue,
+    burn_state: &mut BurnState,
 ) -> Result<()> {
-    // Message format: sender_public_key (32 bytes) || encrypted_payload
-    if message.data.len() < 32 {
-        anyhow::bail!("Invalid message format: too short");
-    }
-
-    let (sender_key_bytes, encrypted_payload) = message.data.split_at(32);
-
-    // Parse sender's public key
-    let mut key_array = [0u8; 32];
-    key_array.copy_from_slice(sender_key_bytes);
-    let sender_public_key = PublicKey::from(key_array);
-
-    // Perform ECDH to get shared secret
-    let shared_secret = identity.shared_secret(&sender_public_key);
-
-    // Decrypt message
-    let decrypted = decrypt_message(&shared_secret, encrypted_payload)?;
-    let message_json = String::from_utf8(decrypted)?;
-
-    // Parse as P2PMessage to determine type
-    let p2p_message: P2PMessage = serde_json::from_str(&message_json)?;
+    let (sender_public_key, p2p_message) = open_p2p_message(identity, data)?;
+
+    // Who really sent it; the `from` fields are only what they claim
+    let sender_id = bs58::encode(sender_public_key.as_bytes()).into_string();
+    canary::inspect(
+        &burn_state.data_dir,
+        identity,
+        events,
+        &sender_id,
+        &p2p_message,
+    );
 
     match p2p_message {
         P2PMessage::Message(ghost_msg) => {
-            println!(
-                "Received message from {}: {}",
-                ghost_msg.from, ghost_msg.content
-            );
-
-            // Queue receipt to be sent back
-            receipt_queue.push((
-                sender_public_key,
-                ghost_msg.id.clone(),
-                ghost_msg.from.clone(),
-            ));
-
-            // Emit to frontend
-            window
-                .emit("ghost_msg", &ghost_msg)
-                .context("Failed to emit message to frontend")?;
+            let data_dir = &burn_state.data_dir;
+            let mut store = KnockStore::load(data_dir, identity)?;
+            match store.status(&sender_id) {
+                Some(KnockStatus::Accepted) => {
+                    deliver_message(
+                        ghost_msg,
+                        data_dir,
+                        identity,
+                        &sender_public_key,
+                        events,
+                        receipt_queue,
+                    )?;
+                }
+                Some(KnockStatus::Declined) => {
+                    info!("Dropped message from declined contact {}", sender_id);
+                }
+                // Writing to us answers our request
+                Some(KnockStatus::Requested) | Some(KnockStatus::Rejected) => {
+                    accept_contact(
+                        &mut store,
+                        data_dir,
+                        identity,
+                        events,
+                        &sender_public_key,
+                        &sender_id,
+                        false,
+                        pending_acks,
+                        receipt_queue,
+                        reply_queue,
+                    );
+                    deliver_message(
+                        ghost_msg,
+                        data_dir,
+                        identity,
+                        &sender_public_key,
+                        events,
+                        receipt_queue,
+                    )?;
+                }
+                None if !store.has_room_for(&sender_id) => {
+                    warn!("Too many open contact requests, dropped message from {}", sender_id);
+                }
+                // A first message without a request counts as one
+                previous @ (Some(KnockStatus::Pending) | None) => {
+                    info!("Holding message from {} until their contact request is accepted", sender_id);
+                    store.hold(&sender_id, false, ghost_msg);
+                    contact_requested(
+                        &mut store,
+                        data_dir,
+                        identity,
+                        events,
+                        &sender_public_key,
+                        &sender_id,
+                        "",
+                        previous,
+                        pending_acks,
+                        receipt_queue,
+                        reply_queue,
+                    );
+                }
+            }
+            store.save()?;
         }
         P2PMessage::Receipt(receipt) => {
-            println!(
+            info!(
                 "Received ACK for message {} from {}",
                 receipt.message_id, receipt.from
             );
 
             // Remove from pending ACKs
-            if let Some((target, _)) = pending_acks.remove(&receipt.message_id) {
+            if let Some((target, message, _)) = pending_acks.remove(&receipt.message_id) {
+                transcript::record(
+                    &burn_state.data_dir,
+                    identity,
+                    &target,
+                    TranscriptKind::Delivered,
+                    &message,
+                );
                 // Emit delivery confirmation to frontend
-                window
+                events
                     .emit(
                         "msg_delivered",
                         serde_json::json!({
@@ -443,11 +1793,989 @@ fn handle_incoming_p2p_message(
                     .context("Failed to emit delivery confirmation")?;
             }
         }
+        P2PMessage::BurnKeyRequest(request) => {
+            // The key is released to, and recorded for, the claimed recipient
+            if request.from != sender_id {
+                anyhow::bail!("Burn key request claims to be from {}", request.from);
+            }
+            info!(
+                "Burn key requested for drop {} by {}",
+                request.drop_id, request.from
+            );
+
+            let response = burn_state.release(identity, events, &request.drop_id, &request.from);
+            reply_queue.push((
+                sender_public_key,
+                request.from,
+                P2PMessage::BurnKeyResponse(response),
+            ));
+        }
+        P2PMessage::BurnKeyResponse(response) => {
+            if let Some(reply) = burn_state.pending.remove(&response.drop_id) {
+                let result = match (response.unwrap_key, response.error) {
+                    (Some(mut key_hex), _) => {
+                        let key = hex::decode(&key_hex)
+                            .map_err(|e| e.to_string())
+                            .and_then(|mut bytes| {
+                                let key = SessionKey::from_bytes(&bytes).map_err(|e| e.to_string());
+                                bytes.zeroize();
+                                key
+                            });
+                        key_hex.zeroize();
+                        key
+                    }
+                    (None, error) => Err(error.unwrap_or_else(|| "Burn key denied".to_string())),
+                };
+                let _ = reply.send(result);
+            }
+        }
+        P2PMessage::ContactRequest(request) => {
+            if request.from != sender_id {
+                anyhow::bail!("Contact request claims to be from {}", request.from);
+            }
+            info!("Contact request from {}", sender_id);
+
+            let data_dir = &burn_state.data_dir;
+            let mut store = KnockStore::load(data_dir, identity)?;
+            match store.status(&sender_id) {
+                Some(KnockStatus::Declined) => {
+                    info!("Ignored contact request from declined contact {}", sender_id);
+                }
+                // Crossed requests, or they lost our earlier answer
+                Some(KnockStatus::Accepted) | Some(KnockStatus::Requested) => {
+                    accept_contact(
+                        &mut store,
+                        data_dir,
+                        identity,
+                        events,
+                        &sender_public_key,
+                        &sender_id,
+                        true,
+                        pending_acks,
+                        receipt_queue,
+                        reply_queue,
+                    );
+                }
+                _ if !store.has_room_for(&sender_id) => {
+                    warn!("Too many open contact requests, ignored the one from {}", sender_id);
+                }
+                previous => contact_requested(
+                    &mut store,
+                    data_dir,
+                    identity,
+                    events,
+                    &sender_public_key,
+                    &sender_id,
+                    &request.note,
+                    previous,
+                    pending_acks,
+                    receipt_queue,
+                    reply_queue,
+                ),
+            }
+            store.save()?;
+        }
+        P2PMessage::ContactResponse(response) => {
+            if response.from != sender_id {
+                anyhow::bail!("Contact response claims to be from {}", response.from);
+            }
+
+            let mut store = KnockStore::load(&burn_state.data_dir, identity)?;
+            if store.status(&sender_id) != Some(KnockStatus::Requested) {
+                return Ok(());
+            }
+            if response.accepted {
+                info!("{} accepted our contact request", sender_id);
+                accept_contact(
+                    &mut store,
+                    &burn_state.data_dir,
+                    identity,
+                    events,
+                    &sender_public_key,
+                    &sender_id,
+                    false,
+                    pending_acks,
+                    receipt_queue,
+                    reply_queue,
+                );
+            } else {
+                info!("{} declined our contact request", sender_id);
+                let record = store.set_status(&sender_id, KnockStatus::Rejected, None);
+                let _ = events.emit("contact_status", &record);
+                for message in store.take_held(&sender_id, true) {
+                    message_failed(events, &message.id, &sender_id, "Contact request declined");
+                }
+            }
+            store.save()?;
+        }
+        P2PMessage::Introduction(signed) => {
+            let introduction = verify_introduction(&signed)?;
+            if introduction.introducer != sender_id {
+                anyhow::bail!("Introduction claims to be from {}", introduction.introducer);
+            }
+            if introduction.contact == identity.public_id() {
+                return Ok(());
+            }
+
+            // Only people we already talk to may introduce others
+            let data_dir = &burn_state.data_dir;
+            let book = ContactBook::load(data_dir, identity)?;
+            let known = KnockStore::load(data_dir, identity)?.status(&sender_id)
+                == Some(KnockStatus::Accepted);
+            let introducer = book.get(&sender_id);
+            if !known && introducer.is_none() {
+                info!("Ignored introduction from unknown peer {}", sender_id);
+                return Ok(());
+            }
+            observe_signing_key(data_dir, identity, events, &sender_id, &signed.signing_key, KeySource::Introduction);
+            // Their card told us which key signs for them
+            if let Some(key) = introducer.and_then(|c| c.signing_key.as_ref()) {
+                if *key != signed.signing_key {
+                    anyhow::bail!("Introduction from {} is signed with an unknown key", sender_id);
+                }
+            }
+            let introducer_nickname = introducer.map(|c| c.nickname.clone());
+
+            info!("{} introduced {}", sender_id, introduction.contact);
+            let mut store = IntroductionStore::load(data_dir, identity)?;
+            let pending = store.add(introduction, signed, introducer_nickname);
+            store.save()?;
+            let _ = events.emit("introduction", &pending);
+        }
+        P2PMessage::GroupShard(delivery) => {
+            // Same bar as introductions: only people we already talk to
+            let data_dir = &burn_state.data_dir;
+            let book = ContactBook::load(data_dir, identity)?;
+            let known = KnockStore::load(data_dir, identity)?.status(&sender_id)
+                == Some(KnockStatus::Accepted);
+            let owner = book.get(&sender_id);
+            if !known && owner.is_none() {
+                info!("Ignored group shard from unknown peer {}", sender_id);
+                return Ok(());
+            }
+            observe_signing_key(data_dir, identity, events, &sender_id, &delivery.manifest.signing_key, KeySource::GroupShard);
+            if let Some(key) = owner.and_then(|c| c.signing_key.as_ref()) {
+                if *key != delivery.manifest.signing_key {
+                    anyhow::bail!("Group manifest from {} is signed with an unknown key", sender_id);
+                }
+            }
+
+            let received = accept_group_shard(identity, &sender_id, delivery)?;
+            info!("Received shard of group drop {} from {}", received.cid, sender_id);
+            let mut store = GroupStore::load(data_dir, identity)?;
+            store.receive(received.clone());
+            store.save()?;
+
+            reply_queue.push((
+                sender_public_key,
+                sender_id,
+                P2PMessage::ShardReceipt(ShardReceipt {
+                    cid: received.cid.clone(),
+                    from: identity.public_id(),
+                    timestamp: now_secs(),
+                }),
+            ));
+            let _ = events.emit("group_shard", &received);
+        }
+        P2PMessage::DeviceRevoked(signed) => {
+            let revocation = signed.verify()?;
+            if revocation.owner != sender_id {
+                anyhow::bail!("Revocation claims to be from {}", revocation.owner);
+            }
+            let book = ContactBook::load(&burn_state.data_dir, identity)?;
+            let Some(contact) = book.get(&sender_id) else {
+                info!("Ignored device revocation from unknown peer {}", sender_id);
+                return Ok(());
+            };
+            observe_signing_key(&burn_state.data_dir, identity, events, &sender_id, &signed.signing_key, KeySource::DeviceRevocation);
+            if let Some(key) = &contact.signing_key {
+                if *key != signed.signing_key {
+                    anyhow::bail!("Revocation from {} is signed with an unknown key", sender_id);
+                }
+            }
+            warn!("{} revoked their device {}", sender_id, revocation.name);
+            let _ = events.emit("contact_device_revoked", &revocation);
+        }
+        P2PMessage::Attestation(signed) => {
+            let attestation = trust::verify_attestation(&signed)?;
+            if attestation.attester != sender_id {
+                anyhow::bail!("Attestation claims to be from {}", attestation.attester);
+            }
+            if attestation.subject == identity.public_id() {
+                return Ok(());
+            }
+
+            // Only the word of people we verified counts
+            let data_dir = &burn_state.data_dir;
+            let book = ContactBook::load(data_dir, identity)?;
+            let Some(attester) = book.get(&sender_id).filter(|c| c.verified) else {
+                info!("Ignored attestation from unverified peer {}", sender_id);
+                return Ok(());
+            };
+            observe_signing_key(data_dir, identity, events, &sender_id, &signed.signing_key, KeySource::Attestation);
+            if let Some(key) = &attester.signing_key {
+                if *key != signed.signing_key {
+                    anyhow::bail!("Attestation from {} is signed with an unknown key", sender_id);
+                }
+            }
+
+            let mut store = AttestationStore::load(data_dir, identity)?;
+            if store.add(attestation.clone(), signed) {
+                store.save()?;
+                info!("{} vouched for {}", sender_id, attestation.subject);
+                let _ = events.emit("attestation_received", &attestation);
+            }
+        }
+        P2PMessage::RemoteWipe(signed) => {
+            match remote_wipe::receive(&burn_state.data_dir, identity, &sender_id, &signed)? {
+                Some(pending) => {
+                    warn!("Remote wipe ordered by {}, due at {}", pending.order.issuer, pending.due_at);
+                    let _ = events.emit("remote_wipe_pending", &pending);
+                }
+                None => info!("Ignored wipe order from {}, a wipe is already pending", sender_id),
+            }
+        }
+        P2PMessage::EscrowRequest(signed) => {
+            // Same bar as introductions: only people we already talk to
+            let data_dir = &burn_state.data_dir;
+            let book = ContactBook::load(data_dir, identity)?;
+            let known = KnockStore::load(data_dir, identity)?.status(&sender_id)
+                == Some(KnockStatus::Accepted);
+            let requester = book.get(&sender_id);
+            if !known && requester.is_none() {
+                info!("Ignored release request from unknown peer {}", sender_id);
+                return Ok(());
+            }
+            observe_signing_key(data_dir, identity, events, &sender_id, &signed.signing_key, KeySource::Escrow);
+            if let Some(key) = requester.and_then(|c| c.signing_key.as_ref()) {
+                if *key != signed.signing_key {
+                    anyhow::bail!("Release request from {} is signed with an unknown key", sender_id);
+                }
+            }
+            if let Some(pending) = escrow::receive_request(data_dir, identity, &sender_id, &signed)? {
+                info!("{} asked us to release a shard of {}", sender_id, pending.drop);
+                let _ = events.emit("escrow_request", &pending);
+            }
+        }
+        P2PMessage::EscrowDecision(signed) => {
+            let data_dir = &burn_state.data_dir;
+            let book = ContactBook::load(data_dir, identity)?;
+            if let Some(key) = book.get(&sender_id).and_then(|c| c.signing_key.as_ref()) {
+                if *key != signed.signing_key {
+                    anyhow::bail!("Release decision from {} is signed with an unknown key", sender_id);
+                }
+            }
+            let Some(status) = escrow::receive_decision(data_dir, identity, &sender_id, &signed)? else {
+                return Ok(());
+            };
+            observe_signing_key(data_dir, identity, events, &sender_id, &signed.signing_key, KeySource::Escrow);
+            info!("{} answered release request {}", sender_id, status.request_id);
+            let _ = events.emit("escrow_decision", &status);
+            // Emitted once, on the approval that completes the quorum
+            if status.ready && status.approved.len() == status.threshold as usize {
+                let _ = events.emit("escrow_approved", &status);
+            }
+        }
+        P2PMessage::Poll(message) => {
+            let data_dir = &burn_state.data_dir;
+            let signing_key = match &message {
+                PollMessage::Open { poll } => Some(poll.signing_key.clone()),
+                PollMessage::Vote { vote } => Some(vote.signing_key.clone()),
+                PollMessage::Tally { .. } => None,
+            };
+            let book = ContactBook::load(data_dir, identity)?;
+            // New polls meet the same bar as introductions; votes and
+            // tallies must come from the poll's participants
+            if matches!(message, PollMessage::Open { .. })
+                && book.get(&sender_id).is_none()
+                && KnockStore::load(data_dir, identity)?.status(&sender_id) != Some(KnockStatus::Accepted)
+            {
+                info!("Ignored poll from unknown peer {}", sender_id);
+                return Ok(());
+            }
+            if let (Some(key), Some(signing_key)) = (
+                book.get(&sender_id).and_then(|c| c.signing_key.as_ref()),
+                &signing_key,
+            ) {
+                if key != signing_key {
+                    anyhow::bail!("Poll message from {} is signed with an unknown key", sender_id);
+                }
+            }
+            let Some(update) = polls::receive(data_dir, identity, &sender_id, message)? else {
+                return Ok(());
+            };
+            if let Some(signing_key) = &signing_key {
+                observe_signing_key(data_dir, identity, events, &sender_id, signing_key, KeySource::Poll);
+            }
+            // As the creator, pass the new tally on to everyone
+            if let Some((recipients, tally)) = update.relay {
+                for recipient in recipients {
+                    match decode_public_key(&recipient) {
+                        Ok(recipient_pk) => reply_queue.push((recipient_pk, recipient, P2PMessage::Poll(tally.clone()))),
+                        Err(e) => warn!("Could not send poll tally to {}: {}", recipient, e),
+                    }
+                }
+            }
+            if update.opened {
+                info!("{} opened poll {}", sender_id, update.results.poll.id);
+                let _ = events.emit("poll", &update.results);
+            }
+            let _ = events.emit("poll_results", &update.results);
+        }
+        P2PMessage::ShardReceipt(receipt) => {
+            if receipt.from != sender_id {
+                anyhow::bail!("Shard receipt claims to be from {}", receipt.from);
+            }
+
+            let mut catalog = DropCatalog::load(&burn_state.data_dir)?;
+            let confirmed_at = catalog
+                .get_mut(&receipt.cid)
+                .and_then(|record| record.group.as_mut())
+                .and_then(|group| group.confirm(&sender_id));
+            let Some(confirmed_at) = confirmed_at else {
+                return Ok(());
+            };
+            catalog.save()?;
+
+            info!("{} confirmed their shard of {}", sender_id, receipt.cid);
+            let _ = events.emit(
+                "shard_confirmed",
+                serde_json::json!({
+                    "cid": receipt.cid,
+                    "member": sender_id,
+                    "confirmed_at": confirmed_at,
+                }),
+            );
+        }
     }
 
     Ok(())
 }
 
+/// Send the shards of a group drop to the members who haven't confirmed
+/// theirs, remembering when each went out
+fn send_group_shards(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    data_dir: &Path,
+    identity: &Identity,
+    cid: &str,
+) -> Result<Vec<String>> {
+    let mut catalog = DropCatalog::load(data_dir)?;
+    let group = catalog
+        .get_mut(cid)
+        .and_then(|record| record.group.as_mut())
+        .context("Not a group drop")?;
+
+    let mut sent = Vec::new();
+    for member in group.members.iter_mut().filter(|m| m.confirmed_at.is_none()) {
+        let delivery = P2PMessage::GroupShard(GroupShard {
+            manifest_cid: group.manifest_cid.clone(),
+            manifest: group.manifest.clone(),
+            shard: member.shard.clone(),
+        });
+        let result = decode_public_key(&member.public_id).and_then(|member_pk| {
+            publish_p2p_message(swarm, identity, &member_pk, &member.public_id, &delivery)
+        });
+        match result {
+            Ok(_) => {
+                member.sent_at = Some(now_secs());
+                sent.push(member.public_id.clone());
+            }
+            Err(e) => warn!("Could not send shard of {} to {}: {}", cid, member.public_id, e),
+        }
+    }
+    catalog.save()?;
+
+    info!("Sent shards of {} to {} members", cid, sent.len());
+    Ok(sent)
+}
+
+/// Log the key a contact signed with, alerting if it isn't the one seen
+/// for them before
+fn observe_signing_key(
+    data_dir: &Path,
+    identity: &Identity,
+    events: &dyn P2PEvents,
+    contact: &str,
+    signing_key: &str,
+    source: KeySource,
+) {
+    if let Some(change) = key_log::observe(data_dir, identity, contact, KeyKind::Signing, signing_key, source) {
+        let _ = events.emit("contact_key_changed", &change);
+    }
+}
+
+/// Send an attestation to each recipient, returning those it went out to
+fn send_attestation(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    identity: &Identity,
+    attestation: SignedAttestation,
+    recipients: &[String],
+) -> Vec<String> {
+    let message = P2PMessage::Attestation(attestation);
+    let mut sent = Vec::new();
+    for recipient in recipients {
+        let result = decode_public_key(recipient).and_then(|recipient_pk| {
+            publish_p2p_message(swarm, identity, &recipient_pk, recipient, &message)
+        });
+        match result {
+            Ok(_) => sent.push(recipient.clone()),
+            Err(e) => warn!("Could not send attestation to {}: {}", recipient, e),
+        }
+    }
+    sent
+}
+
+/// Send a release request to each approver, returning those it went out to
+fn send_escrow_request(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    identity: &Identity,
+    request: SignedEscrowStatement,
+    approvers: &[String],
+) -> Vec<String> {
+    let message = P2PMessage::EscrowRequest(request);
+    let mut sent = Vec::new();
+    for approver in approvers {
+        let result = decode_public_key(approver).and_then(|approver_pk| {
+            publish_p2p_message(swarm, identity, &approver_pk, approver, &message)
+        });
+        match result {
+            Ok(_) => sent.push(approver.clone()),
+            Err(e) => warn!("Could not send release request to {}: {}", approver, e),
+        }
+    }
+    sent
+}
+
+/// Send poll traffic to each recipient, returning those it went out to
+fn send_poll_message(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    identity: &Identity,
+    message: PollMessage,
+    recipients: &[String],
+) -> Vec<String> {
+    let message = P2PMessage::Poll(message);
+    let mut sent = Vec::new();
+    for recipient in recipients {
+        let result = decode_public_key(recipient).and_then(|recipient_pk| {
+            publish_p2p_message(swarm, identity, &recipient_pk, recipient, &message)
+        });
+        match result {
+            Ok(_) => sent.push(recipient.clone()),
+            Err(e) => warn!("Could not send poll message to {}: {}", recipient, e),
+        }
+    }
+    sent
+}
+
+/// Send a device revocation to every contact
+fn announce_revocation(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    data_dir: &Path,
+    identity: &Identity,
+    notice: SignedRevocation,
+) -> Result<Vec<String>> {
+    let book = ContactBook::load(data_dir, identity)?;
+    let message = P2PMessage::DeviceRevoked(notice);
+    let mut sent = Vec::new();
+    for contact in book.contacts() {
+        let result = decode_public_key(&contact.public_id).and_then(|contact_pk| {
+            publish_p2p_message(swarm, identity, &contact_pk, &contact.public_id, &message)
+        });
+        match result {
+            Ok(_) => sent.push(contact.public_id.clone()),
+            Err(e) => warn!("Could not tell {} about the revocation: {}", contact.public_id, e),
+        }
+    }
+    info!("Told {} contacts about a revoked device", sent.len());
+    Ok(sent)
+}
+
+/// Sign an introduction of `contact` and send it to `recipient`, using our
+/// nickname for the contact when we have one
+fn send_introduction(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    data_dir: &Path,
+    identity: &Identity,
+    recipient: &str,
+    contact: &str,
+    note: &str,
+) -> Result<()> {
+    if recipient == contact {
+        anyhow::bail!("Can't introduce a contact to themselves");
+    }
+    let recipient_pk = decode_public_key(recipient)?;
+    let book = ContactBook::load(data_dir, identity)?;
+    let nickname = book.get(contact).map(|c| c.nickname.as_str()).unwrap_or_default();
+
+    let signed = introduce(identity, contact, nickname, note)?;
+    publish_p2p_message(
+        swarm,
+        identity,
+        &recipient_pk,
+        recipient,
+        &P2PMessage::Introduction(signed),
+    )?;
+    info!("Introduced {} to {}", contact, recipient);
+    Ok(())
+}
+
+/// Emit a message to the frontend and queue its receipt. The content of a
+/// view-once message is kept back for `open_view_once_message`.
+fn deliver_message(
+    mut ghost_msg: GhostMessage,
+    data_dir: &Path,
+    identity: &Identity,
+    sender_public_key: &PublicKey,
+    events: &dyn P2PEvents,
+    receipt_queue: &mut ReceiptQueue,
+) -> Result<()> {
+    let sender_id = bs58::encode(sender_public_key.as_bytes()).into_string();
+    // Live-location updates are shown, never kept as messages
+    if let Some(share) = ghost_msg.location.as_ref().filter(|s| s.is_live()) {
+        receipt_queue.push((*sender_public_key, ghost_msg.id.clone(), sender_id.clone()));
+        match location::received(&sender_id, share)? {
+            Some(LiveEvent::Updated(session)) => {
+                let _ = events.emit("live_location", &session);
+            }
+            Some(LiveEvent::Stopped(session)) => {
+                info!("{} stopped live location {}", sender_id, session.id);
+                let _ = events.emit("live_location_stopped", &session);
+            }
+            None => {}
+        }
+        return Ok(());
+    }
+
+    plugins::incoming(&mut ghost_msg, events);
+    if ghost_msg.view_once {
+        view_once::stash(data_dir, identity, &mut ghost_msg)
+            .context("Failed to store view-once message")?;
+    }
+    transcript::record(
+        data_dir,
+        identity,
+        &sender_id,
+        TranscriptKind::Received,
+        &ghost_msg,
+    );
+    search::record(data_dir, identity, &sender_id, false, &ghost_msg);
+
+    info!("Received message {} from {}", ghost_msg.id, ghost_msg.from);
+
+    // Queue receipt to be sent back
+    receipt_queue.push((
+        *sender_public_key,
+        ghost_msg.id.clone(),
+        ghost_msg.from.clone(),
+    ));
+
+    // Emit to frontend
+    let event = MessageEvent {
+        message: &ghost_msg,
+        appearance: contacts::appearance(data_dir, identity, &sender_id),
+        trust: trust::sender_trust(data_dir, identity, &settings::trust_settings(), &sender_id),
+    };
+    events
+        .emit("ghost_msg", &event)
+        .context("Failed to emit message to frontend")?;
+    events.message_received(&ghost_msg);
+    Ok(())
+}
+
+/// Send queued receipts and replies, oldest first
+fn flush_queues(
+    swarm: &mut Swarm<DeadDropBehaviour>,
+    identity: &Identity,
+    receipt_queue: &mut ReceiptQueue,
+    reply_queue: &mut ReplyQueue,
+) {
+    for (sender_pk, msg_id, sender_id) in receipt_queue.drain(..) {
+        if let Err(e) = send_receipt(swarm, identity, &sender_pk, &msg_id, &sender_id) {
+            error!("Failed to send receipt: {}", e);
+        }
+    }
+
+    for (recipient_pk, recipient_id, reply) in reply_queue.drain(..) {
+        if let Err(e) = publish_p2p_message(swarm, identity, &recipient_pk, &recipient_id, &reply) {
+            error!("Failed to send reply: {}", e);
+        }
+    }
+}
+
+fn contact_request_message(identity: &Identity, note: &str) -> P2PMessage {
+    P2PMessage::ContactRequest(ContactRequest {
+        from: identity.public_id(),
+        note: note.to_string(),
+        timestamp: now_secs(),
+    })
+}
+
+fn contact_response_message(identity: &Identity, accepted: bool) -> P2PMessage {
+    P2PMessage::ContactResponse(ContactResponse {
+        from: identity.public_id(),
+        accepted,
+        timestamp: now_secs(),
+    })
+}
+
+/// Whether a message to `target` can go out now. If not, it is held and a
+/// contact request is sent in its place.
+#[allow(clippy::too_many_arguments)]
+fn may_send(
+    data_dir: &Path,
+    identity: &Identity,
+    events: &dyn P2PEvents,
+    target: &str,
+    message: &GhostMessage,
+    pending_acks: &mut PendingAcks,
+    receipt_queue: &mut ReceiptQueue,
+    reply_queue: &mut ReplyQueue,
+) -> Result<bool> {
+    let target_pk = decode_public_key(target)?;
+    let mut store = KnockStore::load(data_dir, identity)?;
+
+    let allowed = match store.status(target) {
+        Some(KnockStatus::Accepted) => true,
+        // Writing back answers their request
+        Some(KnockStatus::Pending) => {
+            accept_contact(
+                &mut store,
+                data_dir,
+                identity,
+                events,
+                &target_pk,
+                target,
+                true,
+                pending_acks,
+                receipt_queue,
+                reply_queue,
+            );
+            true
+        }
+        _ => {
+            if !message.location.as_ref().is_some_and(LocationShare::is_live) {
+                transcript::record(data_dir, identity, target, TranscriptKind::Sent, message);
+                search::record(data_dir, identity, target, true, message);
+            }
+            store.hold(target, true, message.clone());
+            let record = store.set_status(target, KnockStatus::Requested, None);
+            reply_queue.push((target_pk, target.to_string(), contact_request_message(identity, "")));
+            let _ = events.emit("contact_status", &record);
+            let _ = events.emit(
+                "msg_held",
+                serde_json::json!({
+                    "message_id": message.id,
+                    "target": target,
+                }),
+            );
+            false
+        }
+    };
+
+    store.save()?;
+    Ok(allowed)
+}
+
+/// Mark `contact` accepted, deliver what they sent while waiting and send
+/// what we held for them. `respond` tells them with a contact response.
+#[allow(clippy::too_many_arguments)]
+fn accept_contact(
+    store: &mut KnockStore,
+    data_dir: &Path,
+    identity: &Identity,
+    events: &dyn P2PEvents,
+    contact_pk: &PublicKey,
+    contact: &str,
+    respond: bool,
+    pending_acks: &mut PendingAcks,
+    receipt_queue: &mut ReceiptQueue,
+    reply_queue: &mut ReplyQueue,
+) -> KnockRecord {
+    let record = store.set_status(contact, KnockStatus::Accepted, None);
+    let _ = events.emit("contact_status", &record);
+    if respond {
+        reply_queue.push((*contact_pk, contact.to_string(), contact_response_message(identity, true)));
+    }
+
+    for message in store.take_held(contact, false) {
+        if let Err(e) = deliver_message(
+            message,
+            data_dir,
+            identity,
+            contact_pk,
+            events,
+            receipt_queue,
+        ) {
+            error!("Failed to deliver held message: {}", e);
+        }
+    }
+    for message in store.take_held(contact, true) {
+        pending_acks.add(contact.to_string(), message.clone());
+        reply_queue.push((*contact_pk, contact.to_string(), P2PMessage::Message(message)));
+    }
+    record
+}
+
+/// Record an incoming request and accept it if the settings say so,
+/// otherwise ask the user with a `contact_request` event
+#[allow(clippy::too_many_arguments)]
+fn contact_requested(
+    store: &mut KnockStore,
+    data_dir: &Path,
+    identity: &Identity,
+    events: &dyn P2PEvents,
+    sender_pk: &PublicKey,
+    sender_id: &str,
+    note: &str,
+    previous: Option<KnockStatus>,
+    pending_acks: &mut PendingAcks,
+    receipt_queue: &mut ReceiptQueue,
+    reply_queue: &mut ReplyQueue,
+) {
+    let note = Some(note).filter(|n| !n.is_empty());
+    let record = store.set_status(sender_id, KnockStatus::Pending, note);
+
+    let policy = settings::p2p_settings().auto_accept;
+    if auto_accepts(policy, data_dir, identity, sender_id) {
+        info!("Accepted contact request from {} automatically", sender_id);
+        accept_contact(
+            store,
+            data_dir,
+            identity,
+            events,
+            sender_pk,
+            sender_id,
+            true,
+            pending_acks,
+            receipt_queue,
+            reply_queue,
+        );
+    } else if previous != Some(KnockStatus::Pending) || note.is_some() {
+        let _ = events.emit("contact_request", &record);
+    }
+}
+
+/// Knock on a contact explicitly, with a note
+#[allow(clippy::too_many_arguments)]
+fn send_contact_request(
+    data_dir: &Path,
+    identity: &Identity,
+    events: &dyn P2PEvents,
+    public_id: &str,
+    note: &str,
+    pending_acks: &mut PendingAcks,
+    receipt_queue: &mut ReceiptQueue,
+    reply_queue: &mut ReplyQueue,
+) -> Result<KnockRecord> {
+    if public_id == identity.public_id() {
+        anyhow::bail!("Can't send a contact request to yourself");
+    }
+    let contact_pk = decode_public_key(public_id)?;
+    let mut store = KnockStore::load(data_dir, identity)?;
+
+    let record = match store.status(public_id) {
+        Some(KnockStatus::Accepted) => store.get(public_id).cloned().context("Missing contact request")?,
+        Some(KnockStatus::Pending) => accept_contact(
+            &mut store,
+            data_dir,
+            identity,
+            events,
+            &contact_pk,
+            public_id,
+            true,
+            pending_acks,
+            receipt_queue,
+            reply_queue,
+        ),
+        _ => {
+            let record = store.set_status(public_id, KnockStatus::Requested, None);
+            reply_queue.push((contact_pk, public_id.to_string(), contact_request_message(identity, note)));
+            let _ = events.emit("contact_status", &record);
+            record
+        }
+    };
+
+    store.save()?;
+    Ok(record)
+}
+
+/// Accept or decline a pending request. Declining drops what they sent.
+#[allow(clippy::too_many_arguments)]
+fn respond_contact_request(
+    data_dir: &Path,
+    identity: &Identity,
+    events: &dyn P2PEvents,
+    public_id: &str,
+    accept: bool,
+    pending_acks: &mut PendingAcks,
+    receipt_queue: &mut ReceiptQueue,
+    reply_queue: &mut ReplyQueue,
+) -> Result<KnockRecord> {
+    let contact_pk = decode_public_key(public_id)?;
+    let mut store = KnockStore::load(data_dir, identity)?;
+    if store.status(public_id) != Some(KnockStatus::Pending) {
+        anyhow::bail!("No pending contact request from {}", public_id);
+    }
+
+    let record = if accept {
+        accept_contact(
+            &mut store,
+            data_dir,
+            identity,
+            events,
+            &contact_pk,
+            public_id,
+            true,
+            pending_acks,
+            receipt_queue,
+            reply_queue,
+        )
+    } else {
+        let record = store.set_status(public_id, KnockStatus::Declined, None);
+        store.take_held(public_id, false);
+        reply_queue.push((contact_pk, public_id.to_string(), contact_response_message(identity, false)));
+        let _ = events.emit("contact_status", &record);
+        record
+    };
+
+    store.save()?;
+    Ok(record)
+}
+
+/// How an envelope left: over GossipSub, or through the Nostr relays
+/// because no peer on the inbox topic took it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Route {
+    Gossip,
+    Nostr,
+    /// Queued for when the recipient is near, and nothing else took it
+    Mesh,
+}
+
+/// Publish a sealed envelope to a recipient's inbox topic, falling back to
+/// the Nostr relays when that fails. Envelopes for mesh contacts are also
+/// queued for when they are nearby.
+fn publish_sealed(
+    swarm: &mut libp2p::Swarm<DeadDropBehaviour>,
+    recipient_public_key: &PublicKey,
+    recipient_id: &str,
+    data: Vec<u8>,
+) -> Result<Route> {
+    let topic = IdentTopic::new(format!("/deaddrop/inbox/{}", recipient_id));
+    let e = match swarm.behaviour_mut().gossipsub.publish(topic, data.clone()) {
+        Ok(_) => return Ok(Route::Gossip),
+        Err(e) => e,
+    };
+    let meshed = mesh::queue(recipient_id, &data);
+    if nostr::publish(recipient_public_key, &data) {
+        info!("No peer took the envelope for {} ({}), sent it through Nostr", recipient_id, e);
+        Ok(Route::Nostr)
+    } else if meshed {
+        info!("No peer took the envelope for {} ({}), queued it for nearby devices", recipient_id, e);
+        Ok(Route::Mesh)
+    } else {
+        Err(anyhow::anyhow!("Publish failed: {}", e))
+    }
+}
+
+/// Send a message whose receipt never came through the Nostr relays;
+/// returns false if the fallback is off
+fn relay_through_nostr(identity: &Identity, target: &str, message: &GhostMessage) -> bool {
+    let sealed = decode_public_key(target).and_then(|target_pk| {
+        let data = seal_p2p_message(identity, &target_pk, &P2PMessage::Message(message.clone()))?;
+        Ok((target_pk, data))
+    });
+    match sealed {
+        Ok((target_pk, data)) => nostr::publish(&target_pk, &data),
+        Err(e) => {
+            warn!("Can't relay {} through Nostr: {}", message.id, e);
+            false
+        }
+    }
+}
+
+/// Encrypt and publish any P2P message to a recipient's inbox topic
+fn publish_p2p_message(
+    swarm: &mut libp2p::Swarm<DeadDropBehaviour>,
+    identity: &Identity,
+    recipient_public_key: &PublicKey,
+    recipient_id: &str,
+    p2p_message: &P2PMessage,
+) -> Result<Route> {
+    let full_message = seal_p2p_message(identity, recipient_public_key, p2p_message)?;
+    publish_sealed(swarm, recipient_public_key, recipient_id, full_message)
+}
+
+/// Encrypt a message for `recipient_public_key` in the wire format:
+/// sender_public_key (32 bytes) || encrypted_payload
+pub fn seal_p2p_message(
+    identity: &Identity,
+    recipient_public_key: &PublicKey,
+    p2p_message: &P2PMessage,
+) -> Result<Vec<u8>> {
+    let mut encoded = wire::encode(p2p_message)?;
+
+    let shared_secret = identity.shared_secret(recipient_public_key);
+    let encrypted_payload = encrypt_message(&shared_secret, &encoded);
+    encoded.zeroize();
+
+    // Prepend our public key
+    let mut full_message = identity.public_key.as_bytes().to_vec();
+    full_message.extend_from_slice(&encrypted_payload?);
+    Ok(full_message)
+}
+
+/// Decrypt a message in the wire format, returning who sent it
+pub fn open_p2p_message(identity: &Identity, data: &[u8]) -> Result<(PublicKey, P2PMessage)> {
+    if data.len() < 32 {
+        anyhow::bail!("Invalid message format: too short");
+    }
+
+    let (sender_key_bytes, encrypted_payload) = data.split_at(32);
+
+    // Parse sender's public key
+    let mut key_array = [0u8; 32];
+    key_array.copy_from_slice(sender_key_bytes);
+    let sender_public_key = PublicKey::from(key_array);
+
+    // Perform ECDH to get shared secret
+    let shared_secret = identity.shared_secret(&sender_public_key);
+
+    // Decrypt message
+    let mut decrypted = decrypt_message(&shared_secret, encrypted_payload)?;
+    let p2p_message = wire::decode(&decrypted);
+    decrypted.zeroize();
+    let p2p_message = p2p_message?;
+    Ok((sender_public_key, p2p_message))
+}
+
+/// Run raw wire bytes through the incoming message handler with empty
+/// queues, as the fuzz targets do. Contact state lives under `data_dir`.
+#[cfg(any(test, feature = "fuzzing"))]
+pub fn handle_incoming_for_fuzzing(data: &[u8], identity: &Identity, data_dir: &Path) -> Result<()> {
+    struct NoEvents;
+    impl P2PEvents for NoEvents {
+        fn emit_event(&self, _event: &str, _payload: serde_json::Value) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut burn_state = BurnState {
+        data_dir: data_dir.to_path_buf(),
+        pending: HashMap::new(),
+    };
+    handle_incoming_p2p_message(
+        data,
+        identity,
+        &NoEvents,
+        &mut PendingAcks::new(),
+        &mut Vec::new(),
+        &mut Vec::new(),
+        &mut burn_state,
+    )
+}
+
 /// Send a receipt/ACK back to the sender
 fn send_receipt(
     swarm: &mut libp2p::Swarm<DeadDropBehaviour>,
@@ -481,14 +2809,10 @@ fn send_receipt(
     full_message.extend_from_slice(&encrypted_payload);
 
     // Publish to sender's inbox topic
-    let topic = IdentTopic::new(format!("/deaddrop/inbox/{}", sender_id));
-    swarm
-        .behaviour_mut()
-        .gossipsub
-        .publish(topic, full_message)
-        .map_err(|e| anyhow::anyhow!("Receipt publish failed: {}", e))?;
+    publish_sealed(swarm, sender_public_key, sender_id, full_message)
+        .context("Receipt publish failed")?;
 
-    println!("Receipt sent for message {} to {}", message_id, sender_id);
+    info!("Receipt sent for message {} to {}", message_id, sender_id);
 
     Ok(())
 }
@@ -498,56 +2822,106 @@ fn send_ghost_message(
     swarm: &mut libp2p::Swarm<DeadDropBehaviour>,
     identity: &Identity,
     target_public_key_b58: &str,
-    content: &str,
-    message_id: &str,
-) -> Result<()> {
-    // Decode target's public key
-    let target_key_bytes = bs58::decode(target_public_key_b58)
-        .into_vec()
-        .context("Invalid base58 public key")?;
+    ghost_msg: &GhostMessage,
+) -> Result<Route> {
+    let target_public_key = decode_public_key(target_public_key_b58)?;
+    let route = publish_p2p_message(
+        swarm,
+        identity,
+        &target_public_key,
+        target_public_key_b58,
+        &P2PMessage::Message(ghost_msg.clone()),
+    )?;
+
+    info!("Message {} sent to {}", ghost_msg.id, target_public_key_b58);
+
+    Ok(route)
+}
 
-    if target_key_bytes.len() != 32 {
-        anyhow::bail!("Invalid public key length");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Encrypt any payload the way `seal_p2p_message` does, so the handler
+    /// gets past decryption and has to parse it
+    fn seal_raw(sender: &Identity, recipient: &Identity, payload: &[u8]) -> Vec<u8> {
+        let shared_secret = sender.shared_secret(&recipient.public_key);
+        let mut data = sender.public_key.as_bytes().to_vec();
+        data.extend_from_slice(&encrypt_message(&shared_secret, payload).unwrap());
+        data
     }
 
-    let mut key_array = [0u8; 32];
-    key_array.copy_from_slice(&target_key_bytes);
-    let target_public_key = PublicKey::from(key_array);
-
-    // Create message with UUID
-    let ghost_msg = GhostMessage {
-        id: message_id.to_string(),
-        from: identity.public_id(),
-        content: content.to_string(),
-        timestamp: std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-    };
-
-    // Wrap in P2PMessage enum
-    let p2p_message = P2PMessage::Message(ghost_msg);
-    let message_json = serde_json::to_string(&p2p_message)?;
+    #[test]
+    fn message_from_stranger_is_held() {
+        let dir = tempfile::tempdir().unwrap();
+        let (sender, recipient) = (Identity::generate(), Identity::generate());
+        let message = P2PMessage::Message(GhostMessage {
+            id: "1".to_string(),
+            from: sender.public_id(),
+            content: "hello".to_string(),
+            timestamp: 0,
+            view_once: false,
+            location: None,
+        });
+        let data = seal_p2p_message(&sender, &recipient.public_key, &message).unwrap();
 
-    // Perform ECDH
-    let shared_secret = identity.shared_secret(&target_public_key);
+        handle_incoming_for_fuzzing(&data, &recipient, dir.path()).unwrap();
 
-    // Encrypt message
-    let encrypted_payload = encrypt_message(&shared_secret, message_json.as_bytes())?;
+        let store = KnockStore::load(dir.path(), &recipient).unwrap();
+        assert_eq!(
+            store.status(&sender.public_id()),
+            Some(KnockStatus::Pending)
+        );
+    }
 
-    // Prepend sender's public key
-    let mut full_message = identity.public_key.as_bytes().to_vec();
-    full_message.extend_from_slice(&encrypted_payload);
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
 
-    // Publish to target's inbox topic
-    let topic = IdentTopic::new(format!("/deaddrop/inbox/{}", target_public_key_b58));
-    swarm
-        .behaviour_mut()
-        .gossipsub
-        .publish(topic, full_message)
-        .map_err(|e| anyhow::anyhow!("Publish failed: {}", e))?;
+        #[test]
+        fn garbage_wire_bytes_never_panic(data in proptest::collection::vec(any::<u8>(), 0..512)) {
+            let dir = tempfile::tempdir().unwrap();
+            let identity = Identity::generate();
+            prop_assert!(handle_incoming_for_fuzzing(&data, &identity, dir.path()).is_err());
+        }
 
-    println!("Message {} sent to {}", message_id, target_public_key_b58);
+        #[test]
+        fn sealed_garbage_payloads_never_panic(payload in proptest::collection::vec(any::<u8>(), 0..512)) {
+            let dir = tempfile::tempdir().unwrap();
+            let (sender, recipient) = (Identity::generate(), Identity::generate());
+            let data = seal_raw(&sender, &recipient, &payload);
+            let _ = handle_incoming_for_fuzzing(&data, &recipient, dir.path());
+        }
 
-    Ok(())
+        #[test]
+        fn sealed_json_payloads_never_panic(
+            kind in prop_oneof![
+                Just("message"), Just("receipt"), Just("burn_key_request"),
+                Just("burn_key_response"), Just("contact_request"),
+                Just("contact_response"), Just("introduction"),
+                Just("group_shard"), Just("shard_receipt"),
+            ],
+            fields in proptest::collection::btree_map(
+                prop_oneof![
+                    Just("id".to_string()), Just("from".to_string()),
+                    Just("message_id".to_string()), Just("drop_id".to_string()),
+                    Just("timestamp".to_string()), "[a-z_]{1,12}",
+                ],
+                prop_oneof![
+                    any::<String>().prop_map(serde_json::Value::from),
+                    any::<u64>().prop_map(serde_json::Value::from),
+                    any::<bool>().prop_map(serde_json::Value::from),
+                ],
+                0..8,
+            ),
+        ) {
+            let dir = tempfile::tempdir().unwrap();
+            let (sender, recipient) = (Identity::generate(), Identity::generate());
+            let mut object: serde_json::Map<String, serde_json::Value> = fields.into_iter().collect();
+            object.insert("type".to_string(), kind.into());
+            let payload = serde_json::Value::Object(object).to_string();
+            let data = seal_raw(&sender, &recipient, payload.as_bytes());
+            let _ = handle_incoming_for_fuzzing(&data, &recipient, dir.path());
+        }
+    }
 }
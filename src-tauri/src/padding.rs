@@ -0,0 +1,320 @@
+use crate::crypto::SessionKey;
+use crate::dead_drop::{
+    build_drop_stat, cat_bytes_from_ipfs, download_file_from_ipfs, is_upload_pending, object_size,
+    probe_download_speed, stream_decrypt_file, stream_encrypt_file, upload_bytes_to_ipfs,
+    upload_ciphertext, DeadDropCreated, DropOptions, DropStat,
+};
+use crate::ipfs;
+use crate::shards::{recover_session_key, split_session_key};
+use crate::wire;
+use anyhow::{Context, Result};
+use rand::seq::SliceRandom;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tracing::info;
+use zeroize::Zeroize;
+
+/// Drop references pointing at a padding manifest carry this prefix
+pub const PADDED_CID_PREFIX: &str = "pd:";
+const MANIFEST_VERSION: u32 = 1;
+/// Every padded upload is split into this many equal slots, real or decoy
+const SLOTS: u32 = 64;
+/// Buckets grow fourfold from 256 KiB to 1 GiB, then in whole GiB
+const MIN_BUCKET: u64 = 256 * 1024;
+const MAX_BUCKET_STEP: u64 = 1024 * 1024 * 1024;
+/// The manifest is padded too, so its size says nothing about the drop
+const MANIFEST_PADDED_LEN: usize = 4096;
+const RANDOM_BUFFER: usize = 1024 * 1024;
+
+/// Where the ciphertext sits in a padded upload, stored on IPFS encrypted
+/// with the session key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PaddingManifest {
+    pub version: u32,
+    pub cid: String,
+    pub ciphertext_len: u64,
+    pub slot_size: u64,
+    /// A permutation of all slots; the first `real_slots()` hold the
+    /// ciphertext in order, the rest are decoys
+    pub order: Vec<u32>,
+}
+
+impl PaddingManifest {
+    fn real_slots(&self) -> u64 {
+        self.ciphertext_len.div_ceil(self.slot_size)
+    }
+
+    fn padded_len(&self) -> u64 {
+        self.slot_size * self.order.len() as u64
+    }
+
+    fn validate(&self) -> Result<()> {
+        let slots = self.order.len() as u64;
+        if self.slot_size == 0 || self.real_slots() > slots {
+            anyhow::bail!("Invalid padding manifest");
+        }
+        let mut seen = vec![false; self.order.len()];
+        for &slot in &self.order {
+            match seen.get_mut(slot as usize) {
+                Some(seen) if !*seen => *seen = true,
+                _ => anyhow::bail!("Invalid padding manifest"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Smallest standard size holding `len` bytes
+pub fn bucket_size(len: u64) -> u64 {
+    let mut bucket = MIN_BUCKET;
+    while bucket < len && bucket < MAX_BUCKET_STEP {
+        bucket *= 4;
+    }
+    if bucket >= len {
+        bucket
+    } else {
+        len.div_ceil(MAX_BUCKET_STEP) * MAX_BUCKET_STEP
+    }
+}
+
+/// Strip the padding prefix from a drop reference, if present
+pub fn padded_manifest_cid(cid: &str) -> Option<&str> {
+    cid.strip_prefix(PADDED_CID_PREFIX)
+}
+
+/// Create a drop whose upload is rounded up to a standard size bucket, with
+/// the ciphertext chunks scattered among random decoy chunks
+pub async fn create_padded_drop(
+    file_path: &str,
+    threshold: u8,
+    total_shards: u8,
+    options: &DropOptions,
+) -> Result<DeadDropCreated> {
+    if threshold > total_shards {
+        anyhow::bail!("Threshold cannot exceed total shards");
+    }
+    if threshold < 2 {
+        anyhow::bail!("Threshold must be at least 2");
+    }
+
+    let session_key = SessionKey::generate();
+    let ciphertext_file = options.staging_file()?;
+    let ciphertext_len =
+        stream_encrypt_file(file_path, ciphertext_file.path(), &session_key, options)
+            .context("Failed to encrypt file")?;
+
+    let mut order: Vec<u32> = (0..SLOTS).collect();
+    order.shuffle(&mut rand::thread_rng());
+    let mut manifest = PaddingManifest {
+        version: MANIFEST_VERSION,
+        cid: String::new(),
+        ciphertext_len,
+        slot_size: bucket_size(ciphertext_len) / SLOTS as u64,
+        order,
+    };
+
+    let padded_file = options.staging_file()?;
+    write_padded(ciphertext_file.path(), padded_file.path(), &manifest)?;
+    drop(ciphertext_file);
+    manifest.cid = upload_ciphertext(padded_file, options).await?;
+
+    let mut manifest_bytes = wire::encode(&manifest)?;
+    // Trailing whitespace is valid JSON, and the wire decoder ignores it
+    manifest_bytes.resize(manifest_bytes.len().max(MANIFEST_PADDED_LEN), b' ');
+    let encrypted_manifest = session_key.encrypt_file(&manifest_bytes);
+    manifest_bytes.zeroize();
+    let manifest_cid = upload_bytes_to_ipfs(encrypted_manifest?).await?;
+
+    let shards = split_session_key(&session_key, threshold, total_shards);
+    drop(session_key);
+    info!(
+        "Created padded drop {} ({} bytes in a {} byte bucket)",
+        manifest_cid,
+        ciphertext_len,
+        manifest.padded_len()
+    );
+
+    Ok(DeadDropCreated {
+        pending: is_upload_pending(options, &manifest.cid),
+        cid: format!("{}{}", PADDED_CID_PREFIX, manifest_cid),
+        shards,
+        drop_id: None,
+        piece_cids: vec![manifest.cid],
+        shred: None,
+        cipher: Some(options.cipher),
+    })
+}
+
+/// Retrieve a padded drop: pick the real chunks out in order, drop the
+/// decoys and the padding, and decrypt
+pub async fn retrieve_padded_drop(
+    manifest_cid: &str,
+    shard_strings: Vec<String>,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<()> {
+    let session_key = recover_session_key(&shard_strings)?;
+    let manifest = fetch_padding_manifest(manifest_cid, &session_key).await?;
+
+    let padded_file = options.staging_file()?;
+    download_file_from_ipfs(&manifest.cid, padded_file.path(), options).await?;
+    let ciphertext_file = options.staging_file()?;
+    read_padded(padded_file.path(), ciphertext_file.path(), &manifest)?;
+    drop(padded_file);
+
+    let decrypted_size =
+        stream_decrypt_file(ciphertext_file.path(), output_path, &session_key, options)
+            .context("Failed to decrypt file")?;
+    info!("Decrypted padded drop: {} bytes", decrypted_size);
+    Ok(())
+}
+
+/// Preflight estimate; the manifest is encrypted, so shards are needed
+pub async fn stat_padded_drop(
+    manifest_cid: &str,
+    shard_strings: Vec<String>,
+    output_path: &Path,
+    options: &DropOptions,
+) -> Result<DropStat> {
+    let session_key = recover_session_key(&shard_strings)?;
+    let manifest = fetch_padding_manifest(manifest_cid, &session_key).await?;
+    drop(session_key);
+
+    let client = ipfs::client()?;
+    let (size, size_source) = object_size(&client, &manifest.cid).await?;
+    let bytes_per_sec = probe_download_speed(&client, &manifest.cid).await;
+    Ok(build_drop_stat(
+        &format!("{}{}", PADDED_CID_PREFIX, manifest_cid),
+        size,
+        manifest.ciphertext_len,
+        size_source,
+        Some(size == manifest.padded_len()),
+        bytes_per_sec,
+        output_path,
+        options,
+    ))
+}
+
+async fn fetch_padding_manifest(
+    manifest_cid: &str,
+    session_key: &SessionKey,
+) -> Result<PaddingManifest> {
+    let encrypted = cat_bytes_from_ipfs(manifest_cid).await?;
+    let mut manifest_bytes = session_key
+        .decrypt_file(&encrypted)
+        .context("Failed to decrypt padding manifest - wrong shards?")?;
+    let manifest = wire::decode::<PaddingManifest>(&manifest_bytes);
+    manifest_bytes.zeroize();
+    let manifest = manifest.context("Invalid padding manifest")?;
+    if manifest.version > MANIFEST_VERSION {
+        anyhow::bail!(
+            "Padding manifest version {} is not supported",
+            manifest.version
+        );
+    }
+    manifest.validate()?;
+    Ok(manifest)
+}
+
+/// Lay the ciphertext out slot by slot: real chunks where the manifest puts
+/// them, random bytes everywhere else
+fn write_padded(
+    ciphertext_path: &Path,
+    padded_path: &Path,
+    manifest: &PaddingManifest,
+) -> Result<()> {
+    let mut chunk_at_slot = vec![None; manifest.order.len()];
+    for (chunk, &slot) in manifest.order.iter().enumerate() {
+        if (chunk as u64) < manifest.real_slots() {
+            chunk_at_slot[slot as usize] = Some(chunk as u64);
+        }
+    }
+
+    let mut ciphertext = File::open(ciphertext_path).context("Failed to open ciphertext")?;
+    let mut output = BufWriter::new(File::create(padded_path)?);
+    for chunk in chunk_at_slot {
+        let written = match chunk {
+            Some(chunk) => {
+                ciphertext.seek(SeekFrom::Start(chunk * manifest.slot_size))?;
+                io::copy(&mut (&mut ciphertext).take(manifest.slot_size), &mut output)?
+            }
+            None => 0,
+        };
+        // The tail of the last chunk is padded like a decoy
+        write_random(&mut output, manifest.slot_size - written)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Reassemble the ciphertext from the real slots of a padded upload
+fn read_padded(
+    padded_path: &Path,
+    ciphertext_path: &Path,
+    manifest: &PaddingManifest,
+) -> Result<()> {
+    let mut padded = File::open(padded_path).context("Failed to open padded drop")?;
+    if padded.metadata()?.len() != manifest.padded_len() {
+        anyhow::bail!("Padded drop does not match its manifest");
+    }
+    let mut output = BufWriter::new(File::create(ciphertext_path)?);
+    let mut remaining = manifest.ciphertext_len;
+    for &slot in manifest.order.iter().take(manifest.real_slots() as usize) {
+        let len = remaining.min(manifest.slot_size);
+        padded.seek(SeekFrom::Start(slot as u64 * manifest.slot_size))?;
+        io::copy(&mut (&mut padded).take(len), &mut output)?;
+        remaining -= len;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+fn write_random<W: Write>(writer: &mut W, mut len: u64) -> io::Result<()> {
+    let mut buffer = vec![0u8; RANDOM_BUFFER.min(len as usize)];
+    while len > 0 {
+        let n = buffer.len().min(len as usize);
+        rand::thread_rng().fill_bytes(&mut buffer[..n]);
+        writer.write_all(&buffer[..n])?;
+        len -= n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_hide_the_exact_size() {
+        assert_eq!(bucket_size(1), MIN_BUCKET);
+        assert_eq!(bucket_size(MIN_BUCKET + 1), MIN_BUCKET * 4);
+        assert_eq!(bucket_size(MAX_BUCKET_STEP), MAX_BUCKET_STEP);
+        assert_eq!(bucket_size(MAX_BUCKET_STEP + 1), 2 * MAX_BUCKET_STEP);
+    }
+
+    #[test]
+    fn padding_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let ciphertext = dir.path().join("ciphertext");
+        let padded = dir.path().join("padded");
+        let restored = dir.path().join("restored");
+        std::fs::write(&ciphertext, b"0123456789abcdefghij").unwrap();
+
+        let manifest = PaddingManifest {
+            version: MANIFEST_VERSION,
+            cid: String::new(),
+            ciphertext_len: 20,
+            slot_size: 8,
+            order: vec![5, 0, 3, 1, 7, 2, 6, 4],
+        };
+        manifest.validate().unwrap();
+        write_padded(&ciphertext, &padded, &manifest).unwrap();
+        assert_eq!(std::fs::metadata(&padded).unwrap().len(), 64);
+
+        read_padded(&padded, &restored, &manifest).unwrap();
+        assert_eq!(std::fs::read(&restored).unwrap(), b"0123456789abcdefghij");
+    }
+}
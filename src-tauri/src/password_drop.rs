@@ -0,0 +1,232 @@
+use crate::crypto::SessionKey;
+use crate::dead_drop::{
+    cat_bytes_from_ipfs, download_and_decrypt, encrypt_and_upload, is_upload_pending,
+    stat_dead_drop, upload_bytes_to_ipfs, DeadDropCreated, DropOptions, DropStat,
+};
+use crate::wire;
+use anyhow::{Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tracing::info;
+use zeroize::Zeroize;
+
+/// Drop references pointing at a password manifest carry this prefix
+pub const PASSWORD_CID_PREFIX: &str = "pw:";
+const MANIFEST_VERSION: u32 = 1;
+const MIN_PASSPHRASE_CHARS: usize = 8;
+const SALT_LEN: usize = 16;
+const CHECK_LEN: usize = 8;
+/// Spoken passphrases are weak, so the default is far costlier than the
+/// identity file's: 64 MiB, 3 passes
+pub const DEFAULT_M_COST: u32 = 64 * 1024;
+pub const DEFAULT_T_COST: u32 = 3;
+pub const DEFAULT_P_COST: u32 = 1;
+/// The manifest is public; refuse parameters that would exhaust this machine
+const MAX_M_COST: u32 = 1024 * 1024;
+pub const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 16;
+
+/// Argon2id parameters the session key was derived with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KdfParams {
+    pub algorithm: String,
+    pub salt: String, // hex
+    pub m_cost: u32,  // KiB
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl KdfParams {
+    fn generate() -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self {
+            algorithm: "argon2id".to_string(),
+            salt: hex::encode(salt),
+            m_cost: DEFAULT_M_COST,
+            t_cost: DEFAULT_T_COST,
+            p_cost: DEFAULT_P_COST,
+        }
+    }
+
+    fn derive_key(&self, passphrase: &str) -> Result<SessionKey> {
+        if self.algorithm != "argon2id" {
+            anyhow::bail!("Unsupported key derivation {}", self.algorithm);
+        }
+        if self.m_cost > MAX_M_COST || self.t_cost > MAX_T_COST || self.p_cost > MAX_P_COST {
+            anyhow::bail!("Key derivation parameters are too costly");
+        }
+        let salt = hex::decode(&self.salt).context("Invalid salt")?;
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| anyhow::anyhow!("Invalid Argon2 params: {:?}", e))?;
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow::anyhow!("Failed to derive key: {:?}", e))?;
+        let session_key = SessionKey::from_bytes(&key);
+        key.zeroize();
+        session_key
+    }
+}
+
+/// Public manifest of a password drop. `key_check` lets a wrong passphrase
+/// fail before the download; it reveals nothing the ciphertext doesn't.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PasswordManifest {
+    pub version: u32,
+    pub cid: String,
+    pub kdf: KdfParams,
+    pub key_check: String, // hex
+}
+
+/// Strip the password prefix from a drop reference, if present
+pub fn password_manifest_cid(cid: &str) -> Option<&str> {
+    cid.strip_prefix(PASSWORD_CID_PREFIX)
+}
+
+fn key_check(session_key: &SessionKey) -> String {
+    let mut key_bytes = session_key.as_bytes();
+    let digest = Sha256::new()
+        .chain_update(b"control/password-drop/check")
+        .chain_update(key_bytes)
+        .finalize();
+    key_bytes.zeroize();
+    hex::encode(&digest[..CHECK_LEN])
+}
+
+/// Create a drop opened by a passphrase alone, e.g. one read out over the
+/// phone, instead of shards
+pub async fn create_password_drop(
+    file_path: &str,
+    passphrase: &str,
+    options: &DropOptions,
+) -> Result<DeadDropCreated> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_CHARS {
+        anyhow::bail!(
+            "Passphrase must be at least {} characters",
+            MIN_PASSPHRASE_CHARS
+        );
+    }
+    let kdf = KdfParams::generate();
+    let derive_kdf = kdf.clone();
+    let session_key = off_runtime(passphrase, move |passphrase| {
+        derive_kdf.derive_key(passphrase)
+    })
+    .await?;
+    let cid = encrypt_and_upload(file_path, &session_key, options).await?;
+
+    let manifest = PasswordManifest {
+        version: MANIFEST_VERSION,
+        cid: cid.clone(),
+        key_check: key_check(&session_key),
+        kdf,
+    };
+    drop(session_key);
+    let manifest_cid = upload_bytes_to_ipfs(wire::encode(&manifest)?).await?;
+    info!("Created password drop {}", manifest_cid);
+
+    Ok(DeadDropCreated {
+        pending: is_upload_pending(options, &cid),
+        cid: format!("{}{}", PASSWORD_CID_PREFIX, manifest_cid),
+        shards: Vec::new(),
+        drop_id: None,
+        piece_cids: vec![cid],
+        shred: None,
+        cipher: Some(options.cipher),
+    })
+}
+
+/// Fetch and check the manifest of a password drop
+pub async fn fetch_password_manifest(manifest_cid: &str) -> Result<PasswordManifest> {
+    let bytes = cat_bytes_from_ipfs(manifest_cid).await?;
+    let manifest: PasswordManifest =
+        wire::decode(&bytes).context("Invalid password manifest")?;
+    if manifest.version > MANIFEST_VERSION {
+        anyhow::bail!(
+            "Password manifest version {} is not supported",
+            manifest.version
+        );
+    }
+    Ok(manifest)
+}
+
+/// Derive the session key from the passphrase and check it against the manifest
+pub fn unlock(manifest: &PasswordManifest, passphrase: &str) -> Result<SessionKey> {
+    let session_key = manifest.kdf.derive_key(passphrase)?;
+    if key_check(&session_key) != manifest.key_check {
+        anyhow::bail!("Wrong passphrase");
+    }
+    Ok(session_key)
+}
+
+/// Argon2 at these costs takes a while, so it runs on a blocking thread
+async fn off_runtime<T, F>(passphrase: &str, derive: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&str) -> Result<T> + Send + 'static,
+{
+    let mut passphrase = passphrase.to_string();
+    tokio::task::spawn_blocking(move || {
+        let result = derive(&passphrase);
+        passphrase.zeroize();
+        result
+    })
+    .await?
+}
+
+/// Retrieve a password drop
+pub async fn retrieve_password_drop(
+    manifest_cid: &str,
+    passphrase: Option<&str>,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<()> {
+    let passphrase = passphrase.context("This drop needs a passphrase")?;
+    let manifest = fetch_password_manifest(manifest_cid).await?;
+    let unlock_manifest = manifest.clone();
+    let session_key = off_runtime(passphrase, move |passphrase| {
+        unlock(&unlock_manifest, passphrase)
+    })
+    .await?;
+    download_and_decrypt(&manifest.cid, &session_key, output_path, options).await?;
+    Ok(())
+}
+
+/// Preflight estimate; the manifest is public, so no passphrase is needed
+pub async fn stat_password_drop(
+    manifest_cid: &str,
+    output_path: &Path,
+    options: &DropOptions,
+) -> Result<DropStat> {
+    let manifest = fetch_password_manifest(manifest_cid).await?;
+    stat_dead_drop(&manifest.cid, None, output_path, options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_passphrase_unlocks() {
+        // Cheap parameters; the derivation is the same at any cost
+        let kdf = KdfParams {
+            m_cost: 64,
+            t_cost: 1,
+            ..KdfParams::generate()
+        };
+        let session_key = kdf.derive_key("correct horse battery").unwrap();
+        let manifest = PasswordManifest {
+            version: MANIFEST_VERSION,
+            cid: "bafkreiabc".to_string(),
+            key_check: key_check(&session_key),
+            kdf,
+        };
+
+        let unlocked = unlock(&manifest, "correct horse battery").unwrap();
+        assert_eq!(unlocked.as_bytes(), session_key.as_bytes());
+        assert!(unlock(&manifest, "correct horse staple").is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::contacts::Contact;
+use crate::dead_drop::{is_upload_pending, upload_ciphertext, DeadDropCreated, DropOptions};
+use anyhow::{Context, Result};
+use sequoia_openpgp::cert::Cert;
+use sequoia_openpgp::parse::Parse;
+use sequoia_openpgp::policy::StandardPolicy;
+use sequoia_openpgp::serialize::stream::{Armorer, Encryptor2, LiteralWriter, Message};
+use sequoia_openpgp::serialize::SerializeInto;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Recipients of an OpenPGP-format drop. The payload is a standard OpenPGP
+/// message, so `ipfs cat <cid> | gpg -d` opens it outside Control.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PgpDropConfig {
+    /// Armored public keys, fingerprints of keys stored on contacts, or
+    /// public IDs of contacts with a stored key
+    pub recipients: Vec<String>,
+    /// Write an ASCII-armored message instead of binary
+    #[serde(default)]
+    pub armor: bool,
+}
+
+/// An OpenPGP public key attached to a contact
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PgpKey {
+    /// Upper-case hex, no spaces
+    pub fingerprint: String,
+    pub user_ids: Vec<String>,
+    /// The certificate without secret key material
+    pub armored: String,
+}
+
+/// Parse an armored or binary public key, keeping only public material.
+/// Keys that can't receive encrypted messages today are refused.
+pub fn parse_key(key: &str) -> Result<PgpKey> {
+    let cert = Cert::from_bytes(key.trim().as_bytes())
+        .context("Not an OpenPGP public key")?
+        .strip_secret_key_material();
+    let policy = StandardPolicy::new();
+    let valid = cert
+        .with_policy(&policy, None)
+        .context("Key is not valid under the standard policy")?;
+    valid.alive().context("Key has expired")?;
+    if valid
+        .keys()
+        .supported()
+        .alive()
+        .revoked(false)
+        .for_transport_encryption()
+        .next()
+        .is_none()
+    {
+        anyhow::bail!("Key has no usable encryption subkey");
+    }
+
+    let user_ids = valid
+        .userids()
+        .map(|u| String::from_utf8_lossy(u.userid().value()).into_owned())
+        .collect();
+    let armored = String::from_utf8(cert.armored().to_vec()?)?;
+    Ok(PgpKey {
+        fingerprint: cert.fingerprint().to_hex(),
+        user_ids,
+        armored,
+    })
+}
+
+/// The certificate for a recipient: an armored key as is, otherwise the key
+/// stored on the matching contact
+fn resolve_recipient(recipient: &str, contacts: &[Contact]) -> Result<Cert> {
+    let recipient = recipient.trim();
+    if recipient.starts_with("-----BEGIN PGP") {
+        return Cert::from_bytes(parse_key(recipient)?.armored.as_bytes());
+    }
+    let fingerprint = recipient.replace(' ', "").to_uppercase();
+    let key = contacts
+        .iter()
+        .find_map(|c| {
+            c.pgp_key
+                .as_ref()
+                .filter(|k| c.public_id == recipient || k.fingerprint == fingerprint)
+        })
+        .with_context(|| format!("No OpenPGP key stored for {}", recipient))?;
+    Cert::from_bytes(key.armored.as_bytes()).context("Stored OpenPGP key is invalid")
+}
+
+/// Encrypt a file as an OpenPGP message to every recipient's encryption
+/// subkeys and upload it. The drop reference is the plain CID; no shards are
+/// produced.
+pub async fn create_pgp_drop(
+    file_path: &str,
+    config: &PgpDropConfig,
+    contacts: &[Contact],
+    options: &DropOptions,
+) -> Result<DeadDropCreated> {
+    if config.recipients.is_empty() {
+        anyhow::bail!("An OpenPGP drop needs at least one recipient");
+    }
+    let certs = config
+        .recipients
+        .iter()
+        .map(|r| resolve_recipient(r, contacts))
+        .collect::<Result<Vec<_>>>()?;
+
+    let temp_file = options.staging_file()?;
+    let source = PathBuf::from(file_path);
+    let target = temp_file.path().to_path_buf();
+    let armor = config.armor;
+    let encrypted_size =
+        tokio::task::spawn_blocking(move || encrypt_file(&source, &target, &certs, armor))
+            .await??;
+    info!(
+        "Encrypted file as OpenPGP to {} keys: {} bytes",
+        config.recipients.len(),
+        encrypted_size
+    );
+
+    let cid = upload_ciphertext(temp_file, options).await?;
+    Ok(DeadDropCreated {
+        pending: is_upload_pending(options, &cid),
+        cid,
+        shards: Vec::new(),
+        drop_id: None,
+        piece_cids: Vec::new(),
+        shred: None,
+        cipher: None,
+    })
+}
+
+/// The session key is sealed once per encryption subkey (one PKESK packet
+/// each), so any recipient's existing tooling can open the message
+fn encrypt_file(source: &Path, target: &Path, certs: &[Cert], armor: bool) -> Result<u64> {
+    let policy = StandardPolicy::new();
+    let mut recipients = Vec::new();
+    for cert in certs {
+        let keys: Vec<_> = cert
+            .keys()
+            .with_policy(&policy, None)
+            .supported()
+            .alive()
+            .revoked(false)
+            .for_transport_encryption()
+            .collect();
+        if keys.is_empty() {
+            anyhow::bail!("Key {} has no usable encryption subkey", cert.fingerprint());
+        }
+        recipients.extend(keys);
+    }
+
+    let mut input = BufReader::new(File::open(source).context("Failed to open file")?);
+    let mut output = BufWriter::new(File::create(target)?);
+    {
+        let mut message = Message::new(&mut output);
+        if armor {
+            message = Armorer::new(message).build()?;
+        }
+        let message = Encryptor2::for_recipients(message, recipients).build()?;
+        let mut message = LiteralWriter::new(message).build()?;
+        io::copy(&mut input, &mut message).context("Failed to encrypt file")?;
+        message.finalize()?;
+    }
+    output.flush()?;
+    Ok(std::fs::metadata(target)?.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sequoia_openpgp::cert::CertBuilder;
+    use sequoia_openpgp::{Packet, PacketPile};
+
+    fn generate() -> Cert {
+        let (cert, _) = CertBuilder::general_purpose(None, Some("alice@example.org"))
+            .generate()
+            .unwrap();
+        cert
+    }
+
+    #[test]
+    fn parse_key_strips_secrets() {
+        let cert = generate();
+        let with_secrets = String::from_utf8(cert.as_tsk().armored().to_vec().unwrap()).unwrap();
+
+        let key = parse_key(&with_secrets).unwrap();
+        assert_eq!(key.fingerprint, cert.fingerprint().to_hex());
+        assert_eq!(key.user_ids, vec!["alice@example.org".to_string()]);
+        assert!(!Cert::from_bytes(key.armored.as_bytes()).unwrap().is_tsk());
+    }
+
+    #[test]
+    fn seals_session_key_to_each_recipient() {
+        let alice = generate();
+        let bob = generate();
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("plain.txt");
+        let encrypted = dir.path().join("plain.txt.pgp");
+        std::fs::write(&plain, b"dead drop").unwrap();
+
+        encrypt_file(&plain, &encrypted, &[alice, bob], false).unwrap();
+
+        let pile = PacketPile::from_file(&encrypted).unwrap();
+        let sealed = pile
+            .descendants()
+            .filter(|p| matches!(p, Packet::PKESK(_)))
+            .count();
+        assert_eq!(sealed, 2);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::catalog::{now_secs, DropCatalog, DropRecord};
+use crate::dead_drop::{drop_manifest_cid, is_pinned_locally, pin_cid};
+use crate::events;
+use crate::ipfs;
+use crate::notifications;
+use crate::settings;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+const MIN_CHECK_INTERVAL_SECS: u64 = 60;
+const REMOTE_PIN_STATUSES: &str = "queued,pinning,pinned,failed";
+/// A re-pin whose blocks can't be found would otherwise hang the monitor
+const REPIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// How often drops are checked and how many pinned copies each should have
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PinHealthSettings {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// Confirmed pins (local node + pinning services) each drop should have
+    pub replication_factor: usize,
+    /// Remote pinning services registered on the IPFS node (`ipfs pin remote service add`)
+    pub pinning_services: Vec<String>,
+}
+
+impl Default for PinHealthSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 3600,
+            replication_factor: 1,
+            pinning_services: Vec::new(),
+        }
+    }
+}
+
+impl PinHealthSettings {
+    fn check_interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(MIN_CHECK_INTERVAL_SECS))
+    }
+}
+
+/// Pin status of one CID at one location
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PinLocationStatus {
+    pub location: String, // "local" or the pinning service name
+    pub cid: String,
+    pub status: String,   // pinned, queued, pinning, failed, missing
+    pub repinned: bool,
+    pub error: Option<String>,
+}
+
+/// Health of a catalogued drop after a check
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DropPinHealth {
+    pub cid: String,
+    pub file_name: String,
+    pub replicas: usize,
+    pub replication_factor: usize,
+    pub locations: Vec<PinLocationStatus>,
+}
+
+impl DropPinHealth {
+    pub fn at_risk(&self) -> bool {
+        self.replicas < self.replication_factor
+    }
+}
+
+/// Run the pin monitor in the background for the lifetime of the app,
+/// emitting `drop_at_risk` for drops below the replication factor
+pub fn start_pin_monitor(app: tauri::AppHandle, data_dir: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let settings = settings::pin_health_settings();
+
+            if settings.enabled {
+                match check_pin_health(&data_dir, &settings).await {
+                    Ok(report) => {
+                        for health in report.into_iter().filter(|h| h.at_risk()) {
+                            info!(
+                                "Drop {} at risk: {}/{} replicas",
+                                health.cid, health.replicas, health.replication_factor
+                            );
+                            notifications::drop_at_risk(&app, &health);
+                            events::emit(&app, "drop_at_risk", health);
+                        }
+                    }
+                    Err(e) => warn!("Pin health check failed: {}", e),
+                }
+            }
+
+            tokio::time::sleep(settings.check_interval()).await;
+        }
+    });
+}
+
+/// Check every live, pinned drop in the catalog and re-pin anything missing
+pub async fn check_pin_health(data_dir: &Path, settings: &PinHealthSettings) -> Result<Vec<DropPinHealth>> {
+    let catalog = DropCatalog::load(data_dir)?;
+    let client = ipfs::client()?;
+    let now = now_secs();
+
+    let mut report = Vec::new();
+    for record in catalog.records() {
+        if !record.pinned || record.is_expired(now) {
+            continue;
+        }
+        report.push(check_drop(&client, record, settings).await);
+    }
+
+    Ok(report)
+}
+
+async fn check_drop(client: &reqwest::Client, record: &DropRecord, settings: &PinHealthSettings) -> DropPinHealth {
+    // Erasure-coded and indexed drops need their manifest and every piece
+    let mut cids = vec![drop_manifest_cid(&record.cid).unwrap_or(&record.cid).to_string()];
+    cids.extend(record.piece_cids.iter().cloned());
+
+    let mut locations = Vec::new();
+    for cid in &cids {
+        locations.push(check_local(client, cid).await);
+        for service in &settings.pinning_services {
+            locations.push(check_remote(client, service, cid).await);
+        }
+    }
+
+    // A location only counts as a replica if it holds every CID of the drop
+    let replicas = std::iter::once("local")
+        .chain(settings.pinning_services.iter().map(String::as_str))
+        .filter(|&name| {
+            locations
+                .iter()
+                .filter(|l| l.location == name)
+                .all(|l| l.status == "pinned")
+        })
+        .count();
+
+    DropPinHealth {
+        cid: record.cid.clone(),
+        file_name: record.file_name.clone(),
+        replicas,
+        replication_factor: settings.replication_factor,
+        locations,
+    }
+}
+
+async fn check_local(client: &reqwest::Client, cid: &str) -> PinLocationStatus {
+    let mut status = PinLocationStatus {
+        location: "local".to_string(),
+        cid: cid.to_string(),
+        status: "missing".to_string(),
+        repinned: false,
+        error: None,
+    };
+
+    match is_pinned_locally(client, cid).await {
+        Ok(true) => status.status = "pinned".to_string(),
+        Ok(false) => match tokio::time::timeout(REPIN_TIMEOUT, pin_cid(client, cid)).await {
+            Ok(Ok(())) => {
+                info!("Re-pinned {} locally", cid);
+                status.status = "pinned".to_string();
+                status.repinned = true;
+            }
+            Ok(Err(e)) => status.error = Some(e.to_string()),
+            Err(_) => status.error = Some("Re-pinning timed out".to_string()),
+        },
+        Err(e) => status.error = Some(e.to_string()),
+    }
+
+    status
+}
+
+async fn check_remote(client: &reqwest::Client, service: &str, cid: &str) -> PinLocationStatus {
+    let mut status = PinLocationStatus {
+        location: service.to_string(),
+        cid: cid.to_string(),
+        status: "missing".to_string(),
+        repinned: false,
+        error: None,
+    };
+
+    match remote_pin_status(client, service, cid).await {
+        Ok(Some(remote)) if remote != "failed" => status.status = remote,
+        Ok(_) => match remote_pin_add(client, service, cid).await {
+            Ok(()) => {
+                info!("Re-pinned {} on {}", cid, service);
+                status.status = "queued".to_string();
+                status.repinned = true;
+            }
+            Err(e) => status.error = Some(e.to_string()),
+        },
+        Err(e) => status.error = Some(e.to_string()),
+    }
+
+    status
+}
+
+/// Best status reported by a remote pinning service, if it knows the CID
+async fn remote_pin_status(client: &reqwest::Client, service: &str, cid: &str) -> Result<Option<String>> {
+    let response = client
+        .post(ipfs::api("pin/remote/ls"))
+        .query(&[("service", service), ("cid", cid), ("status", REMOTE_PIN_STATUSES)])
+        .send()
+        .await
+        .context("Failed to query remote pins")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("Remote pin query failed: {}", response.status());
+    }
+
+    // Response is newline-delimited JSON, one object per pin
+    let body = response.text().await?;
+    let mut best: Option<String> = None;
+    for line in body.lines().filter(|l| !l.trim().is_empty()) {
+        let json: serde_json::Value = serde_json::from_str(line).context("Invalid remote pin response")?;
+        let Some(pin_status) = json["Status"].as_str() else {
+            continue;
+        };
+        if best.as_deref() != Some("pinned") && (best.is_none() || pin_status != "failed") {
+            best = Some(pin_status.to_string());
+        }
+    }
+
+    Ok(best)
+}
+
+async fn remote_pin_add(client: &reqwest::Client, service: &str, cid: &str) -> Result<()> {
+    let response = client
+        .post(ipfs::api("pin/remote/add"))
+        .query(&[("arg", cid), ("service", service), ("background", "true")])
+        .send()
+        .await
+        .context("Failed to request remote pin")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("Remote pin failed: {}", response.status());
+    }
+
+    Ok(())
+}
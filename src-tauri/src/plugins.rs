@@ -0,0 +1,521 @@
+use crate::p2p::{GhostMessage, P2PEvents};
+use crate::settings;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tracing::{info, warn};
+use wasmtime::{Caller, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+const PLUGINS_DIR: &str = "plugins";
+const MANIFEST_FILE: &str = "plugin.json";
+const MODULE_FILE: &str = "plugin.wasm";
+/// Roughly a hundred milliseconds of work per message
+const FUEL_PER_CALL: u64 = 100_000_000;
+/// How often the engine's epoch advances
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+/// Wall-clock limit per call in epoch ticks (250 ms), for time fuel doesn't
+/// count, such as host calls
+const DEADLINE_TICKS: u64 = 25;
+/// How long the caller waits for a plugin call, should the deadline fail
+const CALL_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_MEMORY_BYTES: usize = 64 * 1024 * 1024;
+const MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+const MAX_ALERT_CHARS: usize = 200;
+/// Module the host functions are imported from
+const HOST_MODULE: &str = "control";
+
+/// What a plugin may do; a plugin gets the capabilities it asks for in its
+/// manifest and the user granted in the settings, nothing else
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// See decrypted incoming messages
+    ReadIncoming,
+    /// See outgoing messages before they are encrypted
+    ReadOutgoing,
+    /// Replace the content of incoming messages; implies reading them
+    ModifyIncoming,
+    /// Replace the content of outgoing messages; implies reading them
+    ModifyOutgoing,
+    /// Raise `plugin_alert` events through the `control.notify` import
+    Notify,
+    /// Write lines to Control's log through the `control.log` import. Logs
+    /// outlive messages, so this can leak what the plugin saw.
+    Log,
+}
+
+/// Plugins are off unless enabled, and each one only gets what is granted here
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PluginSettings {
+    pub enabled: bool,
+    /// Plugin name -> capabilities the user allowed
+    pub granted: BTreeMap<String, Vec<Capability>>,
+}
+
+/// `plugin.json` next to `plugin.wasm` in `plugins/<name>/`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PluginManifest {
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    /// Capabilities the plugin needs, shown to the user before granting
+    pub capabilities: Vec<Capability>,
+}
+
+/// A plugin as listed to the user
+#[derive(Serialize, Debug, Clone)]
+pub struct PluginInfo {
+    pub manifest: PluginManifest,
+    /// Requested and granted
+    pub active: Vec<Capability>,
+    /// Why the plugin can't run, if it can't
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+/// What `on_message` receives, as JSON
+#[derive(Serialize)]
+struct PluginMessage<'a> {
+    direction: Direction,
+    /// The sender for incoming messages, the recipient for outgoing ones
+    peer: &'a str,
+    id: &'a str,
+    content: &'a str,
+    timestamp: u64,
+}
+
+struct Plugin {
+    manifest: PluginManifest,
+    module: Option<Module>,
+    error: Option<String>,
+}
+
+impl Plugin {
+    fn active(&self, settings: &PluginSettings) -> Vec<Capability> {
+        let granted = settings
+            .granted
+            .get(&self.manifest.name)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let mut active: Vec<Capability> = self
+            .manifest
+            .capabilities
+            .iter()
+            .filter(|c| granted.contains(c))
+            .copied()
+            .collect();
+        active.sort();
+        active.dedup();
+        active
+    }
+}
+
+struct PluginHost {
+    engine: Engine,
+    plugins: Vec<Plugin>,
+}
+
+struct HostState {
+    plugin: String,
+    alerts: Vec<String>,
+    limits: StoreLimits,
+}
+
+/// Loaded plugins; empty until `load`
+static HOST: Mutex<Option<PluginHost>> = Mutex::new(None);
+/// Shared by every load so one thread can tick its epoch
+static ENGINE: OnceLock<Engine> = OnceLock::new();
+
+fn plugins_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join(PLUGINS_DIR)
+}
+
+/// Compile every plugin in `plugins/`; plugins that fail are listed with
+/// their error instead of being loaded
+pub fn load(data_dir: &Path) -> Result<()> {
+    let engine = engine()?;
+
+    let mut plugins = Vec::new();
+    let dir = plugins_dir(data_dir);
+    if dir.exists() {
+        let mut entries: Vec<_> = fs::read_dir(&dir)
+            .context("Failed to read plugins directory")?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .collect();
+        entries.sort();
+        for path in entries {
+            match load_plugin(&engine, &path) {
+                Ok(plugin) => plugins.push(plugin),
+                Err(e) => warn!("Skipping plugin in {}: {:#}", path.display(), e),
+            }
+        }
+    }
+
+    info!("Loaded {} plugins", plugins.len());
+    *HOST.lock().unwrap() = Some(PluginHost { engine, plugins });
+    Ok(())
+}
+
+/// The plugin engine, started with a thread that advances its epoch so calls
+/// can be interrupted after `DEADLINE_TICKS`
+fn engine() -> Result<Engine> {
+    if let Some(engine) = ENGINE.get() {
+        return Ok(engine.clone());
+    }
+    let mut config = wasmtime::Config::new();
+    config.consume_fuel(true);
+    config.epoch_interruption(true);
+    let engine = Engine::new(&config).context("Failed to start the plugin engine")?;
+    if ENGINE.set(engine.clone()).is_ok() {
+        std::thread::Builder::new()
+            .name("plugin-epoch".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(EPOCH_TICK);
+                engine.increment_epoch();
+            })
+            .context("Failed to start the plugin timer")?;
+    }
+    Ok(ENGINE.get().expect("engine was just set").clone())
+}
+
+fn load_plugin(engine: &Engine, path: &Path) -> Result<Plugin> {
+    let manifest: PluginManifest = serde_json::from_slice(
+        &fs::read(path.join(MANIFEST_FILE)).context("Failed to read plugin.json")?,
+    )
+    .context("Invalid plugin.json")?;
+    let (module, error) = match Module::from_file(engine, path.join(MODULE_FILE))
+        .and_then(|module| check_imports(&module, &manifest.capabilities).map(|_| module))
+    {
+        Ok(module) => (Some(module), None),
+        Err(e) => (None, Some(format!("{:#}", e))),
+    };
+    Ok(Plugin {
+        manifest,
+        module,
+        error,
+    })
+}
+
+/// A module may only import host functions its manifest asks for, so what it
+/// could do is visible before it is granted anything
+fn check_imports(module: &Module, requested: &[Capability]) -> Result<()> {
+    for import in module.imports() {
+        let allowed = import.module() == HOST_MODULE
+            && match import.name() {
+                "log" => requested.contains(&Capability::Log),
+                "notify" => requested.contains(&Capability::Notify),
+                _ => false,
+            };
+        if !allowed {
+            anyhow::bail!(
+                "Imports {}.{}, which it did not ask for",
+                import.module(),
+                import.name()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Loaded plugins with the capabilities they currently have
+pub fn list() -> Vec<PluginInfo> {
+    let settings = settings::plugin_settings();
+    let host = HOST.lock().unwrap();
+    host.iter()
+        .flat_map(|host| host.plugins.iter())
+        .map(|plugin| PluginInfo {
+            manifest: plugin.manifest.clone(),
+            active: plugin.active(&settings),
+            error: plugin.error.clone(),
+        })
+        .collect()
+}
+
+/// Let plugins see and rewrite a decrypted incoming message before it is shown
+pub fn incoming(message: &mut GhostMessage, events: &dyn P2PEvents) {
+    process(
+        Direction::Incoming,
+        &message.from,
+        &message.id,
+        message.timestamp,
+        &mut message.content,
+        events,
+    );
+}
+
+/// Let plugins see and rewrite an outgoing message before it is encrypted
+pub fn outgoing(recipient: &str, message_id: &str, content: &mut String, events: &dyn P2PEvents) {
+    process(
+        Direction::Outgoing,
+        recipient,
+        message_id,
+        crate::catalog::now_secs(),
+        content,
+        events,
+    );
+}
+
+/// Run every plugin allowed to see this direction, in directory order, each
+/// seeing the content as left by the one before
+fn process(
+    direction: Direction,
+    peer: &str,
+    id: &str,
+    timestamp: u64,
+    content: &mut String,
+    events: &dyn P2PEvents,
+) {
+    let settings = settings::plugin_settings();
+    if !settings.enabled {
+        return;
+    }
+    let (read, modify) = match direction {
+        Direction::Incoming => (Capability::ReadIncoming, Capability::ModifyIncoming),
+        Direction::Outgoing => (Capability::ReadOutgoing, Capability::ModifyOutgoing),
+    };
+    // Modules are cheap to clone; the lock isn't held while plugins run
+    let (engine, runnable) = {
+        let host = HOST.lock().unwrap();
+        let Some(host) = host.as_ref() else {
+            return;
+        };
+        let runnable: Vec<(String, Module, Vec<Capability>)> = host
+            .plugins
+            .iter()
+            .filter_map(|plugin| {
+                let module = plugin.module.clone()?;
+                let active = plugin.active(&settings);
+                (active.contains(&read) || active.contains(&modify))
+                    .then(|| (plugin.manifest.name.clone(), module, active))
+            })
+            .collect();
+        (host.engine.clone(), runnable)
+    };
+
+    for (name, module, active) in runnable {
+        let may_modify = active.contains(&modify);
+        let input = match serde_json::to_vec(&PluginMessage {
+            direction,
+            peer,
+            id,
+            content,
+            timestamp,
+        }) {
+            Ok(input) => input,
+            Err(e) => {
+                warn!("Failed to encode message for plugin {}: {}", name, e);
+                continue;
+            }
+        };
+        match run_blocking(engine.clone(), module, name.clone(), active, input) {
+            Ok((output, alerts)) => {
+                if let Some(output) = output {
+                    if may_modify {
+                        *content = output;
+                    } else {
+                        warn!("Ignored change from plugin {}, which may only read", name);
+                    }
+                }
+                for alert in alerts {
+                    let _ = events.emit_event(
+                        "plugin_alert",
+                        serde_json::json!({
+                            "plugin": name,
+                            "message_id": id,
+                            "text": alert,
+                        }),
+                    );
+                }
+            }
+            Err(e) => warn!("Plugin {} failed: {:#}", name, e),
+        }
+    }
+}
+
+/// Run a plugin call on the blocking pool and wait at most `CALL_TIMEOUT`.
+/// Callers are synchronous (the P2P actor's handlers), so on a multi-thread
+/// runtime the wait uses `block_in_place` to let this worker's other tasks
+/// move on meanwhile.
+fn run_blocking(
+    engine: Engine,
+    module: Module,
+    name: String,
+    active: Vec<Capability>,
+    input: Vec<u8>,
+) -> Result<(Option<String>, Vec<String>)> {
+    use tokio::runtime::{Handle, RuntimeFlavor};
+
+    let handle = match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => handle,
+        // No runtime to hand the call to; the epoch deadline still bounds it
+        _ => return call(&engine, &module, &name, &active, &input),
+    };
+    let task = handle.spawn_blocking(move || call(&engine, &module, &name, &active, &input));
+    tokio::task::block_in_place(|| {
+        handle.block_on(async {
+            tokio::time::timeout(CALL_TIMEOUT, task)
+                .await
+                .context("Plugin timed out")?
+                .context("Plugin call panicked")?
+        })
+    })
+}
+
+/// Instantiate the module with the host functions its capabilities allow,
+/// hand it the message and return its replacement content and alerts
+fn call(
+    engine: &Engine,
+    module: &Module,
+    name: &str,
+    active: &[Capability],
+    input: &[u8],
+) -> Result<(Option<String>, Vec<String>)> {
+    let mut store = Store::new(
+        engine,
+        HostState {
+            plugin: name.to_string(),
+            alerts: Vec::new(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(MAX_MEMORY_BYTES)
+                .instances(1)
+                .build(),
+        },
+    );
+    store.limiter(|state| &mut state.limits);
+    store.set_fuel(FUEL_PER_CALL)?;
+    store.set_epoch_deadline(DEADLINE_TICKS);
+
+    let mut linker = Linker::new(engine);
+    if active.contains(&Capability::Log) {
+        linker.func_wrap(
+            HOST_MODULE,
+            "log",
+            |mut caller: Caller<'_, HostState>, ptr: u32, len: u32| {
+                if let Some(text) = read_string(&mut caller, ptr, len) {
+                    info!("[plugin {}] {}", caller.data().plugin, text);
+                }
+            },
+        )?;
+    } else {
+        // Requested but not granted: the lines go nowhere
+        linker.func_wrap(
+            HOST_MODULE,
+            "log",
+            |_: Caller<'_, HostState>, _: u32, _: u32| {},
+        )?;
+    }
+    if active.contains(&Capability::Notify) {
+        linker.func_wrap(
+            HOST_MODULE,
+            "notify",
+            |mut caller: Caller<'_, HostState>, ptr: u32, len: u32| {
+                if let Some(text) = read_string(&mut caller, ptr, len) {
+                    let text: String = text.chars().take(MAX_ALERT_CHARS).collect();
+                    caller.data_mut().alerts.push(text);
+                }
+            },
+        )?;
+    } else {
+        // Requested but not granted: the plugin runs, its alerts go nowhere
+        linker.func_wrap(
+            HOST_MODULE,
+            "notify",
+            |_: Caller<'_, HostState>, _: u32, _: u32| {},
+        )?;
+    }
+
+    let instance = linker.instantiate(&mut store, module)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .context("Plugin exports no memory")?;
+    let alloc = instance.get_typed_func::<u32, u32>(&mut store, "control_alloc")?;
+    let on_message = instance.get_typed_func::<(u32, u32), u64>(&mut store, "on_message")?;
+
+    let input_ptr = alloc.call(&mut store, input.len() as u32)?;
+    memory
+        .write(&mut store, input_ptr as usize, input)
+        .context("Plugin returned an invalid buffer")?;
+
+    let result = on_message.call(&mut store, (input_ptr, input.len() as u32))?;
+    let output = match unpack(result) {
+        None => None,
+        Some((ptr, len)) => {
+            if len > MAX_OUTPUT_BYTES {
+                anyhow::bail!("Plugin output too large");
+            }
+            let bytes = memory
+                .data(&store)
+                .get(ptr..ptr.saturating_add(len))
+                .context("Plugin returned an invalid buffer")?;
+            Some(String::from_utf8(bytes.to_vec()).context("Plugin output is not UTF-8")?)
+        }
+    };
+    Ok((output, store.into_data().alerts))
+}
+
+/// `on_message` returns `ptr << 32 | len` of the new content, or 0 to keep it
+fn unpack(result: u64) -> Option<(usize, usize)> {
+    if result == 0 {
+        return None;
+    }
+    Some(((result >> 32) as usize, (result & 0xffff_ffff) as usize))
+}
+
+fn read_string(caller: &mut Caller<'_, HostState>, ptr: u32, len: u32) -> Option<String> {
+    let memory = caller.get_export("memory")?.into_memory()?;
+    let (ptr, len) = (ptr as usize, len as usize);
+    let bytes = memory.data(&caller).get(ptr..ptr.checked_add(len)?)?;
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin(capabilities: Vec<Capability>) -> Plugin {
+        Plugin {
+            manifest: PluginManifest {
+                name: "translate".to_string(),
+                version: String::new(),
+                description: String::new(),
+                capabilities,
+            },
+            module: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn active_is_requested_and_granted() {
+        let plugin = plugin(vec![Capability::ReadIncoming, Capability::ModifyIncoming]);
+        let mut settings = PluginSettings::default();
+        assert!(plugin.active(&settings).is_empty());
+
+        settings.granted.insert(
+            "translate".to_string(),
+            vec![Capability::ModifyIncoming, Capability::Notify],
+        );
+        assert_eq!(plugin.active(&settings), vec![Capability::ModifyIncoming]);
+    }
+
+    #[test]
+    fn unpacks_result() {
+        assert_eq!(unpack(0), None);
+        assert_eq!(unpack((16 << 32) | 5), Some((16, 5)));
+    }
+}
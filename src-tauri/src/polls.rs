@@ -0,0 +1,757 @@
+use crate::catalog::now_secs;
+use crate::contacts::ContactBook;
+use crate::crypto::{Identity, SessionKey};
+use crate::devices::verifying_key;
+use crate::groups::Group;
+use crate::no_traces;
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, Verifier};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
+
+const POLLS_FILE: &str = "polls.enc";
+const MAX_QUESTION_CHARS: usize = 300;
+const MAX_OPTION_CHARS: usize = 100;
+const MAX_OPTIONS: usize = 12;
+
+/// When the votes of a poll become visible to its participants
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Reveal {
+    /// Results update as votes come in
+    Live,
+    /// Only how many have voted is known until the poll closes
+    OnClose,
+}
+
+/// A poll as its creator signed it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Poll {
+    pub id: String,
+    pub creator: String,
+    pub group_name: String,
+    pub question: String,
+    pub options: Vec<String>,
+    pub multiple_choice: bool,
+    /// Only the creator, who counts the votes, sees who voted for what
+    pub anonymous: bool,
+    pub reveal: Reveal,
+    /// The group's members and the creator
+    pub participants: Vec<String>,
+    pub created_at: u64,
+}
+
+impl Poll {
+    fn validate(&self) -> Result<()> {
+        if self.question.trim().is_empty() || self.question.chars().count() > MAX_QUESTION_CHARS {
+            anyhow::bail!("Question must be 1 to {} characters", MAX_QUESTION_CHARS);
+        }
+        if !(2..=MAX_OPTIONS).contains(&self.options.len()) {
+            anyhow::bail!("A poll needs 2 to {} options", MAX_OPTIONS);
+        }
+        if self
+            .options
+            .iter()
+            .any(|o| o.trim().is_empty() || o.chars().count() > MAX_OPTION_CHARS)
+        {
+            anyhow::bail!("Options must be 1 to {} characters", MAX_OPTION_CHARS);
+        }
+        if !self.participants.contains(&self.creator) {
+            anyhow::bail!("The creator must take part in the poll");
+        }
+        Ok(())
+    }
+
+    /// Check a voter's choices against the options
+    fn check_choices(&self, choices: &[usize]) -> Result<()> {
+        let unique: BTreeSet<_> = choices.iter().collect();
+        if choices.is_empty() || unique.len() != choices.len() {
+            anyhow::bail!("Pick each option at most once");
+        }
+        if !self.multiple_choice && choices.len() > 1 {
+            anyhow::bail!("This poll takes a single choice");
+        }
+        if choices.iter().any(|&c| c >= self.options.len()) {
+            anyhow::bail!("No such option");
+        }
+        Ok(())
+    }
+}
+
+/// A voter's choices, signed by the voter
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Vote {
+    pub poll_id: String,
+    pub voter: String,
+    /// Indexes into the poll's options
+    pub choices: Vec<usize>,
+    pub cast_at: u64,
+}
+
+/// A poll or vote with the signature of whoever made it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedPollStatement {
+    /// JSON of the statement, signed byte for byte
+    pub statement: String,
+    pub signature: String,   // hex
+    pub signing_key: String, // hex, the signer's ed25519 key
+}
+
+impl SignedPollStatement {
+    fn sign<T: Serialize>(identity: &Identity, statement: &T) -> Result<Self> {
+        let statement = serde_json::to_string(statement)?;
+        let signing_key = identity.signing_key();
+        let signature = signing_key.sign(statement.as_bytes());
+        Ok(Self {
+            statement,
+            signature: hex::encode(signature.to_bytes()),
+            signing_key: hex::encode(signing_key.verifying_key().to_bytes()),
+        })
+    }
+
+    /// Check the signature and return the statement. Whether the key belongs
+    /// to the claimed signer is up to the caller.
+    pub fn verify<T: DeserializeOwned>(&self) -> Result<T> {
+        let key = verifying_key(&self.signing_key)?;
+        let signature = hex::decode(&self.signature).context("Invalid poll signature")?;
+        let signature = Signature::from_slice(&signature).context("Invalid poll signature")?;
+        key.verify(self.statement.as_bytes(), &signature)
+            .context("Poll signature does not match")?;
+        serde_json::from_str(&self.statement).context("Invalid poll statement")
+    }
+}
+
+/// One counted vote as the creator passes it on
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Ballot {
+    /// The voter's public ID, or a random ID on anonymous polls
+    pub id: String,
+    pub choices: Vec<usize>,
+    /// The voter's signed `Vote`, on attributed polls
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vote: Option<SignedPollStatement>,
+}
+
+/// Poll traffic between participants, end-to-end encrypted like all P2P
+/// messages. Votes go to the creator, who sends everyone the tally, so
+/// each client counts the same ballots.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PollMessage {
+    /// A new poll, from its creator
+    Open { poll: SignedPollStatement },
+    /// A vote for the creator; a later vote replaces an earlier one
+    Vote { vote: SignedPollStatement },
+    /// The ballots counted so far, from the creator. Empty until the poll
+    /// closes if its results are revealed then.
+    Tally {
+        poll_id: String,
+        ballots: Vec<Ballot>,
+        voted: usize,
+        closed: bool,
+    },
+}
+
+/// Results of a poll as this client counts them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PollResults {
+    pub poll: Poll,
+    /// Votes per option, all zero while the results are hidden
+    pub counts: Vec<u32>,
+    /// Who voted for each option, on attributed polls
+    pub voters: Vec<Vec<String>>,
+    pub voted: usize,
+    pub revealed: bool,
+    pub my_choices: Option<Vec<usize>>,
+    pub closed_at: Option<u64>,
+}
+
+/// What a received poll message changed
+#[derive(Debug, Clone)]
+pub struct PollUpdate {
+    pub results: PollResults,
+    /// First we hear of the poll
+    pub opened: bool,
+    /// The tally the creator sends on after a vote
+    pub relay: Option<(Vec<String>, PollMessage)>,
+}
+
+/// A vote as the creator keeps it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CastVote {
+    choices: Vec<usize>,
+    signed: SignedPollStatement,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct PollRecord {
+    poll: Poll,
+    ballots: Vec<Ballot>,
+    voted: usize,
+    my_choices: Option<Vec<usize>>,
+    closed_at: Option<u64>,
+    /// Creator only: every vote by voter, hidden or not
+    #[serde(default)]
+    votes: BTreeMap<String, CastVote>,
+    /// Creator only: the random ballot ID of each voter on anonymous polls
+    #[serde(default)]
+    ballot_ids: BTreeMap<String, String>,
+}
+
+impl PollRecord {
+    fn revealed(&self) -> bool {
+        self.poll.reveal == Reveal::Live || self.closed_at.is_some()
+    }
+
+    fn results(&self) -> PollResults {
+        let mut counts = vec![0; self.poll.options.len()];
+        let mut voters = vec![Vec::new(); self.poll.options.len()];
+        for ballot in &self.ballots {
+            for &choice in &ballot.choices {
+                counts[choice] += 1;
+                if !self.poll.anonymous {
+                    voters[choice].push(ballot.id.clone());
+                }
+            }
+        }
+        PollResults {
+            poll: self.poll.clone(),
+            counts,
+            voters,
+            voted: self.voted,
+            revealed: self.revealed(),
+            my_choices: self.my_choices.clone(),
+            closed_at: self.closed_at,
+        }
+    }
+
+    /// Count the votes as the creator and return the tally to send
+    fn tally(&mut self) -> PollMessage {
+        self.voted = self.votes.len();
+        self.ballots = if self.revealed() {
+            let mut ballots: Vec<Ballot> = self
+                .votes
+                .iter()
+                .map(|(voter, cast)| Ballot {
+                    id: if self.poll.anonymous {
+                        self.ballot_ids
+                            .entry(voter.clone())
+                            .or_insert_with(|| uuid::Uuid::new_v4().to_string())
+                            .clone()
+                    } else {
+                        voter.clone()
+                    },
+                    choices: cast.choices.clone(),
+                    vote: (!self.poll.anonymous).then(|| cast.signed.clone()),
+                })
+                .collect();
+            // Random IDs in order say nothing about who voted when
+            ballots.sort_by(|a, b| a.id.cmp(&b.id));
+            ballots
+        } else {
+            Vec::new()
+        };
+        PollMessage::Tally {
+            poll_id: self.poll.id.clone(),
+            ballots: self.ballots.clone(),
+            voted: self.voted,
+            closed: self.closed_at.is_some(),
+        }
+    }
+
+    /// Everyone but the creator
+    fn others(&self) -> Vec<String> {
+        self.poll
+            .participants
+            .iter()
+            .filter(|p| **p != self.poll.creator)
+            .cloned()
+            .collect()
+    }
+
+    /// Take in the creator's tally, checking every ballot. `known_key` gives
+    /// the signing key (hex) each voter is known by.
+    fn accept_tally(
+        &mut self,
+        ballots: Vec<Ballot>,
+        voted: usize,
+        closed: bool,
+        known_key: impl Fn(&str) -> Option<String>,
+    ) -> Result<()> {
+        if voted < ballots.len() || voted > self.poll.participants.len() {
+            anyhow::bail!("Tally of poll {} doesn't add up", self.poll.id);
+        }
+        let mut seen = BTreeSet::new();
+        for ballot in &ballots {
+            if !seen.insert(&ballot.id) {
+                anyhow::bail!("Tally counts a ballot twice");
+            }
+            self.poll.check_choices(&ballot.choices)?;
+            match (&ballot.vote, self.poll.anonymous) {
+                (None, true) => {}
+                (Some(signed), false) => {
+                    let vote: Vote = signed.verify()?;
+                    if vote.voter != ballot.id
+                        || vote.poll_id != self.poll.id
+                        || vote.choices != ballot.choices
+                        || !self.poll.participants.contains(&vote.voter)
+                    {
+                        anyhow::bail!("Ballot doesn't match the voter's signed vote");
+                    }
+                    // The creator passes the votes on, so a key that isn't the
+                    // voter's could be the creator signing for them
+                    if known_key(&vote.voter).as_deref() != Some(signed.signing_key.as_str()) {
+                        anyhow::bail!(
+                            "Ballot of {} is not signed with their known key",
+                            vote.voter
+                        );
+                    }
+                }
+                _ => anyhow::bail!("Ballot doesn't match the poll's anonymity"),
+            }
+        }
+        // Once closed, a poll stays as it closed
+        if self.closed_at.is_none() {
+            self.ballots = ballots;
+            self.voted = voted;
+            if closed {
+                self.closed_at = Some(now_secs());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct PollState {
+    polls: BTreeMap<String, PollRecord>,
+}
+
+/// Polls we created or take part in, encrypted with the local identity
+struct PollStore {
+    path: PathBuf,
+    storage_key: SessionKey,
+    state: PollState,
+}
+
+impl PollStore {
+    fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(POLLS_FILE);
+        let storage_key = identity.storage_key(b"polls");
+
+        let state =
+            if let Some(encrypted) = no_traces::read_file(&path).context("Failed to read polls")? {
+                let mut json = storage_key
+                    .decrypt_file(&encrypted)
+                    .context("Failed to decrypt polls")?;
+                let state = serde_json::from_slice(&json);
+                json.zeroize();
+                state.context("Failed to parse polls")?
+            } else {
+                PollState::default()
+            };
+
+        Ok(Self {
+            path,
+            storage_key,
+            state,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut json = serde_json::to_vec(&self.state)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write polls")?;
+        Ok(())
+    }
+
+    fn get_mut(&mut self, poll_id: &str) -> Result<&mut PollRecord> {
+        self.state.polls.get_mut(poll_id).context("No such poll")
+    }
+}
+
+/// Start a poll among `group`'s members. Returns it with the message to
+/// send and who to send it to.
+#[allow(clippy::too_many_arguments)]
+pub fn create(
+    data_dir: &Path,
+    identity: &Identity,
+    group: &Group,
+    question: &str,
+    options: &[String],
+    multiple_choice: bool,
+    anonymous: bool,
+    reveal: Reveal,
+) -> Result<(PollResults, Vec<String>, PollMessage)> {
+    let creator = identity.public_id();
+    let mut participants = vec![creator.clone()];
+    participants.extend(group.members.iter().filter(|m| **m != creator).cloned());
+    let poll = Poll {
+        id: uuid::Uuid::new_v4().to_string(),
+        creator,
+        group_name: group.name.clone(),
+        question: question.trim().to_string(),
+        options: options.iter().map(|o| o.trim().to_string()).collect(),
+        multiple_choice,
+        anonymous,
+        reveal,
+        participants,
+        created_at: now_secs(),
+    };
+    poll.validate()?;
+    let signed = SignedPollStatement::sign(identity, &poll)?;
+
+    let record = PollRecord {
+        poll,
+        ballots: Vec::new(),
+        voted: 0,
+        my_choices: None,
+        closed_at: None,
+        votes: BTreeMap::new(),
+        ballot_ids: BTreeMap::new(),
+    };
+    let results = record.results();
+    let recipients = record.others();
+    let mut store = PollStore::load(data_dir, identity)?;
+    store.state.polls.insert(record.poll.id.clone(), record);
+    store.save()?;
+    Ok((results, recipients, PollMessage::Open { poll: signed }))
+}
+
+/// Vote, or change our vote, on an open poll. Returns the message to send
+/// and who to send it to: the creator, or everyone when we are the creator.
+pub fn vote(
+    data_dir: &Path,
+    identity: &Identity,
+    poll_id: &str,
+    choices: Vec<usize>,
+) -> Result<(PollResults, Vec<String>, PollMessage)> {
+    let mut store = PollStore::load(data_dir, identity)?;
+    let record = store.get_mut(poll_id)?;
+    if record.closed_at.is_some() {
+        anyhow::bail!("The poll is closed");
+    }
+    record.poll.check_choices(&choices)?;
+    let vote = Vote {
+        poll_id: poll_id.to_string(),
+        voter: identity.public_id(),
+        choices: choices.clone(),
+        cast_at: now_secs(),
+    };
+    let signed = SignedPollStatement::sign(identity, &vote)?;
+    record.my_choices = Some(choices.clone());
+
+    let sent = if vote.voter == record.poll.creator {
+        record
+            .votes
+            .insert(vote.voter, CastVote { choices, signed });
+        (record.others(), record.tally())
+    } else {
+        (
+            vec![record.poll.creator.clone()],
+            PollMessage::Vote { vote: signed },
+        )
+    };
+    let results = record.results();
+    store.save()?;
+    Ok((results, sent.0, sent.1))
+}
+
+/// Close one of our polls, revealing its results to everyone
+pub fn close(
+    data_dir: &Path,
+    identity: &Identity,
+    poll_id: &str,
+) -> Result<(PollResults, Vec<String>, PollMessage)> {
+    let mut store = PollStore::load(data_dir, identity)?;
+    let record = store.get_mut(poll_id)?;
+    if record.poll.creator != identity.public_id() {
+        anyhow::bail!("Only the creator can close a poll");
+    }
+    if record.closed_at.is_some() {
+        anyhow::bail!("The poll is already closed");
+    }
+    record.closed_at = Some(now_secs());
+    let tally = record.tally();
+    let (results, recipients) = (record.results(), record.others());
+    store.save()?;
+    Ok((results, recipients, tally))
+}
+
+/// Take in poll traffic from `sender`. Returns `None` for messages that
+/// change nothing.
+pub fn receive(
+    data_dir: &Path,
+    identity: &Identity,
+    sender: &str,
+    message: PollMessage,
+) -> Result<Option<PollUpdate>> {
+    let mut store = PollStore::load(data_dir, identity)?;
+    let update = match message {
+        PollMessage::Open { poll: signed } => {
+            let poll: Poll = signed.verify()?;
+            if poll.creator != sender {
+                anyhow::bail!("Poll from {} claims to be by {}", sender, poll.creator);
+            }
+            poll.validate()?;
+            if !poll.participants.contains(&identity.public_id()) {
+                anyhow::bail!("Poll {} doesn't include us", poll.id);
+            }
+            if store.state.polls.contains_key(&poll.id) {
+                return Ok(None);
+            }
+            let record = PollRecord {
+                poll,
+                ballots: Vec::new(),
+                voted: 0,
+                my_choices: None,
+                closed_at: None,
+                votes: BTreeMap::new(),
+                ballot_ids: BTreeMap::new(),
+            };
+            let results = record.results();
+            store.state.polls.insert(record.poll.id.clone(), record);
+            PollUpdate {
+                results,
+                opened: true,
+                relay: None,
+            }
+        }
+        PollMessage::Vote { vote: signed } => {
+            let vote: Vote = signed.verify()?;
+            if vote.voter != sender {
+                anyhow::bail!("Vote from {} claims to be by {}", sender, vote.voter);
+            }
+            let Some(record) = store.state.polls.get_mut(&vote.poll_id) else {
+                return Ok(None);
+            };
+            if record.poll.creator != identity.public_id()
+                || !record.poll.participants.contains(&vote.voter)
+            {
+                anyhow::bail!("Vote from {} for a poll we don't count", sender);
+            }
+            // Votes that arrive after the close aren't counted
+            if record.closed_at.is_some() {
+                return Ok(None);
+            }
+            record.poll.check_choices(&vote.choices)?;
+            let cast = CastVote {
+                choices: vote.choices,
+                signed,
+            };
+            record.votes.insert(vote.voter, cast);
+            let tally = record.tally();
+            PollUpdate {
+                results: record.results(),
+                opened: false,
+                relay: Some((record.others(), tally)),
+            }
+        }
+        PollMessage::Tally {
+            poll_id,
+            ballots,
+            voted,
+            closed,
+        } => {
+            let Some(record) = store.state.polls.get_mut(&poll_id) else {
+                return Ok(None);
+            };
+            if record.poll.creator != sender {
+                anyhow::bail!(
+                    "Tally of poll {} from someone other than its creator",
+                    poll_id
+                );
+            }
+            let book = ContactBook::load(data_dir, identity)?;
+            let own_key = hex::encode(identity.signing_key().verifying_key().to_bytes());
+            record.accept_tally(ballots, voted, closed, |voter| {
+                if voter == identity.public_id() {
+                    Some(own_key.clone())
+                } else {
+                    book.get(voter).and_then(|c| c.signing_key.clone())
+                }
+            })?;
+            PollUpdate {
+                results: record.results(),
+                opened: false,
+                relay: None,
+            }
+        }
+    };
+    store.save()?;
+    Ok(Some(update))
+}
+
+/// Every poll we created or take part in, newest first
+pub fn list(data_dir: &Path, identity: &Identity) -> Result<Vec<PollResults>> {
+    let store = PollStore::load(data_dir, identity)?;
+    let mut polls: Vec<_> = store
+        .state
+        .polls
+        .values()
+        .map(PollRecord::results)
+        .collect();
+    polls.sort_by(|a, b| b.poll.created_at.cmp(&a.poll.created_at));
+    Ok(polls)
+}
+
+pub fn get(data_dir: &Path, identity: &Identity, poll_id: &str) -> Result<PollResults> {
+    let store = PollStore::load(data_dir, identity)?;
+    store
+        .state
+        .polls
+        .get(poll_id)
+        .map(PollRecord::results)
+        .context("No such poll")
+}
+
+/// Remove a poll from this device only
+pub fn forget(data_dir: &Path, identity: &Identity, poll_id: &str) -> Result<bool> {
+    let mut store = PollStore::load(data_dir, identity)?;
+    let removed = store.state.polls.remove(poll_id).is_some();
+    if removed {
+        store.save()?;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anonymous_votes_are_counted_without_voters() {
+        let (creator, voter) = (Identity::generate(), Identity::generate());
+        let (creator_dir, voter_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let group = Group {
+            id: "g".to_string(),
+            name: "Team".to_string(),
+            members: vec![voter.public_id()],
+            updated_at: 0,
+        };
+        let options = vec!["Yes".to_string(), "No".to_string()];
+        let (_, recipients, open) = create(
+            creator_dir.path(),
+            &creator,
+            &group,
+            "Lunch?",
+            &options,
+            false,
+            true,
+            Reveal::OnClose,
+        )
+        .unwrap();
+        assert_eq!(recipients, vec![voter.public_id()]);
+        let poll_id = receive(voter_dir.path(), &voter, &creator.public_id(), open)
+            .unwrap()
+            .unwrap()
+            .results
+            .poll
+            .id;
+
+        assert!(vote(voter_dir.path(), &voter, &poll_id, vec![0, 1]).is_err());
+        let (_, to, ballot) = vote(voter_dir.path(), &voter, &poll_id, vec![1]).unwrap();
+        assert_eq!(to, vec![creator.public_id()]);
+        let update = receive(creator_dir.path(), &creator, &voter.public_id(), ballot)
+            .unwrap()
+            .unwrap();
+        // Hidden until the close
+        assert_eq!(update.results.voted, 1);
+        assert_eq!(update.results.counts, vec![0, 0]);
+
+        let (_, _, tally) = close(creator_dir.path(), &creator, &poll_id).unwrap();
+        let results = receive(voter_dir.path(), &voter, &creator.public_id(), tally)
+            .unwrap()
+            .unwrap()
+            .results;
+        assert_eq!(results.counts, vec![0, 1]);
+        assert!(results.voters.iter().all(Vec::is_empty));
+        assert!(results.closed_at.is_some());
+    }
+
+    #[test]
+    fn tally_with_a_ballot_signed_by_the_creator_is_refused() {
+        let (creator, voter, other) = (
+            Identity::generate(),
+            Identity::generate(),
+            Identity::generate(),
+        );
+        let (creator_dir, voter_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let group = Group {
+            id: "g".to_string(),
+            name: "Team".to_string(),
+            members: vec![voter.public_id(), other.public_id()],
+            updated_at: 0,
+        };
+        let options = vec!["Yes".to_string(), "No".to_string()];
+        let (_, _, open) = create(
+            creator_dir.path(),
+            &creator,
+            &group,
+            "Lunch?",
+            &options,
+            false,
+            false,
+            Reveal::Live,
+        )
+        .unwrap();
+        let poll_id = receive(voter_dir.path(), &voter, &creator.public_id(), open)
+            .unwrap()
+            .unwrap()
+            .results
+            .poll
+            .id;
+
+        // The voter's own vote comes back in the tally and is accepted
+        let (_, _, ballot) = vote(voter_dir.path(), &voter, &poll_id, vec![0]).unwrap();
+        let (_, tally) = receive(creator_dir.path(), &creator, &voter.public_id(), ballot)
+            .unwrap()
+            .unwrap()
+            .relay
+            .unwrap();
+        let PollMessage::Tally { mut ballots, .. } = tally.clone() else {
+            panic!("expected a tally");
+        };
+        receive(voter_dir.path(), &voter, &creator.public_id(), tally).unwrap();
+
+        // The creator adds a vote for the other member, signed with their own key
+        let forged = Vote {
+            poll_id: poll_id.clone(),
+            voter: other.public_id(),
+            choices: vec![1],
+            cast_at: now_secs(),
+        };
+        ballots.push(Ballot {
+            id: other.public_id(),
+            choices: vec![1],
+            vote: Some(SignedPollStatement::sign(&creator, &forged).unwrap()),
+        });
+        let forged_tally = PollMessage::Tally {
+            poll_id: poll_id.clone(),
+            ballots,
+            voted: 2,
+            closed: false,
+        };
+        // Refused whether the other member's key is unknown or known
+        assert!(receive(
+            voter_dir.path(),
+            &voter,
+            &creator.public_id(),
+            forged_tally.clone()
+        )
+        .is_err());
+        let mut book = ContactBook::load(voter_dir.path(), &voter).unwrap();
+        let mut contact = book.upsert(&other.public_id(), "Other", true).unwrap();
+        contact.signing_key = Some(hex::encode(other.signing_key().verifying_key().to_bytes()));
+        book.insert(contact);
+        book.save().unwrap();
+        assert!(receive(voter_dir.path(), &voter, &creator.public_id(), forged_tally).is_err());
+
+        let results = get(voter_dir.path(), &voter, &poll_id).unwrap();
+        assert_eq!(results.counts, vec![1, 0]);
+    }
+}
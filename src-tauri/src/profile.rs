@@ -0,0 +1,256 @@
+use crate::catalog::{now_secs, DropCatalog, DropRecord};
+use crate::contacts::{Contact, ContactBook};
+use crate::conversations::{Conversation, ConversationStore};
+use crate::crypto::{password_key, Identity};
+use crate::key_log::{self, KeyKind, KeySource};
+use crate::settings::{self, AppSettings};
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use tracing::info;
+use zeroize::Zeroize;
+
+const PROFILE_FORMAT: &str = "control-profile";
+const PROFILE_VERSION: u32 = 1;
+const IDENTITY_FILE: &str = "identity.enc";
+
+/// On-disk bundle: everything but the header is encrypted with a key derived
+/// from the export password
+#[derive(Serialize, Deserialize)]
+struct ProfileFile {
+    format: String,
+    version: u32,
+    salt: String,
+    ciphertext: String, // base64
+}
+
+/// Decrypted bundle contents
+#[derive(Serialize, Deserialize)]
+struct ProfileBundle {
+    public_id: String,
+    exported_at: u64,
+    identity: String, // hex private key
+    #[serde(default)]
+    contacts: Vec<Contact>,
+    #[serde(default)]
+    settings: Option<AppSettings>,
+    #[serde(default)]
+    conversations: Vec<Conversation>,
+    #[serde(default)]
+    drops: Vec<DropRecord>,
+}
+
+impl Drop for ProfileBundle {
+    fn drop(&mut self) {
+        self.identity.zeroize();
+    }
+}
+
+/// Which parts of a bundle to import
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProfileImportOptions {
+    pub contacts: bool,
+    pub settings: bool,
+    pub conversations: bool,
+    pub drops: bool,
+}
+
+impl Default for ProfileImportOptions {
+    fn default() -> Self {
+        Self {
+            contacts: true,
+            settings: true,
+            conversations: true,
+            drops: true,
+        }
+    }
+}
+
+/// What an import changed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProfileImportReport {
+    pub public_id: String,
+    pub format_version: u32,
+    /// The identity was written to this device (it had none before)
+    pub identity_imported: bool,
+    pub contacts: usize,
+    pub conversations: usize,
+    pub drops: usize,
+    pub settings: bool,
+}
+
+/// Write the identity, contacts, settings, conversations and drop catalog to
+/// `path` as one bundle encrypted with `password`
+pub fn export_profile(
+    data_dir: &Path,
+    identity: &Identity,
+    path: &Path,
+    password: &str,
+) -> Result<()> {
+    if password.is_empty() {
+        anyhow::bail!("Export password must not be empty");
+    }
+
+    let mut secret = identity.secret_bytes();
+    let bundle = ProfileBundle {
+        public_id: identity.public_id(),
+        exported_at: now_secs(),
+        identity: hex::encode(secret),
+        contacts: ContactBook::load(data_dir, identity)?.contacts().to_vec(),
+        settings: Some(settings::saved()),
+        conversations: ConversationStore::load(data_dir, identity)?
+            .conversations()
+            .to_vec(),
+        drops: DropCatalog::load(data_dir)?.records().to_vec(),
+    };
+    secret.zeroize();
+
+    let (key, salt) = password_key(password, None)?;
+    let mut json = serde_json::to_vec(&bundle)?;
+    let ciphertext = key.encrypt_file(&json);
+    json.zeroize();
+
+    let file = ProfileFile {
+        format: PROFILE_FORMAT.to_string(),
+        version: PROFILE_VERSION,
+        salt,
+        ciphertext: STANDARD.encode(ciphertext?),
+    };
+    fs::write(path, serde_json::to_vec_pretty(&file)?).context("Failed to write profile")?;
+
+    info!(
+        "Exported profile ({} contacts, {} conversations, {} drops)",
+        bundle.contacts.len(),
+        bundle.conversations.len(),
+        bundle.drops.len()
+    );
+    Ok(())
+}
+
+/// Import a bundle written by `export_profile`. On a device without an
+/// identity the bundle's identity is stored, encrypted with `password`;
+/// otherwise the bundle must belong to the unlocked identity.
+pub fn import_profile(
+    data_dir: &Path,
+    unlocked: Option<&Identity>,
+    path: &Path,
+    password: &str,
+    options: &ProfileImportOptions,
+) -> Result<ProfileImportReport> {
+    let json = fs::read(path).context("Failed to read profile")?;
+    let file: ProfileFile = serde_json::from_slice(&json).context("Not a Control profile")?;
+    if file.format != PROFILE_FORMAT {
+        anyhow::bail!("Not a Control profile");
+    }
+    if file.version > PROFILE_VERSION {
+        anyhow::bail!(
+            "Profile format {} is newer than this version of Control supports",
+            file.version
+        );
+    }
+
+    let (key, _) = password_key(password, Some(&file.salt))?;
+    let ciphertext = STANDARD
+        .decode(&file.ciphertext)
+        .context("Corrupt profile")?;
+    let mut plaintext = key
+        .decrypt_file(&ciphertext)
+        .context("Failed to decrypt profile - wrong password?")?;
+    let bundle = serde_json::from_slice::<ProfileBundle>(&plaintext);
+    plaintext.zeroize();
+    let bundle = bundle.context("Invalid profile contents")?;
+
+    let mut secret_bytes = hex::decode(&bundle.identity).context("Corrupt identity in profile")?;
+    if secret_bytes.len() != 32 {
+        secret_bytes.zeroize();
+        anyhow::bail!("Corrupt identity in profile");
+    }
+    let mut secret = [0u8; 32];
+    secret.copy_from_slice(&secret_bytes);
+    secret_bytes.zeroize();
+    let identity = Identity::from_secret_bytes(secret);
+    secret.zeroize();
+
+    let identity_imported = match unlocked {
+        Some(current) if current.public_id() != identity.public_id() => {
+            anyhow::bail!("Profile belongs to a different identity than the one on this device")
+        }
+        Some(_) => false,
+        None if data_dir.join(IDENTITY_FILE).exists() => {
+            anyhow::bail!("Unlock before importing a profile into an existing identity")
+        }
+        None => {
+            identity.save(password, data_dir)?;
+            true
+        }
+    };
+
+    let mut report = ProfileImportReport {
+        public_id: identity.public_id(),
+        format_version: file.version,
+        identity_imported,
+        contacts: 0,
+        conversations: 0,
+        drops: 0,
+        settings: false,
+    };
+
+    if options.contacts && !bundle.contacts.is_empty() {
+        let mut book = ContactBook::load(data_dir, &identity)?;
+        for contact in &bundle.contacts {
+            let newer = book
+                .get(&contact.public_id)
+                .map_or(true, |existing| contact.updated_at > existing.updated_at);
+            if newer {
+                book.insert(contact.clone());
+                report.contacts += 1;
+            }
+            if let Some(key) = &contact.signing_key {
+                key_log::observe(
+                    data_dir,
+                    &identity,
+                    &contact.public_id,
+                    KeyKind::Signing,
+                    key,
+                    KeySource::ProfileImport,
+                );
+            }
+        }
+        book.save()?;
+    }
+
+    if options.conversations && !bundle.conversations.is_empty() {
+        let mut store = ConversationStore::load(data_dir, &identity)?;
+        for conversation in &bundle.conversations {
+            store.insert(conversation.clone());
+        }
+        store.save()?;
+        report.conversations = bundle.conversations.len();
+    }
+
+    if options.drops && !bundle.drops.is_empty() {
+        let mut catalog = DropCatalog::load(data_dir)?;
+        for record in &bundle.drops {
+            catalog.insert(record.clone());
+        }
+        catalog.save()?;
+        report.drops = bundle.drops.len();
+    }
+
+    if let Some(imported) = bundle.settings.as_ref().filter(|_| options.settings) {
+        // Paths only make sense on the machine they were chosen on
+        let mut imported = imported.clone();
+        imported.drop.staging_dir = settings::saved().drop.staging_dir;
+        settings::update(imported).context("Failed to apply imported settings")?;
+        report.settings = true;
+    }
+
+    info!(
+        "Imported profile (format {}, identity: {}, {} contacts, {} drops)",
+        report.format_version, report.identity_imported, report.contacts, report.drops
+    );
+    Ok(report)
+}
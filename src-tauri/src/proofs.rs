@@ -0,0 +1,361 @@
+use crate::catalog::now_secs;
+use crate::crypto::{Identity, SessionKey};
+use crate::ipfs;
+use crate::no_traces;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+use zeroize::Zeroize;
+
+const PROOFS_FILE: &str = "proofs.enc";
+const PROOF_TIMEOUT: Duration = Duration::from_secs(20);
+/// Proof documents are a line of text; don't download more than this
+const MAX_PROOF_BYTES: usize = 256 * 1024;
+/// DNS-over-HTTPS, so lookups follow the Tor proxy like other requests
+const DOH_RESOLVER: &str = "https://cloudflare-dns.com/dns-query";
+const WELL_KNOWN_PATH: &str = ".well-known/control-proof.txt";
+
+/// Somewhere outside Control the owner of a public ID says the ID is theirs.
+/// Claims travel in the signed contact card, so a claim can't be attached to
+/// someone else's key; the proof at the location shows the card's owner
+/// controls it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Claim {
+    /// A TXT record on the domain
+    Dns { domain: String },
+    /// `https://<domain>/.well-known/control-proof.txt`
+    Https { domain: String },
+    /// A commit whose message or diff contains the proof, on a forge that
+    /// serves `<repo>/commit/<sha>.patch` (GitHub, GitLab, Gitea, Forgejo)
+    Git { repo: String, commit: String },
+}
+
+impl Claim {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Claim::Dns { domain } | Claim::Https { domain } => check_domain(domain),
+            Claim::Git { repo, commit } => {
+                let url = reqwest::Url::parse(repo).context("Invalid repository URL")?;
+                if url.scheme() != "https" || url.host_str().is_none() {
+                    anyhow::bail!("Repository must be an https:// URL");
+                }
+                if !(7..=64).contains(&commit.len())
+                    || !commit.chars().all(|c| c.is_ascii_hexdigit())
+                {
+                    anyhow::bail!("Commit must be a hex commit hash");
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Where the proof is read from
+    pub fn location(&self) -> String {
+        match self {
+            Claim::Dns { domain } => format!("dns:{}?type=TXT", domain),
+            Claim::Https { domain } => format!("https://{}/{}", domain, WELL_KNOWN_PATH),
+            Claim::Git { repo, commit } => {
+                format!("{}/commit/{}.patch", repo.trim_end_matches('/'), commit)
+            }
+        }
+    }
+}
+
+fn check_domain(domain: &str) -> Result<()> {
+    let valid = !domain.is_empty()
+        && domain.len() <= 253
+        && domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        });
+    if !valid {
+        anyhow::bail!("Invalid domain name: {}", domain);
+    }
+    Ok(())
+}
+
+/// The text to publish at a claim's location
+pub fn proof_token(public_id: &str) -> String {
+    format!("control-proof={}", public_id)
+}
+
+/// One of our claims with what to publish for it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OwnProof {
+    pub claim: Claim,
+    pub token: String,
+    pub location: String,
+}
+
+/// Result of looking for a claim's proof
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProofCheck {
+    pub claim: Claim,
+    pub verified: bool,
+    /// `None` until checked
+    pub checked_at: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl ProofCheck {
+    pub fn unchecked(claim: Claim) -> Self {
+        Self {
+            claim,
+            verified: false,
+            checked_at: None,
+            error: None,
+        }
+    }
+}
+
+/// Look for the proof of `public_id` at the claim's location
+pub async fn check(claim: &Claim, public_id: &str) -> ProofCheck {
+    let result = async {
+        claim.validate()?;
+        let client = ipfs::client()?;
+        let found = match claim {
+            Claim::Dns { domain } => dns_txt(&client, domain)
+                .await?
+                .iter()
+                .any(|record| record.trim() == proof_token(public_id)),
+            Claim::Https { .. } | Claim::Git { .. } => fetch_text(&client, &claim.location())
+                .await?
+                .contains(&proof_token(public_id)),
+        };
+        anyhow::Ok(found)
+    }
+    .await;
+
+    let (verified, error) = match result {
+        Ok(true) => (true, None),
+        Ok(false) => (false, Some("Proof not found".to_string())),
+        Err(e) => (false, Some(e.to_string())),
+    };
+    ProofCheck {
+        claim: claim.clone(),
+        verified,
+        checked_at: Some(now_secs()),
+        error,
+    }
+}
+
+/// Check every claim of `public_id`, in order
+pub async fn check_all(claims: &[Claim], public_id: &str) -> Vec<ProofCheck> {
+    let mut checks = Vec::with_capacity(claims.len());
+    for claim in claims {
+        checks.push(check(claim, public_id).await);
+    }
+    info!(
+        "{} of {} proofs of {} verified",
+        checks.iter().filter(|c| c.verified).count(),
+        checks.len(),
+        public_id
+    );
+    checks
+}
+
+async fn fetch_text(client: &reqwest::Client, url: &str) -> Result<String> {
+    let mut response = client
+        .get(url)
+        .timeout(PROOF_TIMEOUT)
+        .send()
+        .await
+        .context("Proof location unreachable")?;
+    if !response.status().is_success() {
+        anyhow::bail!("Proof location answered {}", response.status());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_PROOF_BYTES {
+            body.truncate(MAX_PROOF_BYTES);
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+/// TXT records of a domain, each with its character strings joined
+async fn dns_txt(client: &reqwest::Client, domain: &str) -> Result<Vec<String>> {
+    const TXT: u16 = 16;
+    let response: DohResponse = client
+        .get(DOH_RESOLVER)
+        .query(&[("name", domain), ("type", "TXT")])
+        .header(reqwest::header::ACCEPT, "application/dns-json")
+        .timeout(PROOF_TIMEOUT)
+        .send()
+        .await
+        .context("DNS resolver unreachable")?
+        .error_for_status()?
+        .json()
+        .await
+        .context("Invalid DNS response")?;
+    Ok(response
+        .answer
+        .into_iter()
+        .filter(|a| a.record_type == TXT)
+        .map(|a| join_txt(&a.data))
+        .collect())
+}
+
+/// `"abc" "def"` -> `abcdef`
+fn join_txt(data: &str) -> String {
+    let data = data.trim();
+    if !data.starts_with('"') {
+        return data.to_string();
+    }
+    data.split('"')
+        .skip(1)
+        .step_by(2)
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+/// Our own claims, encrypted with the local identity
+pub struct ProofStore {
+    path: PathBuf,
+    storage_key: SessionKey,
+    claims: Vec<Claim>,
+}
+
+impl ProofStore {
+    /// Load the store, starting empty if it does not exist yet
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(PROOFS_FILE);
+        let storage_key = identity.storage_key(b"proofs");
+
+        let claims = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read proofs")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt proofs")?;
+            let claims = serde_json::from_slice(&json);
+            json.zeroize();
+            claims.context("Failed to parse proofs")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            claims,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut json = serde_json::to_vec(&self.claims)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write proofs")?;
+        Ok(())
+    }
+
+    pub fn claims(&self) -> &[Claim] {
+        &self.claims
+    }
+
+    /// Add a claim and return what to publish for it
+    pub fn add(&mut self, claim: Claim, public_id: &str) -> Result<OwnProof> {
+        claim.validate()?;
+        if !self.claims.contains(&claim) {
+            self.claims.push(claim.clone());
+        }
+        Ok(own_proof(claim, public_id))
+    }
+
+    pub fn remove(&mut self, claim: &Claim) -> bool {
+        let before = self.claims.len();
+        self.claims.retain(|c| c != claim);
+        self.claims.len() != before
+    }
+
+    pub fn own_proofs(&self, public_id: &str) -> Vec<OwnProof> {
+        self.claims
+            .iter()
+            .cloned()
+            .map(|claim| own_proof(claim, public_id))
+            .collect()
+    }
+}
+
+fn own_proof(claim: Claim, public_id: &str) -> OwnProof {
+    OwnProof {
+        location: claim.location(),
+        token: proof_token(public_id),
+        claim,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_claims() {
+        let dns = |domain: &str| Claim::Dns {
+            domain: domain.to_string(),
+        };
+        assert!(dns("example.org").validate().is_ok());
+        assert!(dns("example").validate().is_err());
+        assert!(dns("-bad.example.org").validate().is_err());
+        assert!(dns("exa mple.org").validate().is_err());
+
+        let git = |repo: &str, commit: &str| Claim::Git {
+            repo: repo.to_string(),
+            commit: commit.to_string(),
+        };
+        assert!(git("https://codeberg.org/alice/keys", "0a1b2c3d")
+            .validate()
+            .is_ok());
+        assert!(git("http://codeberg.org/alice/keys", "0a1b2c3d")
+            .validate()
+            .is_err());
+        assert!(git("https://codeberg.org/alice/keys", "main")
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn git_location_is_the_patch() {
+        let claim = Claim::Git {
+            repo: "https://github.com/alice/keys/".to_string(),
+            commit: "0a1b2c3d".to_string(),
+        };
+        assert_eq!(
+            claim.location(),
+            "https://github.com/alice/keys/commit/0a1b2c3d.patch"
+        );
+    }
+
+    #[test]
+    fn joins_txt_strings() {
+        assert_eq!(join_txt(r#""control-proof=" "abc""#), "control-proof=abc");
+        assert_eq!(join_txt("control-proof=abc"), "control-proof=abc");
+    }
+}
@@ -0,0 +1,193 @@
+use crate::crypto::{decode_public_key, seal_to, Identity, SessionKey};
+use crate::dead_drop::{
+    cat_bytes_from_ipfs, download_and_decrypt, encrypt_and_upload, is_upload_pending,
+    stat_dead_drop, upload_bytes_to_ipfs, DeadDropCreated, DropOptions, DropStat,
+};
+use crate::wire;
+use anyhow::{Context, Result};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::info;
+use x25519_dalek::PublicKey;
+use zeroize::Zeroize;
+
+/// Drop references pointing at a recipient manifest carry this prefix
+pub const RECIPIENTS_CID_PREFIX: &str = "rk:";
+const MANIFEST_VERSION: u32 = 1;
+const MAX_RECIPIENTS: usize = 255;
+
+/// The session key sealed to one recipient. Stanzas do not name their
+/// recipient; each recipient tries them until one opens.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyStanza {
+    pub ephemeral_key: String, // base58
+    pub wrapped_key: String,   // hex
+}
+
+/// Public manifest of a multi-recipient drop: the ciphertext and one stanza
+/// per recipient, in random order
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecipientManifest {
+    pub version: u32,
+    pub cid: String,
+    pub stanzas: Vec<KeyStanza>,
+}
+
+/// Strip the recipients prefix from a drop reference, if present
+pub fn recipients_manifest_cid(cid: &str) -> Option<&str> {
+    cid.strip_prefix(RECIPIENTS_CID_PREFIX)
+}
+
+/// Create a drop any one of `recipients` (base58 public IDs) can open on
+/// their own. There are no shards: the session key is sealed to each
+/// recipient and the sealed copies are published with the ciphertext.
+pub async fn create_recipients_drop(
+    file_path: &str,
+    recipients: &[String],
+    options: &DropOptions,
+) -> Result<DeadDropCreated> {
+    let mut keys: Vec<PublicKey> = Vec::with_capacity(recipients.len());
+    for recipient in recipients {
+        let key = decode_public_key(recipient)
+            .with_context(|| format!("Invalid recipient key {}", recipient))?;
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    if keys.is_empty() || keys.len() > MAX_RECIPIENTS {
+        anyhow::bail!("A drop needs 1 to {} recipients", MAX_RECIPIENTS);
+    }
+
+    let session_key = SessionKey::generate();
+    let cid = encrypt_and_upload(file_path, &session_key, options).await?;
+
+    let mut key_bytes = session_key.as_bytes();
+    let stanzas = keys
+        .iter()
+        .map(|key| {
+            let (ephemeral, wrapped) = seal_to(key, &key_bytes)?;
+            Ok(KeyStanza {
+                ephemeral_key: bs58::encode(ephemeral.as_bytes()).into_string(),
+                wrapped_key: hex::encode(wrapped),
+            })
+        })
+        .collect::<Result<Vec<_>>>();
+    key_bytes.zeroize();
+    drop(session_key);
+    let mut stanzas = stanzas?;
+    stanzas.shuffle(&mut rand::thread_rng());
+
+    let manifest = RecipientManifest {
+        version: MANIFEST_VERSION,
+        cid: cid.clone(),
+        stanzas,
+    };
+    let manifest_cid = upload_bytes_to_ipfs(wire::encode(&manifest)?).await?;
+    info!(
+        "Created drop {} for {} recipients",
+        manifest_cid,
+        keys.len()
+    );
+
+    Ok(DeadDropCreated {
+        pending: is_upload_pending(options, &cid),
+        cid: format!("{}{}", RECIPIENTS_CID_PREFIX, manifest_cid),
+        shards: Vec::new(),
+        drop_id: None,
+        piece_cids: vec![cid],
+        shred: None,
+        cipher: Some(options.cipher),
+    })
+}
+
+/// Fetch and check the manifest of a multi-recipient drop
+pub async fn fetch_recipient_manifest(manifest_cid: &str) -> Result<RecipientManifest> {
+    let bytes = cat_bytes_from_ipfs(manifest_cid).await?;
+    let manifest: RecipientManifest =
+        wire::decode(&bytes).context("Invalid recipient manifest")?;
+    if manifest.version > MANIFEST_VERSION {
+        anyhow::bail!(
+            "Recipient manifest version {} is not supported",
+            manifest.version
+        );
+    }
+    Ok(manifest)
+}
+
+/// Find the stanza sealed to us and recover the session key from it
+pub fn unwrap_session_key(manifest: &RecipientManifest, identity: &Identity) -> Result<SessionKey> {
+    for stanza in &manifest.stanzas {
+        let (Ok(ephemeral), Ok(wrapped)) = (
+            decode_public_key(&stanza.ephemeral_key),
+            hex::decode(&stanza.wrapped_key),
+        ) else {
+            continue;
+        };
+        // Stanzas for other recipients fail authentication
+        if let Ok(mut key_bytes) = identity.open_sealed(&ephemeral, &wrapped) {
+            let key = SessionKey::from_bytes(&key_bytes);
+            key_bytes.zeroize();
+            return key;
+        }
+    }
+    anyhow::bail!("This drop was not encrypted to this identity")
+}
+
+/// Retrieve a multi-recipient drop with our identity instead of shards
+pub async fn retrieve_recipients_drop(
+    manifest_cid: &str,
+    identity: &Identity,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<()> {
+    let manifest = fetch_recipient_manifest(manifest_cid).await?;
+    let session_key = unwrap_session_key(&manifest, identity)?;
+    download_and_decrypt(&manifest.cid, &session_key, output_path, options).await
+}
+
+/// Preflight estimate; the manifest is public, so no identity is needed
+pub async fn stat_recipients_drop(
+    manifest_cid: &str,
+    output_path: &Path,
+    options: &DropOptions,
+) -> Result<DropStat> {
+    let manifest = fetch_recipient_manifest(manifest_cid).await?;
+    stat_dead_drop(&manifest.cid, None, output_path, options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_recipients_unwrap_the_key() {
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let eve = Identity::generate();
+        let session_key = SessionKey::generate();
+
+        let stanzas = [&alice, &bob]
+            .iter()
+            .map(|identity| {
+                let recipient = decode_public_key(&identity.public_id()).unwrap();
+                let (ephemeral, wrapped) = seal_to(&recipient, &session_key.as_bytes()).unwrap();
+                KeyStanza {
+                    ephemeral_key: bs58::encode(ephemeral.as_bytes()).into_string(),
+                    wrapped_key: hex::encode(wrapped),
+                }
+            })
+            .collect();
+        let manifest = RecipientManifest {
+            version: MANIFEST_VERSION,
+            cid: "bafkreiabc".to_string(),
+            stanzas,
+        };
+
+        for identity in [&alice, &bob] {
+            let key = unwrap_session_key(&manifest, identity).unwrap();
+            assert_eq!(key.as_bytes(), session_key.as_bytes());
+        }
+        assert!(unwrap_session_key(&manifest, &eve).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use crate::catalog::now_secs;
+use crate::crypto::{Identity, SessionKey};
+use crate::events;
+use crate::no_traces;
+use crate::session::AppState;
+use crate::settings;
+use crate::shards::{recover_session_key, split_session_key};
+use anyhow::{Context, Result};
+use bip39::Mnemonic;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::Manager;
+use tracing::info;
+use zeroize::Zeroize;
+
+const STATUS_FILE: &str = "recovery.json";
+const MIN_REMINDER_INTERVAL_SECS: u64 = 3600;
+const REMINDER_CHECK_SECS: u64 = 300;
+
+/// How often the app nags about a missing identity backup
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecoverySettings {
+    pub reminders: bool,
+    pub reminder_interval_secs: u64,
+}
+
+impl Default for RecoverySettings {
+    fn default() -> Self {
+        Self {
+            reminders: true,
+            reminder_interval_secs: 86400,
+        }
+    }
+}
+
+impl RecoverySettings {
+    fn reminder_interval(&self) -> Duration {
+        Duration::from_secs(self.reminder_interval_secs.max(MIN_REMINDER_INTERVAL_SECS))
+    }
+}
+
+/// What backups exist for the current identity. Only timestamps and counts
+/// are stored, never the backup itself.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RecoveryStatus {
+    pub public_id: String,
+    pub mnemonic_exported_at: Option<u64>,
+    pub shards_exported_at: Option<u64>,
+    pub shard_threshold: Option<u8>,
+    pub shard_total: Option<u8>,
+    /// Last time a backup was checked with `verify_backup`
+    pub verified_at: Option<u64>,
+    #[serde(skip_deserializing)]
+    pub ready: bool,
+}
+
+impl RecoveryStatus {
+    /// Status for `identity`; a status recorded for another identity doesn't count
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let public_id = identity.public_id();
+        let path = status_path(data_dir);
+
+        let mut status = if let Some(json) =
+            no_traces::read_file(&path).context("Failed to read recovery status")?
+        {
+            serde_json::from_slice::<Self>(&json).context("Failed to parse recovery status")?
+        } else {
+            Self::default()
+        };
+        if status.public_id != public_id {
+            status = Self {
+                public_id,
+                ..Self::default()
+            };
+        }
+
+        status.ready = status.has_backup();
+        Ok(status)
+    }
+
+    fn save(&mut self, data_dir: &Path) -> Result<()> {
+        self.ready = self.has_backup();
+        let json = serde_json::to_string_pretty(self)?;
+        no_traces::write_file(&status_path(data_dir), json)
+            .context("Failed to write recovery status")
+    }
+
+    /// A backup was exported and later shown to restore this identity
+    fn has_backup(&self) -> bool {
+        let exported = self.mnemonic_exported_at.or(self.shards_exported_at);
+        matches!((exported, self.verified_at), (Some(exported), Some(verified)) if verified >= exported)
+    }
+}
+
+fn status_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(STATUS_FILE)
+}
+
+/// The identity's private key as a 24-word BIP-39 phrase
+pub fn export_mnemonic(data_dir: &Path, identity: &Identity) -> Result<String> {
+    let mut secret = identity.secret_bytes();
+    let mnemonic = Mnemonic::from_entropy(&secret);
+    secret.zeroize();
+    let phrase = mnemonic
+        .context("Failed to encode recovery phrase")?
+        .to_string();
+
+    let mut status = RecoveryStatus::load(data_dir, identity)?;
+    status.mnemonic_exported_at = Some(now_secs());
+    status.save(data_dir)?;
+    info!("Recovery phrase exported");
+
+    Ok(phrase)
+}
+
+/// Split the identity's private key into Shamir shards for trusted holders
+pub fn export_shards(
+    data_dir: &Path,
+    identity: &Identity,
+    threshold: u8,
+    total_shards: u8,
+) -> Result<Vec<String>> {
+    if threshold < 2 || threshold > total_shards {
+        anyhow::bail!("Threshold must be at least 2 and at most the number of shards");
+    }
+
+    let mut secret = identity.secret_bytes();
+    let key = SessionKey::from_bytes(&secret);
+    secret.zeroize();
+    let shards = split_session_key(&key?, threshold, total_shards);
+
+    let mut status = RecoveryStatus::load(data_dir, identity)?;
+    status.shards_exported_at = Some(now_secs());
+    status.shard_threshold = Some(threshold);
+    status.shard_total = Some(total_shards);
+    status.save(data_dir)?;
+    info!(
+        "Recovery shards exported ({} of {})",
+        threshold, total_shards
+    );
+
+    Ok(shards)
+}
+
+/// Rebuild an identity from a recovery phrase (one entry) or backup shards
+pub fn restore_identity(backup: &[String]) -> Result<Identity> {
+    let mut secret = [0u8; 32];
+    match backup {
+        [phrase] if phrase.trim().contains(' ') => {
+            let normalized = phrase
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .to_lowercase();
+            let mnemonic =
+                Mnemonic::parse_normalized(&normalized).context("Invalid recovery phrase")?;
+            let mut entropy = mnemonic.to_entropy();
+            if entropy.len() != secret.len() {
+                entropy.zeroize();
+                anyhow::bail!("Recovery phrase must have 24 words");
+            }
+            secret.copy_from_slice(&entropy);
+            entropy.zeroize();
+        }
+        shards => secret = recover_session_key(shards)?.as_bytes(),
+    }
+
+    let identity = Identity::from_secret_bytes(secret);
+    secret.zeroize();
+    Ok(identity)
+}
+
+/// Check that a backup restores `identity`, recording when it last did.
+/// The restored key is only compared, never stored.
+pub fn verify_backup(
+    data_dir: &Path,
+    identity: &Identity,
+    backup: &[String],
+) -> Result<RecoveryStatus> {
+    let restored = restore_identity(backup).context("Backup could not be read")?;
+    if restored.public_id() != identity.public_id() {
+        anyhow::bail!("Backup belongs to a different identity");
+    }
+
+    let mut status = RecoveryStatus::load(data_dir, identity)?;
+    status.verified_at = Some(now_secs());
+    status.save(data_dir)?;
+    info!("Recovery backup verified");
+
+    Ok(status)
+}
+
+/// Emit `recovery_reminder` while the unlocked identity has no verified backup
+pub fn start_recovery_reminders(app: tauri::AppHandle, data_dir: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        let mut last_reminder: Option<std::time::Instant> = None;
+        loop {
+            tokio::time::sleep(Duration::from_secs(REMINDER_CHECK_SECS)).await;
+
+            let settings = settings::recovery_settings();
+            if !settings.reminders
+                || last_reminder.is_some_and(|at| at.elapsed() < settings.reminder_interval())
+            {
+                continue;
+            }
+            let Some(identity) = app.state::<AppState>().peek_identity() else {
+                continue;
+            };
+
+            match RecoveryStatus::load(&data_dir, &identity) {
+                Ok(status) if !status.ready => {
+                    events::emit(&app, "recovery_reminder", status);
+                    last_reminder = Some(std::time::Instant::now());
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Failed to check recovery status: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn mnemonic_and_shards_restore_identity() {
+        let dir = std::env::temp_dir().join(format!("control-recovery-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let identity = Identity::generate();
+
+        let phrase = export_mnemonic(&dir, &identity).unwrap();
+        assert_eq!(phrase.split_whitespace().count(), 24);
+        assert!(!RecoveryStatus::load(&dir, &identity).unwrap().ready);
+
+        let status = verify_backup(&dir, &identity, &[phrase.to_uppercase()]).unwrap();
+        assert!(status.ready);
+
+        let shards = export_shards(&dir, &identity, 2, 3).unwrap();
+        let restored = restore_identity(&shards[1..]).unwrap();
+        assert_eq!(restored.public_id(), identity.public_id());
+
+        let other = Identity::generate();
+        assert!(verify_backup(&dir, &other, &shards[..2]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,421 @@
+use crate::catalog::now_secs;
+use crate::contacts::ContactBook;
+use crate::crypto::{decode_public_key, Identity, SessionKey};
+use crate::devices::{verifying_key, DeviceStatus, DeviceStore};
+use crate::no_traces;
+use crate::settings;
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, Verifier};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
+
+const WIPE_FILE: &str = "remote_wipe.enc";
+/// Older orders are refused, so a captured order can't be replayed later
+const MAX_ORDER_AGE_SECS: u64 = 10 * 60;
+/// How far ahead of our clock an order's timestamp may be
+const MAX_CLOCK_SKEW_SECS: u64 = 5 * 60;
+const DEFAULT_CONFIRMATION_DELAY_SECS: u64 = 5 * 60;
+const MAX_CONFIRMATION_DELAY_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Who may wipe this device from afar, and how long it waits first
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RemoteWipeSettings {
+    /// Orders are refused while off
+    pub enabled: bool,
+    /// Public IDs of contacts who may order a wipe; our own active devices
+    /// always may
+    pub guardians: Vec<String>,
+    /// Time between receiving an order and wiping, to cancel a mistaken one
+    pub confirmation_delay_secs: u64,
+}
+
+impl Default for RemoteWipeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            guardians: Vec::new(),
+            confirmation_delay_secs: DEFAULT_CONFIRMATION_DELAY_SECS,
+        }
+    }
+}
+
+impl RemoteWipeSettings {
+    pub fn validate(&self) -> Result<()> {
+        for guardian in &self.guardians {
+            decode_public_key(guardian)
+                .with_context(|| format!("Invalid guardian public ID: {}", guardian))?;
+        }
+        if self.confirmation_delay_secs > MAX_CONFIRMATION_DELAY_SECS {
+            anyhow::bail!("Confirmation delay can be at most 7 days");
+        }
+        Ok(())
+    }
+}
+
+/// What is signed to order a wipe
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WipeOrder {
+    /// Identity whose device is wiped
+    pub owner: String,
+    /// Device to wipe; every device of the owner but the issuer if unset
+    pub device_id: Option<String>,
+    /// Device ID of one of the owner's devices, or public ID of a guardian
+    pub issuer: String,
+    /// Random, so each order is accepted once
+    pub nonce: String, // hex
+    pub issued_at: u64,
+}
+
+/// Wipe order as sent over Ghost Mode
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedWipe {
+    /// JSON of a `WipeOrder`, signed byte for byte
+    pub statement: String,
+    pub signature: String,   // hex
+    pub signing_key: String, // hex, the issuer's ed25519 key
+}
+
+impl SignedWipe {
+    fn sign(signer: &Identity, order: &WipeOrder) -> Result<Self> {
+        let statement = serde_json::to_string(order)?;
+        let signing_key = signer.signing_key();
+        let signature = signing_key.sign(statement.as_bytes());
+        Ok(Self {
+            statement,
+            signature: hex::encode(signature.to_bytes()),
+            signing_key: hex::encode(signing_key.verifying_key().to_bytes()),
+        })
+    }
+
+    /// Check the signature and return the order. Whether the signing key
+    /// belongs to the issuer is checked by `receive`.
+    fn verify(&self) -> Result<WipeOrder> {
+        let key = verifying_key(&self.signing_key)?;
+        let signature = hex::decode(&self.signature).context("Invalid wipe signature")?;
+        let signature = Signature::from_slice(&signature).context("Invalid wipe signature")?;
+        key.verify(self.statement.as_bytes(), &signature)
+            .context("Wipe signature does not match")?;
+        serde_json::from_str(&self.statement).context("Invalid wipe order")
+    }
+}
+
+/// An accepted order waiting out its confirmation delay
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingWipe {
+    pub order: WipeOrder,
+    pub received_at: u64,
+    /// The device is wiped at this time unless the order is cancelled
+    pub due_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct WipeState {
+    /// Nonces of accepted orders, with their issue time
+    seen: BTreeMap<String, u64>,
+    pending: Option<PendingWipe>,
+}
+
+/// Seen orders and the pending one, encrypted with the local identity
+struct WipeStore {
+    path: PathBuf,
+    storage_key: SessionKey,
+    state: WipeState,
+}
+
+impl WipeStore {
+    fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(WIPE_FILE);
+        let storage_key = identity.storage_key(b"remote-wipe");
+
+        let state = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read remote wipe state")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt remote wipe state")?;
+            let state = serde_json::from_slice(&json);
+            json.zeroize();
+            state.context("Failed to parse remote wipe state")?
+        } else {
+            WipeState::default()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            state,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut json = serde_json::to_vec(&self.state)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?)
+            .context("Failed to write remote wipe state")?;
+        Ok(())
+    }
+}
+
+/// Sign an order to wipe `device_id`, or every other device of `owner`.
+/// For our own devices this device signs with its device key; for a contact
+/// who made us their guardian we sign with our identity. Returns the public
+/// ID to send the order to.
+pub fn order(
+    data_dir: &Path,
+    identity: &Identity,
+    owner: Option<&str>,
+    device_id: Option<String>,
+) -> Result<(String, SignedWipe)> {
+    let owner = owner.map_or_else(|| identity.public_id(), str::to_string);
+    let mut order = WipeOrder {
+        owner: owner.clone(),
+        device_id,
+        issuer: identity.public_id(),
+        nonce: hex::encode(nonce()),
+        issued_at: now_secs(),
+    };
+
+    if owner != identity.public_id() {
+        decode_public_key(&owner).context("Invalid public ID")?;
+        let signed = SignedWipe::sign(identity, &order)?;
+        return Ok((owner, signed));
+    }
+
+    let devices = DeviceStore::load(data_dir, identity)?;
+    if devices.status() != Some(DeviceStatus::Active) {
+        anyhow::bail!("Only an active device can wipe others");
+    }
+    if let Some(target) = &order.device_id {
+        if *target == devices.device_id() {
+            anyhow::bail!("Wipe this device from another one");
+        }
+        devices.get(target).context("Unknown device")?;
+    }
+    order.issuer = devices.device_id();
+    let signed = SignedWipe::sign(devices.device_identity(), &order)?;
+    Ok((owner, signed))
+}
+
+/// Check an order that `sender_id` sent us and start its confirmation
+/// delay. `None` if a wipe is already pending.
+pub fn receive(
+    data_dir: &Path,
+    identity: &Identity,
+    sender_id: &str,
+    signed: &SignedWipe,
+) -> Result<Option<PendingWipe>> {
+    let wipe_settings = settings::remote_wipe_settings();
+    if !wipe_settings.enabled {
+        anyhow::bail!("Remote wipe is turned off");
+    }
+    let order = signed.verify()?;
+    if order.owner != identity.public_id() {
+        anyhow::bail!("Wipe order is for {}", order.owner);
+    }
+    authorize(
+        data_dir,
+        identity,
+        sender_id,
+        signed,
+        &order,
+        &wipe_settings,
+    )?;
+
+    let now = now_secs();
+    if order.issued_at + MAX_ORDER_AGE_SECS < now {
+        anyhow::bail!("Wipe order has expired");
+    }
+    if order.issued_at > now + MAX_CLOCK_SKEW_SECS {
+        anyhow::bail!("Wipe order is from the future");
+    }
+
+    let mut store = WipeStore::load(data_dir, identity)?;
+    store
+        .state
+        .seen
+        .retain(|_, issued_at| *issued_at + MAX_ORDER_AGE_SECS + MAX_CLOCK_SKEW_SECS >= now);
+    if store.state.seen.contains_key(&order.nonce) {
+        anyhow::bail!("Wipe order was already received");
+    }
+    store
+        .state
+        .seen
+        .insert(order.nonce.clone(), order.issued_at);
+
+    let pending = match store.state.pending {
+        Some(_) => None,
+        None => {
+            let pending = PendingWipe {
+                order,
+                received_at: now,
+                due_at: now + wipe_settings.confirmation_delay_secs,
+            };
+            store.state.pending = Some(pending.clone());
+            Some(pending)
+        }
+    };
+    store.save()?;
+    Ok(pending)
+}
+
+/// Our own active devices may order a wipe with their device key; anyone
+/// else must be a guardian contact signing with the key we know for them
+fn authorize(
+    data_dir: &Path,
+    identity: &Identity,
+    sender_id: &str,
+    signed: &SignedWipe,
+    order: &WipeOrder,
+    wipe_settings: &RemoteWipeSettings,
+) -> Result<()> {
+    let devices = DeviceStore::load(data_dir, identity)?;
+    if let Some(target) = &order.device_id {
+        if *target != devices.device_id() {
+            anyhow::bail!("Wipe order is for another device");
+        }
+    }
+
+    if sender_id == identity.public_id() {
+        if order.issuer == devices.device_id() {
+            anyhow::bail!("Wipe order came from this device");
+        }
+        let device = devices
+            .get(&order.issuer)
+            .context("Wipe order from an unknown device")?;
+        if device.status != DeviceStatus::Active {
+            anyhow::bail!("Wipe order from a device that isn't active");
+        }
+        if device.signing_key != signed.signing_key {
+            anyhow::bail!("Wipe order is not signed with the device's key");
+        }
+        return Ok(());
+    }
+
+    if order.issuer != sender_id {
+        anyhow::bail!("Wipe order claims to be from {}", order.issuer);
+    }
+    if !wipe_settings.guardians.iter().any(|g| g == sender_id) {
+        anyhow::bail!("{} is not a guardian", sender_id);
+    }
+    let book = ContactBook::load(data_dir, identity)?;
+    let contact = book
+        .get(sender_id)
+        .context("Guardian is not in the address book")?;
+    if contact.signing_key.as_deref() != Some(signed.signing_key.as_str()) {
+        anyhow::bail!("Wipe order is signed with an unknown key");
+    }
+    Ok(())
+}
+
+pub fn pending(data_dir: &Path, identity: &Identity) -> Result<Option<PendingWipe>> {
+    if !no_traces::file_exists(&data_dir.join(WIPE_FILE)) {
+        return Ok(None);
+    }
+    Ok(WipeStore::load(data_dir, identity)?.state.pending)
+}
+
+/// Call off the pending wipe. Its order stays seen, so it can't be replayed.
+pub fn cancel(data_dir: &Path, identity: &Identity) -> Result<Option<PendingWipe>> {
+    if !no_traces::file_exists(&data_dir.join(WIPE_FILE)) {
+        return Ok(None);
+    }
+    let mut store = WipeStore::load(data_dir, identity)?;
+    let cancelled = store.state.pending.take();
+    if cancelled.is_some() {
+        store.save()?;
+    }
+    Ok(cancelled)
+}
+
+/// The pending wipe, once its confirmation delay is over
+pub fn due(data_dir: &Path, identity: &Identity) -> Result<Option<PendingWipe>> {
+    if !settings::remote_wipe_settings().enabled {
+        return Ok(None);
+    }
+    Ok(pending(data_dir, identity)?.filter(|p| p.due_at <= now_secs()))
+}
+
+fn nonce() -> [u8; 16] {
+    let mut nonce = [0u8; 16];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_are_checked_against_the_device_list() {
+        let identity = Identity::generate();
+        let (laptop_dir, phone_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let mut laptop = DeviceStore::load(laptop_dir.path(), &identity).unwrap();
+        let mut phone = DeviceStore::load(phone_dir.path(), &identity).unwrap();
+        laptop.register(&identity).unwrap();
+        phone.merge(&identity, laptop.signed().unwrap()).unwrap();
+        phone.register(&identity).unwrap();
+        laptop.merge(&identity, phone.signed().unwrap()).unwrap();
+        laptop.approve(&identity, &phone.device_id()).unwrap();
+        phone.merge(&identity, laptop.signed().unwrap()).unwrap();
+        laptop.save().unwrap();
+        phone.save().unwrap();
+
+        let (recipient, signed) =
+            order(laptop_dir.path(), &identity, None, Some(phone.device_id())).unwrap();
+        assert_eq!(recipient, identity.public_id());
+        let wipe_settings = RemoteWipeSettings {
+            enabled: true,
+            ..Default::default()
+        };
+        let order = signed.verify().unwrap();
+        authorize(
+            phone_dir.path(),
+            &identity,
+            &identity.public_id(),
+            &signed,
+            &order,
+            &wipe_settings,
+        )
+        .unwrap();
+
+        // A stranger's key, or an order the laptop would take for itself
+        let stranger = SignedWipe::sign(&Identity::generate(), &order).unwrap();
+        assert!(authorize(
+            phone_dir.path(),
+            &identity,
+            &identity.public_id(),
+            &stranger,
+            &order,
+            &wipe_settings,
+        )
+        .is_err());
+        assert!(authorize(
+            laptop_dir.path(),
+            &identity,
+            &identity.public_id(),
+            &signed,
+            &order,
+            &wipe_settings,
+        )
+        .is_err());
+
+        // Once revoked, the laptop can't order wipes
+        let mut phone = DeviceStore::load(phone_dir.path(), &identity).unwrap();
+        phone.revoke(&identity, &laptop.device_id()).unwrap();
+        phone.save().unwrap();
+        assert!(authorize(
+            phone_dir.path(),
+            &identity,
+            &identity.public_id(),
+            &signed,
+            &order,
+            &wipe_settings,
+        )
+        .is_err());
+    }
+}
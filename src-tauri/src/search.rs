@@ -0,0 +1,395 @@
+use crate::crypto::{Identity, SessionKey};
+use crate::p2p::GhostMessage;
+use crate::settings;
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+use zeroize::Zeroize;
+
+const SEARCH_DIR: &str = "search";
+const META_FILE: &str = "meta.enc";
+/// Term postings are spread over this many files, so a query only decrypts
+/// the few that hold its terms
+const TERM_BUCKETS: usize = 64;
+/// Messages per document segment
+const SEGMENT_SIZE: u64 = 1000;
+const MIN_TERM_CHARS: usize = 2;
+const MAX_TERM_CHARS: usize = 64;
+pub const MAX_RESULTS: usize = 200;
+
+/// Keep a searchable copy of sent and received messages. Off by default,
+/// since nothing else keeps message content after it is shown.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SearchSettings {
+    pub enabled: bool,
+}
+
+/// A message matching every term of a query
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit {
+    pub message_id: String,
+    /// Public ID of the other side of the conversation
+    pub contact: String,
+    pub outgoing: bool,
+    pub timestamp: u64,
+    pub content: String,
+    /// Brought back from an archive, so retention leaves it alone
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub restored: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SearchMeta {
+    /// Number of messages indexed; the next one gets this number
+    docs: u64,
+}
+
+/// Postings of one bucket, keyed by the hashed term
+type Postings = BTreeMap<String, Vec<u64>>;
+/// Messages by number within a segment; `None` where one was archived
+type Segment = Vec<Option<SearchHit>>;
+
+/// Inverted index over message content, stored as encrypted files under
+/// `search/`: document segments, and term postings spread over buckets.
+/// Terms are kept only as keyed hashes, so even a decrypted bucket doesn't
+/// list the words used.
+pub struct SearchIndex {
+    dir: PathBuf,
+    storage_key: SessionKey,
+    term_key: [u8; 32],
+}
+
+impl Drop for SearchIndex {
+    fn drop(&mut self) {
+        self.term_key.zeroize();
+    }
+}
+
+impl SearchIndex {
+    pub fn open(data_dir: &Path, identity: &Identity) -> Self {
+        Self {
+            dir: data_dir.join(SEARCH_DIR),
+            storage_key: identity.storage_key(b"search"),
+            term_key: identity.storage_key(b"search-terms").as_bytes(),
+        }
+    }
+
+    /// Index one message; `contact` is the other side of the conversation
+    pub fn add(&self, contact: &str, outgoing: bool, message: &GhostMessage) -> Result<()> {
+        self.append(SearchHit {
+            message_id: message.id.clone(),
+            contact: contact.to_string(),
+            outgoing,
+            timestamp: message.timestamp,
+            content: message.content.clone(),
+            restored: false,
+        })
+        .map(|_| ())
+    }
+
+    fn append(&self, hit: SearchHit) -> Result<u64> {
+        let mut meta: SearchMeta = self.read(META_FILE)?.unwrap_or_default();
+        let doc = meta.docs;
+
+        let segment = segment_file(doc);
+        let mut hits: Segment = self.read(&segment)?.unwrap_or_default();
+        self.update_postings(&[(doc, &hit.content)], true)?;
+        hits.push(Some(hit));
+        self.write(&segment, &hits)?;
+
+        meta.docs += 1;
+        self.write(META_FILE, &meta)?;
+        Ok(doc)
+    }
+
+    /// Messages containing every word of `query`, newest first. Words match
+    /// whole and regardless of case.
+    pub fn search(
+        &self,
+        query: &str,
+        contact: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>> {
+        let terms = terms(query);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut docs: Option<BTreeSet<u64>> = None;
+        let mut loaded: BTreeMap<usize, Postings> = BTreeMap::new();
+        for term in &terms {
+            let (bucket, hash) = self.hash_term(term);
+            if let Entry::Vacant(entry) = loaded.entry(bucket) {
+                entry.insert(self.read(&bucket_file(bucket))?.unwrap_or_default());
+            }
+            let matching: BTreeSet<u64> = loaded[&bucket]
+                .get(&hash)
+                .map(|d| d.iter().copied().collect())
+                .unwrap_or_default();
+            docs = Some(match docs {
+                Some(docs) => docs.intersection(&matching).copied().collect(),
+                None => matching,
+            });
+        }
+
+        let mut hits = Vec::new();
+        let mut segments: BTreeMap<u64, Segment> = BTreeMap::new();
+        for doc in docs.unwrap_or_default().into_iter().rev() {
+            let segment = doc / SEGMENT_SIZE;
+            if let Entry::Vacant(entry) = segments.entry(segment) {
+                entry.insert(self.read(&segment_file(doc))?.unwrap_or_default());
+            }
+            let Some(Some(hit)) = segments[&segment].get((doc % SEGMENT_SIZE) as usize) else {
+                continue;
+            };
+            if contact.is_some_and(|c| c != hit.contact) {
+                continue;
+            }
+            hits.push(hit.clone());
+            if hits.len() >= limit.min(MAX_RESULTS) {
+                break;
+            }
+        }
+        Ok(hits)
+    }
+
+    /// Every indexed message with its number, oldest first
+    pub fn messages(&self) -> Result<Vec<(u64, SearchHit)>> {
+        let meta: SearchMeta = self.read(META_FILE)?.unwrap_or_default();
+        let mut messages = Vec::new();
+        for segment in 0..meta.docs.div_ceil(SEGMENT_SIZE) {
+            let hits: Segment = self
+                .read(&segment_file(segment * SEGMENT_SIZE))?
+                .unwrap_or_default();
+            messages.extend(
+                hits.into_iter()
+                    .enumerate()
+                    .filter_map(|(i, hit)| Some((segment * SEGMENT_SIZE + i as u64, hit?))),
+            );
+        }
+        Ok(messages)
+    }
+
+    /// Take messages out of the index, returning them with their numbers
+    pub fn remove(&self, docs: &BTreeSet<u64>) -> Result<Vec<(u64, SearchHit)>> {
+        let mut removed = Vec::new();
+        let mut segments: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for &doc in docs {
+            segments.entry(doc / SEGMENT_SIZE).or_default().push(doc);
+        }
+        for (segment, docs) in segments {
+            let file = segment_file(segment * SEGMENT_SIZE);
+            let mut hits: Segment = self.read(&file)?.unwrap_or_default();
+            for doc in docs {
+                if let Some(hit) = hits
+                    .get_mut((doc % SEGMENT_SIZE) as usize)
+                    .and_then(Option::take)
+                {
+                    removed.push((doc, hit));
+                }
+            }
+            self.write(&file, &hits)?;
+        }
+        let contents: Vec<_> = removed
+            .iter()
+            .map(|(doc, hit)| (*doc, hit.content.as_str()))
+            .collect();
+        self.update_postings(&contents, false)?;
+        Ok(removed)
+    }
+
+    /// Put removed messages back under their old numbers. A message whose
+    /// slot was taken in the meantime, because the index was cleared, is
+    /// added as new.
+    pub fn restore(&self, messages: Vec<(u64, SearchHit)>) -> Result<()> {
+        let meta: SearchMeta = self.read(META_FILE)?.unwrap_or_default();
+        let mut segments: BTreeMap<u64, Vec<(u64, SearchHit)>> = BTreeMap::new();
+        for (doc, mut hit) in messages {
+            hit.restored = true;
+            segments
+                .entry(doc / SEGMENT_SIZE)
+                .or_default()
+                .push((doc, hit));
+        }
+
+        let mut displaced = Vec::new();
+        for (segment, messages) in segments {
+            let file = segment_file(segment * SEGMENT_SIZE);
+            let mut hits: Segment = self.read(&file)?.unwrap_or_default();
+            let mut placed = Vec::new();
+            for (doc, hit) in messages {
+                match hits.get_mut((doc % SEGMENT_SIZE) as usize) {
+                    Some(slot) if slot.is_none() && doc < meta.docs => {
+                        placed.push((doc, hit.content.clone()));
+                        *slot = Some(hit);
+                    }
+                    _ => displaced.push(hit),
+                }
+            }
+            self.write(&file, &hits)?;
+            let contents: Vec<_> = placed.iter().map(|(doc, c)| (*doc, c.as_str())).collect();
+            self.update_postings(&contents, true)?;
+        }
+        for hit in displaced {
+            self.append(hit)?;
+        }
+        Ok(())
+    }
+
+    /// Add or drop the postings of messages' words
+    fn update_postings(&self, messages: &[(u64, &str)], add: bool) -> Result<()> {
+        let mut buckets: BTreeMap<usize, Vec<(String, u64)>> = BTreeMap::new();
+        for (doc, content) in messages {
+            for term in terms(content) {
+                let (bucket, hash) = self.hash_term(&term);
+                buckets.entry(bucket).or_default().push((hash, *doc));
+            }
+        }
+        for (bucket, entries) in buckets {
+            let file = bucket_file(bucket);
+            let mut postings: Postings = self.read(&file)?.unwrap_or_default();
+            for (hash, doc) in entries {
+                let docs = postings.entry(hash.clone()).or_default();
+                if add {
+                    docs.push(doc);
+                } else {
+                    docs.retain(|d| *d != doc);
+                    if docs.is_empty() {
+                        postings.remove(&hash);
+                    }
+                }
+            }
+            self.write(&file, &postings)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the whole index
+    pub fn clear(&self) -> Result<()> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir).context("Failed to delete search index")?;
+        }
+        Ok(())
+    }
+
+    fn hash_term(&self, term: &str) -> (usize, String) {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.term_key).expect("HMAC accepts any key length");
+        mac.update(term.as_bytes());
+        let hash = mac.finalize().into_bytes();
+        (hash[0] as usize % TERM_BUCKETS, hex::encode(&hash[..16]))
+    }
+
+    fn read<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>> {
+        let path = self.dir.join(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let encrypted = fs::read(&path).context("Failed to read search index")?;
+        let mut json = self
+            .storage_key
+            .decrypt_file(&encrypted)
+            .context("Failed to decrypt search index")?;
+        let value = serde_json::from_slice(&json);
+        json.zeroize();
+        Ok(Some(value.context("Failed to parse search index")?))
+    }
+
+    fn write<T: Serialize>(&self, name: &str, value: &T) -> Result<()> {
+        fs::create_dir_all(&self.dir).context("Failed to create search index")?;
+        let mut json = serde_json::to_vec(value)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        fs::write(self.dir.join(name), encrypted?).context("Failed to write search index")?;
+        Ok(())
+    }
+}
+
+fn segment_file(doc: u64) -> String {
+    format!("docs-{:06}.enc", doc / SEGMENT_SIZE)
+}
+
+fn bucket_file(bucket: usize) -> String {
+    format!("terms-{:02}.enc", bucket)
+}
+
+/// Lowercased words of `text`, each once
+fn terms(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(str::to_lowercase)
+        .filter(|t| (MIN_TERM_CHARS..=MAX_TERM_CHARS).contains(&t.chars().count()))
+        .collect()
+}
+
+/// Index a sent or received message if search is on. View-once messages
+/// are never indexed.
+pub fn record(
+    data_dir: &Path,
+    identity: &Identity,
+    contact: &str,
+    outgoing: bool,
+    message: &GhostMessage,
+) {
+    if !settings::search_settings().enabled || message.view_once {
+        return;
+    }
+    if let Err(e) = SearchIndex::open(data_dir, identity).add(contact, outgoing, message) {
+        warn!("Failed to index message {}: {}", message.id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, content: &str, timestamp: u64) -> GhostMessage {
+        GhostMessage {
+            id: id.to_string(),
+            from: String::new(),
+            content: content.to_string(),
+            timestamp,
+            view_once: false,
+            location: None,
+        }
+    }
+
+    #[test]
+    fn finds_messages_by_every_word() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = Identity::generate();
+        let index = SearchIndex::open(dir.path(), &identity);
+
+        index
+            .add("ana", false, &message("1", "Meet at the old bridge", 10))
+            .unwrap();
+        index
+            .add(
+                "bo",
+                true,
+                &message("2", "The bridge is closed, meet later", 20),
+            )
+            .unwrap();
+        index.add("ana", true, &message("3", "ok", 30)).unwrap();
+
+        let ids = |hits: Vec<SearchHit>| hits.into_iter().map(|h| h.message_id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(index.search("BRIDGE meet", None, 10).unwrap()),
+            ["2", "1"]
+        );
+        assert_eq!(ids(index.search("bridge", Some("ana"), 10).unwrap()), ["1"]);
+        assert_eq!(ids(index.search("bridge old", None, 1).unwrap()), ["1"]);
+        assert!(index.search("tunnel", None, 10).unwrap().is_empty());
+        assert!(index.search("!", None, 10).unwrap().is_empty());
+
+        index.clear().unwrap();
+        assert!(index.search("bridge", None, 10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,474 @@
+use crate::no_traces;
+use crate::settings;
+use crate::shred::shred_file;
+use crate::staging::cleanup_staging;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tracing::{info, warn};
+
+/// What a stored item holds, most sensitive first
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretKind {
+    Identity,
+    KeyMaterial,
+    Personal,
+    Metadata,
+    Settings,
+    Cache,
+    Logs,
+}
+
+struct ItemSpec {
+    name: &'static str,
+    kind: SecretKind,
+    description: &'static str,
+    encrypted: bool,
+    /// Control can't keep running without it
+    required: bool,
+}
+
+/// Everything Control may persist in the data directory
+const ITEMS: &[ItemSpec] = &[
+    ItemSpec {
+        name: "identity.enc",
+        kind: SecretKind::Identity,
+        description: "Identity private key, encrypted with your password",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "burn_keys.enc",
+        kind: SecretKind::KeyMaterial,
+        description: "Unwrap keys for burn-after-read drops you created",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "versions.enc",
+        kind: SecretKind::KeyMaterial,
+        description: "Session keys and IPNS key names of versioned drops",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "settings.key",
+        kind: SecretKind::KeyMaterial,
+        description: "Unprotected key for the settings file, usage statistics and encrypted log",
+        encrypted: false,
+        required: true,
+    },
+    ItemSpec {
+        name: "contacts.enc",
+        kind: SecretKind::Personal,
+        description: "Address book",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "conversations.enc",
+        kind: SecretKind::Personal,
+        description: "Conversation list with last message previews",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "introductions.enc",
+        kind: SecretKind::Personal,
+        description: "Introductions from contacts waiting for an answer",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "groups.enc",
+        kind: SecretKind::Personal,
+        description: "Groups and shards of group drops held for others",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "polls.enc",
+        kind: SecretKind::Personal,
+        description: "Group polls with their questions and votes",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "knocks.enc",
+        kind: SecretKind::Personal,
+        description: "Contact requests and messages held until they are answered",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "mailbox.enc",
+        kind: SecretKind::Metadata,
+        description: "Envelopes kept for contacts while they are offline",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "mesh.enc",
+        kind: SecretKind::Metadata,
+        description: "Envelopes waiting for trusted devices on the local network",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "nostr.enc",
+        kind: SecretKind::Metadata,
+        description: "Which Nostr relay envelopes were already received",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "proofs.enc",
+        kind: SecretKind::Personal,
+        description: "Domains and repositories you claimed for your identity",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "view_once.enc",
+        kind: SecretKind::KeyMaterial,
+        description: "One-time keys of unread view-once messages and tombstones of viewed ones",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "view_once",
+        kind: SecretKind::Personal,
+        description: "Unread view-once messages, each under its own one-time key",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "search",
+        kind: SecretKind::Personal,
+        description: "Searchable copy of sent and received messages, when search is on",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "archives.enc",
+        kind: SecretKind::Metadata,
+        description: "List of message archives made by the retention settings",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "archives",
+        kind: SecretKind::Personal,
+        description: "Compressed archives of old messages moved out of the search index",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "attestations.enc",
+        kind: SecretKind::Personal,
+        description: "Signed attestations verified contacts sent about other people",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "key_log.enc",
+        kind: SecretKind::Personal,
+        description: "Hash-chained log of every signing and OpenPGP key seen for contacts",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "drop_audit.enc",
+        kind: SecretKind::Metadata,
+        description: "Hash-chained log of what was done to your drops and when",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "remote_wipe.enc",
+        kind: SecretKind::Metadata,
+        description: "Wipe orders already received and a wipe waiting to run",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "dead_man.enc",
+        kind: SecretKind::KeyMaterial,
+        description: "Armed dead-man's switches and the shards they hold back",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "escrow.enc",
+        kind: SecretKind::KeyMaterial,
+        description: "Release requests for escrowed drops and the shards approvers sent back",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "devices.enc",
+        kind: SecretKind::KeyMaterial,
+        description: "This device's own key and the signed list of your devices",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "canaries.enc",
+        kind: SecretKind::Personal,
+        description: "Canary contacts and shards you planted, and the alerts they raised",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "transcripts.enc",
+        kind: SecretKind::Personal,
+        description: "Hash-chained transcripts of conversations under a compliance hold",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "usage.enc",
+        kind: SecretKind::Personal,
+        description: "Local usage statistics",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "drops.json",
+        kind: SecretKind::Metadata,
+        description: "Catalog of drops you created: CIDs, file names and expiry",
+        encrypted: false,
+        required: false,
+    },
+    ItemSpec {
+        name: "pending_uploads.json",
+        kind: SecretKind::Metadata,
+        description: "Drops waiting for an upload window",
+        encrypted: false,
+        required: false,
+    },
+    ItemSpec {
+        name: "watch_folder.enc",
+        kind: SecretKind::Metadata,
+        description: "Files from the watched folder already dropped, with their CIDs",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "backups.enc",
+        kind: SecretKind::Metadata,
+        description: "CIDs and times of the scheduled folder backups still kept",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "sync.enc",
+        kind: SecretKind::Metadata,
+        description: "Device sync state",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "recovery.json",
+        kind: SecretKind::Metadata,
+        description: "When the recovery backup was last exported and verified",
+        encrypted: false,
+        required: false,
+    },
+    ItemSpec {
+        name: "settings.enc",
+        kind: SecretKind::Settings,
+        description: "Application settings",
+        encrypted: true,
+        required: true,
+    },
+    ItemSpec {
+        name: "staging",
+        kind: SecretKind::Cache,
+        description: "Ciphertext staged for uploads in progress",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "drop_cache",
+        kind: SecretKind::Cache,
+        description: "Downloaded drop ciphertext kept for resuming",
+        encrypted: true,
+        required: false,
+    },
+    ItemSpec {
+        name: "plugins",
+        kind: SecretKind::Settings,
+        description: "Installed plugins: their manifests and WASM modules",
+        encrypted: false,
+        required: false,
+    },
+    ItemSpec {
+        name: "kubo",
+        kind: SecretKind::KeyMaterial,
+        description: "Managed IPFS node: Kubo binary, repo and the node's peer key",
+        encrypted: false,
+        required: false,
+    },
+    ItemSpec {
+        name: "updates",
+        kind: SecretKind::Cache,
+        description: "Downloaded update installers",
+        encrypted: false,
+        required: false,
+    },
+    ItemSpec {
+        name: "logs",
+        kind: SecretKind::Logs,
+        description: "Application logs",
+        encrypted: false,
+        required: false,
+    },
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredSecret {
+    pub name: String,
+    pub kind: SecretKind,
+    pub description: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub encrypted: bool,
+    /// Deleting it would break Control, so `delete_stored_secret` refuses
+    pub required: bool,
+    pub size: u64,
+    /// Files inside, for directories
+    pub file_count: u64,
+    pub created_at: Option<u64>,
+    pub modified_at: Option<u64>,
+}
+
+/// Everything Control currently keeps on disk
+pub fn list_stored_secrets(data_dir: &Path) -> Result<Vec<StoredSecret>> {
+    let mut secrets = Vec::new();
+    for spec in ITEMS {
+        let path = item_path(data_dir, spec);
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        let (size, file_count) = if metadata.is_dir() {
+            dir_usage(&path)?
+        } else {
+            (metadata.len(), 1)
+        };
+
+        secrets.push(StoredSecret {
+            name: spec.name.to_string(),
+            kind: spec.kind,
+            description: spec.description.to_string(),
+            path,
+            is_dir: metadata.is_dir(),
+            encrypted: spec.encrypted,
+            required: spec.required,
+            size,
+            file_count,
+            created_at: metadata.created().ok().and_then(unix_secs),
+            modified_at: metadata.modified().ok().and_then(unix_secs),
+        });
+    }
+    Ok(secrets)
+}
+
+/// Delete one listed item. Files are shredded first; directories are removed.
+/// The identity is only deleted when `confirmation` repeats its name.
+pub fn delete_stored_secret(data_dir: &Path, name: &str, confirmation: Option<&str>) -> Result<()> {
+    let spec = ITEMS
+        .iter()
+        .find(|spec| spec.name == name)
+        .with_context(|| format!("Unknown stored item: {}", name))?;
+    if spec.required {
+        anyhow::bail!("{} is needed to run Control and can't be deleted", name);
+    }
+    if spec.kind == SecretKind::Identity && confirmation != Some(name) {
+        anyhow::bail!("Deleting the identity requires confirming its file name");
+    }
+
+    if remove_item(data_dir, spec)? {
+        info!("Deleted stored {}", name);
+    }
+    Ok(())
+}
+
+/// Outcome of wiping everything Control stores
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct WipeReport {
+    pub deleted: Vec<String>,
+    /// Items that could not be deleted, with the reason
+    pub failed: Vec<String>,
+}
+
+/// Delete every listed item, the identity first, without asking. Failures
+/// are reported and don't stop the wipe.
+pub fn panic_wipe(data_dir: &Path) -> WipeReport {
+    let mut report = WipeReport::default();
+    for spec in ITEMS {
+        match remove_item(data_dir, spec) {
+            Ok(true) => report.deleted.push(spec.name.to_string()),
+            Ok(false) => {}
+            Err(e) => report.failed.push(format!("{}: {}", spec.name, e)),
+        }
+    }
+    warn!(
+        "Wiped {} stored items, {} failed",
+        report.deleted.len(),
+        report.failed.len()
+    );
+    report
+}
+
+/// Shred a file or remove a directory; false if there was nothing to delete
+fn remove_item(data_dir: &Path, spec: &ItemSpec) -> Result<bool> {
+    let path = item_path(data_dir, spec);
+    // Copies held in memory in no-traces mode go as well
+    let held = no_traces::forget_files_under(&path);
+    if !path.exists() {
+        return Ok(held);
+    }
+    if spec.name == "staging" {
+        // Only our own temp files; the directory may be user-chosen
+        cleanup_staging(&path)?;
+    } else if path.is_dir() {
+        fs::remove_dir_all(&path).with_context(|| format!("Failed to delete {}", spec.name))?;
+    } else {
+        shred_file(&path)?;
+    }
+    Ok(true)
+}
+
+fn item_path(data_dir: &Path, spec: &ItemSpec) -> PathBuf {
+    match spec.name {
+        "staging" => settings::drop_settings().staging_dir(data_dir),
+        name => data_dir.join(name),
+    }
+}
+
+/// Total size and file count of a directory tree
+fn dir_usage(dir: &Path) -> Result<(u64, u64)> {
+    let mut size = 0;
+    let mut files = 0;
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            let (dir_size, dir_files) = dir_usage(&entry.path())?;
+            size += dir_size;
+            files += dir_files;
+        } else {
+            size += metadata.len();
+            files += 1;
+        }
+    }
+    Ok((size, files))
+}
+
+fn unix_secs(time: std::time::SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
@@ -0,0 +1,377 @@
+use crate::crypto::Identity;
+use crate::daemon;
+use crate::events;
+use crate::p2p::{init_p2p_actor, P2PCommand, P2PEvents, ShutdownSignal};
+use crate::{clipboard, media_stream, settings, tray};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tauri::Manager;
+use tokio::sync::mpsc;
+use tracing::info;
+
+const MIN_IDLE_TIMEOUT_SECS: u64 = 60;
+const IDLE_CHECK_SECS: u64 = 15;
+/// Wall-clock time passing this much faster than the check interval means the machine slept
+const SUSPEND_GAP_SECS: u64 = 60;
+
+/// Notices the machine waking from sleep: monotonic timers stop while suspended,
+/// so the wall clock jumps ahead of a regular tick
+pub struct SuspendDetector {
+    last_tick: SystemTime,
+    interval: Duration,
+}
+
+impl SuspendDetector {
+    /// Call `tick` about every `interval`
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            last_tick: SystemTime::now(),
+            interval,
+        }
+    }
+
+    /// Whether the machine slept since the previous tick
+    pub fn tick(&mut self) -> bool {
+        let now = SystemTime::now();
+        let gap = now.duration_since(self.last_tick).unwrap_or_default();
+        self.last_tick = now;
+        gap > self.interval + Duration::from_secs(SUSPEND_GAP_SECS)
+    }
+}
+
+/// When the session locks itself
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionSettings {
+    pub auto_lock: bool,
+    pub idle_timeout_secs: u64,
+    pub lock_on_suspend: bool,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            auto_lock: true,
+            idle_timeout_secs: 900,
+            lock_on_suspend: true,
+        }
+    }
+}
+
+impl SessionSettings {
+    fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs.max(MIN_IDLE_TIMEOUT_SECS))
+    }
+}
+
+/// Where the app is in its lifecycle: Locked -> Unlocked -> GhostStarting -> GhostActive.
+/// Commands check this before touching keys or the P2P actor.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AppPhase {
+    Locked,
+    Unlocked,
+    GhostStarting,
+    GhostActive,
+}
+
+/// A command was called in a phase that doesn't allow it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    Locked,
+    GhostModeRunning,
+    GhostModeStarting,
+    GhostModeNotRunning,
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            StateError::Locked => "Session is locked",
+            StateError::GhostModeRunning => "Ghost Mode is already running",
+            StateError::GhostModeStarting => "Ghost Mode is still starting",
+            StateError::GhostModeNotRunning => "Ghost Mode is not running",
+        })
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl StateError {
+    /// Catalog ID of the user-facing message; `Display` stays English for logs
+    pub fn message_id(self) -> &'static str {
+        match self {
+            StateError::Locked => "error.session_locked",
+            StateError::GhostModeRunning => "error.ghost_mode_running",
+            StateError::GhostModeStarting => "error.ghost_mode_starting",
+            StateError::GhostModeNotRunning => "error.ghost_mode_not_running",
+        }
+    }
+}
+
+enum GhostMode {
+    Off,
+    /// An actor is being started; nobody else may start one meanwhile
+    Starting,
+    /// The signal stops the actor even when its command queue is full
+    Active(mpsc::Sender<P2PCommand>, ShutdownSignal),
+}
+
+/// Key material that only exists while the session is unlocked
+struct UnlockedSession {
+    identity: Identity,
+    ghost_mode: GhostMode,
+}
+
+impl UnlockedSession {
+    fn phase(&mut self) -> AppPhase {
+        match &self.ghost_mode {
+            GhostMode::Off => AppPhase::Unlocked,
+            GhostMode::Starting => AppPhase::GhostStarting,
+            // The actor or daemon connection went away on its own
+            GhostMode::Active(sender, _) if sender.is_closed() => {
+                self.ghost_mode = GhostMode::Off;
+                AppPhase::Unlocked
+            }
+            GhostMode::Active(..) => AppPhase::GhostActive,
+        }
+    }
+}
+
+/// Application state shared across commands
+pub struct AppState {
+    session: Mutex<Option<UnlockedSession>>,
+    last_activity: Mutex<Instant>,
+    /// Ghost Mode was running when the session locked
+    resume_ghost_mode: Mutex<bool>,
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        Self {
+            session: Mutex::new(None),
+            last_activity: Mutex::new(Instant::now()),
+            resume_ghost_mode: Mutex::new(false),
+        }
+    }
+
+    /// Record user activity, postponing the idle lock
+    pub fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_activity.lock().unwrap().elapsed()
+    }
+
+    pub fn phase(&self) -> AppPhase {
+        self.session
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map_or(AppPhase::Locked, UnlockedSession::phase)
+    }
+
+    /// Clone of the unlocked identity
+    pub fn identity(&self) -> Result<Identity, StateError> {
+        self.touch();
+        self.session
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|session| session.identity.clone())
+            .ok_or(StateError::Locked)
+    }
+
+    /// Like `identity`, for background tasks that shouldn't count as activity
+    pub fn peek_identity(&self) -> Option<Identity> {
+        self.session
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|session| session.identity.clone())
+    }
+
+    /// Handle to the running P2P actor
+    pub fn p2p_sender(&self) -> Result<mpsc::Sender<P2PCommand>, StateError> {
+        let mut session = self.session.lock().unwrap();
+        let session = session.as_mut().ok_or(StateError::Locked)?;
+        match (session.phase(), &session.ghost_mode) {
+            (AppPhase::GhostActive, GhostMode::Active(sender, _)) => Ok(sender.clone()),
+            (AppPhase::GhostStarting, _) => Err(StateError::GhostModeStarting),
+            _ => Err(StateError::GhostModeNotRunning),
+        }
+    }
+
+    /// Locked -> Unlocked. Switching identities while Ghost Mode runs would leave
+    /// the actor speaking for the old one, so that is refused.
+    pub fn unlock(&self, identity: Identity) -> Result<(), StateError> {
+        self.touch();
+        let mut session = self.session.lock().unwrap();
+        match session.as_mut() {
+            Some(session) => match session.phase() {
+                AppPhase::GhostActive => return Err(StateError::GhostModeRunning),
+                AppPhase::GhostStarting => return Err(StateError::GhostModeStarting),
+                _ => session.identity = identity,
+            },
+            None => {
+                *session = Some(UnlockedSession {
+                    identity,
+                    ghost_mode: GhostMode::Off,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Unlocked -> GhostStarting, returning the identity to start the actor with.
+    /// Follow with `finish_ghost_mode`.
+    pub fn begin_ghost_mode(&self) -> Result<Identity, StateError> {
+        self.touch();
+        let mut session = self.session.lock().unwrap();
+        let session = session.as_mut().ok_or(StateError::Locked)?;
+        match session.phase() {
+            AppPhase::GhostActive => Err(StateError::GhostModeRunning),
+            AppPhase::GhostStarting => Err(StateError::GhostModeStarting),
+            _ => {
+                session.ghost_mode = GhostMode::Starting;
+                Ok(session.identity.clone())
+            }
+        }
+    }
+
+    /// GhostStarting -> GhostActive, or back to Unlocked if starting failed.
+    /// If the session locked meanwhile the new actor is shut down again.
+    pub fn finish_ghost_mode(
+        &self,
+        started: Result<mpsc::Sender<P2PCommand>>,
+        shutdown: ShutdownSignal,
+    ) -> Result<()> {
+        let mut session = self.session.lock().unwrap();
+        let Some(session) = session
+            .as_mut()
+            .filter(|session| matches!(session.ghost_mode, GhostMode::Starting))
+        else {
+            shutdown.trigger();
+            return Err(StateError::Locked.into());
+        };
+
+        match started {
+            Ok(sender) => {
+                session.ghost_mode = GhostMode::Active(sender, shutdown);
+                Ok(())
+            }
+            Err(e) => {
+                session.ghost_mode = GhostMode::Off;
+                Err(e)
+            }
+        }
+    }
+
+    /// GhostActive -> Unlocked, handing back the actor to shut down
+    pub fn stop_ghost_mode(&self) -> Result<mpsc::Sender<P2PCommand>, StateError> {
+        let mut session = self.session.lock().unwrap();
+        let session = session.as_mut().ok_or(StateError::Locked)?;
+        match session.phase() {
+            AppPhase::GhostStarting => Err(StateError::GhostModeStarting),
+            AppPhase::GhostActive => {
+                match std::mem::replace(&mut session.ghost_mode, GhostMode::Off) {
+                    GhostMode::Active(sender, _) => Ok(sender),
+                    _ => unreachable!("phase is GhostActive"),
+                }
+            }
+            _ => Err(StateError::GhostModeNotRunning),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.session.lock().unwrap().is_none()
+    }
+
+    /// Whether Ghost Mode should restart after unlocking; cleared once read
+    pub fn take_resume_ghost_mode(&self) -> bool {
+        std::mem::take(&mut *self.resume_ghost_mode.lock().unwrap())
+    }
+
+    /// Any phase -> Locked: drop all key material and stop Ghost Mode.
+    /// Returns false if already locked.
+    pub fn lock(&self) -> bool {
+        let Some(session) = self.session.lock().unwrap().take() else {
+            return false;
+        };
+
+        if let GhostMode::Active(_, shutdown) = session.ghost_mode {
+            *self.resume_ghost_mode.lock().unwrap() = true;
+            // The actor holds its own identity copy; shutting it down drops that too
+            shutdown.trigger();
+        }
+        // `Identity` zeroizes its keys when dropped here
+        true
+    }
+}
+
+/// Lock the session and tell the frontend why (`idle`, `suspend`, `manual`
+/// or `canary`)
+pub fn lock_session(app: &tauri::AppHandle, reason: &str) {
+    let state = app.state::<AppState>();
+    if state.lock() {
+        info!("Session locked ({})", reason);
+        tray::ghost_mode_changed(app, false);
+        clipboard::clear_sensitive(app);
+        events::clear();
+        media_stream::close_all();
+        events::emit(app, "locked", reason);
+    }
+}
+
+/// Start Ghost Mode for the unlocked session, enforcing the phase transitions
+pub async fn start_ghost_mode(
+    state: &AppState,
+    events: Arc<dyn P2PEvents>,
+    data_dir: PathBuf,
+) -> Result<()> {
+    let identity = state.begin_ghost_mode()?;
+    let shutdown = ShutdownSignal::new();
+    let started = connect_ghost_mode(identity, events, data_dir, &shutdown).await;
+    state.finish_ghost_mode(started, shutdown)
+}
+
+/// Attach to the background daemon if one runs for this data directory,
+/// so closing the window leaves Ghost Mode online; otherwise run P2P in-process
+async fn connect_ghost_mode(
+    identity: Identity,
+    events: Arc<dyn P2PEvents>,
+    data_dir: PathBuf,
+    shutdown: &ShutdownSignal,
+) -> Result<mpsc::Sender<P2PCommand>> {
+    if let Some(sender) = daemon::connect(&data_dir, events.clone(), shutdown).await? {
+        return Ok(sender);
+    }
+    init_p2p_actor(identity, events, data_dir, shutdown)
+}
+
+/// Lock the session after the configured idle period, or when the machine
+/// wakes from sleep
+pub fn start_idle_watcher(app: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut suspend = SuspendDetector::new(Duration::from_secs(IDLE_CHECK_SECS));
+        loop {
+            tokio::time::sleep(Duration::from_secs(IDLE_CHECK_SECS)).await;
+            let woke = suspend.tick();
+
+            let settings = settings::session_settings();
+            let state = app.state::<AppState>();
+            if state.is_locked() {
+                continue;
+            }
+
+            if settings.lock_on_suspend && woke {
+                lock_session(&app, "suspend");
+            } else if settings.auto_lock && state.idle_for() >= settings.idle_timeout() {
+                lock_session(&app, "idle");
+            }
+        }
+    });
+}
@@ -0,0 +1,628 @@
+use crate::archive::RetentionSettings;
+use crate::away_ping::AwayPingSettings;
+use crate::backup::BackupSettings;
+use crate::canary::CanarySettings;
+use crate::crypto::SessionKey;
+use crate::dead_drop::{DropOptions, MAX_CHUNK_SIZE, MAX_IN_FLIGHT_BUFFERS, MIN_CHUNK_SIZE};
+use crate::i18n::{self, DEFAULT_LOCALE};
+use crate::ipfs::IpfsNetworkSettings;
+use crate::kubo::KuboSettings;
+use crate::location::LocationSettings;
+use crate::logging::{self, LogSettings};
+use crate::no_traces::{self, NoTracesSettings};
+use crate::notifications::NotificationSettings;
+use crate::p2p::P2PSettings;
+use crate::pin_health::PinHealthSettings;
+use crate::plugins::PluginSettings;
+use crate::recovery::RecoverySettings;
+use crate::remote_wipe::RemoteWipeSettings;
+use crate::search::SearchSettings;
+use crate::session::SessionSettings;
+use crate::staging::{create_staging_dir, default_staging_dir};
+use crate::sync::SyncSettings;
+use crate::transcript::ComplianceSettings;
+use crate::tray::TraySettings;
+use crate::trust::TrustSettings;
+use crate::updater::UpdateSettings;
+use crate::upload_schedule::UploadSchedule;
+use crate::usage::UsageSettings;
+use crate::watch_folder::WatchFolderSettings;
+use crate::webhooks::WebhookSettings;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::watch;
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+const SETTINGS_FILE: &str = "settings.enc";
+const SETTINGS_KEY_FILE: &str = "settings.key";
+const SETTINGS_VERSION: u32 = 1;
+const MAX_CONCURRENT_OPERATIONS: usize = 8;
+
+// Plain JSON files written by earlier versions, migrated on first load
+const LEGACY_DROP_SETTINGS_FILE: &str = "settings.json";
+const LEGACY_NETWORK_SETTINGS_FILE: &str = "network.json";
+const LEGACY_PIN_HEALTH_SETTINGS_FILE: &str = "pin_health.json";
+
+/// All application settings, stored encrypted in the app data directory
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AppSettings {
+    pub version: u32,
+    /// Language of backend messages, one of `i18n::locales()`
+    #[serde(default = "default_locale")]
+    pub locale: String,
+    #[serde(default)]
+    pub drop: DropSettings,
+    #[serde(default)]
+    pub network: IpfsNetworkSettings,
+    #[serde(default)]
+    pub kubo: KuboSettings,
+    #[serde(default)]
+    pub pin_health: PinHealthSettings,
+    #[serde(default)]
+    pub p2p: P2PSettings,
+    #[serde(default)]
+    pub logging: LogSettings,
+    #[serde(default)]
+    pub notifications: NotificationSettings,
+    #[serde(default)]
+    pub session: SessionSettings,
+    #[serde(default)]
+    pub tray: TraySettings,
+    #[serde(default)]
+    pub recovery: RecoverySettings,
+    #[serde(default)]
+    pub sync: SyncSettings,
+    #[serde(default)]
+    pub updates: UpdateSettings,
+    #[serde(default)]
+    pub usage: UsageSettings,
+    #[serde(default)]
+    pub watch_folder: WatchFolderSettings,
+    #[serde(default)]
+    pub backup: BackupSettings,
+    #[serde(default)]
+    pub away_ping: AwayPingSettings,
+    #[serde(default)]
+    pub plugins: PluginSettings,
+    #[serde(default)]
+    pub webhooks: WebhookSettings,
+    #[serde(default)]
+    pub compliance: ComplianceSettings,
+    #[serde(default)]
+    pub canary: CanarySettings,
+    #[serde(default)]
+    pub search: SearchSettings,
+    #[serde(default)]
+    pub retention: RetentionSettings,
+    #[serde(default)]
+    pub remote_wipe: RemoteWipeSettings,
+    #[serde(default)]
+    pub trust: TrustSettings,
+    #[serde(default)]
+    pub no_traces: NoTracesSettings,
+    #[serde(default)]
+    pub location: LocationSettings,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            locale: default_locale(),
+            drop: DropSettings::default(),
+            network: IpfsNetworkSettings::default(),
+            kubo: KuboSettings::default(),
+            pin_health: PinHealthSettings::default(),
+            p2p: P2PSettings::default(),
+            logging: LogSettings::default(),
+            notifications: NotificationSettings::default(),
+            session: SessionSettings::default(),
+            tray: TraySettings::default(),
+            recovery: RecoverySettings::default(),
+            sync: SyncSettings::default(),
+            updates: UpdateSettings::default(),
+            usage: UsageSettings::default(),
+            watch_folder: WatchFolderSettings::default(),
+            backup: BackupSettings::default(),
+            away_ping: AwayPingSettings::default(),
+            plugins: PluginSettings::default(),
+            webhooks: WebhookSettings::default(),
+            compliance: ComplianceSettings::default(),
+            canary: CanarySettings::default(),
+            search: SearchSettings::default(),
+            retention: RetentionSettings::default(),
+            remote_wipe: RemoteWipeSettings::default(),
+            trust: TrustSettings::default(),
+            no_traces: NoTracesSettings::default(),
+            location: LocationSettings::default(),
+        }
+    }
+}
+
+fn default_locale() -> String {
+    DEFAULT_LOCALE.to_string()
+}
+
+impl AppSettings {
+    /// Reject settings that can't be applied; drop tunables are clamped instead
+    pub fn validate(&self) -> Result<()> {
+        if !i18n::is_supported(&self.locale) {
+            anyhow::bail!("Unsupported locale: {}", self.locale);
+        }
+        self.network.validate()?;
+        self.kubo.validate().context("Invalid Kubo settings")?;
+        self.p2p.validate()?;
+        self.logging.validate()?;
+        self.notifications.validate()?;
+        self.updates.validate()?;
+        self.watch_folder
+            .validate()
+            .context("Invalid watch folder settings")?;
+        self.backup.validate().context("Invalid backup settings")?;
+        self.away_ping
+            .validate()
+            .context("Invalid away ping settings")?;
+        self.webhooks.validate()?;
+        self.compliance
+            .validate()
+            .context("Invalid compliance settings")?;
+        self.retention
+            .validate()
+            .context("Invalid retention settings")?;
+        self.remote_wipe
+            .validate()
+            .context("Invalid remote wipe settings")?;
+        self.trust.validate().context("Invalid trust settings")?;
+        self.no_traces
+            .validate()
+            .context("Invalid no-traces settings")?;
+        self.location
+            .validate()
+            .context("Invalid location settings")?;
+        if self.no_traces.enabled && self.compliance.enabled {
+            anyhow::bail!("Turn off the compliance hold first, it keeps transcripts on disk");
+        }
+        self.drop
+            .upload_schedule
+            .validate()
+            .context("Invalid upload schedule")?;
+
+        if let Some(staging_dir) = &self.drop.staging_dir {
+            if !staging_dir.is_absolute() {
+                anyhow::bail!("Staging directory must be an absolute path");
+            }
+            create_staging_dir(staging_dir).context("Staging directory is not usable")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Encrypted settings file plus a channel that notifies subscribers of changes.
+/// The key lives next to the file rather than under the identity because
+/// network settings (e.g. the Tor proxy) must apply before the identity is unlocked.
+///
+/// That makes the encryption obfuscation, not protection: anyone who can read
+/// the data directory can read `settings.key` and decrypt the settings (and the
+/// usage statistics, encrypted log and dead man's switches that share the key).
+/// It keeps casual readers and naive scans of the disk out; nothing that must
+/// stay secret from someone with file access belongs in the settings.
+struct SettingsStore {
+    path: PathBuf,
+    key: SessionKey,
+    sender: watch::Sender<AppSettings>,
+    /// Held across read, change and save so concurrent changes aren't lost
+    writer: Mutex<()>,
+}
+
+static STORE: OnceLock<SettingsStore> = OnceLock::new();
+/// Why `init` failed, if it did
+static LOAD_ERROR: OnceLock<String> = OnceLock::new();
+
+/// Load (or create) the settings store; call once at startup. If this
+/// fails, network traffic stays blocked (see `check_loaded`).
+pub fn init(data_dir: &Path) -> Result<()> {
+    let result = load(data_dir);
+    if let Err(e) = &result {
+        let _ = LOAD_ERROR.set(format!("{:#}", e));
+    }
+    result
+}
+
+fn load(data_dir: &Path) -> Result<()> {
+    fs::create_dir_all(data_dir).context("Failed to create data directory")?;
+    let key = load_or_create_key(data_dir)?;
+    let path = data_dir.join(SETTINGS_FILE);
+
+    let migrating = !path.exists();
+    let settings = if !migrating {
+        let encrypted = fs::read(&path).context("Failed to read settings")?;
+        let mut json = key
+            .decrypt_file(&encrypted)
+            .context("Failed to decrypt settings")?;
+        let settings = serde_json::from_slice::<AppSettings>(&json);
+        json.zeroize();
+        migrate(settings.context("Failed to parse settings")?)
+    } else {
+        migrate_legacy_files(data_dir)
+    };
+
+    write_settings(&path, &key, &settings)?;
+    if migrating {
+        remove_legacy_files(data_dir);
+    }
+    let (sender, _) = watch::channel(settings);
+    let store = SettingsStore {
+        path,
+        key,
+        sender,
+        writer: Mutex::new(()),
+    };
+
+    STORE
+        .set(store)
+        .map_err(|_| anyhow::anyhow!("Settings already initialized"))?;
+    Ok(())
+}
+
+/// Settings in effect, or defaults if the store could not be initialized.
+/// No-traces mode overrides what it must here, so no module can miss it.
+/// Defaults have no Tor proxy, so network code calls `check_loaded` first.
+pub fn current() -> AppSettings {
+    let mut settings = saved();
+    no_traces::enforce(&mut settings);
+    settings
+}
+
+/// Settings as the user saved them, without no-traces overrides; start from
+/// these when changing and saving settings
+pub fn saved() -> AppSettings {
+    STORE
+        .get()
+        .map(|store| store.sender.borrow().clone())
+        .unwrap_or_default()
+}
+
+/// Fails if the settings store could not be loaded. Running on defaults
+/// would send traffic past a configured Tor proxy, so anything that goes
+/// on the network checks this first.
+pub fn check_loaded() -> Result<()> {
+    match LOAD_ERROR.get() {
+        Some(e) if STORE.get().is_none() => {
+            anyhow::bail!(
+                "Settings could not be loaded, network access is blocked: {}",
+                e
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn locale() -> String {
+    current().locale
+}
+
+pub fn drop_settings() -> DropSettings {
+    current().drop
+}
+
+pub fn network_settings() -> IpfsNetworkSettings {
+    current().network
+}
+
+pub fn kubo_settings() -> KuboSettings {
+    current().kubo
+}
+
+pub fn pin_health_settings() -> PinHealthSettings {
+    current().pin_health
+}
+
+pub fn p2p_settings() -> P2PSettings {
+    current().p2p
+}
+
+pub fn log_settings() -> LogSettings {
+    current().logging
+}
+
+pub fn notification_settings() -> NotificationSettings {
+    current().notifications
+}
+
+pub fn session_settings() -> SessionSettings {
+    current().session
+}
+
+pub fn tray_settings() -> TraySettings {
+    current().tray
+}
+
+pub fn recovery_settings() -> RecoverySettings {
+    current().recovery
+}
+
+pub fn sync_settings() -> SyncSettings {
+    current().sync
+}
+
+pub fn update_settings() -> UpdateSettings {
+    current().updates
+}
+
+pub fn usage_settings() -> UsageSettings {
+    current().usage
+}
+
+pub fn watch_folder_settings() -> WatchFolderSettings {
+    current().watch_folder
+}
+
+pub fn backup_settings() -> BackupSettings {
+    current().backup
+}
+
+pub fn away_ping_settings() -> AwayPingSettings {
+    current().away_ping
+}
+
+pub fn plugin_settings() -> PluginSettings {
+    current().plugins
+}
+
+pub fn webhook_settings() -> WebhookSettings {
+    current().webhooks
+}
+
+pub fn compliance_settings() -> ComplianceSettings {
+    current().compliance
+}
+
+pub fn canary_settings() -> CanarySettings {
+    current().canary
+}
+
+pub fn search_settings() -> SearchSettings {
+    current().search
+}
+
+pub fn retention_settings() -> RetentionSettings {
+    current().retention
+}
+
+pub fn remote_wipe_settings() -> RemoteWipeSettings {
+    current().remote_wipe
+}
+
+pub fn trust_settings() -> TrustSettings {
+    current().trust
+}
+
+pub fn no_traces_settings() -> NoTracesSettings {
+    current().no_traces
+}
+
+pub fn location_settings() -> LocationSettings {
+    current().location
+}
+
+/// Key that encrypts the settings file, also used for the log file
+pub fn storage_key() -> Option<SessionKey> {
+    STORE.get().map(|store| store.key.clone())
+}
+
+/// Receive every settings change; `None` if the store is not initialized
+pub fn subscribe() -> Option<watch::Receiver<AppSettings>> {
+    STORE.get().map(|store| store.sender.subscribe())
+}
+
+/// Validate, save and publish new settings. Returns what was stored.
+pub fn update(settings: AppSettings) -> Result<AppSettings> {
+    let store = STORE.get().context("Settings store not initialized")?;
+    let _writer = store.writer.lock().unwrap();
+    store.replace(settings)
+}
+
+/// Apply a change to one section of the settings as saved
+pub fn modify(change: impl FnOnce(&mut AppSettings)) -> Result<AppSettings> {
+    let store = STORE.get().context("Settings store not initialized")?;
+    let _writer = store.writer.lock().unwrap();
+    let mut settings = store.sender.borrow().clone();
+    change(&mut settings);
+    store.replace(settings)
+}
+
+impl SettingsStore {
+    /// Save, then publish, so subscribers never see settings that aren't
+    /// on disk. Callers hold `writer`.
+    fn replace(&self, mut settings: AppSettings) -> Result<AppSettings> {
+        settings.version = SETTINGS_VERSION;
+        settings.drop = settings.drop.clamped();
+        settings.validate()?;
+
+        write_settings(&self.path, &self.key, &settings)?;
+        self.sender.send_replace(settings.clone());
+        logging::apply(&current().logging);
+
+        Ok(settings)
+    }
+}
+
+fn write_settings(path: &Path, key: &SessionKey, settings: &AppSettings) -> Result<()> {
+    let mut json = serde_json::to_vec(settings)?;
+    let encrypted = key.encrypt_file(&json);
+    json.zeroize();
+    fs::write(path, encrypted?).context("Failed to write settings")?;
+    Ok(())
+}
+
+fn load_or_create_key(data_dir: &Path) -> Result<SessionKey> {
+    let path = data_dir.join(SETTINGS_KEY_FILE);
+    if path.exists() {
+        let mut bytes = fs::read(&path).context("Failed to read settings key")?;
+        let key = SessionKey::from_bytes(&bytes);
+        bytes.zeroize();
+        return key;
+    }
+
+    let key = SessionKey::generate();
+    let mut bytes = key.as_bytes();
+    let written = fs::write(&path, bytes);
+    bytes.zeroize();
+    written.context("Failed to write settings key")?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+            .context("Failed to restrict settings key permissions")?;
+    }
+
+    Ok(key)
+}
+
+/// Bring settings from older versions up to date
+fn migrate(mut settings: AppSettings) -> AppSettings {
+    if settings.version > SETTINGS_VERSION {
+        warn!(
+            "Settings were written by a newer version ({}), unknown fields are ignored",
+            settings.version
+        );
+    }
+    settings.version = SETTINGS_VERSION;
+    settings.drop = settings.drop.clamped();
+    settings
+}
+
+/// Import the plain JSON settings files of earlier versions
+fn migrate_legacy_files(data_dir: &Path) -> AppSettings {
+    fn read_legacy<T: serde::de::DeserializeOwned>(path: &Path) -> Option<T> {
+        let json = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&json)
+            .map_err(|e| warn!("Ignoring unreadable {}: {}", path.display(), e))
+            .ok()
+    }
+
+    let mut settings = AppSettings::default();
+    let drop_path = data_dir.join(LEGACY_DROP_SETTINGS_FILE);
+    let network_path = data_dir.join(LEGACY_NETWORK_SETTINGS_FILE);
+    let pin_health_path = data_dir.join(LEGACY_PIN_HEALTH_SETTINGS_FILE);
+
+    if let Some(drop) = read_legacy::<DropSettings>(&drop_path) {
+        settings.drop = drop.clamped();
+    }
+    if let Some(network) = read_legacy(&network_path) {
+        settings.network = network;
+    }
+    if let Some(pin_health) = read_legacy(&pin_health_path) {
+        settings.pin_health = pin_health;
+    }
+
+    settings
+}
+
+/// Delete the legacy JSON files; only once their contents are saved
+fn remove_legacy_files(data_dir: &Path) {
+    for name in [
+        LEGACY_DROP_SETTINGS_FILE,
+        LEGACY_NETWORK_SETTINGS_FILE,
+        LEGACY_PIN_HEALTH_SETTINGS_FILE,
+    ] {
+        let path = data_dir.join(name);
+        if path.exists() {
+            info!("Migrated {} into encrypted settings", path.display());
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Tunables for drop streaming and uploads
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DropSettings {
+    pub chunk_size: usize,
+    pub in_flight_buffers: usize,
+    #[serde(default)]
+    pub upload_schedule: UploadSchedule,
+    /// User-chosen staging directory (e.g. on an encrypted volume)
+    #[serde(default)]
+    pub staging_dir: Option<PathBuf>,
+    /// Decrypt drops while downloading (needs no disk space for ciphertext)
+    #[serde(default)]
+    pub pipelined_retrieval: bool,
+    /// Map large files into memory when encrypting and decrypting; fastest on
+    /// local NVMe, but unsafe if another program truncates the file meanwhile
+    #[serde(default)]
+    pub mmap_io: bool,
+    /// Drop operations the operation manager runs at once
+    #[serde(default = "default_max_concurrent_operations")]
+    pub max_concurrent_operations: usize,
+    /// Refuse drops whose staged ciphertext would be larger than this
+    #[serde(default)]
+    pub max_staging_bytes: Option<u64>,
+}
+
+fn default_max_concurrent_operations() -> usize {
+    2
+}
+
+impl Default for DropSettings {
+    fn default() -> Self {
+        let defaults = DropOptions::default();
+        Self {
+            chunk_size: defaults.chunk_size,
+            in_flight_buffers: defaults.in_flight_buffers,
+            upload_schedule: UploadSchedule::default(),
+            staging_dir: None,
+            pipelined_retrieval: false,
+            mmap_io: false,
+            max_concurrent_operations: default_max_concurrent_operations(),
+            max_staging_bytes: None,
+        }
+    }
+}
+
+impl DropSettings {
+    /// Clamp values into safe bounds (1-16 MB chunks, 1-8 in-flight buffers,
+    /// 1-8 concurrent operations)
+    pub fn clamped(self) -> Self {
+        Self {
+            chunk_size: self.chunk_size.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE),
+            in_flight_buffers: self.in_flight_buffers.clamp(1, MAX_IN_FLIGHT_BUFFERS),
+            upload_schedule: self.upload_schedule,
+            staging_dir: self.staging_dir,
+            pipelined_retrieval: self.pipelined_retrieval,
+            mmap_io: self.mmap_io,
+            max_concurrent_operations: self
+                .max_concurrent_operations
+                .clamp(1, MAX_CONCURRENT_OPERATIONS),
+            max_staging_bytes: self.max_staging_bytes,
+        }
+    }
+
+    /// Staging directory in effect: the user's choice or the app data default
+    pub fn staging_dir(&self, data_dir: &Path) -> PathBuf {
+        self.staging_dir
+            .clone()
+            .unwrap_or_else(|| default_staging_dir(data_dir))
+    }
+
+    /// Streaming options for a drop operation using these settings
+    pub fn drop_options(&self, data_dir: &Path) -> DropOptions {
+        DropOptions {
+            chunk_size: self.chunk_size,
+            in_flight_buffers: self.in_flight_buffers,
+            upload_schedule: self.upload_schedule.clone(),
+            staging_dir: Some(self.staging_dir(data_dir)),
+            pipelined: self.pipelined_retrieval,
+            mmap: self.mmap_io,
+            staging_limit: self.max_staging_bytes,
+            ..DropOptions::default()
+        }
+    }
+}
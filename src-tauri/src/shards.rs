@@ -0,0 +1,370 @@
+use crate::crypto::{decode_public_key, seal_to, Identity, SessionKey};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use sharks::{Share, Sharks};
+use x25519_dalek::PublicKey;
+use zeroize::Zeroize;
+
+const SEALED_SHARD_PREFIX: &str = "sealed";
+const SHARD_ENVELOPE_PREFIX: &str = "shard1";
+const KEY_CHECK_LEN: usize = 8;
+
+/// A Shamir share with the metadata needed to validate a recovery:
+/// `shard1:<threshold>:<key_check>:<hex share>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardEnvelope {
+    pub threshold: u8,
+    pub key_check: String, // hex, identifies the drop without revealing the key
+    pub share: Vec<u8>,
+}
+
+impl ShardEnvelope {
+    pub fn encode(&self) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            SHARD_ENVELOPE_PREFIX,
+            self.threshold,
+            self.key_check,
+            hex::encode(&self.share)
+        )
+    }
+
+    /// Parse an envelope. Legacy bare-hex shards parse with threshold 0
+    /// and no key check.
+    pub fn parse(shard: &str) -> Result<Self> {
+        let shard = shard.trim();
+        let Some(rest) = shard
+            .strip_prefix(SHARD_ENVELOPE_PREFIX)
+            .and_then(|r| r.strip_prefix(':'))
+        else {
+            return Ok(Self {
+                threshold: 0,
+                key_check: String::new(),
+                share: hex::decode(shard).context("Invalid hex shard")?,
+            });
+        };
+
+        let mut parts = rest.splitn(3, ':');
+        let threshold = parts
+            .next()
+            .and_then(|t| t.parse().ok())
+            .context("Invalid threshold in shard")?;
+        let key_check = parts.next().context("Missing key check in shard")?;
+        let share = parts.next().context("Missing share in shard")?;
+
+        Ok(Self {
+            threshold,
+            key_check: key_check.to_string(),
+            share: hex::decode(share).context("Invalid hex shard")?,
+        })
+    }
+
+    /// Share index (the x coordinate)
+    fn index(&self) -> Option<u8> {
+        self.share.first().copied()
+    }
+}
+
+/// Short fingerprint of a session key used to tell drops apart
+fn key_check(key_bytes: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"deaddrop-key-check");
+    hasher.update(key_bytes);
+    hex::encode(&hasher.finalize()[..KEY_CHECK_LEN])
+}
+
+/// Split a session key into Shamir shard envelopes
+pub fn split_session_key(session_key: &SessionKey, threshold: u8, total_shards: u8) -> Vec<String> {
+    let mut key_bytes = session_key.as_bytes();
+    let check = key_check(&key_bytes);
+    let sharks = Sharks(threshold);
+    let dealer = sharks.dealer(&key_bytes);
+
+    let shares: Vec<Share> = dealer.take(total_shards as usize).collect();
+    key_bytes.zeroize();
+
+    shares
+        .iter()
+        .map(|share| {
+            ShardEnvelope {
+                threshold,
+                key_check: check.clone(),
+                // Serialize Share to bytes using Vec::from
+                share: Vec::from(share),
+            }
+            .encode()
+        })
+        .collect()
+}
+
+/// Recover a session key from shard envelopes, explaining what is wrong
+/// when the shards cannot work together
+pub fn recover_session_key(shard_strings: &[String]) -> Result<SessionKey> {
+    let envelopes = shard_strings
+        .iter()
+        .enumerate()
+        .map(|(i, s)| ShardEnvelope::parse(s).with_context(|| format!("Shard #{} is malformed", i + 1)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let first = envelopes.first().context("No shards provided")?;
+    let threshold = first.threshold;
+
+    for (i, envelope) in envelopes.iter().enumerate().skip(1) {
+        if envelope.key_check != first.key_check || envelope.threshold != threshold {
+            anyhow::bail!("Shard #{} belongs to a different drop than shard #1", i + 1);
+        }
+    }
+
+    for (i, envelope) in envelopes.iter().enumerate() {
+        if let Some(j) = envelopes[..i]
+            .iter()
+            .position(|other| other.index() == envelope.index())
+        {
+            anyhow::bail!("Shard #{} is a duplicate of shard #{}", i + 1, j + 1);
+        }
+    }
+
+    if threshold > 0 && envelopes.len() < threshold as usize {
+        anyhow::bail!("Need {} shards, got {}", threshold, envelopes.len());
+    }
+
+    let shares = envelopes
+        .iter()
+        .enumerate()
+        .map(|(i, e)| {
+            Share::try_from(e.share.as_slice())
+                .map_err(|err| anyhow::anyhow!("Shard #{} is invalid: {}", i + 1, err))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Legacy shards don't carry the threshold; assume every share is needed
+    let sharks = Sharks(if threshold > 0 { threshold } else { shares.len() as u8 });
+    let mut recovered_key_bytes = sharks
+        .recover(&shares)
+        .map_err(|e| anyhow::anyhow!("Failed to recover key: {}", e))?;
+
+    if recovered_key_bytes.len() != 32 {
+        recovered_key_bytes.zeroize();
+        anyhow::bail!("Invalid recovered key length");
+    }
+
+    if !first.key_check.is_empty() && key_check(&recovered_key_bytes) != first.key_check {
+        recovered_key_bytes.zeroize();
+        anyhow::bail!("Recovered key does not match the drop - a shard is corrupted");
+    }
+
+    // Create session key from recovered bytes
+    let session_key = SessionKey::from_bytes(&recovered_key_bytes);
+    recovered_key_bytes.zeroize();
+
+    session_key
+}
+
+/// Deal a fresh set of shards for the key behind `shard_strings`.
+/// The key itself is unchanged, so old shards stay valid among themselves;
+/// old and new shards come from different polynomials and cannot be mixed.
+pub fn reshare_session_key(
+    shard_strings: &[String],
+    new_threshold: u8,
+    new_total: u8,
+) -> Result<Vec<String>> {
+    if new_threshold > new_total {
+        anyhow::bail!("Threshold cannot exceed total shards");
+    }
+    if new_threshold < 2 {
+        anyhow::bail!("Threshold must be at least 2");
+    }
+
+    let session_key = recover_session_key(shard_strings)?;
+    Ok(split_session_key(&session_key, new_threshold, new_total))
+}
+
+/// A shard encrypted to one holder: `sealed:<recipient_id>:<ephemeral_key>:<hex>`
+pub struct SealedShard {
+    pub recipient_id: String,
+    pub ephemeral_public: PublicKey,
+    pub ciphertext: Vec<u8>,
+}
+
+impl SealedShard {
+    pub fn parse(shard: &str) -> Option<Result<Self>> {
+        let rest = shard.strip_prefix(SEALED_SHARD_PREFIX)?.strip_prefix(':')?;
+        Some(Self::parse_body(rest))
+    }
+
+    fn parse_body(rest: &str) -> Result<Self> {
+        let mut parts = rest.splitn(3, ':');
+        let recipient_id = parts.next().context("Missing recipient in sealed shard")?;
+        let ephemeral = parts.next().context("Missing ephemeral key in sealed shard")?;
+        let payload = parts.next().context("Missing payload in sealed shard")?;
+
+        Ok(Self {
+            recipient_id: recipient_id.to_string(),
+            ephemeral_public: decode_public_key(ephemeral)?,
+            ciphertext: hex::decode(payload).context("Invalid hex in sealed shard")?,
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            SEALED_SHARD_PREFIX,
+            self.recipient_id,
+            bs58::encode(self.ephemeral_public.as_bytes()).into_string(),
+            hex::encode(&self.ciphertext)
+        )
+    }
+
+    /// Decrypt with the local identity; fails unless we are the intended holder
+    pub fn open(&self, identity: &Identity) -> Result<String> {
+        if self.recipient_id != identity.public_id() {
+            anyhow::bail!("Shard is sealed to {}", self.recipient_id);
+        }
+        let plain = identity
+            .open_sealed(&self.ephemeral_public, &self.ciphertext)
+            .context("Failed to open sealed shard")?;
+        String::from_utf8(plain).context("Sealed shard is not valid text")
+    }
+}
+
+/// Seal each shard to the matching recipient public key (base58)
+pub fn seal_shards(shards: &[String], recipients: &[String]) -> Result<Vec<String>> {
+    if shards.len() != recipients.len() {
+        anyhow::bail!(
+            "Need one recipient per shard: {} shards, {} recipients",
+            shards.len(),
+            recipients.len()
+        );
+    }
+
+    shards
+        .iter()
+        .zip(recipients)
+        .map(|(shard, recipient_id)| {
+            let recipient = decode_public_key(recipient_id)
+                .with_context(|| format!("Invalid recipient key {}", recipient_id))?;
+            let (ephemeral_public, ciphertext) = seal_to(&recipient, shard.as_bytes())?;
+            Ok(SealedShard {
+                recipient_id: recipient_id.clone(),
+                ephemeral_public,
+                ciphertext,
+            }
+            .encode())
+        })
+        .collect()
+}
+
+/// Open any shards sealed to the local identity. Shards sealed to someone else
+/// are rejected - their holder must open them first with `open_sealed_shard`.
+pub fn open_sealed_shards(shards: Vec<String>, identity: Option<&Identity>) -> Result<Vec<String>> {
+    shards
+        .into_iter()
+        .map(|shard| match SealedShard::parse(&shard) {
+            None => Ok(shard),
+            Some(sealed) => {
+                let sealed = sealed?;
+                let identity = identity.context("Identity required to open sealed shards")?;
+                sealed.open(identity)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_recover_round_trip() {
+        let key = SessionKey::generate();
+        let shards = split_session_key(&key, 3, 5);
+
+        let recovered = recover_session_key(&shards[1..4]).unwrap();
+
+        assert_eq!(recovered.as_bytes(), key.as_bytes());
+    }
+
+    #[test]
+    fn test_recover_reports_missing_shards() {
+        let shards = split_session_key(&SessionKey::generate(), 3, 5);
+
+        let err = recover_session_key(&shards[..2]).unwrap_err();
+
+        assert_eq!(err.to_string(), "Need 3 shards, got 2");
+    }
+
+    #[test]
+    fn test_recover_reports_foreign_shard() {
+        let ours = split_session_key(&SessionKey::generate(), 2, 3);
+        let theirs = split_session_key(&SessionKey::generate(), 2, 3);
+
+        let err = recover_session_key(&[ours[0].clone(), theirs[1].clone()]).unwrap_err();
+
+        assert_eq!(err.to_string(), "Shard #2 belongs to a different drop than shard #1");
+    }
+
+    #[test]
+    fn test_recover_legacy_hex_shards() {
+        let key = SessionKey::generate();
+        let legacy: Vec<String> = split_session_key(&key, 2, 3)
+            .iter()
+            .map(|s| hex::encode(ShardEnvelope::parse(s).unwrap().share))
+            .collect();
+
+        let recovered = recover_session_key(&legacy[..2]).unwrap();
+
+        assert_eq!(recovered.as_bytes(), key.as_bytes());
+    }
+
+    #[test]
+    fn test_reshare_recovers_same_key() {
+        let key = SessionKey::generate();
+        let old = split_session_key(&key, 2, 3);
+        let new = reshare_session_key(&old[..2], 3, 5).unwrap();
+
+        assert_eq!(new.len(), 5);
+        let recovered = recover_session_key(&new[1..4]).unwrap();
+        assert_eq!(recovered.as_bytes(), key.as_bytes());
+    }
+
+    /// Shard-shaped strings: mostly well-formed prefixes with hostile bodies
+    fn shard_strategy() -> impl Strategy<Value = String> {
+        prop_oneof![
+            any::<String>(),
+            "[0-9a-f]{0,80}",
+            ("[0-9]{1,4}", "[0-9a-f]{0,20}", "[0-9a-fA-Fxz:]{0,80}")
+                .prop_map(|(t, c, s)| format!("{}:{}:{}:{}", SHARD_ENVELOPE_PREFIX, t, c, s)),
+            any::<String>().prop_map(|s| format!("{}:{}", SEALED_SHARD_PREFIX, s)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn parsing_arbitrary_shards_never_panics(shard in shard_strategy()) {
+            let _ = ShardEnvelope::parse(&shard);
+            let _ = SealedShard::parse(&shard);
+            let _ = open_sealed_shards(vec![shard], Some(&Identity::generate()));
+        }
+
+        #[test]
+        fn recovering_arbitrary_shards_never_panics(
+            shards in proptest::collection::vec(shard_strategy(), 0..6),
+        ) {
+            let _ = recover_session_key(&shards);
+        }
+
+        #[test]
+        fn corrupted_shard_is_rejected(index in 0usize..3, byte in 1usize..33, flip in 1u8..) {
+            let key = SessionKey::generate();
+            let mut envelopes: Vec<ShardEnvelope> = split_session_key(&key, 3, 3)
+                .iter()
+                .map(|s| ShardEnvelope::parse(s).unwrap())
+                .collect();
+            envelopes[index].share[byte] ^= flip;
+            let shards: Vec<String> = envelopes.iter().map(ShardEnvelope::encode).collect();
+
+            prop_assert!(recover_session_key(&shards).is_err());
+        }
+    }
+}
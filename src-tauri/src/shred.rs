@@ -0,0 +1,139 @@
+use anyhow::{Context, Result};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+const SHRED_BUFFER_SIZE: usize = 1024 * 1024;
+const HDD_PASSES: usize = 3; // random, random, zeros
+const SSD_PASSES: usize = 1; // extra passes only wear flash; TRIM does the rest
+
+/// Outcome of shredding a source file after drop creation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShredReport {
+    pub path: String,
+    pub bytes: u64,
+    pub passes: usize,
+    /// `Some(false)` for SSDs, `None` if the device type is unknown
+    pub rotational: Option<bool>,
+    pub deleted: bool,
+    pub warnings: Vec<String>,
+}
+
+impl ShredReport {
+    /// Report for a shred that could not complete; the drop itself is unaffected
+    pub fn failed(path: &Path, error: anyhow::Error) -> Self {
+        Self {
+            path: path.display().to_string(),
+            bytes: 0,
+            passes: 0,
+            rotational: None,
+            deleted: false,
+            warnings: vec![format!("Shredding failed: {}", error)],
+        }
+    }
+}
+
+/// Overwrite a file in place, truncate it and delete it (best effort).
+/// On SSDs and copy-on-write filesystems old blocks may survive overwriting;
+/// truncation lets the filesystem discard them when TRIM is enabled.
+pub fn shred_file(path: &Path) -> Result<ShredReport> {
+    let bytes = fs::metadata(path).context("Failed to read source file metadata")?.len();
+    let rotational = is_rotational(path);
+    let passes = match rotational {
+        Some(false) => SSD_PASSES,
+        _ => HDD_PASSES,
+    };
+
+    let mut warnings = Vec::new();
+    if rotational != Some(true) {
+        warnings.push(
+            "Overwriting is not reliable on SSDs or copy-on-write filesystems; \
+             use full-disk encryption to protect deleted plaintext"
+                .to_string(),
+        );
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .context("Failed to open source file for shredding")?;
+
+    let mut buffer = vec![0u8; SHRED_BUFFER_SIZE];
+    for pass in 0..passes {
+        let last_pass = pass + 1 == passes && passes > 1;
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut remaining = bytes;
+        while remaining > 0 {
+            let len = remaining.min(buffer.len() as u64) as usize;
+            if last_pass {
+                buffer[..len].fill(0);
+            } else {
+                rand::thread_rng().fill_bytes(&mut buffer[..len]);
+            }
+            file.write_all(&buffer[..len]).context("Failed to overwrite source file")?;
+            remaining -= len as u64;
+        }
+
+        // Force each pass to disk so it isn't coalesced in the page cache
+        file.sync_all().context("Failed to sync source file")?;
+    }
+
+    // Release the blocks so the filesystem can TRIM them
+    file.set_len(0).context("Failed to truncate source file")?;
+    file.sync_all()?;
+    drop(file);
+
+    // Rename before unlinking so the original name doesn't linger in the directory
+    let renamed = obscured_path(path);
+    let target = match fs::rename(path, &renamed) {
+        Ok(()) => renamed,
+        Err(e) => {
+            warnings.push(format!("Could not rename before deletion: {}", e));
+            path.to_path_buf()
+        }
+    };
+    fs::remove_file(&target).context("Failed to delete source file")?;
+
+    info!("Shredded {} ({} bytes, {} passes)", path.display(), bytes, passes);
+
+    Ok(ShredReport {
+        path: path.display().to_string(),
+        bytes,
+        passes,
+        rotational,
+        deleted: true,
+        warnings,
+    })
+}
+
+fn obscured_path(path: &Path) -> PathBuf {
+    let mut name = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut name);
+    path.with_file_name(hex::encode(name))
+}
+
+/// Whether the file lives on a spinning disk (Linux only)
+#[cfg(target_os = "linux")]
+fn is_rotational(path: &Path) -> Option<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dev = fs::metadata(path).ok()?.dev();
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & 0xffff_f000);
+    let minor = (dev & 0xff) | ((dev >> 12) & 0xffff_ff00);
+    let device = PathBuf::from(format!("/sys/dev/block/{}:{}", major, minor));
+
+    // Partitions keep the queue attributes on their parent device
+    [device.join("queue/rotational"), device.join("../queue/rotational")]
+        .iter()
+        .find_map(|p| fs::read_to_string(p).ok())
+        .map(|value| value.trim() == "1")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_rotational(_path: &Path) -> Option<bool> {
+    None
+}
@@ -0,0 +1,68 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+use tracing::{error, info};
+
+const STAGING_DIR: &str = "staging";
+const STAGING_PREFIX: &str = "drop-";
+
+/// Default staging directory for drop temp files, inside the app data directory
+pub fn default_staging_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join(STAGING_DIR)
+}
+
+/// Create the staging directory, readable only by the current user
+pub fn create_staging_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).context("Failed to create staging directory")?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+            .context("Failed to restrict staging directory permissions")?;
+    }
+
+    Ok(())
+}
+
+/// Create a temp file for drop staging; removed when dropped, and by
+/// `cleanup_staging` on the next start if the process crashes first
+pub fn staging_file(dir: Option<&Path>) -> Result<NamedTempFile> {
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(STAGING_PREFIX);
+
+    match dir {
+        Some(dir) => {
+            create_staging_dir(dir)?;
+            builder.tempfile_in(dir)
+        }
+        None => builder.tempfile(),
+    }
+    .context("Failed to create temp file")
+}
+
+/// Remove staging files left behind by a crash. Returns how many were removed.
+pub fn cleanup_staging(dir: &Path) -> Result<usize> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+
+    let mut removed = 0;
+    for entry in fs::read_dir(dir).context("Failed to read staging directory")? {
+        let entry = entry?;
+        let is_staging_file = entry.file_name().to_string_lossy().starts_with(STAGING_PREFIX);
+        if is_staging_file && entry.file_type()?.is_file() {
+            match fs::remove_file(entry.path()) {
+                Ok(()) => removed += 1,
+                Err(e) => error!("Failed to remove stale staging file {}: {}", entry.path().display(), e),
+            }
+        }
+    }
+
+    if removed > 0 {
+        info!("Removed {} stale staging files from {}", removed, dir.display());
+    }
+
+    Ok(removed)
+}
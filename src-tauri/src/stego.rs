@@ -0,0 +1,289 @@
+use crate::crypto::SessionKey;
+use crate::dead_drop::{
+    download_file_from_ipfs, is_upload_pending, stream_decrypt_file, stream_encrypt_file,
+    upload_ciphertext, DeadDropCreated, DropOptions,
+};
+use crate::shards::{recover_session_key, split_session_key};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tracing::info;
+
+/// Drop references whose ciphertext hides behind a carrier image carry this
+/// prefix; the CID after it is the image itself
+pub const STEGO_CID_PREFIX: &str = "sg:";
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+const JPEG_EOI: u8 = 0xD9;
+const JPEG_SOS: u8 = 0xDA;
+/// Carriers are ordinary pictures; anything bigger is likely not one
+const MAX_CARRIER_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Strip the carrier prefix from a drop reference, if present
+pub fn stego_cid(cid: &str) -> Option<&str> {
+    cid.strip_prefix(STEGO_CID_PREFIX)
+}
+
+/// Offset just past the end of the PNG or JPEG at the start of `reader`.
+/// Image viewers stop there, so whatever follows is never shown.
+pub fn carrier_end<R: Read>(reader: R) -> Result<u64> {
+    let mut reader = CountingReader {
+        inner: reader,
+        read: 0,
+    };
+    let mut magic = [0u8; 2];
+    reader.read_exact(&mut magic).context("Carrier is empty")?;
+    if magic == JPEG_SOI {
+        jpeg_end(&mut reader)?;
+    } else {
+        let mut rest = [0u8; 6];
+        reader
+            .read_exact(&mut rest)
+            .context("Carrier is not an image")?;
+        if [&magic[..], &rest[..]].concat() != PNG_SIGNATURE {
+            anyhow::bail!("Carrier must be a PNG or JPEG image");
+        }
+        png_end(&mut reader)?;
+    }
+    Ok(reader.read)
+}
+
+/// Walk PNG chunks up to and including IEND
+fn png_end<R: Read>(reader: &mut CountingReader<R>) -> Result<()> {
+    loop {
+        let mut header = [0u8; 8];
+        reader
+            .read_exact(&mut header)
+            .context("PNG carrier ends before IEND")?;
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as u64;
+        // Chunk data and CRC
+        skip(reader, len + 4).context("PNG carrier is truncated")?;
+        if &header[4..] == b"IEND" {
+            return Ok(());
+        }
+    }
+}
+
+/// Walk JPEG segments up to and including EOI, scanning entropy-coded data
+/// after each SOS for the next marker
+fn jpeg_end<R: Read>(reader: &mut CountingReader<R>) -> Result<()> {
+    let mut marker = next_marker(reader)?;
+    loop {
+        match marker {
+            JPEG_EOI => return Ok(()),
+            // Standalone markers carry no length
+            0x01 | 0xD0..=0xD7 => marker = next_marker(reader)?,
+            _ => {
+                let mut len = [0u8; 2];
+                reader
+                    .read_exact(&mut len)
+                    .context("JPEG carrier is truncated")?;
+                let len = u16::from_be_bytes(len) as u64;
+                if len < 2 {
+                    anyhow::bail!("Invalid JPEG segment length");
+                }
+                skip(reader, len - 2).context("JPEG carrier is truncated")?;
+                marker = if marker == JPEG_SOS {
+                    scan_entropy_data(reader)?
+                } else {
+                    next_marker(reader)?
+                };
+            }
+        }
+    }
+}
+
+fn next_marker<R: Read>(reader: &mut CountingReader<R>) -> Result<u8> {
+    let mut byte = [0u8; 1];
+    reader
+        .read_exact(&mut byte)
+        .context("JPEG carrier ends before EOI")?;
+    if byte[0] != 0xFF {
+        anyhow::bail!("Invalid JPEG marker");
+    }
+    // Any number of fill bytes may precede a marker
+    while byte[0] == 0xFF {
+        reader
+            .read_exact(&mut byte)
+            .context("JPEG carrier ends before EOI")?;
+    }
+    Ok(byte[0])
+}
+
+/// Skip entropy-coded data; stuffed zeros and restart markers belong to it
+fn scan_entropy_data<R: Read>(reader: &mut CountingReader<R>) -> Result<u8> {
+    let mut byte = [0u8; 1];
+    loop {
+        reader
+            .read_exact(&mut byte)
+            .context("JPEG carrier ends before EOI")?;
+        if byte[0] != 0xFF {
+            continue;
+        }
+        while byte[0] == 0xFF {
+            reader
+                .read_exact(&mut byte)
+                .context("JPEG carrier ends before EOI")?;
+        }
+        match byte[0] {
+            0x00 | 0xD0..=0xD7 => continue,
+            marker => return Ok(marker),
+        }
+    }
+}
+
+fn skip<R: Read>(reader: &mut R, len: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(len), &mut io::sink())?;
+    if skipped < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+struct CountingReader<R> {
+    inner: R,
+    read: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+/// Create a drop whose ciphertext is appended to a carrier image. The
+/// uploaded file still opens as that picture; anything the carrier already
+/// had after its end is dropped.
+pub async fn create_stego_drop(
+    file_path: &str,
+    carrier_path: &str,
+    threshold: u8,
+    total_shards: u8,
+    options: &DropOptions,
+) -> Result<DeadDropCreated> {
+    if threshold > total_shards {
+        anyhow::bail!("Threshold cannot exceed total shards");
+    }
+    if threshold < 2 {
+        anyhow::bail!("Threshold must be at least 2");
+    }
+    let carrier_size = std::fs::metadata(carrier_path)
+        .context("Failed to read carrier image")?
+        .len();
+    if carrier_size > MAX_CARRIER_BYTES {
+        anyhow::bail!("Carrier image is too large");
+    }
+    let carrier_len = carrier_end(BufReader::new(
+        File::open(carrier_path).context("Failed to open carrier image")?,
+    ))?;
+
+    let session_key = SessionKey::generate();
+    let ciphertext = options.staging_file()?;
+    stream_encrypt_file(file_path, ciphertext.path(), &session_key, options)
+        .context("Failed to encrypt file")?;
+
+    let envelope = options.staging_file()?;
+    {
+        let mut output = BufWriter::new(File::create(envelope.path())?);
+        let mut carrier = File::open(carrier_path)?.take(carrier_len);
+        io::copy(&mut carrier, &mut output)?;
+        io::copy(&mut File::open(ciphertext.path())?, &mut output)?;
+        output.flush()?;
+    }
+    drop(ciphertext);
+    info!("Hid drop ciphertext behind a {} byte carrier", carrier_len);
+
+    let cid = upload_ciphertext(envelope, options).await?;
+    let shards = split_session_key(&session_key, threshold, total_shards);
+    drop(session_key);
+
+    Ok(DeadDropCreated {
+        pending: is_upload_pending(options, &cid),
+        cid: format!("{}{}", STEGO_CID_PREFIX, cid),
+        shards,
+        drop_id: None,
+        piece_cids: Vec::new(),
+        shred: None,
+        cipher: Some(options.cipher),
+    })
+}
+
+/// Retrieve a drop hidden behind a carrier image: download the image, cut the
+/// ciphertext from its end and decrypt it
+pub async fn retrieve_stego_drop(
+    cid: &str,
+    shard_strings: Vec<String>,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<()> {
+    let session_key = recover_session_key(&shard_strings)?;
+    let envelope = options.staging_file()?;
+    download_file_from_ipfs(cid, envelope.path(), options).await?;
+
+    let ciphertext = options.staging_file()?;
+    extract_payload(envelope.path(), ciphertext.path())?;
+    drop(envelope);
+
+    stream_decrypt_file(ciphertext.path(), output_path, &session_key, options)
+        .context("Failed to decrypt file")?;
+    info!("Extracted and decrypted hidden drop {}", cid);
+    Ok(())
+}
+
+/// Copy everything after the carrier image to `output`
+fn extract_payload(envelope: &Path, output: &Path) -> Result<u64> {
+    let mut file = File::open(envelope).context("Failed to open carrier")?;
+    let offset = carrier_end(BufReader::new(&mut file))?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut writer = BufWriter::new(File::create(output)?);
+    let copied = io::copy(&mut file, &mut writer)?;
+    writer.flush()?;
+    if copied == 0 {
+        anyhow::bail!("No drop is hidden in this image");
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png() -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        for (kind, data) in [
+            (&b"IHDR"[..], &[0u8; 13][..]),
+            (b"IDAT", &[1, 2, 3]),
+            (b"IEND", &[]),
+        ] {
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            png.extend_from_slice(kind);
+            png.extend_from_slice(data);
+            png.extend_from_slice(&[0; 4]); // CRC is not checked
+        }
+        png
+    }
+
+    fn jpeg() -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        // APP0 segment, then a scan with a stuffed 0xFF and a restart marker
+        jpeg.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x04, 0xAA, 0xBB]);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x03, 0x01]);
+        jpeg.extend_from_slice(&[0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56]);
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn finds_the_end_of_the_carrier() {
+        for carrier in [png(), jpeg()] {
+            let mut envelope = carrier.clone();
+            envelope.extend_from_slice(b"\xFF\xD9ciphertext");
+            assert_eq!(carrier_end(&envelope[..]).unwrap(), carrier.len() as u64);
+        }
+        assert!(carrier_end(&b"plain text"[..]).is_err());
+        assert!(carrier_end(&png()[..20]).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::dead_drop::drop_cache_dir;
+use crate::settings;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::{error, info, warn};
+
+/// Records a user-chosen data directory; always kept in the default directory
+const LOCATION_FILE: &str = "storage.json";
+const IDENTITY_FILE: &str = "identity.enc";
+
+/// Everything the app keeps in its data directory, moved as a unit on migration
+const DATA_ENTRIES: &[&str] = &[
+    "identity.enc",
+    "burn_keys.enc",
+    "archives.enc",
+    "attestations.enc",
+    "canaries.enc",
+    "backups.enc",
+    "contacts.enc",
+    "conversations.enc",
+    "dead_man.enc",
+    "devices.enc",
+    "escrow.enc",
+    "groups.enc",
+    "introductions.enc",
+    "key_log.enc",
+    "knocks.enc",
+    "mailbox.enc",
+    "mesh.enc",
+    "nostr.enc",
+    "polls.enc",
+    "proofs.enc",
+    "remote_wipe.enc",
+    "sync.enc",
+    "transcripts.enc",
+    "usage.enc",
+    "versions.enc",
+    "view_once.enc",
+    "watch_folder.enc",
+    "drops.json",
+    "pending_uploads.json",
+    "drop_audit.enc",
+    "settings.enc",
+    "settings.key",
+    "settings.json",
+    "network.json",
+    "pin_health.json",
+    "recovery.json",
+    "drop_cache",
+    "staging",
+    "kubo",
+    "logs",
+    "plugins",
+    "view_once",
+    "search",
+    "archives",
+];
+
+/// What earlier versions left in the shared, unscoped data directory. Only
+/// names Control is known to own; generic ones like `settings.json` or `logs`
+/// there may belong to another app.
+const UNSCOPED_ENTRIES: &[&str] = &[
+    "identity.enc",
+    "burn_keys.enc",
+    "versions.enc",
+    "settings.enc",
+    "settings.key",
+    "drop_cache",
+];
+
+/// Where the app stores its files, for display in the settings screen
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoragePaths {
+    pub data_dir: PathBuf,
+    pub default_data_dir: PathBuf,
+    pub custom_data_dir: bool,
+    /// Data directory the app will move to on the next start
+    pub pending_data_dir: Option<PathBuf>,
+    pub drop_cache_dir: PathBuf,
+    pub staging_dir: PathBuf,
+}
+
+/// Contents of `storage.json`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct StorageLocation {
+    data_dir: Option<PathBuf>,
+    #[serde(default)]
+    pending_data_dir: Option<PathBuf>,
+}
+
+impl StorageLocation {
+    fn load(default_dir: &Path) -> Result<Self> {
+        let path = default_dir.join(LOCATION_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(&path).context("Failed to read storage location")?;
+        serde_json::from_str(&json).context("Failed to parse storage location")
+    }
+
+    fn save(&self, default_dir: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(default_dir.join(LOCATION_FILE), json).context("Failed to write storage location")?;
+        Ok(())
+    }
+}
+
+struct Storage {
+    default_dir: PathBuf,
+    data_dir: PathBuf,
+}
+
+static STORAGE: OnceLock<Storage> = OnceLock::new();
+
+/// Resolve the data directory from the app config (its bundle identifier),
+/// finishing a pending move to a user-chosen directory. Call once at startup,
+/// before anything opens files in the data directory.
+pub fn init(config: &tauri::Config) -> Result<PathBuf> {
+    let default_dir = tauri::api::path::app_data_dir(config)
+        .context("Failed to resolve app data directory")?;
+    fs::create_dir_all(&default_dir).context("Failed to create app data directory")?;
+
+    migrate_unscoped_dir(&default_dir);
+
+    let mut location = StorageLocation::load(&default_dir)?;
+    let current_dir = location.data_dir.clone().unwrap_or_else(|| default_dir.clone());
+
+    if let Some(target) = location.pending_data_dir.take() {
+        match copy_data(&current_dir, &target, DATA_ENTRIES) {
+            Ok(copied) => {
+                location.data_dir = (target != default_dir).then(|| target.clone());
+                if let Err(e) = location.save(&default_dir) {
+                    remove_entries(&target, &copied);
+                    return Err(e);
+                }
+                remove_entries(&current_dir, &copied);
+                info!("Moved app data from {} to {}", current_dir.display(), target.display());
+            }
+            Err(e) => {
+                error!("Failed to move app data to {}: {}", target.display(), e);
+                location.save(&default_dir)?;
+            }
+        }
+    }
+
+    let data_dir = location.data_dir.unwrap_or_else(|| default_dir.clone());
+    fs::create_dir_all(&data_dir).context("Failed to create data directory")?;
+
+    STORAGE
+        .set(Storage {
+            default_dir,
+            data_dir: data_dir.clone(),
+        })
+        .map_err(|_| anyhow::anyhow!("Storage already initialized"))?;
+    Ok(data_dir)
+}
+
+/// Data directory in effect for this run
+pub fn data_dir() -> Result<PathBuf> {
+    STORAGE
+        .get()
+        .map(|storage| storage.data_dir.clone())
+        .context("Storage not initialized")
+}
+
+/// Current storage locations
+pub fn paths() -> Result<StoragePaths> {
+    let storage = STORAGE.get().context("Storage not initialized")?;
+    let location = StorageLocation::load(&storage.default_dir)?;
+
+    Ok(StoragePaths {
+        data_dir: storage.data_dir.clone(),
+        default_data_dir: storage.default_dir.clone(),
+        custom_data_dir: storage.data_dir != storage.default_dir,
+        pending_data_dir: location.pending_data_dir,
+        drop_cache_dir: drop_cache_dir(&storage.data_dir),
+        staging_dir: settings::drop_settings().staging_dir(&storage.data_dir),
+    })
+}
+
+/// Schedule a move of all app data to `target` (or back to the default
+/// directory for `None`). Files are moved on the next start, while nothing
+/// has them open.
+pub fn set_data_dir(target: Option<PathBuf>) -> Result<StoragePaths> {
+    let storage = STORAGE.get().context("Storage not initialized")?;
+    let target = target.unwrap_or_else(|| storage.default_dir.clone());
+
+    let mut location = StorageLocation::load(&storage.default_dir)?;
+    if target == storage.data_dir {
+        location.pending_data_dir = None;
+    } else {
+        check_target(&storage.data_dir, &target)?;
+        location.pending_data_dir = Some(target);
+    }
+    location.save(&storage.default_dir)?;
+
+    paths()
+}
+
+/// Reject data directories that can't hold the app data
+fn check_target(current: &Path, target: &Path) -> Result<()> {
+    if !target.is_absolute() {
+        anyhow::bail!("Data directory must be an absolute path");
+    }
+    if target.starts_with(current) || current.starts_with(target) {
+        anyhow::bail!("Data directory can't be inside the current one or contain it");
+    }
+    if target.join(IDENTITY_FILE).exists() {
+        anyhow::bail!("{} already contains an identity", target.display());
+    }
+
+    fs::create_dir_all(target).context("Failed to create data directory")?;
+    tempfile::tempfile_in(target).context("Data directory is not writable")?;
+    Ok(())
+}
+
+/// Copy `entries` from `from` to `to` and return the names copied. Entries
+/// already present in `to` are left alone so nothing there is overwritten.
+/// On failure the copies made so far are removed again, so the sources stay
+/// the only data. Callers delete the sources once they've switched over.
+fn copy_data(from: &Path, to: &Path, entries: &[&'static str]) -> Result<Vec<&'static str>> {
+    fs::create_dir_all(to).context("Failed to create data directory")?;
+
+    let mut copied = Vec::new();
+    for &name in entries {
+        let source = from.join(name);
+        let dest = to.join(name);
+        if !source.exists() {
+            continue;
+        }
+        if dest.exists() {
+            warn!("Not moving {}: {} already exists", source.display(), dest.display());
+            continue;
+        }
+        if let Err(e) = copy_entry(&source, &dest) {
+            remove_entries(to, &copied);
+            remove_entry(&dest);
+            return Err(e).with_context(|| format!("Failed to copy {}", source.display()));
+        }
+        copied.push(name);
+    }
+
+    Ok(copied)
+}
+
+fn remove_entries(dir: &Path, names: &[&str]) {
+    for name in names {
+        remove_entry(&dir.join(name));
+    }
+}
+
+fn remove_entry(path: &Path) {
+    let removed = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else if path.exists() {
+        fs::remove_file(path)
+    } else {
+        Ok(())
+    };
+    if let Err(e) = removed {
+        warn!("Failed to remove {}: {}", path.display(), e);
+    }
+}
+
+fn copy_entry(source: &Path, dest: &Path) -> Result<()> {
+    if source.is_dir() {
+        fs::create_dir_all(dest)?;
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            copy_entry(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(source, dest)?;
+        fs::File::open(dest)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Earlier versions resolved the data directory without the app identifier,
+/// which put files directly in the shared per-user data directory. Move the
+/// ones Control owns into the app's own directory on first start.
+fn migrate_unscoped_dir(default_dir: &Path) {
+    let Some(unscoped_dir) = tauri::api::path::app_data_dir(&tauri::Config::default()) else {
+        return;
+    };
+    if unscoped_dir == default_dir
+        || !unscoped_dir.join(IDENTITY_FILE).exists()
+        || default_dir.join(IDENTITY_FILE).exists()
+    {
+        return;
+    }
+
+    match copy_data(&unscoped_dir, default_dir, UNSCOPED_ENTRIES) {
+        Ok(copied) => {
+            remove_entries(&unscoped_dir, &copied);
+            info!(
+                "Moved app data from {} to {}",
+                unscoped_dir.display(),
+                default_dir.display()
+            );
+        }
+        Err(e) => error!("Failed to move app data out of {}: {}", unscoped_dir.display(), e),
+    }
+}
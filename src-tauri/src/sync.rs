@@ -0,0 +1,560 @@
+use crate::catalog::now_secs;
+use crate::contacts::ContactBook;
+use crate::crypto::{Identity, SessionKey};
+use crate::dead_drop::{cat_bytes_from_ipfs, upload_bytes_to_ipfs};
+use crate::devices::{DeviceStatus, DeviceStore, SignedDeviceList};
+use crate::events;
+use crate::ipfs;
+use crate::no_traces;
+use crate::session::AppState;
+use crate::settings::{self, AppSettings};
+use anyhow::{Context, Result};
+use libp2p::identity::Keypair;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::Manager;
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+const SYNC_STATE_FILE: &str = "sync.enc";
+const SYNC_KEY_NAME: &str = "control-sync";
+const IPNS_LIFETIME: &str = "8760h"; // 1 year
+const SNAPSHOT_VERSION: u32 = 2;
+const MIN_SYNC_INTERVAL_SECS: u64 = 60;
+
+const CONTACT_PREFIX: &str = "contact:";
+const SETTINGS_PREFIX: &str = "settings.";
+// Device-specific sections (paths, proxies, ports, logging) stay local
+const SYNCED_SETTINGS: &[&str] = &["notifications", "session", "tray", "recovery"];
+
+/// Only one sync runs at a time, whether from the worker or `sync_now`
+static SYNC_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Background sync of contacts and settings between devices sharing an identity
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncSettings {
+    pub enabled: bool,
+    pub interval_secs: u64,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 900,
+        }
+    }
+}
+
+impl SyncSettings {
+    fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(MIN_SYNC_INTERVAL_SECS))
+    }
+}
+
+/// Per-device write counters
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct VectorClock(BTreeMap<String, u64>);
+
+impl VectorClock {
+    pub fn increment(&mut self, device_id: &str) {
+        *self.0.entry(device_id.to_string()).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (device, &count) in &other.0 {
+            let entry = self.0.entry(device.clone()).or_default();
+            *entry = (*entry).max(count);
+        }
+    }
+
+    /// Causal order of two clocks; `None` if the writes were concurrent
+    pub fn compare(&self, other: &Self) -> Option<Ordering> {
+        let devices = self.0.keys().chain(other.0.keys());
+        let mut order = Ordering::Equal;
+        for device in devices {
+            let ours = self.0.get(device).copied().unwrap_or(0);
+            let theirs = other.0.get(device).copied().unwrap_or(0);
+            match (order, ours.cmp(&theirs)) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, next) => order = next,
+                (current, next) if current != next => return None,
+                _ => {}
+            }
+        }
+        Some(order)
+    }
+}
+
+/// Latest known value of one synced item; `null` marks a deleted contact
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct SyncEntry {
+    value: Value,
+    clock: VectorClock,
+    updated_at: u64,
+    device_id: String,
+}
+
+impl SyncEntry {
+    /// Pick between two versions of an item: causal order first, the most
+    /// recent write on concurrent edits. Returns the winner and whether the
+    /// edits conflicted.
+    fn resolve(local: &Self, remote: &Self) -> (Self, bool) {
+        match local.clock.compare(&remote.clock) {
+            Some(Ordering::Less) => (remote.clone(), false),
+            Some(_) => (local.clone(), false),
+            None => {
+                let remote_wins =
+                    (remote.updated_at, &remote.device_id) > (local.updated_at, &local.device_id);
+                let mut winner = if remote_wins { remote } else { local }.clone();
+                winner.clock.merge(&local.clock);
+                winner.clock.merge(&remote.clock);
+                (winner, true)
+            }
+        }
+    }
+}
+
+/// Snapshot published under the sync IPNS name, encrypted with the identity
+#[derive(Serialize, Deserialize)]
+struct SyncSnapshot {
+    version: u32,
+    device_id: String,
+    written_at: u64,
+    /// Entries in the clear, as written before device lists
+    #[serde(default)]
+    entries: BTreeMap<String, SyncEntry>,
+    /// Entries sealed with the sync key of `epoch`, hex
+    #[serde(default)]
+    sealed: Option<String>,
+    #[serde(default)]
+    epoch: u64,
+    #[serde(default)]
+    devices: Option<SignedDeviceList>,
+}
+
+impl SyncSnapshot {
+    /// The entries, or `None` if they are sealed with a key of an older epoch
+    fn open(
+        &self,
+        sync_key: &SessionKey,
+        epoch: u64,
+    ) -> Result<Option<BTreeMap<String, SyncEntry>>> {
+        let Some(sealed) = &self.sealed else {
+            return Ok(Some(self.entries.clone()));
+        };
+        if self.epoch != epoch {
+            return Ok(None);
+        }
+        let encrypted = hex::decode(sealed).context("Invalid sealed sync entries")?;
+        let mut json = sync_key
+            .decrypt_file(&encrypted)
+            .context("Failed to decrypt sync entries")?;
+        let entries = serde_json::from_slice(&json);
+        json.zeroize();
+        Ok(Some(entries.context("Invalid sync entries")?))
+    }
+}
+
+fn seal_entries(entries: &BTreeMap<String, SyncEntry>, sync_key: &SessionKey) -> Result<String> {
+    let mut json = serde_json::to_vec(entries)?;
+    let encrypted = sync_key.encrypt_file(&json);
+    json.zeroize();
+    Ok(hex::encode(encrypted?))
+}
+
+/// This device's view of the last sync, encrypted with the identity
+#[derive(Serialize, Deserialize, Default)]
+struct SyncState {
+    device_id: String,
+    entries: BTreeMap<String, SyncEntry>,
+    last_cid: Option<String>,
+    last_synced_at: Option<u64>,
+}
+
+impl SyncState {
+    fn load(path: &Path, storage_key: &SessionKey) -> Result<Self> {
+        let mut state = if let Some(encrypted) =
+            no_traces::read_file(path).context("Failed to read sync state")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt sync state")?;
+            let state = serde_json::from_slice(&json);
+            json.zeroize();
+            state.context("Failed to parse sync state")?
+        } else {
+            Self::default()
+        };
+        if state.device_id.is_empty() {
+            state.device_id = uuid::Uuid::new_v4().to_string();
+        }
+        Ok(state)
+    }
+
+    fn save(&self, path: &Path, storage_key: &SessionKey) -> Result<()> {
+        let mut json = serde_json::to_vec(self)?;
+        let encrypted = storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(path, encrypted?).context("Failed to write sync state")
+    }
+
+    /// Bump the clock of every item that changed locally since the last sync
+    fn record_local(&mut self, local: &BTreeMap<String, Value>, skip_settings: bool) {
+        let deleted = self
+            .entries
+            .iter()
+            .filter(|(key, entry)| !local.contains_key(*key) && !entry.value.is_null())
+            .map(|(key, _)| (key.clone(), Value::Null))
+            .collect::<Vec<_>>();
+        let current = local.iter().map(|(k, v)| (k.clone(), v.clone()));
+
+        for (key, value) in current.chain(deleted) {
+            if skip_settings && key.starts_with(SETTINGS_PREFIX) {
+                continue;
+            }
+            if self.entries.get(&key).map(|e| &e.value) == Some(&value) {
+                continue;
+            }
+            let entry = self.entries.entry(key).or_insert_with(|| SyncEntry {
+                value: Value::Null,
+                clock: VectorClock::default(),
+                updated_at: 0,
+                device_id: String::new(),
+            });
+            entry.value = value;
+            entry.clock.increment(&self.device_id);
+            entry.updated_at = now_secs();
+            entry.device_id = self.device_id.clone();
+        }
+    }
+
+    /// Merge a remote snapshot in, returning (items changed, conflicts)
+    fn merge(&mut self, remote: &BTreeMap<String, SyncEntry>) -> (usize, usize) {
+        let (mut pulled, mut conflicts) = (0, 0);
+        for (key, theirs) in remote {
+            let merged = match self.entries.get(key) {
+                Some(ours) if ours == theirs => continue,
+                Some(ours) => {
+                    let (winner, conflict) = SyncEntry::resolve(ours, theirs);
+                    conflicts += conflict as usize;
+                    if winner.value != ours.value {
+                        pulled += 1;
+                    }
+                    winner
+                }
+                None => {
+                    pulled += 1;
+                    theirs.clone()
+                }
+            };
+            self.entries.insert(key.clone(), merged);
+        }
+        (pulled, conflicts)
+    }
+}
+
+/// Outcome of one sync round
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncReport {
+    /// Snapshot now published for this identity
+    pub cid: Option<String>,
+    /// Items changed here by other devices
+    pub pulled: usize,
+    /// Whether this device published a new snapshot
+    pub pushed: bool,
+    /// Concurrent edits settled by last-writer-wins
+    pub conflicts: usize,
+    pub synced_at: u64,
+}
+
+/// Exchange contacts and portable settings with the identity's other devices
+pub async fn sync_now(data_dir: &Path, identity: &Identity) -> Result<SyncReport> {
+    let _guard = SYNC_LOCK.lock().await;
+
+    let state_path = data_dir.join(SYNC_STATE_FILE);
+    let state_key = identity.storage_key(b"sync-state");
+    let snapshot_key = identity.storage_key(b"sync-snapshot");
+    let ipns_key = sync_keypair(identity)?;
+    let ipns_name = ipns_key.public().to_peer_id().to_string();
+
+    let mut state = SyncState::load(&state_path, &state_key)?;
+    let remote = fetch_snapshot(&ipns_name, &snapshot_key).await?;
+
+    // The device list comes first: a revoked device stops here, and a
+    // pending one can only announce itself until it is approved
+    let mut devices = DeviceStore::load(data_dir, identity)?;
+    let remote_devices = remote.as_ref().and_then(|s| s.devices.clone());
+    if let Some(list) = &remote_devices {
+        devices.merge(identity, list)?;
+    }
+    devices.register(identity)?;
+    devices.save()?;
+    if devices.status() == Some(DeviceStatus::Revoked) {
+        anyhow::bail!("This device was revoked and no longer syncs");
+    }
+    let devices_changed = remote_devices.as_ref() != devices.signed();
+    let Some(sync_key) = devices.sync_key()? else {
+        if devices_changed {
+            let snapshot = SyncSnapshot {
+                version: SNAPSHOT_VERSION,
+                device_id: state.device_id.clone(),
+                written_at: now_secs(),
+                entries: BTreeMap::new(),
+                sealed: remote.as_ref().and_then(|s| s.sealed.clone()),
+                epoch: remote.as_ref().map_or(0, |s| s.epoch),
+                devices: devices.signed().cloned(),
+            };
+            state.last_cid = Some(publish_snapshot(&snapshot, &snapshot_key, &ipns_key).await?);
+            state.save(&state_path, &state_key)?;
+        }
+        info!("Sync waits for another device to approve this one");
+        return Ok(SyncReport {
+            cid: state.last_cid,
+            pulled: 0,
+            pushed: devices_changed,
+            conflicts: 0,
+            synced_at: now_secs(),
+        });
+    };
+
+    // A device joining for the first time takes the existing settings rather
+    // than overwriting them with its defaults
+    let first_sync = state.last_synced_at.is_none() && remote.is_some();
+    let local = local_entries(data_dir, identity)?;
+    state.record_local(&local, first_sync);
+
+    // Sealed with the key of an epoch we no longer hold, right after a
+    // revocation: what we have is republished under the new key
+    let remote_entries = match &remote {
+        Some(snapshot) => snapshot.open(&sync_key, devices.epoch())?,
+        None => Some(BTreeMap::new()),
+    };
+    let (pulled, conflicts) = state.merge(remote_entries.as_ref().unwrap_or(&BTreeMap::new()));
+    apply_entries(data_dir, identity, &state.entries, &local)?;
+
+    let pushed = remote_entries.as_ref() != Some(&state.entries) || devices_changed;
+    if pushed {
+        let snapshot = SyncSnapshot {
+            version: SNAPSHOT_VERSION,
+            device_id: state.device_id.clone(),
+            written_at: now_secs(),
+            entries: BTreeMap::new(),
+            sealed: Some(seal_entries(&state.entries, &sync_key)?),
+            epoch: devices.epoch(),
+            devices: devices.signed().cloned(),
+        };
+        state.last_cid = Some(publish_snapshot(&snapshot, &snapshot_key, &ipns_key).await?);
+    }
+
+    let synced_at = now_secs();
+    state.last_synced_at = Some(synced_at);
+    state.save(&state_path, &state_key)?;
+
+    info!(
+        "Sync complete: {} pulled, {} conflicts, pushed: {}",
+        pulled, conflicts, pushed
+    );
+    Ok(SyncReport {
+        cid: state.last_cid,
+        pulled,
+        pushed,
+        conflicts,
+        synced_at,
+    })
+}
+
+/// Run `sync_now` on the configured interval while unlocked, emitting
+/// `sync_completed` after each round
+pub fn start_sync_worker(app: tauri::AppHandle, data_dir: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let sync_settings = settings::sync_settings();
+            tokio::time::sleep(sync_settings.interval()).await;
+
+            if !settings::sync_settings().enabled {
+                continue;
+            }
+            let Some(identity) = app.state::<AppState>().peek_identity() else {
+                continue;
+            };
+
+            match sync_now(&data_dir, &identity).await {
+                Ok(report) => {
+                    events::emit(&app, "sync_completed", report);
+                }
+                Err(e) => warn!("Background sync failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Current contacts and synced settings sections, keyed like snapshot entries
+fn local_entries(data_dir: &Path, identity: &Identity) -> Result<BTreeMap<String, Value>> {
+    let mut entries = BTreeMap::new();
+
+    let settings = serde_json::to_value(settings::saved())?;
+    for section in SYNCED_SETTINGS {
+        entries.insert(
+            format!("{}{}", SETTINGS_PREFIX, section),
+            settings[*section].clone(),
+        );
+    }
+
+    for contact in ContactBook::load(data_dir, identity)?.contacts() {
+        entries.insert(
+            format!("{}{}", CONTACT_PREFIX, contact.public_id),
+            serde_json::to_value(contact)?,
+        );
+    }
+
+    Ok(entries)
+}
+
+/// Write merged values that differ from the local ones back to the stores
+fn apply_entries(
+    data_dir: &Path,
+    identity: &Identity,
+    entries: &BTreeMap<String, SyncEntry>,
+    local: &BTreeMap<String, Value>,
+) -> Result<()> {
+    let changed = entries
+        .iter()
+        .filter(|(key, entry)| local.get(*key).unwrap_or(&Value::Null) != &entry.value);
+
+    let mut settings = serde_json::to_value(settings::saved())?;
+    let mut contacts = ContactBook::load(data_dir, identity)?;
+    let (mut settings_changed, mut contacts_changed) = (false, false);
+
+    for (key, entry) in changed {
+        if let Some(section) = key.strip_prefix(SETTINGS_PREFIX) {
+            if SYNCED_SETTINGS.contains(&section) && !entry.value.is_null() {
+                settings[section] = entry.value.clone();
+                settings_changed = true;
+            }
+        } else if let Some(public_id) = key.strip_prefix(CONTACT_PREFIX) {
+            if entry.value.is_null() {
+                contacts.remove(public_id);
+            } else {
+                contacts.insert(
+                    serde_json::from_value(entry.value.clone())
+                        .context("Invalid synced contact")?,
+                );
+            }
+            contacts_changed = true;
+        }
+    }
+
+    if settings_changed {
+        let settings: AppSettings =
+            serde_json::from_value(settings).context("Invalid synced settings")?;
+        settings::update(settings).context("Failed to apply synced settings")?;
+    }
+    if contacts_changed {
+        contacts.save()?;
+    }
+    Ok(())
+}
+
+/// IPNS key derived from the identity, so every device publishes under the same name
+fn sync_keypair(identity: &Identity) -> Result<Keypair> {
+    let mut seed = identity.storage_key(b"sync-ipns").as_bytes();
+    let keypair = Keypair::ed25519_from_bytes(&mut seed);
+    seed.zeroize();
+    keypair.context("Failed to derive sync key")
+}
+
+/// Encrypt and upload a snapshot, then point the sync name at it
+async fn publish_snapshot(
+    snapshot: &SyncSnapshot,
+    snapshot_key: &SessionKey,
+    ipns_key: &Keypair,
+) -> Result<String> {
+    let mut json = serde_json::to_vec(snapshot)?;
+    let encrypted = snapshot_key.encrypt_file(&json);
+    json.zeroize();
+    let cid = upload_bytes_to_ipfs(encrypted?).await?;
+
+    let client = ipfs::client()?;
+    ipfs::ensure_key(&client, SYNC_KEY_NAME, ipns_key).await?;
+    ipfs::publish_name(&client, SYNC_KEY_NAME, &cid, IPNS_LIFETIME)
+        .await
+        .context("Failed to publish sync snapshot")?;
+
+    info!("Published sync snapshot {}", cid);
+    Ok(cid)
+}
+
+/// Latest snapshot from any device, or `None` if nothing was published yet
+async fn fetch_snapshot(
+    ipns_name: &str,
+    snapshot_key: &SessionKey,
+) -> Result<Option<SyncSnapshot>> {
+    let client = ipfs::client()?;
+    let response = client
+        .post(ipfs::api("name/resolve"))
+        .query(&[("arg", ipns_name)])
+        .send()
+        .await
+        .context("Failed to resolve sync name")?;
+
+    // The node answers with an error status for names it can't resolve
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+
+    let json: serde_json::Value = response.json().await?;
+    let path = json["Path"].as_str().context("No Path in IPNS response")?;
+
+    let encrypted = cat_bytes_from_ipfs(path).await?;
+    let mut snapshot_json = snapshot_key
+        .decrypt_file(&encrypted)
+        .context("Failed to decrypt sync snapshot")?;
+    let snapshot = serde_json::from_slice::<SyncSnapshot>(&snapshot_json);
+    snapshot_json.zeroize();
+    let snapshot = snapshot.context("Invalid sync snapshot")?;
+
+    if snapshot.version > SNAPSHOT_VERSION {
+        anyhow::bail!("Sync snapshot is from a newer version of the app");
+    }
+    Ok(Some(snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(value: &str, clock: &[(&str, u64)], updated_at: u64, device_id: &str) -> SyncEntry {
+        SyncEntry {
+            value: Value::from(value),
+            clock: VectorClock(clock.iter().map(|(d, c)| (d.to_string(), *c)).collect()),
+            updated_at,
+            device_id: device_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn newer_clock_wins_over_newer_timestamp() {
+        let local = entry("old", &[("a", 1)], 200, "a");
+        let remote = entry("new", &[("a", 1), ("b", 1)], 100, "b");
+        let (winner, conflict) = SyncEntry::resolve(&local, &remote);
+        assert_eq!(winner.value, "new");
+        assert!(!conflict);
+    }
+
+    #[test]
+    fn concurrent_edits_use_last_writer() {
+        let local = entry("mine", &[("a", 2)], 100, "a");
+        let remote = entry("theirs", &[("a", 1), ("b", 1)], 150, "b");
+        let (winner, conflict) = SyncEntry::resolve(&local, &remote);
+        assert_eq!(winner.value, "theirs");
+        assert!(conflict);
+        assert_eq!(winner.clock.compare(&local.clock), Some(Ordering::Greater));
+        assert_eq!(winner.clock.compare(&remote.clock), Some(Ordering::Greater));
+    }
+}
@@ -0,0 +1,353 @@
+use crate::catalog::now_secs;
+use crate::crypto::SessionKey;
+use crate::dead_drop::{
+    cat_bytes_from_ipfs, download_and_decrypt, encrypt_and_upload, is_upload_pending,
+    stat_dead_drop, upload_bytes_to_ipfs, DeadDropCreated, DropOptions, DropStat,
+};
+use crate::ipfs;
+use crate::shards::{recover_session_key, split_session_key};
+use crate::wire;
+use anyhow::{Context, Result};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+/// Drop references pointing at a timelock manifest carry this prefix
+pub const TIMELOCK_CID_PREFIX: &str = "tl:";
+const MANIFEST_VERSION: u32 = 1;
+/// drand's quicknet: a beacon every 3 seconds, with the unchained BLS
+/// signatures timelock encryption needs
+pub const QUICKNET_CHAIN_HASH: &str =
+    "52db9ba70e0cc0f6eaf7803dd07447a1f5477735fd3f661792ba94600c84e971";
+const QUICKNET_SCHEME: &str = "bls-unchained-g1-rfc9380";
+/// The quicknet group key and clock, pinned here like the chain hash: a
+/// relay that served its own key, or moved the genesis, could open drops
+/// before their time
+const QUICKNET_PUBLIC_KEY: &str = "83cf0f2896adee7eb8b5f01fcad3912212c437e0073e911fb90022d3e760183c8c4b450b6a0a6c3ac6a5776a2d1064510d1fec758c921cc22b0e17e63aaf4bcb5ed66304de9cf809bd274ca73bab4af5a6e9c76a4bc09e76eae8991ef5ece45a";
+const QUICKNET_PERIOD: u64 = 3;
+const QUICKNET_GENESIS_TIME: u64 = 1692803367;
+/// Public drand HTTP relays, tried in order. They serve signed beacons, so
+/// a relay can withhold a release but not fake one.
+const DRAND_RELAYS: &[&str] = &[
+    "https://api.drand.sh",
+    "https://api2.drand.sh",
+    "https://drand.cloudflare.com",
+];
+const TIME_SECRET_LEN: usize = 32;
+const CHECK_LEN: usize = 8;
+/// Longest a drop may be locked for; drand makes no promises beyond that
+const MAX_LOCK_SECS: u64 = 10 * 365 * 24 * 60 * 60;
+
+/// The drand chain a timelock is bound to, as its relays describe it
+#[derive(Deserialize, Debug, Clone)]
+struct ChainInfo {
+    public_key: String, // hex
+    period: u64,
+    genesis_time: u64,
+    hash: String, // hex
+    #[serde(rename = "schemeID")]
+    scheme_id: String,
+}
+
+impl ChainInfo {
+    /// First round whose beacon is published at or after `at`
+    fn round_at(&self, at: u64) -> u64 {
+        at.saturating_sub(self.genesis_time).div_ceil(self.period) + 1
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Beacon {
+    round: u64,
+    signature: String, // hex
+}
+
+/// Public manifest of a timelocked drop. The shards rebuild one half of the
+/// session key; the other half is timelock-encrypted to a drand round and
+/// can't be opened by anyone, shard holders included, before that round is
+/// published.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimelockManifest {
+    pub version: u32,
+    pub cid: String,
+    pub unlock_at: u64,
+    pub chain_hash: String, // hex
+    pub round: u64,
+    /// The time half of the key as a tlock (age-format) file, hex
+    pub sealed_secret: String,
+    /// Lets wrong shards fail before the download
+    pub key_check: String, // hex
+}
+
+/// When a timelocked drop opens, for showing before retrieval
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimelockStatus {
+    pub unlock_at: u64,
+    pub round: u64,
+    pub unlocked: bool,
+}
+
+/// Strip the timelock prefix from a drop reference, if present
+pub fn timelock_manifest_cid(cid: &str) -> Option<&str> {
+    cid.strip_prefix(TIMELOCK_CID_PREFIX)
+}
+
+/// The session key is bound to both halves, so neither opens the drop alone
+fn derive_key(share_key: &SessionKey, time_secret: &[u8]) -> Result<SessionKey> {
+    let mut share_bytes = share_key.as_bytes();
+    let mut digest: [u8; 32] = Sha256::new()
+        .chain_update(b"control/timelock-drop/key")
+        .chain_update(share_bytes)
+        .chain_update(time_secret)
+        .finalize()
+        .into();
+    share_bytes.zeroize();
+    let session_key = SessionKey::from_bytes(&digest);
+    digest.zeroize();
+    session_key
+}
+
+fn key_check(session_key: &SessionKey) -> String {
+    let mut key_bytes = session_key.as_bytes();
+    let digest = Sha256::new()
+        .chain_update(b"control/timelock-drop/check")
+        .chain_update(key_bytes)
+        .finalize();
+    key_bytes.zeroize();
+    hex::encode(&digest[..CHECK_LEN])
+}
+
+/// Fetch `path` from the first relay that answers
+async fn relay_get<T: serde::de::DeserializeOwned>(path: &str) -> Result<T> {
+    let client = ipfs::client()?;
+    let mut last_error = None;
+    for relay in DRAND_RELAYS {
+        let url = format!("{}/{}", relay, path);
+        let response = match client.get(&url).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("drand relay {} unreachable: {}", relay, e);
+                last_error = Some(anyhow::Error::from(e));
+                continue;
+            }
+        };
+        match response.error_for_status() {
+            Ok(response) => return response.json().await.context("Invalid drand response"),
+            Err(e) => last_error = Some(e.into()),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| anyhow::anyhow!("No drand relays"))
+        .context("No drand relay answered"))
+}
+
+async fn chain_info(chain_hash: &str) -> Result<ChainInfo> {
+    let info: ChainInfo = relay_get(&format!("{}/info", chain_hash)).await?;
+    // The relay is only trusted to repeat the chain we asked for
+    if info.hash != chain_hash
+        || info.scheme_id != QUICKNET_SCHEME
+        || !info.public_key.eq_ignore_ascii_case(QUICKNET_PUBLIC_KEY)
+        || info.period != QUICKNET_PERIOD
+        || info.genesis_time != QUICKNET_GENESIS_TIME
+    {
+        anyhow::bail!("drand relay returned a different chain");
+    }
+    Ok(info)
+}
+
+/// Create a shard drop that can't be opened before `unlock_at` (Unix
+/// seconds). Afterwards it opens with the shards alone.
+pub async fn create_timelock_drop(
+    file_path: &str,
+    threshold: u8,
+    total_shards: u8,
+    unlock_at: u64,
+    options: &DropOptions,
+) -> Result<DeadDropCreated> {
+    if threshold > total_shards {
+        anyhow::bail!("Threshold cannot exceed total shards");
+    }
+    if threshold < 2 {
+        anyhow::bail!("Threshold must be at least 2");
+    }
+    let now = now_secs();
+    if unlock_at <= now {
+        anyhow::bail!("Unlock time must be in the future");
+    }
+    if unlock_at - now > MAX_LOCK_SECS {
+        anyhow::bail!("A drop can be locked for at most {} seconds", MAX_LOCK_SECS);
+    }
+
+    let info = chain_info(QUICKNET_CHAIN_HASH).await?;
+    let round = info.round_at(unlock_at);
+    let chain_hash = hex::decode(&info.hash).context("Invalid chain hash")?;
+    let public_key = hex::decode(&info.public_key).context("Invalid chain public key")?;
+
+    let share_key = SessionKey::generate();
+    let mut time_secret = [0u8; TIME_SECRET_LEN];
+    OsRng.fill_bytes(&mut time_secret);
+    let session_key = derive_key(&share_key, &time_secret)?;
+
+    let mut sealed_secret = Vec::new();
+    let sealed = tlock_age::encrypt(
+        &mut sealed_secret,
+        &time_secret[..],
+        &chain_hash,
+        &public_key,
+        round,
+    );
+    time_secret.zeroize();
+    sealed.map_err(|e| anyhow::anyhow!("Timelock encryption failed: {}", e))?;
+
+    let cid = encrypt_and_upload(file_path, &session_key, options).await?;
+    let manifest = TimelockManifest {
+        version: MANIFEST_VERSION,
+        cid: cid.clone(),
+        unlock_at,
+        chain_hash: info.hash,
+        round,
+        sealed_secret: hex::encode(sealed_secret),
+        key_check: key_check(&session_key),
+    };
+    drop(session_key);
+    let manifest_cid = upload_bytes_to_ipfs(wire::encode(&manifest)?).await?;
+
+    let shards = split_session_key(&share_key, threshold, total_shards);
+    drop(share_key);
+    info!(
+        "Created timelock drop {} (drand round {})",
+        manifest_cid, round
+    );
+
+    Ok(DeadDropCreated {
+        pending: is_upload_pending(options, &cid),
+        cid: format!("{}{}", TIMELOCK_CID_PREFIX, manifest_cid),
+        shards,
+        drop_id: None,
+        piece_cids: vec![cid],
+        shred: None,
+        cipher: Some(options.cipher),
+    })
+}
+
+/// Fetch and check the manifest of a timelocked drop
+pub async fn fetch_timelock_manifest(manifest_cid: &str) -> Result<TimelockManifest> {
+    let bytes = cat_bytes_from_ipfs(manifest_cid).await?;
+    let manifest: TimelockManifest = wire::decode(&bytes).context("Invalid timelock manifest")?;
+    if manifest.version > MANIFEST_VERSION {
+        anyhow::bail!(
+            "Timelock manifest version {} is not supported",
+            manifest.version
+        );
+    }
+    Ok(manifest)
+}
+
+/// Open the time half of the key with the drand beacon for its round
+async fn open_time_secret(manifest: &TimelockManifest) -> Result<Vec<u8>> {
+    if now_secs() < manifest.unlock_at {
+        anyhow::bail!(
+            "This drop is timelocked until {}",
+            format_time(manifest.unlock_at)
+        );
+    }
+    let beacon: Beacon = relay_get(&format!(
+        "{}/public/{}",
+        manifest.chain_hash, manifest.round
+    ))
+    .await
+    .context("The unlock beacon isn't available yet")?;
+    if beacon.round != manifest.round {
+        anyhow::bail!("drand relay returned the wrong round");
+    }
+
+    let chain_hash = hex::decode(&manifest.chain_hash).context("Invalid chain hash")?;
+    let signature = hex::decode(&beacon.signature).context("Invalid beacon signature")?;
+    let sealed = hex::decode(&manifest.sealed_secret).context("Invalid sealed secret")?;
+    let mut time_secret = Vec::new();
+    // A forged beacon can't decrypt, so this also authenticates the relay's answer
+    tlock_age::decrypt(&mut time_secret, &sealed[..], &chain_hash, &signature)
+        .map_err(|e| anyhow::anyhow!("Timelock decryption failed: {}", e))?;
+    Ok(time_secret)
+}
+
+fn format_time(at: u64) -> String {
+    chrono::DateTime::from_timestamp(at as i64, 0)
+        .map_or_else(|| at.to_string(), |t| t.to_rfc3339())
+}
+
+/// Retrieve a timelocked drop: rebuild the share half from the shards, open
+/// the time half once its round is out, and decrypt
+pub async fn retrieve_timelock_drop(
+    manifest_cid: &str,
+    shard_strings: Vec<String>,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<()> {
+    let manifest = fetch_timelock_manifest(manifest_cid).await?;
+    let share_key = recover_session_key(&shard_strings)?;
+    let mut time_secret = open_time_secret(&manifest).await?;
+    let session_key = derive_key(&share_key, &time_secret);
+    time_secret.zeroize();
+    drop(share_key);
+    let session_key = session_key?;
+    if key_check(&session_key) != manifest.key_check {
+        anyhow::bail!("These shards don't belong to this drop");
+    }
+    download_and_decrypt(&manifest.cid, &session_key, output_path, options).await?;
+    Ok(())
+}
+
+/// When a timelocked drop opens; the manifest is public, so no shards are needed
+pub async fn timelock_status(manifest_cid: &str) -> Result<TimelockStatus> {
+    let manifest = fetch_timelock_manifest(manifest_cid).await?;
+    Ok(TimelockStatus {
+        unlock_at: manifest.unlock_at,
+        round: manifest.round,
+        unlocked: now_secs() >= manifest.unlock_at,
+    })
+}
+
+/// Preflight estimate; the manifest is public, so no shards are needed
+pub async fn stat_timelock_drop(
+    manifest_cid: &str,
+    output_path: &Path,
+    options: &DropOptions,
+) -> Result<DropStat> {
+    let manifest = fetch_timelock_manifest(manifest_cid).await?;
+    stat_dead_drop(&manifest.cid, None, output_path, options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_halves_make_the_key() {
+        let share_key = SessionKey::generate();
+        let key = derive_key(&share_key, &[1u8; TIME_SECRET_LEN]).unwrap();
+        let again = derive_key(&share_key, &[1u8; TIME_SECRET_LEN]).unwrap();
+        let other = derive_key(&share_key, &[2u8; TIME_SECRET_LEN]).unwrap();
+        assert_eq!(key_check(&key), key_check(&again));
+        assert_ne!(key_check(&key), key_check(&other));
+        assert_ne!(key.as_bytes(), share_key.as_bytes());
+    }
+
+    #[test]
+    fn rounds_follow_the_chain_clock() {
+        let info = ChainInfo {
+            public_key: String::new(),
+            period: 3,
+            genesis_time: 1000,
+            hash: QUICKNET_CHAIN_HASH.to_string(),
+            scheme_id: QUICKNET_SCHEME.to_string(),
+        };
+        assert_eq!(info.round_at(1000), 1);
+        assert_eq!(info.round_at(1003), 2);
+        // Never a round published before the unlock time
+        assert_eq!(info.round_at(1004), 3);
+    }
+}
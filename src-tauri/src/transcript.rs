@@ -0,0 +1,343 @@
+use crate::catalog::now_secs;
+use crate::crypto::{decode_public_key, Identity, SessionKey};
+use crate::no_traces;
+use crate::p2p::GhostMessage;
+use crate::settings;
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+const TRANSCRIPTS_FILE: &str = "transcripts.enc";
+pub const TRANSCRIPT_VERSION: u32 = 1;
+
+/// Admin mode for legal and compliance holds. Nothing is recorded unless it
+/// is enabled, and then only conversations with the held contacts.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ComplianceSettings {
+    pub enabled: bool,
+    /// Public IDs of the contacts under hold
+    pub held: Vec<String>,
+}
+
+impl ComplianceSettings {
+    pub fn validate(&self) -> Result<()> {
+        for contact in &self.held {
+            decode_public_key(contact)
+                .with_context(|| format!("Invalid public ID under hold: {}", contact))?;
+        }
+        Ok(())
+    }
+
+    pub fn holds(&self, contact: &str) -> bool {
+        self.enabled && self.held.iter().any(|c| c == contact)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptKind {
+    Sent,
+    Received,
+    /// The contact's receipt for a message we sent
+    Delivered,
+}
+
+/// One link of a conversation's hash chain
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    pub seq: u64,
+    pub recorded_at: u64,
+    pub kind: TranscriptKind,
+    pub message_id: String,
+    /// Timestamp the sender put on the message
+    pub timestamp: u64,
+    /// `None` for receipts and view-once messages
+    pub content: Option<String>,
+    /// SHA-256 of the content as sent, hex; kept even when the content isn't
+    pub content_sha256: String,
+    #[serde(default)]
+    pub view_once: bool,
+    /// Hash of the previous entry of the same conversation, hex
+    pub prev: String,
+}
+
+/// The exported transcript of one conversation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Transcript {
+    pub version: u32,
+    /// Public ID of whoever exported it
+    pub owner: String,
+    pub contact: String,
+    /// The owner's ed25519 key, hex, as in their contact card
+    pub signing_key: String,
+    pub exported_at: u64,
+    pub entries: Vec<TranscriptEntry>,
+    /// Hash of the last entry, so the signature covers the whole chain
+    pub head: String,
+}
+
+/// The export file: the transcript JSON and a signature over it byte for byte
+#[derive(Serialize, Deserialize)]
+struct SignedTranscript {
+    transcript: String,
+    signature: String, // hex
+}
+
+/// Hash chains of held conversations, encrypted with the local identity
+pub struct TranscriptStore {
+    path: PathBuf,
+    storage_key: SessionKey,
+    conversations: BTreeMap<String, Vec<TranscriptEntry>>,
+}
+
+impl TranscriptStore {
+    /// Load the store, starting empty if it does not exist yet
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(TRANSCRIPTS_FILE);
+        let storage_key = identity.storage_key(b"transcripts");
+
+        let conversations = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read transcripts")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt transcripts")?;
+            let conversations = serde_json::from_slice(&json);
+            json.zeroize();
+            conversations.context("Failed to parse transcripts")?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            conversations,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut json = serde_json::to_vec(&self.conversations)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write transcripts")?;
+        Ok(())
+    }
+
+    pub fn entries(&self, contact: &str) -> &[TranscriptEntry] {
+        self.conversations
+            .get(contact)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn append(
+        &mut self,
+        contact: &str,
+        kind: TranscriptKind,
+        message: &GhostMessage,
+    ) -> &TranscriptEntry {
+        let entries = self.conversations.entry(contact.to_string()).or_default();
+        let keep_content = kind != TranscriptKind::Delivered && !message.view_once;
+        let prev = entries.last().map(entry_hash).unwrap_or_default();
+        entries.push(TranscriptEntry {
+            seq: entries.len() as u64,
+            recorded_at: now_secs(),
+            kind,
+            message_id: message.id.clone(),
+            timestamp: message.timestamp,
+            content: keep_content.then(|| message.content.clone()),
+            content_sha256: hex::encode(Sha256::digest(message.content.as_bytes())),
+            view_once: message.view_once,
+            prev,
+        });
+        entries.last().unwrap()
+    }
+}
+
+/// Add a message to the conversation's chain if the conversation is under
+/// hold. Failures are logged so messaging itself is never blocked.
+pub fn record(
+    data_dir: &Path,
+    identity: &Identity,
+    contact: &str,
+    kind: TranscriptKind,
+    message: &GhostMessage,
+) {
+    if !settings::compliance_settings().holds(contact) {
+        return;
+    }
+    let result = TranscriptStore::load(data_dir, identity).and_then(|mut store| {
+        store.append(contact, kind, message);
+        store.save()
+    });
+    if let Err(e) = result {
+        warn!("Failed to record transcript of {}: {}", contact, e);
+    }
+}
+
+/// Write the signed transcript of the conversation with `contact` to `path`
+pub fn export_transcript(
+    data_dir: &Path,
+    identity: &Identity,
+    contact: &str,
+    path: &Path,
+) -> Result<Transcript> {
+    let store = TranscriptStore::load(data_dir, identity)?;
+    let entries = store.entries(contact).to_vec();
+    if entries.is_empty() {
+        anyhow::bail!("Nothing was recorded for {}", contact);
+    }
+    check_chain(&entries)?;
+
+    let signing_key = identity.signing_key();
+    let transcript = Transcript {
+        version: TRANSCRIPT_VERSION,
+        owner: identity.public_id(),
+        contact: contact.to_string(),
+        signing_key: hex::encode(signing_key.verifying_key().to_bytes()),
+        exported_at: now_secs(),
+        head: entries.last().map(entry_hash).unwrap_or_default(),
+        entries,
+    };
+    let transcript_json = serde_json::to_string(&transcript)?;
+    let signature = signing_key.sign(transcript_json.as_bytes());
+    let signed = SignedTranscript {
+        transcript: transcript_json,
+        signature: hex::encode(signature.to_bytes()),
+    };
+
+    fs::write(path, serde_json::to_vec_pretty(&signed)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!(
+        "Exported transcript of {} ({} entries) to {}",
+        contact,
+        transcript.entries.len(),
+        path.display()
+    );
+    Ok(transcript)
+}
+
+/// Check an exported transcript: the signature, the chain and the content
+/// hashes. Whether the signing key is really the owner's is up to the
+/// caller, e.g. by comparing it with the owner's contact card.
+pub fn verify_transcript(path: &Path) -> Result<Transcript> {
+    let signed: SignedTranscript = serde_json::from_slice(
+        &fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?,
+    )
+    .context("Not a transcript")?;
+    let transcript: Transcript =
+        serde_json::from_str(&signed.transcript).context("Invalid transcript")?;
+    if transcript.version > TRANSCRIPT_VERSION {
+        anyhow::bail!("Transcript version {} is not supported", transcript.version);
+    }
+
+    let key_bytes: [u8; 32] = hex::decode(&transcript.signing_key)
+        .context("Invalid signing key in transcript")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Signing key has the wrong length"))?;
+    let key = VerifyingKey::from_bytes(&key_bytes).context("Invalid signing key in transcript")?;
+    let signature = hex::decode(&signed.signature).context("Invalid transcript signature")?;
+    let signature = Signature::from_slice(&signature).context("Invalid transcript signature")?;
+    key.verify(signed.transcript.as_bytes(), &signature)
+        .context("Transcript signature does not match")?;
+
+    check_chain(&transcript.entries)?;
+    if transcript
+        .entries
+        .last()
+        .map(entry_hash)
+        .unwrap_or_default()
+        != transcript.head
+    {
+        anyhow::bail!("Transcript head does not match its last entry");
+    }
+    Ok(transcript)
+}
+
+/// Entries must be numbered in order, each linked to the one before, and
+/// kept content must match its hash
+fn check_chain(entries: &[TranscriptEntry]) -> Result<()> {
+    let mut prev = String::new();
+    for (seq, entry) in entries.iter().enumerate() {
+        if entry.seq != seq as u64 || entry.prev != prev {
+            anyhow::bail!("Transcript chain is broken at entry {}", seq);
+        }
+        if let Some(content) = &entry.content {
+            if hex::encode(Sha256::digest(content.as_bytes())) != entry.content_sha256 {
+                anyhow::bail!("Content of entry {} does not match its hash", seq);
+            }
+        }
+        prev = entry_hash(entry);
+    }
+    Ok(())
+}
+
+fn entry_hash(entry: &TranscriptEntry) -> String {
+    let json = serde_json::to_vec(entry).expect("transcript entries serialize");
+    hex::encode(Sha256::digest(json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, content: &str) -> GhostMessage {
+        GhostMessage {
+            id: id.to_string(),
+            from: "sender".to_string(),
+            content: content.to_string(),
+            timestamp: 1,
+            view_once: false,
+            location: None,
+        }
+    }
+
+    #[test]
+    fn exports_verifiable_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = Identity::generate();
+        let contact = Identity::generate().public_id();
+
+        let mut store = TranscriptStore::load(dir.path(), &identity).unwrap();
+        store.append(&contact, TranscriptKind::Sent, &message("1", "hello"));
+        store.append(&contact, TranscriptKind::Delivered, &message("1", "hello"));
+        store.append(&contact, TranscriptKind::Received, &message("2", "hi"));
+        store.save().unwrap();
+
+        let path = dir.path().join("transcript.json");
+        export_transcript(dir.path(), &identity, &contact, &path).unwrap();
+        let transcript = verify_transcript(&path).unwrap();
+        assert_eq!(transcript.entries.len(), 3);
+        assert_eq!(transcript.entries[1].content, None);
+
+        let edited = fs::read_to_string(&path).unwrap().replace("hello", "howdy");
+        fs::write(&path, edited).unwrap();
+        assert!(verify_transcript(&path).is_err());
+    }
+
+    #[test]
+    fn detects_removed_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = Identity::generate();
+        let mut store = TranscriptStore::load(dir.path(), &identity).unwrap();
+        for id in ["1", "2", "3"] {
+            store.append("contact", TranscriptKind::Received, &message(id, id));
+        }
+
+        let mut entries = store.entries("contact").to_vec();
+        assert!(check_chain(&entries).is_ok());
+        entries.remove(1);
+        assert!(check_chain(&entries).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::events;
+use crate::i18n::{self, t};
+use crate::p2p::P2PCommand;
+use crate::session::{lock_session, start_ghost_mode, AppState, StateError};
+use crate::{settings, storage};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tauri::{
+    AppHandle, CustomMenuItem, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu,
+    SystemTrayMenuItem, Window,
+};
+use tracing::{error, info, warn};
+
+const MAIN_WINDOW: &str = "main";
+const TOOLTIP: &str = "Control";
+
+/// Whether closing the window keeps Control running in the tray
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TraySettings {
+    pub run_in_background: bool,
+}
+
+/// Messages received while the window was hidden or unfocused
+static UNREAD: AtomicUsize = AtomicUsize::new(0);
+/// Last Ghost Mode state shown in the menu, for relabelling on a locale change
+static GHOST_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Tray icon with quick actions; the Ghost Mode item is relabelled as it changes
+pub fn build() -> SystemTray {
+    let menu = SystemTrayMenu::new()
+        .add_item(CustomMenuItem::new("show", t("tray.show")))
+        .add_item(CustomMenuItem::new("ghost_mode", ghost_mode_title(false)))
+        .add_item(CustomMenuItem::new("lock", t("tray.lock")))
+        .add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new("quit", t("tray.quit")));
+    SystemTray::new().with_menu(menu).with_tooltip(TOOLTIP)
+}
+
+pub fn handle_event(app: &AppHandle, event: SystemTrayEvent) {
+    match event {
+        SystemTrayEvent::LeftClick { .. } => show_window(app),
+        SystemTrayEvent::MenuItemClick { id, .. } => match id.as_str() {
+            "show" => show_window(app),
+            "ghost_mode" => toggle_ghost_mode(app.clone()),
+            "lock" => lock_session(app, "manual"),
+            "quit" => {
+                lock_session(app, "quit");
+                app.exit(0);
+            }
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
+/// Hide instead of closing when running in the background; Ghost Mode keeps going
+pub fn close_requested(window: &Window, api: &tauri::CloseRequestApi) {
+    if settings::tray_settings().run_in_background {
+        api.prevent_close();
+        let _ = window.hide();
+    }
+}
+
+/// Count a message that arrived while the user wasn't looking
+pub fn message_received(window: &Window) {
+    let in_front = window.is_focused().unwrap_or(false) && window.is_visible().unwrap_or(false);
+    if !in_front {
+        let unread = UNREAD.fetch_add(1, Ordering::Relaxed) + 1;
+        update_unread(&window.app_handle(), unread);
+    }
+}
+
+/// The window is in front again, so everything counts as read
+pub fn clear_unread(app: &AppHandle) {
+    if UNREAD.swap(0, Ordering::Relaxed) > 0 {
+        update_unread(app, 0);
+    }
+}
+
+/// Keep the Ghost Mode menu item in sync and tell the frontend, whoever toggled it
+pub fn ghost_mode_changed(app: &AppHandle, active: bool) {
+    GHOST_ACTIVE.store(active, Ordering::Relaxed);
+    let _ = app
+        .tray_handle()
+        .get_item("ghost_mode")
+        .set_title(ghost_mode_title(active));
+    events::emit(app, "ghost_mode_changed", active);
+}
+
+/// Relabel the menu whenever the locale setting changes
+pub fn start_label_updater(app: AppHandle) {
+    let Some(mut settings_rx) = settings::subscribe() else {
+        return;
+    };
+    tauri::async_runtime::spawn(async move {
+        let mut locale = settings_rx.borrow().locale.clone();
+        while settings_rx.changed().await.is_ok() {
+            let current = settings_rx.borrow().locale.clone();
+            if current != locale {
+                locale = current;
+                relabel(&app);
+            }
+        }
+    });
+}
+
+fn relabel(app: &AppHandle) {
+    let tray = app.tray_handle();
+    let _ = tray.get_item("show").set_title(t("tray.show"));
+    let _ = tray
+        .get_item("ghost_mode")
+        .set_title(ghost_mode_title(GHOST_ACTIVE.load(Ordering::Relaxed)));
+    let _ = tray.get_item("lock").set_title(t("tray.lock"));
+    let _ = tray.get_item("quit").set_title(t("tray.quit"));
+    update_unread(app, UNREAD.load(Ordering::Relaxed));
+}
+
+fn ghost_mode_title(active: bool) -> String {
+    if active {
+        t("tray.stop_ghost_mode")
+    } else {
+        t("tray.start_ghost_mode")
+    }
+}
+
+fn update_unread(app: &AppHandle, unread: usize) {
+    let tooltip = match unread {
+        0 => TOOLTIP.to_string(),
+        1 => format!("{} - {}", TOOLTIP, t("tray.unread_one")),
+        n => format!(
+            "{} - {}",
+            TOOLTIP,
+            i18n::t_args("tray.unread_many", &[("count", &n.to_string())])
+        ),
+    };
+    let _ = app.tray_handle().set_tooltip(&tooltip);
+    #[cfg(target_os = "macos")]
+    let _ = app.tray_handle().set_title(&if unread == 0 {
+        String::new()
+    } else {
+        unread.to_string()
+    });
+    events::emit(app, "unread_count", unread);
+}
+
+fn show_window(app: &AppHandle) {
+    if let Some(window) = app.get_window(MAIN_WINDOW) {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+fn toggle_ghost_mode(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let state = app.state::<AppState>();
+        match state.stop_ghost_mode() {
+            Ok(sender) => {
+                let _ = sender.send(P2PCommand::Shutdown).await;
+                info!("Ghost Mode stopped from the tray");
+                ghost_mode_changed(&app, false);
+                return;
+            }
+            // Nothing to start while locked; bring up the unlock screen instead
+            Err(StateError::Locked) => {
+                show_window(&app);
+                return;
+            }
+            Err(StateError::GhostModeStarting) => return,
+            Err(_) => {}
+        }
+
+        let Some(window) = app.get_window(MAIN_WINDOW) else {
+            warn!("No main window to attach Ghost Mode to");
+            return;
+        };
+        let data_dir = match storage::data_dir() {
+            Ok(dir) => dir,
+            Err(e) => {
+                error!("Failed to start Ghost Mode: {}", e);
+                return;
+            }
+        };
+
+        match start_ghost_mode(&state, Arc::new(window), data_dir).await {
+            Ok(()) => {
+                info!("Ghost Mode started from the tray");
+                ghost_mode_changed(&app, true);
+            }
+            Err(e) => error!("Failed to start Ghost Mode: {}", e),
+        }
+    });
+}
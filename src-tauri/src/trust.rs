@@ -0,0 +1,322 @@
+use crate::catalog::now_secs;
+use crate::contacts::{Contact, ContactBook};
+use crate::crypto::{decode_public_key, Identity, SessionKey};
+use crate::no_traces;
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
+
+const ATTESTATIONS_FILE: &str = "attestations.enc";
+const DEFAULT_VOUCHERS_NEEDED: usize = 2;
+const MAX_VOUCHERS_NEEDED: usize = 20;
+
+/// How many verified contacts must vouch for someone before they count as
+/// vouched for
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TrustSettings {
+    pub vouchers_needed: usize,
+}
+
+impl Default for TrustSettings {
+    fn default() -> Self {
+        Self {
+            vouchers_needed: DEFAULT_VOUCHERS_NEEDED,
+        }
+    }
+}
+
+impl TrustSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=MAX_VOUCHERS_NEEDED).contains(&self.vouchers_needed) {
+            anyhow::bail!(
+                "Vouchers needed must be between 1 and {}",
+                MAX_VOUCHERS_NEEDED
+            );
+        }
+        Ok(())
+    }
+}
+
+/// What the attester vouches for: they verified that `subject`, with this
+/// signing key, is the person they call `nickname`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Attestation {
+    pub attester: String,
+    pub subject: String,
+    pub nickname: String,
+    /// The subject's ed25519 key (hex), if the attester has their card
+    #[serde(default)]
+    pub signing_key: Option<String>,
+    pub issued_at: u64,
+}
+
+/// Attestation as sent over Ghost Mode
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedAttestation {
+    /// JSON of an `Attestation`, signed byte for byte
+    pub statement: String,
+    pub signature: String,   // hex
+    pub signing_key: String, // hex, the attester's ed25519 key
+}
+
+/// Attestation from a contact, kept to evaluate the subject's trust
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReceivedAttestation {
+    pub attestation: Attestation,
+    pub received_at: u64,
+    pub signed: SignedAttestation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrustLevel {
+    /// We verified them ourselves
+    Verified,
+    /// Enough verified contacts vouch for them
+    Vouched,
+    /// Some verified contacts vouch for them, fewer than needed
+    Partial,
+    Unknown,
+    /// Verified contacts vouch for a different signing key than we know
+    Disputed,
+}
+
+/// Trust computed for a peer from our own verification and attestations
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trust {
+    pub level: TrustLevel,
+    /// Verified contacts vouching for the key we know
+    pub vouchers: Vec<String>,
+    /// Verified contacts vouching for another key
+    pub disputed_by: Vec<String>,
+}
+
+/// Sign an attestation for a contact we verified
+pub fn attest(identity: &Identity, contact: &Contact) -> Result<SignedAttestation> {
+    if !contact.verified {
+        anyhow::bail!("Only verified contacts can be vouched for");
+    }
+    let statement = serde_json::to_string(&Attestation {
+        attester: identity.public_id(),
+        subject: contact.public_id.clone(),
+        nickname: contact.nickname.clone(),
+        signing_key: contact.signing_key.clone(),
+        issued_at: now_secs(),
+    })?;
+    let signing_key = identity.signing_key();
+    let signature = signing_key.sign(statement.as_bytes());
+
+    Ok(SignedAttestation {
+        statement,
+        signature: hex::encode(signature.to_bytes()),
+        signing_key: hex::encode(signing_key.verifying_key().to_bytes()),
+    })
+}
+
+/// Check the signature and return what was signed. Whether the attester is
+/// who they claim is up to the caller, e.g. by the Ghost Mode sender key.
+pub fn verify_attestation(signed: &SignedAttestation) -> Result<Attestation> {
+    let key_bytes: [u8; 32] = hex::decode(&signed.signing_key)
+        .context("Invalid attester key")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Attester key has the wrong length"))?;
+    let key = VerifyingKey::from_bytes(&key_bytes).context("Invalid attester key")?;
+    let signature = hex::decode(&signed.signature).context("Invalid attestation signature")?;
+    let signature = Signature::from_slice(&signature).context("Invalid attestation signature")?;
+    key.verify(signed.statement.as_bytes(), &signature)
+        .context("Attestation signature does not match")?;
+
+    let attestation: Attestation =
+        serde_json::from_str(&signed.statement).context("Invalid attestation")?;
+    decode_public_key(&attestation.subject).context("Invalid attested public ID")?;
+    if attestation.attester == attestation.subject {
+        anyhow::bail!("Attestation of the attester themselves");
+    }
+    Ok(attestation)
+}
+
+/// Attestations received from contacts, encrypted with the local identity
+pub struct AttestationStore {
+    path: PathBuf,
+    storage_key: SessionKey,
+    received: Vec<ReceivedAttestation>,
+}
+
+impl AttestationStore {
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(ATTESTATIONS_FILE);
+        let storage_key = identity.storage_key(b"attestations");
+
+        let received = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read attestations")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt attestations")?;
+            let received = serde_json::from_slice(&json);
+            json.zeroize();
+            received.context("Failed to parse attestations")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            received,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let mut json = serde_json::to_vec(&self.received)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?).context("Failed to write attestations")?;
+        Ok(())
+    }
+
+    /// Keep an attestation, replacing an older one by the same attester
+    /// about the same subject. False if ours was newer.
+    pub fn add(&mut self, attestation: Attestation, signed: SignedAttestation) -> bool {
+        let existing = self.received.iter().position(|r| {
+            r.attestation.attester == attestation.attester
+                && r.attestation.subject == attestation.subject
+        });
+        if let Some(index) = existing {
+            if self.received[index].attestation.issued_at >= attestation.issued_at {
+                return false;
+            }
+            self.received.remove(index);
+        }
+        self.received.push(ReceivedAttestation {
+            attestation,
+            received_at: now_secs(),
+            signed,
+        });
+        true
+    }
+
+    pub fn received(&self) -> &[ReceivedAttestation] {
+        &self.received
+    }
+
+    pub fn about<'a>(&'a self, subject: &'a str) -> impl Iterator<Item = &'a Attestation> {
+        self.received
+            .iter()
+            .map(|r| &r.attestation)
+            .filter(move |a| a.subject == subject)
+    }
+}
+
+/// Trust in `subject`: only attestations by contacts we verified count, and
+/// only while we still have them as verified
+pub fn evaluate(
+    book: &ContactBook,
+    store: &AttestationStore,
+    subject: &str,
+    settings: &TrustSettings,
+) -> Trust {
+    let contact = book.get(subject);
+    if contact.is_some_and(|c| c.verified) {
+        return Trust {
+            level: TrustLevel::Verified,
+            vouchers: Vec::new(),
+            disputed_by: Vec::new(),
+        };
+    }
+    let known_key = contact.and_then(|c| c.signing_key.as_deref());
+
+    let mut vouchers = BTreeSet::new();
+    let mut disputed_by = BTreeSet::new();
+    for attestation in store.about(subject) {
+        if !book.get(&attestation.attester).is_some_and(|c| c.verified) {
+            continue;
+        }
+        match (known_key, attestation.signing_key.as_deref()) {
+            (Some(known), Some(attested)) if known != attested => {
+                disputed_by.insert(attestation.attester.clone())
+            }
+            _ => vouchers.insert(attestation.attester.clone()),
+        };
+    }
+
+    let level = if !disputed_by.is_empty() {
+        TrustLevel::Disputed
+    } else if vouchers.len() >= settings.vouchers_needed {
+        TrustLevel::Vouched
+    } else if !vouchers.is_empty() {
+        TrustLevel::Partial
+    } else {
+        TrustLevel::Unknown
+    };
+    Trust {
+        level,
+        vouchers: vouchers.into_iter().collect(),
+        disputed_by: disputed_by.into_iter().collect(),
+    }
+}
+
+/// Trust in someone who wrote to us, for message events; `None` for
+/// verified contacts or if the stores can't be read
+pub fn sender_trust(
+    data_dir: &Path,
+    identity: &Identity,
+    settings: &TrustSettings,
+    sender: &str,
+) -> Option<Trust> {
+    let book = ContactBook::load(data_dir, identity).ok()?;
+    let store = AttestationStore::load(data_dir, identity).ok()?;
+    Some(evaluate(&book, &store, sender, settings)).filter(|t| t.level != TrustLevel::Verified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An attestation of `subject` by `attester`, as if from their own book
+    fn vouch(attester: &Identity, subject: &str, key: Option<&str>) -> SignedAttestation {
+        let dir = tempfile::tempdir().unwrap();
+        let mut book = ContactBook::load(dir.path(), attester).unwrap();
+        let mut contact = book.upsert(subject, "Carol", true).unwrap();
+        contact.signing_key = key.map(str::to_string);
+        attest(attester, &contact).unwrap()
+    }
+
+    #[test]
+    fn counts_only_verified_attesters() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = Identity::generate();
+        let (alice, bob) = (Identity::generate(), Identity::generate());
+        let carol = Identity::generate().public_id();
+        let mut book = ContactBook::load(dir.path(), &identity).unwrap();
+        book.upsert(&alice.public_id(), "Alice", true).unwrap();
+        book.upsert(&bob.public_id(), "Bob", false).unwrap();
+        let mut store = AttestationStore::load(dir.path(), &identity).unwrap();
+        let settings = TrustSettings::default();
+
+        // Bob isn't verified, so his word doesn't count yet
+        for attester in [&bob, &alice] {
+            let signed = vouch(attester, &carol, Some("aa"));
+            assert!(store.add(verify_attestation(&signed).unwrap(), signed));
+        }
+        let trust = evaluate(&book, &store, &carol, &settings);
+        assert_eq!(trust.level, TrustLevel::Partial);
+        assert_eq!(trust.vouchers, vec![alice.public_id()]);
+
+        book.upsert(&bob.public_id(), "Bob", true).unwrap();
+        let trust = evaluate(&book, &store, &carol, &settings);
+        assert_eq!(trust.level, TrustLevel::Vouched);
+
+        // We know Carol by another key than the one they vouch for
+        let mut known = book.upsert(&carol, "Carol", false).unwrap();
+        known.signing_key = Some("bb".to_string());
+        book.insert(known);
+        let trust = evaluate(&book, &store, &carol, &settings);
+        assert_eq!(trust.level, TrustLevel::Disputed);
+        assert_eq!(trust.disputed_by.len(), 2);
+    }
+}
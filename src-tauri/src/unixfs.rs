@@ -0,0 +1,223 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// Leaf size used for every upload; also passed to Kubo as the chunker
+pub const IPFS_CHUNK_SIZE: usize = 256 * 1024;
+const LINKS_PER_NODE: usize = 174; // Kubo's balanced layout default
+
+const CODEC_RAW: u64 = 0x55;
+const CODEC_DAG_PB: u64 = 0x70;
+const MULTIHASH_SHA2_256: u8 = 0x12;
+const UNIXFS_TYPE_FILE: u64 = 2;
+
+/// Query parameters for `add` (CIDv1, raw leaves, fixed-size chunker, balanced
+/// layout) that produce the same CIDs as `compute_file_cid`
+pub fn add_params() -> [(&'static str, String); 4] {
+    [
+        ("cid-version", "1".to_string()),
+        ("raw-leaves", "true".to_string()),
+        ("chunker", format!("size-{}", IPFS_CHUNK_SIZE)),
+        ("hash", "sha2-256".to_string()),
+    ]
+}
+
+/// A node in the DAG being built, as seen by its parent
+struct DagLink {
+    cid: Vec<u8>,
+    tsize: u64,     // encoded size of the whole subtree
+    data_size: u64, // file bytes under this subtree
+}
+
+/// Incremental CID computation for content that arrives in pieces
+#[derive(Default)]
+pub struct CidBuilder {
+    buffer: Vec<u8>,
+    leaves: Vec<DagLink>,
+}
+
+impl CidBuilder {
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = (IPFS_CHUNK_SIZE - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() == IPFS_CHUNK_SIZE {
+                self.leaves.push(raw_leaf(&self.buffer));
+                self.buffer.clear();
+            }
+        }
+    }
+
+    /// CIDv1 (base32) Kubo would assign to everything passed to `update`
+    pub fn finish(mut self) -> String {
+        if !self.buffer.is_empty() || self.leaves.is_empty() {
+            self.leaves.push(raw_leaf(&self.buffer));
+        }
+        cid_to_string(&build_root(self.leaves))
+    }
+}
+
+/// Compute the CIDv1 (base32) Kubo would assign to this file
+pub fn compute_file_cid(path: &Path) -> Result<String> {
+    let mut reader = BufReader::new(File::open(path).context("Failed to open file for CID")?);
+    let mut builder = CidBuilder::default();
+    let mut chunk = vec![0u8; IPFS_CHUNK_SIZE];
+
+    loop {
+        let bytes_read = reader.read(&mut chunk).context("Failed to read file for CID")?;
+        if bytes_read == 0 {
+            break;
+        }
+        builder.update(&chunk[..bytes_read]);
+    }
+
+    Ok(builder.finish())
+}
+
+/// Compute the CIDv1 (base32) Kubo would assign to these bytes
+pub fn compute_bytes_cid(data: &[u8]) -> String {
+    let mut builder = CidBuilder::default();
+    builder.update(data);
+    builder.finish()
+}
+
+/// Fail if the node returned a CID for different content than we sent
+pub fn verify_cid(expected: &str, returned: &str) -> Result<()> {
+    if expected != returned {
+        anyhow::bail!(
+            "IPFS node returned CID {} but the content hashes to {}",
+            returned,
+            expected
+        );
+    }
+    Ok(())
+}
+
+fn raw_leaf(data: &[u8]) -> DagLink {
+    DagLink {
+        cid: cid_bytes(CODEC_RAW, data),
+        tsize: data.len() as u64,
+        data_size: data.len() as u64,
+    }
+}
+
+/// Group links level by level until a single root remains; a single leaf is its own root
+fn build_root(mut level: Vec<DagLink>) -> Vec<u8> {
+    while level.len() > 1 {
+        level = level.chunks(LINKS_PER_NODE).map(file_node).collect();
+    }
+    level.remove(0).cid
+}
+
+/// Encode a dag-pb UnixFS file node linking to `children`
+fn file_node(children: &[DagLink]) -> DagLink {
+    let file_size: u64 = children.iter().map(|c| c.data_size).sum();
+
+    let mut unixfs = Vec::new();
+    put_varint_field(&mut unixfs, 1, UNIXFS_TYPE_FILE);
+    put_varint_field(&mut unixfs, 3, file_size);
+    for child in children {
+        put_varint_field(&mut unixfs, 4, child.data_size);
+    }
+
+    // dag-pb canonical order: Links (2) before Data (1)
+    let mut node = Vec::new();
+    for child in children {
+        let mut link = Vec::new();
+        put_bytes_field(&mut link, 1, &child.cid);
+        put_bytes_field(&mut link, 2, b"");
+        put_varint_field(&mut link, 3, child.tsize);
+        put_bytes_field(&mut node, 2, &link);
+    }
+    put_bytes_field(&mut node, 1, &unixfs);
+
+    DagLink {
+        cid: cid_bytes(CODEC_DAG_PB, &node),
+        tsize: node.len() as u64 + children.iter().map(|c| c.tsize).sum::<u64>(),
+        data_size: file_size,
+    }
+}
+
+fn cid_bytes(codec: u64, block: &[u8]) -> Vec<u8> {
+    let mut cid = Vec::with_capacity(36);
+    put_varint(&mut cid, 1);
+    put_varint(&mut cid, codec);
+    cid.push(MULTIHASH_SHA2_256);
+    cid.push(32);
+    cid.extend_from_slice(&Sha256::digest(block));
+    cid
+}
+
+/// Multibase base32 (lowercase, unpadded) with the `b` prefix
+fn cid_to_string(cid: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut out = String::from("b");
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in cid {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(out, field << 3);
+    put_varint(out, value);
+}
+
+fn put_bytes_field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(out, (field << 3) | 2);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_file_cid() {
+        // `ipfs add --cid-version=1 --raw-leaves` of an empty file
+        assert_eq!(
+            compute_bytes_cid(&[]),
+            "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"
+        );
+    }
+
+    #[test]
+    fn test_incremental_matches_whole() {
+        let data: Vec<u8> = (0..IPFS_CHUNK_SIZE * 2 + 100).map(|i| i as u8).collect();
+        let mut builder = CidBuilder::default();
+        for piece in data.chunks(1000) {
+            builder.update(piece);
+        }
+        assert_eq!(builder.finish(), compute_bytes_cid(&data));
+    }
+
+    #[test]
+    fn test_multi_chunk_file_is_dag_pb() {
+        let data = vec![7u8; IPFS_CHUNK_SIZE * 3 + 1];
+        let cid = compute_bytes_cid(&data);
+        assert!(cid.starts_with("bafybei"));
+        assert_eq!(cid, compute_bytes_cid(&data));
+    }
+}
@@ -0,0 +1,317 @@
+use crate::ipfs;
+use crate::settings;
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+const UPDATE_DIR: &str = "updates";
+const DEFAULT_ENDPOINT: &str =
+    "https://github.com/denizZz009/Control/releases/latest/download/update.json";
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Release signing key (Ed25519 public key, hex). Manifests signed with
+/// anything else are rejected, so a compromised endpoint can't push a build.
+/// Release builds set it from the key `scripts/sign-update.sh keygen` made
+/// for the maintainers; the private half never leaves the release machine.
+/// Builds without it have no key to trust and refuse to check for updates.
+const PUBLISHER_KEY: Option<&str> = option_env!("CONTROL_UPDATE_PUBLIC_KEY");
+
+/// Where update checks go; nothing is checked unless the user asks
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateSettings {
+    pub endpoint: String,
+    /// Route update traffic through the Tor proxy from the network settings
+    pub use_tor: bool,
+}
+
+impl Default for UpdateSettings {
+    fn default() -> Self {
+        Self {
+            endpoint: DEFAULT_ENDPOINT.to_string(),
+            use_tor: false,
+        }
+    }
+}
+
+impl UpdateSettings {
+    pub fn validate(&self) -> Result<()> {
+        check_url(&self.endpoint).context("Invalid update endpoint")
+    }
+}
+
+/// File served at the update endpoint
+#[derive(Serialize, Deserialize)]
+struct SignedManifest {
+    /// JSON of an `UpdateManifest`, signed byte for byte
+    manifest: String,
+    signature: String, // hex
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateManifest {
+    pub version: String,
+    pub published_at: u64,
+    #[serde(default)]
+    pub notes: String,
+    pub artifacts: Vec<UpdateArtifact>,
+}
+
+/// Download for one platform, e.g. `linux-x86_64`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateArtifact {
+    pub target: String,
+    pub url: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateCheck {
+    pub current_version: String,
+    pub latest_version: String,
+    pub update_available: bool,
+    pub published_at: u64,
+    pub notes: String,
+    /// Download for this platform, if the release has one
+    pub artifact: Option<UpdateArtifact>,
+}
+
+/// Emitted as `update_progress` while downloading
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateProgress {
+    pub bytes_done: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DownloadedUpdate {
+    pub version: String,
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+/// Fetch and verify the release manifest and compare it with this build
+pub async fn check_for_updates() -> Result<UpdateCheck> {
+    let settings = settings::update_settings();
+    let client = client(&settings)?;
+
+    let signed: SignedManifest = client
+        .get(&settings.endpoint)
+        .timeout(CHECK_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .context("Invalid update manifest")?;
+    let manifest = verify_manifest(&signed, &publisher_key()?)?;
+
+    let current_version = env!("CARGO_PKG_VERSION").to_string();
+    let update_available = is_newer(&manifest.version, &current_version)?;
+    let target = current_target();
+    let artifact = manifest
+        .artifacts
+        .iter()
+        .find(|a| a.target == target)
+        .cloned();
+
+    info!(
+        "Update check: latest {}, running {}",
+        manifest.version, current_version
+    );
+    Ok(UpdateCheck {
+        current_version,
+        latest_version: manifest.version,
+        update_available,
+        published_at: manifest.published_at,
+        notes: manifest.notes,
+        artifact,
+    })
+}
+
+/// Download the newer release for this platform into the data directory and
+/// check its hash. The file is never run; installing it is up to the user.
+pub async fn download_update(
+    data_dir: &Path,
+    on_progress: impl Fn(UpdateProgress),
+) -> Result<DownloadedUpdate> {
+    let check = check_for_updates().await?;
+    if !check.update_available {
+        anyhow::bail!("Already running the latest version");
+    }
+    let artifact = check
+        .artifact
+        .with_context(|| format!("No {} download in this release", current_target()))?;
+    check_url(&artifact.url).context("Invalid download URL")?;
+
+    let file_name = artifact
+        .url
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty() && !name.starts_with('.'))
+        .context("Download URL has no file name")?
+        .to_string();
+    let dir = data_dir.join(UPDATE_DIR);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .context("Failed to create update directory")?;
+    let path = dir.join(&file_name);
+    let partial = dir.join(format!("{}.part", file_name));
+
+    let client = client(&settings::update_settings())?;
+    let response = client.get(&artifact.url).send().await?.error_for_status()?;
+    let mut stream = response.bytes_stream();
+    let mut file = tokio::fs::File::create(&partial)
+        .await
+        .context("Failed to create update file")?;
+    let mut hasher = Sha256::new();
+    let mut bytes_done = 0u64;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        bytes_done += chunk.len() as u64;
+        if bytes_done > artifact.size {
+            drop(file);
+            let _ = tokio::fs::remove_file(&partial).await;
+            anyhow::bail!("Download is larger than the manifest says");
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        on_progress(UpdateProgress {
+            bytes_done,
+            total_bytes: artifact.size,
+        });
+    }
+    file.flush().await?;
+    drop(file);
+
+    let sha256 = hex::encode(hasher.finalize());
+    if bytes_done != artifact.size || !sha256.eq_ignore_ascii_case(&artifact.sha256) {
+        let _ = tokio::fs::remove_file(&partial).await;
+        anyhow::bail!("Downloaded update does not match the signed manifest");
+    }
+    tokio::fs::rename(&partial, &path)
+        .await
+        .context("Failed to store update")?;
+
+    info!(
+        "Downloaded update {} to {}",
+        check.latest_version,
+        path.display()
+    );
+    Ok(DownloadedUpdate {
+        version: check.latest_version,
+        path,
+        sha256,
+    })
+}
+
+fn client(settings: &UpdateSettings) -> Result<reqwest::Client> {
+    crate::settings::check_loaded()?;
+    if !settings.use_tor {
+        return reqwest::Client::builder()
+            .build()
+            .context("Failed to build update HTTP client");
+    }
+    if ipfs::network().tor_proxy.is_none() {
+        anyhow::bail!("Updates over Tor need a Tor proxy in the network settings");
+    }
+    ipfs::client()
+}
+
+fn publisher_key() -> Result<VerifyingKey> {
+    let key = PUBLISHER_KEY
+        .context("This build has no update signing key, so updates can't be verified")?;
+    let bytes: [u8; 32] = hex::decode(key.trim())?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Publisher key has the wrong length"))?;
+    VerifyingKey::from_bytes(&bytes).context("Invalid publisher key")
+}
+
+fn verify_manifest(signed: &SignedManifest, key: &VerifyingKey) -> Result<UpdateManifest> {
+    let signature = hex::decode(&signed.signature).context("Invalid manifest signature")?;
+    let signature = Signature::from_slice(&signature).context("Invalid manifest signature")?;
+    key.verify(signed.manifest.as_bytes(), &signature)
+        .context("Update manifest is not signed by the Control publisher")?;
+    serde_json::from_str(&signed.manifest).context("Invalid update manifest")
+}
+
+/// Only HTTPS, or plain HTTP to an onion service where Tor provides the encryption
+fn check_url(url: &str) -> Result<()> {
+    let url = reqwest::Url::parse(url)?;
+    let onion = url.host_str().is_some_and(|h| h.ends_with(".onion"));
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if onion => Ok(()),
+        scheme => anyhow::bail!("{} URLs are not allowed", scheme),
+    }
+}
+
+fn current_target() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// Compare dotted release versions; pre-release suffixes are not offered
+fn is_newer(candidate: &str, current: &str) -> Result<bool> {
+    fn parse(version: &str) -> Result<Vec<u64>> {
+        version
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| {
+                part.parse()
+                    .with_context(|| format!("Invalid version {}", version))
+            })
+            .collect()
+    }
+    Ok(parse(candidate)? > parse(current)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn signed(key: &SigningKey, version: &str) -> SignedManifest {
+        let manifest = serde_json::to_string(&UpdateManifest {
+            version: version.to_string(),
+            published_at: 0,
+            notes: String::new(),
+            artifacts: Vec::new(),
+        })
+        .unwrap();
+        let signature = hex::encode(key.sign(manifest.as_bytes()).to_bytes());
+        SignedManifest {
+            manifest,
+            signature,
+        }
+    }
+
+    #[test]
+    fn accepts_only_the_pinned_key() {
+        let publisher = SigningKey::generate(&mut rand::rngs::OsRng);
+        let attacker = SigningKey::generate(&mut rand::rngs::OsRng);
+        let key = publisher.verifying_key();
+        assert_eq!(publisher_key().is_ok(), PUBLISHER_KEY.is_some());
+
+        assert!(verify_manifest(&signed(&publisher, "1.0.0"), &key).is_ok());
+        assert!(verify_manifest(&signed(&attacker, "1.0.0"), &key).is_err());
+
+        let mut tampered = signed(&publisher, "1.0.0");
+        tampered.manifest = tampered.manifest.replace("1.0.0", "9.0.0");
+        assert!(verify_manifest(&tampered, &key).is_err());
+    }
+
+    #[test]
+    fn compares_versions_numerically() {
+        assert!(is_newer("0.10.0", "0.9.1").unwrap());
+        assert!(is_newer("v1.0.0", "0.1.0").unwrap());
+        assert!(!is_newer("0.1.0", "0.1.0").unwrap());
+        assert!(is_newer("1.0.0-rc1", "0.1.0").is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use crate::catalog::now_secs;
+use crate::dead_drop::{drop_cache_dir, upload_pending, DropOptions};
+use crate::events;
+use crate::health;
+use crate::ipfs;
+use crate::mfs;
+use crate::no_traces;
+use crate::settings;
+use anyhow::{Context, Result};
+use chrono::Timelike;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+const PENDING_UPLOADS_FILE: &str = "pending_uploads.json";
+const WINDOW_POLL_SECS: u64 = 60;
+const QUEUE_POLL_SECS: u64 = 15;
+const RETRY_BASE_SECS: u64 = 30;
+const RETRY_MAX_SECS: u64 = 60 * 60;
+
+/// Bandwidth limit and daily time window for drop uploads,
+/// e.g. 2 MB/s only between 01:00 and 06:00 local time
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UploadSchedule {
+    pub max_bytes_per_sec: Option<u64>,
+    pub window_start: Option<String>, // "HH:MM" local time
+    pub window_end: Option<String>,   // "HH:MM", may wrap past midnight
+}
+
+impl UploadSchedule {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_bytes_per_sec == Some(0) {
+            anyhow::bail!("Upload bandwidth limit must be above zero");
+        }
+        match (&self.window_start, &self.window_end) {
+            (Some(start), Some(end)) => {
+                parse_minutes(start)?;
+                parse_minutes(end)?;
+            }
+            (None, None) => {}
+            _ => anyhow::bail!("Upload window needs both a start and an end time"),
+        }
+        Ok(())
+    }
+
+    /// Whether uploads may run right now
+    pub fn in_window(&self) -> bool {
+        match (&self.window_start, &self.window_end) {
+            (Some(start), Some(end)) => in_daily_window(start, end).unwrap_or(true),
+            _ => true,
+        }
+    }
+
+    /// Sleep until the upload window opens
+    pub async fn wait_for_window(&self) {
+        let mut announced = false;
+        while !self.in_window() {
+            if !announced {
+                info!("Upload paused until the upload window opens");
+                announced = true;
+            }
+            tokio::time::sleep(Duration::from_secs(WINDOW_POLL_SECS)).await;
+        }
+    }
+}
+
+/// Whether the local time is between `start` and `end` ("HH:MM", may wrap past midnight)
+pub fn in_daily_window(start: &str, end: &str) -> Result<bool> {
+    let (start, end) = (parse_minutes(start)?, parse_minutes(end)?);
+    let now = chrono::Local::now();
+    let minute = now.hour() * 60 + now.minute();
+    Ok(if start <= end {
+        (start..end).contains(&minute)
+    } else {
+        minute >= start || minute < end
+    })
+}
+
+/// Parse "HH:MM" into minutes after midnight
+pub fn parse_minutes(time: &str) -> Result<u32> {
+    let (hours, minutes) = time
+        .split_once(':')
+        .with_context(|| format!("Invalid time '{}', expected HH:MM", time))?;
+    let hours: u32 = hours.parse().context("Invalid hour")?;
+    let minutes: u32 = minutes.parse().context("Invalid minute")?;
+    if hours > 23 || minutes > 59 {
+        anyhow::bail!("Invalid time '{}', expected HH:MM", time);
+    }
+    Ok(hours * 60 + minutes)
+}
+
+/// Keeps an upload at or below a byte rate by sleeping between chunks
+pub struct Throttle {
+    max_bytes_per_sec: Option<u64>,
+    started: Instant,
+    sent: u64,
+}
+
+impl Throttle {
+    pub fn new(max_bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            max_bytes_per_sec,
+            started: Instant::now(),
+            sent: 0,
+        }
+    }
+
+    /// Account for `bytes` just sent, sleeping if we are ahead of the limit
+    pub async fn pace(&mut self, bytes: u64) {
+        let Some(rate) = self.max_bytes_per_sec else {
+            return;
+        };
+        self.sent += bytes;
+        let target = Duration::from_secs_f64(self.sent as f64 / rate as f64);
+        let elapsed = self.started.elapsed();
+        if target > elapsed {
+            tokio::time::sleep(target - elapsed).await;
+        }
+    }
+
+    /// Restart the rate calculation, e.g. after waiting for the window
+    pub fn reset(&mut self) {
+        self.started = Instant::now();
+        self.sent = 0;
+    }
+}
+
+/// Ciphertext staged in the drop cache that has not reached IPFS yet
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingUpload {
+    pub cid: String,
+    pub ciphertext_path: PathBuf,
+    pub queued_at: u64,
+    /// Failed attempts so far; each one doubles the wait before the next
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub next_attempt_at: u64,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+/// Uploads waiting for their window, persisted in the drop cache so they survive restarts
+pub struct PendingUploads {
+    path: PathBuf,
+    entries: Vec<PendingUpload>,
+}
+
+impl PendingUploads {
+    pub fn load(cache_dir: &Path) -> Result<Self> {
+        let path = cache_dir.join(PENDING_UPLOADS_FILE);
+        let entries = if let Some(json) =
+            no_traces::read_file(&path).context("Failed to read pending uploads")?
+        {
+            serde_json::from_slice(&json).context("Failed to parse pending uploads")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self { path, entries })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create drop cache directory")?;
+        }
+        let json = serde_json::to_string_pretty(&self.entries)?;
+        no_traces::write_file(&self.path, json).context("Failed to write pending uploads")?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[PendingUpload] {
+        &self.entries
+    }
+
+    pub fn push(&mut self, upload: PendingUpload) {
+        self.entries.retain(|e| e.cid != upload.cid);
+        self.entries.push(upload);
+    }
+
+    pub fn remove(&mut self, cid: &str) {
+        self.entries.retain(|e| e.cid != cid);
+    }
+
+    pub fn contains(&self, cid: &str) -> bool {
+        self.entries.iter().any(|e| e.cid == cid)
+    }
+
+    /// Push the next attempt back with exponential backoff
+    pub fn record_failure(&mut self, cid: &str, error: &str) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.cid == cid) {
+            entry.attempts += 1;
+            entry.next_attempt_at = now_secs() + retry_delay(entry.attempts);
+            entry.last_error = Some(error.to_string());
+        }
+    }
+
+    /// Make every entry due right away
+    pub fn reset_backoff(&mut self) {
+        for entry in &mut self.entries {
+            entry.next_attempt_at = 0;
+        }
+    }
+}
+
+/// Seconds to wait after the `attempts`th failure: 30s doubling up to an hour
+fn retry_delay(attempts: u32) -> u64 {
+    let doublings = attempts.saturating_sub(1).min(16);
+    (RETRY_BASE_SECS << doublings).min(RETRY_MAX_SECS)
+}
+
+/// CIDs currently being uploaded, so queued uploads are not sent twice
+static ACTIVE_UPLOADS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static QUEUE_CHANGED: Notify = Notify::const_new();
+
+/// Mark an upload as in progress; false if it already is
+pub fn claim(cid: &str) -> bool {
+    let mut active = ACTIVE_UPLOADS.lock().unwrap();
+    if active.iter().any(|c| c == cid) {
+        return false;
+    }
+    active.push(cid.to_string());
+    true
+}
+
+pub fn release(cid: &str) {
+    ACTIVE_UPLOADS.lock().unwrap().retain(|c| c != cid);
+}
+
+/// Have the upload worker look at the queue now instead of at its next poll
+pub fn wake_worker() {
+    QUEUE_CHANGED.notify_one();
+}
+
+/// Upload queued drops in the background, resuming uploads left over from a
+/// previous run. Nothing is attempted while the IPFS daemon is unreachable,
+/// so an outage does not use up retries. Emits `drop_ready` with the CID of
+/// each finished upload.
+pub fn start_upload_worker(app: tauri::AppHandle, data_dir: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        let cache_dir = drop_cache_dir(&data_dir);
+        let mut daemon_up = true;
+        loop {
+            let waiting = PendingUploads::load(&cache_dir).map_or(0, |p| p.entries().len());
+            if waiting > 0 {
+                let reachable = match ipfs::client() {
+                    Ok(client) => health::ipfs_version(&client).await.is_ok(),
+                    Err(_) => false,
+                };
+                if reachable && !daemon_up {
+                    info!("IPFS daemon is back, retrying {} queued uploads", waiting);
+                    if let Ok(mut pending) = PendingUploads::load(&cache_dir) {
+                        pending.reset_backoff();
+                        if let Err(e) = pending.save() {
+                            warn!("Failed to update pending uploads: {}", e);
+                        }
+                    }
+                } else if !reachable && daemon_up {
+                    warn!(
+                        "IPFS daemon unreachable, holding {} queued uploads",
+                        waiting
+                    );
+                }
+                daemon_up = reachable;
+            }
+
+            if waiting > 0 && daemon_up {
+                let options = DropOptions {
+                    cache_dir: Some(cache_dir.clone()),
+                    ..settings::drop_settings().drop_options(&data_dir)
+                };
+                match upload_pending(&options).await {
+                    Ok(uploaded) => {
+                        for cid in uploaded {
+                            if let Err(e) = mfs::link_catalogued(&data_dir, &cid).await {
+                                warn!("Drop {} not linked into MFS: {}", cid, e);
+                            }
+                            events::emit(&app, "drop_ready", cid);
+                        }
+                    }
+                    Err(e) => warn!("Queued uploads failed: {}", e),
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(QUEUE_POLL_SECS)) => {}
+                _ = QUEUE_CHANGED.notified() => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(retry_delay(1), 30);
+        assert_eq!(retry_delay(2), 60);
+        assert_eq!(retry_delay(5), 480);
+        assert_eq!(retry_delay(40), RETRY_MAX_SECS);
+    }
+}
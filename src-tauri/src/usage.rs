@@ -0,0 +1,177 @@
+use crate::catalog::now_secs;
+use crate::settings;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const USAGE_FILE: &str = "usage.enc";
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Local usage statistics are off unless the user turns them on. They are
+/// never sent anywhere.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UsageSettings {
+    pub enabled: bool,
+}
+
+/// Counters since `since`, or since the last reset
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct UsageStats {
+    pub since: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub message_bytes_sent: u64,
+    pub message_bytes_received: u64,
+    pub drops_created: u64,
+    pub drops_retrieved: u64,
+    pub bytes_uploaded: u64,
+    pub bytes_downloaded: u64,
+    pub uptime_secs: u64,
+}
+
+pub enum UsageEvent {
+    MessageSent { bytes: usize },
+    MessageReceived { bytes: usize },
+    DropCreated { bytes: u64 },
+    DropRetrieved { bytes: u64 },
+}
+
+/// Stats in memory, written back to `path` by the flush loop
+struct Tracker {
+    path: PathBuf,
+    stats: UsageStats,
+    dirty: bool,
+}
+
+static TRACKER: Mutex<Option<Tracker>> = Mutex::new(None);
+
+/// Count an event if usage statistics are enabled
+pub fn record(event: UsageEvent) {
+    if !settings::usage_settings().enabled {
+        return;
+    }
+    let mut tracker = TRACKER.lock().unwrap();
+    let Some(tracker) = tracker.as_mut() else {
+        return;
+    };
+
+    let stats = &mut tracker.stats;
+    match event {
+        UsageEvent::MessageSent { bytes } => {
+            stats.messages_sent += 1;
+            stats.message_bytes_sent += bytes as u64;
+        }
+        UsageEvent::MessageReceived { bytes } => {
+            stats.messages_received += 1;
+            stats.message_bytes_received += bytes as u64;
+        }
+        UsageEvent::DropCreated { bytes } => {
+            stats.drops_created += 1;
+            stats.bytes_uploaded += bytes;
+        }
+        UsageEvent::DropRetrieved { bytes } => {
+            stats.drops_retrieved += 1;
+            stats.bytes_downloaded += bytes;
+        }
+    }
+    tracker.dirty = true;
+}
+
+/// Load saved stats and keep counting uptime, writing changes back every minute
+pub fn start_usage_tracker(data_dir: &Path) {
+    let path = data_dir.join(USAGE_FILE);
+    let stats = load(&path).unwrap_or_else(|e| {
+        warn!("Starting usage statistics over: {}", e);
+        UsageStats {
+            since: now_secs(),
+            ..Default::default()
+        }
+    });
+    *TRACKER.lock().unwrap() = Some(Tracker {
+        path,
+        stats,
+        dirty: false,
+    });
+
+    tauri::async_runtime::spawn(async move {
+        let mut last_tick = Instant::now();
+        loop {
+            tokio::time::sleep(FLUSH_INTERVAL).await;
+            let elapsed = last_tick.elapsed().as_secs();
+            last_tick = Instant::now();
+
+            let mut tracker = TRACKER.lock().unwrap();
+            let Some(tracker) = tracker.as_mut() else {
+                continue;
+            };
+            if settings::usage_settings().enabled {
+                tracker.stats.uptime_secs += elapsed;
+                tracker.dirty = true;
+            }
+            if let Err(e) = tracker.flush() {
+                warn!("Failed to save usage statistics: {}", e);
+            }
+        }
+    });
+}
+
+/// Current stats, saved first so the file matches what the user sees
+pub fn usage_stats() -> Result<UsageStats> {
+    let mut tracker = TRACKER.lock().unwrap();
+    let tracker = tracker
+        .as_mut()
+        .context("Usage statistics are not available")?;
+    tracker.flush()?;
+    Ok(tracker.stats.clone())
+}
+
+/// Zero every counter and start counting from now
+pub fn reset_usage_stats() -> Result<UsageStats> {
+    let mut tracker = TRACKER.lock().unwrap();
+    let tracker = tracker
+        .as_mut()
+        .context("Usage statistics are not available")?;
+    tracker.stats = UsageStats {
+        since: now_secs(),
+        ..Default::default()
+    };
+    tracker.dirty = true;
+    tracker.flush()?;
+    info!("Usage statistics reset");
+    Ok(tracker.stats.clone())
+}
+
+impl Tracker {
+    fn flush(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let key = settings::storage_key().context("Settings key not available")?;
+        let json = serde_json::to_vec(&self.stats)?;
+        fs::write(&self.path, key.encrypt_file(&json)?)
+            .context("Failed to write usage statistics")?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Encrypted with the settings key, so counting works before unlock
+fn load(path: &Path) -> Result<UsageStats> {
+    if !path.exists() {
+        return Ok(UsageStats {
+            since: now_secs(),
+            ..Default::default()
+        });
+    }
+    let key = settings::storage_key().context("Settings key not available")?;
+    let encrypted = fs::read(path).context("Failed to read usage statistics")?;
+    let json = key
+        .decrypt_file(&encrypted)
+        .context("Failed to decrypt usage statistics")?;
+    serde_json::from_slice(&json).context("Failed to parse usage statistics")
+}
@@ -0,0 +1,370 @@
+use crate::catalog::now_secs;
+use crate::crypto::{Identity, SessionKey};
+use crate::dead_drop::{
+    cat_bytes_from_ipfs, download_and_decrypt, encrypt_and_upload, is_upload_pending,
+    upload_bytes_to_ipfs, DeadDropCreated, DropOptions,
+};
+use crate::ipfs;
+use crate::no_traces;
+use crate::shards::{recover_session_key, split_session_key};
+use crate::wire;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+use zeroize::Zeroize;
+
+const VERSIONS_REGISTRY_FILE: &str = "versions.enc";
+const IPNS_KEY_PREFIX: &str = "deaddrop-";
+const IPNS_LIFETIME: &str = "8760h"; // 1 year
+
+/// One published version of a drop
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DropVersion {
+    pub version: u32,
+    pub cid: String,
+    pub created_at: u64,
+    /// Content key for this version (hex) if rotated, otherwise the root key is used
+    pub rotated_key: Option<String>,
+}
+
+/// Pointer record published under the drop ID, encrypted with the root key
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DropManifest {
+    pub drop_id: String,
+    pub versions: Vec<DropVersion>,
+}
+
+impl DropManifest {
+    /// Latest version, or a specific one if requested
+    pub fn select(&self, version: Option<u32>) -> Result<&DropVersion> {
+        match version {
+            Some(v) => self
+                .versions
+                .iter()
+                .find(|entry| entry.version == v)
+                .with_context(|| format!("Version {} not found", v)),
+            None => self
+                .versions
+                .iter()
+                .max_by_key(|entry| entry.version)
+                .context("Drop has no versions"),
+        }
+    }
+}
+
+/// Version summary returned to the frontend (no key material)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DropVersionInfo {
+    pub drop_id: String,
+    pub version: u32,
+    pub cid: String,
+    pub rotated_key: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct VersionedDropEntry {
+    drop_id: String,
+    key_name: String,
+    root_key: String, // hex
+    manifest: DropManifest,
+}
+
+/// Creator-side registry of versioned drops, encrypted with the local identity
+struct VersionsRegistry {
+    path: PathBuf,
+    storage_key: SessionKey,
+    entries: Vec<VersionedDropEntry>,
+}
+
+impl VersionsRegistry {
+    fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(VERSIONS_REGISTRY_FILE);
+        let storage_key = identity.storage_key(b"versions-registry");
+
+        let entries = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read versions registry")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt versions registry")?;
+            let entries = serde_json::from_slice(&json);
+            json.zeroize();
+            entries.context("Failed to parse versions registry")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            storage_key,
+            entries,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut json = serde_json::to_vec(&self.entries)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?)
+            .context("Failed to write versions registry")?;
+        Ok(())
+    }
+}
+
+/// Create a versioned drop: like a normal drop, but published under a stable
+/// IPNS drop ID pointing at an encrypted manifest of versions
+pub async fn create_versioned_drop(
+    file_path: &str,
+    threshold: u8,
+    total_shards: u8,
+    options: &DropOptions,
+    data_dir: &Path,
+    identity: &Identity,
+) -> Result<DeadDropCreated> {
+    if threshold > total_shards {
+        anyhow::bail!("Threshold cannot exceed total shards");
+    }
+    if threshold < 2 {
+        anyhow::bail!("Threshold must be at least 2");
+    }
+
+    let root_key = SessionKey::generate();
+    let cid = encrypt_and_upload(file_path, &root_key, options).await?;
+
+    let key_name = format!("{}{}", IPNS_KEY_PREFIX, uuid::Uuid::new_v4());
+    let drop_id = generate_ipns_key(&key_name).await?;
+
+    let manifest = DropManifest {
+        drop_id: drop_id.clone(),
+        versions: vec![DropVersion {
+            version: 1,
+            cid: cid.clone(),
+            created_at: now_secs(),
+            rotated_key: None,
+        }],
+    };
+    publish_manifest(&manifest, &root_key, &key_name).await?;
+
+    let mut root_bytes = root_key.as_bytes();
+    let mut registry = VersionsRegistry::load(data_dir, identity)?;
+    registry.entries.push(VersionedDropEntry {
+        drop_id: drop_id.clone(),
+        key_name,
+        root_key: hex::encode(root_bytes),
+        manifest,
+    });
+    root_bytes.zeroize();
+    registry.save()?;
+
+    let shards = split_session_key(&root_key, threshold, total_shards);
+    info!("Created versioned drop {} (v1: {})", drop_id, cid);
+
+    Ok(DeadDropCreated {
+        pending: is_upload_pending(options, &cid),
+        cid,
+        shards,
+        drop_id: Some(drop_id),
+        piece_cids: Vec::new(),
+        shred: None,
+        cipher: Some(options.cipher),
+    })
+}
+
+/// Publish a new version of a drop under its existing drop ID
+pub async fn update_versioned_drop(
+    drop_id: &str,
+    file_path: &str,
+    rotate_key: bool,
+    options: &DropOptions,
+    data_dir: &Path,
+    identity: &Identity,
+) -> Result<DropVersionInfo> {
+    let mut registry = VersionsRegistry::load(data_dir, identity)?;
+    let entry = registry
+        .entries
+        .iter_mut()
+        .find(|e| e.drop_id == drop_id)
+        .context("Unknown drop ID - only the creator can update a drop")?;
+
+    let mut root_bytes = hex::decode(&entry.root_key).context("Corrupt root key")?;
+    let root_key = SessionKey::from_bytes(&root_bytes);
+    root_bytes.zeroize();
+    let root_key = root_key?;
+
+    let (content_key, rotated_key) = if rotate_key {
+        let key = SessionKey::generate();
+        let mut key_bytes = key.as_bytes();
+        let key_hex = hex::encode(key_bytes);
+        key_bytes.zeroize();
+        (key, Some(key_hex))
+    } else {
+        (root_key.clone(), None)
+    };
+
+    let cid = encrypt_and_upload(file_path, &content_key, options).await?;
+
+    let version = entry.manifest.select(None)?.version + 1;
+    entry.manifest.versions.push(DropVersion {
+        version,
+        cid: cid.clone(),
+        created_at: now_secs(),
+        rotated_key,
+    });
+
+    publish_manifest(&entry.manifest, &root_key, &entry.key_name).await?;
+    registry.save()?;
+
+    info!("Published version {} of drop {}: {}", version, drop_id, cid);
+
+    Ok(DropVersionInfo {
+        drop_id: drop_id.to_string(),
+        version,
+        cid,
+        rotated_key: rotate_key,
+    })
+}
+
+/// Retrieve a versioned drop, resolving the drop ID to the latest version
+/// unless a specific version is requested
+pub async fn retrieve_versioned_drop(
+    drop_id: &str,
+    version: Option<u32>,
+    shard_strings: Vec<String>,
+    output_path: &str,
+    options: &DropOptions,
+) -> Result<DropVersionInfo> {
+    let root_key = recover_session_key(&shard_strings)?;
+    let manifest = fetch_manifest(drop_id, &root_key).await?;
+    let selected = manifest.select(version)?;
+
+    let content_key = match &selected.rotated_key {
+        Some(key_hex) => {
+            let mut key_bytes = hex::decode(key_hex).context("Corrupt version key")?;
+            let key = SessionKey::from_bytes(&key_bytes);
+            key_bytes.zeroize();
+            key?
+        }
+        None => root_key,
+    };
+
+    download_and_decrypt(&selected.cid, &content_key, output_path, options).await?;
+
+    Ok(DropVersionInfo {
+        drop_id: drop_id.to_string(),
+        version: selected.version,
+        cid: selected.cid.clone(),
+        rotated_key: selected.rotated_key.is_some(),
+    })
+}
+
+/// List versions of a drop (requires shards to decrypt the manifest)
+pub async fn list_drop_versions(
+    drop_id: &str,
+    shard_strings: Vec<String>,
+) -> Result<Vec<DropVersionInfo>> {
+    let root_key = recover_session_key(&shard_strings)?;
+    let manifest = fetch_manifest(drop_id, &root_key).await?;
+
+    Ok(manifest
+        .versions
+        .iter()
+        .map(|v| DropVersionInfo {
+            drop_id: drop_id.to_string(),
+            version: v.version,
+            cid: v.cid.clone(),
+            rotated_key: v.rotated_key.is_some(),
+        })
+        .collect())
+}
+
+/// Encrypt the manifest with the root key, upload it and point the IPNS name at it
+async fn publish_manifest(manifest: &DropManifest, root_key: &SessionKey, key_name: &str) -> Result<()> {
+    let mut encoded = wire::encode(manifest)?;
+    let encrypted = root_key.encrypt_file(&encoded);
+    encoded.zeroize();
+
+    let manifest_cid = upload_bytes_to_ipfs(encrypted?).await?;
+
+    let client = ipfs::client()?;
+    let ipfs_path = format!("/ipfs/{}", manifest_cid);
+    let response = client
+        .post(ipfs::api("name/publish"))
+        .query(&[
+            ("arg", ipfs_path.as_str()),
+            ("key", key_name),
+            ("lifetime", IPNS_LIFETIME),
+            ("allow-offline", "true"),
+        ])
+        .send()
+        .await
+        .context("Failed to publish IPNS record")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("IPNS publish failed: {}", response.status());
+    }
+
+    info!("Published manifest {} under {}", manifest_cid, key_name);
+    Ok(())
+}
+
+/// Resolve the drop ID and decrypt the manifest it points to
+async fn fetch_manifest(drop_id: &str, root_key: &SessionKey) -> Result<DropManifest> {
+    let client = ipfs::client()?;
+    let response = client
+        .post(ipfs::api("name/resolve"))
+        .query(&[("arg", drop_id)])
+        .send()
+        .await
+        .context("Failed to resolve drop ID")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("IPNS resolve failed: {}", response.status());
+    }
+
+    let json: serde_json::Value = response.json().await?;
+    let path = json["Path"]
+        .as_str()
+        .context("No Path in IPNS response")?;
+
+    let encrypted = cat_bytes_from_ipfs(path).await?;
+    let mut manifest_bytes = root_key
+        .decrypt_file(&encrypted)
+        .context("Failed to decrypt drop manifest - wrong shards?")?;
+    let manifest = wire::decode::<DropManifest>(&manifest_bytes);
+    manifest_bytes.zeroize();
+    let manifest = manifest.context("Invalid drop manifest")?;
+
+    if manifest.drop_id != drop_id {
+        anyhow::bail!("Manifest does not belong to drop {}", drop_id);
+    }
+
+    Ok(manifest)
+}
+
+/// Create a new IPNS key on the local node and return its name (the drop ID)
+async fn generate_ipns_key(key_name: &str) -> Result<String> {
+    let client = ipfs::client()?;
+    let response = client
+        .post(ipfs::api("key/gen"))
+        .query(&[("arg", key_name), ("type", "ed25519")])
+        .send()
+        .await
+        .context("Failed to generate IPNS key")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("IPNS key generation failed: {}", response.status());
+    }
+
+    let json: serde_json::Value = response.json().await?;
+    let id = json["Id"]
+        .as_str()
+        .context("No Id in IPNS key response")?
+        .to_string();
+
+    Ok(id)
+}
@@ -0,0 +1,277 @@
+use crate::catalog::now_secs;
+use crate::crypto::{Identity, SessionKey};
+use crate::no_traces;
+use crate::p2p::GhostMessage;
+use crate::shred::shred_file;
+use anyhow::{Context, Result};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+const VIEW_ONCE_FILE: &str = "view_once.enc";
+const VIEW_ONCE_DIR: &str = "view_once";
+/// An opened message whose display was never confirmed (the window closed
+/// or crashed) is wiped after this long
+const UNCONFIRMED_SECS: u64 = 10 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewOnceState {
+    /// Not opened yet; the content can be read once
+    Pending,
+    /// Handed to the frontend, waiting for it to confirm the display
+    Opened,
+    /// Content and key are gone; only this record is left
+    Viewed,
+}
+
+/// What is kept about a view-once message. After viewing it is a tombstone:
+/// who sent it and when, never what it said.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ViewOnceRecord {
+    pub id: String,
+    pub from: String,
+    pub timestamp: u64,
+    pub state: ViewOnceState,
+    pub received_at: u64,
+    pub opened_at: Option<u64>,
+    pub viewed_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    record: ViewOnceRecord,
+    /// File under `view_once/` holding the content
+    file: String,
+    /// One-time key of the content file, hex; `None` once destroyed
+    key: Option<String>,
+}
+
+impl Entry {
+    /// Destroy the key and the content file, leaving the tombstone
+    fn wipe(&mut self, dir: &Path) {
+        if let Some(mut key) = self.key.take() {
+            key.zeroize();
+        }
+        let path = dir.join(&self.file);
+        if !no_traces::forget_file(&path) && path.exists() {
+            if let Err(e) = shred_file(&path) {
+                warn!(
+                    "Failed to shred view-once message {}: {}",
+                    self.record.id, e
+                );
+                let _ = fs::remove_file(&path);
+            }
+        }
+        self.record.state = ViewOnceState::Viewed;
+        self.record.viewed_at = Some(now_secs());
+    }
+}
+
+/// View-once messages received, encrypted with the local identity. Each
+/// message's content, including inline attachments, is in its own file
+/// encrypted with a random key that only this store knows.
+pub struct ViewOnceStore {
+    path: PathBuf,
+    dir: PathBuf,
+    storage_key: SessionKey,
+    entries: Vec<Entry>,
+}
+
+impl ViewOnceStore {
+    /// Load the store, starting empty if it does not exist yet
+    pub fn load(data_dir: &Path, identity: &Identity) -> Result<Self> {
+        let path = data_dir.join(VIEW_ONCE_FILE);
+        let storage_key = identity.storage_key(b"view-once");
+
+        let entries = if let Some(encrypted) =
+            no_traces::read_file(&path).context("Failed to read view-once messages")?
+        {
+            let mut json = storage_key
+                .decrypt_file(&encrypted)
+                .context("Failed to decrypt view-once messages")?;
+            let entries = serde_json::from_slice(&json);
+            json.zeroize();
+            entries.context("Failed to parse view-once messages")?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            dir: data_dir.join(VIEW_ONCE_DIR),
+            storage_key,
+            entries,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        let mut json = serde_json::to_vec(&self.entries)?;
+        let encrypted = self.storage_key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(&self.path, encrypted?)
+            .context("Failed to write view-once messages")?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<ViewOnceRecord> {
+        self.entries.iter().map(|e| e.record.clone()).collect()
+    }
+
+    /// Move a received message's content into the store and blank it in
+    /// `message`. A message already known keeps its state, so a resend
+    /// can't bring back one that was viewed.
+    pub fn stash(&mut self, message: &mut GhostMessage) -> Result<()> {
+        if !self.entries.iter().any(|e| e.record.id == message.id) {
+            let key = SessionKey::generate();
+            let mut name = [0u8; 16];
+            rand::thread_rng().fill_bytes(&mut name);
+            let file = hex::encode(name);
+            let encrypted = key.encrypt_file(message.content.as_bytes())?;
+            if !no_traces::active() {
+                fs::create_dir_all(&self.dir).context("Failed to create view-once directory")?;
+            }
+            no_traces::write_file(&self.dir.join(&file), encrypted)
+                .context("Failed to write view-once message")?;
+
+            let mut key_bytes = key.as_bytes();
+            self.entries.push(Entry {
+                record: ViewOnceRecord {
+                    id: message.id.clone(),
+                    from: message.from.clone(),
+                    timestamp: message.timestamp,
+                    state: ViewOnceState::Pending,
+                    received_at: now_secs(),
+                    opened_at: None,
+                    viewed_at: None,
+                },
+                file,
+                key: Some(hex::encode(key_bytes)),
+            });
+            key_bytes.zeroize();
+        }
+        message.content.zeroize();
+        Ok(())
+    }
+
+    /// The content of a pending message; it can't be read a second time
+    pub fn open(&mut self, id: &str) -> Result<String> {
+        let entry = self.entry(id)?;
+        if entry.record.state != ViewOnceState::Pending {
+            anyhow::bail!("Message was already viewed");
+        }
+        let mut key_hex = entry.key.clone().context("Message key was destroyed")?;
+        let key_bytes = hex::decode(&key_hex);
+        key_hex.zeroize();
+        let mut key_bytes = key_bytes.context("Invalid message key")?;
+        let key = SessionKey::from_bytes(&key_bytes);
+        key_bytes.zeroize();
+
+        let encrypted = no_traces::read_file(&self.dir.join(&entry.file))
+            .context("Failed to read view-once message")?
+            .context("View-once message is gone")?;
+        let plain = key?
+            .decrypt_file(&encrypted)
+            .context("Failed to decrypt view-once message")?;
+        let content = String::from_utf8(plain).context("Message is not valid text")?;
+
+        entry.record.state = ViewOnceState::Opened;
+        entry.record.opened_at = Some(now_secs());
+        Ok(content)
+    }
+
+    /// The frontend showed the message (or the user dismissed it): destroy
+    /// the key and content and keep the tombstone
+    pub fn confirm_displayed(&mut self, id: &str) -> Result<ViewOnceRecord> {
+        let dir = self.dir.clone();
+        let entry = self.entry(id)?;
+        if entry.record.state != ViewOnceState::Viewed {
+            entry.wipe(&dir);
+            info!("Wiped view-once message {}", id);
+        }
+        Ok(entry.record.clone())
+    }
+
+    /// Wipe messages opened but never confirmed; true if any were
+    pub fn expire_unconfirmed(&mut self) -> bool {
+        let cutoff = now_secs().saturating_sub(UNCONFIRMED_SECS);
+        let mut expired = false;
+        for entry in &mut self.entries {
+            if entry.record.state == ViewOnceState::Opened
+                && entry.record.opened_at.unwrap_or(0) <= cutoff
+            {
+                entry.wipe(&self.dir);
+                expired = true;
+            }
+        }
+        expired
+    }
+
+    fn entry(&mut self, id: &str) -> Result<&mut Entry> {
+        self.entries
+            .iter_mut()
+            .find(|e| e.record.id == id)
+            .context("No such view-once message")
+    }
+}
+
+/// Keep a received view-once message out of the event stream: the content
+/// goes into the store and the emitted message carries only the flag
+pub fn stash(data_dir: &Path, identity: &Identity, message: &mut GhostMessage) -> Result<()> {
+    let mut store = ViewOnceStore::load(data_dir, identity)?;
+    store.stash(message)?;
+    store.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> GhostMessage {
+        GhostMessage {
+            id: "3f2a".to_string(),
+            from: "sender".to_string(),
+            content: "burn this".to_string(),
+            timestamp: 1,
+            view_once: true,
+            location: None,
+        }
+    }
+
+    #[test]
+    fn opens_once_and_leaves_a_tombstone() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = Identity::generate();
+
+        let mut received = message();
+        stash(dir.path(), &identity, &mut received).unwrap();
+        assert!(received.content.is_empty());
+
+        let mut store = ViewOnceStore::load(dir.path(), &identity).unwrap();
+        assert_eq!(store.open("3f2a").unwrap(), "burn this");
+        assert!(store.open("3f2a").is_err());
+
+        let record = store.confirm_displayed("3f2a").unwrap();
+        assert_eq!(record.state, ViewOnceState::Viewed);
+        assert!(store.entries[0].key.is_none());
+        assert_eq!(
+            fs::read_dir(dir.path().join(VIEW_ONCE_DIR))
+                .unwrap()
+                .count(),
+            0
+        );
+
+        // A resend of the same message stays viewed
+        let mut resent = message();
+        store.stash(&mut resent).unwrap();
+        assert!(resent.content.is_empty());
+        assert!(store.open("3f2a").is_err());
+    }
+}
@@ -0,0 +1,318 @@
+use crate::catalog::DropRecord;
+use crate::crypto::{Identity, SessionKey};
+use crate::dead_drop::{create_dead_drop, new_drop_cache, DropOptions};
+use crate::events;
+use crate::groups::{load_recipient_group, record_group_drop, Group};
+use crate::no_traces;
+use crate::session::AppState;
+use crate::settings;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tauri::Manager;
+use tracing::{info, warn};
+use zeroize::Zeroize;
+
+const WATCH_STATE_FILE: &str = "watch_folder.enc";
+const MIN_INTERVAL_SECS: u64 = 10;
+/// Names editors and browsers use while a file is still being written
+const PARTIAL_SUFFIXES: &[&str] = &[".part", ".partial", ".tmp", ".crdownload", ".download", "~"];
+
+/// Folder whose new files become group drops automatically. Every member of
+/// the group gets one shard, so the group size is the shard count.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WatchFolderSettings {
+    pub enabled: bool,
+    pub path: Option<PathBuf>,
+    pub group_id: Option<String>,
+    pub threshold: u8,
+    pub interval_secs: u64,
+}
+
+impl Default for WatchFolderSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            group_id: None,
+            threshold: 2,
+            interval_secs: 30,
+        }
+    }
+}
+
+impl WatchFolderSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.threshold < 2 {
+            anyhow::bail!("Threshold must be at least 2");
+        }
+        if !self.enabled {
+            return Ok(());
+        }
+        let path = self.path.as_ref().context("No folder to watch")?;
+        if !path.is_absolute() {
+            anyhow::bail!("Watched folder must be an absolute path");
+        }
+        if self.group_id.is_none() {
+            anyhow::bail!("Watched folder needs a recipient group");
+        }
+        Ok(())
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(MIN_INTERVAL_SECS))
+    }
+}
+
+/// A file in the watched folder as last seen; a changed file is dropped again
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct FileVersion {
+    size: u64,
+    modified: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct WatchedFile {
+    path: PathBuf,
+    version: FileVersion,
+    cid: String,
+    dropped_at: u64,
+}
+
+/// Files already dropped, encrypted with the local identity
+#[derive(Serialize, Deserialize, Default)]
+struct WatchState {
+    dropped: Vec<WatchedFile>,
+}
+
+impl WatchState {
+    fn load(path: &Path, key: &SessionKey) -> Result<Self> {
+        let Some(encrypted) =
+            no_traces::read_file(path).context("Failed to read watch folder state")?
+        else {
+            return Ok(Self::default());
+        };
+        let mut json = key
+            .decrypt_file(&encrypted)
+            .context("Failed to decrypt watch folder state")?;
+        let state = serde_json::from_slice(&json);
+        json.zeroize();
+        state.context("Failed to parse watch folder state")
+    }
+
+    fn save(&self, path: &Path, key: &SessionKey) -> Result<()> {
+        let mut json = serde_json::to_vec(self)?;
+        let encrypted = key.encrypt_file(&json);
+        json.zeroize();
+        no_traces::write_file(path, encrypted?).context("Failed to write watch folder state")?;
+        Ok(())
+    }
+
+    fn is_dropped(&self, path: &Path, version: &FileVersion) -> bool {
+        self.dropped
+            .iter()
+            .any(|f| f.path == path && &f.version == version)
+    }
+}
+
+/// Result of one watched file, emitted as `watch_drop_created` or
+/// `watch_drop_failed`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WatchDropResult {
+    pub path: PathBuf,
+    pub record: Option<DropRecord>,
+    pub error: Option<String>,
+}
+
+/// Poll the watched folder for the lifetime of the app. Files are dropped once
+/// their size and modification time hold still for a whole interval, and only
+/// while the session is unlocked.
+pub fn start_watch_folder(app: tauri::AppHandle, data_dir: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        // Seen on the previous scan but not yet settled
+        let mut last_seen: HashMap<PathBuf, FileVersion> = HashMap::new();
+        // Failed for this version; retried once the file changes
+        let mut failed: HashMap<PathBuf, FileVersion> = HashMap::new();
+        loop {
+            let watch = settings::watch_folder_settings();
+            tokio::time::sleep(watch.interval()).await;
+
+            let (true, Some(dir)) = (watch.enabled, watch.path.as_ref()) else {
+                last_seen.clear();
+                continue;
+            };
+            let Some(identity) = app.state::<AppState>().peek_identity() else {
+                continue;
+            };
+
+            let current = match scan(dir) {
+                Ok(current) => current,
+                Err(e) => {
+                    warn!("Failed to scan watched folder: {}", e);
+                    continue;
+                }
+            };
+            let state_path = data_dir.join(WATCH_STATE_FILE);
+            let key = identity.storage_key(b"watch_folder");
+            let mut state = match WatchState::load(&state_path, &key) {
+                Ok(state) => state,
+                Err(e) => {
+                    warn!("Watch folder paused: {}", e);
+                    continue;
+                }
+            };
+
+            for (path, version) in &current {
+                let settled = last_seen.get(path) == Some(version);
+                if !settled || state.is_dropped(path, version) || failed.get(path) == Some(version)
+                {
+                    continue;
+                }
+
+                let result = drop_file(&app, &data_dir, &identity, &watch, path).await;
+                match result {
+                    Ok(record) => {
+                        state.dropped.retain(|f| &f.path != path);
+                        state.dropped.push(WatchedFile {
+                            path: path.clone(),
+                            version: version.clone(),
+                            cid: record.cid.clone(),
+                            dropped_at: record.created_at,
+                        });
+                        if let Err(e) = state.save(&state_path, &key) {
+                            warn!("Failed to save watch folder state: {}", e);
+                        }
+                        failed.remove(path);
+                        events::emit(
+                            &app,
+                            "watch_drop_created",
+                            WatchDropResult {
+                                path: path.clone(),
+                                record: Some(record),
+                                error: None,
+                            },
+                        );
+                    }
+                    Err(e) => {
+                        warn!("Watched file {} not dropped: {:#}", path.display(), e);
+                        failed.insert(path.clone(), version.clone());
+                        events::emit(
+                            &app,
+                            "watch_drop_failed",
+                            WatchDropResult {
+                                path: path.clone(),
+                                record: None,
+                                error: Some(format!("{:#}", e)),
+                            },
+                        );
+                    }
+                }
+            }
+
+            // Forget files that were removed, so they are dropped again if they return
+            let before = state.dropped.len();
+            state.dropped.retain(|f| current.contains_key(&f.path));
+            if state.dropped.len() != before {
+                if let Err(e) = state.save(&state_path, &key) {
+                    warn!("Failed to save watch folder state: {}", e);
+                }
+            }
+            failed.retain(|path, _| current.contains_key(path));
+            last_seen = current;
+        }
+    });
+}
+
+/// Regular files directly in the folder, skipping hidden and partial ones
+fn scan(dir: &Path) -> Result<HashMap<PathBuf, FileVersion>> {
+    let mut files = HashMap::new();
+    for entry in fs::read_dir(dir).context("Failed to read watched folder")? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') || PARTIAL_SUFFIXES.iter().any(|s| name.ends_with(s)) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        files.insert(
+            entry.path(),
+            FileVersion {
+                size: metadata.len(),
+                modified,
+            },
+        );
+    }
+    Ok(files)
+}
+
+/// Encrypt and upload one file as a group drop, record it in the catalog and
+/// send the members their shards if Ghost Mode is running
+async fn drop_file(
+    app: &tauri::AppHandle,
+    data_dir: &Path,
+    identity: &Identity,
+    watch: &WatchFolderSettings,
+    path: &Path,
+) -> Result<DropRecord> {
+    let group = load_group(data_dir, identity, watch)?;
+    let threshold = watch.threshold;
+    let total_shards = u8::try_from(group.members.len()).context("Group is too large")?;
+    let options = DropOptions {
+        cache_dir: new_drop_cache(data_dir),
+        ..settings::drop_settings().drop_options(data_dir)
+    };
+
+    let file_path = path
+        .to_str()
+        .context("Watched file name is not valid UTF-8")?;
+    let created = create_dead_drop(file_path, threshold, total_shards, &options).await?;
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let record = record_group_drop(
+        app, data_dir, identity, &group, threshold, file_name, created,
+    )
+    .await?;
+    info!(
+        "Dropped watched file {} as {}",
+        record.file_name, record.cid
+    );
+    Ok(record)
+}
+
+fn load_group(data_dir: &Path, identity: &Identity, watch: &WatchFolderSettings) -> Result<Group> {
+    let group_id = watch
+        .group_id
+        .as_deref()
+        .context("Watched folder needs a recipient group")?;
+    load_recipient_group(data_dir, identity, group_id, watch.threshold)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_skips_hidden_and_partial_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("report.pdf"), b"done").unwrap();
+        fs::write(dir.path().join(".report.pdf.swp"), b"").unwrap();
+        fs::write(dir.path().join("video.mp4.crdownload"), b"").unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+
+        let files = scan(dir.path()).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[&dir.path().join("report.pdf")].size, 4);
+    }
+}
@@ -0,0 +1,267 @@
+use crate::catalog::now_secs;
+use crate::settings;
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::warn;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+const SIGNATURE_HEADER: &str = "X-Control-Signature";
+const EVENT_HEADER: &str = "X-Control-Event";
+
+/// Events a webhook can subscribe to
+pub const WEBHOOK_EVENTS: &[&str] = &[
+    "ghost_msg",
+    "msg_delivered",
+    "drop_created",
+    "drop_updated",
+    "drop_retrieved",
+    "drop_verified",
+    "drop_unpinned",
+    "drop_imported",
+    "drop_ready",
+    "drop_at_risk",
+    "drop_burned",
+    "canary_alert",
+];
+
+/// Outbound HTTP calls to the user's own services when messages arrive or
+/// drops change
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct WebhookSettings {
+    pub enabled: bool,
+    pub endpoints: Vec<WebhookEndpoint>,
+}
+
+impl WebhookSettings {
+    pub fn validate(&self) -> Result<()> {
+        for endpoint in &self.endpoints {
+            endpoint
+                .validate()
+                .with_context(|| format!("Invalid webhook {}", endpoint.url))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookEndpoint {
+    /// On this machine or the local network only
+    pub url: String,
+    /// Signs each body with HMAC-SHA256 in `X-Control-Signature`
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Subset of `WEBHOOK_EVENTS`; empty for all of them
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default)]
+    pub redaction: Redaction,
+}
+
+impl WebhookEndpoint {
+    fn validate(&self) -> Result<()> {
+        let url = reqwest::Url::parse(&self.url).context("Invalid URL")?;
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("Webhook URL must be http:// or https://");
+        }
+        let host = url.host_str().context("Webhook URL has no host")?;
+        if !is_local_host(host) {
+            anyhow::bail!("Webhooks may only go to this machine or the local network");
+        }
+        if let Some(event) = self
+            .events
+            .iter()
+            .find(|e| !WEBHOOK_EVENTS.contains(&e.as_str()))
+        {
+            anyhow::bail!("Unknown webhook event: {}", event);
+        }
+        if self.secret.as_ref().is_some_and(|s| s.is_empty()) {
+            anyhow::bail!("Webhook secret must not be empty");
+        }
+        Ok(())
+    }
+
+    fn wants(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event)
+    }
+}
+
+/// How much of an event's payload leaves the app
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Redaction {
+    /// The payload as the app sees it, message text included
+    None,
+    /// Everything except message text
+    #[default]
+    Content,
+    /// Only the event name and time
+    Payload,
+}
+
+/// Body posted to a webhook
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookCall {
+    pub event: String,
+    pub emitted_at: u64,
+    pub payload: serde_json::Value,
+}
+
+/// Loopback, private and link-local addresses, `localhost`, and mDNS or
+/// home network names
+fn is_local_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        Ok(IpAddr::V6(ip)) => {
+            let first = ip.segments()[0];
+            // Unique local fc00::/7 and link-local fe80::/10
+            ip.is_loopback() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+        }
+        Err(_) => {
+            let host = host.to_ascii_lowercase();
+            host == "localhost"
+                || host.ends_with(".localhost")
+                || host.ends_with(".local")
+                || host.ends_with(".home.arpa")
+        }
+    }
+}
+
+fn redact(payload: &serde_json::Value, redaction: Redaction) -> serde_json::Value {
+    match redaction {
+        Redaction::None => payload.clone(),
+        Redaction::Content => {
+            let mut payload = payload.clone();
+            if let Some(object) = payload.as_object_mut() {
+                object.remove("content");
+            }
+            payload
+        }
+        Redaction::Payload => serde_json::Value::Null,
+    }
+}
+
+/// Hex HMAC-SHA256 of the body, prefixed like GitHub's `sha256=`
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Post `event` to every endpoint subscribed to it, in the background.
+/// Events not in `WEBHOOK_EVENTS` are ignored.
+pub fn fire(event: &str, payload: &serde_json::Value) {
+    if !WEBHOOK_EVENTS.contains(&event) {
+        return;
+    }
+    let settings = settings::webhook_settings();
+    if !settings.enabled {
+        return;
+    }
+    let emitted_at = now_secs();
+    for endpoint in settings.endpoints.into_iter().filter(|e| e.wants(event)) {
+        let call = WebhookCall {
+            event: event.to_string(),
+            emitted_at,
+            payload: redact(payload, endpoint.redaction),
+        };
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = post(&endpoint, &call).await {
+                warn!("Webhook {} failed: {}", endpoint.url, e);
+            }
+        });
+    }
+}
+
+/// Post a `test` event to one endpoint and wait for the answer
+pub async fn send_test(endpoint: &WebhookEndpoint) -> Result<()> {
+    endpoint.validate()?;
+    let call = WebhookCall {
+        event: "test".to_string(),
+        emitted_at: now_secs(),
+        payload: serde_json::Value::Null,
+    };
+    post(endpoint, &call).await
+}
+
+async fn post(endpoint: &WebhookEndpoint, call: &WebhookCall) -> Result<()> {
+    let body = serde_json::to_vec(call)?;
+    // Local endpoints only, so never through the configured proxies
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .context("Failed to build webhook HTTP client")?;
+    let mut request = client
+        .post(&endpoint.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, &call.event);
+    if let Some(secret) = &endpoint.secret {
+        request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+    }
+    let response = request
+        .body(body)
+        .send()
+        .await
+        .context("Endpoint unreachable")?;
+    if !response.status().is_success() {
+        anyhow::bail!("Endpoint answered {}", response.status());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_local_hosts_are_allowed() {
+        for host in [
+            "127.0.0.1",
+            "192.168.1.20",
+            "10.0.0.5",
+            "[::1]",
+            "[fd00::1]",
+            "localhost",
+            "nas.local",
+        ] {
+            assert!(is_local_host(host), "{}", host);
+        }
+        for host in [
+            "8.8.8.8",
+            "[2001:db8::1]",
+            "example.com",
+            "local.example.com",
+        ] {
+            assert!(!is_local_host(host), "{}", host);
+        }
+    }
+
+    #[test]
+    fn content_redaction_keeps_metadata() {
+        let payload = serde_json::json!({ "id": "1", "from": "abc", "content": "secret" });
+        assert_eq!(
+            redact(&payload, Redaction::Content),
+            serde_json::json!({ "id": "1", "from": "abc" })
+        );
+        assert_eq!(
+            redact(&payload, Redaction::Payload),
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn signature_matches_known_vector() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            signature("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use crate::settings;
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// The CBOR self-describe tag (55799). JSON never starts with it, so it tells
+/// the two formats apart.
+const CBOR_MAGIC: [u8; 3] = [0xd9, 0xd9, 0xf7];
+/// Bumped on incompatible changes to any wire message or manifest
+pub const WIRE_VERSION: u32 = 1;
+
+/// Encode a P2P message or manifest for the wire. Writes JSON while
+/// `P2PSettings::legacy_json_wire` is set, for peers on older releases.
+pub fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    if settings::p2p_settings().legacy_json_wire {
+        return Ok(serde_json::to_vec(value)?);
+    }
+    encode_cbor(value)
+}
+
+/// The magic, then `[version, value]` as CBOR. Maps keep field order and
+/// every length and integer uses its shortest form, so equal values encode
+/// to equal bytes.
+pub fn encode_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut bytes = CBOR_MAGIC.to_vec();
+    ciborium::into_writer(&(WIRE_VERSION, value), &mut bytes)
+        .map_err(|e| anyhow::anyhow!("Failed to encode CBOR: {}", e))?;
+    Ok(bytes)
+}
+
+/// Decode what `encode` wrote. Bytes after the value are ignored, so
+/// manifests can be padded.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    let Some(body) = bytes.strip_prefix(CBOR_MAGIC.as_slice()) else {
+        // Releases before the CBOR format wrote JSON. Remove with
+        // `legacy_json_wire` after the next release.
+        return serde_json::from_slice(bytes).context("Invalid JSON payload");
+    };
+    let (version, value): (u32, ciborium::Value) =
+        ciborium::from_reader(body).context("Invalid CBOR payload")?;
+    if version != WIRE_VERSION {
+        anyhow::bail!("Unsupported wire format version {}", version);
+    }
+    value.deserialized().context("Unexpected CBOR payload")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::{MessageReceipt, P2PMessage};
+
+    fn message() -> P2PMessage {
+        P2PMessage::Receipt(MessageReceipt {
+            message_id: "9b2f6c1e".to_string(),
+            from: "alice".to_string(),
+            timestamp: 1_700_000_042,
+        })
+    }
+
+    #[test]
+    fn cbor_round_trips_and_json_still_decodes() {
+        let cbor = encode_cbor(&message()).unwrap();
+        assert!(cbor.starts_with(&CBOR_MAGIC));
+        let decoded: P2PMessage = decode(&cbor).unwrap();
+        assert_eq!(encode_cbor(&decoded).unwrap(), cbor);
+
+        let json = serde_json::to_vec(&message()).unwrap();
+        let decoded: P2PMessage = decode(&json).unwrap();
+        assert_eq!(encode_cbor(&decoded).unwrap(), cbor);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut bytes = CBOR_MAGIC.to_vec();
+        ciborium::into_writer(&(WIRE_VERSION + 1, message()), &mut bytes).unwrap();
+        assert!(decode::<P2PMessage>(&bytes).is_err());
+    }
+}
@@ -3,6 +3,7 @@ import { invoke } from '@tauri-apps/api/tauri';
 import Identity from './components/Identity';
 import GhostChat from './components/GhostChat';
 import DeadDrop from './components/DeadDrop';
+import { errorMessage } from './errors';
 import './index.css';
 
 type Tab = 'identity' | 'ghost' | 'deaddrop';
@@ -27,7 +28,7 @@ function App() {
       setIsInitialized(true);
     } catch (error) {
       console.error('Failed to initialize identity:', error);
-      alert('Failed to initialize identity: ' + error);
+      alert('Failed to initialize identity: ' + errorMessage(error));
     }
   };
 
@@ -1,12 +1,19 @@
 import { useState, useCallback } from 'react';
 import { invoke } from '@tauri-apps/api/tauri';
 import { open } from '@tauri-apps/api/dialog';
+import { errorMessage } from '../errors';
 
 interface DeadDropCreated {
   cid: string;
   shards: string[];
+  cipher?: 'chacha20-poly1305' | 'aes-256-gcm' | null;
 }
 
+const CIPHER_NAMES = {
+  'chacha20-poly1305': 'ChaCha20-Poly1305',
+  'aes-256-gcm': 'AES-256-GCM',
+};
+
 function DeadDrop() {
   const [isDragging, setIsDragging] = useState(false);
   const [isProcessing, setIsProcessing] = useState(false);
@@ -72,14 +79,19 @@ function DeadDrop() {
       setResult(dropResult);
     } catch (error) {
       console.error('Failed to create dead drop:', error);
-      alert('Failed to create dead drop: ' + error);
+      alert('Failed to create dead drop: ' + errorMessage(error));
     } finally {
       setIsProcessing(false);
     }
   };
 
-  const copyShard = (shard: string) => {
-    navigator.clipboard.writeText(shard);
+  // Shards go through the backend so they are wiped from the clipboard again
+  const copyShard = async (shard: string) => {
+    try {
+      await invoke('copy_sensitive', { value: shard, ttlSecs: 30 });
+    } catch (error) {
+      alert(errorMessage(error));
+    }
   };
 
   const copyCID = () => {
@@ -264,6 +276,11 @@ function DeadDrop() {
                   COPY
                 </button>
               </div>
+              {result.cipher && (
+                <div style={{ fontSize: '12px', fontWeight: 900, marginTop: '16px' }}>
+                  CIPHER: {CIPHER_NAMES[result.cipher]}
+                </div>
+              )}
             </div>
           </div>
 
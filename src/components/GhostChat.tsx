@@ -1,6 +1,7 @@
 import { useState, useEffect, useRef } from 'react';
 import { invoke } from '@tauri-apps/api/tauri';
-import { listen } from '@tauri-apps/api/event';
+import { errorMessage } from '../errors';
+import { listenEvent, replayEvents } from '../events';
 
 interface Message {
   id: string;
@@ -10,6 +11,9 @@ interface Message {
   isOutgoing: boolean;
 }
 
+// Undelivered messages shared out of band as tiny dead drops
+const MESSAGE_LINK = /control:\/\/message\/[A-Za-z0-9]+/;
+
 interface GhostChatProps {
   publicId: string;
 }
@@ -24,8 +28,7 @@ function GhostChat({ publicId }: GhostChatProps) {
 
   useEffect(() => {
     // Listen for incoming messages
-    const unlistenMsg = listen<any>('ghost_msg', (event) => {
-      const msg = event.payload;
+    const onMessage = (msg: any) => {
       setMessages((prev) => [
         ...prev,
         {
@@ -36,16 +39,42 @@ function GhostChat({ publicId }: GhostChatProps) {
           isOutgoing: false,
         },
       ]);
-    });
+    };
+    const unlistenMsg = listenEvent<any>('ghost_msg', onMessage);
 
     // Listen for delivery confirmations
-    const unlistenDelivered = listen<any>('msg_delivered', (event) => {
-      console.log('Message delivered:', event.payload);
+    const unlistenDelivered = listenEvent<any>('msg_delivered', (payload) => {
+      console.log('Message delivered:', payload);
+    });
+
+    // P2P delivery failed and the message was packaged as a drop
+    const unlistenFallback = listenEvent<any>('msg_fallback', (payload) => {
+      setMessages((prev) => [
+        ...prev,
+        {
+          id: `${payload.message_id}-fallback`,
+          from: publicId,
+          content: `NOT DELIVERED // SHARE THIS LINK OUT OF BAND: ${payload.link}`,
+          timestamp: Date.now() / 1000,
+          isOutgoing: true,
+        },
+      ]);
     });
 
+    // Ghost Mode may be started without this screen (auto-start, tray)
+    invoke<string>('get_app_state').then((state) => setIsGhostModeActive(state === 'ghost_active'));
+    const unlistenGhostMode = listenEvent<boolean>('ghost_mode_changed', setIsGhostModeActive);
+
+    // Pick up messages that arrived while the webview was reloading
+    replayEvents({ ghost_msg: onMessage, ghost_mode_changed: setIsGhostModeActive }).catch((error) =>
+      console.error('Failed to replay events:', error)
+    );
+
     return () => {
       unlistenMsg.then((fn) => fn());
       unlistenDelivered.then((fn) => fn());
+      unlistenFallback.then((fn) => fn());
+      unlistenGhostMode.then((fn) => fn());
     };
   }, []);
 
@@ -59,11 +88,27 @@ function GhostChat({ publicId }: GhostChatProps) {
       setIsGhostModeActive(true);
     } catch (error) {
       console.error('Failed to start Ghost Mode:', error);
-      alert('Failed to start Ghost Mode: ' + error);
+      alert('Failed to start Ghost Mode: ' + errorMessage(error));
+    }
+  };
+
+  const openMessageLink = async (link: string) => {
+    setIsSending(true);
+    try {
+      // Arrives through the usual ghost_msg event
+      await invoke('open_message_link', { link });
+      setMessageContent('');
+    } catch (error) {
+      console.error('Failed to open message link:', error);
+      alert('Failed to open message link: ' + errorMessage(error));
+    } finally {
+      setIsSending(false);
     }
   };
 
   const sendMessage = async () => {
+    const link = messageContent.match(MESSAGE_LINK);
+    if (link) return openMessageLink(link[0]);
     if (!messageContent.trim() || !targetKey.trim()) return;
 
     setIsSending(true);
@@ -87,7 +132,7 @@ function GhostChat({ publicId }: GhostChatProps) {
       setMessageContent('');
     } catch (error) {
       console.error('Failed to send message:', error);
-      alert('Failed to send message: ' + error);
+      alert('Failed to send message: ' + errorMessage(error));
     } finally {
       setIsSending(false);
     }
@@ -237,20 +282,24 @@ function GhostChat({ publicId }: GhostChatProps) {
             <input
               type="text"
               className="input-bold"
-              placeholder="TRANSMIT MESSAGE..."
+              placeholder="TRANSMIT MESSAGE OR PASTE A MESSAGE LINK..."
               value={messageContent}
               onChange={(e) => setMessageContent(e.target.value)}
               onKeyPress={(e) => e.key === 'Enter' && sendMessage()}
-              disabled={!targetKey || isSending}
+              disabled={isSending}
               style={{ flex: 1 }}
             />
             <button
               className="btn-bold"
               onClick={sendMessage}
-              disabled={!messageContent.trim() || !targetKey || isSending}
+              disabled={
+                !messageContent.trim() ||
+                (!targetKey && !MESSAGE_LINK.test(messageContent)) ||
+                isSending
+              }
               style={{ minWidth: '160px' }}
             >
-              {isSending ? 'SENDING...' : 'TRANSMIT'}
+              {isSending ? 'SENDING...' : MESSAGE_LINK.test(messageContent) ? 'OPEN LINK' : 'TRANSMIT'}
             </button>
           </div>
         </div>
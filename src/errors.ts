@@ -0,0 +1,20 @@
+/** Error shape returned by every backend command and carried by error events */
+export interface ControlError {
+  category: 'state' | 'auth' | 'input' | 'network' | 'storage' | 'crypto' | 'internal';
+  code: string;
+  /** Already translated into the locale from settings */
+  message: string;
+  /** Message catalog ID, for translating on the frontend instead */
+  message_id?: string;
+  retryable: boolean;
+}
+
+export function isControlError(error: unknown): error is ControlError {
+  return typeof error === 'object' && error !== null && 'code' in error && 'message' in error;
+}
+
+/** Readable message for both typed errors and the old plain-string errors */
+export function errorMessage(error: unknown): string {
+  if (isControlError(error)) return error.message;
+  return String(error);
+}
@@ -0,0 +1,71 @@
+import { invoke } from '@tauri-apps/api/tauri';
+import { listen, UnlistenFn } from '@tauri-apps/api/event';
+
+/** Envelope version this frontend understands; must match the backend */
+export const EVENT_SCHEMA_VERSION = 1;
+
+/** What every journaled backend event carries */
+export interface EventEnvelope<T = unknown> {
+  schema_version: number;
+  seq: number;
+  event: string;
+  emitted_at: number;
+  payload: T;
+}
+
+export interface EventReplay {
+  schema_version: number;
+  boot_id: string;
+  latest_seq: number;
+  truncated: boolean;
+  events: EventEnvelope[];
+}
+
+let bootId: string | null = null;
+let lastSeq = 0;
+const seen = new Set<number>();
+
+function accept(envelope: EventEnvelope): boolean {
+  if (envelope.schema_version !== EVENT_SCHEMA_VERSION) {
+    console.warn(
+      `Event ${envelope.event} has schema version ${envelope.schema_version}, expected ${EVENT_SCHEMA_VERSION}`
+    );
+  }
+  if (seen.has(envelope.seq)) return false;
+  seen.add(envelope.seq);
+  lastSeq = Math.max(lastSeq, envelope.seq);
+  return true;
+}
+
+/** Listen for a journaled event, unwrapping its envelope and skipping anything already handled by a replay */
+export function listenEvent<T>(
+  event: string,
+  handler: (payload: T, envelope: EventEnvelope<T>) => void
+): Promise<UnlistenFn> {
+  return listen<EventEnvelope<T>>(event, (e) => {
+    if (accept(e.payload)) handler(e.payload.payload, e.payload);
+  });
+}
+
+/** Fetch events missed since the last one seen (e.g. after a reload) and pass them to the matching handlers */
+export async function replayEvents(
+  handlers: Record<string, (payload: any, envelope: EventEnvelope) => void>
+): Promise<EventReplay> {
+  const replay = await invoke<EventReplay>('get_events_since', { seq: bootId ? lastSeq : 0 });
+  if (bootId !== replay.boot_id) {
+    // The backend restarted and sequence numbers started over
+    if (bootId !== null) {
+      seen.clear();
+      lastSeq = 0;
+    }
+    bootId = replay.boot_id;
+  }
+  if (replay.truncated) {
+    console.warn('Some backend events were dropped before they could be replayed');
+  }
+  for (const envelope of replay.events) {
+    const handler = handlers[envelope.event];
+    if (handler && accept(envelope)) handler(envelope.payload, envelope);
+  }
+  return replay;
+}